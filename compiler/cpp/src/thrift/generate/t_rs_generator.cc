@@ -587,14 +587,13 @@ void t_rs_generator::render_attributes_and_includes() {
   f_gen_ << "use thrift::{ApplicationError, ApplicationErrorKind, ProtocolError, "
             "ProtocolErrorKind, TThriftClient};"
          << '\n';
+  f_gen_ << "use thrift::client::TReplyValidator;" << '\n';
   f_gen_ << "use thrift::protocol::{TFieldIdentifier, TListIdentifier, TMapIdentifier, "
             "TMessageIdentifier, TMessageType, TInputProtocol, TOutputProtocol, TSerializable, "
             "TSetIdentifier, TStructIdentifier, TType};"
          << '\n';
   f_gen_ << "use thrift::protocol::field_id;" << '\n';
   f_gen_ << "use thrift::protocol::verify_expected_message_type;" << '\n';
-  f_gen_ << "use thrift::protocol::verify_expected_sequence_number;" << '\n';
-  f_gen_ << "use thrift::protocol::verify_expected_service_call;" << '\n';
   f_gen_ << "use thrift::protocol::verify_required_field_exists;" << '\n';
   f_gen_ << "use thrift::server::TProcessor;" << '\n';
   f_gen_ << '\n';
@@ -2414,13 +2413,10 @@ void t_rs_generator::render_sync_recv(t_function* tfunc) {
   f_gen_ << indent() << "{" << '\n';
   indent_up();
 
-  f_gen_ << indent() << "let message_ident = self.i_prot_mut().read_message_begin()?;" << '\n';
-  f_gen_
-      << indent()
-      << "verify_expected_sequence_number(self.sequence_number(), message_ident.sequence_number)?;"
-      << '\n';
-  f_gen_ << indent() << "verify_expected_service_call(\"" << tfunc->get_name()
-         << "\", &message_ident.name)?;" << '\n'; // note: use *original* name
+  f_gen_ << indent() << "let sequence_number = self.sequence_number();" << '\n';
+  f_gen_ << indent()
+         << "let message_ident = TReplyValidator::new().read_reply_begin(self.i_prot_mut(), \""
+         << tfunc->get_name() << "\", sequence_number)?;" << '\n'; // note: use *original* name
   // FIXME: replace with a "try" block
   f_gen_ << indent() << "if message_ident.message_type == TMessageType::Exception {" << '\n';
   indent_up();
@@ -0,0 +1,137 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Decoding throughput of `TBinaryInputProtocol` versus
+//! `TAcceleratedBinaryInputProtocol` on framed messages.
+//!
+//! Run with `cargo bench --bench binary_read`.
+
+use std::hint::black_box;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use thrift::protocol::{
+    TAcceleratedBinaryInputProtocol, TBinaryInputProtocol, TBinaryOutputProtocol, TFieldIdentifier,
+    TInputProtocol, TListIdentifier, TOutputProtocol, TStructIdentifier, TType,
+};
+use thrift::transport::{TFramedReadTransport, TFramedWriteTransport};
+
+const ELEMENTS: usize = 20_000;
+const ITERATIONS: u32 = 50;
+
+fn framed_message() -> Vec<u8> {
+    let mut buf = Vec::new();
+    {
+        let mut o_prot = TBinaryOutputProtocol::new(TFramedWriteTransport::new(&mut buf), true);
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("Records"))
+            .unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("records", TType::List, 1))
+            .unwrap();
+        o_prot
+            .write_list_begin(&TListIdentifier::new(TType::Struct, ELEMENTS as i32))
+            .unwrap();
+        for i in 0..ELEMENTS {
+            o_prot
+                .write_struct_begin(&TStructIdentifier::new("Record"))
+                .unwrap();
+            o_prot
+                .write_field_begin(&TFieldIdentifier::new("id", TType::I64, 1))
+                .unwrap();
+            o_prot.write_i64(i as i64).unwrap();
+            o_prot.write_field_end().unwrap();
+            o_prot
+                .write_field_begin(&TFieldIdentifier::new("count", TType::I32, 2))
+                .unwrap();
+            o_prot.write_i32(i as i32 * 3).unwrap();
+            o_prot.write_field_end().unwrap();
+            o_prot
+                .write_field_begin(&TFieldIdentifier::new("name", TType::String, 3))
+                .unwrap();
+            o_prot.write_string("record-name").unwrap();
+            o_prot.write_field_end().unwrap();
+            o_prot.write_field_stop().unwrap();
+            o_prot.write_struct_end().unwrap();
+        }
+        o_prot.write_list_end().unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+    buf
+}
+
+fn read_records<P: TInputProtocol>(i_prot: &mut P) -> i64 {
+    let mut sum = 0i64;
+    i_prot.read_struct_begin().unwrap();
+    i_prot.read_field_begin().unwrap();
+    let list = i_prot.read_list_begin().unwrap();
+    for _ in 0..list.size {
+        i_prot.read_struct_begin().unwrap();
+        loop {
+            let field = i_prot.read_field_begin().unwrap();
+            match field.field_type {
+                TType::Stop => break,
+                TType::I64 => sum = sum.wrapping_add(i_prot.read_i64().unwrap()),
+                TType::I32 => sum = sum.wrapping_add(i_prot.read_i32().unwrap() as i64),
+                _ => sum = sum.wrapping_add(i_prot.read_string().unwrap().len() as i64),
+            }
+            i_prot.read_field_end().unwrap();
+        }
+        i_prot.read_struct_end().unwrap();
+    }
+    i_prot.read_list_end().unwrap();
+    i_prot.read_field_end().unwrap();
+    i_prot.read_field_begin().unwrap();
+    i_prot.read_struct_end().unwrap();
+    sum
+}
+
+fn report<F: FnMut()>(name: &str, bytes: usize, mut f: F) {
+    f(); // warm up
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let per_iter = elapsed / ITERATIONS;
+    let mib_per_sec = (bytes as f64 * ITERATIONS as f64)
+        / elapsed.max(Duration::from_nanos(1)).as_secs_f64()
+        / (1024.0 * 1024.0);
+    println!(
+        "{:<32} {:>10.3?}/iter {:>10.1} MiB/s",
+        name, per_iter, mib_per_sec
+    );
+}
+
+fn main() {
+    let message = framed_message();
+    let bytes = message.len();
+
+    report("binary (framed)", bytes, || {
+        let transport = TFramedReadTransport::new(Cursor::new(black_box(&message[..])));
+        let mut i_prot = TBinaryInputProtocol::new(transport, true);
+        black_box(read_records(&mut i_prot));
+    });
+    report("accelerated binary (framed)", bytes, || {
+        let transport = TFramedReadTransport::new(Cursor::new(black_box(&message[..])));
+        let mut i_prot = TAcceleratedBinaryInputProtocol::new(transport, true);
+        black_box(read_records(&mut i_prot));
+    });
+}
@@ -0,0 +1,106 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Cost of nested structs in the compact protocol.
+//!
+//! Run with `cargo bench --bench compact_nesting`. Every message creates a
+//! fresh protocol, as servers and struct streams do per connection or per
+//! record, and then descends `DEPTH` structs, which exercises the field id
+//! stack kept by the compact protocol.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use thrift::protocol::{
+    TCompactInputProtocol, TCompactOutputProtocol, TFieldIdentifier, TInputProtocol,
+    TOutputProtocol, TStructIdentifier, TType,
+};
+
+const DEPTH: usize = 16;
+const MESSAGES: u32 = 200_000;
+
+fn write_node<T: thrift::transport::TWriteTransport>(
+    o_prot: &mut TCompactOutputProtocol<T>,
+    depth: usize,
+) {
+    o_prot
+        .write_struct_begin(&TStructIdentifier::new("Node"))
+        .unwrap();
+    o_prot
+        .write_field_begin(&TFieldIdentifier::new("value", TType::I32, 1))
+        .unwrap();
+    o_prot.write_i32(7).unwrap();
+    o_prot.write_field_end().unwrap();
+    if depth > 1 {
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("child", TType::Struct, 2))
+            .unwrap();
+        write_node(o_prot, depth - 1);
+        o_prot.write_field_end().unwrap();
+    }
+    o_prot.write_field_stop().unwrap();
+    o_prot.write_struct_end().unwrap();
+}
+
+fn read_node<T: thrift::transport::TReadTransport>(i_prot: &mut TCompactInputProtocol<T>) -> i32 {
+    let mut sum = 0;
+    i_prot.read_struct_begin().unwrap();
+    loop {
+        let field = i_prot.read_field_begin().unwrap();
+        match field.field_type {
+            TType::Stop => break,
+            TType::I32 => sum += i_prot.read_i32().unwrap(),
+            _ => sum += read_node(i_prot),
+        }
+        i_prot.read_field_end().unwrap();
+    }
+    i_prot.read_struct_end().unwrap();
+    sum
+}
+
+fn write_nested(buf: &mut Vec<u8>) {
+    buf.clear();
+    write_node(&mut TCompactOutputProtocol::new(buf), DEPTH);
+}
+
+fn read_nested(buf: &[u8]) -> i32 {
+    read_node(&mut TCompactInputProtocol::new(buf))
+}
+
+fn report<F: FnMut()>(name: &str, mut f: F) {
+    f(); // warm up
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        f();
+    }
+    let per_message = start.elapsed() / MESSAGES;
+    println!("{:<32} {:>10.3?}/message", name, per_message);
+}
+
+fn main() {
+    let mut encoded = Vec::new();
+    write_nested(&mut encoded);
+
+    let mut buf = Vec::with_capacity(encoded.len());
+    report("compact nested write", || {
+        write_nested(&mut buf);
+        black_box(&buf);
+    });
+    report("compact nested read", || {
+        black_box(read_nested(black_box(&encoded)));
+    });
+}
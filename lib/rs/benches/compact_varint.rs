@@ -0,0 +1,148 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Throughput of the compact protocol on a large integer-heavy struct.
+//!
+//! Run with `cargo bench --bench compact_varint`. The `integer_encoding`
+//! rows write the same values through the crate the compact protocol used
+//! to depend on, as a reference point for the varint encoder.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use integer_encoding::VarIntWriter;
+use thrift::protocol::{
+    TCompactInputProtocol, TCompactOutputProtocol, TFieldIdentifier, TInputProtocol,
+    TListIdentifier, TOutputProtocol, TStructIdentifier, TType,
+};
+
+const ELEMENTS: usize = 100_000;
+const ITERATIONS: u32 = 50;
+
+fn values() -> (Vec<i32>, Vec<i64>) {
+    // a spread of magnitudes so that every varint length is exercised
+    let i32s = (0..ELEMENTS)
+        .map(|i| ((i as i32).wrapping_mul(2_654_435_761u32 as i32)) >> (i % 31))
+        .collect();
+    let i64s = (0..ELEMENTS)
+        .map(|i| ((i as i64).wrapping_mul(0x9E37_79B9_7F4A_7C15u64 as i64)) >> (i % 63))
+        .collect();
+    (i32s, i64s)
+}
+
+fn write_struct(i32s: &[i32], i64s: &[i64], buf: &mut Vec<u8>) {
+    buf.clear();
+    let mut o_prot = TCompactOutputProtocol::new(buf);
+    o_prot
+        .write_struct_begin(&TStructIdentifier::new("Ints"))
+        .unwrap();
+    o_prot
+        .write_field_begin(&TFieldIdentifier::new("i32s", TType::List, 1))
+        .unwrap();
+    o_prot
+        .write_list_begin(&TListIdentifier::new(TType::I32, i32s.len() as i32))
+        .unwrap();
+    for &i in i32s {
+        o_prot.write_i32(i).unwrap();
+    }
+    o_prot.write_list_end().unwrap();
+    o_prot.write_field_end().unwrap();
+    o_prot
+        .write_field_begin(&TFieldIdentifier::new("i64s", TType::List, 2))
+        .unwrap();
+    o_prot
+        .write_list_begin(&TListIdentifier::new(TType::I64, i64s.len() as i32))
+        .unwrap();
+    for &i in i64s {
+        o_prot.write_i64(i).unwrap();
+    }
+    o_prot.write_list_end().unwrap();
+    o_prot.write_field_end().unwrap();
+    o_prot.write_field_stop().unwrap();
+    o_prot.write_struct_end().unwrap();
+}
+
+fn read_struct(buf: &[u8]) -> i64 {
+    let mut i_prot = TCompactInputProtocol::new(buf);
+    let mut sum = 0i64;
+    i_prot.read_struct_begin().unwrap();
+    loop {
+        let field = i_prot.read_field_begin().unwrap();
+        if field.field_type == TType::Stop {
+            break;
+        }
+        let list = i_prot.read_list_begin().unwrap();
+        for _ in 0..list.size {
+            sum = sum.wrapping_add(match list.element_type {
+                TType::I32 => i_prot.read_i32().unwrap() as i64,
+                _ => i_prot.read_i64().unwrap(),
+            });
+        }
+        i_prot.read_list_end().unwrap();
+        i_prot.read_field_end().unwrap();
+    }
+    i_prot.read_struct_end().unwrap();
+    sum
+}
+
+fn write_reference(i32s: &[i32], i64s: &[i64], buf: &mut Vec<u8>) {
+    buf.clear();
+    for &i in i32s {
+        buf.write_varint(i).unwrap();
+    }
+    for &i in i64s {
+        buf.write_varint(i).unwrap();
+    }
+}
+
+fn report<F: FnMut()>(name: &str, bytes: usize, mut f: F) {
+    f(); // warm up
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let per_iter = elapsed / ITERATIONS;
+    let mib_per_sec = (bytes as f64 * ITERATIONS as f64)
+        / elapsed.max(Duration::from_nanos(1)).as_secs_f64()
+        / (1024.0 * 1024.0);
+    println!(
+        "{:<32} {:>10.3?}/iter {:>10.1} MiB/s",
+        name, per_iter, mib_per_sec
+    );
+}
+
+fn main() {
+    let (i32s, i64s) = values();
+
+    let mut encoded = Vec::new();
+    write_struct(&i32s, &i64s, &mut encoded);
+    let bytes = encoded.len();
+
+    let mut buf = Vec::with_capacity(bytes);
+    report("compact write", bytes, || {
+        write_struct(black_box(&i32s), black_box(&i64s), &mut buf);
+        black_box(&buf);
+    });
+    report("compact read", bytes, || {
+        black_box(read_struct(black_box(&encoded)));
+    });
+    report("integer_encoding write (ref)", bytes, || {
+        write_reference(black_box(&i32s), black_box(&i64s), &mut buf);
+        black_box(&buf);
+    });
+}
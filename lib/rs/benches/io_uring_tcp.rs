@@ -0,0 +1,94 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Round-trip latency of small framed messages over `TTcpChannel` versus
+//! `TUringChannel`, each talking to an echo server that uses the same kind
+//! of channel.
+//!
+//! Run with `cargo bench --features io-uring --bench io_uring_tcp`.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thrift::transport::{
+    TFramedReadTransport, TFramedWriteTransport, TIoChannel, TTcpChannel, TUringChannel,
+};
+
+const MESSAGE_SIZE: usize = 64;
+const ROUND_TRIPS: u32 = 20_000;
+
+/// Echo every frame received on the first accepted connection.
+fn echo_server<C, F>(wrap: F) -> SocketAddr
+where
+    C: TIoChannel + Send + 'static,
+    F: FnOnce(std::net::TcpStream) -> C + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        let (r, w) = wrap(stream).split().unwrap();
+        let mut r = TFramedReadTransport::new(r);
+        let mut w = TFramedWriteTransport::new(w);
+        let mut buf = [0u8; MESSAGE_SIZE];
+        while r.read_exact(&mut buf).is_ok() {
+            w.write_all(&buf).unwrap();
+            w.flush().unwrap();
+        }
+    });
+    address
+}
+
+fn report<C: TIoChannel>(name: &str, channel: C) {
+    let (r, w) = channel.split().unwrap();
+    let mut r = TFramedReadTransport::new(r);
+    let mut w = TFramedWriteTransport::new(w);
+    let message = [0xabu8; MESSAGE_SIZE];
+    let mut reply = [0u8; MESSAGE_SIZE];
+    let mut round_trip = || {
+        w.write_all(&message).unwrap();
+        w.flush().unwrap();
+        r.read_exact(&mut reply).unwrap();
+    };
+
+    for _ in 0..1_000 {
+        round_trip(); // warm up
+    }
+    let start = Instant::now();
+    for _ in 0..ROUND_TRIPS {
+        round_trip();
+    }
+    let elapsed = start.elapsed();
+    let per_call = elapsed / ROUND_TRIPS;
+    let calls_per_sec = ROUND_TRIPS as f64 / elapsed.max(Duration::from_nanos(1)).as_secs_f64();
+    println!(
+        "{:<32} {:>10.3?}/call {:>10.0} calls/s",
+        name, per_call, calls_per_sec
+    );
+}
+
+fn main() {
+    let address = echo_server(TTcpChannel::with_stream);
+    let mut channel = TTcpChannel::new();
+    channel.open(address).unwrap();
+    report("blocking tcp", channel);
+
+    let address = echo_server(|stream| TUringChannel::with_stream(stream).unwrap());
+    report("io_uring", TUringChannel::connect(address).unwrap());
+}
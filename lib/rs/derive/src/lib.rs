@@ -0,0 +1,304 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Derive macros for the `thrift` crate.
+//!
+//! Use them through the `derive` feature of `thrift`, which re-exports
+//! them as `thrift::ThriftSerialize` and `thrift::ThriftDeserialize`.
+
+use std::collections::HashSet;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, GenericArgument, LitInt, PathArguments, Type,
+};
+
+/// Implement `TWriteValue` for a struct, writing it as a Thrift struct.
+///
+/// Every field needs an id, given with `#[thrift(id = N)]`, and a type
+/// that implements `TWriteValue`. A field of type `Option<T>` is optional,
+/// and is only written when it is `Some`. Other fields are always written.
+/// Together with `ThriftDeserialize` this makes the struct `TSerializable`.
+///
+/// # Examples
+///
+/// ```ignore
+/// use thrift::{ThriftDeserialize, ThriftSerialize};
+///
+/// #[derive(ThriftSerialize, ThriftDeserialize)]
+/// struct LogEntry {
+///     #[thrift(id = 1, required)]
+///     level: i32,
+///     #[thrift(id = 2)]
+///     message: String,
+///     #[thrift(id = 3)]
+///     tags: Option<Vec<String>>,
+/// }
+/// ```
+#[proc_macro_derive(ThriftSerialize, attributes(thrift))]
+pub fn derive_thrift_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input, write_impl)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implement `TReadValue` for a struct, reading it as a Thrift struct.
+///
+/// Fields are given ids and types as for `ThriftSerialize`. Reading fails
+/// if a field marked `#[thrift(required)]` is missing. A missing `Option<T>`
+/// field is `None`, and any other missing field takes its type's default
+/// value. Fields with unknown ids, or with another type than expected, are
+/// skipped.
+#[proc_macro_derive(ThriftDeserialize, attributes(thrift))]
+pub fn derive_thrift_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input, read_impl)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// A field of the struct, with its `#[thrift(...)]` attributes.
+struct Field<'a> {
+    ident: &'a syn::Ident,
+    id: i16,
+    required: bool,
+    /// The type of the field's value: `T` for an `Option<T>` field.
+    ty: &'a Type,
+    optional: bool,
+}
+
+fn expand(
+    input: &DeriveInput,
+    implement: fn(&DeriveInput, &[Field<'_>]) -> TokenStream2,
+) -> syn::Result<TokenStream2> {
+    let named = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref named) => named,
+            _ => return Err(not_a_struct(input)),
+        },
+        _ => return Err(not_a_struct(input)),
+    };
+
+    let mut fields = Vec::new();
+    let mut ids = HashSet::new();
+    for field in &named.named {
+        let ident = field.ident.as_ref().expect("named fields have names");
+        let (id, required) = field_attributes(field)?;
+        if !ids.insert(id) {
+            return Err(syn::Error::new_spanned(
+                field,
+                format!("field id {} is used by more than one field", id),
+            ));
+        }
+        let (ty, optional) = match option_value_type(&field.ty) {
+            Some(ty) => (ty, true),
+            None => (&field.ty, false),
+        };
+        if optional && required {
+            return Err(syn::Error::new_spanned(
+                field,
+                "an `Option` field cannot be required",
+            ));
+        }
+        fields.push(Field {
+            ident,
+            id,
+            required,
+            ty,
+            optional,
+        });
+    }
+    Ok(implement(input, &fields))
+}
+
+fn not_a_struct(input: &DeriveInput) -> syn::Error {
+    syn::Error::new_spanned(
+        &input.ident,
+        "Thrift serialization can only be derived for structs with named fields",
+    )
+}
+
+/// Return the id of `field` and whether it is required.
+fn field_attributes(field: &syn::Field) -> syn::Result<(i16, bool)> {
+    let mut id = None;
+    let mut required = false;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("thrift")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                let lit: LitInt = meta.value()?.parse()?;
+                id = Some(lit.base10_parse::<i16>()?);
+                Ok(())
+            } else if meta.path.is_ident("required") {
+                required = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `id = N` or `required`"))
+            }
+        })?;
+    }
+    match id {
+        Some(id) => Ok((id, required)),
+        None => Err(syn::Error::new_spanned(
+            field,
+            "missing field id: add `#[thrift(id = N)]`",
+        )),
+    }
+}
+
+/// Return `T` if `ty` is `Option<T>`.
+fn option_value_type(ty: &Type) -> Option<&Type> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref args) if args.args.len() == 1 => match args.args[0] {
+            GenericArgument::Type(ref ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn write_impl(input: &DeriveInput, fields: &[Field<'_>]) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let struct_name = name.to_string();
+
+    let write_fields = fields.iter().map(|field| {
+        let ident = field.ident;
+        let field_name = ident.to_string();
+        let id = field.id;
+        let ty = field.ty;
+        let write_field = |value: TokenStream2| {
+            quote! {
+                o_prot.write_field_begin(&::thrift::protocol::TFieldIdentifier::new(
+                    #field_name,
+                    <#ty as ::thrift::protocol::TWriteValue>::FIELD_TYPE,
+                    #id,
+                ))?;
+                ::thrift::protocol::TWriteValue::write_value(#value, o_prot)?;
+                o_prot.write_field_end()?;
+            }
+        };
+        if field.optional {
+            let write = write_field(quote!(value));
+            quote! {
+                if let ::std::option::Option::Some(ref value) = self.#ident {
+                    #write
+                }
+            }
+        } else {
+            write_field(quote!(&self.#ident))
+        }
+    });
+
+    quote! {
+        impl #impl_generics ::thrift::protocol::TWriteValue for #name #ty_generics #where_clause {
+            const FIELD_TYPE: ::thrift::protocol::TType = ::thrift::protocol::TType::Struct;
+
+            fn write_value(
+                &self,
+                o_prot: &mut dyn ::thrift::protocol::TOutputProtocol,
+            ) -> ::thrift::Result<()> {
+                o_prot.write_struct_begin(&::thrift::protocol::TStructIdentifier::new(#struct_name))?;
+                #(#write_fields)*
+                o_prot.write_field_stop()?;
+                o_prot.write_struct_end()
+            }
+        }
+    }
+}
+
+fn read_impl(input: &DeriveInput, fields: &[Field<'_>]) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let struct_name = name.to_string();
+
+    let slot = |field: &Field<'_>| format_ident!("f_{}", field.ident);
+    let declare_slots = fields.iter().map(|field| {
+        let slot = slot(field);
+        let ty = field.ty;
+        quote!(let mut #slot: ::std::option::Option<#ty> = ::std::option::Option::None;)
+    });
+    // fields of another type than expected are skipped, like unknown fields
+    let read_fields = fields.iter().map(|field| {
+        let slot = slot(field);
+        let id = field.id;
+        let ty = field.ty;
+        quote! {
+            ::std::option::Option::Some(#id)
+                if field_ident.field_type == <#ty as ::thrift::protocol::TReadValue>::FIELD_TYPE =>
+            {
+                #slot = ::std::option::Option::Some(
+                    <#ty as ::thrift::protocol::TReadValue>::read_value(i_prot)?,
+                );
+            }
+        }
+    });
+    let verify_required = fields.iter().filter(|field| field.required).map(|field| {
+        let slot = slot(field);
+        let field_name = format!("{}.{}", struct_name, field.ident);
+        quote!(::thrift::protocol::verify_required_field_exists(#field_name, &#slot)?;)
+    });
+    let build_fields = fields.iter().map(|field| {
+        let ident = field.ident;
+        let slot = slot(field);
+        if field.optional {
+            quote!(#ident: #slot)
+        } else if field.required {
+            quote!(#ident: #slot.expect("required fields are checked"))
+        } else {
+            quote!(#ident: #slot.unwrap_or_default())
+        }
+    });
+
+    quote! {
+        impl #impl_generics ::thrift::protocol::TReadValue for #name #ty_generics #where_clause {
+            const FIELD_TYPE: ::thrift::protocol::TType = ::thrift::protocol::TType::Struct;
+
+            fn read_value(
+                i_prot: &mut dyn ::thrift::protocol::TInputProtocol,
+            ) -> ::thrift::Result<Self> {
+                i_prot.read_struct_begin()?;
+                #(#declare_slots)*
+                loop {
+                    let field_ident = i_prot.read_field_begin()?;
+                    if field_ident.field_type == ::thrift::protocol::TType::Stop {
+                        break;
+                    }
+                    match field_ident.id {
+                        #(#read_fields)*
+                        _ => i_prot.skip(field_ident.field_type)?,
+                    }
+                    i_prot.read_field_end()?;
+                }
+                i_prot.read_struct_end()?;
+                #(#verify_required)*
+                ::std::result::Result::Ok(#name {
+                    #(#build_fields,)*
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,819 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::retry::is_transport_failure;
+use super::TResolver;
+use crate::{new_transport_error, TransportErrorKind};
+
+type Connect<C> = dyn Fn(&TEndpoint) -> crate::Result<C> + Send + Sync;
+/// Shortest time between refreshes caused by failed calls.
+const MIN_FAILURE_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+type FailurePredicate = dyn Fn(&crate::Error) -> bool + Send + Sync;
+
+/// Address of a server that calls can be sent to, and its share of them.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TEndpoint {
+    address: String,
+    weight: u32,
+}
+
+impl TEndpoint {
+    /// Create a `TEndpoint` for `address`, in `host:port` form, with a
+    /// weight of 1.
+    pub fn new<S: Into<String>>(address: S) -> TEndpoint {
+        TEndpoint {
+            address: address.into(),
+            weight: 1,
+        }
+    }
+
+    /// Give the endpoint `weight` times the share of calls of an endpoint
+    /// with a weight of 1 under `TBalancePolicy::Weighted`. An endpoint with
+    /// a weight of 0 is sent no calls.
+    pub fn with_weight(mut self, weight: u32) -> TEndpoint {
+        self.weight = weight;
+        self
+    }
+
+    /// Address of the endpoint.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Weight of the endpoint.
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+impl From<&str> for TEndpoint {
+    fn from(address: &str) -> Self {
+        TEndpoint::new(address)
+    }
+}
+
+impl From<String> for TEndpoint {
+    fn from(address: String) -> Self {
+        TEndpoint::new(address)
+    }
+}
+
+impl From<SocketAddr> for TEndpoint {
+    fn from(address: SocketAddr) -> Self {
+        TEndpoint::new(address.to_string())
+    }
+}
+
+/// How a `TLoadBalancer` chooses the endpoint for a call.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TBalancePolicy {
+    /// Each endpoint in turn.
+    #[default]
+    RoundRobin,
+    /// The endpoint with the fewest calls in progress, each in turn when
+    /// several have as few.
+    LeastOutstanding,
+    /// Each endpoint in turn, in proportion to its weight.
+    Weighted,
+}
+
+/// Spreads calls across the clients of several endpoints.
+///
+/// The balancer keeps a pool of clients for each endpoint, built on demand
+/// by the `connect` function it is given, and makes each call on a client
+/// of the endpoint chosen by its `TBalancePolicy`. A client is returned to
+/// its pool after a call, unless the call failed with an error that shows
+/// the connection failed. Calls run without holding any lock, so a
+/// balancer can be shared across threads by cloning it.
+///
+/// An endpoint that fails `failure_threshold` calls in a row, counting
+/// failures to connect, is ejected: it is sent no calls for the ejection
+/// time, after which it is tried again. If every endpoint is ejected, calls
+/// are spread across all of them rather than failed outright.
+///
+/// Endpoints are either fixed, or found by a `TResolver` that `refresh`
+/// calls again to pick up changes, which the balancer can also do on an
+/// interval or after calls fail. Endpoints that are no longer returned are
+/// dropped along with their clients once their calls complete.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client::{TBalancePolicy, TEndpoint, TLoadBalancer};
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{TIoChannel, TTcpChannel};
+/// # struct CalculatorSyncClient<I, O>(I, O);
+/// # impl<I, O> CalculatorSyncClient<I, O> {
+/// #     fn new(i: I, o: O) -> Self { CalculatorSyncClient(i, o) }
+/// #     fn add(&mut self, a: i32, b: i32) -> thrift::Result<i32> { Ok(a + b) }
+/// # }
+///
+/// let balancer = TLoadBalancer::new(
+///     vec![
+///         TEndpoint::new("calc-1.example.com:9090").with_weight(2),
+///         TEndpoint::new("calc-2.example.com:9090"),
+///     ],
+///     |endpoint: &TEndpoint| {
+///         let mut channel = TTcpChannel::new();
+///         channel.open(endpoint.address())?;
+///         let (i_chan, o_chan) = channel.split()?;
+///         Ok(CalculatorSyncClient::new(
+///             TBinaryInputProtocol::new(i_chan, true),
+///             TBinaryOutputProtocol::new(o_chan, true),
+///         ))
+///     },
+/// )
+/// .with_policy(TBalancePolicy::Weighted);
+///
+/// let sum = balancer.call(|client| client.add(1, 2)).unwrap();
+/// ```
+pub struct TLoadBalancer<C> {
+    connect: Arc<Connect<C>>,
+    resolver: Option<Arc<dyn TResolver>>,
+    refresh_interval: Option<Duration>,
+    refresh_on_failure: bool,
+    policy: TBalancePolicy,
+    failure_threshold: u32,
+    ejection_time: Duration,
+    predicate: Arc<FailurePredicate>,
+    state: Arc<Mutex<State<C>>>,
+}
+
+struct State<C> {
+    endpoints: Vec<Endpoint<C>>,
+    cursor: usize,
+    refreshed: Instant,
+    refreshed_after_failure: Option<Instant>,
+}
+
+struct Endpoint<C> {
+    endpoint: TEndpoint,
+    idle: Vec<Idle<C>>,
+    outstanding: usize,
+    failures: u32,
+    ejected_until: Option<Instant>,
+    // smooth weighted round-robin, as in nginx
+    current_weight: i64,
+}
+
+struct Idle<C> {
+    client: C,
+    since: Instant,
+}
+
+impl<C> Endpoint<C> {
+    fn new(endpoint: TEndpoint) -> Endpoint<C> {
+        Endpoint {
+            endpoint,
+            idle: Vec::new(),
+            outstanding: 0,
+            failures: 0,
+            ejected_until: None,
+            current_weight: 0,
+        }
+    }
+
+    fn is_ejected(&self, now: Instant) -> bool {
+        matches!(self.ejected_until, Some(until) if now < until)
+    }
+}
+
+impl<C> TLoadBalancer<C> {
+    /// Create a `TLoadBalancer` over `endpoints` that builds the clients of
+    /// an endpoint with `connect`.
+    ///
+    /// Calls are spread round-robin, and an endpoint is ejected for 30
+    /// seconds after 3 failed calls in a row.
+    pub fn new<I, E, F>(endpoints: I, connect: F) -> TLoadBalancer<C>
+    where
+        I: IntoIterator<Item = E>,
+        E: Into<TEndpoint>,
+        F: Fn(&TEndpoint) -> crate::Result<C> + Send + Sync + 'static,
+    {
+        TLoadBalancer {
+            connect: Arc::new(connect),
+            resolver: None,
+            refresh_interval: None,
+            refresh_on_failure: false,
+            policy: TBalancePolicy::default(),
+            failure_threshold: 3,
+            ejection_time: Duration::from_secs(30),
+            predicate: Arc::new(is_transport_failure),
+            state: Arc::new(Mutex::new(State {
+                endpoints: endpoints
+                    .into_iter()
+                    .map(|e| Endpoint::new(e.into()))
+                    .collect(),
+                cursor: 0,
+                refreshed: Instant::now(),
+                refreshed_after_failure: None,
+            })),
+        }
+    }
+
+    /// Create a `TLoadBalancer` over the endpoints returned by `resolver`,
+    /// which `refresh` calls again.
+    pub fn with_resolver<R, F>(resolver: R, connect: F) -> crate::Result<TLoadBalancer<C>>
+    where
+        R: TResolver + 'static,
+        F: Fn(&TEndpoint) -> crate::Result<C> + Send + Sync + 'static,
+    {
+        let endpoints = resolver.resolve()?;
+        let mut balancer = TLoadBalancer::new(endpoints, connect);
+        balancer.resolver = Some(Arc::new(resolver));
+        Ok(balancer)
+    }
+
+    /// Refresh the endpoints from the resolver before a call once
+    /// `refresh_interval` has passed since they were last refreshed, or
+    /// only when `refresh` is called if `None`.
+    ///
+    /// The resolver is called on the thread making the call.
+    pub fn with_refresh_interval(mut self, refresh_interval: Option<Duration>) -> TLoadBalancer<C> {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    /// Refresh the endpoints from the resolver after a call fails to
+    /// connect or fails with a transport error, at most once a second.
+    pub fn with_refresh_on_failure(mut self, refresh_on_failure: bool) -> TLoadBalancer<C> {
+        self.refresh_on_failure = refresh_on_failure;
+        self
+    }
+
+    /// Choose endpoints with `policy`.
+    pub fn with_policy(mut self, policy: TBalancePolicy) -> TLoadBalancer<C> {
+        self.policy = policy;
+        self
+    }
+
+    /// Eject an endpoint once `failure_threshold` calls in a row have
+    /// failed on it, or never if 0.
+    pub fn with_failure_threshold(mut self, failure_threshold: u32) -> TLoadBalancer<C> {
+        self.failure_threshold = failure_threshold;
+        self
+    }
+
+    /// Send no calls to an ejected endpoint for `ejection_time`.
+    pub fn with_ejection_time(mut self, ejection_time: Duration) -> TLoadBalancer<C> {
+        self.ejection_time = ejection_time;
+        self
+    }
+
+    /// Count the calls whose error `predicate` returns `true` for as
+    /// failures of their endpoint, instead of those that failed with a
+    /// transport error.
+    pub fn with_predicate<F>(mut self, predicate: F) -> TLoadBalancer<C>
+    where
+        F: Fn(&crate::Error) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Arc::new(predicate);
+        self
+    }
+
+    /// Keep idle clients' connections open by calling `ping` on each client
+    /// that has been idle for `interval`, from a background thread.
+    ///
+    /// Use it when a NAT or firewall drops connections that stay idle, which
+    /// would otherwise only show as an error on the next call. `ping` should
+    /// make a cheap call, for example with `thrift::client::ping`. A client
+    /// whose ping fails is dropped, and the thread stops once the balancer
+    /// and its clones are dropped.
+    ///
+    /// Each call starts another thread, so call it once.
+    pub fn with_keepalive<F>(self, interval: Duration, ping: F) -> TLoadBalancer<C>
+    where
+        C: Send + 'static,
+        F: Fn(&mut C) -> crate::Result<()> + Send + 'static,
+    {
+        let state = Arc::downgrade(&self.state);
+        thread::Builder::new()
+            .name("thrift-keepalive".to_owned())
+            .spawn(move || keep_alive(state, interval, ping))
+            .expect("cannot spawn keepalive thread");
+        self
+    }
+
+    /// Return the policy used to choose endpoints.
+    pub fn policy(&self) -> TBalancePolicy {
+        self.policy
+    }
+
+    /// Return the endpoints calls are spread across.
+    pub fn endpoints(&self) -> Vec<TEndpoint> {
+        self.lock()
+            .endpoints
+            .iter()
+            .map(|e| e.endpoint.clone())
+            .collect()
+    }
+
+    /// Return the endpoints currently ejected.
+    pub fn ejected_endpoints(&self) -> Vec<TEndpoint> {
+        let now = Instant::now();
+        self.lock()
+            .endpoints
+            .iter()
+            .filter(|e| e.is_ejected(now))
+            .map(|e| e.endpoint.clone())
+            .collect()
+    }
+
+    /// Replace the endpoints with those returned by the resolver, keeping
+    /// the clients and health of the endpoints it still returns.
+    ///
+    /// Does nothing if the balancer was created with fixed endpoints. If the
+    /// resolver fails, the endpoints are left unchanged.
+    pub fn refresh(&self) -> io::Result<()> {
+        let resolved = match self.resolver {
+            Some(ref resolver) => resolver.resolve()?,
+            None => return Ok(()),
+        };
+        self.set_endpoints(resolved);
+        Ok(())
+    }
+
+    /// Refresh the endpoints if `due` returns `true` for the state, which
+    /// it updates to claim the refresh so concurrent calls do not resolve
+    /// too.
+    fn refresh_if<F>(&self, due: F)
+    where
+        F: FnOnce(&mut State<C>, Instant) -> bool,
+    {
+        if self.resolver.is_none() || !due(&mut self.lock(), Instant::now()) {
+            return;
+        }
+        // the endpoints are kept if the resolver fails, and calls go on
+        let _ = self.refresh();
+    }
+
+    /// Replace the endpoints with `endpoints`, keeping the clients and
+    /// health of the endpoints that remain.
+    pub fn set_endpoints<I, E>(&self, endpoints: I)
+    where
+        I: IntoIterator<Item = E>,
+        E: Into<TEndpoint>,
+    {
+        let mut state = self.lock();
+        let mut previous = std::mem::take(&mut state.endpoints);
+        state.endpoints = endpoints
+            .into_iter()
+            .map(|endpoint| {
+                let endpoint = endpoint.into();
+                match previous
+                    .iter()
+                    .position(|e| e.endpoint.address == endpoint.address)
+                {
+                    Some(i) => Endpoint {
+                        endpoint,
+                        ..previous.swap_remove(i)
+                    },
+                    None => Endpoint::new(endpoint),
+                }
+            })
+            .collect();
+    }
+
+    /// Make a call with `call` on a client of the endpoint chosen by the
+    /// policy, building the client if the endpoint has none idle.
+    pub fn call<T, F>(&self, call: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut C) -> crate::Result<T>,
+    {
+        if let Some(interval) = self.refresh_interval {
+            self.refresh_if(|state, now| {
+                let due = now.duration_since(state.refreshed) >= interval;
+                if due {
+                    state.refreshed = now;
+                }
+                due
+            });
+        }
+
+        let (endpoint, idle) = self.check_out()?;
+        let mut client = match idle {
+            Some(client) => client,
+            None => match (self.connect)(&endpoint) {
+                Ok(client) => client,
+                Err(e) => {
+                    self.check_in(&endpoint, None, true);
+                    return Err(e);
+                }
+            },
+        };
+
+        let result = call(&mut client);
+        let failed = matches!(result, Err(ref e) if (self.predicate)(e));
+        self.check_in(&endpoint, if failed { None } else { Some(client) }, failed);
+        result
+    }
+
+    /// Choose an endpoint for a call and take one of its idle clients.
+    fn check_out(&self) -> crate::Result<(TEndpoint, Option<C>)> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        let State {
+            ref mut endpoints,
+            ref mut cursor,
+            ..
+        } = *state;
+
+        let mut candidates: Vec<usize> = (0..endpoints.len())
+            .filter(|&i| !endpoints[i].is_ejected(now))
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..endpoints.len()).collect();
+        }
+        if self.policy == TBalancePolicy::Weighted {
+            candidates.retain(|&i| endpoints[i].endpoint.weight > 0);
+        }
+        if candidates.is_empty() {
+            return Err(new_transport_error(
+                TransportErrorKind::NotOpen,
+                "no endpoints to send the call to",
+            ));
+        }
+
+        let start = *cursor % candidates.len();
+        *cursor = cursor.wrapping_add(1);
+        let rotated = candidates[start..].iter().chain(&candidates[..start]);
+        let chosen = match self.policy {
+            TBalancePolicy::RoundRobin => candidates[start],
+            TBalancePolicy::LeastOutstanding => *rotated
+                .min_by_key(|&&i| endpoints[i].outstanding)
+                .expect("candidates is not empty"),
+            TBalancePolicy::Weighted => {
+                let mut total = 0;
+                for &i in &candidates {
+                    let weight = i64::from(endpoints[i].endpoint.weight);
+                    endpoints[i].current_weight += weight;
+                    total += weight;
+                }
+                let chosen = *candidates
+                    .iter()
+                    .max_by_key(|&&i| endpoints[i].current_weight)
+                    .expect("candidates is not empty");
+                endpoints[chosen].current_weight -= total;
+                chosen
+            }
+        };
+
+        let endpoint = &mut endpoints[chosen];
+        endpoint.outstanding += 1;
+        Ok((
+            endpoint.endpoint.clone(),
+            endpoint.idle.pop().map(|idle| idle.client),
+        ))
+    }
+
+    /// Return `client` to the pool of `endpoint` and record whether the
+    /// call on it failed.
+    fn check_in(&self, endpoint: &TEndpoint, client: Option<C>, failed: bool) {
+        self.return_client(endpoint, client, failed);
+        if failed && self.refresh_on_failure {
+            self.refresh_if(|state, now| {
+                let due = !matches!(
+                    state.refreshed_after_failure,
+                    Some(t) if now.duration_since(t) < MIN_FAILURE_REFRESH_INTERVAL
+                );
+                if due {
+                    state.refreshed = now;
+                    state.refreshed_after_failure = Some(now);
+                }
+                due
+            });
+        }
+    }
+
+    fn return_client(&self, endpoint: &TEndpoint, client: Option<C>, failed: bool) {
+        let mut state = self.lock();
+        // the endpoint may have been removed by a refresh
+        let endpoint = match state
+            .endpoints
+            .iter_mut()
+            .find(|e| e.endpoint.address == endpoint.address)
+        {
+            Some(endpoint) => endpoint,
+            None => return,
+        };
+
+        endpoint.outstanding = endpoint.outstanding.saturating_sub(1);
+        endpoint.idle.extend(client.map(|client| Idle {
+            client,
+            since: Instant::now(),
+        }));
+        if !failed {
+            endpoint.failures = 0;
+            return;
+        }
+        endpoint.failures += 1;
+        if self.failure_threshold > 0 && endpoint.failures >= self.failure_threshold {
+            endpoint.failures = 0;
+            endpoint.ejected_until = Some(Instant::now() + self.ejection_time);
+            // a new connection will be needed once it is back
+            endpoint.idle.clear();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<C>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Ping the idle clients in `state` that have been idle for `interval` until
+/// `state` is dropped.
+fn keep_alive<C, F>(state: Weak<Mutex<State<C>>>, interval: Duration, ping: F)
+where
+    F: Fn(&mut C) -> crate::Result<()>,
+{
+    let tick = (interval / 2).clamp(Duration::from_millis(1), Duration::from_secs(1));
+    loop {
+        thread::sleep(tick);
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => return,
+        };
+
+        // take the clients out of their pools so no call uses them meanwhile
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for endpoint in &mut state.lock().unwrap_or_else(|e| e.into_inner()).endpoints {
+            let (stale, fresh) = std::mem::take(&mut endpoint.idle)
+                .into_iter()
+                .partition(|idle| now.duration_since(idle.since) >= interval);
+            endpoint.idle = fresh;
+            due.extend(
+                stale
+                    .into_iter()
+                    .map(|idle: Idle<C>| (endpoint.endpoint.address.clone(), idle.client)),
+            );
+        }
+
+        for (address, mut client) in due {
+            if ping(&mut client).is_err() {
+                continue;
+            }
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            // the endpoint may have been removed by a refresh
+            if let Some(endpoint) = state
+                .endpoints
+                .iter_mut()
+                .find(|e| e.endpoint.address == address)
+            {
+                endpoint.idle.push(Idle {
+                    client,
+                    since: Instant::now(),
+                });
+            }
+        }
+    }
+}
+
+impl<C> Clone for TLoadBalancer<C> {
+    fn clone(&self) -> Self {
+        TLoadBalancer {
+            connect: self.connect.clone(),
+            resolver: self.resolver.clone(),
+            refresh_interval: self.refresh_interval,
+            refresh_on_failure: self.refresh_on_failure,
+            policy: self.policy,
+            failure_threshold: self.failure_threshold,
+            ejection_time: self.ejection_time,
+            predicate: self.predicate.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for TLoadBalancer<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TLoadBalancer")
+            .field("endpoints", &self.endpoints())
+            .field("policy", &self.policy)
+            .field("failure_threshold", &self.failure_threshold)
+            .field("ejection_time", &self.ejection_time)
+            .field("refresh_interval", &self.refresh_interval)
+            .field("refresh_on_failure", &self.refresh_on_failure)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// Client that answers with the address of its endpoint.
+    struct Client(String);
+
+    fn balancer(endpoints: &[TEndpoint]) -> TLoadBalancer<Client> {
+        TLoadBalancer::new(endpoints.to_vec(), |e: &TEndpoint| {
+            Ok(Client(e.address().to_owned()))
+        })
+    }
+
+    fn calls(balancer: &TLoadBalancer<Client>, n: usize) -> Vec<String> {
+        (0..n)
+            .map(|_| balancer.call(|c| Ok(c.0.clone())).unwrap())
+            .collect()
+    }
+
+    fn refused() -> crate::Error {
+        new_transport_error(TransportErrorKind::NotOpen, "connection refused")
+    }
+
+    #[test]
+    fn must_spread_calls_round_robin() {
+        let balancer = balancer(&["a:1".into(), "b:1".into(), "c:1".into()]);
+
+        assert_eq!(
+            calls(&balancer, 6),
+            ["a:1", "b:1", "c:1", "a:1", "b:1", "c:1"]
+        );
+    }
+
+    #[test]
+    fn must_spread_calls_by_weight() {
+        let balancer = balancer(&[
+            TEndpoint::new("a:1").with_weight(3),
+            TEndpoint::new("b:1"),
+            TEndpoint::new("c:1").with_weight(0),
+        ])
+        .with_policy(TBalancePolicy::Weighted);
+
+        let made = calls(&balancer, 8);
+
+        assert_eq!(made.iter().filter(|a| *a == "a:1").count(), 6);
+        assert_eq!(made.iter().filter(|a| *a == "b:1").count(), 2);
+        // smooth: b:1 gets one of every four calls
+        assert!(made.windows(4).all(|w| w.iter().any(|a| a == "b:1")));
+    }
+
+    #[test]
+    fn must_prefer_endpoint_with_fewest_outstanding_calls() {
+        let balancer =
+            balancer(&["a:1".into(), "b:1".into()]).with_policy(TBalancePolicy::LeastOutstanding);
+
+        let nested = balancer
+            .call(|outer| {
+                let inner = balancer.call(|c| Ok(c.0.clone()))?;
+                Ok((outer.0.clone(), inner))
+            })
+            .unwrap();
+
+        assert_ne!(nested.0, nested.1);
+    }
+
+    #[test]
+    fn must_ping_idle_clients_and_drop_those_that_fail() {
+        let built = Arc::new(AtomicUsize::new(0));
+        let pings = Arc::new(AtomicUsize::new(0));
+        let counter = built.clone();
+        let ping_counter = pings.clone();
+        let balancer = TLoadBalancer::new(vec!["a:1"], move |e: &TEndpoint| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Client(e.address().to_owned()))
+        })
+        .with_keepalive(Duration::from_millis(10), move |_| {
+            // the first ping succeeds, the rest find the connection gone
+            match ping_counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(()),
+                _ => Err(refused()),
+            }
+        });
+
+        calls(&balancer, 1);
+        for _ in 0..500 {
+            if pings.load(Ordering::SeqCst) >= 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(pings.load(Ordering::SeqCst), 2);
+
+        calls(&balancer, 1);
+        assert_eq!(built.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn must_reuse_clients_and_drop_failed_ones() {
+        let built = Arc::new(AtomicUsize::new(0));
+        let counter = built.clone();
+        let balancer = TLoadBalancer::new(vec!["a:1"], move |e: &TEndpoint| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Client(e.address().to_owned()))
+        });
+
+        calls(&balancer, 3);
+        assert_eq!(built.load(Ordering::SeqCst), 1);
+        let _ = balancer.call(|_| -> crate::Result<()> { Err(refused()) });
+        calls(&balancer, 1);
+
+        assert_eq!(built.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn must_eject_failing_endpoint() {
+        let balancer = TLoadBalancer::new(vec!["a:1", "b:1"], |e: &TEndpoint| {
+            if e.address() == "a:1" {
+                Err(refused())
+            } else {
+                Ok(Client(e.address().to_owned()))
+            }
+        })
+        .with_failure_threshold(2)
+        .with_ejection_time(Duration::from_secs(60));
+
+        for _ in 0..4 {
+            let _ = balancer.call(|c| Ok(c.0.clone()));
+        }
+
+        assert_eq!(balancer.ejected_endpoints(), [TEndpoint::new("a:1")]);
+        assert_eq!(calls(&balancer, 3), ["b:1", "b:1", "b:1"]);
+    }
+
+    #[test]
+    fn must_pick_up_resolved_endpoints_on_refresh() {
+        let resolved = Arc::new(Mutex::new(vec![TEndpoint::new("a:1")]));
+        let source = resolved.clone();
+        let balancer = TLoadBalancer::with_resolver(
+            move || Ok(source.lock().unwrap().clone()),
+            |e: &TEndpoint| Ok(Client(e.address().to_owned())),
+        )
+        .unwrap();
+        assert_eq!(calls(&balancer, 2), ["a:1", "a:1"]);
+
+        *resolved.lock().unwrap() = vec![TEndpoint::new("b:1")];
+        balancer.refresh().unwrap();
+
+        assert_eq!(balancer.endpoints(), [TEndpoint::new("b:1")]);
+        assert_eq!(calls(&balancer, 2), ["b:1", "b:1"]);
+    }
+
+    fn counting_resolver() -> (Arc<AtomicUsize>, impl TResolver) {
+        let resolved = Arc::new(AtomicUsize::new(0));
+        let counter = resolved.clone();
+        let resolver = move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![TEndpoint::new(format!("host-{}:1", n))])
+        };
+        (resolved, resolver)
+    }
+
+    #[test]
+    fn must_refresh_endpoints_on_interval() {
+        let (resolved, resolver) = counting_resolver();
+        let balancer = TLoadBalancer::with_resolver(resolver, |e: &TEndpoint| {
+            Ok(Client(e.address().to_owned()))
+        })
+        .unwrap()
+        .with_refresh_interval(Some(Duration::from_millis(20)));
+
+        assert_eq!(calls(&balancer, 2), ["host-0:1", "host-0:1"]);
+        thread::sleep(Duration::from_millis(30));
+
+        assert_eq!(calls(&balancer, 2), ["host-1:1", "host-1:1"]);
+        assert_eq!(resolved.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn must_refresh_endpoints_after_failed_call() {
+        let (resolved, resolver) = counting_resolver();
+        let balancer = TLoadBalancer::with_resolver(resolver, |e: &TEndpoint| {
+            Ok(Client(e.address().to_owned()))
+        })
+        .unwrap()
+        .with_refresh_on_failure(true);
+
+        let _ = balancer.call(|_| -> crate::Result<()> { Err(refused()) });
+        assert_eq!(calls(&balancer, 1), ["host-1:1"]);
+
+        // at most once a second
+        let _ = balancer.call(|_| -> crate::Result<()> { Err(refused()) });
+        assert_eq!(resolved.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,418 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::retry::is_transport_failure;
+use super::{TClientCall, TClientMiddleware};
+use crate::protocol::{TMessageIdentifier, TOutputProtocol};
+use crate::{new_transport_error, TransportErrorKind};
+
+type FailurePredicate = dyn Fn(&crate::Error) -> bool + Send + Sync;
+
+/// State of a `TCircuitBreaker`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TCircuitState {
+    /// Calls are made, and their outcomes recorded.
+    Closed,
+    /// Calls fail at once without being made.
+    Open,
+    /// A few trial calls are made to find out whether the server has
+    /// recovered; other calls fail at once.
+    HalfOpen,
+}
+
+/// Fails calls to a struggling server fast instead of letting them pile up.
+///
+/// The breaker starts closed and records the outcome of the last `window`
+/// calls. Once at least `minimum_calls` have been recorded and the fraction
+/// that failed reaches the failure rate, the breaker opens: calls fail at
+/// once with a `TransportErrorKind::NotOpen` error, without being made. After
+/// the open duration it is half-open and lets `half_open_calls` trial calls
+/// through. If they all succeed it closes again; if any fails it opens for
+/// another open duration.
+///
+/// Failures are the calls whose error the predicate returns `true` for: by
+/// default, transport errors showing that the server could not be reached
+/// or did not answer in time. Application and IDL exceptions are answers,
+/// and count as successes.
+///
+/// A breaker is shared by cloning it. Run calls through `call`, or use it
+/// as a `TClientMiddleware` (see `client::with_middleware`) to guard every
+/// call of the clients wrapped with it.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use thrift::client::{TCircuitBreaker, TCircuitState};
+///
+/// let breaker = TCircuitBreaker::new()
+///     .with_failure_rate(0.5)
+///     .with_minimum_calls(4)
+///     .with_open_duration(Duration::from_secs(5));
+///
+/// for _ in 0..4 {
+///     let _ = breaker.call(|| -> thrift::Result<()> {
+///         Err(thrift::new_transport_error(
+///             thrift::TransportErrorKind::TimedOut,
+///             "no reply",
+///         ))
+///     });
+/// }
+/// assert_eq!(breaker.state(), TCircuitState::Open);
+/// assert!(breaker.call(|| Ok(())).is_err());
+/// ```
+#[derive(Clone)]
+pub struct TCircuitBreaker {
+    failure_rate: f64,
+    minimum_calls: usize,
+    window: usize,
+    open_duration: Duration,
+    half_open_calls: usize,
+    predicate: Arc<FailurePredicate>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        outcomes: VecDeque<bool>,
+        failures: usize,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        since: Instant,
+        in_flight: usize,
+        successes: usize,
+    },
+}
+
+impl State {
+    fn closed() -> State {
+        State::Closed {
+            outcomes: VecDeque::new(),
+            failures: 0,
+        }
+    }
+}
+
+impl TCircuitBreaker {
+    /// Create a closed `TCircuitBreaker` that opens once half of the last
+    /// 20 calls, and at least 10, have failed, stays open for 10 seconds,
+    /// and then lets one trial call through.
+    pub fn new() -> TCircuitBreaker {
+        TCircuitBreaker {
+            failure_rate: 0.5,
+            minimum_calls: 10,
+            window: 20,
+            open_duration: Duration::from_secs(10),
+            half_open_calls: 1,
+            predicate: Arc::new(is_transport_failure),
+            state: Arc::new(Mutex::new(State::closed())),
+        }
+    }
+
+    /// Open once the fraction of failed calls reaches `failure_rate`,
+    /// clamped to `0.0..=1.0`.
+    pub fn with_failure_rate(mut self, failure_rate: f64) -> TCircuitBreaker {
+        if !failure_rate.is_nan() {
+            self.failure_rate = failure_rate.clamp(0.0, 1.0);
+        }
+        self
+    }
+
+    /// Do not open before `minimum_calls` calls have been recorded.
+    pub fn with_minimum_calls(mut self, minimum_calls: usize) -> TCircuitBreaker {
+        self.minimum_calls = minimum_calls.max(1);
+        self
+    }
+
+    /// Compute the failure rate over the last `window` calls.
+    pub fn with_window(mut self, window: usize) -> TCircuitBreaker {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Stay open for `open_duration` before letting trial calls through.
+    pub fn with_open_duration(mut self, open_duration: Duration) -> TCircuitBreaker {
+        self.open_duration = open_duration;
+        self
+    }
+
+    /// Let `half_open_calls` trial calls through once half-open, all of
+    /// which must succeed to close the breaker.
+    pub fn with_half_open_calls(mut self, half_open_calls: usize) -> TCircuitBreaker {
+        self.half_open_calls = half_open_calls.max(1);
+        self
+    }
+
+    /// Count the calls whose error `predicate` returns `true` for as
+    /// failures, instead of those that failed with a transport error.
+    pub fn with_predicate<F>(mut self, predicate: F) -> TCircuitBreaker
+    where
+        F: Fn(&crate::Error) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Arc::new(predicate);
+        self
+    }
+
+    /// Return the state of the breaker.
+    pub fn state(&self) -> TCircuitState {
+        match *self.lock() {
+            State::Closed { .. } => TCircuitState::Closed,
+            State::Open { until } if Instant::now() < until => TCircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => TCircuitState::HalfOpen,
+        }
+    }
+
+    /// Return `Ok` if a call may be made now, or the error to fail it with
+    /// if the breaker is open.
+    ///
+    /// Every call allowed must be followed by `record`.
+    pub fn try_acquire(&self) -> crate::Result<()> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        match *state {
+            State::Closed { .. } => return Ok(()),
+            State::Open { until } if now < until => {}
+            State::Open { .. } => {
+                *state = State::HalfOpen {
+                    since: now,
+                    in_flight: 1,
+                    successes: 0,
+                };
+                return Ok(());
+            }
+            State::HalfOpen {
+                ref mut since,
+                ref mut in_flight,
+                successes,
+            } => {
+                // trial calls that never reported back must not keep the
+                // breaker half-open for good
+                if now.duration_since(*since) >= self.open_duration {
+                    *since = now;
+                    *in_flight = 0;
+                }
+                if *in_flight + successes < self.half_open_calls {
+                    *in_flight += 1;
+                    return Ok(());
+                }
+            }
+        }
+        Err(new_transport_error(
+            TransportErrorKind::NotOpen,
+            "circuit breaker is open",
+        ))
+    }
+
+    /// Record the outcome of a call allowed by `try_acquire`.
+    pub fn record(&self, result: Result<(), &crate::Error>) {
+        let failed = matches!(result, Err(e) if (self.predicate)(e));
+        let now = Instant::now();
+        let mut state = self.lock();
+        match *state {
+            State::Closed {
+                ref mut outcomes,
+                ref mut failures,
+            } => {
+                outcomes.push_back(failed);
+                *failures += usize::from(failed);
+                if outcomes.len() > self.window && outcomes.pop_front() == Some(true) {
+                    *failures -= 1;
+                }
+                if outcomes.len() >= self.minimum_calls
+                    && *failures as f64 >= self.failure_rate * outcomes.len() as f64
+                {
+                    *state = State::Open {
+                        until: now + self.open_duration,
+                    };
+                }
+            }
+            // a call made before the breaker opened
+            State::Open { .. } => {}
+            State::HalfOpen {
+                ref mut in_flight,
+                ref mut successes,
+                ..
+            } => {
+                if failed {
+                    *state = State::Open {
+                        until: now + self.open_duration,
+                    };
+                    return;
+                }
+                *in_flight = in_flight.saturating_sub(1);
+                *successes += 1;
+                if *successes >= self.half_open_calls {
+                    *state = State::closed();
+                }
+            }
+        }
+    }
+
+    /// Make `call` if the breaker allows it, and record its outcome.
+    pub fn call<T, F>(&self, call: F) -> crate::Result<T>
+    where
+        F: FnOnce() -> crate::Result<T>,
+    {
+        self.try_acquire()?;
+        let result = call();
+        self.record(result.as_ref().map(|_| ()));
+        result
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for TCircuitBreaker {
+    fn default() -> Self {
+        TCircuitBreaker::new()
+    }
+}
+
+impl fmt::Debug for TCircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TCircuitBreaker")
+            .field("failure_rate", &self.failure_rate)
+            .field("minimum_calls", &self.minimum_calls)
+            .field("window", &self.window)
+            .field("open_duration", &self.open_duration)
+            .field("half_open_calls", &self.half_open_calls)
+            .field("state", &*self.lock())
+            .finish_non_exhaustive()
+    }
+}
+
+impl TClientMiddleware for TCircuitBreaker {
+    fn on_send(
+        &self,
+        _: &mut TMessageIdentifier,
+        _: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()> {
+        self.try_acquire()
+    }
+
+    fn on_complete(&self, _: &TClientCall, result: Result<(), &crate::Error>) {
+        self.record(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    fn timed_out() -> crate::Result<()> {
+        Err(new_transport_error(
+            TransportErrorKind::TimedOut,
+            "no reply",
+        ))
+    }
+
+    fn breaker() -> TCircuitBreaker {
+        TCircuitBreaker::new()
+            .with_failure_rate(0.5)
+            .with_minimum_calls(4)
+            .with_window(4)
+            .with_open_duration(Duration::from_millis(50))
+    }
+
+    #[test]
+    fn must_open_once_failure_rate_is_reached() {
+        let breaker = breaker();
+
+        for _ in 0..3 {
+            breaker.call(|| Ok(())).unwrap();
+        }
+        let _ = breaker.call(timed_out);
+        let _ = breaker.call(timed_out);
+        // one success has left the window: 2 of the last 4 calls failed
+        assert_eq!(breaker.state(), TCircuitState::Open);
+
+        let mut made = false;
+        let error = breaker
+            .call(|| {
+                made = true;
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(!made);
+        assert!(matches!(
+            error,
+            crate::Error::Transport(ref e) if e.kind == TransportErrorKind::NotOpen
+        ));
+    }
+
+    #[test]
+    fn must_not_count_application_errors_as_failures() {
+        let breaker = breaker();
+
+        for _ in 0..8 {
+            let _ = breaker.call(|| -> crate::Result<()> {
+                Err(crate::Error::Application(crate::ApplicationError::new(
+                    crate::ApplicationErrorKind::Unknown,
+                    "no such user",
+                )))
+            });
+        }
+
+        assert_eq!(breaker.state(), TCircuitState::Closed);
+    }
+
+    #[test]
+    fn must_close_after_successful_trial_calls() {
+        let breaker = breaker().with_half_open_calls(2);
+        for _ in 0..4 {
+            let _ = breaker.call(timed_out);
+        }
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(breaker.state(), TCircuitState::HalfOpen);
+
+        // only two trial calls are let through at once
+        breaker.try_acquire().unwrap();
+        breaker.try_acquire().unwrap();
+        assert!(breaker.try_acquire().is_err());
+        breaker.record(Ok(()));
+        assert_eq!(breaker.state(), TCircuitState::HalfOpen);
+        breaker.record(Ok(()));
+
+        assert_eq!(breaker.state(), TCircuitState::Closed);
+    }
+
+    #[test]
+    fn must_reopen_when_trial_call_fails() {
+        let breaker = breaker();
+        for _ in 0..4 {
+            let _ = breaker.call(timed_out);
+        }
+        thread::sleep(Duration::from_millis(60));
+
+        let _ = breaker.call(timed_out);
+
+        assert_eq!(breaker.state(), TCircuitState::Open);
+    }
+}
@@ -0,0 +1,643 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io::{Read, Write};
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::protocol::{TDynamicInputProtocol, TDynamicOutputProtocol, TProtocolKind};
+use crate::transport::{
+    TBackoff, TIoChannel, TReadTransport, TReadTransportFactory, TReconnectingChannel, TTcpChannel,
+    TTcpKeepalive, TTcpOptions, TTransportKind, TTransportStackBuilder, TWriteTransport,
+    TWriteTransportFactory,
+};
+
+/// Input protocol of a client built by `TClientChannelBuilder`.
+pub type TClientInputProtocol = TDynamicInputProtocol<Box<dyn TReadTransport + Send>>;
+
+/// Output protocol of a client built by `TClientChannelBuilder`.
+pub type TClientOutputProtocol = TDynamicOutputProtocol<Box<dyn TWriteTransport + Send>>;
+
+/// Connects to a server and builds the protocols of a client from a URI.
+///
+/// The URI names the transport layers, protocol and TLS in its scheme, and
+/// the server in its authority:
+///
+/// ```text
+/// thrift[+<layer>...][+binary|+compact][+tls]://host:port[?option=value&...]
+/// ```
+///
+/// Layers are `framed`, `buffered`, and, with the matching features,
+/// `zlib`, `zstd` and `lz4`, listed in the order `TTransportStackBuilder`
+/// takes them: the first wraps the connection. Without layers the transport
+/// is buffered; without a protocol it is binary. The options are:
+///
+/// * `connect_timeout`, `read_timeout`, `write_timeout` and `timeout` (both
+///   read and write), in milliseconds
+/// * `keepalive`, the idle time in milliseconds before TCP keepalive probes
+///   are sent
+/// * `server_name`, the name to verify the TLS certificate against instead
+///   of the host
+/// * `reconnect`, the number of attempts to connect on first use and after
+///   the connection is lost (see `reconnect`)
+///
+/// TLS needs the `rustls` feature and a configuration given with
+/// `tls_config`, or the `tls-native` feature, which uses the platform's
+/// default settings unless given a connector with `tls_connector`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client::TClientChannelBuilder;
+/// # struct CalculatorSyncClient<I, O>(I, O);
+/// # impl<I, O> CalculatorSyncClient<I, O> {
+/// #     fn new(i: I, o: O) -> Self { CalculatorSyncClient(i, o) }
+/// # }
+///
+/// let (i_prot, o_prot) =
+///     TClientChannelBuilder::from_uri("thrift+framed+compact://localhost:9090?timeout=500")
+///         .unwrap()
+///         .build()
+///         .unwrap();
+/// let client = CalculatorSyncClient::new(i_prot, o_prot);
+/// ```
+#[derive(Debug)]
+pub struct TClientChannelBuilder {
+    address: String,
+    protocol: TProtocolKind,
+    stack: TTransportStackBuilder,
+    layered: bool,
+    tls: bool,
+    server_name: Option<String>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    reconnect: Option<TBackoff>,
+    keepalive: Option<TTcpKeepalive>,
+    #[cfg(feature = "rustls")]
+    tls_config: Option<Arc<rustls::ClientConfig>>,
+    #[cfg(feature = "tls-native")]
+    tls_connector: Option<native_tls::TlsConnector>,
+}
+
+impl TClientChannelBuilder {
+    /// Create a builder for a binary, buffered client of the server at
+    /// `address`, in `host:port` form.
+    pub fn new<S: Into<String>>(address: S) -> TClientChannelBuilder {
+        TClientChannelBuilder {
+            address: address.into(),
+            protocol: TProtocolKind::Binary,
+            stack: TTransportStackBuilder::new(),
+            layered: false,
+            tls: false,
+            server_name: None,
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            reconnect: None,
+            keepalive: None,
+            #[cfg(feature = "rustls")]
+            tls_config: None,
+            #[cfg(feature = "tls-native")]
+            tls_connector: None,
+        }
+    }
+
+    /// Create a builder configured by `uri`.
+    pub fn from_uri(uri: &str) -> crate::Result<TClientChannelBuilder> {
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or_else(|| invalid_uri(uri, "missing \"://\""))?;
+        let (address, query) = match rest.split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (rest, None),
+        };
+        let address = address.trim_end_matches('/');
+        if address.is_empty() {
+            return Err(invalid_uri(uri, "missing host"));
+        }
+
+        let mut parts = scheme.split('+');
+        if parts.next() != Some("thrift") {
+            return Err(invalid_uri(uri, "scheme must start with \"thrift\""));
+        }
+        let mut builder = TClientChannelBuilder::new(address);
+        for part in parts {
+            builder = match part {
+                "binary" => builder.protocol(TProtocolKind::Binary),
+                "compact" => builder.protocol(TProtocolKind::Compact),
+                "tls" => builder.tls(true),
+                "framed" => builder.layer(|s| s.framed()),
+                "buffered" => builder.layer(|s| s.buffered()),
+                #[cfg(feature = "zlib")]
+                "zlib" => builder.layer(|s| s.zlib()),
+                #[cfg(feature = "zstd")]
+                "zstd" => builder.layer(|s| s.zstd()),
+                #[cfg(feature = "lz4")]
+                "lz4" => builder.layer(|s| s.lz4()),
+                #[cfg(not(feature = "zlib"))]
+                "zlib" => return Err(invalid_uri(uri, "zlib needs the zlib feature")),
+                #[cfg(not(feature = "zstd"))]
+                "zstd" => return Err(invalid_uri(uri, "zstd needs the zstd feature")),
+                #[cfg(not(feature = "lz4"))]
+                "lz4" => return Err(invalid_uri(uri, "lz4 needs the lz4 feature")),
+                _ => return Err(invalid_uri(uri, &format!("unknown layer {:?}", part))),
+            };
+        }
+
+        for option in query.into_iter().flat_map(|q| q.split('&')) {
+            if option.is_empty() {
+                continue;
+            }
+            let (name, value) = option.split_once('=').unwrap_or((option, ""));
+            let millis = || {
+                value
+                    .parse()
+                    .map(Duration::from_millis)
+                    .map_err(|_| invalid_uri(uri, &format!("{} must be in milliseconds", name)))
+            };
+            builder = match name {
+                "connect_timeout" => builder.connect_timeout(Some(millis()?)),
+                "read_timeout" => builder.read_timeout(Some(millis()?)),
+                "write_timeout" => builder.write_timeout(Some(millis()?)),
+                "timeout" => {
+                    let timeout = millis()?;
+                    builder
+                        .read_timeout(Some(timeout))
+                        .write_timeout(Some(timeout))
+                }
+                "server_name" => builder.server_name(value),
+                "keepalive" => builder.keepalive(Some(TTcpKeepalive::new(millis()?))),
+                "reconnect" => {
+                    let attempts = value
+                        .parse()
+                        .map_err(|_| invalid_uri(uri, "reconnect must be a number of attempts"))?;
+                    builder.reconnect(Some(TBackoff::default().with_max_attempts(Some(attempts))))
+                }
+                _ => return Err(invalid_uri(uri, &format!("unknown option {:?}", name))),
+            };
+        }
+
+        Ok(builder)
+    }
+
+    /// Use the `protocol` protocol.
+    pub fn protocol(mut self, protocol: TProtocolKind) -> TClientChannelBuilder {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Use the transport layers of `stack`, replacing any set before.
+    pub fn transport(mut self, stack: TTransportStackBuilder) -> TClientChannelBuilder {
+        self.stack = stack;
+        self.layered = true;
+        self
+    }
+
+    /// Connect over TLS, or not.
+    pub fn tls(mut self, tls: bool) -> TClientChannelBuilder {
+        self.tls = tls;
+        self
+    }
+
+    /// Verify the TLS certificate of the server against `server_name`
+    /// instead of the host of the address.
+    pub fn server_name<S: Into<String>>(mut self, server_name: S) -> TClientChannelBuilder {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    /// Give up connecting after `timeout`. See `TTcpChannel::set_connect_timeout`.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> TClientChannelBuilder {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Fail reads that wait longer than `timeout`.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> TClientChannelBuilder {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Fail writes that wait longer than `timeout`.
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> TClientChannelBuilder {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Enable TCP keepalive on the connection with `keepalive`, so that it
+    /// is not dropped while idle by NATs and firewalls along the way, and a
+    /// dead peer is noticed. See `TTcpOptions`.
+    pub fn keepalive(mut self, keepalive: Option<TTcpKeepalive>) -> TClientChannelBuilder {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Connect on the first call instead of in `build`, and connect again
+    /// on the next call after the connection is lost, retrying failed
+    /// connects according to `backoff`. See `TReconnectingChannel`.
+    ///
+    /// Without this, `build` fails if the server is not reachable, and the
+    /// protocols fail every call once the connection is lost.
+    pub fn reconnect(mut self, backoff: Option<TBackoff>) -> TClientChannelBuilder {
+        self.reconnect = backoff;
+        self
+    }
+
+    /// Connect over TLS with the rustls configuration `config`.
+    #[cfg(feature = "rustls")]
+    pub fn tls_config(mut self, config: Arc<rustls::ClientConfig>) -> TClientChannelBuilder {
+        self.tls_config = Some(config);
+        self.tls = true;
+        self
+    }
+
+    /// Connect over TLS with the native-tls connector `connector`.
+    #[cfg(feature = "tls-native")]
+    pub fn tls_connector(mut self, connector: native_tls::TlsConnector) -> TClientChannelBuilder {
+        self.tls_connector = Some(connector);
+        self.tls = true;
+        self
+    }
+
+    fn layer<F>(mut self, add: F) -> TClientChannelBuilder
+    where
+        F: FnOnce(TTransportStackBuilder) -> TTransportStackBuilder,
+    {
+        self.stack = add(std::mem::take(&mut self.stack));
+        self.layered = true;
+        self
+    }
+
+    /// Address of the server.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Protocol the client uses.
+    pub fn protocol_kind(&self) -> TProtocolKind {
+        self.protocol
+    }
+
+    /// Return `true` if the client connects over TLS.
+    pub fn uses_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Connect to the server and return the input and output protocols to
+    /// build a client with.
+    ///
+    /// With `reconnect` set, the connection is instead opened by the first
+    /// call made through the protocols.
+    pub fn build(mut self) -> crate::Result<(TClientInputProtocol, TClientOutputProtocol)> {
+        let stack = if self.layered {
+            std::mem::take(&mut self.stack)
+        } else {
+            TTransportStackBuilder::new().buffered()
+        };
+        let (read_factory, write_factory) = stack.build()?;
+        let protocol = self.protocol;
+
+        let (reader, writer) = match self.reconnect {
+            Some(backoff) => {
+                let channel = TReconnectingChannel::lazy(move || self.connect(), backoff);
+                split(channel)?
+            }
+            None => self.connect()?.split_boxed()?,
+        };
+
+        Ok((
+            TDynamicInputProtocol::new(protocol, read_factory.create(reader)),
+            TDynamicOutputProtocol::new(protocol, write_factory.create(writer)),
+        ))
+    }
+
+    fn connect(&self) -> crate::Result<Box<dyn Connection>> {
+        let mut channel = TTcpChannel::new();
+        channel.set_connect_timeout(self.connect_timeout);
+        channel.set_timeouts(self.read_timeout, self.write_timeout)?;
+        if self.keepalive.is_some() {
+            channel.set_options(TTcpOptions::builder().keepalive(self.keepalive).build())?;
+        }
+        channel.open(self.address.as_str())?;
+
+        if !self.tls {
+            return Ok(Box::new(channel));
+        }
+        let server_name = match self.server_name {
+            Some(ref name) => name.clone(),
+            None => host(&self.address).to_owned(),
+        };
+        #[cfg(any(feature = "rustls", feature = "tls-native"))]
+        {
+            self.connect_tls(channel, server_name)
+        }
+        #[cfg(not(any(feature = "rustls", feature = "tls-native")))]
+        {
+            let _ = server_name;
+            Err(tls_unavailable())
+        }
+    }
+
+    #[cfg(any(feature = "rustls", feature = "tls-native"))]
+    fn connect_tls(
+        &self,
+        channel: TTcpChannel,
+        server_name: String,
+    ) -> crate::Result<Box<dyn Connection>> {
+        #[cfg(feature = "rustls")]
+        if let Some(ref config) = self.tls_config {
+            let server_name =
+                rustls::pki_types::ServerName::try_from(server_name).map_err(|e| {
+                    crate::new_transport_error(
+                        crate::TransportErrorKind::Unknown,
+                        format!("invalid TLS server name: {}", e),
+                    )
+                })?;
+            return Ok(Box::new(crate::transport::TTlsClientChannel::with_channel(
+                channel,
+                server_name,
+                config.clone(),
+            )?));
+        }
+
+        #[cfg(feature = "tls-native")]
+        {
+            let connector = match self.tls_connector {
+                Some(ref connector) => connector.clone(),
+                None => native_tls::TlsConnector::new().map_err(|e| {
+                    crate::new_transport_error(
+                        crate::TransportErrorKind::Unknown,
+                        format!("cannot create TLS connector: {}", e),
+                    )
+                })?,
+            };
+            Ok(Box::new(
+                crate::transport::TNativeTlsClientChannel::with_channel(
+                    channel,
+                    &server_name,
+                    &connector,
+                )?,
+            ))
+        }
+
+        #[cfg(not(feature = "tls-native"))]
+        {
+            let _ = (channel, server_name);
+            Err(crate::new_transport_error(
+                crate::TransportErrorKind::Unknown,
+                "TLS with rustls needs a configuration: call tls_config",
+            ))
+        }
+    }
+}
+
+/// Connect to the server at `address`, in `host:port` form, and return the
+/// input and output protocols to build a client with.
+///
+/// This is a shorthand for a `TClientChannelBuilder` with the given
+/// protocol and transport and default settings otherwise. Use the builder
+/// for timeouts, TLS, or more transport layers.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client;
+/// use thrift::protocol::TProtocolKind;
+/// use thrift::transport::TTransportKind;
+/// # struct CalculatorSyncClient<I, O>(I, O);
+/// # impl<I, O> CalculatorSyncClient<I, O> {
+/// #     fn new(i: I, o: O) -> Self { CalculatorSyncClient(i, o) }
+/// # }
+///
+/// let (i_prot, o_prot) =
+///     client::connect("localhost:9090", TProtocolKind::Compact, TTransportKind::Framed)?;
+/// let client = CalculatorSyncClient::new(i_prot, o_prot);
+/// # Ok::<(), thrift::Error>(())
+/// ```
+pub fn connect<S: Into<String>>(
+    address: S,
+    protocol: TProtocolKind,
+    transport: TTransportKind,
+) -> crate::Result<(TClientInputProtocol, TClientOutputProtocol)> {
+    TClientChannelBuilder::new(address)
+        .protocol(protocol)
+        .transport(transport.into())
+        .build()
+}
+
+/// Connection to the server, over TLS or not.
+trait Connection: Read + Write + Send {
+    fn split_boxed(self: Box<Self>)
+        -> crate::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)>;
+}
+
+impl<C> Connection for C
+where
+    C: TIoChannel + Read + Write + Send + 'static,
+{
+    fn split_boxed(
+        self: Box<Self>,
+    ) -> crate::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        split(*self)
+    }
+}
+
+fn split<C>(channel: C) -> crate::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)>
+where
+    C: TIoChannel + Send + 'static,
+{
+    let (reader, writer) = channel.split()?;
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+/// Return the host of `address`, without the port or IPv6 brackets.
+fn host(address: &str) -> &str {
+    let host = match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => address,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(not(any(feature = "rustls", feature = "tls-native")))]
+fn tls_unavailable() -> crate::Error {
+    crate::new_transport_error(
+        crate::TransportErrorKind::Unknown,
+        "TLS needs the rustls or tls-native feature",
+    )
+}
+
+fn invalid_uri(uri: &str, message: &str) -> crate::Error {
+    crate::new_transport_error(
+        crate::TransportErrorKind::Unknown,
+        format!("Invalid client URI {:?}: {}", uri, message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TCompactInputProtocol, TInputProtocol, TMessageIdentifier,
+        TMessageType, TOutputProtocol,
+    };
+
+    #[test]
+    fn must_read_layers_protocol_and_options_from_uri() {
+        let builder = TClientChannelBuilder::from_uri(
+            "thrift+framed+compact+tls://calc.example.com:9090/?timeout=250&connect_timeout=1000&keepalive=60000",
+        )
+        .unwrap();
+
+        assert_eq!(builder.address(), "calc.example.com:9090");
+        assert_eq!(builder.protocol_kind(), TProtocolKind::Compact);
+        assert!(builder.uses_tls());
+        assert!(builder.layered);
+        assert_eq!(builder.read_timeout, Some(Duration::from_millis(250)));
+        assert_eq!(builder.write_timeout, Some(Duration::from_millis(250)));
+        assert_eq!(builder.connect_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(
+            builder.keepalive,
+            Some(TTcpKeepalive::new(Duration::from_secs(60)))
+        );
+    }
+
+    #[test]
+    fn must_default_to_binary_without_tls() {
+        let builder = TClientChannelBuilder::from_uri("thrift://[::1]:9090").unwrap();
+
+        assert_eq!(builder.protocol_kind(), TProtocolKind::Binary);
+        assert!(!builder.uses_tls());
+        assert!(!builder.layered);
+        assert_eq!(host(builder.address()), "::1");
+    }
+
+    #[test]
+    fn must_reject_malformed_uris() {
+        for uri in [
+            "localhost:9090",
+            "http://localhost:9090",
+            "thrift+json://localhost:9090",
+            "thrift://",
+            "thrift://localhost:9090?timeout=soon",
+            "thrift://localhost:9090?retries=3",
+        ] {
+            assert!(
+                TClientChannelBuilder::from_uri(uri).is_err(),
+                "{} was accepted",
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn must_connect_with_stack_and_protocol_from_uri() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut frame_size = [0u8; 4];
+            stream.read_exact(&mut frame_size).unwrap();
+            let mut frame = vec![0u8; u32::from_be_bytes(frame_size) as usize];
+            stream.read_exact(&mut frame).unwrap();
+            TCompactInputProtocol::new(&frame[..])
+                .read_message_begin()
+                .unwrap()
+        });
+
+        let uri = format!("thrift+framed+compact://{}?timeout=5000", address);
+        let (_i_prot, mut o_prot) = TClientChannelBuilder::from_uri(&uri)
+            .unwrap()
+            .build()
+            .unwrap();
+        let identifier = TMessageIdentifier::new("add", TMessageType::Call, 1);
+        o_prot.write_message_begin(&identifier).unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        assert_eq!(server.join().unwrap(), identifier);
+    }
+
+    #[test]
+    fn must_connect_with_protocol_and_transport_kinds() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut frame_size = [0u8; 4];
+            stream.read_exact(&mut frame_size).unwrap();
+            let mut frame = vec![0u8; u32::from_be_bytes(frame_size) as usize];
+            stream.read_exact(&mut frame).unwrap();
+            TCompactInputProtocol::new(&frame[..])
+                .read_message_begin()
+                .unwrap()
+        });
+
+        let (_i_prot, mut o_prot) = connect(
+            address.to_string(),
+            TProtocolKind::Compact,
+            TTransportKind::Framed,
+        )
+        .unwrap();
+        let identifier = TMessageIdentifier::new("add", TMessageType::Call, 1);
+        o_prot.write_message_begin(&identifier).unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        assert_eq!(server.join().unwrap(), identifier);
+    }
+
+    #[test]
+    fn must_connect_on_first_call_when_reconnecting() {
+        // find a free port, and start the server only after building
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let uri = format!("thrift+framed://{}?timeout=5000&reconnect=3", address);
+        let (_i_prot, mut o_prot) = TClientChannelBuilder::from_uri(&uri)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let listener = TcpListener::bind(address).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut frame_size = [0u8; 4];
+            stream.read_exact(&mut frame_size).unwrap();
+            let mut frame = vec![0u8; u32::from_be_bytes(frame_size) as usize];
+            stream.read_exact(&mut frame).unwrap();
+            TBinaryInputProtocol::new(&frame[..], true)
+                .read_message_begin()
+                .unwrap()
+        });
+
+        let identifier = TMessageIdentifier::new("add", TMessageType::Call, 1);
+        o_prot.write_message_begin(&identifier).unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        assert_eq!(server.join().unwrap(), identifier);
+    }
+}
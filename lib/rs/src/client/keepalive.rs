@@ -0,0 +1,166 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use super::TReplyValidator;
+use crate::protocol::{TMessageIdentifier, TMessageType, TStructIdentifier, TType};
+use crate::TThriftClient;
+
+/// Make a call of `method` with no arguments on `client` and read its
+/// reply, to check that the connection still works and keep it from being
+/// dropped for being idle.
+///
+/// The reply itself is skipped. A reply with an application error, such as
+/// an unknown method, counts as success since it arrived over the
+/// connection, so `method` need not exist on the server; pick a cheap one
+/// if the service has it. Pass it to `TLoadBalancer::with_keepalive` to
+/// ping idle pooled clients.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use thrift::client::{self, TEndpoint, TLoadBalancer};
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{TIoChannel, TTcpChannel};
+/// # use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// # struct CalculatorSyncClient<I, O>(I, O, i32);
+/// # impl<I, O> CalculatorSyncClient<I, O> {
+/// #     fn new(i: I, o: O) -> Self { CalculatorSyncClient(i, o, 0) }
+/// # }
+/// # impl<I: TInputProtocol, O: TOutputProtocol> thrift::TThriftClient for CalculatorSyncClient<I, O> {
+/// #     fn i_prot_mut(&mut self) -> &mut dyn TInputProtocol { &mut self.0 }
+/// #     fn o_prot_mut(&mut self) -> &mut dyn TOutputProtocol { &mut self.1 }
+/// #     fn sequence_number(&self) -> i32 { self.2 }
+/// #     fn increment_sequence_number(&mut self) -> i32 { self.2 += 1; self.2 }
+/// # }
+///
+/// let balancer = TLoadBalancer::new(vec!["calc.example.com:9090"], |endpoint: &TEndpoint| {
+///     let mut channel = TTcpChannel::new();
+///     channel.open(endpoint.address())?;
+///     let (i_chan, o_chan) = channel.split()?;
+///     Ok(CalculatorSyncClient::new(
+///         TBinaryInputProtocol::new(i_chan, true),
+///         TBinaryOutputProtocol::new(o_chan, true),
+///     ))
+/// })
+/// .with_keepalive(Duration::from_secs(30), |c| client::ping(c, "ping"));
+/// ```
+pub fn ping<C: TThriftClient + ?Sized>(client: &mut C, method: &str) -> crate::Result<()> {
+    let sequence_number = client.increment_sequence_number();
+    let o_prot = client.o_prot_mut();
+    o_prot.write_message_begin(&TMessageIdentifier::new(
+        method,
+        TMessageType::Call,
+        sequence_number,
+    ))?;
+    o_prot.write_struct_begin(&TStructIdentifier::new(format!("{}_args", method)))?;
+    o_prot.write_field_stop()?;
+    o_prot.write_struct_end()?;
+    o_prot.write_message_end()?;
+    o_prot.flush()?;
+
+    let i_prot = client.i_prot_mut();
+    TReplyValidator::new().read_reply_begin(i_prot, method, sequence_number)?;
+    i_prot.skip(TType::Struct)?;
+    i_prot.read_message_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TOutputProtocol,
+    };
+    use crate::transport::TBufferChannel;
+    use crate::{ApplicationError, ApplicationErrorKind};
+
+    struct Client {
+        i_prot: TBinaryInputProtocol<TBufferChannel>,
+        o_prot: TBinaryOutputProtocol<TBufferChannel>,
+        sequence_number: i32,
+    }
+
+    impl TThriftClient for Client {
+        fn i_prot_mut(&mut self) -> &mut dyn TInputProtocol {
+            &mut self.i_prot
+        }
+        fn o_prot_mut(&mut self) -> &mut dyn TOutputProtocol {
+            &mut self.o_prot
+        }
+        fn sequence_number(&self) -> i32 {
+            self.sequence_number
+        }
+        fn increment_sequence_number(&mut self) -> i32 {
+            self.sequence_number += 1;
+            self.sequence_number
+        }
+    }
+
+    /// Client whose server answers `method` with sequence number
+    /// `sequence_number` with an unknown method error.
+    fn client(method: &str, sequence_number: i32) -> Client {
+        let mut channel = TBufferChannel::with_capacity(256, 256);
+        {
+            let mut o_prot = TBinaryOutputProtocol::new(&mut channel, true);
+            o_prot
+                .write_message_begin(&TMessageIdentifier::new(
+                    method,
+                    TMessageType::Exception,
+                    sequence_number,
+                ))
+                .unwrap();
+            crate::Error::write_application_error_to_out_protocol(
+                &ApplicationError::new(ApplicationErrorKind::UnknownMethod, "no such method"),
+                &mut o_prot,
+            )
+            .unwrap();
+            o_prot.write_message_end().unwrap();
+        }
+        channel.copy_write_buffer_to_read_buffer();
+        Client {
+            i_prot: TBinaryInputProtocol::new(channel, true),
+            o_prot: TBinaryOutputProtocol::new(TBufferChannel::with_capacity(0, 256), true),
+            sequence_number: 0,
+        }
+    }
+
+    #[test]
+    fn must_count_application_error_as_successful_ping() {
+        let mut client = client("ping", 1);
+
+        ping(&mut client, "ping").unwrap();
+
+        let written = client.o_prot.transport.write_bytes();
+        let mut written = TBinaryInputProtocol::new(&written[..], true);
+        assert_eq!(
+            written.read_message_begin().unwrap(),
+            TMessageIdentifier::new("ping", TMessageType::Call, 1)
+        );
+    }
+
+    #[test]
+    fn must_fail_ping_on_mismatched_reply() {
+        let mut client = client("ping", 7);
+
+        match ping(&mut client, "ping") {
+            Err(crate::Error::Application(e)) => {
+                assert_eq!(e.kind, ApplicationErrorKind::BadSequenceId)
+            }
+            other => panic!("expected bad sequence id, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{TClientCall, TClientMiddleware};
+use crate::protocol::{TMessageIdentifier, TOutputProtocol};
+use crate::transport::TTransportObserver;
+
+/// Receives measurements of the calls made by clients.
+///
+/// Attach an implementation to a client through a `TClientMeter`. Methods
+/// are called on the thread making the call and must be cheap: they run on
+/// every call.
+///
+/// With the `metrics` feature, `TClientMetricsRecorder` forwards all
+/// measurements to the `metrics` crate.
+pub trait TClientMetrics: Send + Sync {
+    /// Called when a call has completed or failed.
+    fn call_completed(&self, _call: &TCallMeasurement<'_>) {}
+}
+
+/// Measurements of one call, passed to `TClientMetrics`.
+#[derive(Debug)]
+pub struct TCallMeasurement<'a> {
+    method: &'a str,
+    latency: Duration,
+    request_size: Option<u64>,
+    reply_size: Option<u64>,
+    error: Option<&'a crate::Error>,
+}
+
+impl TCallMeasurement<'_> {
+    /// Name of the method called.
+    pub fn method(&self) -> &str {
+        self.method
+    }
+
+    /// Time from the start of the request to the end of the reply, or to
+    /// the failure of the call.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Bytes sent for the request, including any framing, or `None` if the
+    /// client's channel is not metered.
+    pub fn request_size(&self) -> Option<u64> {
+        self.request_size
+    }
+
+    /// Bytes received for the reply, including any framing, or `None` if
+    /// the client's channel is not metered.
+    pub fn reply_size(&self) -> Option<u64> {
+        self.reply_size
+    }
+
+    /// Error the call failed with, if it failed. A reply that is an
+    /// exception from the server is not an error.
+    pub fn error(&self) -> Option<&crate::Error> {
+        self.error
+    }
+}
+
+/// Measures the calls made by one client and reports them to a
+/// `TClientMetrics`.
+///
+/// A meter is a `TClientMiddleware`: wrap the client's protocols with it
+/// using `with_middleware` to measure the method, latency and outcome of
+/// every call. It is also a `TTransportObserver`: wrap the client's channel
+/// in a `TInstrumentedChannel` observed by the same meter to measure the
+/// size of requests and replies too. Any channel can be metered this way,
+/// including the clients a `TLoadBalancer` builds; use a meter for each
+/// client, sharing one `TClientMetrics`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use thrift::client::{self, TCallMeasurement, TClientMeter, TClientMetrics};
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{TInstrumentedChannel, TIoChannel, TTcpChannel};
+///
+/// struct Logger;
+///
+/// impl TClientMetrics for Logger {
+///     fn call_completed(&self, call: &TCallMeasurement<'_>) {
+///         println!("{} took {:?}", call.method(), call.latency());
+///     }
+/// }
+///
+/// let meter = TClientMeter::new(Arc::new(Logger));
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+/// let channel = TInstrumentedChannel::new(channel, Arc::new(meter.clone()));
+/// let (i_chan, o_chan) = channel.split().unwrap();
+/// let (i_prot, o_prot) = client::with_middleware(
+///     TBinaryInputProtocol::new(i_chan, true),
+///     TBinaryOutputProtocol::new(o_chan, true),
+///     Arc::new(meter),
+/// );
+/// // let mut client = CalculatorSyncClient::new(i_prot, o_prot);
+/// # let _ = (i_prot, o_prot);
+/// ```
+#[derive(Clone)]
+pub struct TClientMeter {
+    metrics: Arc<dyn TClientMetrics>,
+    traffic: Arc<Traffic>,
+}
+
+#[derive(Default)]
+struct Traffic {
+    metered: AtomicBool,
+    written: AtomicU64,
+    read: AtomicU64,
+}
+
+impl TClientMeter {
+    /// Create a `TClientMeter` that reports calls to `metrics`.
+    pub fn new(metrics: Arc<dyn TClientMetrics>) -> TClientMeter {
+        TClientMeter {
+            metrics,
+            traffic: Arc::new(Traffic::default()),
+        }
+    }
+}
+
+impl fmt::Debug for TClientMeter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TClientMeter")
+            .field("metered", &self.traffic.metered.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl TClientMiddleware for TClientMeter {
+    fn on_send(
+        &self,
+        _identifier: &mut TMessageIdentifier,
+        _o_prot: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()> {
+        self.traffic.written.store(0, Ordering::Relaxed);
+        self.traffic.read.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn on_complete(&self, call: &TClientCall, result: Result<(), &crate::Error>) {
+        let metered = self.traffic.metered.load(Ordering::Relaxed);
+        let size = |bytes: &AtomicU64| Some(bytes.load(Ordering::Relaxed)).filter(|_| metered);
+        self.metrics.call_completed(&TCallMeasurement {
+            method: &call.identifier().name,
+            latency: call.elapsed(),
+            request_size: size(&self.traffic.written),
+            reply_size: size(&self.traffic.read),
+            error: result.err(),
+        });
+    }
+}
+
+impl TTransportObserver for TClientMeter {
+    fn on_open(&self) {
+        self.traffic.metered.store(true, Ordering::Relaxed);
+    }
+
+    fn on_bytes_read(&self, count: usize) {
+        self.traffic.read.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn on_bytes_written(&self, count: usize) {
+        self.traffic
+            .written
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+/// `TClientMetrics` that records measurements with the `metrics` crate.
+///
+/// Whichever recorder the application installs with `metrics` receives:
+///
+/// * `thrift_client_calls_total`: counter of calls, labelled `method`
+/// * `thrift_client_call_duration_seconds`: histogram of call latencies,
+///   labelled `method`
+/// * `thrift_client_request_bytes` and `thrift_client_reply_bytes`:
+///   histograms of payload sizes, labelled `method`, for metered channels
+/// * `thrift_client_errors_total`: counter of failed calls, labelled
+///   `method`, `error` (`transport`, `protocol`, `application` or `user`)
+///   and `kind`, the error's kind within its category
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TClientMetricsRecorder;
+
+#[cfg(feature = "metrics")]
+impl TClientMetricsRecorder {
+    /// Create a `TClientMetricsRecorder`.
+    pub fn new() -> TClientMetricsRecorder {
+        TClientMetricsRecorder
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl TClientMetrics for TClientMetricsRecorder {
+    fn call_completed(&self, call: &TCallMeasurement<'_>) {
+        let method = call.method().to_owned();
+        metrics::counter!("thrift_client_calls_total", "method" => method.clone()).increment(1);
+        metrics::histogram!("thrift_client_call_duration_seconds", "method" => method.clone())
+            .record(call.latency().as_secs_f64());
+        if let Some(size) = call.request_size() {
+            metrics::histogram!("thrift_client_request_bytes", "method" => method.clone())
+                .record(size as f64);
+        }
+        if let Some(size) = call.reply_size() {
+            metrics::histogram!("thrift_client_reply_bytes", "method" => method.clone())
+                .record(size as f64);
+        }
+        if let Some(e) = call.error() {
+            let (error, kind) = match e {
+                crate::Error::Transport(e) => ("transport", format!("{:?}", e.kind)),
+                crate::Error::Protocol(e) => ("protocol", format!("{:?}", e.kind)),
+                crate::Error::Application(e) => ("application", format!("{:?}", e.kind)),
+                crate::Error::User(_) => ("user", "User".to_owned()),
+            };
+            metrics::counter!(
+                "thrift_client_errors_total",
+                "method" => method,
+                "error" => error,
+                "kind" => kind
+            )
+            .increment(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use crate::client::with_middleware;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TMessageType,
+    };
+    use crate::transport::{TBufferChannel, TInstrumentedChannel, TIoChannel};
+
+    /// Method, request size, reply size and success of a call.
+    type Call = (String, Option<u64>, Option<u64>, bool);
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Mutex<Vec<Call>>,
+    }
+
+    impl TClientMetrics for Recorder {
+        fn call_completed(&self, call: &TCallMeasurement<'_>) {
+            self.calls.lock().unwrap().push((
+                call.method().to_owned(),
+                call.request_size(),
+                call.reply_size(),
+                call.error().is_none(),
+            ));
+        }
+    }
+
+    /// Make a call of `method` over `channel`, returning whether the reply
+    /// was read.
+    fn call<C: TIoChannel>(channel: C, meter: TClientMeter, method: &str) -> bool {
+        let (i_chan, o_chan) = channel.split().unwrap();
+        let (mut i_prot, mut o_prot) = with_middleware(
+            TBinaryInputProtocol::new(i_chan, true),
+            TBinaryOutputProtocol::new(o_chan, true),
+            Arc::new(meter),
+        );
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new(method, TMessageType::Call, 1))
+            .unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+        i_prot
+            .read_message_begin()
+            .and_then(|_| i_prot.read_message_end())
+            .is_ok()
+    }
+
+    fn reply(method: &str) -> TBufferChannel {
+        let mut channel = TBufferChannel::with_capacity(64, 64);
+        let mut o_prot = TBinaryOutputProtocol::new(&mut channel, true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new(method, TMessageType::Reply, 1))
+            .unwrap();
+        o_prot.write_message_end().unwrap();
+        channel.copy_write_buffer_to_read_buffer();
+        channel.empty_write_buffer();
+        channel
+    }
+
+    #[test]
+    fn must_measure_calls_and_payload_sizes() {
+        let recorder = Arc::new(Recorder::default());
+        let meter = TClientMeter::new(recorder.clone());
+
+        let channel = TInstrumentedChannel::new(reply("add"), Arc::new(meter.clone()));
+        assert!(call(channel, meter, "add"));
+
+        // a binary message header for "add" is 4 + 4 + 3 + 4 bytes long
+        assert_eq!(
+            *recorder.calls.lock().unwrap(),
+            [("add".to_owned(), Some(15), Some(15), true)]
+        );
+    }
+
+    #[test]
+    fn must_report_failed_calls_without_sizes_when_channel_is_not_metered() {
+        let recorder = Arc::new(Recorder::default());
+        let meter = TClientMeter::new(recorder.clone());
+
+        assert!(!call(TBufferChannel::with_capacity(0, 64), meter, "add"));
+
+        assert_eq!(
+            *recorder.calls.lock().unwrap(),
+            [("add".to_owned(), None, None, false)]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn must_forward_measurements_to_metrics_crate() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let debugging = DebuggingRecorder::new();
+        let snapshotter = debugging.snapshotter();
+        metrics::with_local_recorder(&debugging, || {
+            let recorder = TClientMetricsRecorder::new();
+            recorder.call_completed(&TCallMeasurement {
+                method: "ping",
+                latency: Duration::from_millis(5),
+                request_size: Some(20),
+                reply_size: Some(30),
+                error: None,
+            });
+            recorder.call_completed(&TCallMeasurement {
+                method: "ping",
+                latency: Duration::from_millis(7),
+                request_size: None,
+                reply_size: None,
+                error: Some(&crate::new_transport_error(
+                    crate::TransportErrorKind::TimedOut,
+                    "timed out",
+                )),
+            });
+        });
+
+        let mut values: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let value = match value {
+                    DebugValue::Counter(n) => n as f64,
+                    DebugValue::Gauge(g) => g.into_inner(),
+                    DebugValue::Histogram(h) => h.len() as f64,
+                };
+                let labels: Vec<_> = key.key().labels().map(|l| l.value().to_owned()).collect();
+                (key.key().name().to_owned(), labels, value)
+            })
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        let ping = || vec!["ping".to_owned()];
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "thrift_client_call_duration_seconds".to_owned(),
+                    ping(),
+                    2.0
+                ),
+                ("thrift_client_calls_total".to_owned(), ping(), 2.0),
+                (
+                    "thrift_client_errors_total".to_owned(),
+                    vec![
+                        "ping".to_owned(),
+                        "transport".to_owned(),
+                        "TimedOut".to_owned()
+                    ],
+                    1.0
+                ),
+                ("thrift_client_reply_bytes".to_owned(), ping(), 1.0),
+                ("thrift_client_request_bytes".to_owned(), ping(), 1.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,622 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TMessageType, TOutputProtocol, TRawString, TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::TConfiguration;
+
+/// Hooks run around every call a client makes.
+///
+/// A middleware sees each call through the protocols of the client making
+/// it, whatever the service: wrap a client's protocols with
+/// `with_middleware` before passing them to the generated client's
+/// constructor. It can attach data such as auth tokens or trace context to
+/// requests, check replies, and record the outcome and latency of calls.
+///
+/// The hooks are called on the thread making the call:
+///
+/// 1. `on_send` before the header of the request is written. It may change
+///    the header, or write to the protocol ahead of it.
+/// 2. `on_reply` once the header of the reply has been read. It may change
+///    the header before the client sees it, or read from the protocol.
+/// 3. `on_complete` once the reply has been read, a oneway call has been
+///    sent, or the call failed while being sent or received. A reply that
+///    is an exception completes the call successfully: the exception is an
+///    answer from the server, and `on_reply` sees its message type.
+///
+/// An error returned by `on_send` fails the call before it is started, and
+/// `on_complete` is not called for it. An error returned by `on_reply`
+/// fails the call with that error.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use thrift::client::{self, TClientCall, TClientMiddleware};
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::TBufferChannel;
+///
+/// struct Logger;
+///
+/// impl TClientMiddleware for Logger {
+///     fn on_complete(&self, call: &TClientCall, result: Result<(), &thrift::Error>) {
+///         println!("{} took {:?}: {:?}", call.identifier().name, call.elapsed(), result);
+///     }
+/// }
+///
+/// let channel = TBufferChannel::new();
+/// let (i_prot, o_prot) = client::with_middleware(
+///     TBinaryInputProtocol::new(channel.clone(), true),
+///     TBinaryOutputProtocol::new(channel, true),
+///     Arc::new(Logger),
+/// );
+/// // let mut client = CalculatorSyncClient::new(i_prot, o_prot);
+/// # let _ = (i_prot, o_prot);
+/// ```
+pub trait TClientMiddleware: Send + Sync {
+    /// Called before the header of a request, `identifier`, is written to
+    /// `o_prot`.
+    fn on_send(
+        &self,
+        identifier: &mut TMessageIdentifier,
+        o_prot: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()> {
+        let _ = (identifier, o_prot);
+        Ok(())
+    }
+
+    /// Called after the header of the reply to `call`, `reply`, has been
+    /// read from `i_prot`.
+    fn on_reply(
+        &self,
+        call: &TClientCall,
+        reply: &mut TMessageIdentifier,
+        i_prot: &mut dyn TInputProtocol,
+    ) -> crate::Result<()> {
+        let _ = (call, reply, i_prot);
+        Ok(())
+    }
+
+    /// Called once `call` has completed or failed.
+    fn on_complete(&self, call: &TClientCall, result: Result<(), &crate::Error>) {
+        let _ = (call, result);
+    }
+}
+
+/// A call seen by a `TClientMiddleware`.
+#[derive(Clone, Debug)]
+pub struct TClientCall {
+    identifier: TMessageIdentifier,
+    started: Instant,
+}
+
+impl TClientCall {
+    /// Return the header of the request, as written.
+    pub fn identifier(&self) -> &TMessageIdentifier {
+        &self.identifier
+    }
+
+    /// Return when the call was started.
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Return the time since the call was started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Wrap the input and output protocols of a client so that `middleware` is
+/// run around each of its calls.
+///
+/// Wrap protocols that are already wrapped to run several middlewares: the
+/// outermost one sees requests first and replies last.
+pub fn with_middleware<I, O>(
+    i_prot: I,
+    o_prot: O,
+    middleware: Arc<dyn TClientMiddleware>,
+) -> (TMiddlewareInputProtocol<I>, TMiddlewareOutputProtocol<O>)
+where
+    I: TInputProtocol,
+    O: TOutputProtocol,
+{
+    let hooks = Hooks {
+        middleware,
+        call: Arc::new(Mutex::new(None)),
+    };
+    (
+        TMiddlewareInputProtocol {
+            inner: i_prot,
+            hooks: hooks.clone(),
+        },
+        TMiddlewareOutputProtocol {
+            inner: o_prot,
+            hooks,
+        },
+    )
+}
+
+/// The middleware of a pair of protocols and the call in progress on them.
+#[derive(Clone)]
+struct Hooks {
+    middleware: Arc<dyn TClientMiddleware>,
+    call: Arc<Mutex<Option<TClientCall>>>,
+}
+
+impl Hooks {
+    fn start(&self, identifier: TMessageIdentifier) {
+        *self.lock() = Some(TClientCall {
+            identifier,
+            started: Instant::now(),
+        });
+    }
+
+    fn current(&self) -> Option<TClientCall> {
+        self.lock().clone()
+    }
+
+    fn complete(&self, result: Result<(), &crate::Error>) {
+        // release the lock before running the hook
+        let call = self.lock().take();
+        if let Some(call) = call {
+            self.middleware.on_complete(&call, result);
+        }
+    }
+
+    /// Complete the call in progress if `result` is an error.
+    fn check<T>(&self, result: crate::Result<T>) -> crate::Result<T> {
+        if let Err(ref e) = result {
+            self.complete(Err(e));
+        }
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<TClientCall>> {
+        self.call.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("call", &*self.lock())
+            .finish_non_exhaustive()
+    }
+}
+
+/// `TInputProtocol` that runs a `TClientMiddleware` on the replies it
+/// reads. Created by `with_middleware`.
+#[derive(Debug)]
+pub struct TMiddlewareInputProtocol<P>
+where
+    P: TInputProtocol,
+{
+    inner: P,
+    hooks: Hooks,
+}
+
+impl<P> TMiddlewareInputProtocol<P>
+where
+    P: TInputProtocol,
+{
+    /// Return the wrapped protocol.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+// FIXME: avoid passthrough methods
+impl<P> TInputProtocol for TMiddlewareInputProtocol<P>
+where
+    P: TInputProtocol,
+{
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        let result = self.inner.read_message_begin().and_then(|mut reply| {
+            if let Some(call) = self.hooks.current() {
+                self.hooks
+                    .middleware
+                    .on_reply(&call, &mut reply, &mut self.inner)?;
+            }
+            Ok(reply)
+        });
+        self.hooks.check(result)
+    }
+
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        let result = self.inner.read_message_end();
+        self.hooks.complete(result.as_ref().map(|_| ()));
+        result
+    }
+
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.hooks.check(self.inner.read_struct_begin())
+    }
+
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.read_struct_end())
+    }
+
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        self.hooks.check(self.inner.read_field_begin())
+    }
+
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.read_field_end())
+    }
+
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        self.hooks.check(self.inner.read_bool())
+    }
+
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        self.hooks.check(self.inner.read_bytes())
+    }
+
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        self.hooks.check(self.inner.read_i8())
+    }
+
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.hooks.check(self.inner.read_i16())
+    }
+
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        self.hooks.check(self.inner.read_i32())
+    }
+
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        self.hooks.check(self.inner.read_i64())
+    }
+
+    fn read_double(&mut self) -> crate::Result<f64> {
+        self.hooks.check(self.inner.read_double())
+    }
+
+    fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
+        self.hooks.check(self.inner.read_uuid())
+    }
+
+    fn read_string(&mut self) -> crate::Result<String> {
+        self.hooks.check(self.inner.read_string())
+    }
+
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        self.hooks.check(self.inner.read_raw_string())
+    }
+
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        self.hooks.check(self.inner.read_list_begin())
+    }
+
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.read_list_end())
+    }
+
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        self.hooks.check(self.inner.read_set_begin())
+    }
+
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.read_set_end())
+    }
+
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        self.hooks.check(self.inner.read_map_begin())
+    }
+
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.read_map_end())
+    }
+
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        self.hooks
+            .check(self.inner.skip_till_depth(field_type, depth))
+    }
+
+    fn min_serialized_size(&self, field_type: TType) -> usize {
+        self.inner.min_serialized_size(field_type)
+    }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        self.inner.set_configuration(config)
+    }
+
+    // utility
+    //
+
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        self.hooks.check(self.inner.read_byte())
+    }
+}
+
+/// `TOutputProtocol` that runs a `TClientMiddleware` on the requests it
+/// writes. Created by `with_middleware`.
+#[derive(Debug)]
+pub struct TMiddlewareOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    inner: P,
+    hooks: Hooks,
+}
+
+impl<P> TMiddlewareOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    /// Return the wrapped protocol.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+// FIXME: avoid passthrough methods
+impl<P> TOutputProtocol for TMiddlewareOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        let mut identifier = identifier.clone();
+        self.hooks
+            .middleware
+            .on_send(&mut identifier, &mut self.inner)?;
+        self.hooks.start(identifier.clone());
+        self.hooks
+            .check(self.inner.write_message_begin(&identifier))
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_message_end())
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_struct_begin(identifier))
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_struct_end())
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_field_begin(identifier))
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_field_end())
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_field_stop())
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_bool(b))
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_bytes(b))
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_i8(i))
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_i16(i))
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_i32(i))
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_i64(i))
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_double(d))
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_uuid(uuid))
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_string(s))
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_raw_string(s))
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_list_begin(identifier))
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_list_end())
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_set_begin(identifier))
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_set_end())
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_map_begin(identifier))
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_map_end())
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        let result = self.hooks.check(self.inner.flush());
+        // no reply will complete a oneway call
+        let oneway = matches!(
+            self.hooks.current(),
+            Some(call) if call.identifier.message_type == TMessageType::OneWay
+        );
+        if result.is_ok() && oneway {
+            self.hooks.complete(Ok(()));
+        }
+        result
+    }
+
+    // utility
+    //
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.hooks.check(self.inner.write_byte(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+    use crate::transport::TBufferChannel;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn events(&self) -> Vec<String> {
+            self.events.lock().unwrap().clone()
+        }
+
+        fn push(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl TClientMiddleware for Recorder {
+        fn on_send(
+            &self,
+            identifier: &mut TMessageIdentifier,
+            _: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            self.push(format!("send {}", identifier.name));
+            identifier.name = format!("Calculator:{}", identifier.name);
+            Ok(())
+        }
+
+        fn on_reply(
+            &self,
+            call: &TClientCall,
+            reply: &mut TMessageIdentifier,
+            _: &mut dyn TInputProtocol,
+        ) -> crate::Result<()> {
+            self.push(format!(
+                "reply {} to {}",
+                reply.name,
+                call.identifier().name
+            ));
+            reply.name = "add".to_owned();
+            Ok(())
+        }
+
+        fn on_complete(&self, call: &TClientCall, result: Result<(), &crate::Error>) {
+            self.push(format!(
+                "complete {} {}",
+                call.identifier().sequence_number,
+                if result.is_ok() { "ok" } else { "failed" }
+            ));
+        }
+    }
+
+    fn protocols(
+        channel: &TBufferChannel,
+        recorder: &Arc<Recorder>,
+    ) -> (
+        TMiddlewareInputProtocol<TBinaryInputProtocol<TBufferChannel>>,
+        TMiddlewareOutputProtocol<TBinaryOutputProtocol<TBufferChannel>>,
+    ) {
+        with_middleware(
+            TBinaryInputProtocol::new(channel.clone(), true),
+            TBinaryOutputProtocol::new(channel.clone(), true),
+            recorder.clone(),
+        )
+    }
+
+    fn send(o_prot: &mut dyn TOutputProtocol, message_type: TMessageType) -> crate::Result<()> {
+        o_prot.write_message_begin(&TMessageIdentifier::new("add", message_type, 4))?;
+        o_prot.write_i32(1)?;
+        o_prot.write_message_end()?;
+        o_prot.flush()
+    }
+
+    #[test]
+    fn must_run_hooks_around_call() {
+        let recorder = Arc::new(Recorder::default());
+        let mut channel = TBufferChannel::new();
+        let (mut i_prot, mut o_prot) = protocols(&channel, &recorder);
+
+        send(&mut o_prot, TMessageType::Call).unwrap();
+        // answer with the request itself
+        channel.copy_write_buffer_to_read_buffer();
+        let reply = i_prot.read_message_begin().unwrap();
+        assert_eq!(reply.name, "add");
+        assert_eq!(i_prot.read_i32().unwrap(), 1);
+        i_prot.read_message_end().unwrap();
+
+        assert_eq!(
+            recorder.events(),
+            [
+                "send add",
+                "reply Calculator:add to Calculator:add",
+                "complete 4 ok"
+            ]
+        );
+    }
+
+    #[test]
+    fn must_complete_oneway_call_once_sent() {
+        let recorder = Arc::new(Recorder::default());
+        let channel = TBufferChannel::new();
+        let (_i_prot, mut o_prot) = protocols(&channel, &recorder);
+
+        send(&mut o_prot, TMessageType::OneWay).unwrap();
+
+        assert_eq!(recorder.events(), ["send add", "complete 4 ok"]);
+    }
+
+    #[test]
+    fn must_complete_call_that_fails_to_read_reply() {
+        let recorder = Arc::new(Recorder::default());
+        let channel = TBufferChannel::new();
+        let (mut i_prot, mut o_prot) = protocols(&channel, &recorder);
+
+        send(&mut o_prot, TMessageType::Call).unwrap();
+        assert!(i_prot.read_message_begin().is_err());
+
+        assert_eq!(recorder.events(), ["send add", "complete 4 failed"]);
+    }
+}
@@ -0,0 +1,57 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Types used to call Thrift services.
+//!
+//! Generated clients implement their service's calls for any type that
+//! implements `TThriftClient`. The types in this module work with any of
+//! them, adding behaviour such as retries around the calls.
+
+mod balance;
+mod breaker;
+mod builder;
+mod keepalive;
+mod metrics;
+mod middleware;
+mod multiplexed;
+mod oneway;
+mod pipeline;
+mod reply;
+mod resolve;
+mod retry;
+
+pub use self::balance::{TBalancePolicy, TEndpoint, TLoadBalancer};
+pub use self::breaker::{TCircuitBreaker, TCircuitState};
+pub use self::builder::{
+    connect, TClientChannelBuilder, TClientInputProtocol, TClientOutputProtocol,
+};
+pub use self::keepalive::ping;
+#[cfg(feature = "metrics")]
+pub use self::metrics::TClientMetricsRecorder;
+pub use self::metrics::{TCallMeasurement, TClientMeter, TClientMetrics};
+pub use self::middleware::{
+    with_middleware, TClientCall, TClientMiddleware, TMiddlewareInputProtocol,
+    TMiddlewareOutputProtocol,
+};
+pub use self::multiplexed::{
+    TMultiplexedClientFactory, TSharedInputProtocol, TSharedOutputProtocol,
+};
+pub use self::oneway::{send_oneway, TBatchingOutputProtocol};
+pub use self::pipeline::{TPipelinedChannel, TPipelinedConnection};
+pub use self::reply::TReplyValidator;
+pub use self::resolve::{TDnsResolver, TResolver};
+pub use self::retry::{TRetryPolicy, TRetryingClient};
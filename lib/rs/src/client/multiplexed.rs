@@ -0,0 +1,597 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TMessageType, TMultiplexedOutputProtocol, TOutputProtocol, TRawString, TSetIdentifier,
+    TStructIdentifier, TType,
+};
+use crate::TConfiguration;
+
+/// Creates clients of several services that share one connection.
+///
+/// Every client created by a `TMultiplexedClientFactory` reads and writes
+/// through the same pair of protocols, prefixing the name of its service to
+/// the calls it makes as a `TMultiplexedOutputProtocol` does. The server
+/// must route them with a `TMultiplexedProcessor`.
+///
+/// Clients may be used from different threads. A client takes the
+/// connection when it starts writing a call and holds it until it has read
+/// the reply, or until it has flushed a oneway call, so that calls and
+/// replies of different clients never interleave: a client starting a call
+/// while another one is in progress waits for it to complete. A call that
+/// fails releases the connection, although the connection itself may no
+/// longer be usable.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client::TMultiplexedClientFactory;
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{TIoChannel, TTcpChannel};
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090")?;
+/// let (i_chan, o_chan) = channel.split()?;
+///
+/// let factory = TMultiplexedClientFactory::new(
+///     TBinaryInputProtocol::new(i_chan, true),
+///     TBinaryOutputProtocol::new(o_chan, true),
+/// );
+/// // let calculator = factory.client("Calculator", CalculatorSyncClient::new);
+/// // let logger = factory.client("Logger", LoggerSyncClient::new);
+/// # Ok::<(), thrift::Error>(())
+/// ```
+pub struct TMultiplexedClientFactory<I, O>
+where
+    I: TInputProtocol,
+    O: TOutputProtocol,
+{
+    shared: Arc<Shared<I, O>>,
+}
+
+impl<I, O> TMultiplexedClientFactory<I, O>
+where
+    I: TInputProtocol,
+    O: TOutputProtocol,
+{
+    /// Create a `TMultiplexedClientFactory` whose clients read replies
+    /// from `i_prot` and write calls to `o_prot`.
+    pub fn new(i_prot: I, o_prot: O) -> TMultiplexedClientFactory<I, O> {
+        TMultiplexedClientFactory {
+            shared: Arc::new(Shared {
+                connection: Mutex::new(Connection {
+                    i_prot,
+                    o_prot,
+                    caller: None,
+                    oneway: false,
+                    callers: 0,
+                }),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Return the input and output protocols for a client of the service
+    /// named `service_name`.
+    pub fn protocols(
+        &self,
+        service_name: &str,
+    ) -> (
+        TSharedInputProtocol<I, O>,
+        TMultiplexedOutputProtocol<TSharedOutputProtocol<I, O>>,
+    ) {
+        let id = {
+            let mut connection = self.shared.lock();
+            connection.callers += 1;
+            connection.callers
+        };
+        let caller = Arc::new(Caller {
+            shared: self.shared.clone(),
+            id,
+        });
+        (
+            TSharedInputProtocol {
+                caller: caller.clone(),
+            },
+            TMultiplexedOutputProtocol::new(service_name, TSharedOutputProtocol { caller }),
+        )
+    }
+
+    /// Create a client of the service named `service_name` by passing its
+    /// protocols to `new`, usually the constructor of the generated client.
+    pub fn client<C, F>(&self, service_name: &str, new: F) -> C
+    where
+        F: FnOnce(
+            TSharedInputProtocol<I, O>,
+            TMultiplexedOutputProtocol<TSharedOutputProtocol<I, O>>,
+        ) -> C,
+    {
+        let (i_prot, o_prot) = self.protocols(service_name);
+        new(i_prot, o_prot)
+    }
+}
+
+impl<I, O> Clone for TMultiplexedClientFactory<I, O>
+where
+    I: TInputProtocol,
+    O: TOutputProtocol,
+{
+    fn clone(&self) -> Self {
+        TMultiplexedClientFactory {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<I, O> fmt::Debug for TMultiplexedClientFactory<I, O>
+where
+    I: TInputProtocol,
+    O: TOutputProtocol,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TMultiplexedClientFactory")
+            .field("shared", &self.shared)
+            .finish()
+    }
+}
+
+struct Shared<I, O> {
+    connection: Mutex<Connection<I, O>>,
+    released: Condvar,
+}
+
+struct Connection<I, O> {
+    i_prot: I,
+    o_prot: O,
+    /// The client whose call is in progress.
+    caller: Option<u64>,
+    /// Whether the call in progress is a oneway call.
+    oneway: bool,
+    callers: u64,
+}
+
+impl<I, O> Shared<I, O> {
+    fn lock(&self) -> MutexGuard<'_, Connection<I, O>> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<I, O> fmt::Debug for Shared<I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let connection = self.lock();
+        f.debug_struct("Shared")
+            .field("caller", &connection.caller)
+            .field("oneway", &connection.oneway)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The client using a pair of protocols created by a
+/// `TMultiplexedClientFactory`.
+struct Caller<I, O> {
+    shared: Arc<Shared<I, O>>,
+    id: u64,
+}
+
+impl<I, O> Caller<I, O> {
+    /// Wait until no other client has a call in progress, and start a call.
+    fn begin(&self, oneway: bool) -> MutexGuard<'_, Connection<I, O>> {
+        let mut connection = self.shared.lock();
+        while matches!(connection.caller, Some(id) if id != self.id) {
+            connection = self
+                .shared
+                .released
+                .wait(connection)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        connection.caller = Some(self.id);
+        connection.oneway = oneway;
+        connection
+    }
+
+    /// Run `f` on the connection, ending the call in progress if it fails.
+    fn with<T, F>(&self, f: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut Connection<I, O>) -> crate::Result<T>,
+    {
+        let mut connection = self.shared.lock();
+        let result = f(&mut connection);
+        if result.is_err() {
+            self.end(&mut connection);
+        }
+        result
+    }
+
+    /// End the call in progress if it is this client's.
+    fn end(&self, connection: &mut Connection<I, O>) {
+        if connection.caller == Some(self.id) {
+            connection.caller = None;
+            self.shared.released.notify_all();
+        }
+    }
+}
+
+impl<I, O> Drop for Caller<I, O> {
+    fn drop(&mut self) {
+        let mut connection = self.shared.lock();
+        self.end(&mut connection);
+    }
+}
+
+impl<I, O> fmt::Debug for Caller<I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Caller")
+            .field("shared", &self.shared)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// `TInputProtocol` through which a client created by a
+/// `TMultiplexedClientFactory` reads replies. Reading the end of a reply
+/// releases the connection to other clients.
+#[derive(Debug)]
+pub struct TSharedInputProtocol<I, O> {
+    caller: Arc<Caller<I, O>>,
+}
+
+// FIXME: avoid passthrough methods
+impl<I, O> TInputProtocol for TSharedInputProtocol<I, O>
+where
+    I: TInputProtocol,
+    O: TOutputProtocol,
+{
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        self.caller.with(|c| c.i_prot.read_message_begin())
+    }
+
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        let mut connection = self.caller.shared.lock();
+        let result = connection.i_prot.read_message_end();
+        self.caller.end(&mut connection);
+        result
+    }
+
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.caller.with(|c| c.i_prot.read_struct_begin())
+    }
+
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.i_prot.read_struct_end())
+    }
+
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        self.caller.with(|c| c.i_prot.read_field_begin())
+    }
+
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.i_prot.read_field_end())
+    }
+
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        self.caller.with(|c| c.i_prot.read_bool())
+    }
+
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        self.caller.with(|c| c.i_prot.read_bytes())
+    }
+
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        self.caller.with(|c| c.i_prot.read_i8())
+    }
+
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.caller.with(|c| c.i_prot.read_i16())
+    }
+
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        self.caller.with(|c| c.i_prot.read_i32())
+    }
+
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        self.caller.with(|c| c.i_prot.read_i64())
+    }
+
+    fn read_double(&mut self) -> crate::Result<f64> {
+        self.caller.with(|c| c.i_prot.read_double())
+    }
+
+    fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
+        self.caller.with(|c| c.i_prot.read_uuid())
+    }
+
+    fn read_string(&mut self) -> crate::Result<String> {
+        self.caller.with(|c| c.i_prot.read_string())
+    }
+
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        self.caller.with(|c| c.i_prot.read_raw_string())
+    }
+
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        self.caller.with(|c| c.i_prot.read_list_begin())
+    }
+
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.i_prot.read_list_end())
+    }
+
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        self.caller.with(|c| c.i_prot.read_set_begin())
+    }
+
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.i_prot.read_set_end())
+    }
+
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        self.caller.with(|c| c.i_prot.read_map_begin())
+    }
+
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.i_prot.read_map_end())
+    }
+
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        self.caller
+            .with(|c| c.i_prot.skip_till_depth(field_type, depth))
+    }
+
+    fn min_serialized_size(&self, field_type: TType) -> usize {
+        self.caller
+            .shared
+            .lock()
+            .i_prot
+            .min_serialized_size(field_type)
+    }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        self.caller.shared.lock().i_prot.set_configuration(config)
+    }
+
+    // utility
+    //
+
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        self.caller.with(|c| c.i_prot.read_byte())
+    }
+}
+
+/// `TOutputProtocol` through which a client created by a
+/// `TMultiplexedClientFactory` writes calls. Writing the header of a call
+/// waits for the connection to be released by other clients.
+#[derive(Debug)]
+pub struct TSharedOutputProtocol<I, O> {
+    caller: Arc<Caller<I, O>>,
+}
+
+// FIXME: avoid passthrough methods
+impl<I, O> TOutputProtocol for TSharedOutputProtocol<I, O>
+where
+    I: TInputProtocol,
+    O: TOutputProtocol,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        let mut connection = self
+            .caller
+            .begin(identifier.message_type == TMessageType::OneWay);
+        let result = connection.o_prot.write_message_begin(identifier);
+        if result.is_err() {
+            self.caller.end(&mut connection);
+        }
+        result
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_message_end())
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        self.caller
+            .with(|c| c.o_prot.write_struct_begin(identifier))
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_struct_end())
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_field_begin(identifier))
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_field_end())
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_field_stop())
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_bytes(b))
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_bool(b))
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_i8(i))
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_i16(i))
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_i32(i))
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_i64(i))
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_double(d))
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_string(s))
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_raw_string(s))
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_uuid(uuid))
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_list_begin(identifier))
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_list_end())
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_set_begin(identifier))
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_set_end())
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_map_begin(identifier))
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_map_end())
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        let mut connection = self.caller.shared.lock();
+        let result = connection.o_prot.flush();
+        // no reply will end a oneway call
+        if result.is_err() || connection.oneway {
+            self.caller.end(&mut connection);
+        }
+        result
+    }
+
+    // utility
+    //
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.caller.with(|c| c.o_prot.write_byte(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+    use crate::transport::TBufferChannel;
+
+    type Factory = TMultiplexedClientFactory<
+        TBinaryInputProtocol<TBufferChannel>,
+        TBinaryOutputProtocol<TBufferChannel>,
+    >;
+
+    fn factory(channel: &TBufferChannel) -> Factory {
+        TMultiplexedClientFactory::new(
+            TBinaryInputProtocol::new(channel.clone(), true),
+            TBinaryOutputProtocol::new(channel.clone(), true),
+        )
+    }
+
+    fn call(o_prot: &mut dyn TOutputProtocol, method: &str, message_type: TMessageType) {
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new(method, message_type, 1))
+            .unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    fn written(channel: &TBufferChannel) -> Vec<String> {
+        let written = channel.write_bytes();
+        let mut i_prot = TBinaryInputProtocol::new(&written[..], true);
+        let mut methods = Vec::new();
+        while let Ok(identifier) = i_prot.read_message_begin() {
+            methods.push(identifier.name);
+        }
+        methods
+    }
+
+    #[test]
+    fn must_prefix_calls_with_service_of_client() {
+        let channel = TBufferChannel::with_capacity(0, 1024);
+        let factory = factory(&channel);
+        let (_, mut calculator) = factory.protocols("Calculator");
+        let (_, mut logger) = factory.protocols("Logger");
+
+        call(&mut logger, "log", TMessageType::OneWay);
+        call(&mut calculator, "add", TMessageType::Call);
+
+        assert_eq!(written(&channel), vec!["Logger:log", "Calculator:add"]);
+    }
+
+    #[test]
+    fn must_hold_connection_until_reply_is_read() {
+        let mut channel = TBufferChannel::with_capacity(64, 1024);
+        let reply = {
+            let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+            o_prot
+                .write_message_begin(&TMessageIdentifier::new("add", TMessageType::Reply, 1))
+                .unwrap();
+            o_prot.write_message_end().unwrap();
+            o_prot.transport
+        };
+        channel.set_readable_bytes(&reply);
+        let factory = factory(&channel);
+        let (mut i_prot, mut calculator) = factory.protocols("Calculator");
+        let (_, mut logger) = factory.protocols("Logger");
+
+        call(&mut calculator, "add", TMessageType::Call);
+        let logged = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let logged = logged.clone();
+            thread::spawn(move || {
+                call(&mut logger, "log", TMessageType::OneWay);
+                logged.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!logged.load(Ordering::SeqCst));
+
+        assert_eq!(i_prot.read_message_begin().unwrap().name, "add");
+        i_prot.read_message_end().unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(written(&channel), vec!["Calculator:add", "Logger:log"]);
+    }
+}
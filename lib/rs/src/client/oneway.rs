@@ -0,0 +1,376 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TMessageType,
+    TOutputProtocol, TRawString, TSetIdentifier, TStructIdentifier,
+};
+use crate::TThriftClient;
+
+/// Send a oneway call of `method` on `client`, writing its arguments with
+/// `write_args`, and flush it.
+///
+/// No reply is read: the call is complete once the output protocol has been
+/// flushed, which with a `TBatchingOutputProtocol` may leave it in a batch
+/// to be sent with later calls. `write_args` writes the arguments struct,
+/// usually with the `write_to_out_protocol` method of the generated
+/// `<service>_<method>_args` struct.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client;
+/// use thrift::protocol::{TFieldIdentifier, TStructIdentifier, TType};
+/// # use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// # struct LoggerSyncClient<I, O>(I, O, i32);
+/// # impl<I: TInputProtocol, O: TOutputProtocol> thrift::TThriftClient for LoggerSyncClient<I, O> {
+/// #     fn i_prot_mut(&mut self) -> &mut dyn TInputProtocol { &mut self.0 }
+/// #     fn o_prot_mut(&mut self) -> &mut dyn TOutputProtocol { &mut self.1 }
+/// #     fn sequence_number(&self) -> i32 { self.2 }
+/// #     fn increment_sequence_number(&mut self) -> i32 { self.2 += 1; self.2 }
+/// # }
+/// # fn f<I: TInputProtocol, O: TOutputProtocol>(mut client: LoggerSyncClient<I, O>) -> thrift::Result<()> {
+///
+/// client::send_oneway(&mut client, "log", |o_prot| {
+///     o_prot.write_struct_begin(&TStructIdentifier::new("log_args"))?;
+///     o_prot.write_field_begin(&TFieldIdentifier::new("line", TType::String, 1))?;
+///     o_prot.write_string("started")?;
+///     o_prot.write_field_end()?;
+///     o_prot.write_field_stop()?;
+///     o_prot.write_struct_end()
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn send_oneway<C, F>(client: &mut C, method: &str, write_args: F) -> crate::Result<()>
+where
+    C: TThriftClient + ?Sized,
+    F: FnOnce(&mut dyn TOutputProtocol) -> crate::Result<()>,
+{
+    let sequence_number = client.increment_sequence_number();
+    let o_prot = client.o_prot_mut();
+    o_prot.write_message_begin(&TMessageIdentifier::new(
+        method,
+        TMessageType::OneWay,
+        sequence_number,
+    ))?;
+    write_args(o_prot)?;
+    o_prot.write_message_end()?;
+    o_prot.flush()
+}
+
+/// `TOutputProtocol` that sends oneway calls in batches.
+///
+/// Flushing a oneway call adds it to the current batch instead of sending
+/// it, until the batch holds `max_messages` calls or its first call has
+/// waited for `max_delay`; the flush that completes the batch sends it.
+/// Any other flush sends the batch along with it, including the flush of a
+/// call that expects a reply and a flush made with no message written
+/// since the last one, so `o_prot_mut().flush()` sends a partial batch.
+///
+/// The batch is held by the transport beneath the protocol, which must
+/// buffer the written bytes until it is flushed. Over a
+/// `TFramedWriteTransport` a batch is sent as a single frame holding
+/// several messages, which servers reading framed messages process one
+/// after the other. The delay is only checked when a call is flushed, so
+/// flush a quiet client's protocol periodically to bound how long a call
+/// can wait.
+#[derive(Debug)]
+pub struct TBatchingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    inner: P,
+    max_messages: usize,
+    max_delay: Option<Duration>,
+    batched: usize,
+    batch_started: Option<Instant>,
+    oneway: Option<bool>,
+}
+
+impl<P> TBatchingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    /// Create a `TBatchingOutputProtocol` that sends oneway calls written
+    /// to `wrapped` in batches of `max_messages`.
+    pub fn new(wrapped: P, max_messages: usize) -> TBatchingOutputProtocol<P> {
+        TBatchingOutputProtocol {
+            inner: wrapped,
+            max_messages,
+            max_delay: None,
+            batched: 0,
+            batch_started: None,
+            oneway: None,
+        }
+    }
+
+    /// Send a batch once its first call has waited for `max_delay`, or
+    /// only once it is full if `None`.
+    pub fn with_max_delay(mut self, max_delay: Option<Duration>) -> TBatchingOutputProtocol<P> {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Return the number of calls in the current batch, written but not
+    /// yet sent.
+    pub fn batched(&self) -> usize {
+        self.batched
+    }
+
+    /// Return the wrapped protocol. Calls in the current batch are sent
+    /// when it is next flushed.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn batch_is_due(&self) -> bool {
+        self.batched + 1 >= self.max_messages
+            || matches!(
+                (self.max_delay, self.batch_started),
+                (Some(delay), Some(started)) if started.elapsed() >= delay
+            )
+    }
+}
+
+// FIXME: avoid passthrough methods
+impl<P> TOutputProtocol for TBatchingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        self.oneway = Some(identifier.message_type == TMessageType::OneWay);
+        self.inner.write_message_begin(identifier)
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.inner.write_message_end()
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        self.inner.write_struct_begin(identifier)
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.inner.write_struct_end()
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        self.inner.write_field_begin(identifier)
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.inner.write_field_end()
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        self.inner.write_field_stop()
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.inner.write_bytes(b)
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.inner.write_bool(b)
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.inner.write_i8(i)
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.inner.write_i16(i)
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.inner.write_i32(i)
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.inner.write_i64(i)
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.inner.write_double(d)
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.inner.write_string(s)
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.inner.write_raw_string(s)
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        self.inner.write_uuid(uuid)
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.inner.write_list_begin(identifier)
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.inner.write_list_end()
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.inner.write_set_begin(identifier)
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.inner.write_set_end()
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        self.inner.write_map_begin(identifier)
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.inner.write_map_end()
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        if self.oneway.take() == Some(true) && !self.batch_is_due() {
+            self.batched += 1;
+            self.batch_started.get_or_insert_with(Instant::now);
+            return Ok(());
+        }
+        self.batched = 0;
+        self.batch_started = None;
+        self.inner.flush()
+    }
+
+    // utility
+    //
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.inner.write_byte(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol};
+    use crate::transport::{
+        TBufferChannel, TFramedReadTransport, TFramedWriteTransport, TIoChannel, WriteHalf,
+    };
+
+    type Protocol = TBatchingOutputProtocol<
+        TBinaryOutputProtocol<TFramedWriteTransport<WriteHalf<TBufferChannel>>>,
+    >;
+
+    fn protocol(max_messages: usize) -> (TBufferChannel, Protocol) {
+        let channel = TBufferChannel::with_capacity(0, 1024);
+        let (_, o_chan) = channel.clone().split().unwrap();
+        let o_prot = TBinaryOutputProtocol::new(TFramedWriteTransport::new(o_chan), true);
+        (channel, TBatchingOutputProtocol::new(o_prot, max_messages))
+    }
+
+    fn send(o_prot: &mut dyn TOutputProtocol, method: &str, message_type: TMessageType) {
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new(method, message_type, 1))
+            .unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    /// Return the number of frames written to `channel` and the methods
+    /// of the messages in them.
+    fn sent(channel: &TBufferChannel) -> (usize, Vec<String>) {
+        let written = channel.write_bytes();
+        let mut frames = 0;
+        let mut pos = 0;
+        while pos < written.len() {
+            let len = u32::from_be_bytes(written[pos..pos + 4].try_into().unwrap());
+            pos += 4 + len as usize;
+            frames += 1;
+        }
+        let mut i_prot = TBinaryInputProtocol::new(TFramedReadTransport::new(&written[..]), true);
+        let mut methods = Vec::new();
+        while let Ok(identifier) = i_prot.read_message_begin() {
+            methods.push(identifier.name);
+        }
+        (frames, methods)
+    }
+
+    #[test]
+    fn must_send_oneway_calls_in_one_frame_per_batch() {
+        let (channel, mut o_prot) = protocol(3);
+
+        for method in ["a", "b", "c", "d"] {
+            send(&mut o_prot, method, TMessageType::OneWay);
+        }
+
+        assert_eq!(o_prot.batched(), 1);
+        assert_eq!(
+            sent(&channel),
+            (1, vec!["a".into(), "b".into(), "c".into()])
+        );
+        o_prot.flush().unwrap();
+        assert_eq!(o_prot.batched(), 0);
+        assert_eq!(sent(&channel).0, 2);
+    }
+
+    #[test]
+    fn must_send_batch_with_call_that_expects_reply() {
+        let (channel, mut o_prot) = protocol(10);
+
+        send(&mut o_prot, "log", TMessageType::OneWay);
+        send(&mut o_prot, "add", TMessageType::Call);
+
+        assert_eq!(sent(&channel), (1, vec!["log".into(), "add".into()]));
+    }
+
+    #[test]
+    fn must_send_oneway_call_with_helper() {
+        struct Client(Protocol, i32);
+
+        impl TThriftClient for Client {
+            fn i_prot_mut(&mut self) -> &mut dyn TInputProtocol {
+                unreachable!("oneway calls read no reply")
+            }
+            fn o_prot_mut(&mut self) -> &mut dyn TOutputProtocol {
+                &mut self.0
+            }
+            fn sequence_number(&self) -> i32 {
+                self.1
+            }
+            fn increment_sequence_number(&mut self) -> i32 {
+                self.1 += 1;
+                self.1
+            }
+        }
+
+        let (channel, o_prot) = protocol(1);
+        let mut client = Client(o_prot, 0);
+
+        send_oneway(&mut client, "log", |o_prot| {
+            o_prot.write_struct_begin(&TStructIdentifier::new("log_args"))?;
+            o_prot.write_field_stop()?;
+            o_prot.write_struct_end()
+        })
+        .unwrap();
+
+        assert_eq!(sent(&channel), (1, vec!["log".into()]));
+        assert_eq!(client.sequence_number(), 1);
+    }
+}
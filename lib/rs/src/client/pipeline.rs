@@ -0,0 +1,676 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use byteorder::{BigEndian, ReadBytesExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+
+use crate::protocol::{
+    TInputProtocolFactory, TMessageIdentifier, TMessageType, TOutputProtocolFactory,
+};
+use crate::transport::{ReadHalf, TBufferChannel, TIoChannel, WriteHalf};
+use crate::TConfiguration;
+
+type Reply = io::Result<Vec<u8>>;
+
+/// Connection that carries the calls of many clients at once.
+///
+/// Calls are sent as soon as they are made, without waiting for the replies
+/// to earlier calls, which makes the most of a connection over a
+/// high-latency link. Each call is given a sequence number unique on the
+/// connection, and a reader thread hands every reply to the caller waiting
+/// for the call with its sequence number, in whatever order the server
+/// answers them. The server must accept framed messages; a server that
+/// processes requests concurrently (see `TServer::set_concurrent_requests`) also
+/// answers them out of order.
+///
+/// Clients use the connection through `TPipelinedChannel`s, one for each
+/// client, created with `channel`. A channel carries one call at a time:
+/// create a client for each thread making calls. The sequence numbers chosen
+/// by the clients are replaced on the wire and restored in their replies, so
+/// the clients need not coordinate them.
+///
+/// Once the connection fails, or the server closes it, every outstanding
+/// call fails with the error and later calls fail at once; open a new
+/// connection to continue. The reader thread exits at the same time.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client::TPipelinedConnection;
+/// use thrift::protocol::{
+///     TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+///     TBinaryOutputProtocolFactory,
+/// };
+/// use thrift::transport::{TIoChannel, TTcpChannel};
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+/// let connection = TPipelinedConnection::new(
+///     channel,
+///     TBinaryInputProtocolFactory::new(),
+///     TBinaryOutputProtocolFactory::new(),
+/// )
+/// .unwrap();
+///
+/// // for each thread
+/// let (i_chan, o_chan) = connection.channel().split().unwrap();
+/// let i_prot = TBinaryInputProtocol::new(i_chan, true);
+/// let o_prot = TBinaryOutputProtocol::new(o_chan, true);
+/// // let mut client = CalculatorSyncClient::new(i_prot, o_prot);
+/// # let _ = (i_prot, o_prot);
+/// ```
+#[derive(Clone, Debug)]
+pub struct TPipelinedConnection {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    codec: HeaderCodec,
+    writer: Mutex<Box<dyn Write + Send>>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_sequence_number: i32,
+    waiting: HashMap<i32, Waiter>,
+    failure: Option<(io::ErrorKind, String)>,
+}
+
+struct Waiter {
+    sequence_number: i32,
+    reply: mpsc::Sender<Reply>,
+}
+
+impl TPipelinedConnection {
+    /// Create a `TPipelinedConnection` over the open `channel`, reading and
+    /// writing message headers with protocols from `input_protocol_factory`
+    /// and `output_protocol_factory`.
+    ///
+    /// Clients must use the same protocol as the factories.
+    pub fn new<C, IPF, OPF>(
+        channel: C,
+        input_protocol_factory: IPF,
+        output_protocol_factory: OPF,
+    ) -> crate::Result<TPipelinedConnection>
+    where
+        C: TIoChannel + Send + 'static,
+        IPF: TInputProtocolFactory + Send + Sync + 'static,
+        OPF: TOutputProtocolFactory + Send + Sync + 'static,
+    {
+        TPipelinedConnection::with_config(
+            channel,
+            input_protocol_factory,
+            output_protocol_factory,
+            TConfiguration::default(),
+        )
+    }
+
+    /// Create a `TPipelinedConnection` that rejects replies larger than the
+    /// `max_frame_size` of `config`.
+    pub fn with_config<C, IPF, OPF>(
+        channel: C,
+        input_protocol_factory: IPF,
+        output_protocol_factory: OPF,
+        config: TConfiguration,
+    ) -> crate::Result<TPipelinedConnection>
+    where
+        C: TIoChannel + Send + 'static,
+        IPF: TInputProtocolFactory + Send + Sync + 'static,
+        OPF: TOutputProtocolFactory + Send + Sync + 'static,
+    {
+        let (reader, writer) = channel.split()?;
+        let shared = Arc::new(Shared {
+            codec: HeaderCodec {
+                input: Box::new(input_protocol_factory),
+                output: Box::new(output_protocol_factory),
+            },
+            writer: Mutex::new(Box::new(writer) as Box<dyn Write + Send>),
+            state: Mutex::new(State::default()),
+        });
+
+        let weak = Arc::downgrade(&shared);
+        thread::Builder::new()
+            .name("thrift-pipelined-reader".to_owned())
+            .spawn(move || read_replies(reader, weak, config.max_frame_size()))?;
+
+        Ok(TPipelinedConnection { shared })
+    }
+
+    /// Create a channel for one client to make calls over this connection.
+    pub fn channel(&self) -> TPipelinedChannel {
+        TPipelinedChannel {
+            connection: self.clone(),
+            call: Arc::new(Mutex::new(Call::default())),
+        }
+    }
+
+    /// Return the number of calls waiting for their reply.
+    pub fn outstanding_calls(&self) -> usize {
+        self.shared.lock_state().waiting.len()
+    }
+
+    /// Return `true` if the connection has failed.
+    pub fn is_failed(&self) -> bool {
+        self.shared.lock_state().failure.is_some()
+    }
+
+    fn send(&self, request: &[u8]) -> io::Result<Option<(i32, mpsc::Receiver<Reply>)>> {
+        let (mut identifier, header_len) = self.shared.codec.read(request)?;
+        let caller_sequence_number = identifier.sequence_number;
+
+        let receiver = {
+            let mut state = self.shared.lock_state();
+            if let Some((kind, ref message)) = state.failure {
+                return Err(io::Error::new(kind, message.clone()));
+            }
+            identifier.sequence_number = state.next_sequence_number;
+            state.next_sequence_number = state.next_sequence_number.wrapping_add(1);
+
+            // oneway calls get no reply
+            if identifier.message_type == TMessageType::OneWay {
+                None
+            } else {
+                let (sender, receiver) = mpsc::channel();
+                state.waiting.insert(
+                    identifier.sequence_number,
+                    Waiter {
+                        sequence_number: caller_sequence_number,
+                        reply: sender,
+                    },
+                );
+                Some((identifier.sequence_number, receiver))
+            }
+        };
+
+        let mut frame = vec![0; 4];
+        frame.extend(self.shared.codec.write(&identifier)?);
+        frame.extend_from_slice(&request[header_len..]);
+        let frame_len = (frame.len() - 4) as u32;
+        frame[..4].copy_from_slice(&frame_len.to_be_bytes());
+
+        let written = {
+            let mut writer = self.shared.writer.lock().unwrap_or_else(|e| e.into_inner());
+            writer.write_all(&frame).and_then(|_| writer.flush())
+        };
+        match written {
+            Ok(()) => Ok(receiver),
+            Err(e) => {
+                // the frame may have been partly written
+                self.shared.fail(&e);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Fail the call waiting for the reply with `sequence_number` at once.
+    ///
+    /// The call stays outstanding, so that its reply is read and dropped
+    /// when it arrives rather than taken for the reply to another call.
+    fn cancel(&self, sequence_number: i32) -> bool {
+        match self.lock_state().waiting.get(&sequence_number) {
+            Some(waiter) => waiter
+                .reply
+                .send(Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "call cancelled",
+                )))
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    /// Fail every outstanding call, and every later call, with `error`.
+    fn fail(&self, error: &io::Error) {
+        let mut state = self.lock_state();
+        if state.failure.is_none() {
+            state.failure = Some((error.kind(), error.to_string()));
+        }
+        for (_, waiter) in state.waiting.drain() {
+            let _ = waiter
+                .reply
+                .send(Err(io::Error::new(error.kind(), error.to_string())));
+        }
+    }
+
+    fn deliver(&self, reply: Vec<u8>) -> io::Result<()> {
+        let (mut identifier, header_len) = self.codec.read(&reply)?;
+        let waiter = match self
+            .lock_state()
+            .waiting
+            .remove(&identifier.sequence_number)
+        {
+            Some(waiter) => waiter,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "reply with unknown sequence number {}",
+                        identifier.sequence_number
+                    ),
+                ))
+            }
+        };
+
+        identifier.sequence_number = waiter.sequence_number;
+        let mut restored = self.codec.write(&identifier)?;
+        restored.extend_from_slice(&reply[header_len..]);
+        // the caller may have given up on the call
+        let _ = waiter.reply.send(Ok(restored));
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock_state();
+        f.debug_struct("Shared")
+            .field("outstanding_calls", &state.waiting.len())
+            .field("failure", &state.failure)
+            .finish()
+    }
+}
+
+/// Read framed replies and hand them to their callers until the connection
+/// fails or every handle to it is dropped.
+fn read_replies<C>(mut reader: ReadHalf<C>, shared: Weak<Shared>, max_frame_size: Option<usize>)
+where
+    C: Read,
+{
+    loop {
+        let frame = read_frame(&mut reader, max_frame_size);
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        let result = frame.and_then(|frame| shared.deliver(frame));
+        if let Err(e) = result {
+            shared.fail(&e);
+            return;
+        }
+    }
+}
+
+fn read_frame<R: Read>(reader: &mut R, max_frame_size: Option<usize>) -> io::Result<Vec<u8>> {
+    let frame_size = reader.read_i32::<BigEndian>()?;
+    if frame_size < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Negative frame size: {}", frame_size),
+        ));
+    }
+
+    let frame_size = frame_size as usize;
+    if let Some(max_frame_size) = max_frame_size {
+        if frame_size > max_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Frame size {} exceeds maximum allowed size of {}",
+                    frame_size, max_frame_size
+                ),
+            ));
+        }
+    }
+
+    let mut frame = vec![0; frame_size];
+    reader.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+/// Reads and writes message headers in the connection's protocol.
+struct HeaderCodec {
+    input: Box<dyn TInputProtocolFactory + Send + Sync>,
+    output: Box<dyn TOutputProtocolFactory + Send + Sync>,
+}
+
+impl HeaderCodec {
+    /// Read the header of `message`, returning it and its encoded length.
+    fn read(&self, message: &[u8]) -> io::Result<(TMessageIdentifier, usize)> {
+        let mut channel = TBufferChannel::new();
+        channel.set_readable_bytes(message);
+        let mut i_prot = self.input.create(Box::new(channel.clone()));
+        let identifier = i_prot.read_message_begin().map_err(invalid_header)?;
+        Ok((identifier, channel.read_position()))
+    }
+
+    fn write(&self, identifier: &TMessageIdentifier) -> io::Result<Vec<u8>> {
+        let mut channel = TBufferChannel::new();
+        let mut o_prot = self.output.create(Box::new(channel.clone()));
+        o_prot
+            .write_message_begin(identifier)
+            .and_then(|_| o_prot.flush())
+            .map_err(invalid_header)?;
+        Ok(channel.take_written_bytes())
+    }
+}
+
+fn invalid_header(e: crate::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("cannot read message header: {}", e),
+    )
+}
+
+/// Channel for one client to make calls over a `TPipelinedConnection`.
+///
+/// The bytes of a call are sent as one frame when the client flushes them,
+/// and the client's next read waits for the reply. Split the channel into
+/// the halves used by the client's input and output protocols; do not wrap
+/// them in framed or buffered transports, since the connection frames the
+/// calls itself.
+///
+/// A call can be abandoned with `cancel`, for example from a thread that
+/// enforces a timeout. Its reply is dropped when it arrives, so the
+/// connection and the channel can go on carrying calls.
+#[derive(Clone, Debug)]
+pub struct TPipelinedChannel {
+    connection: TPipelinedConnection,
+    call: Arc<Mutex<Call>>,
+}
+
+#[derive(Debug, Default)]
+struct Call {
+    request: Vec<u8>,
+    // sequence number of the call on the connection while it is outstanding
+    sequence_number: Option<i32>,
+    reply: Option<mpsc::Receiver<Reply>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl TPipelinedChannel {
+    /// Return the connection this channel makes calls over.
+    pub fn connection(&self) -> &TPipelinedConnection {
+        &self.connection
+    }
+
+    /// Cancel the call being made over this channel.
+    ///
+    /// A call waiting for its reply fails with a `call cancelled` error,
+    /// and a call that has been written but not flushed is discarded.
+    /// Returns `false` if there was no call to cancel, because it had
+    /// already completed or none was made.
+    pub fn cancel(&self) -> bool {
+        let sequence_number = {
+            let mut call = self.lock_call();
+            let unsent = !call.request.is_empty();
+            call.request.clear();
+            match call.sequence_number.take() {
+                Some(sequence_number) => sequence_number,
+                None => return unsent,
+            }
+        };
+        self.connection.shared.cancel(sequence_number)
+    }
+
+    fn lock_call(&self) -> MutexGuard<'_, Call> {
+        self.call.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl TIoChannel for TPipelinedChannel {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+impl Read for TPipelinedChannel {
+    fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+        let pending = {
+            let mut call = self.lock_call();
+            if call.pos < call.buf.len() {
+                None
+            } else {
+                call.reply.take()
+            }
+        };
+
+        // wait without holding the lock
+        if let Some(reply) = pending {
+            let reply = reply.recv().unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "pipelined connection closed",
+                ))
+            })?;
+            let mut call = self.lock_call();
+            call.sequence_number = None;
+            call.buf = reply;
+            call.pos = 0;
+        }
+
+        let mut call = self.lock_call();
+        let pos = call.pos;
+        let n = b.len().min(call.buf.len() - pos);
+        b[..n].copy_from_slice(&call.buf[pos..pos + n]);
+        call.pos += n;
+        Ok(n)
+    }
+}
+
+impl Write for TPipelinedChannel {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.lock_call().request.extend_from_slice(b);
+        Ok(b.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let request = mem::take(&mut self.lock_call().request);
+        if request.is_empty() {
+            return Ok(());
+        }
+
+        let reply = self.connection.send(&request)?;
+        let mut call = self.lock_call();
+        call.buf.clear();
+        call.pos = 0;
+        call.sequence_number = reply.as_ref().map(|(sequence_number, _)| *sequence_number);
+        call.reply = reply.map(|(_, receiver)| receiver);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TInputProtocol, TOutputProtocol,
+    };
+    use crate::transport::TTcpChannel;
+
+    /// Accept one connection, read `calls` requests carrying an i32 and,
+    /// once all have arrived, answer them in reverse order with ten times
+    /// the value.
+    fn reversing_server(calls: usize) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut requests = Vec::new();
+            for _ in 0..calls {
+                let frame = read_frame(&mut stream, None).unwrap();
+                let mut channel = TBufferChannel::new();
+                channel.set_readable_bytes(&frame);
+                let mut i_prot = TBinaryInputProtocol::new(channel, true);
+                let identifier = i_prot.read_message_begin().unwrap();
+                requests.push((identifier, i_prot.read_i32().unwrap()));
+            }
+            for (identifier, value) in requests.into_iter().rev() {
+                let mut o_prot = TBinaryOutputProtocol::new(TBufferChannel::new(), true);
+                o_prot
+                    .write_message_begin(&TMessageIdentifier::new(
+                        identifier.name,
+                        TMessageType::Reply,
+                        identifier.sequence_number,
+                    ))
+                    .unwrap();
+                o_prot.write_i32(value * 10).unwrap();
+                let reply = o_prot.transport.take_written_bytes();
+                stream
+                    .write_all(&(reply.len() as u32).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&reply).unwrap();
+            }
+        });
+        (TcpStream::connect(address).unwrap(), handle)
+    }
+
+    fn connection(stream: TcpStream) -> TPipelinedConnection {
+        TPipelinedConnection::new(
+            TTcpChannel::with_stream(stream),
+            TBinaryInputProtocolFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+        )
+        .unwrap()
+    }
+
+    fn send(
+        o_prot: &mut TBinaryOutputProtocol<WriteHalf<TPipelinedChannel>>,
+        message_type: TMessageType,
+        value: i32,
+    ) -> crate::Result<()> {
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("add", message_type, 7))
+            .and_then(|_| o_prot.write_i32(value))
+            .and_then(|_| o_prot.write_message_end())
+            .and_then(|_| o_prot.flush())
+    }
+
+    fn call(channel: TPipelinedChannel, value: i32) -> crate::Result<(i32, i32)> {
+        let (i_chan, o_chan) = channel.split()?;
+        let mut i_prot = TBinaryInputProtocol::new(i_chan, true);
+        let mut o_prot = TBinaryOutputProtocol::new(o_chan, true);
+        send(&mut o_prot, TMessageType::Call, value)?;
+        let identifier = i_prot.read_message_begin()?;
+        Ok((identifier.sequence_number, i_prot.read_i32()?))
+    }
+
+    #[test]
+    fn must_match_out_of_order_replies_to_their_calls() {
+        let (stream, server) = reversing_server(3);
+        let connection = connection(stream);
+
+        let callers: Vec<_> = (1..=3)
+            .map(|value| {
+                let channel = connection.channel();
+                thread::spawn(move || call(channel, value).unwrap())
+            })
+            .collect();
+        let replies: Vec<_> = callers.into_iter().map(|c| c.join().unwrap()).collect();
+
+        // every caller sees its own value and sequence number
+        assert_eq!(replies, [(7, 10), (7, 20), (7, 30)]);
+        assert_eq!(connection.outstanding_calls(), 0);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn must_not_wait_for_replies_to_oneway_calls() {
+        let (stream, _server) = reversing_server(2);
+        let connection = connection(stream);
+
+        let (_i_chan, o_chan) = connection.channel().split().unwrap();
+        let mut o_prot = TBinaryOutputProtocol::new(o_chan, true);
+        send(&mut o_prot, TMessageType::OneWay, 1).unwrap();
+
+        assert_eq!(connection.outstanding_calls(), 0);
+    }
+
+    #[test]
+    fn must_drop_reply_to_cancelled_call() {
+        // answer two calls in order once both have arrived, and keep the
+        // connection open
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let requests: Vec<_> = (0..2)
+                .map(|_| read_frame(&mut stream, None).unwrap())
+                .collect();
+            for request in requests {
+                let mut reply = request;
+                // turn the call into a reply by flipping its message type
+                reply[3] = TMessageType::Reply as u8;
+                stream
+                    .write_all(&(reply.len() as u32).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&reply).unwrap();
+            }
+            let _ = stream.read(&mut [0u8; 1]);
+        });
+        let connection = connection(TcpStream::connect(address).unwrap());
+        let channel = connection.channel();
+
+        let cancelled = {
+            let channel = channel.clone();
+            thread::spawn(move || call(channel, 1))
+        };
+        while connection.outstanding_calls() == 0 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(channel.cancel());
+        match cancelled.join().unwrap() {
+            Err(crate::Error::Transport(e)) => assert_eq!(e.message, "call cancelled"),
+            other => panic!("expected cancelled call, got {:?}", other),
+        }
+        assert!(!channel.cancel());
+
+        // the reply to the cancelled call arrives first and is dropped
+        assert_eq!(call(channel, 2).unwrap(), (7, 2));
+        assert_eq!(connection.outstanding_calls(), 0);
+        assert!(!connection.is_failed());
+    }
+
+    #[test]
+    fn must_fail_outstanding_and_later_calls_when_connection_closes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_frame(&mut stream, None).unwrap();
+        });
+        let connection = connection(TcpStream::connect(address).unwrap());
+
+        assert!(call(connection.channel(), 1).is_err());
+        server.join().unwrap();
+        while !connection.is_failed() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(call(connection.channel(), 2).is_err());
+        assert_eq!(connection.outstanding_calls(), 0);
+    }
+}
@@ -0,0 +1,204 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use crate::protocol::{
+    verify_expected_sequence_number, verify_expected_service_call, TInputProtocol,
+    TMessageIdentifier, TType,
+};
+
+/// Checks that a reply belongs to the call it is read for.
+///
+/// A reply whose sequence number differs from the call's is reported as an
+/// `ApplicationErrorKind::BadSequenceId` error, and one whose method name
+/// differs as an `ApplicationErrorKind::WrongMethodName` error. A wrong
+/// sequence number usually means an earlier call gave up on its reply, for
+/// example after a timeout, and the reply arrived later on the same
+/// connection.
+///
+/// By default the validator is strict and the caller is expected to close
+/// the connection after a mismatch. Created with `with_resync` it instead
+/// skips replies to earlier calls, up to a limit, until it finds the
+/// expected one.
+///
+/// Generated clients use a strict validator to read the beginning of every
+/// reply.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client::TReplyValidator;
+/// use thrift::protocol::TBinaryInputProtocol;
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+/// let mut i_prot = TBinaryInputProtocol::new(channel, true);
+///
+/// // skip up to 4 late replies to calls that timed out
+/// let validator = TReplyValidator::with_resync(4);
+/// let ident = validator.read_reply_begin(&mut i_prot, "add", 7).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TReplyValidator {
+    max_skipped: usize,
+}
+
+impl TReplyValidator {
+    /// Create a `TReplyValidator` that fails on any mismatched reply.
+    pub fn new() -> TReplyValidator {
+        TReplyValidator::default()
+    }
+
+    /// Create a `TReplyValidator` that skips up to `max_skipped` replies to
+    /// earlier calls before the expected reply.
+    pub fn with_resync(max_skipped: usize) -> TReplyValidator {
+        TReplyValidator { max_skipped }
+    }
+
+    /// Maximum number of replies skipped while reading a single reply.
+    pub fn max_skipped(&self) -> usize {
+        self.max_skipped
+    }
+
+    /// Check that `ident` is the beginning of the reply to the call of
+    /// `method` with `sequence_number`.
+    pub fn check(
+        &self,
+        method: &str,
+        sequence_number: i32,
+        ident: &TMessageIdentifier,
+    ) -> crate::Result<()> {
+        verify_expected_sequence_number(sequence_number, ident.sequence_number)?;
+        verify_expected_service_call(method, &ident.name)
+    }
+
+    /// Read the beginning of the reply to the call of `method` with
+    /// `sequence_number` from `i_prot`.
+    ///
+    /// The caller reads the rest of the reply as usual. In resync mode,
+    /// replies to earlier calls are read in full and dropped first.
+    pub fn read_reply_begin(
+        &self,
+        i_prot: &mut dyn TInputProtocol,
+        method: &str,
+        sequence_number: i32,
+    ) -> crate::Result<TMessageIdentifier> {
+        let mut skipped = 0;
+        loop {
+            let ident = i_prot.read_message_begin()?;
+            if skipped < self.max_skipped && is_earlier(ident.sequence_number, sequence_number) {
+                i_prot.skip(TType::Struct)?;
+                i_prot.read_message_end()?;
+                skipped += 1;
+                continue;
+            }
+            self.check(method, sequence_number, &ident)?;
+            return Ok(ident);
+        }
+    }
+}
+
+// sequence numbers wrap, so compare them the way TCP does
+fn is_earlier(actual: i32, expected: i32) -> bool {
+    expected.wrapping_sub(actual) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TMessageType, TOutputProtocol,
+    };
+    use crate::transport::TBufferChannel;
+    use crate::ApplicationErrorKind;
+
+    fn replies(replies: &[(&str, i32)]) -> TBinaryInputProtocol<TBufferChannel> {
+        let mut channel = TBufferChannel::with_capacity(256, 256);
+        {
+            let mut o_prot = TBinaryOutputProtocol::new(&mut channel, true);
+            for &(name, sequence_number) in replies {
+                o_prot
+                    .write_message_begin(&TMessageIdentifier::new(
+                        name,
+                        TMessageType::Reply,
+                        sequence_number,
+                    ))
+                    .unwrap();
+                o_prot.write_field_stop().unwrap();
+                o_prot.write_message_end().unwrap();
+            }
+        }
+        channel.copy_write_buffer_to_read_buffer();
+        TBinaryInputProtocol::new(channel, true)
+    }
+
+    fn assert_mismatch(result: crate::Result<TMessageIdentifier>, kind: ApplicationErrorKind) {
+        match result {
+            Err(crate::Error::Application(e)) => assert_eq!(e.kind, kind),
+            other => panic!("expected {:?}, got {:?}", kind, other),
+        }
+    }
+
+    #[test]
+    fn must_reject_earlier_reply_by_default() {
+        let mut i_prot = replies(&[("add", 2), ("add", 3)]);
+
+        assert_mismatch(
+            TReplyValidator::new().read_reply_begin(&mut i_prot, "add", 3),
+            ApplicationErrorKind::BadSequenceId,
+        );
+    }
+
+    #[test]
+    fn must_reject_reply_to_other_method_by_default() {
+        let mut i_prot = replies(&[("mul", 3)]);
+
+        assert_mismatch(
+            TReplyValidator::new().read_reply_begin(&mut i_prot, "add", 3),
+            ApplicationErrorKind::WrongMethodName,
+        );
+    }
+
+    #[test]
+    fn must_skip_earlier_replies_when_resyncing() {
+        let mut i_prot = replies(&[("mul", 1), ("add", 2), ("add", 3)]);
+
+        let ident = TReplyValidator::with_resync(2)
+            .read_reply_begin(&mut i_prot, "add", 3)
+            .unwrap();
+
+        assert_eq!(ident.sequence_number, 3);
+        assert_mismatch(
+            TReplyValidator::with_resync(1).read_reply_begin(
+                &mut replies(&[("mul", 1), ("add", 2), ("add", 3)]),
+                "add",
+                3,
+            ),
+            ApplicationErrorKind::BadSequenceId,
+        );
+    }
+
+    #[test]
+    fn must_reject_reply_to_other_method_when_resyncing() {
+        let mut i_prot = replies(&[("mul", 3)]);
+
+        assert_mismatch(
+            TReplyValidator::with_resync(4).read_reply_begin(&mut i_prot, "add", 3),
+            ApplicationErrorKind::WrongMethodName,
+        );
+    }
+}
@@ -0,0 +1,125 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use super::TEndpoint;
+
+/// Finds the endpoints of a service.
+///
+/// A `TLoadBalancer` created with a resolver calls it again to pick up
+/// endpoints that have changed, on an interval or after calls fail (see
+/// `TLoadBalancer::with_refresh_interval`). Implement it to look endpoints
+/// up in a service registry; `TDnsResolver` looks up the addresses of a
+/// host name. Functions returning the endpoints implement it too.
+pub trait TResolver: Send + Sync {
+    /// Return the current endpoints of the service.
+    fn resolve(&self) -> io::Result<Vec<TEndpoint>>;
+}
+
+impl<F> TResolver for F
+where
+    F: Fn() -> io::Result<Vec<TEndpoint>> + Send + Sync,
+{
+    fn resolve(&self) -> io::Result<Vec<TEndpoint>> {
+        self()
+    }
+}
+
+/// `TResolver` that returns an endpoint for each address a host name
+/// resolves to.
+///
+/// Connecting to the addresses rather than to the host name spreads calls
+/// across all of them, and re-resolving the name moves calls to the new
+/// addresses when the DNS records change, for example on failover.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use thrift::client::{TDnsResolver, TEndpoint, TLoadBalancer};
+/// use thrift::transport::TTcpChannel;
+///
+/// let balancer = TLoadBalancer::with_resolver(
+///     TDnsResolver::new("calc.example.com:9090"),
+///     |endpoint: &TEndpoint| {
+///         let mut channel = TTcpChannel::new();
+///         channel.open(endpoint.address())?;
+///         Ok(channel)
+///     },
+/// )
+/// .unwrap()
+/// .with_refresh_interval(Some(Duration::from_secs(30)))
+/// .with_refresh_on_failure(true);
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TDnsResolver {
+    address: String,
+}
+
+impl TDnsResolver {
+    /// Create a `TDnsResolver` for `address`, in `host:port` form.
+    pub fn new<S: Into<String>>(address: S) -> TDnsResolver {
+        TDnsResolver {
+            address: address.into(),
+        }
+    }
+
+    /// Address whose host name is resolved.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+impl TResolver for TDnsResolver {
+    fn resolve(&self) -> io::Result<Vec<TEndpoint>> {
+        let mut addrs: Vec<SocketAddr> = Vec::new();
+        for addr in self.address.to_socket_addrs()? {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} did not resolve to any addresses", self.address),
+            ));
+        }
+        Ok(addrs.into_iter().map(TEndpoint::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_return_endpoint_per_address() {
+        let endpoints = TDnsResolver::new("127.0.0.1:9090").resolve().unwrap();
+
+        assert_eq!(endpoints, [TEndpoint::new("127.0.0.1:9090")]);
+    }
+
+    #[test]
+    fn must_keep_port_of_resolved_host_name() {
+        let endpoints = TDnsResolver::new("localhost:9090").resolve().unwrap();
+
+        assert!(!endpoints.is_empty());
+        assert!(endpoints.iter().all(|e| e.address().ends_with(":9090")));
+    }
+}
@@ -0,0 +1,393 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::transport::TBackoff;
+use crate::TransportErrorKind;
+
+type RetryPredicate = dyn Fn(&crate::Error) -> bool + Send + Sync;
+
+/// When, and how often, a failed call is made again.
+///
+/// The delays between attempts follow a `TBackoff`, which also limits the
+/// number of attempts: a call is made at most `max_attempts` times in all.
+/// Each delay is shortened by a random amount of up to the jitter fraction,
+/// so clients that failed together do not retry together.
+///
+/// By default only calls that failed with a transport error - the
+/// connection was lost, could not be opened, or timed out - are retried.
+/// Application and IDL exceptions are answers from the server and are
+/// returned at once, as are protocol errors. A call that failed after its
+/// request was sent may already have been processed by the server, so
+/// retry only idempotent calls, or narrow the predicate with
+/// `with_predicate`.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use thrift::client::TRetryPolicy;
+/// use thrift::transport::TBackoff;
+///
+/// let policy = TRetryPolicy::new(
+///     TBackoff::exponential(Duration::from_millis(20), Duration::from_secs(1))
+///         .with_max_attempts(Some(4)),
+/// )
+/// .with_jitter(0.5);
+///
+/// let mut failures = 0;
+/// let answer = policy.retry(|_attempt| {
+///     if failures < 2 {
+///         failures += 1;
+///         return Err(thrift::new_transport_error(
+///             thrift::TransportErrorKind::NotOpen,
+///             "connection refused",
+///         ));
+///     }
+///     Ok(42)
+/// });
+/// assert_eq!(answer.unwrap(), 42);
+/// ```
+#[derive(Clone)]
+pub struct TRetryPolicy {
+    backoff: TBackoff,
+    jitter: f64,
+    predicate: Arc<RetryPredicate>,
+}
+
+impl TRetryPolicy {
+    /// Create a `TRetryPolicy` that waits between attempts according to
+    /// `backoff`, with a jitter of `0.5`, and retries transport errors.
+    pub fn new(backoff: TBackoff) -> TRetryPolicy {
+        TRetryPolicy {
+            backoff,
+            jitter: 0.5,
+            predicate: Arc::new(is_transport_failure),
+        }
+    }
+
+    /// Shorten each delay by a random fraction of up to `jitter` of it.
+    ///
+    /// `jitter` is clamped to `0.0..=1.0`: `0.0` waits exactly as long as
+    /// the backoff says, `1.0` waits anywhere from no time at all up to that
+    /// long.
+    pub fn with_jitter(mut self, jitter: f64) -> TRetryPolicy {
+        self.jitter = if jitter.is_nan() {
+            0.0
+        } else {
+            jitter.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    /// Retry the calls whose error `predicate` returns `true` for, instead
+    /// of those that failed with a transport error.
+    pub fn with_predicate<F>(mut self, predicate: F) -> TRetryPolicy
+    where
+        F: Fn(&crate::Error) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Arc::new(predicate);
+        self
+    }
+
+    /// Delays between attempts, and the number of attempts.
+    pub fn backoff(&self) -> TBackoff {
+        self.backoff
+    }
+
+    /// Largest fraction by which a delay is shortened.
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    /// Return `true` if a call that failed with `error` should be made
+    /// again, attempts permitting.
+    pub fn is_retryable(&self, error: &crate::Error) -> bool {
+        (self.predicate)(error)
+    }
+
+    /// Call `call` until it succeeds, fails with an error that is not
+    /// retryable, or has been made as many times as the backoff allows.
+    ///
+    /// `call` is passed the number of the attempt, counting from 0. The
+    /// error of the last attempt is returned.
+    pub fn retry<T, F>(&self, mut call: F) -> crate::Result<T>
+    where
+        F: FnMut(u32) -> crate::Result<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            match call(attempt) {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    if !self.may_retry(attempt + 1) || !self.is_retryable(&e) {
+                        return Err(e);
+                    }
+                    thread::sleep(self.delay(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Whether another attempt may be made after `failed` attempts.
+    fn may_retry(&self, failed: u32) -> bool {
+        !matches!(self.backoff.max_attempts(), Some(max) if failed >= max.max(1))
+    }
+
+    /// Delay to wait after the failed attempt numbered `attempt`.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.backoff.delay(attempt);
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        delay.mul_f64(1.0 - self.jitter * random_fraction())
+    }
+}
+
+impl Default for TRetryPolicy {
+    /// Three attempts, 50ms apart and then 100ms, with a jitter of `0.5`.
+    fn default() -> Self {
+        TRetryPolicy::new(
+            TBackoff::exponential(Duration::from_millis(50), Duration::from_secs(2))
+                .with_max_attempts(Some(3)),
+        )
+    }
+}
+
+impl fmt::Debug for TRetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TRetryPolicy")
+            .field("backoff", &self.backoff)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Return `true` if `error` shows that the connection to the server failed,
+/// rather than that the server answered with an error.
+pub(super) fn is_transport_failure(error: &crate::Error) -> bool {
+    match error {
+        crate::Error::Transport(e) => matches!(
+            e.kind,
+            TransportErrorKind::Unknown
+                | TransportErrorKind::NotOpen
+                | TransportErrorKind::TimedOut
+                | TransportErrorKind::EndOfFile
+        ),
+        _ => false,
+    }
+}
+
+/// Return a random number in `0.0..1.0`.
+fn random_fraction() -> f64 {
+    // every `RandomState` is seeded differently, which is all the
+    // randomness jitter needs
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+type Connect<C> = dyn FnMut() -> crate::Result<C> + Send;
+
+/// Generated client, or any other client, whose calls are retried
+/// according to a `TRetryPolicy`.
+///
+/// Calls are made through `call`, with a closure that makes one attempt of
+/// the call on the wrapped client. A client whose connection has failed
+/// cannot usually be used again, so either build it on a
+/// `TReconnectingChannel`, which opens a new connection for the next
+/// attempt, or give the wrapper a function that builds a new client with
+/// `with_reconnect`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::client::{TRetryPolicy, TRetryingClient};
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{TIoChannel, TTcpChannel};
+/// # struct CalculatorSyncClient<I, O>(I, O);
+/// # impl<I, O> CalculatorSyncClient<I, O> {
+/// #     fn new(i: I, o: O) -> Self { CalculatorSyncClient(i, o) }
+/// #     fn add(&mut self, a: i32, b: i32) -> thrift::Result<i32> { Ok(a + b) }
+/// # }
+///
+/// let connect = || {
+///     let mut channel = TTcpChannel::new();
+///     channel.open("localhost:9090")?;
+///     let (i_chan, o_chan) = channel.split()?;
+///     Ok(CalculatorSyncClient::new(
+///         TBinaryInputProtocol::new(i_chan, true),
+///         TBinaryOutputProtocol::new(o_chan, true),
+///     ))
+/// };
+///
+/// let mut client = TRetryingClient::new(connect().unwrap(), TRetryPolicy::default())
+///     .with_reconnect(connect);
+/// let sum = client.call(|c| c.add(1, 2)).unwrap();
+/// ```
+pub struct TRetryingClient<C> {
+    client: Option<C>,
+    policy: TRetryPolicy,
+    reconnect: Option<Box<Connect<C>>>,
+}
+
+impl<C> TRetryingClient<C> {
+    /// Create a `TRetryingClient` that makes calls on `client`, retrying
+    /// them according to `policy`.
+    pub fn new(client: C, policy: TRetryPolicy) -> TRetryingClient<C> {
+        TRetryingClient {
+            client: Some(client),
+            policy,
+            reconnect: None,
+        }
+    }
+
+    /// Replace the client with one built by `reconnect` after every attempt
+    /// that failed with a retryable error.
+    ///
+    /// The new client is built just before the next attempt. If building it
+    /// fails, that counts as a failed attempt.
+    pub fn with_reconnect<F>(mut self, reconnect: F) -> TRetryingClient<C>
+    where
+        F: FnMut() -> crate::Result<C> + Send + 'static,
+    {
+        self.reconnect = Some(Box::new(reconnect));
+        self
+    }
+
+    /// Make a call with `call`, which makes one attempt of it on the
+    /// client, retrying it according to the policy.
+    pub fn call<T, F>(&mut self, mut call: F) -> crate::Result<T>
+    where
+        F: FnMut(&mut C) -> crate::Result<T>,
+    {
+        let TRetryingClient {
+            client,
+            policy,
+            reconnect,
+        } = self;
+        policy.retry(|_| {
+            let result = match (client.as_mut(), reconnect.as_mut()) {
+                (Some(c), _) => call(c),
+                (None, Some(reconnect)) => {
+                    let c = client.insert(reconnect()?);
+                    call(c)
+                }
+                (None, None) => unreachable!("client is only dropped when it can be rebuilt"),
+            };
+            if let (Err(ref e), Some(_)) = (&result, reconnect.as_ref()) {
+                if policy.is_retryable(e) {
+                    *client = None;
+                }
+            }
+            result
+        })
+    }
+
+    /// Return the retry policy.
+    pub fn policy(&self) -> &TRetryPolicy {
+        &self.policy
+    }
+
+    /// Return the wrapped client, unless it failed and has not yet been
+    /// rebuilt.
+    pub fn client(&mut self) -> Option<&mut C> {
+        self.client.as_mut()
+    }
+}
+
+impl<C> fmt::Debug for TRetryingClient<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TRetryingClient")
+            .field("connected", &self.client.is_some())
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{new_transport_error, ApplicationError, ApplicationErrorKind};
+
+    fn refused() -> crate::Error {
+        new_transport_error(TransportErrorKind::NotOpen, "connection refused")
+    }
+
+    fn quick(max_attempts: u32) -> TRetryPolicy {
+        TRetryPolicy::new(
+            TBackoff::fixed(Duration::from_millis(1)).with_max_attempts(Some(max_attempts)),
+        )
+    }
+
+    #[test]
+    fn must_retry_transport_errors_up_to_max_attempts() {
+        let mut attempts = Vec::new();
+        let result: crate::Result<()> = quick(3).retry(|attempt| {
+            attempts.push(attempt);
+            Err(refused())
+        });
+        assert!(matches!(result, Err(crate::Error::Transport(_))));
+        assert_eq!(attempts, [0, 1, 2]);
+    }
+
+    #[test]
+    fn must_not_retry_application_errors() {
+        let mut attempts = 0;
+        let result: crate::Result<()> = quick(3).retry(|_| {
+            attempts += 1;
+            Err(ApplicationError::new(ApplicationErrorKind::InternalError, "boom").into())
+        });
+        assert!(matches!(result, Err(crate::Error::Application(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn must_keep_jittered_delay_within_backoff() {
+        let policy =
+            TRetryPolicy::new(TBackoff::fixed(Duration::from_millis(100))).with_jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(0);
+            assert!(delay > Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+        assert_eq!(policy.with_jitter(f64::NAN).jitter(), 0.0);
+    }
+
+    #[test]
+    fn must_rebuild_client_after_retryable_failure() {
+        let built = Arc::new(AtomicUsize::new(0));
+        let counted = built.clone();
+        let mut client = TRetryingClient::new(false, quick(3)).with_reconnect(move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+            Ok(true)
+        });
+        // the first client always fails; the rebuilt one succeeds
+        let result = client.call(|healthy| if *healthy { Ok(7) } else { Err(refused()) });
+        assert_eq!(result.unwrap(), 7);
+        assert_eq!(built.load(Ordering::Relaxed), 1);
+        assert_eq!(client.client(), Some(&mut true));
+    }
+}
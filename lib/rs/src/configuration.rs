@@ -15,6 +15,52 @@
 // specific language governing permissions and limitations
 // under the License.
 
+/// How protocols treat `f64` values that are NaN or infinite.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TNonFiniteDoublePolicy {
+    /// Read and write non-finite doubles unchanged.
+    #[default]
+    Accept,
+    /// Fail with `ProtocolErrorKind::InvalidData` when a non-finite double is
+    /// read or written.
+    Reject,
+    /// Replace NaN with `0.0` and positive/negative infinity with
+    /// `f64::MAX`/`f64::MIN` when reading or writing.
+    Normalize,
+}
+
+/// How input protocols treat strings that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TUtf8DecodePolicy {
+    /// Fail with an error, as `String::from_utf8` does.
+    #[default]
+    Strict,
+    /// Replace each invalid sequence with `U+FFFD REPLACEMENT CHARACTER`, as
+    /// `String::from_utf8_lossy` does.
+    Lossy,
+    /// Replace each invalid byte with a `\xNN` escape of its hex value. Valid
+    /// text, including any literal backslashes, is kept as is.
+    Escape,
+}
+
+/// How the compact protocol encodes `bool` values that are not struct fields,
+/// i.e. collection elements and map keys and values.
+///
+/// The current compact protocol specification encodes true as `1` and false
+/// as `2`. Implementations written before the specification was clarified in
+/// 2016 encode false as `0` instead.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TCompactBoolEncoding {
+    /// Write `1`/`2` and accept `0`, `1` or `2` when reading.
+    #[default]
+    Standard,
+    /// Write `1`/`2` and reject anything else when reading.
+    Strict,
+    /// Write `1`/`0`, for peers that only understand the pre-2016 encoding,
+    /// and accept `0`, `1` or `2` when reading.
+    Legacy,
+}
+
 /// Configuration for Thrift protocols.
 #[derive(Debug, Clone)]
 pub struct TConfiguration {
@@ -23,6 +69,11 @@ pub struct TConfiguration {
     max_recursion_depth: Option<usize>,
     max_container_size: Option<usize>,
     max_string_size: Option<usize>,
+    non_finite_double_policy: TNonFiniteDoublePolicy,
+    utf8_decode_policy: TUtf8DecodePolicy,
+    compact_bool_encoding: TCompactBoolEncoding,
+    max_skip_depth: Option<usize>,
+    max_skip_bytes: Option<usize>,
 }
 
 impl TConfiguration {
@@ -45,6 +96,11 @@ impl TConfiguration {
             max_recursion_depth: None,
             max_container_size: None,
             max_string_size: None,
+            non_finite_double_policy: TNonFiniteDoublePolicy::Accept,
+            utf8_decode_policy: TUtf8DecodePolicy::Strict,
+            compact_bool_encoding: TCompactBoolEncoding::Standard,
+            max_skip_depth: None,
+            max_skip_bytes: None,
         }
     }
 
@@ -68,6 +124,34 @@ impl TConfiguration {
         self.max_string_size
     }
 
+    pub fn non_finite_double_policy(&self) -> TNonFiniteDoublePolicy {
+        self.non_finite_double_policy
+    }
+
+    pub fn utf8_decode_policy(&self) -> TUtf8DecodePolicy {
+        self.utf8_decode_policy
+    }
+
+    pub fn compact_bool_encoding(&self) -> TCompactBoolEncoding {
+        self.compact_bool_encoding
+    }
+
+    /// Maximum nesting depth `TInputProtocol::skip` descends into when
+    /// skipping a single field. `None` keeps the built-in default of 64.
+    pub fn max_skip_depth(&self) -> Option<usize> {
+        self.max_skip_depth
+    }
+
+    /// Maximum number of bytes `TInputProtocol::skip` may consume when
+    /// skipping a single field.
+    ///
+    /// Bytes are counted using each protocol's minimum encoded size for
+    /// headers and primitives plus the length of every string, so the count
+    /// never exceeds the actual number of bytes read.
+    pub fn max_skip_bytes(&self) -> Option<usize> {
+        self.max_skip_bytes
+    }
+
     pub fn builder() -> TConfigurationBuilder {
         TConfigurationBuilder::default()
     }
@@ -81,6 +165,11 @@ impl Default for TConfiguration {
             max_recursion_depth: Some(Self::DEFAULT_RECURSION_LIMIT),
             max_container_size: Self::DEFAULT_CONTAINER_LIMIT,
             max_string_size: Some(Self::DEFAULT_STRING_LIMIT),
+            non_finite_double_policy: TNonFiniteDoublePolicy::Accept,
+            utf8_decode_policy: TUtf8DecodePolicy::Strict,
+            compact_bool_encoding: TCompactBoolEncoding::Standard,
+            max_skip_depth: None,
+            max_skip_bytes: None,
         }
     }
 }
@@ -116,6 +205,31 @@ impl TConfigurationBuilder {
         self
     }
 
+    pub fn non_finite_double_policy(mut self, policy: TNonFiniteDoublePolicy) -> Self {
+        self.config.non_finite_double_policy = policy;
+        self
+    }
+
+    pub fn utf8_decode_policy(mut self, policy: TUtf8DecodePolicy) -> Self {
+        self.config.utf8_decode_policy = policy;
+        self
+    }
+
+    pub fn compact_bool_encoding(mut self, encoding: TCompactBoolEncoding) -> Self {
+        self.config.compact_bool_encoding = encoding;
+        self
+    }
+
+    pub fn max_skip_depth(mut self, limit: Option<usize>) -> Self {
+        self.config.max_skip_depth = limit;
+        self
+    }
+
+    pub fn max_skip_bytes(mut self, limit: Option<usize>) -> Self {
+        self.config.max_skip_bytes = limit;
+        self
+    }
+
     pub fn build(self) -> crate::Result<TConfiguration> {
         if let (Some(frame_size), Some(message_size)) =
             (self.config.max_frame_size, self.config.max_message_size)
@@ -148,6 +262,8 @@ mod tests {
             .max_recursion_depth(Some(10))
             .max_container_size(Some(100))
             .max_string_size(Some(256))
+            .max_skip_depth(Some(8))
+            .max_skip_bytes(Some(128))
             .build()
             .unwrap();
 
@@ -156,6 +272,17 @@ mod tests {
         assert_eq!(config.max_recursion_depth(), Some(10));
         assert_eq!(config.max_container_size(), Some(100));
         assert_eq!(config.max_string_size(), Some(256));
+        assert_eq!(config.max_skip_depth(), Some(8));
+        assert_eq!(config.max_skip_bytes(), Some(128));
+        assert_eq!(
+            config.non_finite_double_policy(),
+            TNonFiniteDoublePolicy::Accept
+        );
+        assert_eq!(config.utf8_decode_policy(), TUtf8DecodePolicy::Strict);
+        assert_eq!(
+            config.compact_bool_encoding(),
+            TCompactBoolEncoding::Standard
+        );
     }
 
     #[test]
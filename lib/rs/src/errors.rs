@@ -243,26 +243,33 @@ impl Error {
         e: &ApplicationError,
         o: &mut dyn TOutputProtocol,
     ) -> crate::Result<()> {
-        o.write_struct_begin(&TStructIdentifier {
-            name: "TApplicationException".to_owned(),
-        })?;
+        write_application_error_struct(e, o)?;
+        o.flush()
+    }
+}
 
-        let message_field = TFieldIdentifier::new("message", TType::String, 1);
-        let type_field = TFieldIdentifier::new("type", TType::I32, 2);
+/// Write the `TApplicationException` struct for `e` without flushing.
+pub(crate) fn write_application_error_struct(
+    e: &ApplicationError,
+    o: &mut dyn TOutputProtocol,
+) -> crate::Result<()> {
+    o.write_struct_begin(&TStructIdentifier {
+        name: "TApplicationException".to_owned(),
+    })?;
 
-        o.write_field_begin(&message_field)?;
-        o.write_string(&e.message)?;
-        o.write_field_end()?;
+    let message_field = TFieldIdentifier::new("message", TType::String, 1);
+    let type_field = TFieldIdentifier::new("type", TType::I32, 2);
 
-        o.write_field_begin(&type_field)?;
-        o.write_i32(e.kind as i32)?;
-        o.write_field_end()?;
+    o.write_field_begin(&message_field)?;
+    o.write_string(&e.message)?;
+    o.write_field_end()?;
 
-        o.write_field_stop()?;
-        o.write_struct_end()?;
+    o.write_field_begin(&type_field)?;
+    o.write_i32(e.kind as i32)?;
+    o.write_field_end()?;
 
-        o.flush()
-    }
+    o.write_field_stop()?;
+    o.write_struct_end()
 }
 
 impl error::Error for Error {}
@@ -513,6 +520,9 @@ pub enum ProtocolErrorKind {
     EmptyUnion = 7,
     /// A Thrift union contained fields but none matched known variants.
     UnknownUnionVariant = 8,
+    /// A protocol method was called out of sequence, for example ending a
+    /// struct that was never begun.
+    InvalidState = 9,
 }
 
 impl Display for ProtocolError {
@@ -527,6 +537,7 @@ impl Display for ProtocolError {
             ProtocolErrorKind::DepthLimit => "maximum skip depth reached",
             ProtocolErrorKind::EmptyUnion => "empty union",
             ProtocolErrorKind::UnknownUnionVariant => "unknown union variant",
+            ProtocolErrorKind::InvalidState => "invalid protocol state",
         };
 
         write!(f, "{}", error_text)
@@ -546,6 +557,7 @@ impl TryFrom<i32> for ProtocolErrorKind {
             6 => Ok(ProtocolErrorKind::DepthLimit),
             7 => Ok(ProtocolErrorKind::EmptyUnion),
             8 => Ok(ProtocolErrorKind::UnknownUnionVariant),
+            9 => Ok(ProtocolErrorKind::InvalidState),
             _ => Err(Error::Protocol(ProtocolError {
                 kind: ProtocolErrorKind::Unknown,
                 message: format!("cannot convert {} to ProtocolErrorKind", from),
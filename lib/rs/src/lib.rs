@@ -25,7 +25,8 @@
 //! 3. protocol
 //! 4. transport
 //! 5. server
-//! 6. autogen
+//! 6. client
+//! 7. autogen
 //!
 //! The modules are layered as shown in the diagram below. The `autogen'd`
 //! layer is generated by the Thrift compiler's Rust plugin. It uses the
@@ -71,11 +72,15 @@ macro_rules! assert_success {
     }};
 }
 
+pub mod client;
 pub mod protocol;
 
+#[cfg(feature = "serde")]
+pub mod serde;
 #[cfg(feature = "server")]
 pub mod server;
 pub mod transport;
+pub mod value;
 
 mod errors;
 pub use crate::errors::*;
@@ -92,6 +97,9 @@ pub use crate::configuration::*;
 /// with `E` defined as the `thrift::Error` type.
 pub type Result<T> = std::result::Result<T, self::Error>;
 
+#[cfg(feature = "derive")]
+pub use thrift_derive::{ThriftDeserialize, ThriftSerialize};
+
 // Re-export ordered-float, since it is used by the generator
 // FIXME: check the guidance around type reexports
 pub use ordered_float::OrderedFloat;
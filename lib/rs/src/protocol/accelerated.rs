@@ -0,0 +1,456 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io::{BufRead, BufReader};
+
+use super::binary::field_type_from_u8;
+use super::{
+    TBinaryInputProtocol, TFieldIdentifier, TInputProtocol, TInputProtocolFactory, TListIdentifier,
+    TMapIdentifier, TMessageIdentifier, TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::transport::TReadTransport;
+use crate::{ProtocolError, ProtocolErrorKind, TConfiguration};
+
+/// Read messages encoded in the Thrift simple binary encoding from a
+/// transport that exposes its internal buffer.
+///
+/// Decodes exactly the same wire format as `TBinaryInputProtocol`, but
+/// requires a transport that implements `std::io::BufRead` (for example
+/// `TFramedReadTransport`, `TBufferedReadTransport`, `std::io::BufReader` or
+/// a byte slice). Fixed-width integers, field headers and binary or string
+/// values that are already buffered are decoded straight from the
+/// transport's buffer; only values that straddle a buffer boundary fall back
+/// to `Read::read_exact`.
+///
+/// # Examples
+///
+/// Create and use a `TAcceleratedBinaryInputProtocol`.
+///
+/// ```no_run
+/// use thrift::protocol::{TAcceleratedBinaryInputProtocol, TInputProtocol};
+/// use thrift::transport::{TFramedReadTransport, TTcpChannel};
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+///
+/// let transport = TFramedReadTransport::new(channel);
+/// let mut protocol = TAcceleratedBinaryInputProtocol::new(transport, true);
+///
+/// let recvd_bool = protocol.read_bool().unwrap();
+/// let recvd_string = protocol.read_string().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TAcceleratedBinaryInputProtocol<T>
+where
+    T: BufRead,
+{
+    inner: TBinaryInputProtocol<T>,
+}
+
+impl<T> TAcceleratedBinaryInputProtocol<T>
+where
+    T: BufRead,
+{
+    /// Create a `TAcceleratedBinaryInputProtocol` that reads bytes from
+    /// `transport`.
+    ///
+    /// Set `strict` to `true` if all incoming messages contain the protocol
+    /// version number in the protocol header.
+    pub fn new(transport: T, strict: bool) -> Self {
+        Self::with_config(transport, strict, TConfiguration::default())
+    }
+
+    /// Create a `TAcceleratedBinaryInputProtocol` that reads bytes from
+    /// `transport` and enforces the limits in `config`.
+    pub fn with_config(transport: T, strict: bool, config: TConfiguration) -> Self {
+        TAcceleratedBinaryInputProtocol {
+            inner: TBinaryInputProtocol::with_config(transport, strict, config),
+        }
+    }
+
+    /// Consume this protocol and return the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner.transport
+    }
+
+    fn read_array<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        let mut out = [0u8; N];
+        let transport = &mut self.inner.transport;
+        let available = transport.fill_buf()?;
+        if available.len() >= N {
+            out.copy_from_slice(&available[..N]);
+            transport.consume(N);
+        } else {
+            transport.read_exact(&mut out)?;
+        }
+        Ok(out)
+    }
+}
+
+impl<T> TInputProtocol for TAcceleratedBinaryInputProtocol<T>
+where
+    T: BufRead,
+{
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        self.inner.read_message_begin()
+    }
+
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        self.inner.read_message_end()
+    }
+
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.inner.read_struct_begin()
+    }
+
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.inner.read_struct_end()
+    }
+
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        let field_type = field_type_from_u8(self.read_byte()?)?;
+        let id = match field_type {
+            TType::Stop => 0,
+            _ => self.read_i16()?,
+        };
+        Ok(TFieldIdentifier::new::<Option<String>, String, i16>(
+            None, field_type, id,
+        ))
+    }
+
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        self.inner.read_field_end()
+    }
+
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        let num_bytes = self.read_i32()?;
+
+        if num_bytes < 0 {
+            return Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::NegativeSize,
+                format!("Negative byte array size: {}", num_bytes),
+            )));
+        }
+
+        let num_bytes = num_bytes as usize;
+
+        if let Some(max_size) = self.inner.config().max_string_size() {
+            if num_bytes > max_size {
+                return Err(crate::Error::Protocol(ProtocolError::new(
+                    ProtocolErrorKind::SizeLimit,
+                    format!(
+                        "Byte array size {} exceeds maximum allowed size of {}",
+                        num_bytes, max_size
+                    ),
+                )));
+            }
+        }
+
+        let transport = &mut self.inner.transport;
+        let available = transport.fill_buf()?;
+        if available.len() >= num_bytes {
+            let buf = available[..num_bytes].to_vec();
+            transport.consume(num_bytes);
+            Ok(buf)
+        } else {
+            let mut buf = vec![0u8; num_bytes];
+            transport.read_exact(&mut buf)?;
+            Ok(buf)
+        }
+    }
+
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        Ok(self.read_byte()? != 0)
+    }
+
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        Ok(self.read_byte()? as i8)
+    }
+
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.read_array().map(i16::from_be_bytes)
+    }
+
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        self.read_array().map(i32::from_be_bytes)
+    }
+
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        self.read_array().map(i64::from_be_bytes)
+    }
+
+    fn read_double(&mut self) -> crate::Result<f64> {
+        let d = self.read_array().map(f64::from_be_bytes)?;
+        super::check_double(self.inner.config(), d)
+    }
+
+    fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
+        self.read_array().map(uuid::Uuid::from_bytes)
+    }
+
+    fn read_string(&mut self) -> crate::Result<String> {
+        let bytes = self.read_bytes()?;
+        super::decode_string(self.inner.config(), bytes)
+    }
+
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        let element_type = field_type_from_u8(self.read_byte()?)?;
+        let size = self.read_i32()?;
+        let min_element_size = self.min_serialized_size(element_type);
+        super::check_container_size(self.inner.config(), size, min_element_size)?;
+        Ok(TListIdentifier::new(element_type, size))
+    }
+
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        self.inner.read_list_end()
+    }
+
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        let element_type = field_type_from_u8(self.read_byte()?)?;
+        let size = self.read_i32()?;
+        let min_element_size = self.min_serialized_size(element_type);
+        super::check_container_size(self.inner.config(), size, min_element_size)?;
+        Ok(TSetIdentifier::new(element_type, size))
+    }
+
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        self.inner.read_set_end()
+    }
+
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        let [key_type, value_type] = self.read_array::<2>()?;
+        let key_type = field_type_from_u8(key_type)?;
+        let value_type = field_type_from_u8(value_type)?;
+        let size = self.read_i32()?;
+
+        let element_size =
+            self.min_serialized_size(key_type) + self.min_serialized_size(value_type);
+        super::check_container_size(self.inner.config(), size, element_size)?;
+
+        Ok(TMapIdentifier::new(key_type, value_type, size))
+    }
+
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        self.inner.read_map_end()
+    }
+
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        let depth = super::capped_skip_depth(self.inner.config(), depth);
+        let mut budget = super::SkipBudget::from_config(self.inner.config());
+        super::skip_with_budget(self, field_type, depth, &mut budget)
+    }
+
+    fn min_serialized_size(&self, field_type: TType) -> usize {
+        self.inner.min_serialized_size(field_type)
+    }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        self.inner.set_configuration(config)
+    }
+
+    // utility
+    //
+
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        self.read_array::<1>().map(|b| b[0])
+    }
+}
+
+/// Factory for creating instances of `TAcceleratedBinaryInputProtocol`.
+///
+/// Created protocols read through a `std::io::BufReader` wrapped around the
+/// supplied transport, so this factory should be paired with a plain
+/// (unbuffered) read transport factory.
+#[derive(Default)]
+pub struct TAcceleratedBinaryInputProtocolFactory;
+
+impl TAcceleratedBinaryInputProtocolFactory {
+    /// Create a `TAcceleratedBinaryInputProtocolFactory`.
+    pub fn new() -> TAcceleratedBinaryInputProtocolFactory {
+        TAcceleratedBinaryInputProtocolFactory {}
+    }
+}
+
+impl TInputProtocolFactory for TAcceleratedBinaryInputProtocolFactory {
+    fn create(&self, transport: Box<dyn TReadTransport + Send>) -> Box<dyn TInputProtocol + Send> {
+        Box::new(TAcceleratedBinaryInputProtocol::new(
+            BufReader::new(transport),
+            true,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{self, Read};
+
+    use super::*;
+    use crate::protocol::{
+        TBinaryOutputProtocol, TFieldIdentifier, TMessageType, TOutputProtocol, TStructIdentifier,
+    };
+
+    // yields at most `chunk` bytes per `fill_buf` call so that values
+    // straddle buffer boundaries
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.chunk).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    impl BufRead for Chunked<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            let n = self.chunk.min(self.data.len());
+            Ok(&self.data[..n])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.data = &self.data[amt..];
+        }
+    }
+
+    fn encoded_message() -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut o_prot = TBinaryOutputProtocol::new(&mut buf, true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("echo", TMessageType::Call, 3))
+            .unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("args"))
+            .unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("a", TType::I64, 1))
+            .unwrap();
+        o_prot.write_i64(-1234567890123).unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("b", TType::String, 2))
+            .unwrap();
+        o_prot.write_string("hello accelerated").unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("c", TType::Map, 3))
+            .unwrap();
+        o_prot
+            .write_map_begin(&TMapIdentifier::new(TType::I16, TType::Double, 1))
+            .unwrap();
+        o_prot.write_i16(-7).unwrap();
+        o_prot.write_double(2.5).unwrap();
+        o_prot.write_map_end().unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        buf
+    }
+
+    fn assert_decodes_message<P: TInputProtocol>(i_prot: &mut P) {
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(
+            ident,
+            TMessageIdentifier::new("echo", TMessageType::Call, 3)
+        );
+        i_prot.read_struct_begin().unwrap();
+
+        let field = i_prot.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I64, Some(1)));
+        assert_eq!(i_prot.read_i64().unwrap(), -1234567890123);
+        i_prot.read_field_end().unwrap();
+
+        let field = i_prot.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::String, Some(2)));
+        assert_eq!(i_prot.read_string().unwrap(), "hello accelerated");
+        i_prot.read_field_end().unwrap();
+
+        let field = i_prot.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Map, Some(3)));
+        let map = i_prot.read_map_begin().unwrap();
+        assert_eq!(map, TMapIdentifier::new(TType::I16, TType::Double, 1));
+        assert_eq!(i_prot.read_i16().unwrap(), -7);
+        assert_eq!(i_prot.read_double().unwrap(), 2.5);
+        i_prot.read_map_end().unwrap();
+        i_prot.read_field_end().unwrap();
+
+        let field = i_prot.read_field_begin().unwrap();
+        assert_eq!(field.field_type, TType::Stop);
+        i_prot.read_struct_end().unwrap();
+        i_prot.read_message_end().unwrap();
+    }
+
+    #[test]
+    fn must_decode_from_contiguous_buffer() {
+        let buf = encoded_message();
+        let mut i_prot = TAcceleratedBinaryInputProtocol::new(&buf[..], true);
+        assert_decodes_message(&mut i_prot);
+    }
+
+    #[test]
+    fn must_decode_values_straddling_buffer_boundaries() {
+        let buf = encoded_message();
+        for chunk in 1..8 {
+            let transport = Chunked { data: &buf, chunk };
+            let mut i_prot = TAcceleratedBinaryInputProtocol::new(transport, true);
+            assert_decodes_message(&mut i_prot);
+        }
+    }
+
+    #[test]
+    fn must_fail_on_truncated_input() {
+        let buf = encoded_message();
+        let mut i_prot = TAcceleratedBinaryInputProtocol::new(&buf[..buf.len() - 2], true);
+        i_prot.read_message_begin().unwrap();
+        i_prot.read_struct_begin().unwrap();
+        i_prot.read_field_begin().unwrap();
+        i_prot.read_i64().unwrap();
+        i_prot.read_field_end().unwrap();
+        i_prot.read_field_begin().unwrap();
+        i_prot.read_string().unwrap();
+        i_prot.read_field_end().unwrap();
+        i_prot.read_field_begin().unwrap();
+        i_prot.read_map_begin().unwrap();
+        i_prot.read_i16().unwrap();
+        assert!(i_prot.read_double().is_err());
+    }
+
+    #[test]
+    fn must_enforce_string_size_limit() {
+        let buf = encoded_message();
+        let config = TConfiguration::builder()
+            .max_string_size(Some(8))
+            .build()
+            .unwrap();
+        let mut i_prot = TAcceleratedBinaryInputProtocol::with_config(&buf[..], true, config);
+        i_prot.read_message_begin().unwrap();
+        i_prot.read_struct_begin().unwrap();
+        i_prot.read_field_begin().unwrap();
+        i_prot.read_i64().unwrap();
+        i_prot.read_field_end().unwrap();
+        i_prot.read_field_begin().unwrap();
+        match i_prot.read_string() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::SizeLimit),
+            other => panic!("expected size limit error, got {:?}", other),
+        }
+    }
+}
@@ -17,6 +17,7 @@
 
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 use std::convert::{From, TryFrom};
+use std::io;
 
 use super::{
     TFieldIdentifier, TInputProtocol, TInputProtocolFactory, TListIdentifier, TMapIdentifier,
@@ -82,6 +83,10 @@ where
         }
     }
 
+    pub(super) fn config(&self) -> &TConfiguration {
+        &self.config
+    }
+
     fn check_recursion_depth(&self) -> crate::Result<()> {
         if let Some(limit) = self.config.max_recursion_depth() {
             if self.recursion_depth >= limit {
@@ -171,7 +176,10 @@ where
     }
 
     fn read_struct_end(&mut self) -> crate::Result<()> {
-        self.recursion_depth -= 1;
+        self.recursion_depth = self
+            .recursion_depth
+            .checked_sub(1)
+            .ok_or_else(super::unbalanced_struct_end_error)?;
         Ok(())
     }
 
@@ -245,7 +253,8 @@ where
     }
 
     fn read_double(&mut self) -> crate::Result<f64> {
-        self.transport.read_f64::<BigEndian>().map_err(From::from)
+        let d = self.transport.read_f64::<BigEndian>()?;
+        super::check_double(&self.config, d)
     }
 
     fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
@@ -258,7 +267,7 @@ where
 
     fn read_string(&mut self) -> crate::Result<String> {
         let bytes = self.read_bytes()?;
-        String::from_utf8(bytes).map_err(From::from)
+        super::decode_string(&self.config, bytes)
     }
 
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
@@ -302,6 +311,12 @@ where
         Ok(())
     }
 
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        let depth = super::capped_skip_depth(&self.config, depth);
+        let mut budget = super::SkipBudget::from_config(&self.config);
+        super::skip_with_budget(self, field_type, depth, &mut budget)
+    }
+
     // utility
     //
 
@@ -328,6 +343,19 @@ where
             TType::Utf7 => 1,   // 1 byte
         }
     }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        self.config = config.clone();
+    }
+}
+
+impl<T> io::Seek for TBinaryInputProtocol<T>
+where
+    T: io::Seek + TReadTransport,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.transport.seek(pos)
+    }
 }
 
 /// Factory for creating instances of `TBinaryInputProtocol`.
@@ -447,7 +475,10 @@ where
     }
 
     fn write_struct_end(&mut self) -> crate::Result<()> {
-        self.recursion_depth -= 1;
+        self.recursion_depth = self
+            .recursion_depth
+            .checked_sub(1)
+            .ok_or_else(super::unbalanced_struct_end_error)?;
         Ok(())
     }
 
@@ -479,8 +510,8 @@ where
     }
 
     fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
-        self.write_i32(b.len() as i32)?;
-        self.transport.write_all(b).map_err(From::from)
+        let header = (b.len() as i32).to_be_bytes();
+        TWriteTransport::write_all_vectored(&mut self.transport, &[&header, b]).map_err(From::from)
     }
 
     fn write_bool(&mut self, b: bool) -> crate::Result<()> {
@@ -508,6 +539,7 @@ where
     }
 
     fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        let d = super::check_double(&self.config, d)?;
         self.transport.write_f64::<BigEndian>(d).map_err(From::from)
     }
 
@@ -567,6 +599,15 @@ where
     }
 }
 
+impl<T> io::Seek for TBinaryOutputProtocol<T>
+where
+    T: io::Seek + TWriteTransport,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.transport.seek(pos)
+    }
+}
+
 /// Factory for creating instances of `TBinaryOutputProtocol`.
 #[derive(Default)]
 pub struct TBinaryOutputProtocolFactory;
@@ -606,7 +647,7 @@ fn field_type_to_u8(field_type: TType) -> u8 {
     }
 }
 
-fn field_type_from_u8(b: u8) -> crate::Result<TType> {
+pub(super) fn field_type_from_u8(b: u8) -> crate::Result<TType> {
     match b {
         0x00 => Ok(TType::Stop),
         0x01 => Ok(TType::Void),
@@ -1343,4 +1384,144 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "hello");
     }
+
+    #[test]
+    fn must_seek_within_seekable_transports() {
+        use std::io::{Cursor, Seek, SeekFrom};
+
+        let mut o_prot = TBinaryOutputProtocol::new(Cursor::new(Vec::new()), true);
+        assert_success!(o_prot.write_i32(1));
+        assert_success!(o_prot.write_i32(2));
+
+        // overwrite the first record in place
+        assert_eq!(o_prot.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert_success!(o_prot.write_i32(3));
+
+        let buf = o_prot.transport.into_inner();
+        assert_eq!(buf, vec![0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02]);
+
+        let mut i_prot = TBinaryInputProtocol::new(Cursor::new(buf), true);
+
+        // jump straight to the second record, then go back to the first
+        assert_eq!(i_prot.seek(SeekFrom::Start(4)).unwrap(), 4);
+        assert_eq!(i_prot.read_i32().unwrap(), 2);
+        assert_eq!(i_prot.seek(SeekFrom::Current(-8)).unwrap(), 0);
+        assert_eq!(i_prot.read_i32().unwrap(), 3);
+    }
+
+    #[test]
+    fn must_fail_on_unbalanced_struct_end() {
+        let (mut i_prot, mut o_prot) = test_objects(true);
+
+        assert_success!(i_prot.read_struct_begin());
+        assert_success!(i_prot.read_struct_end());
+        match i_prot.read_struct_end() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidState),
+            other => panic!("expected invalid state error, got {:?}", other),
+        }
+
+        match o_prot.write_struct_end() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidState),
+            other => panic!("expected invalid state error, got {:?}", other),
+        }
+    }
+
+    fn double_policy_config(policy: crate::TNonFiniteDoublePolicy) -> TConfiguration {
+        TConfiguration::builder()
+            .non_finite_double_policy(policy)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn must_reject_non_finite_doubles_when_configured() {
+        let config = double_policy_config(crate::TNonFiniteDoublePolicy::Reject);
+
+        let mut buf = Vec::new();
+        let mut o_prot = TBinaryOutputProtocol::with_config(&mut buf, true, config.clone());
+        assert!(o_prot.write_double(1.5).is_ok());
+        for d in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            match o_prot.write_double(d) {
+                Err(crate::Error::Protocol(e)) => {
+                    assert_eq!(e.kind, ProtocolErrorKind::InvalidData)
+                }
+                other => panic!("expected protocol error, got {:?}", other),
+            }
+        }
+        assert_eq!(buf.len(), 8);
+
+        let nan = f64::NAN.to_be_bytes();
+        let mut i_prot = TBinaryInputProtocol::with_config(&nan[..], true, config);
+        match i_prot.read_double() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_normalize_non_finite_doubles_when_configured() {
+        let mut buf = Vec::new();
+        for d in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            buf.extend_from_slice(&d.to_be_bytes());
+        }
+
+        let config = double_policy_config(crate::TNonFiniteDoublePolicy::Normalize);
+        let mut i_prot = TBinaryInputProtocol::with_config(&buf[..], true, config);
+        assert_eq!(i_prot.read_double().unwrap(), 0.0);
+        assert_eq!(i_prot.read_double().unwrap(), f64::MAX);
+        assert_eq!(i_prot.read_double().unwrap(), f64::MIN);
+    }
+
+    #[test]
+    fn must_accept_non_finite_doubles_by_default() {
+        let mut buf = Vec::new();
+        let mut o_prot = TBinaryOutputProtocol::new(&mut buf, true);
+        o_prot.write_double(f64::NAN).unwrap();
+
+        let mut i_prot = TBinaryInputProtocol::new(&buf[..], true);
+        assert!(i_prot.read_double().unwrap().is_nan());
+    }
+
+    #[test]
+    fn must_read_invalid_utf8_string_with_lossy_policy() {
+        let mut buf = Vec::new();
+        let mut o_prot = TBinaryOutputProtocol::new(&mut buf, true);
+        o_prot.write_bytes(&[b'o', 0xF8, b'k']).unwrap();
+
+        let mut i_prot = TBinaryInputProtocol::new(&buf[..], true);
+        assert!(i_prot.read_string().is_err());
+
+        let config = TConfiguration::builder()
+            .utf8_decode_policy(crate::TUtf8DecodePolicy::Lossy)
+            .build()
+            .unwrap();
+        let mut i_prot = TBinaryInputProtocol::with_config(&buf[..], true, config);
+        assert_eq!(i_prot.read_string().unwrap(), "o\u{FFFD}k");
+    }
+
+    #[test]
+    fn must_limit_bytes_consumed_by_skip() {
+        let mut buf = Vec::new();
+        {
+            let mut o_prot = TBinaryOutputProtocol::new(&mut buf, true);
+            o_prot
+                .write_map_begin(&TMapIdentifier::new(TType::I32, TType::I64, 2))
+                .unwrap();
+            for i in 0..2 {
+                o_prot.write_i32(i).unwrap();
+                o_prot.write_i64(i as i64).unwrap();
+            }
+            o_prot.write_map_end().unwrap();
+        }
+        // skip charges the map header at its minimum size of 4 bytes, plus
+        // 2 * (4 + 8) bytes of entries
+        for (limit, ok) in [(27, false), (28, true)] {
+            let config = TConfiguration::builder()
+                .max_skip_bytes(Some(limit))
+                .build()
+                .unwrap();
+            let mut i_prot = TBinaryInputProtocol::with_config(&buf[..], true, config);
+            assert_eq!(i_prot.skip(TType::Map).is_ok(), ok, "limit {}", limit);
+        }
+    }
 }
@@ -0,0 +1,488 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use super::{
+    TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TOutputProtocol,
+    TRawString, TSetIdentifier, TStructIdentifier,
+};
+use crate::{ProtocolError, ProtocolErrorKind};
+
+/// `TOutputProtocol` that buffers struct fields and writes them to the
+/// wrapped protocol in ascending field-id order.
+///
+/// Some encodings - notably the compact protocol - encode field ids as deltas
+/// from the previously-written field, and therefore expect fields to be
+/// written in ascending id order. A `TFieldBufferingOutputProtocol` accepts
+/// fields in any order: all calls made between `write_struct_begin` and
+/// `write_struct_end` are recorded, and when the struct is closed its fields
+/// are sorted by id and replayed to the wrapped protocol. Nested structs are
+/// sorted independently. Calls made outside a struct (message envelopes,
+/// top-level primitives and containers) are forwarded immediately.
+///
+/// Fields with equal ids are replayed in the order they were written.
+///
+/// # Examples
+///
+/// Write fields out of order.
+///
+/// ```no_run
+/// use thrift::protocol::{TCompactOutputProtocol, TFieldBufferingOutputProtocol};
+/// use thrift::protocol::{TFieldIdentifier, TOutputProtocol, TStructIdentifier, TType};
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+///
+/// let protocol = TCompactOutputProtocol::new(channel);
+/// let mut protocol = TFieldBufferingOutputProtocol::new(protocol);
+///
+/// protocol.write_struct_begin(&TStructIdentifier::new("foo")).unwrap();
+/// protocol.write_field_begin(&TFieldIdentifier::new("second", TType::I32, 2)).unwrap();
+/// protocol.write_i32(2).unwrap();
+/// protocol.write_field_end().unwrap();
+/// protocol.write_field_begin(&TFieldIdentifier::new("first", TType::I32, 1)).unwrap();
+/// protocol.write_i32(1).unwrap();
+/// protocol.write_field_end().unwrap();
+/// protocol.write_field_stop().unwrap();
+/// protocol.write_struct_end().unwrap(); // field 1 is written before field 2
+/// ```
+#[derive(Debug)]
+pub struct TFieldBufferingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    inner: P,
+    // One entry for each struct that is currently open.
+    open_structs: Vec<BufferedStruct>,
+}
+
+#[derive(Debug)]
+struct BufferedStruct {
+    identifier: TStructIdentifier,
+    fields: Vec<BufferedField>,
+    current: Option<BufferedField>,
+}
+
+#[derive(Debug)]
+struct BufferedField {
+    id: i16,
+    ops: Vec<WriteOp>,
+}
+
+#[derive(Debug)]
+enum WriteOp {
+    StructBegin(TStructIdentifier),
+    StructEnd,
+    FieldBegin(TFieldIdentifier),
+    FieldEnd,
+    FieldStop,
+    Bool(bool),
+    Bytes(Vec<u8>),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Double(f64),
+    Uuid(uuid::Uuid),
+    String(String),
+    RawString(TRawString),
+    ListBegin(TListIdentifier),
+    ListEnd,
+    SetBegin(TSetIdentifier),
+    SetEnd,
+    MapBegin(TMapIdentifier),
+    MapEnd,
+    Byte(u8),
+}
+
+impl<P> TFieldBufferingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    /// Create a `TFieldBufferingOutputProtocol` that writes sorted struct
+    /// fields to `wrapped`.
+    pub fn new(wrapped: P) -> TFieldBufferingOutputProtocol<P> {
+        TFieldBufferingOutputProtocol {
+            inner: wrapped,
+            open_structs: Vec::new(),
+        }
+    }
+
+    /// Consume this protocol and return the wrapped protocol.
+    ///
+    /// Any fields belonging to a struct that has not been closed are discarded.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn record(&mut self, op: WriteOp) -> crate::Result<()> {
+        match self.open_structs.last_mut() {
+            None => replay(&mut self.inner, op),
+            Some(open) => match open.current {
+                Some(ref mut field) => {
+                    field.ops.push(op);
+                    Ok(())
+                }
+                None => Err(crate::Error::Protocol(ProtocolError::new(
+                    ProtocolErrorKind::InvalidState,
+                    format!("cannot write {:?} outside a struct field", op),
+                ))),
+            },
+        }
+    }
+}
+
+impl<P> TOutputProtocol for TFieldBufferingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        if self.open_structs.is_empty() {
+            self.inner.write_message_begin(identifier)
+        } else {
+            Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                "cannot write message begin inside a struct",
+            )))
+        }
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        if self.open_structs.is_empty() {
+            self.inner.write_message_end()
+        } else {
+            Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                "cannot write message end inside a struct",
+            )))
+        }
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        if let Some(open) = self.open_structs.last() {
+            if open.current.is_none() {
+                return Err(crate::Error::Protocol(ProtocolError::new(
+                    ProtocolErrorKind::InvalidState,
+                    format!("cannot begin struct {:?} outside a field", identifier),
+                )));
+            }
+        }
+        self.open_structs.push(BufferedStruct {
+            identifier: identifier.clone(),
+            fields: Vec::new(),
+            current: None,
+        });
+        Ok(())
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        let mut closed = self.open_structs.pop().ok_or_else(|| {
+            crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                "cannot end struct that was never begun",
+            ))
+        })?;
+        if let Some(field) = closed.current.take() {
+            return Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                format!("field {} was not ended before its struct", field.id),
+            )));
+        }
+
+        // stable sort, so that fields with equal ids keep their write order
+        closed.fields.sort_by_key(|f| f.id);
+
+        let mut ops =
+            Vec::with_capacity(closed.fields.iter().map(|f| f.ops.len()).sum::<usize>() + 3);
+        ops.push(WriteOp::StructBegin(closed.identifier));
+        for field in closed.fields {
+            ops.extend(field.ops);
+        }
+        ops.push(WriteOp::FieldStop);
+        ops.push(WriteOp::StructEnd);
+
+        match self.open_structs.last_mut() {
+            Some(parent) => {
+                // the parent's current field is guaranteed to exist since
+                // write_struct_begin refuses to open a struct outside a field
+                if let Some(ref mut field) = parent.current {
+                    field.ops.extend(ops);
+                }
+                Ok(())
+            }
+            None => {
+                for op in ops {
+                    replay(&mut self.inner, op)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        let open = match self.open_structs.last_mut() {
+            Some(open) => open,
+            None => return self.inner.write_field_begin(identifier),
+        };
+
+        if let Some(ref field) = open.current {
+            return Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                format!(
+                    "cannot begin field {:?} before field {} has ended",
+                    identifier, field.id
+                ),
+            )));
+        }
+
+        let id = identifier.id.ok_or_else(|| {
+            crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                format!("cannot write field {:?} without a field id", identifier),
+            ))
+        })?;
+
+        open.current = Some(BufferedField {
+            id,
+            ops: vec![WriteOp::FieldBegin(identifier.clone())],
+        });
+        Ok(())
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        let open = match self.open_structs.last_mut() {
+            Some(open) => open,
+            None => return self.inner.write_field_end(),
+        };
+
+        match open.current.take() {
+            Some(mut field) => {
+                field.ops.push(WriteOp::FieldEnd);
+                open.fields.push(field);
+                Ok(())
+            }
+            None => Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                "cannot end field that was never begun",
+            ))),
+        }
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        match self.open_structs.last() {
+            // the stop field is written when the struct is replayed
+            Some(open) if open.current.is_none() => Ok(()),
+            Some(open) => Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                format!(
+                    "cannot write field stop before field {} has ended",
+                    open.current.as_ref().map(|f| f.id).unwrap_or_default()
+                ),
+            ))),
+            None => self.inner.write_field_stop(),
+        }
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.record(WriteOp::Bool(b))
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.record(WriteOp::Bytes(b.to_vec()))
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.record(WriteOp::I8(i))
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.record(WriteOp::I16(i))
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.record(WriteOp::I32(i))
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.record(WriteOp::I64(i))
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.record(WriteOp::Double(d))
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        self.record(WriteOp::Uuid(*uuid))
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.record(WriteOp::String(s.to_owned()))
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.record(WriteOp::RawString(s.clone()))
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.record(WriteOp::ListBegin(identifier.clone()))
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.record(WriteOp::ListEnd)
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.record(WriteOp::SetBegin(identifier.clone()))
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.record(WriteOp::SetEnd)
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        self.record(WriteOp::MapBegin(identifier.clone()))
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.record(WriteOp::MapEnd)
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        self.inner.flush()
+    }
+
+    // utility
+    //
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.record(WriteOp::Byte(b))
+    }
+}
+
+fn replay<P: TOutputProtocol + ?Sized>(o_prot: &mut P, op: WriteOp) -> crate::Result<()> {
+    match op {
+        WriteOp::StructBegin(ident) => o_prot.write_struct_begin(&ident),
+        WriteOp::StructEnd => o_prot.write_struct_end(),
+        WriteOp::FieldBegin(ident) => o_prot.write_field_begin(&ident),
+        WriteOp::FieldEnd => o_prot.write_field_end(),
+        WriteOp::FieldStop => o_prot.write_field_stop(),
+        WriteOp::Bool(b) => o_prot.write_bool(b),
+        WriteOp::Bytes(b) => o_prot.write_bytes(&b),
+        WriteOp::I8(i) => o_prot.write_i8(i),
+        WriteOp::I16(i) => o_prot.write_i16(i),
+        WriteOp::I32(i) => o_prot.write_i32(i),
+        WriteOp::I64(i) => o_prot.write_i64(i),
+        WriteOp::Double(d) => o_prot.write_double(d),
+        WriteOp::Uuid(u) => o_prot.write_uuid(&u),
+        WriteOp::String(s) => o_prot.write_string(&s),
+        WriteOp::RawString(s) => o_prot.write_raw_string(&s),
+        WriteOp::ListBegin(ident) => o_prot.write_list_begin(&ident),
+        WriteOp::ListEnd => o_prot.write_list_end(),
+        WriteOp::SetBegin(ident) => o_prot.write_set_begin(&ident),
+        WriteOp::SetEnd => o_prot.write_set_end(),
+        WriteOp::MapBegin(ident) => o_prot.write_map_begin(&ident),
+        WriteOp::MapEnd => o_prot.write_map_end(),
+        WriteOp::Byte(b) => o_prot.write_byte(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::{TCompactOutputProtocol, TType};
+    use crate::transport::TBufferChannel;
+
+    fn write_i32_field(o_prot: &mut dyn TOutputProtocol, id: i16, value: i32) {
+        assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("f", TType::I32, id)));
+        assert_success!(o_prot.write_i32(value));
+        assert_success!(o_prot.write_field_end());
+    }
+
+    fn write_nested(o_prot: &mut dyn TOutputProtocol, outer_ids: &[i16], inner_ids: &[i16]) {
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("outer")));
+        for id in outer_ids {
+            if *id == 3 {
+                assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new(
+                    "inner",
+                    TType::Struct,
+                    3
+                )));
+                assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("inner")));
+                for inner_id in inner_ids {
+                    write_i32_field(o_prot, *inner_id, *inner_id as i32 * 10);
+                }
+                assert_success!(o_prot.write_field_stop());
+                assert_success!(o_prot.write_struct_end());
+                assert_success!(o_prot.write_field_end());
+            } else {
+                write_i32_field(o_prot, *id, *id as i32);
+            }
+        }
+        assert_success!(o_prot.write_field_stop());
+        assert_success!(o_prot.write_struct_end());
+    }
+
+    #[test]
+    fn must_write_fields_in_ascending_id_order() {
+        let expected_channel = TBufferChannel::with_capacity(0, 64);
+        let mut expected = TCompactOutputProtocol::new(expected_channel.clone());
+        write_nested(&mut expected, &[1, 2, 3, 17], &[1, 5]);
+
+        let actual_channel = TBufferChannel::with_capacity(0, 64);
+        let mut actual =
+            TFieldBufferingOutputProtocol::new(TCompactOutputProtocol::new(actual_channel.clone()));
+        write_nested(&mut actual, &[17, 3, 1, 2], &[5, 1]);
+
+        assert!(!expected_channel.write_bytes().is_empty());
+        assert_eq!(actual_channel.write_bytes(), expected_channel.write_bytes());
+    }
+
+    #[test]
+    fn must_not_write_struct_until_it_is_closed() {
+        let channel = TBufferChannel::with_capacity(0, 64);
+        let mut o_prot =
+            TFieldBufferingOutputProtocol::new(TCompactOutputProtocol::new(channel.clone()));
+
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
+        write_i32_field(&mut o_prot, 2, 2);
+        assert!(channel.write_bytes().is_empty());
+
+        assert_success!(o_prot.write_field_stop());
+        assert_success!(o_prot.write_struct_end());
+        assert!(!channel.write_bytes().is_empty());
+    }
+
+    #[test]
+    fn must_fail_if_value_is_written_outside_a_field() {
+        let channel = TBufferChannel::with_capacity(0, 64);
+        let mut o_prot = TFieldBufferingOutputProtocol::new(TCompactOutputProtocol::new(channel));
+
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
+        assert!(o_prot.write_i32(1).is_err());
+    }
+
+    #[test]
+    fn must_fail_if_struct_is_ended_with_open_field() {
+        let channel = TBufferChannel::with_capacity(0, 64);
+        let mut o_prot = TFieldBufferingOutputProtocol::new(TCompactOutputProtocol::new(channel));
+
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
+        assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("f", TType::I32, 1)));
+        assert!(o_prot.write_struct_end().is_err());
+    }
+}
@@ -16,23 +16,22 @@
 // under the License.
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use integer_encoding::VarIntWriter;
 use std::convert::{From, TryFrom};
 use std::io;
 
+use super::field_id_stack::FieldIdStack;
+use super::varint;
 use super::{
     TFieldIdentifier, TInputProtocol, TInputProtocolFactory, TListIdentifier, TMapIdentifier,
     TMessageIdentifier, TMessageType,
 };
 use super::{TOutputProtocol, TOutputProtocolFactory, TSetIdentifier, TStructIdentifier, TType};
 use crate::transport::{TReadTransport, TWriteTransport};
-use crate::{ProtocolError, ProtocolErrorKind, TConfiguration};
+use crate::{ProtocolError, ProtocolErrorKind, TCompactBoolEncoding, TConfiguration};
 
 const COMPACT_PROTOCOL_ID: u8 = 0x82;
 const COMPACT_VERSION: u8 = 0x01;
 const COMPACT_VERSION_MASK: u8 = 0x1F;
-const MAX_VARINT32_BYTES: usize = 5; // ceil(32/7); matches protobuf wire format
-const MAX_VARINT64_BYTES: usize = 10; // ceil(64/7); matches protobuf wire format
 
 /// Read messages encoded in the Thrift compact protocol.
 ///
@@ -60,7 +59,7 @@ where
     // Identifier of the last field deserialized for a struct.
     last_read_field_id: i16,
     // Stack of the last read field ids (a new entry is added each time a nested struct is read).
-    read_field_id_stack: Vec<i16>,
+    read_field_id_stack: FieldIdStack,
     // Boolean value for a field.
     // Saved because boolean fields and their value are encoded in a single byte,
     // and reading the field only occurs after the field id is read.
@@ -86,7 +85,7 @@ where
     pub fn with_config(transport: T, config: TConfiguration) -> TCompactInputProtocol<T> {
         TCompactInputProtocol {
             last_read_field_id: 0,
-            read_field_id_stack: Vec::new(),
+            read_field_id_stack: FieldIdStack::new(),
             pending_read_bool_value: None,
             transport,
             config,
@@ -125,37 +124,11 @@ where
     }
 
     fn read_varint32(&mut self) -> crate::Result<u32> {
-        let mut result = 0u32;
-        let mut shift = 0u32;
-        for _ in 0..MAX_VARINT32_BYTES {
-            let b = self.read_byte()?;
-            result |= ((b & 0x7F) as u32) << shift;
-            if b & 0x80 == 0 {
-                return Ok(result);
-            }
-            shift += 7;
-        }
-        Err(crate::Error::Protocol(ProtocolError::new(
-            ProtocolErrorKind::InvalidData,
-            "Variable-length int over 5 bytes.",
-        )))
+        varint::read_u32(|| self.read_byte())
     }
 
     fn read_varint64(&mut self) -> crate::Result<u64> {
-        let mut result = 0u64;
-        let mut shift = 0u32;
-        for _ in 0..MAX_VARINT64_BYTES {
-            let b = self.read_byte()?;
-            result |= ((b & 0x7F) as u64) << shift;
-            if b & 0x80 == 0 {
-                return Ok(result);
-            }
-            shift += 7;
-        }
-        Err(crate::Error::Protocol(ProtocolError::new(
-            ProtocolErrorKind::InvalidData,
-            "Variable-length int over 10 bytes.",
-        )))
+        varint::read_u64(|| self.read_byte())
     }
 }
 
@@ -217,11 +190,11 @@ where
     }
 
     fn read_struct_end(&mut self) -> crate::Result<()> {
-        self.recursion_depth -= 1;
         self.last_read_field_id = self
             .read_field_id_stack
             .pop()
-            .expect("should have previous field ids");
+            .ok_or_else(super::unbalanced_struct_end_error)?;
+        self.recursion_depth -= 1;
         Ok(())
     }
 
@@ -284,11 +257,12 @@ where
             Some(b) => Ok(b),
             None => {
                 let b = self.read_byte()?;
+                let strict = self.config.compact_bool_encoding() == TCompactBoolEncoding::Strict;
                 match b {
                     // Previous versions of the thrift compact protocol specification said to use 0
                     // and 1 inside collections, but that differed from existing implementations.
                     // The specification was updated in https://github.com/apache/thrift/commit/2c29c5665bc442e703480bb0ee60fe925ffe02e8.
-                    0x00 => Ok(false),
+                    0x00 if !strict => Ok(false),
                     0x01 => Ok(true),
                     0x02 => Ok(false),
                     unkn => Err(crate::Error::Protocol(crate::ProtocolError {
@@ -327,21 +301,20 @@ where
     }
 
     fn read_i16(&mut self) -> crate::Result<i16> {
-        Ok(zigzag_to_i32(self.read_varint32()?) as i16)
+        Ok(varint::zigzag_to_i32(self.read_varint32()?) as i16)
     }
 
     fn read_i32(&mut self) -> crate::Result<i32> {
-        Ok(zigzag_to_i32(self.read_varint32()?))
+        Ok(varint::zigzag_to_i32(self.read_varint32()?))
     }
 
     fn read_i64(&mut self) -> crate::Result<i64> {
-        Ok(zigzag_to_i64(self.read_varint64()?))
+        Ok(varint::zigzag_to_i64(self.read_varint64()?))
     }
 
     fn read_double(&mut self) -> crate::Result<f64> {
-        self.transport
-            .read_f64::<LittleEndian>()
-            .map_err(From::from)
+        let d = self.transport.read_f64::<LittleEndian>()?;
+        super::check_double(&self.config, d)
     }
 
     fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
@@ -354,7 +327,7 @@ where
 
     fn read_string(&mut self) -> crate::Result<String> {
         let bytes = self.read_bytes()?;
-        String::from_utf8(bytes).map_err(From::from)
+        super::decode_string(&self.config, bytes)
     }
 
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
@@ -397,6 +370,12 @@ where
         Ok(())
     }
 
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        let depth = super::capped_skip_depth(&self.config, depth);
+        let mut budget = super::SkipBudget::from_config(&self.config);
+        super::skip_with_budget(self, field_type, depth, &mut budget)
+    }
+
     // utility
     //
 
@@ -411,6 +390,10 @@ where
     fn min_serialized_size(&self, field_type: TType) -> usize {
         compact_protocol_min_serialized_size(field_type)
     }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        self.config = config.clone();
+    }
 }
 
 pub(crate) fn compact_protocol_min_serialized_size(field_type: TType) -> usize {
@@ -433,16 +416,6 @@ pub(crate) fn compact_protocol_min_serialized_size(field_type: TType) -> usize {
     }
 }
 
-#[inline]
-fn zigzag_to_i32(n: u32) -> i32 {
-    ((n >> 1) as i32) ^ (0i32.wrapping_sub((n & 1) as i32))
-}
-
-#[inline]
-fn zigzag_to_i64(n: u64) -> i64 {
-    ((n >> 1) as i64) ^ (0i64.wrapping_sub((n & 1) as i64))
-}
-
 impl<T> io::Seek for TCompactInputProtocol<T>
 where
     T: io::Seek + TReadTransport,
@@ -495,7 +468,7 @@ where
     // Identifier of the last field serialized for a struct.
     last_write_field_id: i16,
     // Stack of the last written field ids (new entry added each time a nested struct is written).
-    write_field_id_stack: Vec<i16>,
+    write_field_id_stack: FieldIdStack,
     // Field identifier of the boolean field to be written.
     // Saved because boolean fields and their value are encoded in a single byte
     pending_write_bool_field_identifier: Option<TFieldIdentifier>,
@@ -517,7 +490,7 @@ where
     pub fn with_config(transport: T, config: TConfiguration) -> TCompactOutputProtocol<T> {
         TCompactOutputProtocol {
             last_write_field_id: 0,
-            write_field_id_stack: Vec::new(),
+            write_field_id_stack: FieldIdStack::new(),
             pending_write_bool_field_identifier: None,
             transport,
             config,
@@ -537,6 +510,18 @@ where
         Ok(())
     }
 
+    fn write_varint32(&mut self, n: u32) -> crate::Result<()> {
+        let mut buf = [0u8; varint::MAX_VARINT32_BYTES];
+        let len = varint::encode_u32(n, &mut buf);
+        self.transport.write_all(&buf[..len]).map_err(From::from)
+    }
+
+    fn write_varint64(&mut self, n: u64) -> crate::Result<()> {
+        let mut buf = [0u8; varint::MAX_VARINT64_BYTES];
+        let len = varint::encode_u64(n, &mut buf);
+        self.transport.write_all(&buf[..len]).map_err(From::from)
+    }
+
     // FIXME: field_type as unconstrained u8 is bad
     fn write_field_header(&mut self, field_type: u8, field_id: i16) -> crate::Result<()> {
         let field_delta = field_id - self.last_write_field_id;
@@ -555,7 +540,7 @@ where
         element_type: TType,
         element_count: i32,
     ) -> crate::Result<()> {
-        let elem_identifier = collection_type_to_u8(element_type);
+        let elem_identifier = collection_type_to_u8(element_type)?;
         if element_count <= 14 {
             let header = ((element_count as u8) << 4) | elem_identifier;
             self.write_byte(header)
@@ -564,16 +549,17 @@ where
             self.write_byte(header)?;
             // element count is strictly positive as per the spec, so
             // cast i32 as u32 so that varint writing won't use zigzag encoding
-            self.transport
-                .write_varint(element_count as u32)
-                .map_err(From::from)
-                .map(|_| ())
+            self.write_varint32(element_count as u32)
         }
     }
 
-    fn assert_no_pending_bool_write(&self) {
-        if let Some(ref f) = self.pending_write_bool_field_identifier {
-            panic!("pending bool field {:?} not written", f)
+    fn check_no_pending_bool_write(&self) -> crate::Result<()> {
+        match self.pending_write_bool_field_identifier {
+            Some(ref f) => Err(invalid_state_error(format!(
+                "pending bool field {:?} not written",
+                f
+            ))),
+            None => Ok(()),
         }
     }
 }
@@ -586,15 +572,13 @@ where
         self.write_byte(COMPACT_PROTOCOL_ID)?;
         self.write_byte((u8::from(identifier.message_type) << 5) | COMPACT_VERSION)?;
         // cast i32 as u32 so that varint writing won't use zigzag encoding
-        self.transport
-            .write_varint(identifier.sequence_number as u32)?;
+        self.write_varint32(identifier.sequence_number as u32)?;
         self.write_string(&identifier.name)?;
         Ok(())
     }
 
     fn write_message_end(&mut self) -> crate::Result<()> {
-        self.assert_no_pending_bool_write();
-        Ok(())
+        self.check_no_pending_bool_write()
     }
 
     fn write_struct_begin(&mut self, _: &TStructIdentifier) -> crate::Result<()> {
@@ -606,11 +590,11 @@ where
     }
 
     fn write_struct_end(&mut self) -> crate::Result<()> {
-        self.assert_no_pending_bool_write();
+        self.check_no_pending_bool_write()?;
         self.last_write_field_id = self
             .write_field_id_stack
             .pop()
-            .expect("should have previous field ids");
+            .ok_or_else(super::unbalanced_struct_end_error)?;
         self.recursion_depth -= 1;
         Ok(())
     }
@@ -619,43 +603,51 @@ where
         match identifier.field_type {
             TType::Bool => {
                 if self.pending_write_bool_field_identifier.is_some() {
-                    panic!(
-                        "should not have a pending bool while writing another bool with id: \
-                         {:?}",
+                    return Err(invalid_state_error(format!(
+                        "should not have a pending bool while writing another bool with id: {:?}",
                         identifier
-                    )
+                    )));
                 }
                 self.pending_write_bool_field_identifier = Some(identifier.clone());
                 Ok(())
             }
             _ => {
-                let field_type = type_to_u8(identifier.field_type);
-                let field_id = identifier.id.expect("non-stop field should have field id");
+                self.check_no_pending_bool_write()?;
+                let field_type = type_to_u8(identifier.field_type)?;
+                let field_id = identifier.id.ok_or_else(|| {
+                    invalid_state_error(format!(
+                        "non-stop field {:?} should have field id",
+                        identifier
+                    ))
+                })?;
                 self.write_field_header(field_type, field_id)
             }
         }
     }
 
     fn write_field_end(&mut self) -> crate::Result<()> {
-        self.assert_no_pending_bool_write();
-        Ok(())
+        self.check_no_pending_bool_write()
     }
 
     fn write_field_stop(&mut self) -> crate::Result<()> {
-        self.assert_no_pending_bool_write();
-        self.write_byte(type_to_u8(TType::Stop))
+        self.check_no_pending_bool_write()?;
+        self.write_byte(type_to_u8(TType::Stop)?)
     }
 
     fn write_bool(&mut self, b: bool) -> crate::Result<()> {
         match self.pending_write_bool_field_identifier.take() {
             Some(pending) => {
-                let field_id = pending.id.expect("bool field should have a field id");
+                let field_id = pending.id.ok_or_else(|| {
+                    invalid_state_error(format!("bool field {:?} should have field id", pending))
+                })?;
                 let field_type_as_u8 = if b { 0x01 } else { 0x02 };
                 self.write_field_header(field_type_as_u8, field_id)
             }
             None => {
                 if b {
                     self.write_byte(0x01)
+                } else if self.config.compact_bool_encoding() == TCompactBoolEncoding::Legacy {
+                    self.write_byte(0x00)
                 } else {
                     self.write_byte(0x02)
                 }
@@ -666,8 +658,10 @@ where
     fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
         // length is strictly positive as per the spec, so
         // cast i32 as u32 so that varint writing won't use zigzag encoding
-        self.transport.write_varint(b.len() as u32)?;
-        self.transport.write_all(b).map_err(From::from)
+        let mut header = [0u8; varint::MAX_VARINT32_BYTES];
+        let len = varint::encode_u32(b.len() as u32, &mut header);
+        TWriteTransport::write_all_vectored(&mut self.transport, &[&header[..len], b])
+            .map_err(From::from)
     }
 
     fn write_i8(&mut self, i: i8) -> crate::Result<()> {
@@ -675,27 +669,19 @@ where
     }
 
     fn write_i16(&mut self, i: i16) -> crate::Result<()> {
-        self.transport
-            .write_varint(i)
-            .map_err(From::from)
-            .map(|_| ())
+        self.write_varint32(varint::i32_to_zigzag(i as i32))
     }
 
     fn write_i32(&mut self, i: i32) -> crate::Result<()> {
-        self.transport
-            .write_varint(i)
-            .map_err(From::from)
-            .map(|_| ())
+        self.write_varint32(varint::i32_to_zigzag(i))
     }
 
     fn write_i64(&mut self, i: i64) -> crate::Result<()> {
-        self.transport
-            .write_varint(i)
-            .map_err(From::from)
-            .map(|_| ())
+        self.write_varint64(varint::i64_to_zigzag(i))
     }
 
     fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        let d = super::check_double(&self.config, d)?;
         self.transport
             .write_f64::<LittleEndian>(d)
             .map_err(From::from)
@@ -733,17 +719,17 @@ where
         } else {
             // element count is strictly positive as per the spec, so
             // cast i32 as u32 so that varint writing won't use zigzag encoding
-            self.transport.write_varint(identifier.size as u32)?;
+            self.write_varint32(identifier.size as u32)?;
 
-            let key_type = identifier
-                .key_type
-                .expect("map identifier to write should contain key type");
-            let key_type_byte = collection_type_to_u8(key_type) << 4;
+            let key_type = identifier.key_type.ok_or_else(|| {
+                invalid_state_error("map identifier to write should contain key type")
+            })?;
+            let key_type_byte = collection_type_to_u8(key_type)? << 4;
 
-            let val_type = identifier
-                .value_type
-                .expect("map identifier to write should contain value type");
-            let val_type_byte = collection_type_to_u8(val_type);
+            let val_type = identifier.value_type.ok_or_else(|| {
+                invalid_state_error("map identifier to write should contain value type")
+            })?;
+            let val_type_byte = collection_type_to_u8(val_type)?;
 
             let map_type_header = key_type_byte | val_type_byte;
             self.write_byte(map_type_header)
@@ -766,6 +752,15 @@ where
     }
 }
 
+impl<T> io::Seek for TCompactOutputProtocol<T>
+where
+    T: io::Seek + TWriteTransport,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.transport.seek(pos)
+    }
+}
+
 /// Factory for creating instances of `TCompactOutputProtocol`.
 #[derive(Default)]
 pub struct TCompactOutputProtocolFactory;
@@ -786,15 +781,19 @@ impl TOutputProtocolFactory for TCompactOutputProtocolFactory {
     }
 }
 
-fn collection_type_to_u8(field_type: TType) -> u8 {
+fn invalid_state_error<S: Into<String>>(message: S) -> crate::Error {
+    crate::Error::Protocol(ProtocolError::new(ProtocolErrorKind::InvalidState, message))
+}
+
+fn collection_type_to_u8(field_type: TType) -> crate::Result<u8> {
     match field_type {
-        TType::Bool => 0x01,
+        TType::Bool => Ok(0x01),
         f => type_to_u8(f),
     }
 }
 
-fn type_to_u8(field_type: TType) -> u8 {
-    match field_type {
+fn type_to_u8(field_type: TType) -> crate::Result<u8> {
+    let b = match field_type {
         TType::Stop => 0x00,
         TType::I08 => 0x03, // equivalent to TType::Byte
         TType::I16 => 0x04,
@@ -807,8 +806,14 @@ fn type_to_u8(field_type: TType) -> u8 {
         TType::Map => 0x0B,
         TType::Struct => 0x0C,
         TType::Uuid => 0x0D,
-        _ => panic!("should not have attempted to convert {} to u8", field_type),
-    }
+        _ => {
+            return Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidData,
+                format!("cannot convert {} to u8", field_type),
+            )))
+        }
+    };
+    Ok(b)
 }
 
 fn collection_u8_to_type(b: u8) -> crate::Result<TType> {
@@ -2677,37 +2682,80 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn must_fail_if_write_field_end_without_writing_bool_value() {
         let (_, mut o_prot) = test_objects();
         assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
         assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("foo", TType::Bool, 1)));
-        o_prot.write_field_end().unwrap();
+        assert_invalid_state(o_prot.write_field_end());
     }
 
     #[test]
-    #[should_panic]
     fn must_fail_if_write_stop_field_without_writing_bool_value() {
         let (_, mut o_prot) = test_objects();
         assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
         assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("foo", TType::Bool, 1)));
-        o_prot.write_field_stop().unwrap();
+        assert_invalid_state(o_prot.write_field_stop());
     }
 
     #[test]
-    #[should_panic]
     fn must_fail_if_write_struct_end_without_writing_bool_value() {
         let (_, mut o_prot) = test_objects();
         assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
         assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("foo", TType::Bool, 1)));
-        o_prot.write_struct_end().unwrap();
+        assert_invalid_state(o_prot.write_struct_end());
     }
 
     #[test]
-    #[should_panic]
     fn must_fail_if_write_struct_end_without_any_fields() {
         let (_, mut o_prot) = test_objects();
-        o_prot.write_struct_end().unwrap();
+        assert_invalid_state(o_prot.write_struct_end());
+    }
+
+    #[test]
+    fn must_fail_if_write_bool_field_begin_with_bool_pending() {
+        let (_, mut o_prot) = test_objects();
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
+        assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("foo", TType::Bool, 1)));
+        assert_invalid_state(o_prot.write_field_begin(&TFieldIdentifier::new(
+            "bar",
+            TType::Bool,
+            2,
+        )));
+    }
+
+    #[test]
+    fn must_fail_if_write_field_begin_without_field_id() {
+        let (_, mut o_prot) = test_objects();
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
+        assert_invalid_state(o_prot.write_field_begin(&TFieldIdentifier {
+            name: None,
+            field_type: TType::I32,
+            id: None,
+        }));
+    }
+
+    #[test]
+    fn must_fail_if_write_map_begin_without_key_or_value_type() {
+        let (_, mut o_prot) = test_objects();
+        assert_invalid_state(o_prot.write_map_begin(&TMapIdentifier::new(None, TType::I32, 1)));
+        assert_invalid_state(o_prot.write_map_begin(&TMapIdentifier::new(TType::I32, None, 1)));
+    }
+
+    #[test]
+    fn must_fail_if_writing_field_of_unsupported_type() {
+        let (_, mut o_prot) = test_objects();
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("foo")));
+        match o_prot.write_field_begin(&TFieldIdentifier::new("foo", TType::Void, 1)) {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected invalid data error, got {:?}", other),
+        }
+    }
+
+    fn assert_invalid_state(result: crate::Result<()>) {
+        match result {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidState),
+            other => panic!("expected invalid state error, got {:?}", other),
+        }
     }
 
     #[test]
@@ -3340,4 +3388,186 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "hello");
     }
+
+    #[test]
+    fn must_seek_output_within_seekable_transport() {
+        use std::io::{Cursor, Seek, SeekFrom};
+
+        let mut o_prot = TCompactOutputProtocol::new(Cursor::new(Vec::new()));
+        assert_success!(o_prot.write_i32(1));
+        assert_success!(o_prot.write_i32(2));
+
+        // overwrite the first record in place
+        assert_eq!(o_prot.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert_success!(o_prot.write_i32(-1));
+        assert_eq!(o_prot.seek(SeekFrom::End(0)).unwrap(), 2);
+
+        let buf = o_prot.transport.into_inner();
+        assert_eq!(buf, vec![0x01, 0x04]);
+    }
+
+    #[test]
+    fn must_fail_if_read_struct_end_without_struct_begin() {
+        let (mut i_prot, _) = test_objects();
+        match i_prot.read_struct_end() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidState),
+            other => panic!("expected invalid state error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_fail_if_read_struct_end_called_more_often_than_struct_begin() {
+        let (mut i_prot, _) = test_objects();
+        assert_success!(i_prot.read_struct_begin());
+        assert_success!(i_prot.read_struct_begin());
+        assert_success!(i_prot.read_struct_end());
+        assert_success!(i_prot.read_struct_end());
+        assert!(i_prot.read_struct_end().is_err());
+
+        // the protocol is still usable after the failed call
+        assert_success!(i_prot.read_struct_begin());
+        assert_success!(i_prot.read_struct_end());
+    }
+
+    #[test]
+    fn must_apply_non_finite_double_policy() {
+        let reject = TConfiguration::builder()
+            .non_finite_double_policy(crate::TNonFiniteDoublePolicy::Reject)
+            .build()
+            .unwrap();
+        let normalize = TConfiguration::builder()
+            .non_finite_double_policy(crate::TNonFiniteDoublePolicy::Normalize)
+            .build()
+            .unwrap();
+
+        let mut buf = Vec::new();
+        {
+            let mut o_prot = TCompactOutputProtocol::with_config(&mut buf, reject.clone());
+            match o_prot.write_double(f64::NAN) {
+                Err(crate::Error::Protocol(e)) => {
+                    assert_eq!(e.kind, ProtocolErrorKind::InvalidData)
+                }
+                other => panic!("expected protocol error, got {:?}", other),
+            }
+        }
+        assert!(buf.is_empty());
+
+        {
+            let mut o_prot = TCompactOutputProtocol::with_config(&mut buf, normalize);
+            o_prot.write_double(f64::NEG_INFINITY).unwrap();
+        }
+        assert_eq!(buf, f64::MIN.to_le_bytes());
+
+        let inf = f64::INFINITY.to_le_bytes();
+        let mut i_prot = TCompactInputProtocol::with_config(&inf[..], reject);
+        match i_prot.read_double() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+    }
+
+    fn bool_encoding_config(encoding: TCompactBoolEncoding) -> TConfiguration {
+        TConfiguration::builder()
+            .compact_bool_encoding(encoding)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn must_write_legacy_collection_bools_when_configured() {
+        let mut buf = Vec::new();
+        {
+            let config = bool_encoding_config(TCompactBoolEncoding::Legacy);
+            let mut o_prot = TCompactOutputProtocol::with_config(&mut buf, config);
+            o_prot
+                .write_list_begin(&TListIdentifier::new(TType::Bool, 2))
+                .unwrap();
+            o_prot.write_bool(true).unwrap();
+            o_prot.write_bool(false).unwrap();
+            o_prot.write_list_end().unwrap();
+
+            // field bools are encoded in the field type and are unaffected
+            o_prot
+                .write_field_begin(&TFieldIdentifier::new("b", TType::Bool, 1))
+                .unwrap();
+            o_prot.write_bool(false).unwrap();
+            o_prot.write_field_end().unwrap();
+        }
+        assert_eq!(buf, vec![0x21, 0x01, 0x00, 0x12]);
+
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+        assert_eq!(
+            i_prot.read_list_begin().unwrap(),
+            TListIdentifier::new(TType::Bool, 2)
+        );
+        assert!(i_prot.read_bool().unwrap());
+        assert!(!i_prot.read_bool().unwrap());
+    }
+
+    #[test]
+    fn must_reject_legacy_collection_bools_in_strict_mode() {
+        let buf = [0x21, 0x01, 0x00];
+
+        let config = bool_encoding_config(TCompactBoolEncoding::Strict);
+        let mut i_prot = TCompactInputProtocol::with_config(&buf[..], config);
+        i_prot.read_list_begin().unwrap();
+        assert!(i_prot.read_bool().unwrap());
+        match i_prot.read_bool() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_limit_bytes_consumed_by_skip() {
+        // list<string> declaring 14 elements, of which only 2 are present
+        let buf = [0x28 | 0xE0, 0x03, b'a', b'b', b'c', 0x03, b'd', b'e', b'f'];
+
+        let config = TConfiguration::builder()
+            .max_skip_bytes(Some(8))
+            .build()
+            .unwrap();
+        let mut i_prot = TCompactInputProtocol::with_config(&buf[..], config);
+        // rejected from the declared size before any element is read
+        match i_prot.skip(TType::List) {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::SizeLimit),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+        assert_eq!(i_prot.read_byte().unwrap(), 0x03);
+
+        // a list<string> of 2 elements: 1 + (1 + 3) * 2 = 9 bytes
+        let buf = [0x28, 0x03, b'a', b'b', b'c', 0x03, b'd', b'e', b'f'];
+        for (limit, ok) in [(8, false), (9, true)] {
+            let config = TConfiguration::builder()
+                .max_skip_bytes(Some(limit))
+                .build()
+                .unwrap();
+            let mut i_prot: Box<dyn TInputProtocol> =
+                Box::new(TCompactInputProtocol::with_config(&buf[..], config));
+            assert_eq!(i_prot.skip(TType::List).is_ok(), ok, "limit {}", limit);
+        }
+    }
+
+    #[test]
+    fn must_limit_depth_of_skip() {
+        // struct { 1: struct { 1: struct {} } }
+        let buf = [0x1C, 0x1C, 0x00, 0x00, 0x00];
+
+        let config = TConfiguration::builder()
+            .max_skip_depth(Some(2))
+            .build()
+            .unwrap();
+        let mut i_prot = TCompactInputProtocol::with_config(&buf[..], config);
+        match i_prot.skip(TType::Struct) {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::DepthLimit),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+
+        let config = TConfiguration::builder()
+            .max_skip_depth(Some(3))
+            .build()
+            .unwrap();
+        let mut i_prot = TCompactInputProtocol::with_config(&buf[..], config);
+        assert!(i_prot.skip(TType::Struct).is_ok());
+    }
 }
@@ -0,0 +1,304 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use super::{
+    TInputProtocol, TMapIdentifier, TMessageIdentifier, TOutputProtocol, TStructIdentifier, TType,
+};
+use crate::{new_protocol_error, ProtocolErrorKind};
+
+/// Deepest nesting of containers and structs copied between protocols.
+pub(crate) const MAXIMUM_COPY_DEPTH: i8 = 64;
+
+/// Copy one complete message from `i_prot` to `o_prot`, then flush
+/// `o_prot`.
+///
+/// The message is copied value by value as it is read, so no generated code
+/// is needed, the message is never held in memory as a whole, and the two
+/// protocols may differ, e.g. to translate calls between binary and compact
+/// clients and servers. Fails if values are nested more than 64 levels deep.
+/// Returns the identifier of the copied message.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::protocol::{transcode, TBinaryInputProtocol, TCompactOutputProtocol};
+///
+/// # let mut binary = Vec::new();
+/// # {
+/// #     use thrift::protocol::{
+/// #         TBinaryOutputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol,
+/// #         TStructIdentifier,
+/// #     };
+/// #     let mut o_prot = TBinaryOutputProtocol::new(&mut binary, true);
+/// #     o_prot.write_message_begin(&TMessageIdentifier::new("ping", TMessageType::Call, 1)).unwrap();
+/// #     o_prot.write_struct_begin(&TStructIdentifier::new("ping_args")).unwrap();
+/// #     o_prot.write_field_stop().unwrap();
+/// #     o_prot.write_struct_end().unwrap();
+/// #     o_prot.write_message_end().unwrap();
+/// # }
+/// let mut compact = Vec::new();
+/// let ident = transcode(
+///     &mut TBinaryInputProtocol::new(&binary[..], true),
+///     &mut TCompactOutputProtocol::new(&mut compact),
+/// )
+/// .unwrap();
+/// assert_eq!(ident.name, "ping");
+/// ```
+pub fn transcode(
+    i_prot: &mut dyn TInputProtocol,
+    o_prot: &mut dyn TOutputProtocol,
+) -> crate::Result<TMessageIdentifier> {
+    let ident = i_prot.read_message_begin()?;
+    o_prot.write_message_begin(&ident)?;
+    copy_value(i_prot, o_prot, TType::Struct, MAXIMUM_COPY_DEPTH)?;
+    i_prot.read_message_end()?;
+    o_prot.write_message_end()?;
+    o_prot.flush()?;
+    Ok(ident)
+}
+
+/// Copy one value of type `field_type` from `i` to `o`.
+pub(crate) fn copy_value(
+    i: &mut dyn TInputProtocol,
+    o: &mut dyn TOutputProtocol,
+    field_type: TType,
+    depth: i8,
+) -> crate::Result<()> {
+    if depth == 0 {
+        return Err(new_protocol_error(
+            ProtocolErrorKind::DepthLimit,
+            format!("cannot copy past {:?}", field_type),
+        ));
+    }
+
+    match field_type {
+        TType::Bool => o.write_bool(i.read_bool()?),
+        TType::I08 => o.write_i8(i.read_i8()?),
+        TType::I16 => o.write_i16(i.read_i16()?),
+        TType::I32 => o.write_i32(i.read_i32()?),
+        TType::I64 => o.write_i64(i.read_i64()?),
+        TType::Double => o.write_double(i.read_double()?),
+        TType::String => o.write_bytes(&i.read_bytes()?),
+        TType::Uuid => o.write_uuid(&i.read_uuid()?),
+        TType::Struct => {
+            i.read_struct_begin()?;
+            o.write_struct_begin(&TStructIdentifier::new("args"))?;
+            loop {
+                let field_ident = i.read_field_begin()?;
+                if field_ident.field_type == TType::Stop {
+                    break;
+                }
+                o.write_field_begin(&field_ident)?;
+                copy_value(i, o, field_ident.field_type, depth - 1)?;
+                i.read_field_end()?;
+                o.write_field_end()?;
+            }
+            i.read_struct_end()?;
+            o.write_field_stop()?;
+            o.write_struct_end()
+        }
+        TType::List => {
+            let list_ident = i.read_list_begin()?;
+            o.write_list_begin(&list_ident)?;
+            for _ in 0..list_ident.size {
+                copy_value(i, o, list_ident.element_type, depth - 1)?;
+            }
+            i.read_list_end()?;
+            o.write_list_end()
+        }
+        TType::Set => {
+            let set_ident = i.read_set_begin()?;
+            o.write_set_begin(&set_ident)?;
+            for _ in 0..set_ident.size {
+                copy_value(i, o, set_ident.element_type, depth - 1)?;
+            }
+            i.read_set_end()?;
+            o.write_set_end()
+        }
+        TType::Map => {
+            let map_ident = i.read_map_begin()?;
+            // the compact protocol does not record the key and value types of
+            // an empty map, but the binary protocol cannot write one without
+            let key_type = map_ident.key_type.unwrap_or(TType::I08);
+            let value_type = map_ident.value_type.unwrap_or(TType::I08);
+            o.write_map_begin(&TMapIdentifier::new(key_type, value_type, map_ident.size))?;
+            for _ in 0..map_ident.size {
+                copy_value(i, o, key_type, depth - 1)?;
+                copy_value(i, o, value_type, depth - 1)?;
+            }
+            i.read_map_end()?;
+            o.write_map_end()
+        }
+        u => Err(new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            format!("cannot copy field type {:?}", u),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol,
+        TFieldIdentifier, TListIdentifier, TMessageType,
+    };
+
+    #[test]
+    fn must_copy_nested_values() {
+        let mut original = TBinaryOutputProtocol::new(Vec::new(), true);
+        original
+            .write_struct_begin(&TStructIdentifier::new("args"))
+            .unwrap();
+        original
+            .write_field_begin(&TFieldIdentifier::new("names", TType::List, 1))
+            .unwrap();
+        original
+            .write_list_begin(&TListIdentifier::new(TType::String, 2))
+            .unwrap();
+        original.write_string("a").unwrap();
+        original.write_string("b").unwrap();
+        original.write_list_end().unwrap();
+        original.write_field_end().unwrap();
+        original
+            .write_field_begin(&TFieldIdentifier::new("counts", TType::Map, 2))
+            .unwrap();
+        original
+            .write_map_begin(&TMapIdentifier::new(TType::I16, TType::Double, 1))
+            .unwrap();
+        original.write_i16(7).unwrap();
+        original.write_double(1.5).unwrap();
+        original.write_map_end().unwrap();
+        original.write_field_end().unwrap();
+        original.write_field_stop().unwrap();
+        original.write_struct_end().unwrap();
+        let original = original.transport;
+
+        let mut i_prot = TBinaryInputProtocol::new(&original[..], true);
+        let mut copy = TBinaryOutputProtocol::new(Vec::new(), true);
+        copy_value(&mut i_prot, &mut copy, TType::Struct, MAXIMUM_COPY_DEPTH).unwrap();
+        assert_eq!(copy.transport, original);
+    }
+
+    /// Write a message whose body is a struct holding `field` as field 1.
+    fn write_message(
+        o_prot: &mut dyn TOutputProtocol,
+        ident: &TMessageIdentifier,
+        field_type: TType,
+        field: impl FnOnce(&mut dyn TOutputProtocol),
+    ) {
+        o_prot.write_message_begin(ident).unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("args"))
+            .unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("field", field_type, 1))
+            .unwrap();
+        field(o_prot);
+        o_prot.write_field_end().unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+    }
+
+    fn write_names(o_prot: &mut dyn TOutputProtocol) {
+        o_prot
+            .write_list_begin(&TListIdentifier::new(TType::String, 2))
+            .unwrap();
+        o_prot.write_string("a").unwrap();
+        o_prot.write_string("b").unwrap();
+        o_prot.write_list_end().unwrap();
+    }
+
+    #[test]
+    fn must_transcode_message_between_protocols() {
+        let ident = TMessageIdentifier::new("order", TMessageType::Reply, 12);
+        let mut binary = Vec::new();
+        write_message(
+            &mut TBinaryOutputProtocol::new(&mut binary, true),
+            &ident,
+            TType::List,
+            write_names,
+        );
+        let mut expected = Vec::new();
+        write_message(
+            &mut TCompactOutputProtocol::new(&mut expected),
+            &ident,
+            TType::List,
+            write_names,
+        );
+
+        let mut compact = Vec::new();
+        let copied = assert_success!(transcode(
+            &mut TBinaryInputProtocol::new(&binary[..], true),
+            &mut TCompactOutputProtocol::new(&mut compact),
+        ));
+        assert_eq!(copied, ident);
+        assert_eq!(compact, expected);
+    }
+
+    #[test]
+    fn must_transcode_empty_map_from_compact_to_binary() {
+        let ident = TMessageIdentifier::new("lookup", TMessageType::Call, 1);
+        let empty_map = |o_prot: &mut dyn TOutputProtocol| {
+            o_prot
+                .write_map_begin(&TMapIdentifier::new(TType::String, TType::I32, 0))
+                .unwrap();
+            o_prot.write_map_end().unwrap();
+        };
+        let mut compact = Vec::new();
+        write_message(
+            &mut TCompactOutputProtocol::new(&mut compact),
+            &ident,
+            TType::Map,
+            empty_map,
+        );
+
+        let mut binary = Vec::new();
+        assert_success!(transcode(
+            &mut TCompactInputProtocol::new(&compact[..]),
+            &mut TBinaryOutputProtocol::new(&mut binary, true),
+        ));
+
+        let mut i_prot = TBinaryInputProtocol::new(&binary[..], true);
+        assert_success!(i_prot.read_message_begin());
+        assert_success!(i_prot.read_struct_begin());
+        assert_eq!(assert_success!(i_prot.read_field_begin()).id, Some(1));
+        assert_eq!(assert_success!(i_prot.read_map_begin()).size, 0);
+    }
+
+    #[test]
+    fn must_not_copy_values_nested_too_deeply() {
+        let mut nested = Vec::new();
+        {
+            let mut o_prot = TBinaryOutputProtocol::new(&mut nested, true);
+            for _ in 0..MAXIMUM_COPY_DEPTH {
+                o_prot
+                    .write_list_begin(&TListIdentifier::new(TType::List, 1))
+                    .unwrap();
+            }
+        }
+
+        let mut i_prot = TBinaryInputProtocol::new(&nested[..], true);
+        let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+        match copy_value(&mut i_prot, &mut o_prot, TType::List, MAXIMUM_COPY_DEPTH) {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::DepthLimit),
+            other => panic!("expected a depth limit error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,663 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+
+use super::{
+    TDynamicInputProtocol, TDynamicOutputProtocol, TFieldIdentifier, TInputProtocol,
+    TInputProtocolFactory, TListIdentifier, TMapIdentifier, TMessageIdentifier, TOutputProtocol,
+    TOutputProtocolFactory, TProtocolKind, TRawString, TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::transport::{TReadTransport, TWriteTransport};
+use crate::{new_protocol_error, ProtocolErrorKind, TConfiguration};
+
+type Matcher = dyn Fn(u8) -> bool + Send + Sync;
+
+/// One protocol a `TProtocolDetector` can pick.
+enum Candidate {
+    BuiltIn(TProtocolKind),
+    Custom {
+        matches: Box<Matcher>,
+        input: Box<dyn TInputProtocolFactory + Send + Sync>,
+        output: Box<dyn TOutputProtocolFactory + Send + Sync>,
+    },
+}
+
+impl Candidate {
+    fn matches(&self, first_byte: u8) -> bool {
+        match self {
+            // the strict binary protocol starts with the high byte of its
+            // version, the compact protocol with its protocol id
+            Candidate::BuiltIn(TProtocolKind::Binary) => first_byte == 0x80,
+            Candidate::BuiltIn(TProtocolKind::Compact) => first_byte == 0x82,
+            Candidate::Custom { matches, .. } => matches(first_byte),
+        }
+    }
+}
+
+/// Chooses the protocol of each connection from the first byte the client
+/// sends.
+///
+/// A server configured with a detector accepts clients that speak any of
+/// its protocols on one port, so a fleet of clients can be moved from one
+/// protocol to another without running the server twice. Protocols are
+/// tried in the order they were added. Replies are written in the protocol
+/// the client's first message was read in. A connection whose first byte
+/// matches no protocol fails with `ProtocolErrorKind::InvalidData`.
+///
+/// The built-in binary (strict mode only) and compact protocols are
+/// recognised by their headers. Other protocols are added with
+/// `with_factories` and a test on the first byte of their messages.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use thrift::protocol::{TProtocolDetector, TProtocolKind};
+///
+/// let detector = TProtocolDetector::new()
+///     .with_protocol(TProtocolKind::Binary)
+///     .with_protocol(TProtocolKind::Compact);
+/// let detector = Arc::new(detector);
+/// ```
+pub struct TProtocolDetector {
+    candidates: Vec<Candidate>,
+    config: TConfiguration,
+}
+
+impl TProtocolDetector {
+    /// Create a `TProtocolDetector` that does not recognise any protocol
+    /// yet.
+    pub fn new() -> TProtocolDetector {
+        TProtocolDetector {
+            candidates: Vec::new(),
+            config: TConfiguration::default(),
+        }
+    }
+
+    /// Accept clients that speak the built-in protocol `kind`.
+    pub fn with_protocol(mut self, kind: TProtocolKind) -> TProtocolDetector {
+        self.candidates.push(Candidate::BuiltIn(kind));
+        self
+    }
+
+    /// Accept clients whose messages start with a byte for which `matches`
+    /// returns `true`, reading and writing them with the protocols created
+    /// by `input_factory` and `output_factory`.
+    pub fn with_factories<F, IPF, OPF>(
+        mut self,
+        matches: F,
+        input_factory: IPF,
+        output_factory: OPF,
+    ) -> TProtocolDetector
+    where
+        F: Fn(u8) -> bool + Send + Sync + 'static,
+        IPF: TInputProtocolFactory + Send + Sync + 'static,
+        OPF: TOutputProtocolFactory + Send + Sync + 'static,
+    {
+        self.candidates.push(Candidate::Custom {
+            matches: Box::new(matches),
+            input: Box::new(input_factory),
+            output: Box::new(output_factory),
+        });
+        self
+    }
+
+    /// Enforce the limits in `config` in the built-in protocols.
+    pub fn with_configuration(mut self, config: TConfiguration) -> TProtocolDetector {
+        self.config = config;
+        self
+    }
+
+    /// Create an input protocol that reads from `transport` in whichever
+    /// protocol the client turns out to speak, and the selection that
+    /// creates the matching output protocols.
+    ///
+    /// Nothing is read from `transport` until the input protocol is first
+    /// used.
+    pub fn detect(
+        self: &Arc<Self>,
+        transport: Box<dyn TReadTransport + Send>,
+    ) -> (Box<dyn TInputProtocol + Send>, TProtocolSelection) {
+        let selection = TProtocolSelection {
+            detector: self.clone(),
+            chosen: Arc::new(Mutex::new(None)),
+        };
+        let i_prot = TDetectingInputProtocol {
+            state: InputState::Pending(Some(transport)),
+            selection: selection.clone(),
+            config: None,
+        };
+        (Box::new(i_prot), selection)
+    }
+
+    fn input_protocol(
+        &self,
+        candidate: usize,
+        transport: Box<dyn TReadTransport + Send>,
+    ) -> Box<dyn TInputProtocol + Send> {
+        match self.candidates[candidate] {
+            Candidate::BuiltIn(kind) => Box::new(TDynamicInputProtocol::with_config(
+                kind,
+                transport,
+                self.config.clone(),
+            )),
+            Candidate::Custom { ref input, .. } => input.create(transport),
+        }
+    }
+
+    fn output_protocol(
+        &self,
+        candidate: usize,
+        transport: Box<dyn TWriteTransport + Send>,
+    ) -> Box<dyn TOutputProtocol + Send> {
+        match self.candidates[candidate] {
+            Candidate::BuiltIn(kind) => Box::new(TDynamicOutputProtocol::with_config(
+                kind,
+                transport,
+                self.config.clone(),
+            )),
+            Candidate::Custom { ref output, .. } => output.create(transport),
+        }
+    }
+}
+
+impl Default for TProtocolDetector {
+    fn default() -> Self {
+        TProtocolDetector::new()
+    }
+}
+
+impl fmt::Debug for TProtocolDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+        for candidate in &self.candidates {
+            match candidate {
+                Candidate::BuiltIn(kind) => list.entry(kind),
+                Candidate::Custom { .. } => list.entry(&"Custom"),
+            };
+        }
+        list.finish()
+    }
+}
+
+/// The protocol a `TProtocolDetector` chose for one connection.
+#[derive(Clone)]
+pub struct TProtocolSelection {
+    detector: Arc<TProtocolDetector>,
+    chosen: Arc<Mutex<Option<usize>>>,
+}
+
+impl TProtocolSelection {
+    /// Create an output protocol that writes to `transport` in the protocol
+    /// chosen for the connection.
+    ///
+    /// The protocol is picked when the first message is written, so this
+    /// can be called before the client has sent anything. A message written
+    /// before the client's first message has been read is written in the
+    /// detector's first protocol.
+    pub fn output_protocol(
+        &self,
+        transport: Box<dyn TWriteTransport + Send>,
+    ) -> Box<dyn TOutputProtocol + Send> {
+        Box::new(TDetectingOutputProtocol {
+            state: OutputState::Pending(Some(transport)),
+            selection: self.clone(),
+        })
+    }
+
+    /// Built-in protocol chosen for the connection, if one has been chosen
+    /// and it is a built-in one.
+    pub fn protocol_kind(&self) -> Option<TProtocolKind> {
+        match self.chosen().map(|c| &self.detector.candidates[c]) {
+            Some(Candidate::BuiltIn(kind)) => Some(*kind),
+            _ => None,
+        }
+    }
+
+    fn chosen(&self) -> Option<usize> {
+        *self.chosen.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for TProtocolSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TProtocolSelection")
+            .field("chosen", &self.chosen())
+            .finish()
+    }
+}
+
+enum InputState {
+    Pending(Option<Box<dyn TReadTransport + Send>>),
+    Detected(Box<dyn TInputProtocol + Send>),
+}
+
+/// `TInputProtocol` that picks its protocol from the first byte it reads.
+struct TDetectingInputProtocol {
+    state: InputState,
+    selection: TProtocolSelection,
+    // set before the protocol was detected, replacing the detector's
+    config: Option<TConfiguration>,
+}
+
+impl TDetectingInputProtocol {
+    fn inner(&mut self) -> crate::Result<&mut (dyn TInputProtocol + Send)> {
+        if let InputState::Pending(ref mut transport) = self.state {
+            let mut transport = match transport.take() {
+                Some(transport) => transport,
+                None => {
+                    return Err(new_protocol_error(
+                        ProtocolErrorKind::InvalidData,
+                        "protocol detection failed earlier on this connection",
+                    ))
+                }
+            };
+            let mut first = [0u8; 1];
+            transport.read_exact(&mut first)?;
+
+            let detector = &self.selection.detector;
+            let candidate = detector
+                .candidates
+                .iter()
+                .position(|c| c.matches(first[0]))
+                .ok_or_else(|| {
+                    new_protocol_error(
+                        ProtocolErrorKind::InvalidData,
+                        format!("no protocol starts with byte {:#04x}", first[0]),
+                    )
+                })?;
+            *self
+                .selection
+                .chosen
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = Some(candidate);
+            let transport = Replay {
+                first: Some(first[0]),
+                inner: transport,
+            };
+            let mut inner = detector.input_protocol(candidate, Box::new(transport));
+            if let Some(config) = self.config.take() {
+                inner.set_configuration(&config);
+            }
+            self.state = InputState::Detected(inner);
+        }
+        match self.state {
+            InputState::Detected(ref mut inner) => Ok(&mut **inner),
+            InputState::Pending(_) => unreachable!("protocol was detected above"),
+        }
+    }
+}
+
+/// Reader that returns the byte used for detection before reading on.
+struct Replay {
+    first: Option<u8>,
+    inner: Box<dyn TReadTransport + Send>,
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.first.take() {
+            Some(b) if !buf.is_empty() => {
+                buf[0] = b;
+                Ok(1)
+            }
+            first => {
+                self.first = first;
+                self.inner.read(buf)
+            }
+        }
+    }
+}
+
+impl TInputProtocol for TDetectingInputProtocol {
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        self.inner()?.read_message_begin()
+    }
+
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        self.inner()?.read_message_end()
+    }
+
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.inner()?.read_struct_begin()
+    }
+
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.inner()?.read_struct_end()
+    }
+
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        self.inner()?.read_field_begin()
+    }
+
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        self.inner()?.read_field_end()
+    }
+
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        self.inner()?.read_bytes()
+    }
+
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        self.inner()?.read_bool()
+    }
+
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        self.inner()?.read_i8()
+    }
+
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.inner()?.read_i16()
+    }
+
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        self.inner()?.read_i32()
+    }
+
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        self.inner()?.read_i64()
+    }
+
+    fn read_double(&mut self) -> crate::Result<f64> {
+        self.inner()?.read_double()
+    }
+
+    fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
+        self.inner()?.read_uuid()
+    }
+
+    fn read_string(&mut self) -> crate::Result<String> {
+        self.inner()?.read_string()
+    }
+
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        self.inner()?.read_raw_string()
+    }
+
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        self.inner()?.read_list_begin()
+    }
+
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        self.inner()?.read_list_end()
+    }
+
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        self.inner()?.read_set_begin()
+    }
+
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        self.inner()?.read_set_end()
+    }
+
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        self.inner()?.read_map_begin()
+    }
+
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        self.inner()?.read_map_end()
+    }
+
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        self.inner()?.skip_till_depth(field_type, depth)
+    }
+
+    // utility
+    //
+
+    fn min_serialized_size(&self, field_type: TType) -> usize {
+        match self.state {
+            InputState::Detected(ref inner) => inner.min_serialized_size(field_type),
+            InputState::Pending(_) => 0,
+        }
+    }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        match self.state {
+            InputState::Detected(ref mut inner) => inner.set_configuration(config),
+            InputState::Pending(_) => self.config = Some(config.clone()),
+        }
+    }
+
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        self.inner()?.read_byte()
+    }
+}
+
+enum OutputState {
+    Pending(Option<Box<dyn TWriteTransport + Send>>),
+    Chosen(Box<dyn TOutputProtocol + Send>),
+}
+
+/// `TOutputProtocol` that writes in the protocol chosen by the input side of
+/// its connection.
+struct TDetectingOutputProtocol {
+    state: OutputState,
+    selection: TProtocolSelection,
+}
+
+impl TDetectingOutputProtocol {
+    fn inner(&mut self) -> crate::Result<&mut (dyn TOutputProtocol + Send)> {
+        if let OutputState::Pending(ref mut transport) = self.state {
+            let transport = transport.take().expect("output protocol is pending");
+            let candidate = self.selection.chosen().unwrap_or(0);
+            let detector = &self.selection.detector;
+            if detector.candidates.is_empty() {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::InvalidData,
+                    "no protocol to write with",
+                ));
+            }
+            self.state = OutputState::Chosen(detector.output_protocol(candidate, transport));
+        }
+        match self.state {
+            OutputState::Chosen(ref mut inner) => Ok(&mut **inner),
+            OutputState::Pending(_) => unreachable!("protocol was chosen above"),
+        }
+    }
+}
+
+impl TOutputProtocol for TDetectingOutputProtocol {
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        self.inner()?.write_message_begin(identifier)
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.inner()?.write_message_end()
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        self.inner()?.write_struct_begin(identifier)
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.inner()?.write_struct_end()
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        self.inner()?.write_field_begin(identifier)
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.inner()?.write_field_end()
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        self.inner()?.write_field_stop()
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.inner()?.write_bytes(b)
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.inner()?.write_bool(b)
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.inner()?.write_i8(i)
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.inner()?.write_i16(i)
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.inner()?.write_i32(i)
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.inner()?.write_i64(i)
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.inner()?.write_double(d)
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.inner()?.write_string(s)
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.inner()?.write_raw_string(s)
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        self.inner()?.write_uuid(uuid)
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.inner()?.write_list_begin(identifier)
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.inner()?.write_list_end()
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.inner()?.write_set_begin(identifier)
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.inner()?.write_set_end()
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        self.inner()?.write_map_begin(identifier)
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.inner()?.write_map_end()
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        match self.state {
+            OutputState::Chosen(ref mut inner) => inner.flush(),
+            OutputState::Pending(_) => Ok(()),
+        }
+    }
+
+    // utility
+    //
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.inner()?.write_byte(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{
+        TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol, TMessageType,
+    };
+    use crate::transport::TBufferChannel;
+
+    fn detector() -> Arc<TProtocolDetector> {
+        Arc::new(
+            TProtocolDetector::new()
+                .with_protocol(TProtocolKind::Binary)
+                .with_protocol(TProtocolKind::Compact),
+        )
+    }
+
+    fn call(o_prot: &mut dyn TOutputProtocol) {
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("ping", TMessageType::Call, 3))
+            .unwrap();
+        o_prot.write_string("hello").unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    #[test]
+    fn must_read_and_reply_in_detected_protocol() {
+        let mut request = Vec::new();
+        call(&mut TCompactOutputProtocol::new(&mut request));
+
+        let (mut i_prot, selection) = detector().detect(Box::new(io::Cursor::new(request)));
+        assert_eq!(selection.protocol_kind(), None);
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.name, "ping");
+        assert_eq!(i_prot.read_string().unwrap(), "hello");
+        assert_eq!(selection.protocol_kind(), Some(TProtocolKind::Compact));
+
+        let channel = TBufferChannel::with_capacity(0, 64);
+        let mut o_prot = selection.output_protocol(Box::new(channel.clone()));
+        call(&mut *o_prot);
+        let mut reply = TCompactInputProtocol::new(io::Cursor::new(channel.write_bytes()));
+        assert_eq!(reply.read_message_begin().unwrap().name, "ping");
+    }
+
+    #[test]
+    fn must_detect_binary_protocol() {
+        let mut request = Vec::new();
+        call(&mut TBinaryOutputProtocol::new(&mut request, true));
+
+        let (mut i_prot, selection) = detector().detect(Box::new(io::Cursor::new(request)));
+        assert_eq!(i_prot.read_message_begin().unwrap().sequence_number, 3);
+        assert_eq!(selection.protocol_kind(), Some(TProtocolKind::Binary));
+    }
+
+    #[test]
+    fn must_use_custom_factories() {
+        let detector = Arc::new(TProtocolDetector::new().with_factories(
+            |b| b == 0x82,
+            crate::protocol::TCompactInputProtocolFactory::new(),
+            crate::protocol::TCompactOutputProtocolFactory::new(),
+        ));
+        let mut request = Vec::new();
+        call(&mut TCompactOutputProtocol::new(&mut request));
+
+        let (mut i_prot, selection) = detector.detect(Box::new(io::Cursor::new(request)));
+        assert_eq!(i_prot.read_message_begin().unwrap().name, "ping");
+        assert_eq!(selection.protocol_kind(), None);
+    }
+
+    #[test]
+    fn must_reject_unknown_protocol() {
+        let (mut i_prot, _) = detector().detect(Box::new(io::Cursor::new(vec![b'[', b'1'])));
+        match i_prot.read_message_begin() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+    }
+}
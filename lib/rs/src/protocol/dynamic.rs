@@ -0,0 +1,467 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use super::{
+    TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol,
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TOutputProtocol, TRawString, TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::transport::{TReadTransport, TWriteTransport};
+use crate::TConfiguration;
+
+// Forward a call to whichever protocol the enum currently holds.
+macro_rules! dispatch {
+    ($self:ident, $p:ident => $call:expr) => {
+        match $self {
+            Self::Binary($p) => $call,
+            Self::Compact($p) => $call,
+        }
+    };
+}
+
+/// Identifies one of the built-in Thrift protocols.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TProtocolKind {
+    /// The binary protocol (`TBinaryInputProtocol`/`TBinaryOutputProtocol`).
+    Binary,
+    /// The compact protocol (`TCompactInputProtocol`/`TCompactOutputProtocol`).
+    Compact,
+}
+
+/// `TInputProtocol` that can be any of the built-in protocols, chosen at
+/// runtime.
+///
+/// Unlike a `Box<dyn TInputProtocol>` this dispatches with a `match` rather
+/// than a virtual call, which the compiler can inline into hot decode loops.
+/// Use it when the protocol is only known once a connection has been
+/// accepted, for example in servers and proxies that serve several
+/// protocols on one port.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TDynamicInputProtocol, TInputProtocol, TProtocolKind};
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+///
+/// let mut protocol = TDynamicInputProtocol::new(TProtocolKind::Compact, channel);
+///
+/// let recvd_bool = protocol.read_bool().unwrap();
+/// let recvd_string = protocol.read_string().unwrap();
+/// ```
+#[derive(Debug)]
+pub enum TDynamicInputProtocol<T>
+where
+    T: TReadTransport,
+{
+    /// Strict or non-strict binary protocol.
+    Binary(TBinaryInputProtocol<T>),
+    /// Compact protocol.
+    Compact(TCompactInputProtocol<T>),
+}
+
+impl<T> TDynamicInputProtocol<T>
+where
+    T: TReadTransport,
+{
+    /// Create a `TDynamicInputProtocol` of type `kind` that reads bytes from
+    /// `transport`. The binary protocol is created in strict mode.
+    pub fn new(kind: TProtocolKind, transport: T) -> Self {
+        Self::with_config(kind, transport, TConfiguration::default())
+    }
+
+    /// Create a `TDynamicInputProtocol` of type `kind` that reads bytes from
+    /// `transport` and enforces the limits in `config`. The binary protocol
+    /// is created in strict mode.
+    pub fn with_config(kind: TProtocolKind, transport: T, config: TConfiguration) -> Self {
+        match kind {
+            TProtocolKind::Binary => TDynamicInputProtocol::Binary(
+                TBinaryInputProtocol::with_config(transport, true, config),
+            ),
+            TProtocolKind::Compact => TDynamicInputProtocol::Compact(
+                TCompactInputProtocol::with_config(transport, config),
+            ),
+        }
+    }
+
+    /// The kind of protocol wrapped by this instance.
+    pub fn kind(&self) -> TProtocolKind {
+        match self {
+            TDynamicInputProtocol::Binary(_) => TProtocolKind::Binary,
+            TDynamicInputProtocol::Compact(_) => TProtocolKind::Compact,
+        }
+    }
+}
+
+impl<T> TInputProtocol for TDynamicInputProtocol<T>
+where
+    T: TReadTransport,
+{
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        dispatch!(self, p => p.read_message_begin())
+    }
+
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.read_message_end())
+    }
+
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        dispatch!(self, p => p.read_struct_begin())
+    }
+
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.read_struct_end())
+    }
+
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        dispatch!(self, p => p.read_field_begin())
+    }
+
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.read_field_end())
+    }
+
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        dispatch!(self, p => p.read_bool())
+    }
+
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        dispatch!(self, p => p.read_bytes())
+    }
+
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        dispatch!(self, p => p.read_i8())
+    }
+
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        dispatch!(self, p => p.read_i16())
+    }
+
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        dispatch!(self, p => p.read_i32())
+    }
+
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        dispatch!(self, p => p.read_i64())
+    }
+
+    fn read_double(&mut self) -> crate::Result<f64> {
+        dispatch!(self, p => p.read_double())
+    }
+
+    fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
+        dispatch!(self, p => p.read_uuid())
+    }
+
+    fn read_string(&mut self) -> crate::Result<String> {
+        dispatch!(self, p => p.read_string())
+    }
+
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        dispatch!(self, p => p.read_raw_string())
+    }
+
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        dispatch!(self, p => p.read_list_begin())
+    }
+
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.read_list_end())
+    }
+
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        dispatch!(self, p => p.read_set_begin())
+    }
+
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.read_set_end())
+    }
+
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        dispatch!(self, p => p.read_map_begin())
+    }
+
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.read_map_end())
+    }
+
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        dispatch!(self, p => p.skip_till_depth(field_type, depth))
+    }
+
+    fn min_serialized_size(&self, field_type: TType) -> usize {
+        dispatch!(self, p => p.min_serialized_size(field_type))
+    }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        dispatch!(self, p => p.set_configuration(config))
+    }
+
+    // utility
+    //
+
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        dispatch!(self, p => p.read_byte())
+    }
+}
+
+/// `TOutputProtocol` that can be any of the built-in protocols, chosen at
+/// runtime.
+///
+/// The output counterpart of `TDynamicInputProtocol`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TDynamicOutputProtocol, TOutputProtocol, TProtocolKind};
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+///
+/// let mut protocol = TDynamicOutputProtocol::new(TProtocolKind::Binary, channel);
+///
+/// protocol.write_bool(true).unwrap();
+/// protocol.write_string("test_string").unwrap();
+/// ```
+#[derive(Debug)]
+pub enum TDynamicOutputProtocol<T>
+where
+    T: TWriteTransport,
+{
+    /// Strict or non-strict binary protocol.
+    Binary(TBinaryOutputProtocol<T>),
+    /// Compact protocol.
+    Compact(TCompactOutputProtocol<T>),
+}
+
+impl<T> TDynamicOutputProtocol<T>
+where
+    T: TWriteTransport,
+{
+    /// Create a `TDynamicOutputProtocol` of type `kind` that writes bytes to
+    /// `transport`. The binary protocol is created in strict mode.
+    pub fn new(kind: TProtocolKind, transport: T) -> Self {
+        Self::with_config(kind, transport, TConfiguration::default())
+    }
+
+    /// Create a `TDynamicOutputProtocol` of type `kind` that writes bytes to
+    /// `transport` and enforces the limits in `config`. The binary protocol
+    /// is created in strict mode.
+    pub fn with_config(kind: TProtocolKind, transport: T, config: TConfiguration) -> Self {
+        match kind {
+            TProtocolKind::Binary => TDynamicOutputProtocol::Binary(
+                TBinaryOutputProtocol::with_config(transport, true, config),
+            ),
+            TProtocolKind::Compact => TDynamicOutputProtocol::Compact(
+                TCompactOutputProtocol::with_config(transport, config),
+            ),
+        }
+    }
+
+    /// The kind of protocol wrapped by this instance.
+    pub fn kind(&self) -> TProtocolKind {
+        match self {
+            TDynamicOutputProtocol::Binary(_) => TProtocolKind::Binary,
+            TDynamicOutputProtocol::Compact(_) => TProtocolKind::Compact,
+        }
+    }
+}
+
+impl<T> TOutputProtocol for TDynamicOutputProtocol<T>
+where
+    T: TWriteTransport,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        dispatch!(self, p => p.write_message_begin(identifier))
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.write_message_end())
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        dispatch!(self, p => p.write_struct_begin(identifier))
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.write_struct_end())
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        dispatch!(self, p => p.write_field_begin(identifier))
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.write_field_end())
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.write_field_stop())
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        dispatch!(self, p => p.write_bool(b))
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        dispatch!(self, p => p.write_bytes(b))
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        dispatch!(self, p => p.write_i8(i))
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        dispatch!(self, p => p.write_i16(i))
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        dispatch!(self, p => p.write_i32(i))
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        dispatch!(self, p => p.write_i64(i))
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        dispatch!(self, p => p.write_double(d))
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        dispatch!(self, p => p.write_uuid(uuid))
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        dispatch!(self, p => p.write_string(s))
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        dispatch!(self, p => p.write_raw_string(s))
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        dispatch!(self, p => p.write_list_begin(identifier))
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.write_list_end())
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        dispatch!(self, p => p.write_set_begin(identifier))
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.write_set_end())
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        dispatch!(self, p => p.write_map_begin(identifier))
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.write_map_end())
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        dispatch!(self, p => p.flush())
+    }
+
+    // utility
+    //
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        dispatch!(self, p => p.write_byte(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::{TMessageType, TStructIdentifier};
+
+    fn round_trip(kind: TProtocolKind) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut o_prot = TDynamicOutputProtocol::new(kind, &mut buf);
+        assert_eq!(o_prot.kind(), kind);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("ping", TMessageType::Call, 9))
+            .unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("args"))
+            .unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("flag", TType::Bool, 1))
+            .unwrap();
+        o_prot.write_bool(true).unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("value", TType::I64, 2))
+            .unwrap();
+        o_prot.write_i64(-42).unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        let mut i_prot = TDynamicInputProtocol::new(kind, &buf[..]);
+        assert_eq!(i_prot.kind(), kind);
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(
+            ident,
+            TMessageIdentifier::new("ping", TMessageType::Call, 9)
+        );
+        i_prot.read_struct_begin().unwrap();
+        // skip the bool, then read the i64
+        let field = i_prot.read_field_begin().unwrap();
+        i_prot.skip(field.field_type).unwrap();
+        i_prot.read_field_end().unwrap();
+        let field = i_prot.read_field_begin().unwrap();
+        assert_eq!(field.id, Some(2));
+        assert_eq!(i_prot.read_i64().unwrap(), -42);
+        i_prot.read_field_end().unwrap();
+        assert_eq!(i_prot.read_field_begin().unwrap().field_type, TType::Stop);
+        i_prot.read_struct_end().unwrap();
+        i_prot.read_message_end().unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn must_round_trip_through_each_protocol_kind() {
+        let binary = round_trip(TProtocolKind::Binary);
+        let compact = round_trip(TProtocolKind::Compact);
+
+        // binary messages start with the strict version header, compact ones
+        // with the compact protocol id
+        assert_eq!(&binary[..2], &[0x80, 0x01]);
+        assert_eq!(compact[0], 0x82);
+    }
+
+    #[test]
+    fn must_use_wrapped_protocol_for_min_serialized_size() {
+        let binary = TDynamicInputProtocol::new(TProtocolKind::Binary, &[][..]);
+        let compact = TDynamicInputProtocol::new(TProtocolKind::Compact, &[][..]);
+        assert_eq!(binary.min_serialized_size(TType::I32), 4);
+        assert_eq!(compact.min_serialized_size(TType::I32), 1);
+    }
+}
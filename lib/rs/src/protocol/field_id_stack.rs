@@ -0,0 +1,100 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Stack of enclosing struct field ids kept by the compact protocol.
+//!
+//! Every nested struct pushes the id of the last field read or written in the
+//! enclosing struct, so that field id deltas can be restored when the nested
+//! struct ends. The first `INLINE_CAPACITY` entries live inside the protocol
+//! itself, which covers the default recursion limit without touching the
+//! heap. Deeper nesting (only possible with a raised limit) spills to a `Vec`.
+
+use crate::TConfiguration;
+
+const INLINE_CAPACITY: usize = TConfiguration::DEFAULT_RECURSION_LIMIT;
+
+#[derive(Clone, Debug)]
+pub(crate) struct FieldIdStack {
+    inline: [i16; INLINE_CAPACITY],
+    spilled: Vec<i16>,
+    len: usize,
+}
+
+impl FieldIdStack {
+    pub(crate) fn new() -> FieldIdStack {
+        FieldIdStack {
+            inline: [0; INLINE_CAPACITY],
+            spilled: Vec::new(),
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, field_id: i16) {
+        if self.len < INLINE_CAPACITY {
+            self.inline[self.len] = field_id;
+        } else {
+            self.spilled.push(field_id);
+        }
+        self.len += 1;
+    }
+
+    #[inline]
+    pub(crate) fn pop(&mut self) -> Option<i16> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        if self.len < INLINE_CAPACITY {
+            Some(self.inline[self.len])
+        } else {
+            self.spilled.pop()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn must_pop_in_reverse_push_order() {
+        let mut stack = FieldIdStack::new();
+        assert_eq!(stack.pop(), None);
+
+        let depth = INLINE_CAPACITY as i16 * 2 + 3;
+        for id in 0..depth {
+            stack.push(id);
+        }
+        assert_eq!(stack.spilled.len(), INLINE_CAPACITY + 3);
+
+        for id in (0..depth).rev() {
+            assert_eq!(stack.pop(), Some(id));
+        }
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn must_not_allocate_within_inline_capacity() {
+        let mut stack = FieldIdStack::new();
+        for id in 0..INLINE_CAPACITY as i16 {
+            stack.push(id);
+        }
+        assert_eq!(stack.spilled.capacity(), 0);
+    }
+}
@@ -0,0 +1,414 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+use ordered_float::OrderedFloat;
+
+use super::{
+    TInputProtocol, TListIdentifier, TMapIdentifier, TOutputProtocol, TSetIdentifier, TType,
+};
+use crate::{ProtocolErrorKind, TConfiguration};
+
+/// A value that can be written as a struct field, container element or
+/// map entry.
+///
+/// Implemented for Rust types that map directly to Thrift types: `bool`,
+/// the signed integers, `f64` and `OrderedFloat<f64>` (double), `String`,
+/// `Vec<u8>` (binary), `uuid::Uuid`, `Vec<T>` (list), `BTreeSet<T>` and
+/// `HashSet<T>` (set), and `BTreeMap<K, V>` and `HashMap<K, V>` (map), and
+/// for structs deriving `ThriftSerialize`.
+pub trait TWriteValue {
+    /// The Thrift type this value is written as.
+    const FIELD_TYPE: TType;
+
+    /// Write this value to `o_prot`.
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()>;
+}
+
+/// A value that can be read as a struct field, container element or map
+/// entry.
+///
+/// Implemented for the same types as `TWriteValue`, and for structs
+/// deriving `ThriftDeserialize`. With the `derive` feature, types
+/// implementing both are `TSerializable`.
+pub trait TReadValue: Sized {
+    /// The Thrift type this value is read as.
+    const FIELD_TYPE: TType;
+
+    /// Read a value from `i_prot`.
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self>;
+}
+
+#[cfg(feature = "derive")]
+impl<T> super::TSerializable for T
+where
+    T: TReadValue + TWriteValue,
+{
+    fn read_from_in_protocol(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        T::read_value(i_prot)
+    }
+
+    fn write_to_out_protocol(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        self.write_value(o_prot)
+    }
+}
+
+macro_rules! primitive_value {
+    ($($ty:ty => $field_type:ident, $read:ident, $write:ident;)*) => {
+        $(
+            impl TWriteValue for $ty {
+                const FIELD_TYPE: TType = TType::$field_type;
+
+                fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+                    o_prot.$write(self.clone().into())
+                }
+            }
+
+            impl TReadValue for $ty {
+                const FIELD_TYPE: TType = TType::$field_type;
+
+                fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+                    i_prot.$read().map(Into::into)
+                }
+            }
+        )*
+    };
+}
+
+primitive_value! {
+    bool => Bool, read_bool, write_bool;
+    i8 => I08, read_i8, write_i8;
+    i16 => I16, read_i16, write_i16;
+    i32 => I32, read_i32, write_i32;
+    i64 => I64, read_i64, write_i64;
+    f64 => Double, read_double, write_double;
+    OrderedFloat<f64> => Double, read_double, write_double;
+}
+
+impl TWriteValue for String {
+    const FIELD_TYPE: TType = TType::String;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        o_prot.write_string(self)
+    }
+}
+
+impl TReadValue for String {
+    const FIELD_TYPE: TType = TType::String;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        i_prot.read_string()
+    }
+}
+
+impl TWriteValue for Vec<u8> {
+    const FIELD_TYPE: TType = TType::String;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        o_prot.write_bytes(self)
+    }
+}
+
+impl TReadValue for Vec<u8> {
+    const FIELD_TYPE: TType = TType::String;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        i_prot.read_bytes()
+    }
+}
+
+impl TWriteValue for uuid::Uuid {
+    const FIELD_TYPE: TType = TType::Uuid;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        o_prot.write_uuid(self)
+    }
+}
+
+impl TReadValue for uuid::Uuid {
+    const FIELD_TYPE: TType = TType::Uuid;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        i_prot.read_uuid()
+    }
+}
+
+impl<T> TWriteValue for Box<T>
+where
+    T: TWriteValue,
+{
+    const FIELD_TYPE: TType = T::FIELD_TYPE;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        (**self).write_value(o_prot)
+    }
+}
+
+impl<T> TReadValue for Box<T>
+where
+    T: TReadValue,
+{
+    const FIELD_TYPE: TType = T::FIELD_TYPE;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        T::read_value(i_prot).map(Box::new)
+    }
+}
+
+/// Check that the elements of a container with `size` elements of type
+/// `element_type` can be read as `expected`.
+fn check_element_type(element_type: TType, expected: TType, size: i32) -> crate::Result<()> {
+    if size > 0 && element_type != expected {
+        return Err(crate::new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            format!(
+                "expected elements of type {} but found {}",
+                expected, element_type
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn write_list<'a, T, I>(
+    o_prot: &mut dyn TOutputProtocol,
+    len: usize,
+    elements: I,
+) -> crate::Result<()>
+where
+    T: TWriteValue + 'a,
+    I: Iterator<Item = &'a T>,
+{
+    o_prot.write_list_begin(&TListIdentifier::new(T::FIELD_TYPE, size(len)?))?;
+    for element in elements {
+        element.write_value(o_prot)?;
+    }
+    o_prot.write_list_end()
+}
+
+fn write_set<'a, T, I>(
+    o_prot: &mut dyn TOutputProtocol,
+    len: usize,
+    elements: I,
+) -> crate::Result<()>
+where
+    T: TWriteValue + 'a,
+    I: Iterator<Item = &'a T>,
+{
+    o_prot.write_set_begin(&TSetIdentifier::new(T::FIELD_TYPE, size(len)?))?;
+    for element in elements {
+        element.write_value(o_prot)?;
+    }
+    o_prot.write_set_end()
+}
+
+fn write_map<'a, K, V, I>(
+    o_prot: &mut dyn TOutputProtocol,
+    len: usize,
+    entries: I,
+) -> crate::Result<()>
+where
+    K: TWriteValue + 'a,
+    V: TWriteValue + 'a,
+    I: Iterator<Item = (&'a K, &'a V)>,
+{
+    o_prot.write_map_begin(&TMapIdentifier::new(
+        K::FIELD_TYPE,
+        V::FIELD_TYPE,
+        size(len)?,
+    ))?;
+    for (key, value) in entries {
+        key.write_value(o_prot)?;
+        value.write_value(o_prot)?;
+    }
+    o_prot.write_map_end()
+}
+
+fn size(len: usize) -> crate::Result<i32> {
+    i32::try_from(len).map_err(|_| {
+        crate::new_protocol_error(
+            ProtocolErrorKind::SizeLimit,
+            format!("{} elements are too many for a Thrift container", len),
+        )
+    })
+}
+
+impl<T> TWriteValue for Vec<T>
+where
+    T: TWriteValue,
+{
+    const FIELD_TYPE: TType = TType::List;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        write_list(o_prot, self.len(), self.iter())
+    }
+}
+
+impl<T> TReadValue for Vec<T>
+where
+    T: TReadValue,
+{
+    const FIELD_TYPE: TType = TType::List;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        let list = i_prot.read_list_begin()?;
+        check_element_type(list.element_type, T::FIELD_TYPE, list.size)?;
+        let mut elements = Vec::with_capacity(list.capacity_hint(&TConfiguration::default()));
+        for _ in 0..list.size {
+            elements.push(T::read_value(i_prot)?);
+        }
+        i_prot.read_list_end()?;
+        Ok(elements)
+    }
+}
+
+impl<T> TWriteValue for BTreeSet<T>
+where
+    T: TWriteValue,
+{
+    const FIELD_TYPE: TType = TType::Set;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        write_set(o_prot, self.len(), self.iter())
+    }
+}
+
+impl<T> TReadValue for BTreeSet<T>
+where
+    T: TReadValue + Ord,
+{
+    const FIELD_TYPE: TType = TType::Set;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        let set = i_prot.read_set_begin()?;
+        check_element_type(set.element_type, T::FIELD_TYPE, set.size)?;
+        let mut elements = BTreeSet::new();
+        for _ in 0..set.size {
+            elements.insert(T::read_value(i_prot)?);
+        }
+        i_prot.read_set_end()?;
+        Ok(elements)
+    }
+}
+
+impl<T> TWriteValue for HashSet<T>
+where
+    T: TWriteValue,
+{
+    const FIELD_TYPE: TType = TType::Set;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        write_set(o_prot, self.len(), self.iter())
+    }
+}
+
+impl<T> TReadValue for HashSet<T>
+where
+    T: TReadValue + Eq + Hash,
+{
+    const FIELD_TYPE: TType = TType::Set;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        let set = i_prot.read_set_begin()?;
+        check_element_type(set.element_type, T::FIELD_TYPE, set.size)?;
+        let mut elements = HashSet::with_capacity(set.capacity_hint(&TConfiguration::default()));
+        for _ in 0..set.size {
+            elements.insert(T::read_value(i_prot)?);
+        }
+        i_prot.read_set_end()?;
+        Ok(elements)
+    }
+}
+
+impl<K, V> TWriteValue for BTreeMap<K, V>
+where
+    K: TWriteValue,
+    V: TWriteValue,
+{
+    const FIELD_TYPE: TType = TType::Map;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        write_map(o_prot, self.len(), self.iter())
+    }
+}
+
+impl<K, V> TReadValue for BTreeMap<K, V>
+where
+    K: TReadValue + Ord,
+    V: TReadValue,
+{
+    const FIELD_TYPE: TType = TType::Map;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        let map = i_prot.read_map_begin()?;
+        if map.size > 0 {
+            check_element_type(map.key_type.unwrap_or(TType::Stop), K::FIELD_TYPE, map.size)?;
+            check_element_type(
+                map.value_type.unwrap_or(TType::Stop),
+                V::FIELD_TYPE,
+                map.size,
+            )?;
+        }
+        let mut entries = BTreeMap::new();
+        for _ in 0..map.size {
+            let key = K::read_value(i_prot)?;
+            entries.insert(key, V::read_value(i_prot)?);
+        }
+        i_prot.read_map_end()?;
+        Ok(entries)
+    }
+}
+
+impl<K, V> TWriteValue for HashMap<K, V>
+where
+    K: TWriteValue,
+    V: TWriteValue,
+{
+    const FIELD_TYPE: TType = TType::Map;
+
+    fn write_value(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        write_map(o_prot, self.len(), self.iter())
+    }
+}
+
+impl<K, V> TReadValue for HashMap<K, V>
+where
+    K: TReadValue + Eq + Hash,
+    V: TReadValue,
+{
+    const FIELD_TYPE: TType = TType::Map;
+
+    fn read_value(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+        let map = i_prot.read_map_begin()?;
+        if map.size > 0 {
+            check_element_type(map.key_type.unwrap_or(TType::Stop), K::FIELD_TYPE, map.size)?;
+            check_element_type(
+                map.value_type.unwrap_or(TType::Stop),
+                V::FIELD_TYPE,
+                map.size,
+            )?;
+        }
+        let mut entries = HashMap::with_capacity(map.capacity_hint(&TConfiguration::default()));
+        for _ in 0..map.size {
+            let key = K::read_value(i_prot)?;
+            entries.insert(key, V::read_value(i_prot)?);
+        }
+        i_prot.read_map_end()?;
+        Ok(entries)
+    }
+}
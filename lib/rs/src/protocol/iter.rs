@@ -0,0 +1,697 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use super::{TInputProtocol, TMapIdentifier, TType};
+use crate::{ProtocolError, ProtocolErrorKind};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ContainerKind {
+    List,
+    Set,
+}
+
+/// Iterator that lazily decodes the elements of a Thrift list or set.
+///
+/// Created by `TInputProtocol::read_list_iter` or
+/// `TInputProtocol::read_set_iter`. Each call to `next` decodes one element
+/// with the caller-provided closure, so arbitrarily large containers can be
+/// processed without first collecting them into memory. Exactly as many
+/// elements as were declared in the container header are yielded; once the
+/// last one is returned the end of the container is read from the protocol.
+///
+/// Iteration stops after the first error. If reading the end of the
+/// container fails after the last element was decoded, the element is still
+/// yielded and the error follows it. If the iterator is dropped before
+/// it is exhausted the protocol is left in the middle of the container; use
+/// `TElementIter::finish` to skip the remaining elements instead.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocol, TInputProtocol};
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("127.0.0.1:9090").unwrap();
+///
+/// let mut protocol = TBinaryInputProtocol::new(channel, true);
+///
+/// let mut total = 0i64;
+/// for element in protocol.read_list_iter(|p| p.read_i64()).unwrap() {
+///     total += element.unwrap();
+/// }
+/// println!("sum of list elements: {}", total);
+/// ```
+#[derive(Debug)]
+pub struct TElementIter<'a, P, F>
+where
+    P: TInputProtocol + ?Sized,
+{
+    i_prot: &'a mut P,
+    element_type: TType,
+    remaining: usize,
+    kind: ContainerKind,
+    decode: F,
+    ended: bool,
+    // error reading the end of the container, returned after the last element
+    end_error: Option<crate::Error>,
+    failed: bool,
+}
+
+impl<'a, P, F> TElementIter<'a, P, F>
+where
+    P: TInputProtocol + ?Sized,
+{
+    /// Read a list header from `i_prot` and return an iterator that decodes
+    /// its elements with `decode`.
+    pub fn list(i_prot: &'a mut P, decode: F) -> crate::Result<Self> {
+        let identifier = i_prot.read_list_begin()?;
+        Ok(Self::new(
+            i_prot,
+            identifier.element_type,
+            identifier.size,
+            ContainerKind::List,
+            decode,
+        ))
+    }
+
+    /// Read a set header from `i_prot` and return an iterator that decodes
+    /// its elements with `decode`.
+    pub fn set(i_prot: &'a mut P, decode: F) -> crate::Result<Self> {
+        let identifier = i_prot.read_set_begin()?;
+        Ok(Self::new(
+            i_prot,
+            identifier.element_type,
+            identifier.size,
+            ContainerKind::Set,
+            decode,
+        ))
+    }
+
+    fn new(
+        i_prot: &'a mut P,
+        element_type: TType,
+        size: i32,
+        kind: ContainerKind,
+        decode: F,
+    ) -> Self {
+        TElementIter {
+            i_prot,
+            element_type,
+            // protocols reject negative sizes when reading the header
+            remaining: size.max(0) as usize,
+            kind,
+            decode,
+            ended: false,
+            end_error: None,
+            failed: false,
+        }
+    }
+
+    /// Type of the elements in the container, as declared in its header.
+    pub fn element_type(&self) -> TType {
+        self.element_type
+    }
+
+    /// Skip any elements that have not been decoded yet and read the end of
+    /// the container, leaving the protocol positioned after it.
+    ///
+    /// Fails if an earlier element could not be decoded.
+    pub fn finish(mut self) -> crate::Result<()> {
+        if self.failed {
+            return Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                "cannot finish a container after a decode error",
+            )));
+        }
+        if let Some(e) = self.end_error.take() {
+            return Err(e);
+        }
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            self.i_prot.skip(self.element_type)?;
+        }
+        self.read_end()
+    }
+
+    fn read_end(&mut self) -> crate::Result<()> {
+        if self.ended {
+            return Ok(());
+        }
+        self.ended = true;
+        match self.kind {
+            ContainerKind::List => self.i_prot.read_list_end(),
+            ContainerKind::Set => self.i_prot.read_set_end(),
+        }
+    }
+}
+
+impl<P, F, T> Iterator for TElementIter<'_, P, F>
+where
+    P: TInputProtocol + ?Sized,
+    F: FnMut(&mut P) -> crate::Result<T>,
+{
+    type Item = crate::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Some(e) = self.end_error.take() {
+            self.failed = true;
+            return Some(Err(e));
+        }
+
+        if self.remaining == 0 {
+            if self.ended {
+                return None;
+            }
+            return match self.read_end() {
+                Ok(()) => None,
+                Err(e) => {
+                    self.failed = true;
+                    Some(Err(e))
+                }
+            };
+        }
+
+        self.remaining -= 1;
+        let result = (self.decode)(&mut *self.i_prot);
+        if result.is_err() {
+            self.failed = true;
+        } else if self.remaining == 0 {
+            // read the container end eagerly so that the protocol is usable
+            // as soon as the last element has been returned
+            self.end_error = self.read_end().err();
+        }
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            return (0, Some(0));
+        }
+        // the lower bound is deliberately small: the element count comes from
+        // the peer and must not drive preallocation in `collect`
+        let trailing_end =
+            ((self.remaining == 0 && !self.ended) || self.end_error.is_some()) as usize;
+        (self.remaining.min(1), Some(self.remaining + trailing_end))
+    }
+}
+
+/// Iterator that lazily decodes the entries of a Thrift map.
+///
+/// Created by `TInputProtocol::read_map_entries`. Each call to `next` decodes
+/// one key with `decode_key` and its value with `decode_value`, so huge maps
+/// can be folded over without building a `BTreeMap` first. The map size is
+/// validated against the protocol's `TConfiguration` when the map header is
+/// read, and exactly that many entries are yielded; once the last one is
+/// returned the end of the map is read from the protocol.
+///
+/// As with `TElementIter`, iteration stops after the first error and
+/// `TMapEntryIter::finish` skips entries that have not been decoded.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocol, TInputProtocol};
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("127.0.0.1:9090").unwrap();
+///
+/// let mut protocol = TBinaryInputProtocol::new(channel, true);
+///
+/// let longest_key = protocol
+///     .read_map_entries(|p| p.read_string(), |p| p.read_i64())
+///     .unwrap()
+///     .map(|entry| entry.map(|(k, _)| k.len()))
+///     .try_fold(0, |acc, len| len.map(|len| acc.max(len)))
+///     .unwrap();
+/// println!("longest key: {}", longest_key);
+/// ```
+#[derive(Debug)]
+pub struct TMapEntryIter<'a, P, FK, FV>
+where
+    P: TInputProtocol + ?Sized,
+{
+    i_prot: &'a mut P,
+    key_type: Option<TType>,
+    value_type: Option<TType>,
+    remaining: usize,
+    decode_key: FK,
+    decode_value: FV,
+    ended: bool,
+    // error reading the end of the map, returned after the last entry
+    end_error: Option<crate::Error>,
+    failed: bool,
+}
+
+impl<'a, P, FK, FV> TMapEntryIter<'a, P, FK, FV>
+where
+    P: TInputProtocol + ?Sized,
+{
+    /// Read a map header from `i_prot` and return an iterator that decodes
+    /// its entries with `decode_key` and `decode_value`.
+    pub fn new(i_prot: &'a mut P, decode_key: FK, decode_value: FV) -> crate::Result<Self> {
+        let TMapIdentifier {
+            key_type,
+            value_type,
+            size,
+        } = i_prot.read_map_begin()?;
+        Ok(TMapEntryIter {
+            i_prot,
+            key_type,
+            value_type,
+            // protocols reject negative sizes when reading the header
+            remaining: size.max(0) as usize,
+            decode_key,
+            decode_value,
+            ended: false,
+            end_error: None,
+            failed: false,
+        })
+    }
+
+    /// Map key type as declared in the map header, if any.
+    pub fn key_type(&self) -> Option<TType> {
+        self.key_type
+    }
+
+    /// Map value type as declared in the map header, if any.
+    pub fn value_type(&self) -> Option<TType> {
+        self.value_type
+    }
+
+    /// Skip any entries that have not been decoded yet and read the end of
+    /// the map, leaving the protocol positioned after it.
+    ///
+    /// Fails if an earlier entry could not be decoded.
+    pub fn finish(mut self) -> crate::Result<()> {
+        if self.failed {
+            return Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidState,
+                "cannot finish a map after a decode error",
+            )));
+        }
+        if let Some(e) = self.end_error.take() {
+            return Err(e);
+        }
+        if self.remaining > 0 {
+            let (key_type, value_type) = match (self.key_type, self.value_type) {
+                (Some(k), Some(v)) => (k, v),
+                _ => {
+                    return Err(crate::Error::Protocol(ProtocolError::new(
+                        ProtocolErrorKind::InvalidData,
+                        "non-empty map without key or value type",
+                    )))
+                }
+            };
+            while self.remaining > 0 {
+                self.remaining -= 1;
+                self.i_prot.skip(key_type)?;
+                self.i_prot.skip(value_type)?;
+            }
+        }
+        self.read_end()
+    }
+
+    fn read_end(&mut self) -> crate::Result<()> {
+        if self.ended {
+            return Ok(());
+        }
+        self.ended = true;
+        self.i_prot.read_map_end()
+    }
+}
+
+impl<P, FK, FV, K, V> Iterator for TMapEntryIter<'_, P, FK, FV>
+where
+    P: TInputProtocol + ?Sized,
+    FK: FnMut(&mut P) -> crate::Result<K>,
+    FV: FnMut(&mut P) -> crate::Result<V>,
+{
+    type Item = crate::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Some(e) = self.end_error.take() {
+            self.failed = true;
+            return Some(Err(e));
+        }
+
+        if self.remaining == 0 {
+            if self.ended {
+                return None;
+            }
+            return match self.read_end() {
+                Ok(()) => None,
+                Err(e) => {
+                    self.failed = true;
+                    Some(Err(e))
+                }
+            };
+        }
+
+        self.remaining -= 1;
+        let result = (self.decode_key)(&mut *self.i_prot)
+            .and_then(|k| (self.decode_value)(&mut *self.i_prot).map(|v| (k, v)));
+        if result.is_err() {
+            self.failed = true;
+        } else if self.remaining == 0 {
+            // read the map end eagerly so that the protocol is usable as soon
+            // as the last entry has been returned
+            self.end_error = self.read_end().err();
+        }
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            return (0, Some(0));
+        }
+        // see `TElementIter::size_hint`
+        let trailing_end =
+            ((self.remaining == 0 && !self.ended) || self.end_error.is_some()) as usize;
+        (self.remaining.min(1), Some(self.remaining + trailing_end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::{
+        TCompactInputProtocol, TCompactOutputProtocol, TListIdentifier, TMapIdentifier,
+        TOutputProtocol, TSetIdentifier,
+    };
+    use crate::transport::TMockChannel;
+    use crate::TConfiguration;
+    use std::io;
+
+    fn encoded_list(values: &[i32]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut o_prot = TCompactOutputProtocol::new(&mut buf);
+        o_prot
+            .write_list_begin(&TListIdentifier::new(TType::I32, values.len() as i32))
+            .unwrap();
+        for v in values {
+            o_prot.write_i32(*v).unwrap();
+        }
+        o_prot.write_list_end().unwrap();
+        // trailing value used to check the protocol position afterwards
+        o_prot.write_i32(99).unwrap();
+        buf
+    }
+
+    #[test]
+    fn must_yield_exactly_the_declared_number_of_elements() {
+        let buf = encoded_list(&[1, 2, 3, 4]);
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+
+        let iter = i_prot.read_list_iter(|p| p.read_i32()).unwrap();
+        assert_eq!(iter.element_type(), TType::I32);
+        let values: Vec<i32> = iter.collect::<crate::Result<_>>().unwrap();
+        assert_eq!(values, vec![1, 2, 3, 4]);
+
+        assert_eq!(i_prot.read_i32().unwrap(), 99);
+    }
+
+    #[test]
+    fn must_handle_empty_list() {
+        let buf = encoded_list(&[]);
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+
+        assert_eq!(i_prot.read_list_iter(|p| p.read_i32()).unwrap().count(), 0);
+        assert_eq!(i_prot.read_i32().unwrap(), 99);
+    }
+
+    #[test]
+    fn must_skip_remaining_elements_on_finish() {
+        let buf = encoded_list(&[1, 2, 3, 4]);
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+
+        let mut iter = i_prot.read_list_iter(|p| p.read_i32()).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), 1);
+        iter.finish().unwrap();
+
+        assert_eq!(i_prot.read_i32().unwrap(), 99);
+    }
+
+    #[test]
+    fn must_stop_after_decode_error() {
+        let buf = encoded_list(&[1, 2]);
+        let mut i_prot = TCompactInputProtocol::new(&buf[..3]);
+
+        let mut iter = i_prot.read_list_iter(|p| p.read_i32()).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), 1);
+        assert!(iter.next().unwrap().is_ok());
+        // the list end is fine, but nothing follows
+        assert!(iter.next().is_none());
+
+        let mut i_prot = TCompactInputProtocol::new(&buf[..2]);
+        let mut iter = i_prot.read_list_iter(|p| p.read_i32()).unwrap();
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        assert!(iter.finish().is_err());
+    }
+
+    #[test]
+    fn must_iterate_set_elements() {
+        let mut buf = Vec::new();
+        let mut o_prot = TCompactOutputProtocol::new(&mut buf);
+        o_prot
+            .write_set_begin(&TSetIdentifier::new(TType::String, 2))
+            .unwrap();
+        o_prot.write_string("foo").unwrap();
+        o_prot.write_string("bar").unwrap();
+        o_prot.write_set_end().unwrap();
+
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+        let values: Vec<String> = i_prot
+            .read_set_iter(|p| p.read_string())
+            .unwrap()
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(values, vec!["foo".to_owned(), "bar".to_owned()]);
+    }
+
+    fn encoded_map(entries: &[(&str, i64)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut o_prot = TCompactOutputProtocol::new(&mut buf);
+        o_prot
+            .write_map_begin(&TMapIdentifier::new(
+                TType::String,
+                TType::I64,
+                entries.len() as i32,
+            ))
+            .unwrap();
+        for (k, v) in entries {
+            o_prot.write_string(k).unwrap();
+            o_prot.write_i64(*v).unwrap();
+        }
+        o_prot.write_map_end().unwrap();
+        o_prot.write_i32(99).unwrap();
+        buf
+    }
+
+    #[test]
+    fn must_yield_map_entries_in_wire_order() {
+        let buf = encoded_map(&[("a", 1), ("b", -2), ("c", 3)]);
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+
+        let iter = i_prot
+            .read_map_entries(|p| p.read_string(), |p| p.read_i64())
+            .unwrap();
+        assert_eq!(iter.key_type(), Some(TType::String));
+        assert_eq!(iter.value_type(), Some(TType::I64));
+        let sum = iter
+            .map(|e| e.map(|(_, v)| v))
+            .sum::<crate::Result<i64>>()
+            .unwrap();
+        assert_eq!(sum, 2);
+
+        assert_eq!(i_prot.read_i32().unwrap(), 99);
+    }
+
+    #[test]
+    fn must_handle_empty_map() {
+        let buf = encoded_map(&[]);
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+
+        let iter = i_prot
+            .read_map_entries(|p| p.read_string(), |p| p.read_i64())
+            .unwrap();
+        assert_eq!(iter.count(), 0);
+        assert_eq!(i_prot.read_i32().unwrap(), 99);
+    }
+
+    #[test]
+    fn must_skip_remaining_map_entries_on_finish() {
+        let buf = encoded_map(&[("a", 1), ("b", 2), ("c", 3)]);
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+
+        let mut iter = i_prot
+            .read_map_entries(|p| p.read_string(), |p| p.read_i64())
+            .unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), ("a".to_owned(), 1));
+        iter.finish().unwrap();
+
+        assert_eq!(i_prot.read_i32().unwrap(), 99);
+    }
+
+    #[test]
+    fn must_enforce_container_size_limit_on_map_header() {
+        let buf = encoded_map(&[("a", 1), ("b", 2), ("c", 3)]);
+        let config = TConfiguration::builder()
+            .max_container_size(Some(2))
+            .build()
+            .unwrap();
+        let mut i_prot = TCompactInputProtocol::with_config(&buf[..], config);
+
+        let res = i_prot.read_map_entries(|p| p.read_string(), |p| p.read_i64());
+        match res {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::SizeLimit),
+            other => panic!("expected size limit error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    /// Protocol that, like text protocols, reads a byte marking the end of
+    /// each container from the transport.
+    struct MarkedEnds<P>(P);
+
+    // FIXME: avoid passthrough methods
+    impl<P: TInputProtocol> TInputProtocol for MarkedEnds<P> {
+        fn read_message_begin(&mut self) -> crate::Result<crate::protocol::TMessageIdentifier> {
+            self.0.read_message_begin()
+        }
+        fn read_message_end(&mut self) -> crate::Result<()> {
+            self.0.read_message_end()
+        }
+        fn read_struct_begin(
+            &mut self,
+        ) -> crate::Result<Option<crate::protocol::TStructIdentifier>> {
+            self.0.read_struct_begin()
+        }
+        fn read_struct_end(&mut self) -> crate::Result<()> {
+            self.0.read_struct_end()
+        }
+        fn read_field_begin(&mut self) -> crate::Result<crate::protocol::TFieldIdentifier> {
+            self.0.read_field_begin()
+        }
+        fn read_field_end(&mut self) -> crate::Result<()> {
+            self.0.read_field_end()
+        }
+        fn read_bool(&mut self) -> crate::Result<bool> {
+            self.0.read_bool()
+        }
+        fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+            self.0.read_bytes()
+        }
+        fn read_i8(&mut self) -> crate::Result<i8> {
+            self.0.read_i8()
+        }
+        fn read_i16(&mut self) -> crate::Result<i16> {
+            self.0.read_i16()
+        }
+        fn read_i32(&mut self) -> crate::Result<i32> {
+            self.0.read_i32()
+        }
+        fn read_i64(&mut self) -> crate::Result<i64> {
+            self.0.read_i64()
+        }
+        fn read_double(&mut self) -> crate::Result<f64> {
+            self.0.read_double()
+        }
+        fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
+            self.0.read_uuid()
+        }
+        fn read_string(&mut self) -> crate::Result<String> {
+            self.0.read_string()
+        }
+        fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+            self.0.read_list_begin()
+        }
+        fn read_list_end(&mut self) -> crate::Result<()> {
+            self.0.read_byte().map(|_| ())
+        }
+        fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+            self.0.read_set_begin()
+        }
+        fn read_set_end(&mut self) -> crate::Result<()> {
+            self.0.read_byte().map(|_| ())
+        }
+        fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+            self.0.read_map_begin()
+        }
+        fn read_map_end(&mut self) -> crate::Result<()> {
+            self.0.read_byte().map(|_| ())
+        }
+        fn read_byte(&mut self) -> crate::Result<u8> {
+            self.0.read_byte()
+        }
+    }
+
+    /// Protocol over `buf` followed by an end marker, whose transport fails
+    /// when the marker is read.
+    fn failing_end_marker(buf: &[u8]) -> MarkedEnds<TCompactInputProtocol<TMockChannel>> {
+        let mut channel = TMockChannel::new();
+        channel.push_readable_bytes(buf);
+        channel.push_readable_bytes(&[0xFF]);
+        channel.fail_read_at(buf.len(), io::ErrorKind::ConnectionReset);
+        MarkedEnds(TCompactInputProtocol::new(channel))
+    }
+
+    fn assert_transport_error<T: std::fmt::Debug>(result: Option<crate::Result<T>>) {
+        match result {
+            Some(Err(crate::Error::Transport(_))) => {}
+            other => panic!("expected transport error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_yield_last_element_before_failed_list_end() {
+        let mut buf = encoded_list(&[1, 2]);
+        buf.truncate(buf.len() - 2); // drop the trailing varint
+        let mut i_prot = failing_end_marker(&buf);
+
+        let mut iter = i_prot.read_list_iter(|p| p.read_i32()).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), 1);
+        assert_eq!(iter.next().unwrap().unwrap(), 2);
+        assert_eq!(iter.size_hint(), (0, Some(1)));
+        assert_transport_error(iter.next());
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn must_yield_last_entry_before_failed_map_end() {
+        let mut buf = encoded_map(&[("a", 1)]);
+        buf.truncate(buf.len() - 2); // drop the trailing varint
+        let mut i_prot = failing_end_marker(&buf);
+
+        let mut iter = i_prot
+            .read_map_entries(|p| p.read_string(), |p| p.read_i64())
+            .unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), ("a".to_owned(), 1));
+        assert_transport_error(iter.next());
+        assert!(iter.next().is_none());
+    }
+}
@@ -62,7 +62,10 @@ use std::fmt;
 use std::fmt::{Display, Formatter};
 
 use crate::transport::{TReadTransport, TWriteTransport};
-use crate::{ProtocolError, ProtocolErrorKind, TConfiguration};
+use crate::{
+    ApplicationError, ProtocolError, ProtocolErrorKind, TConfiguration, TNonFiniteDoublePolicy,
+    TUtf8DecodePolicy,
+};
 
 #[cfg(test)]
 macro_rules! assert_eq_written_bytes {
@@ -86,21 +89,53 @@ macro_rules! set_readable_bytes {
     };
 }
 
+mod accelerated;
 mod binary;
+mod buffering;
 mod compact;
+mod copy;
+mod detect;
+mod dynamic;
+mod field_id_stack;
+mod field_value;
+mod iter;
 mod multiplexed;
+mod raw_string;
 mod stored;
-
+mod stream;
+#[cfg(feature = "testsuite")]
+pub mod testsuite;
+#[cfg(feature = "tracing")]
+mod traced;
+mod varint;
+
+pub use self::accelerated::{
+    TAcceleratedBinaryInputProtocol, TAcceleratedBinaryInputProtocolFactory,
+};
 pub use self::binary::{
     TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
     TBinaryOutputProtocolFactory,
 };
+pub use self::buffering::TFieldBufferingOutputProtocol;
 pub use self::compact::{
     TCompactInputProtocol, TCompactInputProtocolFactory, TCompactOutputProtocol,
     TCompactOutputProtocolFactory,
 };
+pub use self::copy::transcode;
+#[cfg(feature = "server")]
+pub(crate) use self::copy::{copy_value, MAXIMUM_COPY_DEPTH};
+pub use self::detect::{TProtocolDetector, TProtocolSelection};
+pub use self::dynamic::{TDynamicInputProtocol, TDynamicOutputProtocol, TProtocolKind};
+pub use self::field_value::{TReadValue, TWriteValue};
+pub use self::iter::{TElementIter, TMapEntryIter};
 pub use self::multiplexed::TMultiplexedOutputProtocol;
+pub use self::raw_string::TRawString;
 pub use self::stored::TStoredInputProtocol;
+pub use self::stream::{TStructStreamReader, TStructStreamSource, TStructStreamWriter};
+#[cfg(all(test, feature = "tracing", feature = "server"))]
+pub(crate) use self::traced::SpanRecorder;
+#[cfg(feature = "tracing")]
+pub use self::traced::TTracingOutputProtocol;
 
 /// Reads and writes the struct to Thrift protocols.
 ///
@@ -114,7 +149,7 @@ pub trait TSerializable: Sized {
 // field. A default is necessary because Thrift structs or collections may
 // contain nested structs and collections, which could result in indefinite
 // recursion.
-const MAXIMUM_SKIP_DEPTH: i8 = 64;
+pub(crate) const MAXIMUM_SKIP_DEPTH: i8 = 64;
 
 /// Converts a stream of bytes into Thrift identifiers, primitives,
 /// containers, or structs.
@@ -175,6 +210,17 @@ pub trait TInputProtocol {
     fn read_uuid(&mut self) -> crate::Result<uuid::Uuid>;
     /// Read a fixed-length string (not null terminated).
     fn read_string(&mut self) -> crate::Result<String>;
+    /// Read a string without validating that it is UTF-8.
+    ///
+    /// Size limits are enforced as for `read_string`, but the bytes are
+    /// returned as-is. See `TRawString` for how to validate them later.
+    ///
+    /// The default implementation reads the string with `read_bytes`, which
+    /// is correct for every protocol that encodes strings and binary values
+    /// identically.
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        self.read_bytes().map(TRawString::from_bytes)
+    }
     /// Read the beginning of a list.
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier>;
     /// Read the end of a list.
@@ -193,85 +239,138 @@ pub trait TInputProtocol {
         self.skip_till_depth(field_type, MAXIMUM_SKIP_DEPTH)
     }
     /// Skip a field with type `field_type` recursively up to `depth` levels.
+    ///
+    /// Protocols constructed with a `TConfiguration` also enforce its
+    /// `max_skip_depth` and `max_skip_bytes` limits.
     fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
-        if depth == 0 {
-            return Err(crate::Error::Protocol(ProtocolError {
-                kind: ProtocolErrorKind::DepthLimit,
-                message: format!("cannot parse past {:?}", field_type),
-            }));
-        }
+        skip_with_budget(self, field_type, depth, &mut SkipBudget::unlimited())
+    }
 
-        match field_type {
-            TType::Bool => self.read_bool().map(|_| ()),
-            TType::I08 => self.read_i8().map(|_| ()),
-            TType::I16 => self.read_i16().map(|_| ()),
-            TType::I32 => self.read_i32().map(|_| ()),
-            TType::I64 => self.read_i64().map(|_| ()),
-            TType::Double => self.read_double().map(|_| ()),
-            TType::String => self.read_bytes().map(|_| ()),
-            TType::Uuid => self.read_uuid().map(|_| ()),
-            TType::Struct => {
-                self.read_struct_begin()?;
-                loop {
-                    let field_ident = self.read_field_begin()?;
-                    if field_ident.field_type == TType::Stop {
-                        break;
-                    }
-                    self.skip_till_depth(field_ident.field_type, depth - 1)?;
-                }
-                self.read_struct_end()
-            }
-            TType::List => {
-                let list_ident = self.read_list_begin()?;
-                for _ in 0..list_ident.size {
-                    self.skip_till_depth(list_ident.element_type, depth - 1)?;
-                }
-                self.read_list_end()
-            }
-            TType::Set => {
-                let set_ident = self.read_set_begin()?;
-                for _ in 0..set_ident.size {
-                    self.skip_till_depth(set_ident.element_type, depth - 1)?;
-                }
-                self.read_set_end()
-            }
-            TType::Map => {
-                let map_ident = self.read_map_begin()?;
-                for _ in 0..map_ident.size {
-                    let key_type = map_ident
-                        .key_type
-                        .expect("non-zero sized map should contain key type");
-                    let val_type = map_ident
-                        .value_type
-                        .expect("non-zero sized map should contain value type");
-                    self.skip_till_depth(key_type, depth - 1)?;
-                    self.skip_till_depth(val_type, depth - 1)?;
-                }
-                self.read_map_end()
-            }
-            u => Err(crate::Error::Protocol(ProtocolError {
-                kind: ProtocolErrorKind::Unknown,
-                message: format!("cannot skip field type {:?}", &u),
-            })),
-        }
+    /// Read a complete Thrift message.
+    ///
+    /// Reads the message header, passes it along with this protocol to
+    /// `read_body`, then reads the end of the message. `read_message_end` is
+    /// called even if `read_body` fails; in that case the error from
+    /// `read_body` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use thrift::protocol::{TBinaryInputProtocol, TInputProtocol};
+    /// use thrift::transport::TTcpChannel;
+    ///
+    /// let mut channel = TTcpChannel::new();
+    /// channel.open("127.0.0.1:9090").unwrap();
+    ///
+    /// let mut protocol = TBinaryInputProtocol::new(channel, true);
+    ///
+    /// let (name, value) = protocol
+    ///     .read_message(|ident, prot| {
+    ///         prot.read_struct_begin()?;
+    ///         let value = prot.read_i32()?;
+    ///         prot.read_struct_end()?;
+    ///         Ok((ident.name, value))
+    ///     })
+    ///     .unwrap();
+    /// ```
+    fn read_message<F, R>(&mut self, read_body: F) -> crate::Result<R>
+    where
+        Self: Sized,
+        F: FnOnce(TMessageIdentifier, &mut Self) -> crate::Result<R>,
+    {
+        let identifier = self.read_message_begin()?;
+        let result = read_body(identifier, self);
+        let end_result = self.read_message_end();
+        let value = result?;
+        end_result?;
+        Ok(value)
     }
 
-    // utility (DO NOT USE IN GENERATED CODE!!!!)
-    //
+    /// Read the header of a list and return an iterator that lazily decodes
+    /// its elements with `decode`.
+    ///
+    /// See `TElementIter` for details.
+    fn read_list_iter<F, T>(&mut self, decode: F) -> crate::Result<TElementIter<'_, Self, F>>
+    where
+        Self: Sized,
+        F: FnMut(&mut Self) -> crate::Result<T>,
+    {
+        TElementIter::list(self, decode)
+    }
 
-    /// Read an unsigned byte.
+    /// Read the header of a set and return an iterator that lazily decodes
+    /// its elements with `decode`.
     ///
-    /// This method should **never** be used in generated code.
-    fn read_byte(&mut self) -> crate::Result<u8>;
+    /// See `TElementIter` for details.
+    fn read_set_iter<F, T>(&mut self, decode: F) -> crate::Result<TElementIter<'_, Self, F>>
+    where
+        Self: Sized,
+        F: FnMut(&mut Self) -> crate::Result<T>,
+    {
+        TElementIter::set(self, decode)
+    }
 
-    /// Get the minimum number of bytes a type will consume on the wire.
-    /// This picks the minimum possible across all protocols (so currently matches the compact protocol).
+    /// Read the header of a map and return an iterator that lazily decodes
+    /// its entries, using `decode_key` for each key and `decode_value` for
+    /// each value.
+    ///
+    /// See `TMapEntryIter` for details.
+    fn read_map_entries<FK, FV, K, V>(
+        &mut self,
+        decode_key: FK,
+        decode_value: FV,
+    ) -> crate::Result<TMapEntryIter<'_, Self, FK, FV>>
+    where
+        Self: Sized,
+        FK: FnMut(&mut Self) -> crate::Result<K>,
+        FV: FnMut(&mut Self) -> crate::Result<V>,
+    {
+        TMapEntryIter::new(self, decode_key, decode_value)
+    }
+
+    /// Read a `TApplicationException` struct, as sent by a server in the body
+    /// of an `Exception` message.
+    ///
+    /// Unknown fields are skipped. If the struct does not contain a message or
+    /// type the error defaults to "general remote error" and
+    /// `ApplicationErrorKind::Unknown`.
+    fn read_application_error(&mut self) -> crate::Result<ApplicationError>
+    where
+        Self: Sized,
+    {
+        crate::Error::read_application_error_from_in_protocol(self)
+    }
+
+    /// Get the minimum number of bytes a value of type `field_type` consumes
+    /// on the wire when encoded with this protocol.
+    ///
+    /// Protocols use this to reject container sizes that could not possibly
+    /// fit in the configured message size before allocating anything.
+    /// Readers can use it in the same way, for example through
+    /// `container_capacity_hint`. The actual data may be larger (e.g., for
+    /// strings, lists, etc.).
     ///
-    /// This is used for pre-allocation size checks.
-    /// The actual data may be larger (e.g., for strings, lists, etc.).
+    /// The default implementation returns the minimum possible across all
+    /// protocols (so currently matches the compact protocol).
     fn min_serialized_size(&self, field_type: TType) -> usize {
         self::compact::compact_protocol_min_serialized_size(field_type)
     }
+
+    /// Enforce the limits in `config` from the next value read on.
+    ///
+    /// Servers use this to change the limits of open connections. The
+    /// default implementation does nothing, for protocols without limits.
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        let _ = config;
+    }
+
+    // utility (DO NOT USE IN GENERATED CODE!!!!)
+    //
+
+    /// Read an unsigned byte.
+    ///
+    /// This method should **never** be used in generated code.
+    fn read_byte(&mut self) -> crate::Result<u8>;
 }
 
 /// Converts Thrift identifiers, primitives, containers or structs into a
@@ -339,6 +438,15 @@ pub trait TOutputProtocol {
     fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()>;
     /// Write a fixed-length string.
     fn write_string(&mut self, s: &str) -> crate::Result<()>;
+    /// Write a string whose bytes were read with `read_raw_string`, without
+    /// validating that they are UTF-8.
+    ///
+    /// The default implementation writes the bytes with `write_bytes`, which
+    /// is correct for every protocol that encodes strings and binary values
+    /// identically.
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.write_bytes(s.as_bytes())
+    }
     /// Write the beginning of a list.
     fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()>;
     /// Write the end of a list.
@@ -354,6 +462,63 @@ pub trait TOutputProtocol {
     /// Flush buffered bytes to the underlying transport.
     fn flush(&mut self) -> crate::Result<()>;
 
+    /// Write a complete Thrift message and flush it.
+    ///
+    /// Writes the message header described by `identifier`, calls
+    /// `write_body` with this protocol, then writes the end of the message
+    /// and flushes. The end of the message is written and the protocol is
+    /// flushed even if `write_body` fails; in that case the error from
+    /// `write_body` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use thrift::protocol::{TBinaryOutputProtocol, TMessageIdentifier, TMessageType};
+    /// use thrift::protocol::{TOutputProtocol, TStructIdentifier};
+    /// use thrift::transport::TTcpChannel;
+    ///
+    /// let mut channel = TTcpChannel::new();
+    /// channel.open("127.0.0.1:9090").unwrap();
+    ///
+    /// let mut protocol = TBinaryOutputProtocol::new(channel, true);
+    ///
+    /// let ident = TMessageIdentifier::new("ping", TMessageType::Call, 1);
+    /// protocol
+    ///     .write_message(&ident, |prot| {
+    ///         prot.write_struct_begin(&TStructIdentifier::new("ping_args"))?;
+    ///         prot.write_field_stop()?;
+    ///         prot.write_struct_end()
+    ///     })
+    ///     .unwrap();
+    /// ```
+    fn write_message<F, R>(
+        &mut self,
+        identifier: &TMessageIdentifier,
+        write_body: F,
+    ) -> crate::Result<R>
+    where
+        Self: Sized,
+        F: FnOnce(&mut Self) -> crate::Result<R>,
+    {
+        self.write_message_begin(identifier)?;
+        let result = write_body(self);
+        let end_result = self.write_message_end().and_then(|_| self.flush());
+        let value = result?;
+        end_result?;
+        Ok(value)
+    }
+
+    /// Write `e` as a `TApplicationException` struct.
+    ///
+    /// Only the struct is written; wrap it in an `Exception` message (for
+    /// example with `write_message`) to send it as a reply.
+    fn write_application_error(&mut self, e: &ApplicationError) -> crate::Result<()>
+    where
+        Self: Sized,
+    {
+        crate::errors::write_application_error_struct(e, self)
+    }
+
     // utility (DO NOT USE IN GENERATED CODE!!!!)
     //
 
@@ -427,6 +592,10 @@ where
         (**self).read_string()
     }
 
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        (**self).read_raw_string()
+    }
+
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
         (**self).read_list_begin()
     }
@@ -451,6 +620,10 @@ where
         (**self).read_map_end()
     }
 
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        (**self).skip_till_depth(field_type, depth)
+    }
+
     fn read_byte(&mut self) -> crate::Result<u8> {
         (**self).read_byte()
     }
@@ -458,6 +631,10 @@ where
     fn min_serialized_size(&self, field_type: TType) -> usize {
         (**self).min_serialized_size(field_type)
     }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        (**self).set_configuration(config)
+    }
 }
 
 impl<P> TOutputProtocol for Box<P>
@@ -528,6 +705,10 @@ where
         (**self).write_string(s)
     }
 
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        (**self).write_raw_string(s)
+    }
+
     fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
         (**self).write_list_begin(identifier)
     }
@@ -719,6 +900,17 @@ impl TListIdentifier {
     pub fn new(element_type: TType, size: i32) -> TListIdentifier {
         TListIdentifier { element_type, size }
     }
+
+    /// Number of elements to preallocate when reading this list.
+    ///
+    /// See `container_capacity_hint`.
+    pub fn capacity_hint(&self, config: &TConfiguration) -> usize {
+        container_capacity_hint(
+            config,
+            self.size,
+            compact::compact_protocol_min_serialized_size(self.element_type),
+        )
+    }
 }
 
 /// Thrift set identifier.
@@ -736,6 +928,17 @@ impl TSetIdentifier {
     pub fn new(element_type: TType, size: i32) -> TSetIdentifier {
         TSetIdentifier { element_type, size }
     }
+
+    /// Number of elements to preallocate when reading this set.
+    ///
+    /// See `container_capacity_hint`.
+    pub fn capacity_hint(&self, config: &TConfiguration) -> usize {
+        container_capacity_hint(
+            config,
+            self.size,
+            compact::compact_protocol_min_serialized_size(self.element_type),
+        )
+    }
 }
 
 /// Thrift map identifier.
@@ -763,6 +966,22 @@ impl TMapIdentifier {
             size,
         }
     }
+
+    /// Number of entries to preallocate when reading this map.
+    ///
+    /// Key and value types that are absent (as for an empty map) count as
+    /// the smallest possible encoding. See `container_capacity_hint`.
+    pub fn capacity_hint(&self, config: &TConfiguration) -> usize {
+        let min_size = |t: Option<TType>| {
+            t.map(compact::compact_protocol_min_serialized_size)
+                .unwrap_or(1)
+        };
+        container_capacity_hint(
+            config,
+            self.size,
+            min_size(self.key_type) + min_size(self.value_type),
+        )
+    }
 }
 
 /// Thrift message types.
@@ -997,6 +1216,289 @@ pub(crate) fn check_container_size(
     }
 }
 
+/// Apply the configured `TNonFiniteDoublePolicy` to a double that is about to
+/// be written or has just been read.
+pub(crate) fn check_double(config: &TConfiguration, d: f64) -> crate::Result<f64> {
+    if d.is_finite() {
+        return Ok(d);
+    }
+
+    match config.non_finite_double_policy() {
+        TNonFiniteDoublePolicy::Accept => Ok(d),
+        TNonFiniteDoublePolicy::Reject => Err(crate::Error::Protocol(ProtocolError::new(
+            ProtocolErrorKind::InvalidData,
+            format!("non-finite double {} not allowed", d),
+        ))),
+        TNonFiniteDoublePolicy::Normalize => Ok(if d.is_nan() {
+            0.0
+        } else if d > 0.0 {
+            f64::MAX
+        } else {
+            f64::MIN
+        }),
+    }
+}
+
+/// Convert the bytes of a string field to a `String` according to the
+/// configured `TUtf8DecodePolicy`.
+pub(crate) fn decode_string(config: &TConfiguration, bytes: Vec<u8>) -> crate::Result<String> {
+    let err = match String::from_utf8(bytes) {
+        Ok(s) => return Ok(s),
+        Err(e) => e,
+    };
+
+    match config.utf8_decode_policy() {
+        TUtf8DecodePolicy::Strict => Err(err.into()),
+        TUtf8DecodePolicy::Lossy => Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        TUtf8DecodePolicy::Escape => Ok(escape_invalid_utf8(err.as_bytes())),
+    }
+}
+
+fn escape_invalid_utf8(mut bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut s = String::with_capacity(bytes.len() + 8);
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                s.push_str(valid);
+                return s;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                // `valid` ends at valid_up_to(), so this cannot fail
+                s.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                let invalid_len = e.error_len().unwrap_or(rest.len());
+                for b in &rest[..invalid_len] {
+                    // writing to a String cannot fail
+                    let _ = write!(s, "\\x{:02x}", b);
+                }
+                bytes = &rest[invalid_len..];
+            }
+        }
+    }
+}
+
+/// Number of bytes a single `skip` has consumed, and may consume.
+pub(crate) struct SkipBudget {
+    limit: Option<usize>,
+    consumed: usize,
+}
+
+impl SkipBudget {
+    pub(crate) fn unlimited() -> SkipBudget {
+        SkipBudget {
+            limit: None,
+            consumed: 0,
+        }
+    }
+
+    pub(crate) fn from_config(config: &TConfiguration) -> SkipBudget {
+        SkipBudget {
+            limit: config.max_skip_bytes(),
+            consumed: 0,
+        }
+    }
+
+    fn check(&self, bytes: usize) -> crate::Result<()> {
+        match self.limit {
+            Some(limit) if self.consumed.saturating_add(bytes) > limit => {
+                Err(crate::Error::Protocol(ProtocolError::new(
+                    ProtocolErrorKind::SizeLimit,
+                    format!("skipped field exceeds maximum of {} bytes", limit),
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Fail early if `count` elements of the given types cannot fit.
+    fn check_elements<P>(&self, p: &P, count: i32, types: &[TType]) -> crate::Result<()>
+    where
+        P: TInputProtocol + ?Sized,
+    {
+        if self.limit.is_none() || count <= 0 {
+            return Ok(());
+        }
+        let element_size: usize = types.iter().map(|t| p.min_serialized_size(*t)).sum();
+        self.check((count as usize).saturating_mul(element_size))
+    }
+
+    fn charge(&mut self, bytes: usize) -> crate::Result<()> {
+        self.check(bytes)?;
+        self.consumed = self.consumed.saturating_add(bytes);
+        Ok(())
+    }
+}
+
+/// Skip depth to use for a skip requested to `depth` levels, after applying
+/// the configured `max_skip_depth`.
+pub(crate) fn capped_skip_depth(config: &TConfiguration, depth: i8) -> i8 {
+    match config.max_skip_depth() {
+        Some(max) if max < depth.max(0) as usize => max as i8,
+        _ => depth,
+    }
+}
+
+/// Skip a value of type `field_type`, recursing at most `depth` levels and
+/// charging the bytes skipped to `budget`.
+///
+/// Primitives are charged `min_serialized_size`, strings their length on top
+/// of that. Containers are rejected up front if their elements could not fit
+/// in the remaining budget, so a huge declared size fails before any element
+/// is read.
+pub(crate) fn skip_with_budget<P>(
+    p: &mut P,
+    field_type: TType,
+    depth: i8,
+    budget: &mut SkipBudget,
+) -> crate::Result<()>
+where
+    P: TInputProtocol + ?Sized,
+{
+    if depth == 0 {
+        return Err(crate::Error::Protocol(ProtocolError {
+            kind: ProtocolErrorKind::DepthLimit,
+            message: format!("cannot parse past {:?}", field_type),
+        }));
+    }
+
+    budget.charge(p.min_serialized_size(field_type))?;
+
+    match field_type {
+        TType::Bool => p.read_bool().map(|_| ()),
+        TType::I08 => p.read_i8().map(|_| ()),
+        TType::I16 => p.read_i16().map(|_| ()),
+        TType::I32 => p.read_i32().map(|_| ()),
+        TType::I64 => p.read_i64().map(|_| ()),
+        TType::Double => p.read_double().map(|_| ()),
+        TType::String => {
+            let bytes = p.read_bytes()?;
+            budget.charge(bytes.len())
+        }
+        TType::Uuid => p.read_uuid().map(|_| ()),
+        TType::Struct => {
+            p.read_struct_begin()?;
+            loop {
+                let field_ident = p.read_field_begin()?;
+                if field_ident.field_type == TType::Stop {
+                    break;
+                }
+                skip_with_budget(p, field_ident.field_type, depth - 1, budget)?;
+            }
+            p.read_struct_end()
+        }
+        TType::List => {
+            let list_ident = p.read_list_begin()?;
+            budget.check_elements(p, list_ident.size, &[list_ident.element_type])?;
+            for _ in 0..list_ident.size {
+                skip_with_budget(p, list_ident.element_type, depth - 1, budget)?;
+            }
+            p.read_list_end()
+        }
+        TType::Set => {
+            let set_ident = p.read_set_begin()?;
+            budget.check_elements(p, set_ident.size, &[set_ident.element_type])?;
+            for _ in 0..set_ident.size {
+                skip_with_budget(p, set_ident.element_type, depth - 1, budget)?;
+            }
+            p.read_set_end()
+        }
+        TType::Map => {
+            let map_ident = p.read_map_begin()?;
+            if map_ident.size > 0 {
+                let key_type = map_ident
+                    .key_type
+                    .expect("non-zero sized map should contain key type");
+                let val_type = map_ident
+                    .value_type
+                    .expect("non-zero sized map should contain value type");
+                budget.check_elements(p, map_ident.size, &[key_type, val_type])?;
+            }
+            for _ in 0..map_ident.size {
+                let key_type = map_ident
+                    .key_type
+                    .expect("non-zero sized map should contain key type");
+                let val_type = map_ident
+                    .value_type
+                    .expect("non-zero sized map should contain value type");
+                skip_with_budget(p, key_type, depth - 1, budget)?;
+                skip_with_budget(p, val_type, depth - 1, budget)?;
+            }
+            p.read_map_end()
+        }
+        u => Err(crate::Error::Protocol(ProtocolError {
+            kind: ProtocolErrorKind::Unknown,
+            message: format!("cannot skip field type {:?}", &u),
+        })),
+    }
+}
+
+/// Error returned when a struct end is read or written without a matching
+/// struct begin.
+pub(crate) fn unbalanced_struct_end_error() -> crate::Error {
+    crate::Error::Protocol(ProtocolError::new(
+        ProtocolErrorKind::InvalidState,
+        "struct end without matching struct begin",
+    ))
+}
+
+/// Compute how many elements can safely be preallocated for a container that
+/// claims to hold `container_size` elements, each taking at least
+/// `min_element_size` bytes on the wire.
+///
+/// The size in a container header comes from the remote side, so passing it
+/// straight to `Vec::with_capacity` or `HashMap::with_capacity` lets a
+/// malicious peer force huge allocations. The returned hint is clamped to
+/// the configured maximum container size and to the number of elements
+/// that could fit in a message of the configured maximum size. Negative
+/// sizes yield `0`.
+///
+/// The hint only bounds the allocation: it does not validate the container.
+/// Protocols still reject sizes that exceed `config` when the container
+/// header is read.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::protocol::{container_capacity_hint, TListIdentifier, TType};
+/// use thrift::TConfiguration;
+///
+/// let config = TConfiguration::builder()
+///     .max_message_size(Some(1024))
+///     .max_frame_size(Some(1024))
+///     .build()
+///     .unwrap();
+///
+/// // a list claiming i32::MAX doubles can hold at most 1024 / 8 of them
+/// let list = TListIdentifier::new(TType::Double, i32::MAX);
+/// assert_eq!(list.capacity_hint(&config), 128);
+///
+/// assert_eq!(container_capacity_hint(&config, 16, 8), 16);
+/// assert_eq!(container_capacity_hint(&config, -1, 8), 0);
+/// ```
+pub fn container_capacity_hint(
+    config: &TConfiguration,
+    container_size: i32,
+    min_element_size: usize,
+) -> usize {
+    if container_size <= 0 {
+        return 0;
+    }
+
+    let mut hint = container_size as usize;
+
+    if let Some(max_size) = config.max_container_size() {
+        hint = hint.min(max_size);
+    }
+
+    if let Some(max_message_size) = config.max_message_size() {
+        hint = hint.min(max_message_size / min_element_size.max(1));
+    }
+
+    hint
+}
+
 /// Extract the field id from a Thrift field identifier.
 ///
 /// `field_ident` must *not* have `TFieldIdentifier.field_type` of type `TType::Stop`.
@@ -1133,4 +1635,295 @@ mod tests {
         let data = build_struct_with_unknown_binary_field(&[]);
         assert_eq!(read_struct_skipping_unknown(&data).unwrap(), 42);
     }
+
+    #[test]
+    fn must_clamp_capacity_hint_to_configured_limits() {
+        let config = TConfiguration::builder()
+            .max_message_size(Some(100))
+            .max_frame_size(Some(100))
+            .max_container_size(Some(50))
+            .build()
+            .unwrap();
+
+        // within every limit
+        assert_eq!(container_capacity_hint(&config, 10, 1), 10);
+        // clamped by max container size
+        assert_eq!(container_capacity_hint(&config, 1000, 1), 50);
+        // clamped by the number of elements that fit in a message
+        assert_eq!(container_capacity_hint(&config, 1000, 16), 6);
+        // zero-sized elements are treated as one byte
+        assert_eq!(container_capacity_hint(&config, 1000, 0), 50);
+        // negative and empty containers
+        assert_eq!(container_capacity_hint(&config, -5, 1), 0);
+        assert_eq!(container_capacity_hint(&config, 0, 1), 0);
+    }
+
+    #[test]
+    fn must_not_clamp_capacity_hint_without_limits() {
+        let config = TConfiguration::no_limits();
+        assert_eq!(
+            container_capacity_hint(&config, i32::MAX, 16),
+            i32::MAX as usize
+        );
+    }
+
+    #[test]
+    fn must_compute_capacity_hints_from_identifiers() {
+        let config = TConfiguration::builder()
+            .max_message_size(Some(160))
+            .max_frame_size(Some(160))
+            .build()
+            .unwrap();
+
+        let list = TListIdentifier::new(TType::Uuid, i32::MAX);
+        assert_eq!(list.capacity_hint(&config), 10);
+
+        let set = TSetIdentifier::new(TType::I32, 3);
+        assert_eq!(set.capacity_hint(&config), 3);
+
+        let map = TMapIdentifier::new(TType::Double, TType::Uuid, i32::MAX);
+        assert_eq!(map.capacity_hint(&config), 6);
+
+        let empty_map = TMapIdentifier::new(None, None, 0);
+        assert_eq!(empty_map.capacity_hint(&config), 0);
+    }
+
+    #[test]
+    fn must_pair_message_begin_and_end_through_combinators() {
+        let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+        let ident = TMessageIdentifier::new("foo", TMessageType::Call, 7);
+        let written = o_prot
+            .write_message(&ident, |prot| {
+                prot.write_i32(42)?;
+                Ok("done")
+            })
+            .unwrap();
+        assert_eq!(written, "done");
+
+        let mut i_prot = TBinaryInputProtocol::new(Cursor::new(o_prot.transport), true);
+        let (read_ident, value) = i_prot
+            .read_message(|ident, prot| Ok((ident, prot.read_i32()?)))
+            .unwrap();
+        assert_eq!(read_ident, ident);
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn must_end_and_flush_message_if_body_fails() {
+        struct Recorder {
+            calls: Vec<&'static str>,
+        }
+
+        impl TOutputProtocol for Recorder {
+            fn write_message_begin(&mut self, _: &TMessageIdentifier) -> crate::Result<()> {
+                self.calls.push("message_begin");
+                Ok(())
+            }
+            fn write_message_end(&mut self) -> crate::Result<()> {
+                self.calls.push("message_end");
+                Ok(())
+            }
+            fn write_struct_begin(&mut self, _: &TStructIdentifier) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_struct_end(&mut self) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_field_begin(&mut self, _: &TFieldIdentifier) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_field_end(&mut self) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_field_stop(&mut self) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_bytes(&mut self, _: &[u8]) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_bool(&mut self, _: bool) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_i8(&mut self, _: i8) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_i16(&mut self, _: i16) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_i32(&mut self, _: i32) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_i64(&mut self, _: i64) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_double(&mut self, _: f64) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_string(&mut self, _: &str) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_uuid(&mut self, _: &uuid::Uuid) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_list_begin(&mut self, _: &TListIdentifier) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_list_end(&mut self) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_set_begin(&mut self, _: &TSetIdentifier) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_set_end(&mut self) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_map_begin(&mut self, _: &TMapIdentifier) -> crate::Result<()> {
+                Ok(())
+            }
+            fn write_map_end(&mut self) -> crate::Result<()> {
+                Ok(())
+            }
+            fn flush(&mut self) -> crate::Result<()> {
+                self.calls.push("flush");
+                Ok(())
+            }
+            fn write_byte(&mut self, _: u8) -> crate::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut o_prot = Recorder { calls: Vec::new() };
+        let ident = TMessageIdentifier::new("foo", TMessageType::Call, 1);
+        let result: crate::Result<()> = o_prot.write_message(&ident, |_| {
+            Err(crate::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidData,
+                "body failed",
+            )))
+        });
+
+        match result {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.message, "body failed"),
+            other => panic!("expected body error, got {:?}", other),
+        }
+        assert_eq!(o_prot.calls, vec!["message_begin", "message_end", "flush"]);
+    }
+
+    fn utf8_policy_config(policy: TUtf8DecodePolicy) -> TConfiguration {
+        TConfiguration::builder()
+            .utf8_decode_policy(policy)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn must_decode_strings_according_to_utf8_policy() {
+        // "caf\xe9" in latin-1 followed by valid multi-byte UTF-8
+        let latin1 = b"caf\xe9 \xe2\x82\xac".to_vec();
+
+        let strict = utf8_policy_config(TUtf8DecodePolicy::Strict);
+        assert!(matches!(
+            decode_string(&strict, latin1.clone()),
+            Err(crate::Error::Protocol(_))
+        ));
+
+        let lossy = utf8_policy_config(TUtf8DecodePolicy::Lossy);
+        assert_eq!(
+            decode_string(&lossy, latin1.clone()).unwrap(),
+            "caf\u{FFFD} \u{20AC}"
+        );
+
+        let escape = utf8_policy_config(TUtf8DecodePolicy::Escape);
+        assert_eq!(decode_string(&escape, latin1).unwrap(), "caf\\xe9 \u{20AC}");
+        assert_eq!(
+            decode_string(&escape, vec![0xFF, 0xFE, b'a', 0xE2, 0x82]).unwrap(),
+            "\\xff\\xfea\\xe2\\x82"
+        );
+        assert_eq!(decode_string(&escape, b"ok".to_vec()).unwrap(), "ok");
+    }
+
+    #[test]
+    fn must_round_trip_application_error_in_exception_message() {
+        let error = ApplicationError::new(
+            crate::ApplicationErrorKind::UnknownMethod,
+            "no such method: frobnicate",
+        );
+        let ident = TMessageIdentifier::new("frobnicate", TMessageType::Exception, 3);
+
+        let mut buf = Vec::new();
+        let mut o_prot = TCompactOutputProtocol::new(&mut buf);
+        o_prot
+            .write_message(&ident, |prot| prot.write_application_error(&error))
+            .unwrap();
+
+        let mut i_prot = TCompactInputProtocol::new(&buf[..]);
+        let (read_ident, read_error) = i_prot
+            .read_message(|ident, prot| Ok((ident, prot.read_application_error()?)))
+            .unwrap();
+        assert_eq!(read_ident, ident);
+        assert_eq!(read_error, error);
+    }
+
+    #[test]
+    fn must_write_same_application_error_bytes_as_error_helper() {
+        let error = ApplicationError::new(crate::ApplicationErrorKind::InternalError, "boom");
+
+        let mut expected = Vec::new();
+        crate::Error::write_application_error_to_out_protocol(
+            &error,
+            &mut TBinaryOutputProtocol::new(&mut expected, true),
+        )
+        .unwrap();
+
+        let mut actual = Vec::new();
+        TBinaryOutputProtocol::new(&mut actual, true)
+            .write_application_error(&error)
+            .unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn must_forward_raw_strings_without_validation() {
+        let latin1 = [b'c', b'a', b'f', 0xE9];
+
+        let mut binary = Vec::new();
+        TBinaryOutputProtocol::new(&mut binary, true)
+            .write_bytes(&latin1)
+            .unwrap();
+
+        // binary -> compact, as a write-through proxy would
+        let mut compact = Vec::new();
+        {
+            let mut i_prot = TBinaryInputProtocol::new(&binary[..], true);
+            let mut o_prot = TCompactOutputProtocol::new(&mut compact);
+            let raw = i_prot.read_raw_string().unwrap();
+            assert!(!raw.is_utf8());
+            o_prot.write_raw_string(&raw).unwrap();
+        }
+
+        assert!(TCompactInputProtocol::new(&compact[..])
+            .read_string()
+            .is_err());
+        let raw = TCompactInputProtocol::new(&compact[..])
+            .read_raw_string()
+            .unwrap();
+        assert_eq!(raw.as_bytes(), &latin1);
+    }
+
+    #[test]
+    fn must_enforce_string_size_limit_on_raw_strings() {
+        let mut buf = Vec::new();
+        TCompactOutputProtocol::new(&mut buf)
+            .write_string("too long")
+            .unwrap();
+
+        let config = TConfiguration::builder()
+            .max_string_size(Some(4))
+            .build()
+            .unwrap();
+        match TCompactInputProtocol::with_config(&buf[..], config).read_raw_string() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::SizeLimit),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+    }
 }
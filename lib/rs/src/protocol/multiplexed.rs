@@ -17,7 +17,7 @@
 
 use super::{
     TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TMessageType,
-    TOutputProtocol, TSetIdentifier, TStructIdentifier,
+    TOutputProtocol, TRawString, TSetIdentifier, TStructIdentifier,
 };
 
 /// `TOutputProtocol` that prefixes the service name to all outgoing Thrift
@@ -152,6 +152,10 @@ where
         self.inner.write_string(s)
     }
 
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.inner.write_raw_string(s)
+    }
+
     fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
         self.inner.write_uuid(uuid)
     }
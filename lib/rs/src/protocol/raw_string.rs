@@ -0,0 +1,150 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::borrow::Cow;
+use std::str;
+
+/// The undecoded bytes of a Thrift `string` value.
+///
+/// Returned by `TInputProtocol::read_raw_string`, which skips the UTF-8
+/// validation that `read_string` performs. The bytes are exactly what the
+/// remote sent: they are *not* guaranteed to be valid UTF-8, and nothing in
+/// this type assumes they are. Validate with `to_str` or `into_string` before
+/// treating the value as text, or pass it on unchanged with
+/// `TOutputProtocol::write_raw_string`.
+///
+/// This lets proxies and other write-through code forward strings without
+/// paying for validation, and lets readers validate every string of a struct
+/// in one place after decoding it.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::protocol::{TCompactInputProtocol, TCompactOutputProtocol};
+/// use thrift::protocol::{TInputProtocol, TOutputProtocol};
+///
+/// let mut buf = Vec::new();
+/// TCompactOutputProtocol::new(&mut buf).write_string("hello").unwrap();
+///
+/// let mut protocol = TCompactInputProtocol::new(&buf[..]);
+/// let raw = protocol.read_raw_string().unwrap();
+/// assert_eq!(raw.as_bytes(), b"hello");
+/// assert_eq!(raw.to_str().unwrap(), "hello");
+/// ```
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TRawString(Vec<u8>);
+
+impl TRawString {
+    /// Wrap `bytes` without validating them.
+    pub fn from_bytes(bytes: Vec<u8>) -> TRawString {
+        TRawString(bytes)
+    }
+
+    /// The raw bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Consume this value and return the raw bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Whether the bytes are valid UTF-8.
+    pub fn is_utf8(&self) -> bool {
+        str::from_utf8(&self.0).is_ok()
+    }
+
+    /// Validate the bytes and borrow them as a `&str`.
+    ///
+    /// Fails with `ProtocolErrorKind::InvalidData`, as `read_string` would,
+    /// if they are not valid UTF-8.
+    pub fn to_str(&self) -> crate::Result<&str> {
+        str::from_utf8(&self.0).map_err(|e| {
+            crate::Error::Protocol(crate::ProtocolError::new(
+                crate::ProtocolErrorKind::InvalidData,
+                e.to_string(),
+            ))
+        })
+    }
+
+    /// Validate the bytes and convert them into a `String` without copying.
+    ///
+    /// Fails with `ProtocolErrorKind::InvalidData`, as `read_string` would,
+    /// if they are not valid UTF-8.
+    pub fn into_string(self) -> crate::Result<String> {
+        String::from_utf8(self.0).map_err(From::from)
+    }
+
+    /// Convert the bytes to text, replacing invalid sequences with
+    /// `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+}
+
+impl From<Vec<u8>> for TRawString {
+    fn from(bytes: Vec<u8>) -> Self {
+        TRawString(bytes)
+    }
+}
+
+impl From<String> for TRawString {
+    fn from(s: String) -> Self {
+        TRawString(s.into_bytes())
+    }
+}
+
+impl From<&str> for TRawString {
+    fn from(s: &str) -> Self {
+        TRawString(s.as_bytes().to_vec())
+    }
+}
+
+impl AsRef<[u8]> for TRawString {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::ProtocolErrorKind;
+
+    #[test]
+    fn must_validate_only_on_request() {
+        let raw = TRawString::from_bytes(vec![b'a', 0xFF, b'b']);
+        assert!(!raw.is_utf8());
+        assert_eq!(raw.as_bytes(), &[b'a', 0xFF, b'b']);
+        assert_eq!(raw.to_string_lossy(), "a\u{FFFD}b");
+
+        match raw.to_str() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+        match raw.into_string() {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+
+        let raw = TRawString::from("caf\u{e9}");
+        assert!(raw.is_utf8());
+        assert_eq!(raw.into_string().unwrap(), "caf\u{e9}");
+    }
+}
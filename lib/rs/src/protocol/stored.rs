@@ -19,9 +19,9 @@ use std::convert::Into;
 
 use super::{
     TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
-    TSetIdentifier, TStructIdentifier,
+    TRawString, TSetIdentifier, TStructIdentifier, TType,
 };
-use crate::ProtocolErrorKind;
+use crate::{ProtocolErrorKind, TConfiguration};
 
 /// `TInputProtocol` required to use a `TMultiplexedProcessor`.
 ///
@@ -170,6 +170,10 @@ impl<'a> TInputProtocol for TStoredInputProtocol<'a> {
         self.inner.read_string()
     }
 
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        self.inner.read_raw_string()
+    }
+
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
         self.inner.read_list_begin()
     }
@@ -194,9 +198,21 @@ impl<'a> TInputProtocol for TStoredInputProtocol<'a> {
         self.inner.read_map_end()
     }
 
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        self.inner.skip_till_depth(field_type, depth)
+    }
+
     // utility
     //
 
+    fn min_serialized_size(&self, field_type: TType) -> usize {
+        self.inner.min_serialized_size(field_type)
+    }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        self.inner.set_configuration(config)
+    }
+
     fn read_byte(&mut self) -> crate::Result<u8> {
         self.inner.read_byte()
     }
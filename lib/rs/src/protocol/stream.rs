@@ -0,0 +1,337 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::{TInputProtocol, TOutputProtocol, TSerializable};
+use crate::TransportErrorKind;
+
+/// Readable source for a `TStructStreamReader`.
+///
+/// Wraps the underlying reader and counts the bytes read from it, which is
+/// how the stream reader tells a clean end of stream (no bytes left before
+/// the next struct) from a struct that was cut short.
+#[derive(Debug)]
+pub struct TStructStreamSource<R>
+where
+    R: Read,
+{
+    inner: R,
+    consumed: Arc<AtomicU64>,
+}
+
+impl<R> Read for TStructStreamSource<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.consumed.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Reads a sequence of bare Thrift structs, without message envelopes, from
+/// a stream.
+///
+/// This is the layout used by Thrift-encoded log files and by message
+/// queues carrying one or more Thrift structs per payload. Structs are
+/// decoded one at a time as the reader is iterated. Iteration ends when the
+/// source is exhausted exactly at a struct boundary; running out of bytes
+/// in the middle of a struct yields an error.
+///
+/// The protocol must read from the `TStructStreamSource` handed to the
+/// constructor. If buffering is needed, buffer the underlying reader (for
+/// example with `std::io::BufReader`) instead of wrapping the source in a
+/// buffered transport, so that the end of the stream is detected correctly.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// use thrift::protocol::{TCompactInputProtocol, TSerializable, TStructStreamReader};
+/// # use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// # struct LogEntry;
+/// # impl TSerializable for LogEntry {
+/// #     fn read_from_in_protocol(_: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+/// #         unimplemented!()
+/// #     }
+/// #     fn write_to_out_protocol(&self, _: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+/// #         unimplemented!()
+/// #     }
+/// # }
+///
+/// let file = BufReader::new(File::open("entries.log").unwrap());
+/// let entries: TStructStreamReader<_, LogEntry> =
+///     TStructStreamReader::new(file, TCompactInputProtocol::new);
+///
+/// for entry in entries {
+///     let entry = entry.unwrap();
+///     // process entry
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TStructStreamReader<P, T>
+where
+    P: TInputProtocol,
+{
+    i_prot: P,
+    consumed: Arc<AtomicU64>,
+    done: bool,
+    _struct: PhantomData<fn() -> T>,
+}
+
+impl<P, T> TStructStreamReader<P, T>
+where
+    P: TInputProtocol,
+    T: TSerializable,
+{
+    /// Create a `TStructStreamReader` that decodes structs from `source`
+    /// using the protocol built by `make_protocol`.
+    pub fn new<R, F>(source: R, make_protocol: F) -> Self
+    where
+        R: Read,
+        F: FnOnce(TStructStreamSource<R>) -> P,
+    {
+        let consumed = Arc::new(AtomicU64::new(0));
+        let source = TStructStreamSource {
+            inner: source,
+            consumed: consumed.clone(),
+        };
+        TStructStreamReader {
+            i_prot: make_protocol(source),
+            consumed,
+            done: false,
+            _struct: PhantomData,
+        }
+    }
+
+    /// Consume this reader and return the protocol it reads from.
+    pub fn into_inner(self) -> P {
+        self.i_prot
+    }
+}
+
+impl<P, T> Iterator for TStructStreamReader<P, T>
+where
+    P: TInputProtocol,
+    T: TSerializable,
+{
+    type Item = crate::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let consumed_before = self.consumed.load(Ordering::Relaxed);
+        match T::read_from_in_protocol(&mut self.i_prot) {
+            Ok(t) => Some(Ok(t)),
+            Err(crate::Error::Transport(ref e))
+                if e.kind == TransportErrorKind::EndOfFile
+                    && self.consumed.load(Ordering::Relaxed) == consumed_before =>
+            {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Writes a sequence of bare Thrift structs, without message envelopes, to
+/// a stream.
+///
+/// The counterpart of `TStructStreamReader`: structs are written back to
+/// back, and can be read again with a `TStructStreamReader` that uses the
+/// matching protocol.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufWriter;
+///
+/// use thrift::protocol::{TCompactOutputProtocol, TSerializable, TStructStreamWriter};
+/// # use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// # struct LogEntry;
+/// # impl TSerializable for LogEntry {
+/// #     fn read_from_in_protocol(_: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+/// #         unimplemented!()
+/// #     }
+/// #     fn write_to_out_protocol(&self, _: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+/// #         unimplemented!()
+/// #     }
+/// # }
+///
+/// let file = BufWriter::new(File::create("entries.log").unwrap());
+/// let mut writer = TStructStreamWriter::new(TCompactOutputProtocol::new(file));
+///
+/// writer.write(&LogEntry).unwrap();
+/// writer.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TStructStreamWriter<P>
+where
+    P: TOutputProtocol,
+{
+    o_prot: P,
+}
+
+impl<P> TStructStreamWriter<P>
+where
+    P: TOutputProtocol,
+{
+    /// Create a `TStructStreamWriter` that writes structs to `o_prot`.
+    pub fn new(o_prot: P) -> Self {
+        TStructStreamWriter { o_prot }
+    }
+
+    /// Append `t` to the stream.
+    pub fn write<T: TSerializable>(&mut self, t: &T) -> crate::Result<()> {
+        t.write_to_out_protocol(&mut self.o_prot)
+    }
+
+    /// Flush buffered bytes to the underlying transport.
+    pub fn flush(&mut self) -> crate::Result<()> {
+        self.o_prot.flush()
+    }
+
+    /// Consume this writer and return the protocol it writes to.
+    pub fn into_inner(self) -> P {
+        self.o_prot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::{
+        field_id, TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol,
+        TCompactOutputProtocol, TFieldIdentifier, TStructIdentifier, TType,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl TSerializable for Point {
+        fn read_from_in_protocol(i_prot: &mut dyn TInputProtocol) -> crate::Result<Self> {
+            let mut point = Point { x: 0, y: 0 };
+            i_prot.read_struct_begin()?;
+            loop {
+                let field_ident = i_prot.read_field_begin()?;
+                if field_ident.field_type == TType::Stop {
+                    break;
+                }
+                match field_id(&field_ident)? {
+                    1 => point.x = i_prot.read_i32()?,
+                    2 => point.y = i_prot.read_i32()?,
+                    _ => i_prot.skip(field_ident.field_type)?,
+                }
+                i_prot.read_field_end()?;
+            }
+            i_prot.read_struct_end()?;
+            Ok(point)
+        }
+
+        fn write_to_out_protocol(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+            o_prot.write_struct_begin(&TStructIdentifier::new("Point"))?;
+            o_prot.write_field_begin(&TFieldIdentifier::new("x", TType::I32, 1))?;
+            o_prot.write_i32(self.x)?;
+            o_prot.write_field_end()?;
+            o_prot.write_field_begin(&TFieldIdentifier::new("y", TType::I32, 2))?;
+            o_prot.write_i32(self.y)?;
+            o_prot.write_field_end()?;
+            o_prot.write_field_stop()?;
+            o_prot.write_struct_end()
+        }
+    }
+
+    fn points() -> Vec<Point> {
+        (0..3).map(|i| Point { x: i, y: -i }).collect()
+    }
+
+    #[test]
+    fn must_round_trip_struct_stream_with_compact_protocol() {
+        let mut buf = Vec::new();
+        let mut writer = TStructStreamWriter::new(TCompactOutputProtocol::new(&mut buf));
+        for p in points() {
+            writer.write(&p).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let reader = TStructStreamReader::new(&buf[..], TCompactInputProtocol::new);
+        let read: Vec<Point> = reader.collect::<crate::Result<_>>().unwrap();
+        assert_eq!(read, points());
+    }
+
+    #[test]
+    fn must_round_trip_struct_stream_with_binary_protocol() {
+        let mut buf = Vec::new();
+        let mut writer = TStructStreamWriter::new(TBinaryOutputProtocol::new(&mut buf, true));
+        for p in points() {
+            writer.write(&p).unwrap();
+        }
+
+        let reader = TStructStreamReader::new(&buf[..], |s| TBinaryInputProtocol::new(s, true));
+        let read: Vec<Point> = reader.collect::<crate::Result<_>>().unwrap();
+        assert_eq!(read, points());
+    }
+
+    #[test]
+    fn must_yield_nothing_for_empty_stream() {
+        let mut reader: TStructStreamReader<_, Point> =
+            TStructStreamReader::new(&[][..], TCompactInputProtocol::new);
+        assert!(reader.next().is_none());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn must_fail_on_truncated_struct() {
+        let mut buf = Vec::new();
+        let mut writer = TStructStreamWriter::new(TCompactOutputProtocol::new(&mut buf));
+        for p in points() {
+            writer.write(&p).unwrap();
+        }
+        buf.pop();
+
+        let mut reader: TStructStreamReader<_, Point> =
+            TStructStreamReader::new(&buf[..], TCompactInputProtocol::new);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next() {
+            Some(Err(crate::Error::Transport(e))) => {
+                assert_eq!(e.kind, TransportErrorKind::EndOfFile)
+            }
+            other => panic!("expected end-of-file error, got {:?}", other),
+        }
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,977 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Conformance tests for `TInputProtocol`/`TOutputProtocol` implementations.
+//!
+//! This module is only available with the `testsuite` feature. It exposes
+//! the corpus of test cases the built-in protocols are checked against, so
+//! that custom protocol implementations can be validated in the same way.
+//!
+//! Implement `TProtocolUnderTest` to tell the suite how to create your
+//! protocols, then call `run_round_trip_tests` from a test. If your protocol
+//! is wire-compatible with one of the built-in protocols also call
+//! `run_golden_tests` with `BINARY_GOLDEN` or `COMPACT_GOLDEN`. Both
+//! functions panic with the name of the first failing case.
+//!
+//! # Examples
+//!
+//! ```
+//! use thrift::protocol::testsuite::{self, TProtocolUnderTest};
+//! use thrift::protocol::{
+//!     TCompactInputProtocol, TCompactOutputProtocol, TInputProtocol, TOutputProtocol,
+//! };
+//!
+//! struct Compact;
+//!
+//! impl TProtocolUnderTest for Compact {
+//!     fn output_protocol<'a>(&self, buf: &'a mut Vec<u8>) -> Box<dyn TOutputProtocol + 'a> {
+//!         Box::new(TCompactOutputProtocol::new(buf))
+//!     }
+//!
+//!     fn input_protocol<'a>(&self, buf: &'a [u8]) -> Box<dyn TInputProtocol + 'a> {
+//!         Box::new(TCompactInputProtocol::new(buf))
+//!     }
+//! }
+//!
+//! testsuite::run_round_trip_tests(&Compact);
+//! testsuite::run_golden_tests(&Compact, testsuite::COMPACT_GOLDEN);
+//! ```
+
+use super::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TMessageType, TOutputProtocol, TSetIdentifier, TStructIdentifier, TType,
+};
+
+/// Creates the protocol pair exercised by the test suite.
+pub trait TProtocolUnderTest {
+    /// Create an output protocol that appends everything it writes to `buf`.
+    fn output_protocol<'a>(&self, buf: &'a mut Vec<u8>) -> Box<dyn TOutputProtocol + 'a>;
+    /// Create an input protocol that reads from `buf`.
+    fn input_protocol<'a>(&self, buf: &'a [u8]) -> Box<dyn TInputProtocol + 'a>;
+}
+
+/// A single conformance test case.
+///
+/// `write` encodes a value and `verify` decodes it again, returning an error
+/// if anything read differs from what was written.
+pub struct TTestCase {
+    /// Unique name of the case, used in golden tables and failure messages.
+    pub name: &'static str,
+    /// Write the value under test.
+    pub write: fn(&mut dyn TOutputProtocol) -> crate::Result<()>,
+    /// Read the value written by `write` and check it.
+    pub verify: fn(&mut dyn TInputProtocol) -> crate::Result<()>,
+}
+
+/// Every test case in the suite.
+pub static TEST_CASES: &[TTestCase] = &[
+    TTestCase {
+        name: "bool",
+        write: write_bool,
+        verify: verify_bool,
+    },
+    TTestCase {
+        name: "i8",
+        write: write_i8,
+        verify: verify_i8,
+    },
+    TTestCase {
+        name: "i16",
+        write: write_i16,
+        verify: verify_i16,
+    },
+    TTestCase {
+        name: "i32",
+        write: write_i32,
+        verify: verify_i32,
+    },
+    TTestCase {
+        name: "i64",
+        write: write_i64,
+        verify: verify_i64,
+    },
+    TTestCase {
+        name: "double",
+        write: write_double,
+        verify: verify_double,
+    },
+    TTestCase {
+        name: "string",
+        write: write_string,
+        verify: verify_string,
+    },
+    TTestCase {
+        name: "binary",
+        write: write_binary,
+        verify: verify_binary,
+    },
+    TTestCase {
+        name: "uuid",
+        write: write_uuid,
+        verify: verify_uuid,
+    },
+    TTestCase {
+        name: "message",
+        write: write_message,
+        verify: verify_message,
+    },
+    TTestCase {
+        name: "empty_struct",
+        write: write_empty_struct,
+        verify: verify_empty_struct,
+    },
+    TTestCase {
+        name: "struct_fields",
+        write: write_struct_fields,
+        verify: verify_struct_fields,
+    },
+    TTestCase {
+        name: "list",
+        write: write_list,
+        verify: verify_list,
+    },
+    TTestCase {
+        name: "bool_list",
+        write: write_bool_list,
+        verify: verify_bool_list,
+    },
+    TTestCase {
+        name: "set",
+        write: write_set,
+        verify: verify_set,
+    },
+    TTestCase {
+        name: "map",
+        write: write_map,
+        verify: verify_map,
+    },
+    TTestCase {
+        name: "empty_containers",
+        write: write_empty_containers,
+        verify: verify_empty_containers,
+    },
+    TTestCase {
+        name: "nested_containers",
+        write: write_nested_containers,
+        verify: verify_nested_containers,
+    },
+    TTestCase {
+        name: "skip",
+        write: write_struct_fields,
+        verify: verify_skip,
+    },
+];
+
+/// Expected binary protocol (strict) encoding of each case in `TEST_CASES`.
+pub static BINARY_GOLDEN: &[(&str, &[u8])] = &[
+    ("bool", &[0x01, 0x00]),
+    ("i8", &[0x00, 0xFF, 0x80, 0x7F]),
+    (
+        "i16",
+        &[0x00, 0x00, 0xFF, 0xFF, 0x01, 0x2C, 0x80, 0x00, 0x7F, 0xFF],
+    ),
+    (
+        "i32",
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x01,
+            0x11, 0x70, 0x80, 0x00, 0x00, 0x00, 0x7F, 0xFF, 0xFF, 0xFF,
+        ],
+    ),
+    (
+        "i64",
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x01,
+            0x2A, 0x05, 0xF2, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7F, 0xFF,
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ],
+    ),
+    (
+        "double",
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xBF, 0xF8, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x40, 0x09, 0x21, 0xFB, 0x54, 0x44, 0x2D, 0x18, 0x7F, 0xEF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0xFF, 0xFF,
+        ],
+    ),
+    (
+        "string",
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0x00,
+            0x00, 0x00, 0x0A, 0x68, 0xC3, 0xA9, 0x6C, 0x6C, 0x6F, 0x20, 0xE2, 0x9C, 0x93,
+        ],
+    ),
+    (
+        "binary",
+        &[0x00, 0x00, 0x00, 0x05, 0x00, 0x01, 0x7F, 0x80, 0xFF],
+    ),
+    (
+        "uuid",
+        &[
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ],
+    ),
+    (
+        "message",
+        &[
+            0x80, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x70, 0x69, 0x6E, 0x67, 0x00, 0x00,
+            0x00, 0x01, 0x80, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x70, 0x69, 0x6E, 0x67,
+            0x00, 0x00, 0x00, 0x01, 0x80, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x66, 0x61,
+            0x69, 0x6C, 0x7F, 0xFF, 0xFF, 0xFF, 0x80, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+            0xFF, 0xFF, 0xFF, 0xFF,
+        ],
+    ),
+    ("empty_struct", &[0x00]),
+    (
+        "struct_fields",
+        &[
+            0x03, 0x00, 0x01, 0xF8, 0x02, 0x00, 0x02, 0x01, 0x02, 0x00, 0x03, 0x00, 0x08, 0x00,
+            0x14, 0x00, 0x01, 0x11, 0x70, 0x0B, 0x00, 0x15, 0x00, 0x00, 0x00, 0x05, 0x66, 0x69,
+            0x65, 0x6C, 0x64, 0x0A, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFB,
+            0x0C, 0x00, 0x1E, 0x02, 0x00, 0x01, 0x01, 0x04, 0x00, 0x02, 0x3F, 0xE0, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x1F, 0xFE, 0xD4, 0x0F, 0x00, 0x20, 0x03,
+            0x00, 0x00, 0x00, 0x02, 0x01, 0x02, 0x10, 0x00, 0x21, 0x00, 0x11, 0x22, 0x33, 0x44,
+            0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF, 0x00,
+        ],
+    ),
+    (
+        "list",
+        &[
+            0x08, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xFF,
+            0xFF, 0xFF, 0xFF, 0x00, 0x01, 0x11, 0x70, 0x80, 0x00, 0x00, 0x00, 0x7F, 0xFF, 0xFF,
+            0xFF,
+        ],
+    ),
+    (
+        "bool_list",
+        &[0x02, 0x00, 0x00, 0x00, 0x03, 0x01, 0x00, 0x01],
+    ),
+    (
+        "set",
+        &[
+            0x0B, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x68,
+            0x65, 0x6C, 0x6C, 0x6F, 0x00, 0x00, 0x00, 0x0A, 0x68, 0xC3, 0xA9, 0x6C, 0x6C, 0x6F,
+            0x20, 0xE2, 0x9C, 0x93,
+        ],
+    ),
+    (
+        "map",
+        &[
+            0x0B, 0x0A, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x6F, 0x6E, 0x65, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x09, 0x6D, 0x69, 0x6E,
+            0x75, 0x73, 0x20, 0x6F, 0x6E, 0x65, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ],
+    ),
+    (
+        "empty_containers",
+        &[
+            0x0B, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x08, 0x04, 0x00, 0x00,
+            0x00, 0x00,
+        ],
+    ),
+    (
+        "nested_containers",
+        &[
+            0x0D, 0x00, 0x00, 0x00, 0x02, 0x08, 0x0F, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x07, 0x02, 0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x08, 0x0F, 0x00, 0x00, 0x00, 0x01,
+            0xFF, 0xFF, 0xFF, 0xF9, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01,
+        ],
+    ),
+    (
+        "skip",
+        &[
+            0x03, 0x00, 0x01, 0xF8, 0x02, 0x00, 0x02, 0x01, 0x02, 0x00, 0x03, 0x00, 0x08, 0x00,
+            0x14, 0x00, 0x01, 0x11, 0x70, 0x0B, 0x00, 0x15, 0x00, 0x00, 0x00, 0x05, 0x66, 0x69,
+            0x65, 0x6C, 0x64, 0x0A, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFB,
+            0x0C, 0x00, 0x1E, 0x02, 0x00, 0x01, 0x01, 0x04, 0x00, 0x02, 0x3F, 0xE0, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x1F, 0xFE, 0xD4, 0x0F, 0x00, 0x20, 0x03,
+            0x00, 0x00, 0x00, 0x02, 0x01, 0x02, 0x10, 0x00, 0x21, 0x00, 0x11, 0x22, 0x33, 0x44,
+            0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF, 0x00,
+        ],
+    ),
+];
+
+/// Expected compact protocol encoding of each case in `TEST_CASES`.
+pub static COMPACT_GOLDEN: &[(&str, &[u8])] = &[
+    ("bool", &[0x01, 0x02]),
+    ("i8", &[0x00, 0xFF, 0x80, 0x7F]),
+    (
+        "i16",
+        &[0x00, 0x01, 0xD8, 0x04, 0xFF, 0xFF, 0x03, 0xFE, 0xFF, 0x03],
+    ),
+    (
+        "i32",
+        &[
+            0x00, 0x02, 0x01, 0xE0, 0xC5, 0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0xFE, 0xFF, 0xFF,
+            0xFF, 0x0F,
+        ],
+    ),
+    (
+        "i64",
+        &[
+            0x00, 0x02, 0x01, 0x80, 0xC8, 0xAF, 0xA0, 0x25, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0xFF, 0x01, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01,
+        ],
+    ),
+    (
+        "double",
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xF8, 0xBF, 0x18, 0x2D, 0x44, 0x54, 0xFB, 0x21, 0x09, 0x40, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0xEF, 0x7F,
+        ],
+    ),
+    (
+        "string",
+        &[
+            0x00, 0x05, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0x0A, 0x68, 0xC3, 0xA9, 0x6C, 0x6C, 0x6F,
+            0x20, 0xE2, 0x9C, 0x93,
+        ],
+    ),
+    ("binary", &[0x05, 0x00, 0x01, 0x7F, 0x80, 0xFF]),
+    (
+        "uuid",
+        &[
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ],
+    ),
+    (
+        "message",
+        &[
+            0x82, 0x21, 0x01, 0x04, 0x70, 0x69, 0x6E, 0x67, 0x82, 0x41, 0x01, 0x04, 0x70, 0x69,
+            0x6E, 0x67, 0x82, 0x61, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, 0x04, 0x66, 0x61, 0x69, 0x6C,
+            0x82, 0x81, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x00,
+        ],
+    ),
+    ("empty_struct", &[0x00]),
+    (
+        "struct_fields",
+        &[
+            0x13, 0xF8, 0x11, 0x12, 0x05, 0x28, 0xE0, 0xC5, 0x08, 0x18, 0x05, 0x66, 0x69, 0x65,
+            0x6C, 0x64, 0x06, 0x0A, 0x09, 0x0C, 0x3C, 0x11, 0x17, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0xE0, 0x3F, 0x00, 0x14, 0xD7, 0x04, 0x19, 0x23, 0x01, 0x02, 0x1D, 0x00, 0x11,
+            0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF,
+            0x00,
+        ],
+    ),
+    (
+        "list",
+        &[
+            0x65, 0x00, 0x02, 0x01, 0xE0, 0xC5, 0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0xFE, 0xFF,
+            0xFF, 0xFF, 0x0F,
+        ],
+    ),
+    ("bool_list", &[0x31, 0x01, 0x02, 0x01]),
+    (
+        "set",
+        &[
+            0x38, 0x00, 0x05, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0x0A, 0x68, 0xC3, 0xA9, 0x6C, 0x6C,
+            0x6F, 0x20, 0xE2, 0x9C, 0x93,
+        ],
+    ),
+    (
+        "map",
+        &[
+            0x02, 0x86, 0x03, 0x6F, 0x6E, 0x65, 0x02, 0x09, 0x6D, 0x69, 0x6E, 0x75, 0x73, 0x20,
+            0x6F, 0x6E, 0x65, 0x01,
+        ],
+    ),
+    ("empty_containers", &[0x08, 0x06, 0x00]),
+    (
+        "nested_containers",
+        &[
+            0x2B, 0x01, 0x59, 0x0E, 0x21, 0x01, 0x02, 0x01, 0x59, 0x0D, 0x21, 0x02, 0x01,
+        ],
+    ),
+    (
+        "skip",
+        &[
+            0x13, 0xF8, 0x11, 0x12, 0x05, 0x28, 0xE0, 0xC5, 0x08, 0x18, 0x05, 0x66, 0x69, 0x65,
+            0x6C, 0x64, 0x06, 0x0A, 0x09, 0x0C, 0x3C, 0x11, 0x17, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0xE0, 0x3F, 0x00, 0x14, 0xD7, 0x04, 0x19, 0x23, 0x01, 0x02, 0x1D, 0x00, 0x11,
+            0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF,
+            0x00,
+        ],
+    ),
+];
+
+/// Write every case in `TEST_CASES` with `protocol`, read it back and check
+/// that the decoded values match and that all written bytes were consumed.
+///
+/// # Panics
+///
+/// Panics if any case fails.
+pub fn run_round_trip_tests<P: TProtocolUnderTest + ?Sized>(protocol: &P) {
+    for case in TEST_CASES {
+        let buf = encode(protocol, case);
+        if let Err(e) = decode(protocol, case, &buf) {
+            panic!("test case '{}' failed: {}", case.name, e);
+        }
+    }
+}
+
+/// Check that `protocol` writes exactly the bytes listed in `golden` for
+/// each named case, and that it can read those bytes back.
+///
+/// # Panics
+///
+/// Panics if any case fails or `golden` names an unknown case.
+pub fn run_golden_tests<P: TProtocolUnderTest + ?Sized>(protocol: &P, golden: &[(&str, &[u8])]) {
+    for (name, expected) in golden {
+        let case = TEST_CASES
+            .iter()
+            .find(|c| c.name == *name)
+            .unwrap_or_else(|| panic!("unknown test case '{}'", name));
+
+        let actual = encode(protocol, case);
+        assert!(
+            actual == *expected,
+            "test case '{}' wrote {:02x?}, expected {:02x?}",
+            name,
+            actual,
+            expected
+        );
+        if let Err(e) = decode(protocol, case, expected) {
+            panic!("test case '{}' failed to read golden bytes: {}", name, e);
+        }
+    }
+}
+
+fn encode<P: TProtocolUnderTest + ?Sized>(protocol: &P, case: &TTestCase) -> Vec<u8> {
+    let mut buf = Vec::new();
+    {
+        let mut o_prot = protocol.output_protocol(&mut buf);
+        if let Err(e) = (case.write)(&mut *o_prot).and_then(|_| o_prot.flush()) {
+            panic!("test case '{}' failed to write: {}", case.name, e);
+        }
+    }
+    buf
+}
+
+fn decode<P: TProtocolUnderTest + ?Sized>(
+    protocol: &P,
+    case: &TTestCase,
+    buf: &[u8],
+) -> crate::Result<()> {
+    let mut i_prot = protocol.input_protocol(buf);
+    (case.verify)(&mut *i_prot)?;
+    match i_prot.read_byte() {
+        Ok(_) => Err("trailing bytes left after reading".into()),
+        Err(_) => Ok(()),
+    }
+}
+
+// Return an error from the enclosing verify function if the values differ.
+macro_rules! expect_eq {
+    ($actual:expr, $expected:expr) => {{
+        let actual = $actual;
+        let expected = $expected;
+        if actual != expected {
+            return Err(crate::Error::from(format!(
+                "expected {:?}, got {:?}",
+                expected, actual
+            )));
+        }
+    }};
+}
+
+const I8_VALUES: [i8; 4] = [0, -1, i8::MIN, i8::MAX];
+const I16_VALUES: [i16; 5] = [0, -1, 300, i16::MIN, i16::MAX];
+const I32_VALUES: [i32; 6] = [0, 1, -1, 70_000, i32::MIN, i32::MAX];
+const I64_VALUES: [i64; 6] = [0, 1, -1, 5_000_000_000, i64::MIN, i64::MAX];
+const DOUBLE_VALUES: [f64; 4] = [0.0, -1.5, std::f64::consts::PI, f64::MAX];
+const STRING_VALUES: [&str; 3] = ["", "hello", "h\u{e9}llo \u{2713}"];
+const BINARY_VALUE: [u8; 5] = [0x00, 0x01, 0x7F, 0x80, 0xFF];
+const UUID_VALUE: u128 = 0x0011_2233_4455_6677_8899_aabb_ccdd_eeff;
+
+fn write_bool(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_bool(true)?;
+    o.write_bool(false)
+}
+
+fn verify_bool(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(i.read_bool()?, true);
+    expect_eq!(i.read_bool()?, false);
+    Ok(())
+}
+
+fn write_i8(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    I8_VALUES.iter().try_for_each(|v| o.write_i8(*v))
+}
+
+fn verify_i8(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    for v in I8_VALUES {
+        expect_eq!(i.read_i8()?, v);
+    }
+    Ok(())
+}
+
+fn write_i16(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    I16_VALUES.iter().try_for_each(|v| o.write_i16(*v))
+}
+
+fn verify_i16(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    for v in I16_VALUES {
+        expect_eq!(i.read_i16()?, v);
+    }
+    Ok(())
+}
+
+fn write_i32(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    I32_VALUES.iter().try_for_each(|v| o.write_i32(*v))
+}
+
+fn verify_i32(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    for v in I32_VALUES {
+        expect_eq!(i.read_i32()?, v);
+    }
+    Ok(())
+}
+
+fn write_i64(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    I64_VALUES.iter().try_for_each(|v| o.write_i64(*v))
+}
+
+fn verify_i64(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    for v in I64_VALUES {
+        expect_eq!(i.read_i64()?, v);
+    }
+    Ok(())
+}
+
+fn write_double(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    DOUBLE_VALUES.iter().try_for_each(|v| o.write_double(*v))
+}
+
+fn verify_double(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    for v in DOUBLE_VALUES {
+        expect_eq!(i.read_double()?.to_bits(), v.to_bits());
+    }
+    Ok(())
+}
+
+fn write_string(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    STRING_VALUES.iter().try_for_each(|v| o.write_string(v))
+}
+
+fn verify_string(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    for v in STRING_VALUES {
+        expect_eq!(i.read_string()?, v);
+    }
+    Ok(())
+}
+
+fn write_binary(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_bytes(&BINARY_VALUE)
+}
+
+fn verify_binary(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(i.read_bytes()?, BINARY_VALUE);
+    Ok(())
+}
+
+fn write_uuid(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_uuid(&uuid::Uuid::from_u128(UUID_VALUE))
+}
+
+fn verify_uuid(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(i.read_uuid()?, uuid::Uuid::from_u128(UUID_VALUE));
+    Ok(())
+}
+
+fn test_messages() -> [TMessageIdentifier; 4] {
+    [
+        TMessageIdentifier::new("ping", TMessageType::Call, 1),
+        TMessageIdentifier::new("ping", TMessageType::Reply, 1),
+        TMessageIdentifier::new("fail", TMessageType::Exception, i32::MAX),
+        TMessageIdentifier::new("", TMessageType::OneWay, -1),
+    ]
+}
+
+fn write_message(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    for ident in &test_messages() {
+        o.write_message_begin(ident)?;
+        o.write_message_end()?;
+    }
+    Ok(())
+}
+
+fn verify_message(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    for ident in test_messages() {
+        expect_eq!(i.read_message_begin()?, ident);
+        i.read_message_end()?;
+    }
+    Ok(())
+}
+
+fn write_empty_struct(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_struct_begin(&TStructIdentifier::new("Empty"))?;
+    o.write_field_stop()?;
+    o.write_struct_end()
+}
+
+fn verify_empty_struct(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    i.read_struct_begin()?;
+    expect_eq!(i.read_field_begin()?.field_type, TType::Stop);
+    i.read_struct_end()
+}
+
+// Field ids cover small forward deltas, a delta too large for the compact
+// short form, a backwards jump and a nested struct with its own id sequence.
+fn write_struct_fields(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_struct_begin(&TStructIdentifier::new("Fields"))?;
+
+    o.write_field_begin(&TFieldIdentifier::new("a", TType::I08, 1))?;
+    o.write_i8(-8)?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("b", TType::Bool, 2))?;
+    o.write_bool(true)?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("c", TType::Bool, 3))?;
+    o.write_bool(false)?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("d", TType::I32, 20))?;
+    o.write_i32(70_000)?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("e", TType::String, 21))?;
+    o.write_string("field")?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("f", TType::I64, 5))?;
+    o.write_i64(-5)?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("g", TType::Struct, 30))?;
+    o.write_struct_begin(&TStructIdentifier::new("Inner"))?;
+    o.write_field_begin(&TFieldIdentifier::new("h", TType::Bool, 1))?;
+    o.write_bool(true)?;
+    o.write_field_end()?;
+    o.write_field_begin(&TFieldIdentifier::new("i", TType::Double, 2))?;
+    o.write_double(0.5)?;
+    o.write_field_end()?;
+    o.write_field_stop()?;
+    o.write_struct_end()?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("j", TType::I16, 31))?;
+    o.write_i16(-300)?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("k", TType::List, 32))?;
+    o.write_list_begin(&TListIdentifier::new(TType::I08, 2))?;
+    o.write_i8(1)?;
+    o.write_i8(2)?;
+    o.write_list_end()?;
+    o.write_field_end()?;
+
+    o.write_field_begin(&TFieldIdentifier::new("l", TType::Uuid, 33))?;
+    o.write_uuid(&uuid::Uuid::from_u128(UUID_VALUE))?;
+    o.write_field_end()?;
+
+    o.write_field_stop()?;
+    o.write_struct_end()
+}
+
+fn expect_field(
+    i: &mut dyn TInputProtocol,
+    field_type: TType,
+    id: i16,
+) -> crate::Result<TFieldIdentifier> {
+    let field = i.read_field_begin()?;
+    expect_eq!((field.field_type, field.id), (field_type, Some(id)));
+    Ok(field)
+}
+
+fn verify_struct_fields(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    i.read_struct_begin()?;
+
+    expect_field(i, TType::I08, 1)?;
+    expect_eq!(i.read_i8()?, -8);
+    i.read_field_end()?;
+
+    expect_field(i, TType::Bool, 2)?;
+    expect_eq!(i.read_bool()?, true);
+    i.read_field_end()?;
+
+    expect_field(i, TType::Bool, 3)?;
+    expect_eq!(i.read_bool()?, false);
+    i.read_field_end()?;
+
+    expect_field(i, TType::I32, 20)?;
+    expect_eq!(i.read_i32()?, 70_000);
+    i.read_field_end()?;
+
+    expect_field(i, TType::String, 21)?;
+    expect_eq!(i.read_string()?, "field");
+    i.read_field_end()?;
+
+    expect_field(i, TType::I64, 5)?;
+    expect_eq!(i.read_i64()?, -5);
+    i.read_field_end()?;
+
+    expect_field(i, TType::Struct, 30)?;
+    i.read_struct_begin()?;
+    expect_field(i, TType::Bool, 1)?;
+    expect_eq!(i.read_bool()?, true);
+    i.read_field_end()?;
+    expect_field(i, TType::Double, 2)?;
+    expect_eq!(i.read_double()?, 0.5);
+    i.read_field_end()?;
+    expect_eq!(i.read_field_begin()?.field_type, TType::Stop);
+    i.read_struct_end()?;
+    i.read_field_end()?;
+
+    expect_field(i, TType::I16, 31)?;
+    expect_eq!(i.read_i16()?, -300);
+    i.read_field_end()?;
+
+    expect_field(i, TType::List, 32)?;
+    expect_eq!(i.read_list_begin()?, TListIdentifier::new(TType::I08, 2));
+    expect_eq!(i.read_i8()?, 1);
+    expect_eq!(i.read_i8()?, 2);
+    i.read_list_end()?;
+    i.read_field_end()?;
+
+    expect_field(i, TType::Uuid, 33)?;
+    expect_eq!(i.read_uuid()?, uuid::Uuid::from_u128(UUID_VALUE));
+    i.read_field_end()?;
+
+    expect_eq!(i.read_field_begin()?.field_type, TType::Stop);
+    i.read_struct_end()
+}
+
+fn verify_skip(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    i.read_struct_begin()?;
+    loop {
+        let field = i.read_field_begin()?;
+        if field.field_type == TType::Stop {
+            break;
+        }
+        i.skip(field.field_type)?;
+        i.read_field_end()?;
+    }
+    i.read_struct_end()
+}
+
+fn write_list(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_list_begin(&TListIdentifier::new(TType::I32, I32_VALUES.len() as i32))?;
+    I32_VALUES.iter().try_for_each(|v| o.write_i32(*v))?;
+    o.write_list_end()
+}
+
+fn verify_list(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(
+        i.read_list_begin()?,
+        TListIdentifier::new(TType::I32, I32_VALUES.len() as i32)
+    );
+    for v in I32_VALUES {
+        expect_eq!(i.read_i32()?, v);
+    }
+    i.read_list_end()
+}
+
+const BOOL_LIST: [bool; 3] = [true, false, true];
+
+fn write_bool_list(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_list_begin(&TListIdentifier::new(TType::Bool, BOOL_LIST.len() as i32))?;
+    BOOL_LIST.iter().try_for_each(|v| o.write_bool(*v))?;
+    o.write_list_end()
+}
+
+fn verify_bool_list(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(
+        i.read_list_begin()?,
+        TListIdentifier::new(TType::Bool, BOOL_LIST.len() as i32)
+    );
+    for v in BOOL_LIST {
+        expect_eq!(i.read_bool()?, v);
+    }
+    i.read_list_end()
+}
+
+fn write_set(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_set_begin(&TSetIdentifier::new(
+        TType::String,
+        STRING_VALUES.len() as i32,
+    ))?;
+    STRING_VALUES.iter().try_for_each(|v| o.write_string(v))?;
+    o.write_set_end()
+}
+
+fn verify_set(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(
+        i.read_set_begin()?,
+        TSetIdentifier::new(TType::String, STRING_VALUES.len() as i32)
+    );
+    for v in STRING_VALUES {
+        expect_eq!(i.read_string()?, v);
+    }
+    i.read_set_end()
+}
+
+const MAP_ENTRIES: [(&str, i64); 2] = [("one", 1), ("minus one", -1)];
+
+fn write_map(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_map_begin(&TMapIdentifier::new(
+        TType::String,
+        TType::I64,
+        MAP_ENTRIES.len() as i32,
+    ))?;
+    for (k, v) in MAP_ENTRIES {
+        o.write_string(k)?;
+        o.write_i64(v)?;
+    }
+    o.write_map_end()
+}
+
+fn verify_map(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(
+        i.read_map_begin()?,
+        TMapIdentifier::new(TType::String, TType::I64, MAP_ENTRIES.len() as i32)
+    );
+    for (k, v) in MAP_ENTRIES {
+        expect_eq!(i.read_string()?, k);
+        expect_eq!(i.read_i64()?, v);
+    }
+    i.read_map_end()
+}
+
+fn write_empty_containers(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_list_begin(&TListIdentifier::new(TType::String, 0))?;
+    o.write_list_end()?;
+    o.write_set_begin(&TSetIdentifier::new(TType::I64, 0))?;
+    o.write_set_end()?;
+    o.write_map_begin(&TMapIdentifier::new(TType::I32, TType::Double, 0))?;
+    o.write_map_end()
+}
+
+// Empty maps may not record their key and value types (the compact
+// protocol omits them), so only the sizes are checked.
+fn verify_empty_containers(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(i.read_list_begin()?.size, 0);
+    i.read_list_end()?;
+    expect_eq!(i.read_set_begin()?.size, 0);
+    i.read_set_end()?;
+    expect_eq!(i.read_map_begin()?.size, 0);
+    i.read_map_end()
+}
+
+// list<map<i32, list<bool>>> with two maps of one entry each
+fn write_nested_containers(o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    o.write_list_begin(&TListIdentifier::new(TType::Map, 2))?;
+    for key in [7, -7] {
+        o.write_map_begin(&TMapIdentifier::new(TType::I32, TType::List, 1))?;
+        o.write_i32(key)?;
+        o.write_list_begin(&TListIdentifier::new(TType::Bool, 2))?;
+        o.write_bool(key > 0)?;
+        o.write_bool(key < 0)?;
+        o.write_list_end()?;
+        o.write_map_end()?;
+    }
+    o.write_list_end()
+}
+
+fn verify_nested_containers(i: &mut dyn TInputProtocol) -> crate::Result<()> {
+    expect_eq!(i.read_list_begin()?, TListIdentifier::new(TType::Map, 2));
+    for key in [7, -7] {
+        expect_eq!(
+            i.read_map_begin()?,
+            TMapIdentifier::new(TType::I32, TType::List, 1)
+        );
+        expect_eq!(i.read_i32()?, key);
+        expect_eq!(i.read_list_begin()?, TListIdentifier::new(TType::Bool, 2));
+        expect_eq!(i.read_bool()?, key > 0);
+        expect_eq!(i.read_bool()?, key < 0);
+        i.read_list_end()?;
+        i.read_map_end()?;
+    }
+    i.read_list_end()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol,
+    };
+
+    struct Binary;
+
+    impl TProtocolUnderTest for Binary {
+        fn output_protocol<'a>(&self, buf: &'a mut Vec<u8>) -> Box<dyn TOutputProtocol + 'a> {
+            Box::new(TBinaryOutputProtocol::new(buf, true))
+        }
+
+        fn input_protocol<'a>(&self, buf: &'a [u8]) -> Box<dyn TInputProtocol + 'a> {
+            Box::new(TBinaryInputProtocol::new(buf, true))
+        }
+    }
+
+    struct Compact;
+
+    impl TProtocolUnderTest for Compact {
+        fn output_protocol<'a>(&self, buf: &'a mut Vec<u8>) -> Box<dyn TOutputProtocol + 'a> {
+            Box::new(TCompactOutputProtocol::new(buf))
+        }
+
+        fn input_protocol<'a>(&self, buf: &'a [u8]) -> Box<dyn TInputProtocol + 'a> {
+            Box::new(TCompactInputProtocol::new(buf))
+        }
+    }
+
+    #[test]
+    fn must_have_unique_case_names() {
+        for (n, case) in TEST_CASES.iter().enumerate() {
+            assert!(TEST_CASES[n + 1..].iter().all(|c| c.name != case.name));
+        }
+    }
+
+    #[test]
+    fn binary_protocol_must_pass_round_trip_tests() {
+        run_round_trip_tests(&Binary);
+    }
+
+    #[test]
+    fn compact_protocol_must_pass_round_trip_tests() {
+        run_round_trip_tests(&Compact);
+    }
+
+    #[test]
+    fn binary_protocol_must_match_golden_bytes() {
+        assert_eq!(BINARY_GOLDEN.len(), TEST_CASES.len());
+        run_golden_tests(&Binary, BINARY_GOLDEN);
+    }
+
+    #[test]
+    fn compact_protocol_must_match_golden_bytes() {
+        assert_eq!(COMPACT_GOLDEN.len(), TEST_CASES.len());
+        run_golden_tests(&Compact, COMPACT_GOLDEN);
+    }
+
+    #[test]
+    #[should_panic(expected = "test case 'bool' wrote")]
+    fn must_report_golden_mismatch() {
+        run_golden_tests(&Compact, &[("bool", &[0x00])]);
+    }
+}
@@ -0,0 +1,303 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use tracing::Span;
+
+use super::{
+    TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TOutputProtocol,
+    TRawString, TSetIdentifier, TStructIdentifier,
+};
+
+/// `TOutputProtocol` that records each outgoing message in a `tracing` span.
+///
+/// A `thrift.send` span is created when a message is started, as a child of
+/// the span that is current at that point, and closed once the message has
+/// been flushed. It carries the method name, sequence number and message
+/// type, so a client call can be followed from the application's own spans
+/// to the bytes leaving the process. The flush runs inside the span, and a
+/// failed flush is reported as an error event in it.
+///
+/// Available through the optional `tracing` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryOutputProtocol, TMessageIdentifier, TMessageType};
+/// use thrift::protocol::{TOutputProtocol, TTracingOutputProtocol};
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+///
+/// let protocol = TBinaryOutputProtocol::new(channel, true);
+/// let mut protocol = TTracingOutputProtocol::new(protocol);
+///
+/// let _request = tracing::info_span!("checkout", order = 42).entered();
+/// let ident = TMessageIdentifier::new("reserve", TMessageType::Call, 1);
+/// protocol.write_message_begin(&ident).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TTracingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    inner: P,
+    span: Option<Span>,
+}
+
+impl<P> TTracingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    /// Create a `TTracingOutputProtocol` that traces the messages written to
+    /// `wrapped`.
+    pub fn new(wrapped: P) -> TTracingOutputProtocol<P> {
+        TTracingOutputProtocol {
+            inner: wrapped,
+            span: None,
+        }
+    }
+
+    /// Return the wrapped protocol.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+// FIXME: avoid passthrough methods
+impl<P> TOutputProtocol for TTracingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        self.span = Some(tracing::info_span!(
+            "thrift.send",
+            rpc.method = %identifier.name,
+            rpc.seq_id = identifier.sequence_number,
+            rpc.message_type = %identifier.message_type,
+        ));
+        self.inner.write_message_begin(identifier)
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.inner.write_message_end()
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        self.inner.write_struct_begin(identifier)
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.inner.write_struct_end()
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        self.inner.write_field_begin(identifier)
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.inner.write_field_end()
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        self.inner.write_field_stop()
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.inner.write_bytes(b)
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.inner.write_bool(b)
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.inner.write_i8(i)
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.inner.write_i16(i)
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.inner.write_i32(i)
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.inner.write_i64(i)
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.inner.write_double(d)
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.inner.write_string(s)
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.inner.write_raw_string(s)
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        self.inner.write_uuid(uuid)
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.inner.write_list_begin(identifier)
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.inner.write_list_end()
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.inner.write_set_begin(identifier)
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.inner.write_set_end()
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        self.inner.write_map_begin(identifier)
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.inner.write_map_end()
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        let span = match self.span.take() {
+            Some(span) => span,
+            None => return self.inner.flush(),
+        };
+        let _entered = span.enter();
+        let result = self.inner.flush();
+        if let Err(ref e) = result {
+            tracing::error!(error = %e, "failed to send message");
+        }
+        result
+    }
+
+    // utility
+    //
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.inner.write_byte(b)
+    }
+}
+
+/// `tracing` subscriber that records the spans and events it sees, for
+/// tests.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SpanRecorder {
+    records: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    next_id: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+#[cfg(test)]
+impl SpanRecorder {
+    /// Spans as `span <name> <field>=<value>...`, and events as
+    /// `event <field>=<value>...`, in the order they were created.
+    pub(crate) fn records(&self) -> Vec<String> {
+        self.records.lock().unwrap().clone()
+    }
+
+    fn push(&self, prefix: String, fields: &dyn Fn(&mut dyn tracing::field::Visit)) {
+        struct Fields(String);
+        impl tracing::field::Visit for Fields {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+        let mut visitor = Fields(prefix);
+        fields(&mut visitor);
+        self.records.lock().unwrap().push(visitor.0);
+    }
+}
+
+#[cfg(test)]
+impl tracing::Subscriber for SpanRecorder {
+    fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let name = format!("span {}", span.metadata().name());
+        self.push(name, &|visitor| span.record(visitor));
+        let id = self
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        tracing::span::Id::from_u64(id + 1)
+    }
+
+    fn record(&self, _: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+        self.push("record".to_owned(), &|visitor| values.record(visitor));
+    }
+
+    fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        self.push("event".to_owned(), &|visitor| event.record(visitor));
+    }
+
+    fn enter(&self, _: &tracing::span::Id) {}
+
+    fn exit(&self, _: &tracing::span::Id) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::protocol::{TBinaryOutputProtocol, TMessageType};
+
+    struct Broken;
+
+    impl io::Write for Broken {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+    }
+
+    #[test]
+    fn must_record_span_per_message() {
+        let recorder = SpanRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut o_prot = TTracingOutputProtocol::new(TBinaryOutputProtocol::new(Broken, true));
+            o_prot
+                .write_message_begin(&TMessageIdentifier::new("ping", TMessageType::Call, 3))
+                .unwrap();
+            o_prot.write_message_end().unwrap();
+            assert!(o_prot.flush().is_err());
+        });
+
+        let records = recorder.records();
+        assert_eq!(
+            records[0],
+            "span thrift.send rpc.method=ping rpc.seq_id=3 rpc.message_type=Call"
+        );
+        assert!(records[1].starts_with("event message=failed to send message error="));
+        assert_eq!(records.len(), 2);
+    }
+}
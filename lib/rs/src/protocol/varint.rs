@@ -0,0 +1,243 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Unsigned LEB128 ("varint") and zigzag codecs used by the compact protocol.
+//!
+//! The encoders write into a stack buffer so that a varint reaches the
+//! transport in a single `write_all` call. The decoders special-case the one-
+//! and two-byte encodings, which cover field ids, lengths, collection sizes
+//! and the majority of integer values seen on the wire.
+
+use crate::{ProtocolError, ProtocolErrorKind};
+
+pub(crate) const MAX_VARINT32_BYTES: usize = 5; // ceil(32/7); matches protobuf wire format
+pub(crate) const MAX_VARINT64_BYTES: usize = 10; // ceil(64/7); matches protobuf wire format
+
+/// Encode `n` into `buf`, returning the number of bytes used.
+#[inline]
+pub(crate) fn encode_u32(n: u32, buf: &mut [u8; MAX_VARINT32_BYTES]) -> usize {
+    if n < 1 << 7 {
+        buf[0] = n as u8;
+        return 1;
+    }
+    if n < 1 << 14 {
+        buf[0] = (n as u8) | 0x80;
+        buf[1] = (n >> 7) as u8;
+        return 2;
+    }
+
+    let mut n = n;
+    let mut i = 0;
+    while n >= 0x80 {
+        buf[i] = (n as u8) | 0x80;
+        n >>= 7;
+        i += 1;
+    }
+    buf[i] = n as u8;
+    i + 1
+}
+
+/// Encode `n` into `buf`, returning the number of bytes used.
+#[inline]
+pub(crate) fn encode_u64(n: u64, buf: &mut [u8; MAX_VARINT64_BYTES]) -> usize {
+    if n < 1 << 7 {
+        buf[0] = n as u8;
+        return 1;
+    }
+    if n < 1 << 14 {
+        buf[0] = (n as u8) | 0x80;
+        buf[1] = (n >> 7) as u8;
+        return 2;
+    }
+
+    let mut n = n;
+    let mut i = 0;
+    while n >= 0x80 {
+        buf[i] = (n as u8) | 0x80;
+        n >>= 7;
+        i += 1;
+    }
+    buf[i] = n as u8;
+    i + 1
+}
+
+/// Decode a varint whose bytes are supplied one at a time by `next_byte`.
+#[inline]
+pub(crate) fn read_u32<F>(mut next_byte: F) -> crate::Result<u32>
+where
+    F: FnMut() -> crate::Result<u8>,
+{
+    let b0 = next_byte()?;
+    if b0 & 0x80 == 0 {
+        return Ok(b0 as u32);
+    }
+    let b1 = next_byte()?;
+    if b1 & 0x80 == 0 {
+        return Ok((b0 & 0x7F) as u32 | ((b1 as u32) << 7));
+    }
+
+    let mut result = (b0 & 0x7F) as u32 | (((b1 & 0x7F) as u32) << 7);
+    let mut shift = 14;
+    for _ in 2..MAX_VARINT32_BYTES {
+        let b = next_byte()?;
+        result |= ((b & 0x7F) as u32) << shift;
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+    Err(overlong_varint_error(MAX_VARINT32_BYTES))
+}
+
+/// Decode a varint whose bytes are supplied one at a time by `next_byte`.
+#[inline]
+pub(crate) fn read_u64<F>(mut next_byte: F) -> crate::Result<u64>
+where
+    F: FnMut() -> crate::Result<u8>,
+{
+    let b0 = next_byte()?;
+    if b0 & 0x80 == 0 {
+        return Ok(b0 as u64);
+    }
+    let b1 = next_byte()?;
+    if b1 & 0x80 == 0 {
+        return Ok((b0 & 0x7F) as u64 | ((b1 as u64) << 7));
+    }
+
+    let mut result = (b0 & 0x7F) as u64 | (((b1 & 0x7F) as u64) << 7);
+    let mut shift = 14;
+    for _ in 2..MAX_VARINT64_BYTES {
+        let b = next_byte()?;
+        result |= ((b & 0x7F) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+    Err(overlong_varint_error(MAX_VARINT64_BYTES))
+}
+
+#[inline]
+pub(crate) fn i32_to_zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+#[inline]
+pub(crate) fn i64_to_zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+#[inline]
+pub(crate) fn zigzag_to_i32(n: u32) -> i32 {
+    ((n >> 1) as i32) ^ (0i32.wrapping_sub((n & 1) as i32))
+}
+
+#[inline]
+pub(crate) fn zigzag_to_i64(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ (0i64.wrapping_sub((n & 1) as i64))
+}
+
+fn overlong_varint_error(max_bytes: usize) -> crate::Error {
+    crate::Error::Protocol(ProtocolError::new(
+        ProtocolErrorKind::InvalidData,
+        format!("Variable-length int over {} bytes.", max_bytes),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn byte_source(buf: &[u8]) -> impl FnMut() -> crate::Result<u8> + '_ {
+        let mut bytes = buf.iter();
+        move || {
+            bytes
+                .next()
+                .copied()
+                .ok_or_else(|| crate::Error::from("out of bytes"))
+        }
+    }
+
+    fn round_trip_u32(n: u32) {
+        let mut buf = [0u8; MAX_VARINT32_BYTES];
+        let len = encode_u32(n, &mut buf);
+        assert_eq!(read_u32(byte_source(&buf[..len])).unwrap(), n);
+        assert!(read_u32(byte_source(&buf[..len - 1])).is_err());
+    }
+
+    fn round_trip_u64(n: u64) {
+        let mut buf = [0u8; MAX_VARINT64_BYTES];
+        let len = encode_u64(n, &mut buf);
+        assert_eq!(read_u64(byte_source(&buf[..len])).unwrap(), n);
+        assert!(read_u64(byte_source(&buf[..len - 1])).is_err());
+    }
+
+    #[test]
+    fn must_round_trip_u32_at_every_length_boundary() {
+        for shift in 0..32 {
+            round_trip_u32(1 << shift);
+            round_trip_u32((1 << shift) - 1 + (shift == 0) as u32);
+        }
+        round_trip_u32(u32::MAX);
+    }
+
+    #[test]
+    fn must_round_trip_u64_at_every_length_boundary() {
+        for shift in 0..64 {
+            round_trip_u64(1 << shift);
+            round_trip_u64((1 << shift) - 1 + (shift == 0) as u64);
+        }
+        round_trip_u64(u64::MAX);
+    }
+
+    #[test]
+    fn must_encode_known_values() {
+        let mut buf = [0u8; MAX_VARINT32_BYTES];
+        assert_eq!(encode_u32(0, &mut buf), 1);
+        assert_eq!(buf[0], 0x00);
+        assert_eq!(encode_u32(300, &mut buf), 2);
+        assert_eq!(&buf[..2], &[0xAC, 0x02]);
+        assert_eq!(encode_u32(u32::MAX, &mut buf), 5);
+        assert_eq!(&buf, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+    }
+
+    #[test]
+    fn must_reject_overlong_varint() {
+        match read_u32(byte_source(&[0xFF; 6])) {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+        match read_u64(byte_source(&[0xFF; 11])) {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_round_trip_zigzag() {
+        for n in [0, 1, -1, 63, -64, i32::MAX, i32::MIN] {
+            assert_eq!(zigzag_to_i32(i32_to_zigzag(n)), n);
+        }
+        for n in [0, 1, -1, 63, -64, i64::MAX, i64::MIN] {
+            assert_eq!(zigzag_to_i64(i64_to_zigzag(n)), n);
+        }
+        assert_eq!(i32_to_zigzag(-1), 1);
+        assert_eq!(i32_to_zigzag(1), 2);
+        assert_eq!(i64_to_zigzag(i64::MIN), u64::MAX);
+    }
+}
@@ -0,0 +1,679 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+
+use ::serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+
+use super::{enum_value, field_id, invalid_data};
+use crate::protocol::{TDynamicInputProtocol, TInputProtocol, TProtocolKind, TType};
+use crate::ProtocolErrorKind;
+
+/// Read a value of type `T` from `i_prot`.
+pub fn from_protocol<T>(i_prot: &mut dyn TInputProtocol) -> crate::Result<T>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(&mut TDeserializer::new(i_prot))
+}
+
+/// Read a value of type `T` encoded with the `protocol` protocol from
+/// `bytes`.
+pub fn from_slice<T>(bytes: &[u8], protocol: TProtocolKind) -> crate::Result<T>
+where
+    T: DeserializeOwned,
+{
+    let mut i_prot = TDynamicInputProtocol::new(protocol, bytes);
+    from_protocol(&mut i_prot)
+}
+
+/// `serde::Deserializer` that reads values from a `TInputProtocol`.
+///
+/// Values are mapped to Thrift types as described in the module
+/// documentation. Inside structs, lists and maps the encoded type of every
+/// value is known, and a value of another type than the one expected fails
+/// to read. A value read on its own is read as the type expected, so
+/// `deserialize_any`, and thereby types such as untagged enums, only work
+/// inside them.
+pub struct TDeserializer<'a> {
+    i_prot: &'a mut dyn TInputProtocol,
+    /// The encoded type of the value to read, if known.
+    field_type: Option<TType>,
+}
+
+impl<'a> TDeserializer<'a> {
+    /// Create a `TDeserializer` that reads values from `i_prot`.
+    pub fn new(i_prot: &'a mut dyn TInputProtocol) -> TDeserializer<'a> {
+        TDeserializer {
+            i_prot,
+            field_type: None,
+        }
+    }
+
+    /// Return a deserializer for a nested value encoded as `field_type`.
+    fn nested(&mut self, field_type: TType) -> TDeserializer<'_> {
+        TDeserializer {
+            i_prot: &mut *self.i_prot,
+            field_type: Some(field_type),
+        }
+    }
+
+    fn expect(&self, expected: TType) -> crate::Result<()> {
+        match self.field_type {
+            Some(field_type) if field_type != expected => Err(invalid_data(format!(
+                "expected a value of type {} but found {}",
+                expected, field_type
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_struct<'de, V>(
+        &mut self,
+        fields: Option<&'static [&'static str]>,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::Struct)?;
+        self.i_prot.read_struct_begin()?;
+        let value = visitor.visit_map(StructAccess {
+            de: self,
+            fields,
+            field_type: None,
+        })?;
+        self.i_prot.read_struct_end()?;
+        Ok(value)
+    }
+
+    fn read_list<'de, V>(&mut self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let set = self.field_type == Some(TType::Set);
+        let (element_type, size) = if set {
+            let identifier = self.i_prot.read_set_begin()?;
+            (identifier.element_type, identifier.size)
+        } else {
+            self.expect(TType::List)?;
+            let identifier = self.i_prot.read_list_begin()?;
+            (identifier.element_type, identifier.size)
+        };
+        let mut access = ListAccess {
+            de: self,
+            element_type,
+            remaining: size_of(size)?,
+        };
+        let value = visitor.visit_seq(&mut access)?;
+        if access.remaining > 0 {
+            return Err(invalid_data(format!(
+                "{} more elements than expected",
+                access.remaining
+            )));
+        }
+        if set {
+            self.i_prot.read_set_end()?;
+        } else {
+            self.i_prot.read_list_end()?;
+        }
+        Ok(value)
+    }
+
+    fn read_map<'de, V>(&mut self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::Map)?;
+        let identifier = self.i_prot.read_map_begin()?;
+        let remaining = size_of(identifier.size)?;
+        let (key_type, value_type) = match (identifier.key_type, identifier.value_type) {
+            (Some(key_type), Some(value_type)) => (key_type, value_type),
+            // only empty maps may leave their types out
+            _ => (TType::Void, TType::Void),
+        };
+        let value = visitor.visit_map(MapAccess {
+            de: self,
+            key_type,
+            value_type,
+            remaining,
+        })?;
+        self.i_prot.read_map_end()?;
+        Ok(value)
+    }
+}
+
+impl fmt::Debug for TDeserializer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TDeserializer")
+            .field("field_type", &self.field_type)
+            .finish_non_exhaustive()
+    }
+}
+
+fn size_of(size: i32) -> crate::Result<usize> {
+    usize::try_from(size).map_err(|_| {
+        crate::new_protocol_error(
+            ProtocolErrorKind::NegativeSize,
+            format!("negative container size {}", size),
+        )
+    })
+}
+
+fn out_of_range<T: fmt::Display>(value: T, target: &str) -> crate::Error {
+    invalid_data(format!("{} does not fit in a {}", value, target))
+}
+
+impl<'de> de::Deserializer<'de> for &mut TDeserializer<'_> {
+    type Error = crate::Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let field_type = self.field_type.ok_or_else(|| {
+            invalid_data("the type of a value read on its own must be known in advance")
+        })?;
+        match field_type {
+            TType::Bool => visitor.visit_bool(self.i_prot.read_bool()?),
+            TType::I08 => visitor.visit_i8(self.i_prot.read_i8()?),
+            TType::I16 => visitor.visit_i16(self.i_prot.read_i16()?),
+            TType::I32 => visitor.visit_i32(self.i_prot.read_i32()?),
+            TType::I64 => visitor.visit_i64(self.i_prot.read_i64()?),
+            TType::Double => visitor.visit_f64(self.i_prot.read_double()?),
+            TType::String => match String::from_utf8(self.i_prot.read_bytes()?) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            TType::Uuid => visitor.visit_bytes(self.i_prot.read_uuid()?.as_bytes()),
+            TType::Struct => self.read_struct(None, visitor),
+            TType::List | TType::Set => self.read_list(visitor),
+            TType::Map => self.read_map(visitor),
+            TType::Stop | TType::Void | TType::Utf7 => Err(invalid_data(format!(
+                "cannot read a value of type {}",
+                field_type
+            ))),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::Bool)?;
+        visitor.visit_bool(self.i_prot.read_bool()?)
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I08)?;
+        visitor.visit_i8(self.i_prot.read_i8()?)
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I16)?;
+        visitor.visit_i16(self.i_prot.read_i16()?)
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I32)?;
+        visitor.visit_i32(self.i_prot.read_i32()?)
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I64)?;
+        visitor.visit_i64(self.i_prot.read_i64()?)
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I08)?;
+        visitor.visit_u8(self.i_prot.read_i8()? as u8)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I32)?;
+        let v = self.i_prot.read_i32()?;
+        visitor.visit_u16(u16::try_from(v).map_err(|_| out_of_range(v, "u16"))?)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I64)?;
+        let v = self.i_prot.read_i64()?;
+        visitor.visit_u32(u32::try_from(v).map_err(|_| out_of_range(v, "u32"))?)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::I64)?;
+        let v = self.i_prot.read_i64()?;
+        visitor.visit_u64(u64::try_from(v).map_err(|_| out_of_range(v, "u64"))?)
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::Double)?;
+        visitor.visit_f32(self.i_prot.read_double()? as f32)
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::Double)?;
+        visitor.visit_f64(self.i_prot.read_double()?)
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::String)?;
+        let s = self.i_prot.read_string()?;
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(invalid_data(format!("{:?} is not a single character", s))),
+        }
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::String)?;
+        visitor.visit_string(self.i_prot.read_string()?)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if self.field_type == Some(TType::Uuid) {
+            return visitor.visit_bytes(self.i_prot.read_uuid()?.as_bytes());
+        }
+        self.expect(TType::String)?;
+        visitor.visit_byte_buf(self.i_prot.read_bytes()?)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        // missing fields are `None`, so a value that is read is `Some`
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.expect(TType::Struct)?;
+        self.i_prot.skip(TType::Struct)?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.read_list(visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.read_list(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.read_list(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.read_map(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.read_struct(Some(fields), visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if self.field_type == Some(TType::I32) {
+            let value = self.i_prot.read_i32()?;
+            let variant = (0..variants.len())
+                .find(|&i| enum_value(variants[i], i) == value)
+                .map(|i| variants[i])
+                .ok_or_else(|| invalid_data(format!("unknown enum value {}", value)))?;
+            return visitor
+                .visit_enum(IntoDeserializer::<crate::Error>::into_deserializer(variant));
+        }
+
+        self.expect(TType::Struct)?;
+        self.i_prot.read_struct_begin()?;
+        let field = self.i_prot.read_field_begin()?;
+        if field.field_type == TType::Stop {
+            return Err(crate::new_protocol_error(
+                ProtocolErrorKind::EmptyUnion,
+                "received empty union",
+            ));
+        }
+        let id = field.id.unwrap_or_default();
+        let variant = (0..variants.len())
+            .find(|&i| field_id(variants[i], i + 1) == id)
+            .map(|i| variants[i])
+            .ok_or_else(|| {
+                crate::new_protocol_error(
+                    ProtocolErrorKind::UnknownUnionVariant,
+                    format!("unknown union field {}", id),
+                )
+            })?;
+        let value = visitor.visit_enum(UnionAccess {
+            de: self,
+            variant,
+            field_type: field.field_type,
+        })?;
+        self.i_prot.read_field_end()?;
+        if self.i_prot.read_field_begin()?.field_type != TType::Stop {
+            return Err(invalid_data("received multiple fields for union"));
+        }
+        self.i_prot.read_struct_end()?;
+        Ok(value)
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let field_type = self.field_type.ok_or_else(|| {
+            invalid_data("the type of a value read on its own must be known in advance")
+        })?;
+        self.i_prot.skip(field_type)?;
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Reads the fields of a struct. With the names of the fields of the Rust
+/// type, fields are identified by name and unknown fields are skipped;
+/// without them, fields are identified by id.
+struct StructAccess<'b, 'a> {
+    de: &'b mut TDeserializer<'a>,
+    fields: Option<&'static [&'static str]>,
+    /// The encoded type of the value of the field whose id was just read.
+    field_type: Option<TType>,
+}
+
+impl<'de> de::MapAccess<'de> for StructAccess<'_, '_> {
+    type Error = crate::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> crate::Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        loop {
+            let field = self.de.i_prot.read_field_begin()?;
+            if field.field_type == TType::Stop {
+                return Ok(None);
+            }
+            let id = field.id.unwrap_or_default();
+            let fields = match self.fields {
+                Some(fields) => fields,
+                None => {
+                    self.field_type = Some(field.field_type);
+                    let key = IntoDeserializer::<crate::Error>::into_deserializer(id);
+                    return seed.deserialize(key).map(Some);
+                }
+            };
+            match (0..fields.len()).find(|&i| field_id(fields[i], i + 1) == id) {
+                Some(i) => {
+                    self.field_type = Some(field.field_type);
+                    let key = IntoDeserializer::<crate::Error>::into_deserializer(fields[i]);
+                    return seed.deserialize(key).map(Some);
+                }
+                None => {
+                    self.de.i_prot.skip(field.field_type)?;
+                    self.de.i_prot.read_field_end()?;
+                }
+            }
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> crate::Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let field_type = self.field_type.take().ok_or_else(|| {
+            crate::new_protocol_error(
+                ProtocolErrorKind::InvalidState,
+                "struct field value read before its id",
+            )
+        })?;
+        let value = seed.deserialize(&mut self.de.nested(field_type))?;
+        self.de.i_prot.read_field_end()?;
+        Ok(value)
+    }
+}
+
+/// Reads the elements of a list or set.
+struct ListAccess<'b, 'a> {
+    de: &'b mut TDeserializer<'a>,
+    element_type: TType,
+    remaining: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for ListAccess<'_, '_> {
+    type Error = crate::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> crate::Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut self.de.nested(self.element_type))
+            .map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+/// Reads the entries of a map.
+struct MapAccess<'b, 'a> {
+    de: &'b mut TDeserializer<'a>,
+    key_type: TType,
+    value_type: TType,
+    remaining: usize,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'_, '_> {
+    type Error = crate::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> crate::Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut self.de.nested(self.key_type))
+            .map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> crate::Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut self.de.nested(self.value_type))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+/// Reads the variant held by a union, whose field header has been read.
+struct UnionAccess<'b, 'a> {
+    de: &'b mut TDeserializer<'a>,
+    variant: &'static str,
+    field_type: TType,
+}
+
+impl<'de> de::EnumAccess<'de> for UnionAccess<'_, '_> {
+    type Error = crate::Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> crate::Result<(V::Value, Self)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let key = IntoDeserializer::<crate::Error>::into_deserializer(self.variant);
+        Ok((seed.deserialize(key)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for UnionAccess<'_, '_> {
+    type Error = crate::Error;
+
+    fn unit_variant(self) -> crate::Result<()> {
+        self.de.i_prot.skip(self.field_type)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> crate::Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut self.de.nested(self.field_type))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.de.nested(self.field_type).read_list(visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.de
+            .nested(self.field_type)
+            .read_struct(Some(fields), visitor)
+    }
+}
@@ -0,0 +1,290 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Read and write types that implement serde's `Serialize` and
+//! `Deserialize` with Thrift protocols, without generated code.
+//!
+//! `to_protocol` and `from_protocol` write and read a value with any
+//! `TOutputProtocol` or `TInputProtocol`, and `to_vec` and `from_slice`
+//! with a byte buffer in the binary or compact format. `TSerializer` and
+//! `TDeserializer` are the underlying `serde::Serializer` and
+//! `serde::Deserializer`.
+//!
+//! Rust types map to Thrift types as follows:
+//!
+//! * `bool`, `i8`, `i16`, `i32`, `i64` and `f64` to their Thrift
+//!   equivalents, `f32` to a double, and `u8` to a byte
+//! * `u16` to an `i32`, and `u32` and `u64` to an `i64`; values that do not
+//!   fit fail to read or write
+//! * strings and chars to strings, and bytes (see the `serde_bytes` crate)
+//!   to binary
+//! * structs to structs, and sequences and tuples to lists; sets are read
+//!   from lists or sets
+//! * maps to maps
+//! * enum variants without data to `i32` values, like Thrift enums, and
+//!   other variants to unions with a single field holding the variant's
+//!   data
+//!
+//! A struct field's id is its position in the struct, starting from 1,
+//! unless it is renamed to a number with `#[serde(rename = "4")]`. Fields
+//! skipped with `#[serde(skip)]` are not counted; give the fields explicit
+//! ids when skipping only the serialization or deserialization of a field.
+//! Likewise, the value of an enum variant is its position starting from 0
+//! unless it is renamed to a number, and a union variant's field id is its
+//! position starting from 1. A field whose value is `None` is not written,
+//! and a field that is missing when read is `None`. Fields with ids that
+//! are not in the struct are skipped when read.
+//!
+//! A value read on its own, outside a struct, list or map, is read as the
+//! type the Rust type expects, and an enum as a union; write enums whose
+//! variants have no data inside a struct.
+//!
+//! Since Thrift lists and maps have a single element type, all their
+//! elements must map to the same Thrift type. Empty lists and maps are
+//! written with byte elements.
+//!
+//! # Examples
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use thrift::protocol::TProtocolKind;
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct LogEntry {
+//!     level: i32,
+//!     message: String,
+//!     #[serde(rename = "5")]
+//!     tags: Option<Vec<String>>,
+//! }
+//!
+//! let entry = LogEntry {
+//!     level: 2,
+//!     message: "started".to_owned(),
+//!     tags: None,
+//! };
+//! let bytes = thrift::serde::to_vec(&entry, TProtocolKind::Compact)?;
+//! let read: LogEntry = thrift::serde::from_slice(&bytes, TProtocolKind::Compact)?;
+//! assert_eq!(read, entry);
+//! # Ok::<(), thrift::Error>(())
+//! ```
+
+use std::fmt::Display;
+
+use crate::ProtocolErrorKind;
+
+mod de;
+mod ser;
+
+pub use self::de::{from_protocol, from_slice, TDeserializer};
+pub use self::ser::{to_protocol, to_vec, TSerializer};
+
+impl ::serde::ser::Error for crate::Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        invalid_data(msg.to_string())
+    }
+}
+
+impl ::serde::de::Error for crate::Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        invalid_data(msg.to_string())
+    }
+}
+
+fn invalid_data<S: Into<String>>(message: S) -> crate::Error {
+    crate::new_protocol_error(ProtocolErrorKind::InvalidData, message)
+}
+
+/// Return the id of the field named `name` at `position`: the name if it is
+/// a number, the position otherwise.
+fn field_id(name: &str, position: usize) -> i16 {
+    name.parse().unwrap_or(position as i16)
+}
+
+/// Return the value of the enum variant named `name` at `position`.
+fn enum_value(name: &str, position: usize) -> i32 {
+    name.parse().unwrap_or(position as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ::serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TFieldIdentifier, TInputProtocol,
+        TOutputProtocol, TProtocolKind, TStructIdentifier, TType,
+    };
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Level {
+        Debug,
+        Info,
+        #[serde(rename = "10")]
+        Error,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Source {
+        Host(String),
+        Process { pid: u32, name: String },
+        Span(i64, i64),
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Entry {
+        level: Level,
+        message: String,
+        source: Source,
+        attempts: u8,
+        ratio: f32,
+        tags: Vec<String>,
+        counts: BTreeMap<String, u64>,
+        parent: Option<Box<Entry>>,
+        timestamp: (i64, i64),
+    }
+
+    fn entry() -> Entry {
+        Entry {
+            level: Level::Error,
+            message: "disk full".to_owned(),
+            source: Source::Process {
+                pid: 4242,
+                name: "writer".to_owned(),
+            },
+            attempts: 200,
+            ratio: 0.5,
+            tags: vec!["disk".to_owned(), "storage".to_owned()],
+            counts: [("retries".to_owned(), 3), ("writes".to_owned(), 17)].into(),
+            parent: Some(Box::new(Entry {
+                level: Level::Info,
+                message: String::new(),
+                source: Source::Span(1, 2),
+                attempts: 0,
+                ratio: 0.0,
+                tags: Vec::new(),
+                counts: BTreeMap::new(),
+                parent: None,
+                timestamp: (0, 0),
+            })),
+            timestamp: (1_700_000_000, 250),
+        }
+    }
+
+    #[test]
+    fn must_round_trip_values_with_both_protocols() {
+        for protocol in [TProtocolKind::Binary, TProtocolKind::Compact] {
+            let bytes = to_vec(&entry(), protocol).unwrap();
+            let read: Entry = from_slice(&bytes, protocol).unwrap();
+            assert_eq!(read, entry());
+        }
+
+        let source = Source::Host("db1".to_owned());
+        let bytes = to_vec(&source, TProtocolKind::Binary).unwrap();
+        assert_eq!(
+            from_slice::<Source>(&bytes, TProtocolKind::Binary).unwrap(),
+            source
+        );
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Sparse {
+        name: String,
+        #[serde(rename = "5", skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<i32>,
+    }
+
+    #[test]
+    fn must_write_fields_with_their_ids() {
+        let sparse = Sparse {
+            name: "a".to_owned(),
+            note: Some("b".to_owned()),
+            values: Vec::new(),
+        };
+        let mut bytes = Vec::new();
+        to_protocol(&sparse, &mut TBinaryOutputProtocol::new(&mut bytes, true)).unwrap();
+
+        let mut i_prot = TBinaryInputProtocol::new(&bytes[..], true);
+        i_prot.read_struct_begin().unwrap();
+        let mut fields = Vec::new();
+        loop {
+            let field = i_prot.read_field_begin().unwrap();
+            if field.field_type == TType::Stop {
+                break;
+            }
+            fields.push((field.id, field.field_type, i_prot.read_string().unwrap()));
+            i_prot.read_field_end().unwrap();
+        }
+        assert_eq!(
+            fields,
+            vec![
+                (Some(1), TType::String, "a".to_owned()),
+                (Some(5), TType::String, "b".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn must_skip_unknown_fields_and_reject_mismatched_types() {
+        let write = |values_id: i16| {
+            let mut bytes = Vec::new();
+            let mut o_prot = TBinaryOutputProtocol::new(&mut bytes, true);
+            o_prot
+                .write_struct_begin(&TStructIdentifier::new("Sparse"))
+                .unwrap();
+            o_prot
+                .write_field_begin(&TFieldIdentifier::new("extra", TType::I64, 2))
+                .unwrap();
+            o_prot.write_i64(7).unwrap();
+            o_prot.write_field_end().unwrap();
+            o_prot
+                .write_field_begin(&TFieldIdentifier::new("name", TType::String, 1))
+                .unwrap();
+            o_prot.write_string("a").unwrap();
+            o_prot.write_field_end().unwrap();
+            o_prot
+                .write_field_begin(&TFieldIdentifier::new("values", TType::I32, values_id))
+                .unwrap();
+            o_prot.write_i32(9).unwrap();
+            o_prot.write_field_end().unwrap();
+            o_prot.write_field_stop().unwrap();
+            o_prot.write_struct_end().unwrap();
+            bytes
+        };
+
+        // a list field holding an i32
+        let bytes = write(3);
+        let result: crate::Result<Sparse> = from_slice(&bytes, TProtocolKind::Binary);
+        assert!(matches!(
+            result,
+            Err(crate::Error::Protocol(ref e)) if e.kind == ProtocolErrorKind::InvalidData
+        ));
+
+        let bytes = write(9);
+        let read: Sparse = from_slice(&bytes, TProtocolKind::Binary).unwrap();
+        assert_eq!(
+            read,
+            Sparse {
+                name: "a".to_owned(),
+                note: None,
+                values: Vec::new(),
+            }
+        );
+    }
+}
@@ -0,0 +1,880 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+
+use ::serde::ser::{self, Serialize};
+
+use super::{enum_value, field_id, invalid_data};
+use crate::protocol::{
+    TDynamicOutputProtocol, TFieldIdentifier, TListIdentifier, TMapIdentifier, TOutputProtocol,
+    TProtocolKind, TStructIdentifier, TType,
+};
+
+/// Write `value` to `o_prot`.
+///
+/// The protocol is not flushed.
+pub fn to_protocol<T>(value: &T, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()>
+where
+    T: Serialize + ?Sized,
+{
+    value.serialize(&mut TSerializer::new(o_prot))
+}
+
+/// Return `value` encoded with the `protocol` protocol.
+pub fn to_vec<T>(value: &T, protocol: TProtocolKind) -> crate::Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    let mut bytes = Vec::new();
+    let mut o_prot = TDynamicOutputProtocol::new(protocol, &mut bytes);
+    to_protocol(value, &mut o_prot)?;
+    o_prot.flush()?;
+    Ok(bytes)
+}
+
+/// `serde::Serializer` that writes values to a `TOutputProtocol`.
+///
+/// Values are mapped to Thrift types as described in the module
+/// documentation. Each value is encoded in memory before it is written,
+/// since the element types of lists and maps have to be written before
+/// their elements.
+pub struct TSerializer<'a> {
+    o_prot: &'a mut dyn TOutputProtocol,
+}
+
+impl<'a> TSerializer<'a> {
+    /// Create a `TSerializer` that writes values to `o_prot`.
+    pub fn new(o_prot: &'a mut dyn TOutputProtocol) -> TSerializer<'a> {
+        TSerializer { o_prot }
+    }
+
+    fn write(&mut self, node: Node) -> crate::Result<()> {
+        match node {
+            // a top-level `None` writes nothing
+            Node::Absent => Ok(()),
+            node => node.write(self.o_prot),
+        }
+    }
+
+    fn writer<B>(&mut self, builder: B) -> Writer<'_, B> {
+        Writer {
+            o_prot: &mut *self.o_prot,
+            builder,
+        }
+    }
+}
+
+impl fmt::Debug for TSerializer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TSerializer").finish_non_exhaustive()
+    }
+}
+
+macro_rules! write_with {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method(self, $($arg: $ty),*) -> crate::Result<()> {
+                let node = NodeSerializer.$method($($arg),*)?;
+                self.write(node)
+            }
+        )*
+    };
+}
+
+impl<'b> ser::Serializer for &'b mut TSerializer<'_> {
+    type Ok = ();
+    type Error = crate::Error;
+    type SerializeSeq = Writer<'b, ListBuilder>;
+    type SerializeTuple = Writer<'b, ListBuilder>;
+    type SerializeTupleStruct = Writer<'b, ListBuilder>;
+    type SerializeTupleVariant = Writer<'b, UnionBuilder<ListBuilder>>;
+    type SerializeMap = Writer<'b, MapBuilder>;
+    type SerializeStruct = Writer<'b, StructBuilder>;
+    type SerializeStructVariant = Writer<'b, UnionBuilder<StructBuilder>>;
+
+    write_with! {
+        serialize_bool(v: bool);
+        serialize_i8(v: i8);
+        serialize_i16(v: i16);
+        serialize_i32(v: i32);
+        serialize_i64(v: i64);
+        serialize_u8(v: u8);
+        serialize_u16(v: u16);
+        serialize_u32(v: u32);
+        serialize_u64(v: u64);
+        serialize_f32(v: f32);
+        serialize_f64(v: f64);
+        serialize_char(v: char);
+        serialize_str(v: &str);
+        serialize_bytes(v: &[u8]);
+        serialize_none();
+        serialize_unit();
+        serialize_unit_struct(name: &'static str);
+        serialize_unit_variant(name: &'static str, variant_index: u32, variant: &'static str);
+    }
+
+    fn serialize_some<T>(self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        let node = NodeSerializer.serialize_newtype_variant(name, variant_index, variant, value)?;
+        self.write(node)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> crate::Result<Self::SerializeSeq> {
+        Ok(self.writer(NodeSerializer.serialize_seq(len)?))
+    }
+
+    fn serialize_tuple(self, len: usize) -> crate::Result<Self::SerializeTuple> {
+        Ok(self.writer(NodeSerializer.serialize_tuple(len)?))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> crate::Result<Self::SerializeTupleStruct> {
+        Ok(self.writer(NodeSerializer.serialize_tuple_struct(name, len)?))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> crate::Result<Self::SerializeTupleVariant> {
+        let builder = NodeSerializer.serialize_tuple_variant(name, variant_index, variant, len)?;
+        Ok(self.writer(builder))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> crate::Result<Self::SerializeMap> {
+        Ok(self.writer(NodeSerializer.serialize_map(len)?))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> crate::Result<Self::SerializeStruct> {
+        Ok(self.writer(NodeSerializer.serialize_struct(name, len)?))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> crate::Result<Self::SerializeStructVariant> {
+        let builder = NodeSerializer.serialize_struct_variant(name, variant_index, variant, len)?;
+        Ok(self.writer(builder))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Builds a compound value in memory with `builder`, and writes it once it
+/// is complete.
+pub struct Writer<'a, B> {
+    o_prot: &'a mut dyn TOutputProtocol,
+    builder: B,
+}
+
+impl<B> ser::SerializeSeq for Writer<'_, B>
+where
+    B: ser::SerializeSeq<Ok = Node, Error = crate::Error>,
+{
+    type Ok = ();
+    type Error = crate::Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_element(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.builder.end()?.write(self.o_prot)
+    }
+}
+
+impl<B> ser::SerializeTuple for Writer<'_, B>
+where
+    B: ser::SerializeTuple<Ok = Node, Error = crate::Error>,
+{
+    type Ok = ();
+    type Error = crate::Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_element(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.builder.end()?.write(self.o_prot)
+    }
+}
+
+impl<B> ser::SerializeTupleStruct for Writer<'_, B>
+where
+    B: ser::SerializeTupleStruct<Ok = Node, Error = crate::Error>,
+{
+    type Ok = ();
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_field(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.builder.end()?.write(self.o_prot)
+    }
+}
+
+impl<B> ser::SerializeTupleVariant for Writer<'_, B>
+where
+    B: ser::SerializeTupleVariant<Ok = Node, Error = crate::Error>,
+{
+    type Ok = ();
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_field(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.builder.end()?.write(self.o_prot)
+    }
+}
+
+impl<B> ser::SerializeMap for Writer<'_, B>
+where
+    B: ser::SerializeMap<Ok = Node, Error = crate::Error>,
+{
+    type Ok = ();
+    type Error = crate::Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_key(key)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_value(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.builder.end()?.write(self.o_prot)
+    }
+}
+
+impl<B> ser::SerializeStruct for Writer<'_, B>
+where
+    B: ser::SerializeStruct<Ok = Node, Error = crate::Error>,
+{
+    type Ok = ();
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_field(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> crate::Result<()> {
+        self.builder.skip_field(key)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.builder.end()?.write(self.o_prot)
+    }
+}
+
+impl<B> ser::SerializeStructVariant for Writer<'_, B>
+where
+    B: ser::SerializeStructVariant<Ok = Node, Error = crate::Error>,
+{
+    type Ok = ();
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.serialize_field(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> crate::Result<()> {
+        self.builder.skip_field(key)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.builder.end()?.write(self.o_prot)
+    }
+}
+
+/// A value encoded in memory, ready to be written.
+#[derive(Debug)]
+pub enum Node {
+    /// A `None`, which is only written as a missing struct field.
+    Absent,
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Double(f64),
+    String(String),
+    Binary(Vec<u8>),
+    Struct(&'static str, Vec<Field>),
+    List(TType, Vec<Node>),
+    Map(TType, TType, Vec<(Node, Node)>),
+}
+
+#[derive(Debug)]
+pub struct Field {
+    name: &'static str,
+    id: i16,
+    value: Node,
+}
+
+impl Node {
+    fn field_type(&self) -> TType {
+        match self {
+            // never written, see `element`
+            Node::Absent => TType::Void,
+            Node::Bool(_) => TType::Bool,
+            Node::I8(_) => TType::I08,
+            Node::I16(_) => TType::I16,
+            Node::I32(_) => TType::I32,
+            Node::I64(_) => TType::I64,
+            Node::Double(_) => TType::Double,
+            Node::String(_) | Node::Binary(_) => TType::String,
+            Node::Struct(..) => TType::Struct,
+            Node::List(..) => TType::List,
+            Node::Map(..) => TType::Map,
+        }
+    }
+
+    fn write(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        match self {
+            Node::Absent => Err(invalid_data("None can only be written as a struct field")),
+            Node::Bool(b) => o_prot.write_bool(*b),
+            Node::I8(i) => o_prot.write_i8(*i),
+            Node::I16(i) => o_prot.write_i16(*i),
+            Node::I32(i) => o_prot.write_i32(*i),
+            Node::I64(i) => o_prot.write_i64(*i),
+            Node::Double(d) => o_prot.write_double(*d),
+            Node::String(s) => o_prot.write_string(s),
+            Node::Binary(b) => o_prot.write_bytes(b),
+            Node::Struct(name, fields) => {
+                o_prot.write_struct_begin(&TStructIdentifier::new(*name))?;
+                for field in fields {
+                    o_prot.write_field_begin(&TFieldIdentifier::new(
+                        field.name,
+                        field.value.field_type(),
+                        field.id,
+                    ))?;
+                    field.value.write(o_prot)?;
+                    o_prot.write_field_end()?;
+                }
+                o_prot.write_field_stop()?;
+                o_prot.write_struct_end()
+            }
+            Node::List(element_type, elements) => {
+                o_prot.write_list_begin(&TListIdentifier::new(
+                    *element_type,
+                    size(elements.len())?,
+                ))?;
+                for element in elements {
+                    element.write(o_prot)?;
+                }
+                o_prot.write_list_end()
+            }
+            Node::Map(key_type, value_type, entries) => {
+                o_prot.write_map_begin(&TMapIdentifier::new(
+                    *key_type,
+                    *value_type,
+                    size(entries.len())?,
+                ))?;
+                for (key, value) in entries {
+                    key.write(o_prot)?;
+                    value.write(o_prot)?;
+                }
+                o_prot.write_map_end()
+            }
+        }
+    }
+}
+
+fn size(len: usize) -> crate::Result<i32> {
+    i32::try_from(len).map_err(|_| invalid_data(format!("{} elements are too many", len)))
+}
+
+/// Check that `node` can be an element of a list or map, or a union's
+/// value.
+fn element(node: Node) -> crate::Result<Node> {
+    match node {
+        Node::Absent => Err(invalid_data("None can only be written as a struct field")),
+        node => Ok(node),
+    }
+}
+
+/// Return the type shared by all of `elements`.
+fn element_type<'a, I>(mut elements: I) -> crate::Result<TType>
+where
+    I: Iterator<Item = &'a Node>,
+{
+    let element_type = match elements.next() {
+        Some(first) => first.field_type(),
+        None => return Ok(TType::I08),
+    };
+    if elements.any(|element| element.field_type() != element_type) {
+        return Err(invalid_data(
+            "all elements of a list or map must have the same Thrift type",
+        ));
+    }
+    Ok(element_type)
+}
+
+/// `serde::Serializer` that encodes values in memory.
+struct NodeSerializer;
+
+impl ser::Serializer for NodeSerializer {
+    type Ok = Node;
+    type Error = crate::Error;
+    type SerializeSeq = ListBuilder;
+    type SerializeTuple = ListBuilder;
+    type SerializeTupleStruct = ListBuilder;
+    type SerializeTupleVariant = UnionBuilder<ListBuilder>;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = StructBuilder;
+    type SerializeStructVariant = UnionBuilder<StructBuilder>;
+
+    fn serialize_bool(self, v: bool) -> crate::Result<Node> {
+        Ok(Node::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> crate::Result<Node> {
+        Ok(Node::I8(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> crate::Result<Node> {
+        Ok(Node::I16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> crate::Result<Node> {
+        Ok(Node::I32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> crate::Result<Node> {
+        Ok(Node::I64(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> crate::Result<Node> {
+        Ok(Node::I8(v as i8))
+    }
+
+    fn serialize_u16(self, v: u16) -> crate::Result<Node> {
+        Ok(Node::I32(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> crate::Result<Node> {
+        Ok(Node::I64(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> crate::Result<Node> {
+        i64::try_from(v)
+            .map(Node::I64)
+            .map_err(|_| invalid_data(format!("{} does not fit in an i64", v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> crate::Result<Node> {
+        Ok(Node::Double(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> crate::Result<Node> {
+        Ok(Node::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> crate::Result<Node> {
+        Ok(Node::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> crate::Result<Node> {
+        Ok(Node::String(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> crate::Result<Node> {
+        Ok(Node::Binary(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> crate::Result<Node> {
+        Ok(Node::Absent)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> crate::Result<Node>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> crate::Result<Node> {
+        Ok(Node::Struct("", Vec::new()))
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> crate::Result<Node> {
+        Ok(Node::Struct(name, Vec::new()))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> crate::Result<Node> {
+        Ok(Node::I32(enum_value(variant, variant_index as usize)))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> crate::Result<Node>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> crate::Result<Node>
+    where
+        T: Serialize + ?Sized,
+    {
+        let value = element(value.serialize(NodeSerializer)?)?;
+        Ok(union(name, variant_index, variant, value))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> crate::Result<ListBuilder> {
+        Ok(ListBuilder {
+            elements: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> crate::Result<ListBuilder> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> crate::Result<ListBuilder> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> crate::Result<UnionBuilder<ListBuilder>> {
+        Ok(UnionBuilder {
+            name,
+            variant_index,
+            variant,
+            builder: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> crate::Result<MapBuilder> {
+        Ok(MapBuilder {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> crate::Result<StructBuilder> {
+        Ok(StructBuilder {
+            name,
+            fields: Vec::with_capacity(len),
+            position: 1,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> crate::Result<UnionBuilder<StructBuilder>> {
+        Ok(UnionBuilder {
+            name,
+            variant_index,
+            variant,
+            builder: self.serialize_struct(variant, len)?,
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Return the union `name` holding `value` in the field of `variant`.
+fn union(name: &'static str, variant_index: u32, variant: &'static str, value: Node) -> Node {
+    Node::Struct(
+        name,
+        vec![Field {
+            name: variant,
+            id: field_id(variant, variant_index as usize + 1),
+            value,
+        }],
+    )
+}
+
+#[derive(Debug)]
+pub struct ListBuilder {
+    elements: Vec<Node>,
+}
+
+impl ListBuilder {
+    fn push<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.elements
+            .push(element(value.serialize(NodeSerializer)?)?);
+        Ok(())
+    }
+
+    fn build(self) -> crate::Result<Node> {
+        let element_type = element_type(self.elements.iter())?;
+        Ok(Node::List(element_type, self.elements))
+    }
+}
+
+impl ser::SerializeSeq for ListBuilder {
+    type Ok = Node;
+    type Error = crate::Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> crate::Result<Node> {
+        self.build()
+    }
+}
+
+impl ser::SerializeTuple for ListBuilder {
+    type Ok = Node;
+    type Error = crate::Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> crate::Result<Node> {
+        self.build()
+    }
+}
+
+impl ser::SerializeTupleStruct for ListBuilder {
+    type Ok = Node;
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> crate::Result<Node> {
+        self.build()
+    }
+}
+
+#[derive(Debug)]
+pub struct MapBuilder {
+    entries: Vec<(Node, Node)>,
+    key: Option<Node>,
+}
+
+impl ser::SerializeMap for MapBuilder {
+    type Ok = Node;
+    type Error = crate::Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.key = Some(element(key.serialize(NodeSerializer)?)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| invalid_data("map value serialized before its key"))?;
+        let value = element(value.serialize(NodeSerializer)?)?;
+        self.entries.push((key, value));
+        Ok(())
+    }
+
+    fn end(self) -> crate::Result<Node> {
+        let key_type = element_type(self.entries.iter().map(|(key, _)| key))?;
+        let value_type = element_type(self.entries.iter().map(|(_, value)| value))?;
+        Ok(Node::Map(key_type, value_type, self.entries))
+    }
+}
+
+#[derive(Debug)]
+pub struct StructBuilder {
+    name: &'static str,
+    fields: Vec<Field>,
+    /// The position of the next field.
+    position: usize,
+}
+
+impl ser::SerializeStruct for StructBuilder {
+    type Ok = Node;
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        let id = field_id(key, self.position);
+        self.position += 1;
+        match value.serialize(NodeSerializer)? {
+            Node::Absent => {}
+            value => self.fields.push(Field {
+                name: key,
+                id,
+                value,
+            }),
+        }
+        Ok(())
+    }
+
+    fn skip_field(&mut self, _key: &'static str) -> crate::Result<()> {
+        self.position += 1;
+        Ok(())
+    }
+
+    fn end(self) -> crate::Result<Node> {
+        Ok(Node::Struct(self.name, self.fields))
+    }
+}
+
+/// Builds the value of a union variant with `builder`.
+#[derive(Debug)]
+pub struct UnionBuilder<B> {
+    name: &'static str,
+    variant_index: u32,
+    variant: &'static str,
+    builder: B,
+}
+
+impl ser::SerializeTupleVariant for UnionBuilder<ListBuilder> {
+    type Ok = Node;
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.push(value)
+    }
+
+    fn end(self) -> crate::Result<Node> {
+        let value = self.builder.build()?;
+        Ok(union(self.name, self.variant_index, self.variant, value))
+    }
+}
+
+impl ser::SerializeStructVariant for UnionBuilder<StructBuilder> {
+    type Ok = Node;
+    type Error = crate::Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> crate::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        ser::SerializeStruct::serialize_field(&mut self.builder, key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> crate::Result<()> {
+        ser::SerializeStruct::skip_field(&mut self.builder, key)
+    }
+
+    fn end(self) -> crate::Result<Node> {
+        let value = ser::SerializeStruct::end(self.builder)?;
+        Ok(union(self.name, self.variant_index, self.variant, value))
+    }
+}
@@ -0,0 +1,142 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use socket2::{SockRef, Type};
+
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::process;
+
+/// First descriptor passed by systemd, after stdin, stdout and stderr.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Take the TCP listeners passed to this process by systemd socket
+/// activation, in the order of the `ListenStream=` lines of the socket unit.
+///
+/// This follows the `sd_listen_fds` protocol: the sockets are descriptors 3
+/// and up, `LISTEN_FDS` holds their number and `LISTEN_PID` the process they
+/// were meant for. Returns an empty `Vec` if the process was not started by
+/// socket activation. The variables are removed from the environment, so
+/// the sockets are only taken once and are not passed on to child
+/// processes, which is also why they are marked close-on-exec.
+///
+/// Each listener can be served with `TServer::listen_on`. Returns `Err` if a
+/// passed descriptor is not a listening TCP socket.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::server::systemd_listeners;
+/// use std::net::TcpListener;
+///
+/// let listener = match systemd_listeners().unwrap().pop() {
+///     Some(listener) => listener,
+///     None => TcpListener::bind("127.0.0.1:9090").unwrap(),
+/// };
+/// ```
+pub fn systemd_listeners() -> io::Result<Vec<TcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = fds.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid LISTEN_FDS value {:?}", fds),
+        )
+    })?;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // the environment was cleared above, so each descriptor is only
+            // ever owned by the listener created here
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            check_listener(&listener, fd)?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+fn check_listener(listener: &TcpListener, fd: RawFd) -> io::Result<()> {
+    let socket = SockRef::from(listener);
+    socket.set_cloexec(true)?;
+    let is_tcp = socket.local_addr()?.as_socket().is_some();
+    if !is_tcp || socket.r#type()? != Type::STREAM || !is_listening(&socket)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("descriptor {} is not a listening TCP socket", fd),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux"
+))]
+fn is_listening(socket: &SockRef<'_>) -> io::Result<bool> {
+    socket.is_listener()
+}
+
+// SO_ACCEPTCONN cannot be read here; accept() reports the mistake instead
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux"
+)))]
+fn is_listening(_: &SockRef<'_>) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_return_nothing_without_socket_activation() {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        assert!(systemd_listeners().unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn must_reject_descriptors_that_are_not_listening() {
+        use std::os::unix::io::IntoRawFd;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let fd = stream.into_raw_fd();
+        let socket = unsafe { TcpListener::from_raw_fd(fd) };
+        assert!(check_listener(&socket, fd).is_err());
+        assert!(check_listener(&listener, 0).is_ok());
+    }
+}
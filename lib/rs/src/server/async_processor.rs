@@ -0,0 +1,539 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use crate::protocol::{
+    copy_value, TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TMessageIdentifier,
+    TOutputProtocol, TType, MAXIMUM_COPY_DEPTH,
+};
+use crate::{ApplicationError, ApplicationErrorKind};
+
+use super::{request_context, with_request_context, TProcessor};
+
+/// Future returned by `TAsyncProcessor::process`.
+pub type TProcessFuture<'a> = Pin<Box<dyn Future<Output = crate::Result<()>> + Send + 'a>>;
+
+/// Processes Thrift service calls without blocking the calling thread.
+///
+/// This is the asynchronous counterpart of `TProcessor`, for servers that
+/// serve connections as tasks of an async runtime. The library does not
+/// depend on a runtime: the returned future can be polled by any of them.
+///
+/// A processor for asynchronous handlers reads the arguments from `i`,
+/// awaits the handler and writes its result to `o`, the same way a
+/// `TProcessor` does; `handle_process_result` works for both. Synchronous
+/// processors are hosted with `TSpawnBlockingProcessor`, and asynchronous
+/// processors are served by the threaded servers with `TBlockOnProcessor`.
+pub trait TAsyncProcessor: Send + Sync {
+    /// Process a Thrift service call.
+    ///
+    /// Reads arguments from `i`, executes the user's handler code, and
+    /// writes the response to `o`.
+    ///
+    /// Resolves to `()` if the handler was executed; `Err` otherwise.
+    fn process<'a>(
+        &'a self,
+        i: &'a mut (dyn TInputProtocol + Send),
+        o: &'a mut (dyn TOutputProtocol + Send),
+    ) -> TProcessFuture<'a>;
+}
+
+impl<P> TAsyncProcessor for Arc<P>
+where
+    P: TAsyncProcessor + ?Sized,
+{
+    fn process<'a>(
+        &'a self,
+        i: &'a mut (dyn TInputProtocol + Send),
+        o: &'a mut (dyn TOutputProtocol + Send),
+    ) -> TProcessFuture<'a> {
+        (**self).process(i, o)
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+type Spawner = dyn Fn(Job) + Send + Sync;
+
+/// `TAsyncProcessor` that runs a synchronous `TProcessor` off the async
+/// runtime's threads.
+///
+/// The arguments of each request are read into memory, and the processor
+/// is run on the copy by a job handed to the spawner, so a slow handler
+/// blocks neither the runtime nor the connection's protocols. The reply is
+/// copied back to the connection when the job is done. The request context
+/// of the polling thread is passed on to the job, and a panic in the
+/// handler is resumed in the task that awaits the request.
+///
+/// # Examples
+///
+/// With tokio, hand the jobs to `spawn_blocking`:
+///
+/// ```no_run
+/// # use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// # use thrift::server::{TProcessor, TSpawnBlockingProcessor};
+/// # struct SyncProcessor;
+/// # impl TProcessor for SyncProcessor {
+/// #     fn process(&self, _: &mut dyn TInputProtocol, _: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # mod tokio { pub mod task { pub fn spawn_blocking<F: FnOnce()>(f: F) { f() } } }
+/// let processor = TSpawnBlockingProcessor::new(SyncProcessor, |job| {
+///     tokio::task::spawn_blocking(job);
+/// });
+/// ```
+pub struct TSpawnBlockingProcessor<PRC> {
+    processor: Arc<PRC>,
+    spawn: Box<Spawner>,
+}
+
+impl<PRC> TSpawnBlockingProcessor<PRC>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    /// Create a `TSpawnBlockingProcessor` that runs `processor` in the jobs
+    /// it passes to `spawn`.
+    ///
+    /// `spawn` must run each job on a thread where blocking is allowed. A
+    /// job that is dropped without being run fails its request.
+    pub fn new<F>(processor: PRC, spawn: F) -> TSpawnBlockingProcessor<PRC>
+    where
+        F: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static,
+    {
+        TSpawnBlockingProcessor {
+            processor: Arc::new(processor),
+            spawn: Box::new(spawn),
+        }
+    }
+}
+
+impl<PRC> TAsyncProcessor for TSpawnBlockingProcessor<PRC>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    fn process<'a>(
+        &'a self,
+        i: &'a mut (dyn TInputProtocol + Send),
+        o: &'a mut (dyn TOutputProtocol + Send),
+    ) -> TProcessFuture<'a> {
+        Box::pin(async move {
+            let request = read_request(i)?;
+            let processor = self.processor.clone();
+            let context = request_context().unwrap_or_default();
+            let (sender, completion) = completion();
+            (self.spawn)(Box::new(move || {
+                let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                    with_request_context(context, || process_buffered(&*processor, &request))
+                }));
+                sender.send(served);
+            }));
+
+            let (reply, result) = match completion.await {
+                Some(Ok(served)) => served,
+                Some(Err(payload)) => panic::resume_unwind(payload),
+                None => {
+                    return Err(ApplicationError::new(
+                        ApplicationErrorKind::InternalError,
+                        "request was dropped before it was processed",
+                    )
+                    .into())
+                }
+            };
+            write_reply(&reply, o)?;
+            result
+        })
+    }
+}
+
+impl<PRC> fmt::Debug for TSpawnBlockingProcessor<PRC> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TSpawnBlockingProcessor")
+    }
+}
+
+/// `TProcessor` that serves a `TAsyncProcessor` by blocking the calling
+/// thread until each request has been processed.
+///
+/// This lets `TServer` and the other threaded servers host asynchronous
+/// handlers, for example ones generated for an async service, with one
+/// request in flight per worker. The arguments of each request are read
+/// into memory first, so the future does not borrow the connection's
+/// protocols.
+#[derive(Debug)]
+pub struct TBlockOnProcessor<APRC> {
+    processor: APRC,
+}
+
+impl<APRC> TBlockOnProcessor<APRC>
+where
+    APRC: TAsyncProcessor,
+{
+    /// Create a `TBlockOnProcessor` that serves `processor`.
+    pub fn new(processor: APRC) -> TBlockOnProcessor<APRC> {
+        TBlockOnProcessor { processor }
+    }
+}
+
+impl<APRC> TProcessor for TBlockOnProcessor<APRC>
+where
+    APRC: TAsyncProcessor,
+{
+    fn process(
+        &self,
+        i: &mut dyn TInputProtocol,
+        o: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()> {
+        let request = read_request(i)?;
+        let mut request = TBinaryInputProtocol::new(&request[..], true);
+        let mut reply = TBinaryOutputProtocol::new(Vec::new(), true);
+        let result = block_on(self.processor.process(&mut request, &mut reply));
+        write_reply(&reply.transport, o)?;
+        result
+    }
+}
+
+/// Read the next request from `i`, returning it encoded with the binary
+/// protocol.
+fn read_request(i: &mut dyn TInputProtocol) -> crate::Result<Vec<u8>> {
+    let ident = i.read_message_begin()?;
+    read_arguments(&ident, i)
+}
+
+/// Read the rest of the request whose header `ident` has been read from
+/// `i`, returning the whole request encoded with the binary protocol.
+pub(super) fn read_arguments(
+    ident: &TMessageIdentifier,
+    i: &mut dyn TInputProtocol,
+) -> crate::Result<Vec<u8>> {
+    let mut request = TBinaryOutputProtocol::new(Vec::new(), true);
+    request.write_message_begin(ident)?;
+    copy_value(i, &mut request, TType::Struct, MAXIMUM_COPY_DEPTH)?;
+    i.read_message_end()?;
+    request.write_message_end()?;
+    Ok(request.transport)
+}
+
+/// Run `processor` on a request read by `read_request`, returning the
+/// reply it wrote encoded with the binary protocol.
+pub(super) fn process_buffered<PRC>(processor: &PRC, request: &[u8]) -> (Vec<u8>, crate::Result<()>)
+where
+    PRC: TProcessor,
+{
+    let mut i = TBinaryInputProtocol::new(request, true);
+    let mut o = TBinaryOutputProtocol::new(Vec::new(), true);
+    let result = processor.process(&mut i, &mut o);
+    (o.transport, result)
+}
+
+/// Copy the reply written by `process_buffered` to `o`, if there is one.
+pub(super) fn write_reply(reply: &[u8], o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    if reply.is_empty() {
+        // oneway requests, and requests that failed before a reply was
+        // written, have nothing to send
+        return Ok(());
+    }
+    copy_message(&mut TBinaryInputProtocol::new(reply, true), o)?;
+    o.flush()
+}
+
+/// Copy one message from `i` to `o`.
+fn copy_message(i: &mut dyn TInputProtocol, o: &mut dyn TOutputProtocol) -> crate::Result<()> {
+    let ident = i.read_message_begin()?;
+    o.write_message_begin(&ident)?;
+    copy_value(i, o, TType::Struct, MAXIMUM_COPY_DEPTH)?;
+    i.read_message_end()?;
+    o.write_message_end()
+}
+
+type Served = thread::Result<(Vec<u8>, crate::Result<()>)>;
+
+#[derive(Default)]
+struct Slot {
+    served: Option<Served>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// Create the two ends through which a spawned job reports its outcome.
+fn completion() -> (CompletionSender, Completion) {
+    let slot = Arc::new(Mutex::new(Slot::default()));
+    (CompletionSender(Some(slot.clone())), Completion(slot))
+}
+
+/// Sending end of a `Completion`; dropping it unsent completes it with
+/// nothing.
+struct CompletionSender(Option<Arc<Mutex<Slot>>>);
+
+impl CompletionSender {
+    fn send(mut self, served: Served) {
+        self.complete(Some(served));
+    }
+
+    fn complete(&mut self, served: Option<Served>) {
+        if let Some(slot) = self.0.take() {
+            let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+            slot.served = served;
+            slot.done = true;
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for CompletionSender {
+    fn drop(&mut self) {
+        self.complete(None);
+    }
+}
+
+/// Future that resolves to the outcome of a spawned job, or to `None` if
+/// the job was dropped without being run.
+struct Completion(Arc<Mutex<Slot>>);
+
+impl Future for Completion {
+    type Output = Option<Served>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if slot.done {
+            Poll::Ready(slot.served.take())
+        } else {
+            slot.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Waker that unparks the thread blocked in `block_on`.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll `future` on the current thread until it completes.
+fn block_on<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::ThreadId;
+
+    use crate::protocol::{TFieldIdentifier, TMessageIdentifier, TMessageType, TStructIdentifier};
+
+    /// Read a call with an `i32` argument in field 1.
+    fn read_call(i: &mut dyn TInputProtocol) -> crate::Result<(TMessageIdentifier, i32)> {
+        let ident = i.read_message_begin()?;
+        i.read_struct_begin()?;
+        let field = i.read_field_begin()?;
+        assert_eq!(field.id, Some(1));
+        let value = i.read_i32()?;
+        i.read_field_end()?;
+        assert_eq!(i.read_field_begin()?.field_type, TType::Stop);
+        i.read_struct_end()?;
+        i.read_message_end()?;
+        Ok((ident, value))
+    }
+
+    /// Write a message with an `i32` in field `id` of its struct.
+    fn write_message(
+        o: &mut dyn TOutputProtocol,
+        ident: &TMessageIdentifier,
+        id: i16,
+        value: i32,
+    ) -> crate::Result<()> {
+        o.write_message_begin(ident)?;
+        o.write_struct_begin(&TStructIdentifier::new("value"))?;
+        o.write_field_begin(&TFieldIdentifier::new("value", TType::I32, id))?;
+        o.write_i32(value)?;
+        o.write_field_end()?;
+        o.write_field_stop()?;
+        o.write_struct_end()?;
+        o.write_message_end()?;
+        o.flush()
+    }
+
+    fn reply_to(ident: TMessageIdentifier) -> TMessageIdentifier {
+        TMessageIdentifier::new(ident.name, TMessageType::Reply, ident.sequence_number)
+    }
+
+    /// Replies with twice its argument, and records the thread it ran on.
+    struct Double(Mutex<Option<ThreadId>>);
+
+    impl TProcessor for Double {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            *self.0.lock().unwrap() = Some(thread::current().id());
+            let (ident, value) = read_call(i)?;
+            if value < 0 {
+                panic!("negative argument");
+            }
+            if ident.message_type == TMessageType::OneWay {
+                return Ok(());
+            }
+            write_message(o, &reply_to(ident), 0, value * 2)
+        }
+    }
+
+    /// Replies with twice its argument after yielding once.
+    struct AsyncDouble;
+
+    impl TAsyncProcessor for AsyncDouble {
+        fn process<'a>(
+            &'a self,
+            i: &'a mut (dyn TInputProtocol + Send),
+            o: &'a mut (dyn TOutputProtocol + Send),
+        ) -> TProcessFuture<'a> {
+            Box::pin(async move {
+                let (ident, value) = read_call(i)?;
+                YieldOnce(false).await;
+                write_message(o, &reply_to(ident), 0, value * 2)
+            })
+        }
+    }
+
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn request(message_type: TMessageType, value: i32) -> Vec<u8> {
+        let mut o = TBinaryOutputProtocol::new(Vec::new(), true);
+        let ident = TMessageIdentifier::new("double", message_type, 7);
+        write_message(&mut o, &ident, 1, value).unwrap();
+        o.transport
+    }
+
+    fn read_reply(reply: &[u8]) -> i32 {
+        let mut i = TBinaryInputProtocol::new(reply, true);
+        let ident = i.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Reply);
+        assert_eq!(ident.sequence_number, 7);
+        i.read_struct_begin().unwrap();
+        assert_eq!(i.read_field_begin().unwrap().id, Some(0));
+        i.read_i32().unwrap()
+    }
+
+    fn spawn_thread(job: Box<dyn FnOnce() + Send>) {
+        thread::spawn(job);
+    }
+
+    fn process_spawned(
+        processor: &TSpawnBlockingProcessor<Double>,
+        message_type: TMessageType,
+        value: i32,
+    ) -> (Vec<u8>, crate::Result<()>) {
+        let request = request(message_type, value);
+        let mut i = TBinaryInputProtocol::new(&request[..], true);
+        let mut o = TBinaryOutputProtocol::new(Vec::new(), true);
+        let result = block_on(processor.process(&mut i, &mut o));
+        (o.transport, result)
+    }
+
+    #[test]
+    fn must_run_sync_processor_on_spawned_job() {
+        let processor = TSpawnBlockingProcessor::new(Double(Mutex::new(None)), spawn_thread);
+
+        let (reply, result) = process_spawned(&processor, TMessageType::Call, 21);
+        result.unwrap();
+        assert_eq!(read_reply(&reply), 42);
+        let ran_on = processor.processor.0.lock().unwrap().unwrap();
+        assert_ne!(ran_on, thread::current().id());
+    }
+
+    #[test]
+    fn must_not_reply_to_oneway_requests() {
+        let processor = TSpawnBlockingProcessor::new(Double(Mutex::new(None)), spawn_thread);
+
+        let (reply, result) = process_spawned(&processor, TMessageType::OneWay, 21);
+        result.unwrap();
+        assert!(reply.is_empty());
+    }
+
+    #[test]
+    fn must_fail_requests_whose_job_is_dropped() {
+        let processor = TSpawnBlockingProcessor::new(Double(Mutex::new(None)), drop);
+
+        let (reply, result) = process_spawned(&processor, TMessageType::Call, 21);
+        assert!(reply.is_empty());
+        match result {
+            Err(crate::Error::Application(e)) => {
+                assert_eq!(e.kind, ApplicationErrorKind::InternalError)
+            }
+            other => panic!("expected an application error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_resume_handler_panics_in_awaiting_task() {
+        let processor = TSpawnBlockingProcessor::new(Double(Mutex::new(None)), spawn_thread);
+
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            process_spawned(&processor, TMessageType::Call, -1)
+        }));
+        let payload = served.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"negative argument"));
+    }
+
+    #[test]
+    fn must_serve_async_processor_synchronously() {
+        let processor = TBlockOnProcessor::new(AsyncDouble);
+        let request = request(TMessageType::Call, 5);
+        let mut i = TBinaryInputProtocol::new(&request[..], true);
+        let mut o = TBinaryOutputProtocol::new(Vec::new(), true);
+
+        TProcessor::process(&processor, &mut i, &mut o).unwrap();
+        assert_eq!(read_reply(&o.transport), 10);
+    }
+}
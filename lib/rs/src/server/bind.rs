@@ -0,0 +1,291 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+/// Where `TServer::listen` accepts connections.
+///
+/// Strings and socket addresses convert to `Tcp`, and paths to `Unix`, so
+/// `listen` can be called with any of them directly.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use thrift::server::TBindTarget;
+///
+/// let tcp = TBindTarget::from("127.0.0.1:9090");
+/// let unix = TBindTarget::from(Path::new("/run/calculator.sock"));
+/// assert_eq!(unix, TBindTarget::Unix("/run/calculator.sock".into()));
+/// # let _ = tcp;
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum TBindTarget {
+    /// TCP address in any form `ToSocketAddrs` accepts, such as
+    /// `"localhost:9090"` or `"[::1]:9090"`.
+    Tcp(String),
+    /// Path of a Unix domain socket in the file system.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Name of a Unix domain socket in the Linux abstract namespace, without
+    /// the leading NUL byte.
+    ///
+    /// Abstract sockets do not appear in the file system and disappear when
+    /// the last socket bound to them is closed, so there is no stale socket
+    /// file to remove. They are scoped to the network namespace.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Abstract(Vec<u8>),
+}
+
+impl fmt::Display for TBindTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TBindTarget::Tcp(address) => f.write_str(address),
+            #[cfg(unix)]
+            TBindTarget::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            TBindTarget::Abstract(name) => write!(f, "unix:@{}", String::from_utf8_lossy(name)),
+        }
+    }
+}
+
+impl From<&str> for TBindTarget {
+    fn from(address: &str) -> Self {
+        TBindTarget::Tcp(address.to_owned())
+    }
+}
+
+impl From<String> for TBindTarget {
+    fn from(address: String) -> Self {
+        TBindTarget::Tcp(address)
+    }
+}
+
+impl From<&String> for TBindTarget {
+    fn from(address: &String) -> Self {
+        TBindTarget::Tcp(address.clone())
+    }
+}
+
+impl From<SocketAddr> for TBindTarget {
+    fn from(address: SocketAddr) -> Self {
+        TBindTarget::Tcp(address.to_string())
+    }
+}
+
+impl From<(IpAddr, u16)> for TBindTarget {
+    fn from(address: (IpAddr, u16)) -> Self {
+        SocketAddr::from(address).into()
+    }
+}
+
+impl From<(&str, u16)> for TBindTarget {
+    fn from((host, port): (&str, u16)) -> Self {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => (ip, port).into(),
+            Err(_) => TBindTarget::Tcp(format!("{}:{}", host, port)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<PathBuf> for TBindTarget {
+    fn from(path: PathBuf) -> Self {
+        TBindTarget::Unix(path)
+    }
+}
+
+#[cfg(unix)]
+impl From<&Path> for TBindTarget {
+    fn from(path: &Path) -> Self {
+        TBindTarget::Unix(path.to_owned())
+    }
+}
+
+/// Maximum number of connections waiting to be accepted on an abstract
+/// socket, as `UnixListener::bind` uses for path sockets.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const LISTEN_BACKLOG: i32 = 128;
+
+/// Bind a listening Unix domain socket to `name` in the abstract namespace.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(super) fn bind_abstract(name: &[u8]) -> io::Result<UnixListener> {
+    use socket2::{Domain, SockAddr, Socket, Type};
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut path = Vec::with_capacity(name.len() + 1);
+    path.push(0);
+    path.extend_from_slice(name);
+    let address = SockAddr::unix(OsStr::from_bytes(&path))?;
+
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    socket.bind(&address)?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_convert_addresses_to_tcp_targets() {
+        let tcp = |address: &str| TBindTarget::Tcp(address.to_owned());
+        assert_eq!(TBindTarget::from("localhost:9090"), tcp("localhost:9090"));
+        let address: SocketAddr = "[::1]:9090".parse().unwrap();
+        assert_eq!(TBindTarget::from(address), tcp("[::1]:9090"));
+        assert_eq!(TBindTarget::from(("::1", 9090)), tcp("[::1]:9090"));
+        assert_eq!(
+            TBindTarget::from(("localhost", 9090)),
+            tcp("localhost:9090")
+        );
+    }
+
+    #[cfg(unix)]
+    mod unix {
+        use super::*;
+        use std::io;
+        use std::os::unix::net::UnixStream;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+        use std::time::Duration;
+
+        use crate::protocol::{
+            TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+            TBinaryOutputProtocolFactory, TInputProtocol, TMessageIdentifier, TMessageType,
+            TOutputProtocol, TStructIdentifier, TType,
+        };
+        use crate::server::{TProcessor, TServer};
+        use crate::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+        /// Answers each call with an empty reply.
+        struct Empty;
+
+        impl TProcessor for Empty {
+            fn process(
+                &self,
+                i: &mut dyn TInputProtocol,
+                o: &mut dyn TOutputProtocol,
+            ) -> crate::Result<()> {
+                let ident = i.read_message_begin()?;
+                i.skip(TType::Struct)?;
+                i.read_message_end()?;
+                o.write_message_begin(&TMessageIdentifier::new(
+                    ident.name,
+                    TMessageType::Reply,
+                    ident.sequence_number,
+                ))?;
+                o.write_message_end()?;
+                o.flush()
+            }
+        }
+
+        fn serve(target: TBindTarget) {
+            let mut server = TServer::new(
+                TBufferedReadTransportFactory::new(),
+                TBinaryInputProtocolFactory::new(),
+                TBufferedWriteTransportFactory::new(),
+                TBinaryOutputProtocolFactory::new(),
+                Empty,
+                1,
+            );
+            thread::spawn(move || server.listen(target));
+        }
+
+        /// Call the server on `stream` and check that it replies.
+        fn call(stream: UnixStream) {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+            o_prot
+                .write_message_begin(&TMessageIdentifier::new("ping", TMessageType::Call, 1))
+                .unwrap();
+            o_prot
+                .write_struct_begin(&TStructIdentifier::new("ping_args"))
+                .unwrap();
+            o_prot.write_field_stop().unwrap();
+            o_prot.write_struct_end().unwrap();
+            o_prot.write_message_end().unwrap();
+            o_prot.flush().unwrap();
+
+            let mut i_prot = TBinaryInputProtocol::new(stream, true);
+            let ident = i_prot.read_message_begin().unwrap();
+            assert_eq!(ident.message_type, TMessageType::Reply);
+        }
+
+        /// Retry `connect` until the server thread is listening.
+        fn connect<F>(connect: F) -> UnixStream
+        where
+            F: Fn() -> io::Result<UnixStream>,
+        {
+            for _ in 0..500 {
+                if let Ok(stream) = connect() {
+                    return stream;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            connect().unwrap()
+        }
+
+        fn unique_name(prefix: &str) -> String {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            format!(
+                "{}-{}-{}",
+                prefix,
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            )
+        }
+
+        #[test]
+        fn must_serve_unix_socket_path() {
+            let path = std::env::temp_dir().join(unique_name("thrift-bind") + ".sock");
+            serve(path.as_path().into());
+
+            call(connect(|| UnixStream::connect(&path)));
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        #[test]
+        fn must_serve_abstract_socket() {
+            use socket2::{Domain, SockAddr, Socket, Type};
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+
+            let name = unique_name("thrift-bind");
+            serve(TBindTarget::Abstract(name.clone().into_bytes()));
+
+            let path = [&[0u8][..], name.as_bytes()].concat();
+            let address = SockAddr::unix(OsStr::from_bytes(&path)).unwrap();
+            call(connect(|| {
+                let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+                socket.connect(&address)?;
+                Ok(socket.into())
+            }));
+        }
+    }
+}
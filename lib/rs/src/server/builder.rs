@@ -0,0 +1,613 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#[cfg(feature = "tls-native")]
+use native_tls::TlsAcceptor;
+#[cfg(feature = "rustls")]
+use rustls::ServerConfig;
+
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::protocol::{
+    TDynamicInputProtocol, TDynamicOutputProtocol, TInputProtocol, TInputProtocolFactory,
+    TOutputProtocol, TOutputProtocolFactory, TProtocolDetector, TProtocolKind,
+};
+use crate::transport::{
+    TBufferedReadTransportFactory, TBufferedWriteTransportFactory, TReadTransport,
+    TReadTransportFactory, TTcpOptions, TTransportObserver, TWriteTransport,
+    TWriteTransportFactory,
+};
+use crate::TConfiguration;
+
+use super::panic::PanicHook;
+#[cfg(any(feature = "rustls", feature = "tls-native"))]
+use super::threaded::TlsSettings;
+use super::{
+    TAuthenticator, TConfigurationHandle, TDrainHandle, TErrorMapper, THandlerPanic, TProcessor,
+    TRateLimiter, TRequestTracker, TServer, TServerEventHandler, TServerMetrics, TWriteWatermarks,
+};
+
+/// `TServer` created by a `TServerBuilder`, with its transport and protocol
+/// factories boxed.
+pub type TBoxedServer<PRC> = TServer<
+    PRC,
+    Box<dyn TReadTransportFactory + Send>,
+    Box<dyn TInputProtocolFactory + Send>,
+    Box<dyn TWriteTransportFactory + Send>,
+    Box<dyn TOutputProtocolFactory + Send>,
+>;
+
+/// Builder for a `TServer`.
+///
+/// Everything but the processor has a default: buffered transports, the
+/// strict binary protocol with the default `TConfiguration`, and one worker
+/// thread per available CPU. Settings that are not called out below behave
+/// as the `TServer` setter of the same name.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use thrift::protocol::{TInputProtocol, TOutputProtocol, TProtocolKind};
+/// use thrift::server::{TProcessor, TServerBuilder};
+/// use thrift::transport::{TFramedReadTransportFactory, TFramedWriteTransportFactory};
+/// use thrift::TConfiguration;
+///
+/// struct SimpleServiceSyncProcessor;
+/// impl TProcessor for SimpleServiceSyncProcessor {
+///     fn process(&self, i: &mut dyn TInputProtocol, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+///         unimplemented!();
+///     }
+/// }
+///
+/// let config = TConfiguration::builder()
+///     .max_message_size(Some(1024 * 1024))
+///     .max_frame_size(Some(1024 * 1024))
+///     .build()
+///     .unwrap();
+///
+/// let mut server = TServerBuilder::new(SimpleServiceSyncProcessor)
+///     .transports(
+///         TFramedReadTransportFactory::with_config(config.clone()),
+///         TFramedWriteTransportFactory::with_config(config.clone()),
+///     )
+///     .protocol(TProtocolKind::Compact)
+///     .configuration(config)
+///     .workers(16)
+///     .idle_timeout(Some(Duration::from_secs(60)))
+///     .build();
+///
+/// match server.listen("127.0.0.1:9090") {
+///   Ok(_)  => println!("listen completed"),
+///   Err(e) => println!("listen failed with error {:?}", e),
+/// }
+/// ```
+pub struct TServerBuilder<PRC>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    processor: PRC,
+    r_trans_factory: Box<dyn TReadTransportFactory + Send>,
+    w_trans_factory: Box<dyn TWriteTransportFactory + Send>,
+    protocols: Protocols,
+    config: TConfiguration,
+    num_workers: usize,
+    oneway_workers: usize,
+    concurrent_requests: Option<(usize, usize)>,
+    write_watermarks: Option<TWriteWatermarks>,
+    tcp_options: TTcpOptions,
+    idle_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    transport_observer: Option<Arc<dyn TTransportObserver>>,
+    event_handler: Option<Arc<dyn TServerEventHandler>>,
+    metrics: Option<Arc<dyn TServerMetrics>>,
+    panic_hook: Option<PanicHook>,
+    rate_limiter: Option<Arc<TRateLimiter>>,
+    authenticator: Option<Arc<dyn TAuthenticator>>,
+    error_mapper: Option<Arc<dyn TErrorMapper>>,
+    request_tracker: Option<Arc<TRequestTracker>>,
+    configuration_handle: Option<TConfigurationHandle>,
+    drain_handle: Option<TDrainHandle>,
+    protocol_detector: Option<Arc<TProtocolDetector>>,
+    #[cfg(any(feature = "rustls", feature = "tls-native"))]
+    tls: Option<TlsSettings>,
+}
+
+impl<PRC> fmt::Debug for TServerBuilder<PRC>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TServerBuilder")
+            .field("protocols", &self.protocols)
+            .field("config", &self.config)
+            .field("num_workers", &self.num_workers)
+            .field("oneway_workers", &self.oneway_workers)
+            .field("tcp_options", &self.tcp_options)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("request_timeout", &self.request_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Protocols a `TServerBuilder` creates for each connection.
+enum Protocols {
+    BuiltIn(TProtocolKind),
+    Custom(
+        Box<dyn TInputProtocolFactory + Send>,
+        Box<dyn TOutputProtocolFactory + Send>,
+    ),
+}
+
+impl fmt::Debug for Protocols {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocols::BuiltIn(kind) => f.debug_tuple("BuiltIn").field(kind).finish(),
+            Protocols::Custom(..) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl<PRC> TServerBuilder<PRC>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    /// Start building a server that hands requests to `processor`.
+    pub fn new(processor: PRC) -> TServerBuilder<PRC> {
+        TServerBuilder {
+            processor,
+            r_trans_factory: Box::new(TBufferedReadTransportFactory::new()),
+            w_trans_factory: Box::new(TBufferedWriteTransportFactory::new()),
+            protocols: Protocols::BuiltIn(TProtocolKind::Binary),
+            config: TConfiguration::default(),
+            num_workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            oneway_workers: 0,
+            concurrent_requests: None,
+            write_watermarks: None,
+            tcp_options: TTcpOptions::default(),
+            idle_timeout: None,
+            request_timeout: None,
+            transport_observer: None,
+            event_handler: None,
+            metrics: None,
+            panic_hook: None,
+            rate_limiter: None,
+            authenticator: None,
+            error_mapper: None,
+            request_tracker: None,
+            configuration_handle: None,
+            drain_handle: None,
+            protocol_detector: None,
+            #[cfg(any(feature = "rustls", feature = "tls-native"))]
+            tls: None,
+        }
+    }
+
+    /// Create the read and write half of each connection's transport with
+    /// `read_factory` and `write_factory`.
+    pub fn transports<RTF, WTF>(mut self, read_factory: RTF, write_factory: WTF) -> Self
+    where
+        RTF: TReadTransportFactory + Send + 'static,
+        WTF: TWriteTransportFactory + Send + 'static,
+    {
+        self.r_trans_factory = Box::new(read_factory);
+        self.w_trans_factory = Box::new(write_factory);
+        self
+    }
+
+    /// Speak the built-in protocol `kind`, with the limits set by
+    /// `configuration`.
+    pub fn protocol(mut self, kind: TProtocolKind) -> Self {
+        self.protocols = Protocols::BuiltIn(kind);
+        self
+    }
+
+    /// Create each connection's protocols with `input_factory` and
+    /// `output_factory` instead of a built-in protocol.
+    ///
+    /// The factories are used as they are, so `configuration` does not apply
+    /// to the protocols they create.
+    pub fn protocols<IPF, OPF>(mut self, input_factory: IPF, output_factory: OPF) -> Self
+    where
+        IPF: TInputProtocolFactory + Send + 'static,
+        OPF: TOutputProtocolFactory + Send + 'static,
+    {
+        self.protocols = Protocols::Custom(Box::new(input_factory), Box::new(output_factory));
+        self
+    }
+
+    /// Pick the protocol of each connection with `detector`, overriding
+    /// `protocol` and `protocols`. See `TServer::set_protocol_detector`.
+    pub fn detect_protocols(mut self, detector: Arc<TProtocolDetector>) -> Self {
+        self.protocol_detector = Some(detector);
+        self
+    }
+
+    /// Enforce the message, container, string and recursion limits in
+    /// `config` in the built-in protocols.
+    ///
+    /// Transports take their own configuration; pass the same `config` to
+    /// the framed transport factories, for example, to limit frame sizes.
+    pub fn configuration(mut self, config: TConfiguration) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Serve connections on `num_workers` threads.
+    pub fn workers(mut self, num_workers: usize) -> Self {
+        self.num_workers = num_workers;
+        self
+    }
+
+    /// See `TServer::set_oneway_workers`.
+    pub fn oneway_workers(mut self, num_workers: usize) -> Self {
+        self.oneway_workers = num_workers;
+        self
+    }
+
+    /// See `TServer::set_concurrent_requests`.
+    pub fn concurrent_requests(mut self, max_per_connection: usize, num_workers: usize) -> Self {
+        self.concurrent_requests = Some((max_per_connection, num_workers));
+        self
+    }
+
+    /// See `TServer::set_write_watermarks`.
+    pub fn write_watermarks(mut self, watermarks: TWriteWatermarks) -> Self {
+        self.write_watermarks = Some(watermarks);
+        self
+    }
+
+    /// See `TServer::set_tcp_options`.
+    pub fn tcp_options(mut self, options: TTcpOptions) -> Self {
+        self.tcp_options = options;
+        self
+    }
+
+    /// See `TServer::set_idle_timeout`.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// See `TServer::set_request_timeout`.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// See `TServer::set_transport_observer`.
+    pub fn transport_observer(mut self, observer: Arc<dyn TTransportObserver>) -> Self {
+        self.transport_observer = Some(observer);
+        self
+    }
+
+    /// See `TServer::set_event_handler`.
+    pub fn event_handler(mut self, handler: Arc<dyn TServerEventHandler>) -> Self {
+        self.event_handler = Some(handler);
+        self
+    }
+
+    /// See `TServer::set_metrics`.
+    pub fn metrics(mut self, metrics: Arc<dyn TServerMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// See `TServer::set_panic_hook`.
+    pub fn panic_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&THandlerPanic) + Send + Sync + 'static,
+    {
+        self.panic_hook = Some(PanicHook(Arc::new(hook)));
+        self
+    }
+
+    /// See `TServer::set_rate_limiter`.
+    pub fn rate_limiter(mut self, limiter: Arc<TRateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// See `TServer::set_configuration_handle`.
+    ///
+    /// The configuration held by `handle` takes precedence over the one
+    /// set with `configuration`.
+    pub fn configuration_handle(mut self, handle: TConfigurationHandle) -> Self {
+        self.configuration_handle = Some(handle);
+        self
+    }
+
+    /// See `TServer::set_drain_handle`.
+    pub fn drain_handle(mut self, handle: TDrainHandle) -> Self {
+        self.drain_handle = Some(handle);
+        self
+    }
+
+    /// See `TServer::set_request_tracker`.
+    pub fn request_tracker(mut self, tracker: Arc<TRequestTracker>) -> Self {
+        self.request_tracker = Some(tracker);
+        self
+    }
+
+    /// See `TServer::set_authenticator`.
+    pub fn authenticator(mut self, authenticator: Arc<dyn TAuthenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// See `TServer::set_error_mapper`.
+    pub fn error_mapper(mut self, mapper: Arc<dyn TErrorMapper>) -> Self {
+        self.error_mapper = Some(mapper);
+        self
+    }
+
+    /// Serve TLS with `config` from `TServer::listen`, as
+    /// `TServer::listen_tls` does.
+    #[cfg(feature = "rustls")]
+    pub fn tls(mut self, config: Arc<ServerConfig>) -> Self {
+        self.tls = Some(TlsSettings::Rustls(config));
+        self
+    }
+
+    /// Serve TLS with `acceptor` from `TServer::listen`, as
+    /// `TServer::listen_native_tls` does.
+    #[cfg(feature = "tls-native")]
+    pub fn native_tls(mut self, acceptor: Arc<TlsAcceptor>) -> Self {
+        self.tls = Some(TlsSettings::Native(acceptor));
+        self
+    }
+
+    /// Create the server.
+    pub fn build(self) -> TBoxedServer<PRC> {
+        let (i_proto_factory, o_proto_factory): (
+            Box<dyn TInputProtocolFactory + Send>,
+            Box<dyn TOutputProtocolFactory + Send>,
+        ) = match self.protocols {
+            Protocols::BuiltIn(kind) => {
+                let factory = BuiltInProtocolFactory {
+                    kind,
+                    config: self.config,
+                };
+                (Box::new(factory.clone()), Box::new(factory))
+            }
+            Protocols::Custom(i, o) => (i, o),
+        };
+
+        let mut server = TServer::new(
+            self.r_trans_factory,
+            i_proto_factory,
+            self.w_trans_factory,
+            o_proto_factory,
+            self.processor,
+            self.num_workers,
+        );
+        server.set_oneway_workers(self.oneway_workers);
+        if let Some((max_per_connection, num_workers)) = self.concurrent_requests {
+            server.set_concurrent_requests(max_per_connection, num_workers);
+        }
+        server.set_write_watermarks(self.write_watermarks);
+        server.set_tcp_options(self.tcp_options);
+        server.set_idle_timeout(self.idle_timeout);
+        server.set_request_timeout(self.request_timeout);
+        if let Some(observer) = self.transport_observer {
+            server.set_transport_observer(observer);
+        }
+        if let Some(handler) = self.event_handler {
+            server.set_event_handler(handler);
+        }
+        if let Some(metrics) = self.metrics {
+            server.set_metrics(metrics);
+        }
+        if let Some(PanicHook(hook)) = self.panic_hook {
+            server.set_panic_hook(move |details| hook(details));
+        }
+        if let Some(detector) = self.protocol_detector {
+            server.set_protocol_detector(detector);
+        }
+        if let Some(limiter) = self.rate_limiter {
+            server.set_rate_limiter(limiter);
+        }
+        if let Some(handle) = self.configuration_handle {
+            server.set_configuration_handle(handle);
+        }
+        if let Some(handle) = self.drain_handle {
+            server.set_drain_handle(handle);
+        }
+        if let Some(tracker) = self.request_tracker {
+            server.set_request_tracker(tracker);
+        }
+        if let Some(authenticator) = self.authenticator {
+            server.set_authenticator(authenticator);
+        }
+        if let Some(mapper) = self.error_mapper {
+            server.set_error_mapper(mapper);
+        }
+        #[cfg(any(feature = "rustls", feature = "tls-native"))]
+        if let Some(tls) = self.tls {
+            server.set_tls(tls);
+        }
+        server
+    }
+}
+
+/// Creates built-in protocols that enforce a `TConfiguration`.
+#[derive(Clone, Debug)]
+struct BuiltInProtocolFactory {
+    kind: TProtocolKind,
+    config: TConfiguration,
+}
+
+impl TInputProtocolFactory for BuiltInProtocolFactory {
+    fn create(&self, transport: Box<dyn TReadTransport + Send>) -> Box<dyn TInputProtocol + Send> {
+        Box::new(TDynamicInputProtocol::with_config(
+            self.kind,
+            transport,
+            self.config.clone(),
+        ))
+    }
+}
+
+impl TOutputProtocolFactory for BuiltInProtocolFactory {
+    fn create(
+        &self,
+        transport: Box<dyn TWriteTransport + Send>,
+    ) -> Box<dyn TOutputProtocol + Send> {
+        Box::new(TDynamicOutputProtocol::with_config(
+            self.kind,
+            transport,
+            self.config.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol,
+        TMessageIdentifier, TMessageType,
+    };
+
+    /// Answers each request with its string argument.
+    struct Echo;
+
+    impl TProcessor for Echo {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            let value = i.read_string()?;
+            i.read_message_end()?;
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_string(&value)?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    fn start() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = TConfiguration::builder()
+            .max_string_size(Some(8))
+            .build()
+            .unwrap();
+        let mut server = TServerBuilder::new(Echo)
+            .protocol(TProtocolKind::Compact)
+            .configuration(config)
+            .workers(1)
+            .build();
+        thread::spawn(move || server.listen_on(listener));
+        address
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let mut attempts = 0;
+        loop {
+            match TcpStream::connect(address) {
+                Ok(s) => return s,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("cannot connect to server: {:?}", e),
+            }
+        }
+    }
+
+    fn send(stream: &TcpStream, value: &str) {
+        send_with(
+            &mut TCompactOutputProtocol::new(stream.try_clone().unwrap()),
+            value,
+        );
+    }
+
+    fn send_with(o_prot: &mut dyn TOutputProtocol, value: &str) {
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("echo", TMessageType::Call, 1))
+            .unwrap();
+        o_prot.write_string(value).unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    #[test]
+    fn must_serve_configured_protocol() {
+        let address = start();
+        let stream = connect(address);
+        send(&stream, "hello");
+
+        let mut i_prot = TCompactInputProtocol::new(stream);
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Reply);
+        assert_eq!(i_prot.read_string().unwrap(), "hello");
+    }
+
+    #[test]
+    fn must_apply_configuration_limits() {
+        let address = start();
+        let mut stream = connect(address);
+        send(&stream, "longer than eight bytes");
+
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn must_serve_detected_protocols() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let detector = TProtocolDetector::new()
+            .with_protocol(TProtocolKind::Binary)
+            .with_protocol(TProtocolKind::Compact);
+        let mut server = TServerBuilder::new(Echo)
+            .detect_protocols(Arc::new(detector))
+            .workers(2)
+            .build();
+        thread::spawn(move || server.listen_on(listener));
+
+        let compact = connect(address);
+        send(&compact, "compact");
+        let mut i_prot = TCompactInputProtocol::new(compact);
+        i_prot.read_message_begin().unwrap();
+        assert_eq!(i_prot.read_string().unwrap(), "compact");
+
+        let binary = connect(address);
+        send_with(
+            &mut TBinaryOutputProtocol::new(binary.try_clone().unwrap(), true),
+            "binary",
+        );
+        let mut i_prot = TBinaryInputProtocol::new(binary, true);
+        i_prot.read_message_begin().unwrap();
+        assert_eq!(i_prot.read_string().unwrap(), "binary");
+    }
+}
@@ -0,0 +1,496 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use threadpool::ThreadPool;
+
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+use crate::protocol::{TInputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol};
+use crate::TransportErrorKind;
+
+use super::async_processor::{process_buffered, read_arguments, write_reply};
+use super::connection::ConnectionOptions;
+use super::drain::Closer;
+use super::panic::{report_handler_panic, PanicHook};
+use super::rate_limit::reject_throttled;
+use super::watermark::WriteBacklog;
+use super::{
+    debug, error, warn, with_request_context, TConnectionContext, TProcessor, TRequestContext,
+    TRequestTracker, TServerEventHandler, TServerMetrics,
+};
+
+/// Settings for processing several requests of one connection at a time.
+#[derive(Clone, Debug)]
+pub(super) struct Concurrency {
+    pub(super) pool: ThreadPool,
+    pub(super) max_per_connection: usize,
+}
+
+/// State shared by a connection's reader and the jobs processing its
+/// requests.
+struct Shared {
+    o_prot: Mutex<Box<dyn TOutputProtocol + Send>>,
+    context: Mutex<TConnectionContext>,
+    in_flight: InFlight,
+    backlog: WriteBacklog,
+    // set when a request failed in a way that closes the connection
+    failed: AtomicBool,
+    closer: Option<Closer>,
+}
+
+impl Shared {
+    /// Close the connection after a request failed, waking the reader if it
+    /// is waiting for the next request.
+    fn fail(&self) {
+        self.failed.store(true, Ordering::Release);
+        if let Some(ref closer) = self.closer {
+            closer.close();
+        }
+    }
+}
+
+/// Sequence numbers of the requests being processed on one connection.
+struct InFlight {
+    max: usize,
+    sequence_numbers: Mutex<HashSet<i32>>,
+    changed: Condvar,
+}
+
+impl InFlight {
+    /// Wait until the connection is below its limit and no request with
+    /// `sequence_number` is being processed, then count one more.
+    fn acquire(&self, sequence_number: i32) {
+        let mut in_flight = self.lock();
+        while in_flight.len() >= self.max || in_flight.contains(&sequence_number) {
+            in_flight = self
+                .changed
+                .wait(in_flight)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        in_flight.insert(sequence_number);
+    }
+
+    fn release(&self, sequence_number: i32) {
+        self.lock().remove(&sequence_number);
+        self.changed.notify_all();
+    }
+
+    /// Wait until every request has been processed.
+    fn wait_idle(&self) {
+        let mut in_flight = self.lock();
+        while !in_flight.is_empty() {
+            in_flight = self
+                .changed
+                .wait(in_flight)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<i32>> {
+        self.sequence_numbers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Releases a request's slot however its job ends.
+struct Slot<'a> {
+    in_flight: &'a InFlight,
+    sequence_number: i32,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.in_flight.release(self.sequence_number);
+    }
+}
+
+/// Serve requests on one connection, processing up to the configured number
+/// of them at a time, until the client disconnects or an error occurs.
+///
+/// The connection's thread reads each request into memory and hands it to
+/// the concurrency pool. Replies are written whole, under a lock, in the
+/// order requests complete; clients match them to their calls by sequence
+/// number. A request that reuses the sequence number of one still being
+/// processed waits for it, so clients that do not number their calls get
+/// their replies in order. Reading stops while the replies waiting to be
+/// written are above the connection's write watermarks. Returns once every request read has been
+/// processed.
+pub(super) fn serve_concurrently<PRC>(
+    processor: &Arc<PRC>,
+    options: &ConnectionOptions,
+    concurrency: &Concurrency,
+    i_prot: &mut dyn TInputProtocol,
+    o_prot: Box<dyn TOutputProtocol + Send>,
+    request_context: TRequestContext,
+    context: TConnectionContext,
+) -> TConnectionContext
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    let peer_addr = context.peer_addr();
+    let shared = Arc::new(Shared {
+        o_prot: Mutex::new(o_prot),
+        context: Mutex::new(context),
+        in_flight: InFlight {
+            max: concurrency.max_per_connection,
+            sequence_numbers: Mutex::new(HashSet::new()),
+            changed: Condvar::new(),
+        },
+        backlog: WriteBacklog::new(options.write_watermarks),
+        failed: AtomicBool::new(false),
+        closer: options.closer.clone(),
+    });
+
+    let mut applied_configuration = 0;
+    while !shared.failed.load(Ordering::Acquire) {
+        // stop reading while the client is slow to take its replies
+        shared.backlog.wait_for_room();
+        if let Some(ref configuration) = options.configuration {
+            configuration.refresh(&mut applied_configuration, i_prot);
+        }
+        let ident = match options.read_message_begin(i_prot) {
+            Ok(ident) => ident,
+            Err(crate::Error::Transport(ref e)) if e.kind == TransportErrorKind::EndOfFile => break,
+            Err(e) => {
+                error!("failed to read request with error: {:?}", e);
+                break;
+            }
+        };
+
+        if let Some(ref limiter) = options.rate_limiter {
+            if !limiter.allow_request(&request_context) {
+                debug!("request {} exceeded the client's rate limit", ident.name);
+                let mut o_prot = lock(&shared.o_prot);
+                let message = "request rate limit exceeded";
+                match reject_throttled(&ident, i_prot, &mut **o_prot, message) {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        warn!("failed to send rate limit exception with error {:?}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let request = match read_arguments(&ident, i_prot) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("failed to read request {} with error: {:?}", ident.name, e);
+                break;
+            }
+        };
+        shared.in_flight.acquire(ident.sequence_number);
+        {
+            let mut context = lock(&shared.context);
+            context.requests += 1;
+            if let Some(ref handler) = options.event_handler {
+                handler.pre_process(&mut context, &ident);
+            }
+        }
+
+        let job = Job {
+            processor: processor.clone(),
+            shared: shared.clone(),
+            ident,
+            request,
+            request_context: request_context.clone(),
+            options: JobOptions {
+                event_handler: options.event_handler.clone(),
+                metrics: options.metrics.clone(),
+                panic_hook: options.panic_hook.clone(),
+                request_tracker: options.request_tracker.clone(),
+            },
+        };
+        concurrency.pool.execute(move || job.run());
+    }
+
+    shared.in_flight.wait_idle();
+    let mut context = lock(&shared.context);
+    std::mem::replace(&mut *context, TConnectionContext::new(peer_addr))
+}
+
+/// Hooks a job reports its request to.
+struct JobOptions {
+    event_handler: Option<Arc<dyn TServerEventHandler>>,
+    metrics: Option<Arc<dyn TServerMetrics>>,
+    panic_hook: Option<PanicHook>,
+    request_tracker: Option<Arc<TRequestTracker>>,
+}
+
+/// One request read from a connection, waiting to be processed.
+struct Job<PRC> {
+    processor: Arc<PRC>,
+    shared: Arc<Shared>,
+    ident: TMessageIdentifier,
+    request: Vec<u8>,
+    request_context: TRequestContext,
+    options: JobOptions,
+}
+
+impl<PRC> Job<PRC>
+where
+    PRC: TProcessor,
+{
+    fn run(self) {
+        let Job {
+            processor,
+            shared,
+            ident,
+            request,
+            request_context,
+            options,
+        } = self;
+        let _slot = Slot {
+            in_flight: &shared.in_flight,
+            sequence_number: ident.sequence_number,
+        };
+        let peer_addr = request_context.peer_addr();
+        let _tracked = options
+            .request_tracker
+            .as_ref()
+            .map(|tracker| tracker.start(&ident, peer_addr));
+
+        let started = Instant::now();
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            with_request_context(request_context, || process_buffered(&*processor, &request))
+        }));
+        let (reply, result) = match served {
+            Ok(served) => served,
+            Err(payload) => {
+                // only this request is answered, then the connection is
+                // closed
+                let mut o_prot = lock(&shared.o_prot);
+                report_handler_panic(
+                    payload,
+                    Some(&ident),
+                    peer_addr,
+                    options.panic_hook.as_ref(),
+                    Some(&mut **o_prot),
+                );
+                shared.fail();
+                return;
+            }
+        };
+
+        let oneway = ident.message_type == TMessageType::OneWay;
+        let result = if oneway {
+            match result {
+                Err(crate::Error::Application(e)) => {
+                    warn!("oneway request {} failed with error {:?}", ident.name, e);
+                    Ok(())
+                }
+                other => other,
+            }
+        } else {
+            let _held = shared.backlog.hold(reply.len());
+            let written = write_reply(&reply, &mut **lock(&shared.o_prot));
+            result.and(written)
+        };
+
+        if let Some(ref metrics) = options.metrics {
+            metrics.request_completed(&ident.name, started.elapsed(), &result);
+        }
+        if let Some(ref handler) = options.event_handler {
+            handler.post_process(&mut lock(&shared.context), &ident, &result);
+        }
+        if let Err(e) = result {
+            error!("processor completed with error: {:?}", e);
+            shared.fail();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol, TStructIdentifier, TType};
+    use crate::server::TServerBuilder;
+    use crate::transport::{
+        TFramedReadTransport, TFramedReadTransportFactory, TFramedWriteTransport,
+        TFramedWriteTransportFactory,
+    };
+
+    /// Answers `fast` at once, and `slow` once the test lets it. Panics on
+    /// `panic`.
+    struct Gate(Mutex<Receiver<()>>);
+
+    impl TProcessor for Gate {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.skip(TType::Struct)?;
+            i.read_message_end()?;
+            match &*ident.name {
+                "slow" => lock(&self.0).recv().unwrap(),
+                "panic" => panic!("handler failed"),
+                _ => {}
+            }
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_struct_begin(&TStructIdentifier::new("result"))?;
+            o.write_field_stop()?;
+            o.write_struct_end()?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    fn start(max_per_connection: usize) -> (SocketAddr, Sender<()>) {
+        let (release, gate) = channel();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = TServerBuilder::new(Gate(Mutex::new(gate)))
+            .workers(1)
+            .concurrent_requests(max_per_connection, 4)
+            .transports(
+                TFramedReadTransportFactory::new(),
+                TFramedWriteTransportFactory::new(),
+            )
+            .build();
+        thread::spawn(move || server.listen_on(listener));
+        (address, release)
+    }
+
+    struct Client {
+        i_prot: TBinaryInputProtocol<TFramedReadTransport<TcpStream>>,
+        o_prot: TBinaryOutputProtocol<TFramedWriteTransport<TcpStream>>,
+    }
+
+    impl Client {
+        fn connect(address: SocketAddr) -> Client {
+            let stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            Client {
+                i_prot: TBinaryInputProtocol::new(
+                    TFramedReadTransport::new(stream.try_clone().unwrap()),
+                    true,
+                ),
+                o_prot: TBinaryOutputProtocol::new(TFramedWriteTransport::new(stream), true),
+            }
+        }
+
+        fn send(&mut self, name: &str, sequence_number: i32) {
+            let ident = TMessageIdentifier::new(name, TMessageType::Call, sequence_number);
+            self.o_prot.write_message_begin(&ident).unwrap();
+            self.o_prot
+                .write_struct_begin(&TStructIdentifier::new("args"))
+                .unwrap();
+            self.o_prot.write_field_stop().unwrap();
+            self.o_prot.write_struct_end().unwrap();
+            self.o_prot.write_message_end().unwrap();
+            self.o_prot.flush().unwrap();
+        }
+
+        fn receive(&mut self) -> crate::Result<(String, i32)> {
+            let ident = self.i_prot.read_message_begin()?;
+            assert_eq!(ident.message_type, TMessageType::Reply);
+            self.i_prot.skip(TType::Struct)?;
+            self.i_prot.read_message_end()?;
+            Ok((ident.name, ident.sequence_number))
+        }
+    }
+
+    #[test]
+    fn must_not_hold_up_requests_behind_slow_one() {
+        let (address, release) = start(4);
+        let mut client = Client::connect(address);
+
+        client.send("slow", 1);
+        client.send("fast", 2);
+        client.send("fast", 3);
+        // the fast requests run on different workers, in either order
+        let mut fast = [client.receive().unwrap(), client.receive().unwrap()];
+        fast.sort();
+        assert_eq!(fast, [("fast".to_owned(), 2), ("fast".to_owned(), 3)]);
+
+        release.send(()).unwrap();
+        assert_eq!(client.receive().unwrap(), ("slow".to_owned(), 1));
+    }
+
+    #[test]
+    fn must_process_reused_sequence_numbers_in_order() {
+        let (address, release) = start(4);
+        let mut client = Client::connect(address);
+
+        client.send("slow", 1);
+        client.send("fast", 1);
+        // the fast call would overtake the slow one if it did not wait
+        thread::sleep(Duration::from_millis(100));
+        release.send(()).unwrap();
+        assert_eq!(client.receive().unwrap(), ("slow".to_owned(), 1));
+        assert_eq!(client.receive().unwrap(), ("fast".to_owned(), 1));
+    }
+
+    #[test]
+    fn must_close_connection_once_request_panics() {
+        let (address, _release) = start(4);
+        let mut client = Client::connect(address);
+
+        client.send("panic", 1);
+        let ident = client.i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Exception);
+        client.i_prot.skip(TType::Struct).unwrap();
+        client.i_prot.read_message_end().unwrap();
+        // closed without waiting for the client's next request, which would
+        // otherwise end in the client's read timeout
+        match client.i_prot.read_message_begin() {
+            Err(crate::Error::Transport(e)) => assert_eq!(e.kind, TransportErrorKind::EndOfFile),
+            other => panic!("expected end of file, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_limit_requests_per_connection() {
+        let (address, release) = start(2);
+        let mut client = Client::connect(address);
+
+        client.send("slow", 1);
+        client.send("slow", 2);
+        client.send("fast", 3);
+        // both slots are taken by the slow calls
+        thread::sleep(Duration::from_millis(100));
+        release.send(()).unwrap();
+        let first = client.receive().unwrap();
+        assert_eq!(first.0, "slow");
+        release.send(()).unwrap();
+        let mut rest = [client.receive().unwrap(), client.receive().unwrap()];
+        rest.sort();
+        assert_eq!(rest[0].0, "fast");
+        assert_eq!(rest[1].0, "slow");
+    }
+}
@@ -0,0 +1,294 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use threadpool::ThreadPool;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    TInputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol, TStoredInputProtocol,
+};
+use crate::{new_transport_error, TransportErrorKind};
+
+use super::auth::TAuthenticator;
+use super::concurrent::{serve_concurrently, Concurrency};
+use super::context::with_current;
+use super::drain::{Closer, DrainedConnection};
+use super::error_map::{ErrorMappingOutputProtocol, TErrorMapper};
+use super::message::MessageInputProtocol;
+use super::oneway::{process_oneway, queue_oneway};
+use super::panic::{report_handler_panic, PanicHook};
+use super::rate_limit::{reject_throttled, TRateLimiter};
+use super::reload::TConfigurationHandle;
+use super::timeout::RequestTimeout;
+use super::tracker::TRequestTracker;
+use super::watermark::TWriteWatermarks;
+use super::{
+    debug, error, warn, with_request_context, TConnectionContext, TProcessor, TRequestContext,
+    TServerEventHandler, TServerMetrics,
+};
+
+/// Settings that apply to each request on one connection.
+#[derive(Default)]
+pub(super) struct ConnectionOptions {
+    pub(super) idle_timeout: Option<Duration>,
+    pub(super) request_timeout: Option<RequestTimeout>,
+    pub(super) event_handler: Option<Arc<dyn TServerEventHandler>>,
+    pub(super) oneway_pool: Option<ThreadPool>,
+    pub(super) metrics: Option<Arc<dyn TServerMetrics>>,
+    pub(super) panic_hook: Option<PanicHook>,
+    pub(super) rate_limiter: Option<Arc<TRateLimiter>>,
+    pub(super) authenticator: Option<Arc<dyn TAuthenticator>>,
+    pub(super) error_mapper: Option<Arc<dyn TErrorMapper>>,
+    pub(super) request_tracker: Option<Arc<TRequestTracker>>,
+    pub(super) configuration: Option<TConfigurationHandle>,
+    pub(super) concurrency: Option<Concurrency>,
+    pub(super) write_watermarks: Option<TWriteWatermarks>,
+    pub(super) drain: Option<DrainedConnection>,
+    // ends the read of the next request once a concurrent request fails
+    pub(super) closer: Option<Closer>,
+}
+
+impl ConnectionOptions {
+    /// Return `true` if the server has to read the message header of each
+    /// request itself, rather than leaving it to the processor.
+    fn reads_message_begin(&self) -> bool {
+        self.idle_timeout.is_some()
+            || self.request_timeout.is_some()
+            || self.event_handler.is_some()
+            || self.oneway_pool.is_some()
+            || self.metrics.is_some()
+            || self.request_tracker.is_some()
+            || matches!(self.rate_limiter, Some(ref l) if l.limits_requests())
+    }
+
+    /// Read the next request and process it.
+    ///
+    /// Oneway requests are processed without a reply, on the oneway pool if
+    /// there is one.
+    fn process<PRC>(
+        &self,
+        processor: &Arc<PRC>,
+        i_prot: &mut dyn TInputProtocol,
+        o_prot: &mut dyn TOutputProtocol,
+        context: &mut TConnectionContext,
+    ) -> crate::Result<()>
+    where
+        PRC: TProcessor + Send + Sync + 'static,
+    {
+        let ident = self.read_message_begin(i_prot)?;
+
+        if let Some(ref limiter) = self.rate_limiter {
+            let allowed = with_current(|c| match c {
+                Some(c) => limiter.allow_request(c),
+                None => true,
+            });
+            if !allowed {
+                debug!("request {} exceeded the client's rate limit", ident.name);
+                return reject_throttled(&ident, i_prot, o_prot, "request rate limit exceeded");
+            }
+        }
+
+        let _tracked = self
+            .request_tracker
+            .as_ref()
+            .map(|tracker| tracker.start(&ident, context.peer_addr()));
+
+        context.requests += 1;
+        let event_ident = self.event_handler.as_ref().map(|handler| {
+            handler.pre_process(context, &ident);
+            ident.clone()
+        });
+
+        let started = Instant::now();
+        let method = self.metrics.as_ref().map(|_| ident.name.clone());
+        let result = if ident.message_type == TMessageType::OneWay {
+            match self.oneway_pool {
+                Some(ref pool) => {
+                    queue_oneway(pool, processor, ident, i_prot, self.panic_hook.clone())
+                }
+                None => process_oneway(&**processor, ident, i_prot),
+            }
+        } else {
+            match self.request_timeout {
+                Some(ref timeout) => timeout.process(&**processor, ident, i_prot, o_prot),
+                None => processor.process(&mut TStoredInputProtocol::new(i_prot, ident), o_prot),
+            }
+        };
+
+        if let (Some(metrics), Some(method)) = (self.metrics.as_ref(), method) {
+            metrics.request_completed(&method, started.elapsed(), &result);
+        }
+        if let (Some(handler), Some(ident)) = (self.event_handler.as_ref(), event_ident) {
+            handler.post_process(context, &ident, &result);
+        }
+        result
+    }
+
+    /// Wait for the next request and read its message header.
+    ///
+    /// A connection that has been idle for longer than the idle timeout is
+    /// reported as closed by the client.
+    pub(super) fn read_message_begin(
+        &self,
+        i_prot: &mut dyn TInputProtocol,
+    ) -> crate::Result<TMessageIdentifier> {
+        let waiting_since = Instant::now();
+        match i_prot.read_message_begin() {
+            Err(crate::Error::Transport(_)) if self.idle_expired(waiting_since) => {
+                Err(new_transport_error(
+                    TransportErrorKind::EndOfFile,
+                    "connection closed after idle timeout",
+                ))
+            }
+            other => other,
+        }
+    }
+
+    fn idle_expired(&self, waiting_since: Instant) -> bool {
+        matches!(self.idle_timeout, Some(idle) if waiting_since.elapsed() >= idle)
+    }
+}
+
+/// Serve requests on one connection until the client disconnects, an error
+/// occurs or a request handler panics.
+pub(super) fn handle_incoming_connection<PRC>(
+    processor: Arc<PRC>,
+    i_prot: Box<dyn TInputProtocol + Send>,
+    o_prot: Box<dyn TOutputProtocol + Send>,
+    mut options: ConnectionOptions,
+    mut request_context: TRequestContext,
+) where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    let peer_addr = request_context.peer_addr();
+    let mut i_prot = MessageInputProtocol::new(i_prot, peer_addr).with_drain(options.drain.take());
+    let mut o_prot = match options.error_mapper {
+        Some(ref mapper) => Box::new(ErrorMappingOutputProtocol::new(o_prot, mapper.clone())),
+        None => o_prot,
+    };
+    if let Some(ref limiter) = options.rate_limiter {
+        if !limiter.allow_connection(&request_context) {
+            debug!("connection from {:?} exceeded its rate limit", peer_addr);
+            if let Ok(ident) = i_prot.read_message_begin() {
+                let message = "connection rate limit exceeded";
+                if let Err(e) = reject_throttled(&ident, &mut i_prot, &mut *o_prot, message) {
+                    warn!("failed to send rate limit exception with error {:?}", e);
+                }
+            }
+            return;
+        }
+    }
+    if let Some(ref authenticator) = options.authenticator {
+        let authenticated = panic::catch_unwind(AssertUnwindSafe(|| {
+            authenticator.authenticate(&request_context, &mut i_prot, &mut *o_prot)
+        }));
+        match authenticated {
+            Ok(Ok(principal)) => {
+                debug!(
+                    "connection from {:?} authenticated as {}",
+                    peer_addr, principal
+                );
+                request_context = request_context.with_principal(Some(principal));
+            }
+            Ok(Err(e)) => {
+                warn!("rejected connection from {:?}: {:?}", peer_addr, e);
+                return;
+            }
+            Err(_) => {
+                error!("authenticator panicked on connection from {:?}", peer_addr);
+                return;
+            }
+        }
+    }
+    let mut context = TConnectionContext::new(peer_addr);
+    if let Some(ref handler) = options.event_handler {
+        handler.context_created(&mut context);
+    }
+    let opened = Instant::now();
+    if let Some(ref metrics) = options.metrics {
+        metrics.connection_opened();
+    }
+
+    if let Some(ref concurrency) = options.concurrency {
+        let context = serve_concurrently(
+            &processor,
+            &options,
+            concurrency,
+            &mut i_prot,
+            o_prot,
+            request_context,
+            context,
+        );
+        connection_closed(&options, opened, context);
+        return;
+    }
+
+    let reads_message_begin = options.reads_message_begin();
+    let mut applied_configuration = 0;
+    with_request_context(request_context, || loop {
+        if let Some(ref configuration) = options.configuration {
+            configuration.refresh(&mut applied_configuration, &mut i_prot);
+        }
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            if reads_message_begin {
+                options.process(&processor, &mut i_prot, &mut *o_prot, &mut context)
+            } else {
+                processor.process(&mut i_prot, &mut *o_prot)
+            }
+        }));
+        let result = match served {
+            Ok(result) => result,
+            Err(payload) => {
+                // the handler may have left its state half-updated, so only
+                // the client's request is answered and the connection closed
+                report_handler_panic(
+                    payload,
+                    i_prot.message(),
+                    peer_addr,
+                    options.panic_hook.as_ref(),
+                    Some(&mut *o_prot),
+                );
+                break;
+            }
+        };
+        match result {
+            Ok(()) => {}
+            Err(err) => {
+                match err {
+                    crate::Error::Transport(ref transport_err)
+                        if transport_err.kind == TransportErrorKind::EndOfFile => {}
+                    other => error!("processor completed with error: {:?}", other),
+                }
+                break;
+            }
+        }
+    });
+
+    connection_closed(&options, opened, context);
+}
+
+/// Report the end of a connection opened at `opened`.
+fn connection_closed(options: &ConnectionOptions, opened: Instant, context: TConnectionContext) {
+    if let Some(ref metrics) = options.metrics {
+        metrics.connection_closed(opened.elapsed());
+    }
+    if let Some(ref handler) = options.event_handler {
+        handler.connection_closed(context);
+    }
+}
@@ -0,0 +1,226 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::transport::TPeerIdentity;
+
+thread_local! {
+    static REQUEST_CONTEXT: RefCell<Option<TRequestContext>> = const { RefCell::new(None) };
+}
+
+/// Information about the request being handled on the current thread.
+///
+/// `TServer` populates a `TRequestContext` for every connection it serves
+/// and makes it available to processors, handlers and middleware through
+/// `thrift::server::request_context()`, so handlers can use the caller's
+/// address, TLS identity or deadline without a change to the generated
+/// service signatures.
+///
+/// The server fills in the peer address, the ALPN protocol and client
+/// identity negotiated by TLS listeners, the principal found by the
+/// server's `TAuthenticator`, and the deadline set by
+/// `TServer::set_request_timeout`. Headers are left for middleware to
+/// fill in: a processor that wraps another can decode headers from the
+/// request and run the inner processor with an extended context through
+/// `thrift::server::with_request_context`.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::server::{self, TRequestContext};
+///
+/// fn handle_call() -> thrift::Result<String> {
+///     let context = server::request_context().unwrap_or_default();
+///     let caller = context.header("caller").unwrap_or("anonymous");
+///     Ok(format!("hello, {} at {:?}", caller, context.peer_addr()))
+/// }
+///
+/// let context = TRequestContext::new().with_header("caller", "tests");
+/// let reply = server::with_request_context(context, handle_call).unwrap();
+/// assert_eq!(reply, "hello, tests at None");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TRequestContext {
+    peer_addr: Option<SocketAddr>,
+    protocol: Option<Vec<u8>>,
+    peer_identity: Option<TPeerIdentity>,
+    principal: Option<String>,
+    headers: BTreeMap<String, String>,
+    deadline: Option<Instant>,
+}
+
+impl TRequestContext {
+    /// Create an empty `TRequestContext`.
+    pub fn new() -> TRequestContext {
+        TRequestContext::default()
+    }
+
+    /// Set the address of the client.
+    pub fn with_peer_addr(mut self, peer_addr: Option<SocketAddr>) -> TRequestContext {
+        self.peer_addr = peer_addr;
+        self
+    }
+
+    /// Set the application protocol negotiated with the client.
+    pub fn with_protocol(mut self, protocol: Option<Vec<u8>>) -> TRequestContext {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Set the verified identity of the client.
+    pub fn with_peer_identity(mut self, peer_identity: Option<TPeerIdentity>) -> TRequestContext {
+        self.peer_identity = peer_identity;
+        self
+    }
+
+    /// Set the principal the client was authenticated as.
+    pub fn with_principal(mut self, principal: Option<String>) -> TRequestContext {
+        self.principal = principal;
+        self
+    }
+
+    /// Add a header, replacing any header with the same name.
+    pub fn with_header<K, V>(mut self, name: K, value: V) -> TRequestContext
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.insert_header(name, value);
+        self
+    }
+
+    /// Set the deadline of the request.
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> TRequestContext {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Address of the client, if the connection has one.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Application protocol negotiated with the client, such as the ALPN
+    /// protocol agreed during a TLS handshake.
+    pub fn protocol(&self) -> Option<&[u8]> {
+        self.protocol.as_deref()
+    }
+
+    /// Verified identity of the client, if it presented a certificate.
+    pub fn peer_identity(&self) -> Option<&TPeerIdentity> {
+        self.peer_identity.as_ref()
+    }
+
+    /// Principal the client was authenticated as by the server's
+    /// `TAuthenticator`, if it has one.
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    /// Return the value of the header `name`, if it is set.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// All headers of the request, ordered by name.
+    pub fn headers(&self) -> &BTreeMap<String, String> {
+        &self.headers
+    }
+
+    /// Add a header, returning the previous value of a header with the same
+    /// name.
+    pub fn insert_header<K, V>(&mut self, name: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.insert(name.into(), value.into())
+    }
+
+    /// Deadline by which the request should be answered, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+/// Return a copy of the context of the request being handled on the current
+/// thread, or `None` outside of a request handler.
+pub fn request_context() -> Option<TRequestContext> {
+    REQUEST_CONTEXT.with(|c| c.borrow().clone())
+}
+
+/// Run `f` with `context` as the context returned by `request_context()` on
+/// this thread. The previous context is restored when `f` returns.
+pub fn with_request_context<F, R>(context: TRequestContext, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Restore(Option<TRequestContext>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            REQUEST_CONTEXT.with(|c| *c.borrow_mut() = previous);
+        }
+    }
+
+    let previous = REQUEST_CONTEXT.with(|c| c.borrow_mut().replace(context));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Call `f` with the current request context without copying it.
+pub(super) fn with_current<F, R>(f: F) -> R
+where
+    F: FnOnce(Option<&TRequestContext>) -> R,
+{
+    REQUEST_CONTEXT.with(|c| f(c.borrow().as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_restore_outer_context() {
+        assert_eq!(request_context(), None);
+
+        let outer = TRequestContext::new().with_header("a", "1");
+        with_request_context(outer.clone(), || {
+            let inner = request_context().unwrap().with_header("b", "2");
+            with_request_context(inner, || {
+                let context = request_context().unwrap();
+                assert_eq!(context.header("a"), Some("1"));
+                assert_eq!(context.header("b"), Some("2"));
+            });
+            assert_eq!(request_context(), Some(outer));
+        });
+
+        assert_eq!(request_context(), None);
+    }
+
+    #[test]
+    fn must_restore_context_after_panic() {
+        let result = std::panic::catch_unwind(|| {
+            with_request_context(TRequestContext::new(), || panic!("handler failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(request_context(), None);
+    }
+}
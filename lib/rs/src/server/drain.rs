@@ -0,0 +1,443 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use socket2::{SockAddr, SockRef, Socket, Type};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::{debug, warn};
+
+/// Switch that takes a running server out of service without dropping the
+/// requests it is processing.
+///
+/// Register a handle with `TServer::set_drain_handle` and keep a clone of
+/// it. Once `drain` is called:
+///
+/// * the server stops accepting connections, and `listen` returns `Ok`
+/// * every connection finishes the request it is processing, sends the
+///   reply and is then closed
+/// * connections waiting for their next request are closed at once
+///
+/// Closing the connection after a reply is the signal for clients to
+/// reconnect, which behind an L4 load balancer takes them to another
+/// server. A request that the server had not started reading when it was
+/// drained is never processed: its client sees the connection close
+/// without a reply, and can safely retry it elsewhere.
+///
+/// `wait` blocks until every connection has been closed, so a rolling
+/// deploy can drain the old server, wait, and then exit.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use thrift::server::TDrainHandle;
+///
+/// let drain = TDrainHandle::new();
+/// // server.set_drain_handle(drain.clone());
+/// // thread::spawn(move || server.listen("0.0.0.0:9090"));
+///
+/// // later, when the deploy replaces this server
+/// drain.drain();
+/// if !drain.wait(Duration::from_secs(30)) {
+///     eprintln!("{} connections still open", drain.active_connections());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TDrainHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    draining: AtomicBool,
+    state: Mutex<State>,
+    closed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    connections: HashMap<u64, Connection>,
+    listeners: Vec<SockAddr>,
+}
+
+struct Connection {
+    idle: bool,
+    closer: Option<Closer>,
+}
+
+impl TDrainHandle {
+    /// Create a `TDrainHandle` for a server that is not draining.
+    pub fn new() -> TDrainHandle {
+        TDrainHandle {
+            shared: Arc::new(Shared {
+                draining: AtomicBool::new(false),
+                state: Mutex::new(State::default()),
+                closed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Stop accepting connections and close every connection once its
+    /// current request has been answered.
+    ///
+    /// Draining cannot be undone. Calling `drain` again does nothing.
+    pub fn drain(&self) {
+        if self.shared.draining.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let state = self.lock();
+        debug!(
+            "draining server with {} open connections",
+            state.connections.len()
+        );
+        for connection in state.connections.values() {
+            if let (true, Some(ref closer)) = (connection.idle, &connection.closer) {
+                closer.close();
+            }
+        }
+        for address in &state.listeners {
+            // an accept loop only notices the drain once it accepts a
+            // connection, so give it one
+            if let Err(e) = wake(address) {
+                warn!("failed to wake accept loop with error {:?}", e);
+            }
+        }
+    }
+
+    /// Return `true` once `drain` has been called.
+    pub fn is_draining(&self) -> bool {
+        self.shared.draining.load(Ordering::SeqCst)
+    }
+
+    /// Return the number of connections that are still open.
+    pub fn active_connections(&self) -> usize {
+        self.lock().connections.len()
+    }
+
+    /// Block until every connection has been closed, or `timeout` has
+    /// passed.
+    ///
+    /// Return `true` if every connection has been closed.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        while !state.connections.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            state = self
+                .shared
+                .closed
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+
+    /// Remember the address of a listener, to wake its accept loop when the
+    /// server is drained.
+    pub(super) fn add_listener(&self, listener: SockRef<'_>) {
+        match listener.local_addr().map(wake_address) {
+            Ok(address) => self.lock().listeners.push(address),
+            Err(e) => warn!("failed to read listener address with error {:?}", e),
+        }
+    }
+
+    /// Register a newly accepted connection, which `closer` closes if it is
+    /// idle when the server is drained.
+    pub(super) fn add_connection(&self, closer: Option<Closer>) -> DrainedConnection {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state
+            .connections
+            .insert(id, Connection { idle: true, closer });
+        DrainedConnection {
+            handle: self.clone(),
+            id,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for TDrainHandle {
+    fn default() -> Self {
+        TDrainHandle::new()
+    }
+}
+
+impl fmt::Debug for TDrainHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TDrainHandle")
+            .field("draining", &self.is_draining())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Registration of one connection with a `TDrainHandle`, removed when it
+/// is dropped.
+pub(super) struct DrainedConnection {
+    handle: TDrainHandle,
+    id: u64,
+}
+
+impl DrainedConnection {
+    /// Mark the connection as waiting for its next request.
+    ///
+    /// Return `false` if the server is draining, in which case the
+    /// connection should be closed instead.
+    pub(super) fn idle(&self) -> bool {
+        let mut state = self.handle.lock();
+        // checked under the lock, so `drain` either sees the connection as
+        // idle or the connection sees the drain
+        if self.handle.is_draining() {
+            return false;
+        }
+        if let Some(connection) = state.connections.get_mut(&self.id) {
+            connection.idle = true;
+        }
+        true
+    }
+
+    /// Mark the connection as processing a request.
+    pub(super) fn busy(&self) {
+        if let Some(connection) = self.handle.lock().connections.get_mut(&self.id) {
+            connection.idle = false;
+        }
+    }
+}
+
+impl Drop for DrainedConnection {
+    fn drop(&mut self) {
+        self.handle.lock().connections.remove(&self.id);
+        self.handle.shared.closed.notify_all();
+    }
+}
+
+/// Shuts down the read side of an accepted connection, which ends a read
+/// blocked waiting for the next request.
+#[derive(Clone)]
+pub(super) struct Closer(Arc<dyn Fn() -> io::Result<()> + Send + Sync>);
+
+impl Closer {
+    pub(super) fn tcp(stream: &TcpStream) -> Option<Closer> {
+        let stream = stream.try_clone().ok()?;
+        Some(Closer(Arc::new(move || stream.shutdown(Shutdown::Read))))
+    }
+
+    #[cfg(unix)]
+    pub(super) fn unix(stream: &UnixStream) -> Option<Closer> {
+        let stream = stream.try_clone().ok()?;
+        Some(Closer(Arc::new(move || stream.shutdown(Shutdown::Read))))
+    }
+
+    pub(super) fn close(&self) {
+        if let Err(e) = (self.0)() {
+            debug!("failed to close connection with error {:?}", e);
+        }
+    }
+}
+
+/// Return the address to connect to in order to reach a listener bound to
+/// `address`.
+fn wake_address(address: SockAddr) -> SockAddr {
+    match address.as_socket() {
+        Some(mut socket_address) if socket_address.ip().is_unspecified() => {
+            let loopback = match socket_address.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            };
+            socket_address.set_ip(loopback);
+            socket_address.into()
+        }
+        _ => address,
+    }
+}
+
+fn wake(address: &SockAddr) -> io::Result<()> {
+    let socket = Socket::new(address.domain(), Type::STREAM, None)?;
+    socket.connect_timeout(address, Duration::from_secs(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread::{self, JoinHandle};
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TInputProtocol, TMessageIdentifier, TMessageType,
+        TOutputProtocol, TStructIdentifier, TType,
+    };
+    use crate::server::{TProcessor, TServer};
+    use crate::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+    /// Answers each call with an empty reply, once it has been released.
+    struct Gated {
+        started: Mutex<Sender<()>>,
+        release: Mutex<Receiver<()>>,
+    }
+
+    impl TProcessor for Gated {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.skip(TType::Struct)?;
+            i.read_message_end()?;
+            self.started.lock().unwrap().send(()).unwrap();
+            self.release.lock().unwrap().recv().unwrap();
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    struct Harness {
+        drain: TDrainHandle,
+        address: std::net::SocketAddr,
+        listening: JoinHandle<crate::Result<()>>,
+        started: Receiver<()>,
+        release: Sender<()>,
+    }
+
+    fn serve() -> Harness {
+        let (started_tx, started) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel();
+        let processor = Gated {
+            started: Mutex::new(started_tx),
+            release: Mutex::new(release_rx),
+        };
+        let mut server = TServer::new(
+            TBufferedReadTransportFactory::new(),
+            TBinaryInputProtocolFactory::new(),
+            TBufferedWriteTransportFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+            processor,
+            2,
+        );
+        let drain = TDrainHandle::new();
+        server.set_drain_handle(drain.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let listening = thread::spawn(move || server.listen_on(listener));
+        Harness {
+            drain,
+            address,
+            listening,
+            started,
+            release,
+        }
+    }
+
+    fn send_call(stream: &TcpStream, sequence_number: i32) {
+        let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new(
+                "ping",
+                TMessageType::Call,
+                sequence_number,
+            ))
+            .unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("ping_args"))
+            .unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    fn read_reply(stream: &TcpStream) -> TMessageIdentifier {
+        let mut i_prot = TBinaryInputProtocol::new(stream.try_clone().unwrap(), true);
+        i_prot.read_message_begin().unwrap()
+    }
+
+    fn connect(address: std::net::SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    fn assert_closed(mut stream: &TcpStream) {
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn must_stop_accepting_and_close_idle_connections() {
+        let harness = serve();
+        let client = connect(harness.address);
+        send_call(&client, 1);
+        harness.started.recv().unwrap();
+        harness.release.send(()).unwrap();
+        assert_eq!(read_reply(&client).sequence_number, 1);
+
+        harness.drain.drain();
+
+        harness.listening.join().unwrap().unwrap();
+        assert_closed(&client);
+        assert!(harness.drain.wait(Duration::from_secs(5)));
+        assert_eq!(harness.drain.active_connections(), 0);
+    }
+
+    #[test]
+    fn must_answer_request_in_flight_before_closing() {
+        let harness = serve();
+        let client = connect(harness.address);
+        send_call(&client, 7);
+        harness.started.recv().unwrap();
+
+        harness.drain.drain();
+        harness.listening.join().unwrap().unwrap();
+        assert!(!harness.drain.wait(Duration::from_millis(50)));
+        assert_eq!(harness.drain.active_connections(), 1);
+
+        harness.release.send(()).unwrap();
+        let reply = read_reply(&client);
+        assert_eq!(reply.message_type, TMessageType::Reply);
+        assert_eq!(reply.sequence_number, 7);
+        assert_closed(&client);
+        assert!(harness.drain.wait(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,456 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use crate::errors::write_application_error_struct;
+use crate::protocol::{
+    TBinaryInputProtocol, TBinaryOutputProtocol, TFieldIdentifier, TListIdentifier, TMapIdentifier,
+    TMessageIdentifier, TMessageType, TOutputProtocol, TRawString, TSetIdentifier,
+    TStructIdentifier,
+};
+use crate::{new_transport_error, ApplicationError, ApplicationErrorKind, TransportErrorKind};
+
+use super::{error, warn};
+
+/// Decides what clients are told when a request fails.
+///
+/// Register an implementation with `TServer::set_error_mapper`. Every
+/// `TApplicationException` the server sends - the ones generated processors
+/// write for handler errors that are not declared in the IDL, and the ones
+/// the server writes itself, for example after a handler panic - is passed
+/// through the mapper before it is written, along with the header of the
+/// exception message. The mapper returns the error to send instead, and
+/// whether the connection should be closed once it has been sent.
+///
+/// Exceptions declared in the IDL are part of the service's interface and
+/// are sent to the client unchanged.
+///
+/// Closures with the signature of `map_error` are error mappers too.
+///
+/// # Examples
+///
+/// Hide the details of internal errors, and close the connection after a
+/// protocol error.
+///
+/// ```
+/// use std::sync::Arc;
+/// use thrift::protocol::TMessageIdentifier;
+/// use thrift::server::{TErrorMapper, TMappedError};
+/// use thrift::{ApplicationError, ApplicationErrorKind};
+///
+/// let mapper = |_: &TMessageIdentifier, e: ApplicationError| match e.kind {
+///     ApplicationErrorKind::Unknown | ApplicationErrorKind::InternalError => {
+///         TMappedError::reply(ApplicationError::new(e.kind, "internal error"))
+///     }
+///     ApplicationErrorKind::ProtocolError => TMappedError::close(e),
+///     _ => TMappedError::reply(e),
+/// };
+/// let mapper: Arc<dyn TErrorMapper> = Arc::new(mapper);
+/// ```
+pub trait TErrorMapper: Send + Sync {
+    /// Map `error`, about to be sent in the exception message `ident`, to
+    /// the error the client is sent.
+    fn map_error(&self, ident: &TMessageIdentifier, error: ApplicationError) -> TMappedError;
+}
+
+impl<F> TErrorMapper for F
+where
+    F: Fn(&TMessageIdentifier, ApplicationError) -> TMappedError + Send + Sync,
+{
+    fn map_error(&self, ident: &TMessageIdentifier, error: ApplicationError) -> TMappedError {
+        self(ident, error)
+    }
+}
+
+/// The error a `TErrorMapper` sends to the client, and what then happens to
+/// the connection.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TMappedError {
+    error: ApplicationError,
+    close_connection: bool,
+}
+
+impl TMappedError {
+    /// Send `error` and keep serving the connection.
+    pub fn reply(error: ApplicationError) -> TMappedError {
+        TMappedError {
+            error,
+            close_connection: false,
+        }
+    }
+
+    /// Send `error` and close the connection.
+    pub fn close(error: ApplicationError) -> TMappedError {
+        TMappedError {
+            error,
+            close_connection: true,
+        }
+    }
+
+    /// Return the error sent to the client.
+    pub fn error(&self) -> &ApplicationError {
+        &self.error
+    }
+
+    /// Return `true` if the connection is closed once the error is sent.
+    pub fn closes_connection(&self) -> bool {
+        self.close_connection
+    }
+}
+
+impl From<ApplicationError> for TMappedError {
+    fn from(error: ApplicationError) -> Self {
+        TMappedError::reply(error)
+    }
+}
+
+/// `TErrorMapper` that replaces the message of errors which may carry
+/// internal details with a fixed one.
+///
+/// Errors of kind `Unknown` and `InternalError` - the kinds generated
+/// processors use for handler errors and the server uses for panics - have
+/// their message replaced, and the original is logged. Errors of other
+/// kinds describe what was wrong with the request and are sent unchanged.
+#[derive(Debug)]
+pub struct TRedactingErrorMapper {
+    message: String,
+}
+
+impl TRedactingErrorMapper {
+    /// Create a `TRedactingErrorMapper` that sends `"internal error"`.
+    pub fn new() -> TRedactingErrorMapper {
+        TRedactingErrorMapper {
+            message: "internal error".to_owned(),
+        }
+    }
+
+    /// Send `message` in place of the original message.
+    pub fn with_message<S: Into<String>>(mut self, message: S) -> TRedactingErrorMapper {
+        self.message = message.into();
+        self
+    }
+}
+
+impl Default for TRedactingErrorMapper {
+    fn default() -> Self {
+        TRedactingErrorMapper::new()
+    }
+}
+
+impl TErrorMapper for TRedactingErrorMapper {
+    fn map_error(&self, ident: &TMessageIdentifier, error: ApplicationError) -> TMappedError {
+        match error.kind {
+            ApplicationErrorKind::Unknown | ApplicationErrorKind::InternalError => {
+                warn!(
+                    "request {} failed with error {:?}; sending redacted error to client",
+                    ident.name, error
+                );
+                TMappedError::reply(ApplicationError::new(error.kind, self.message.clone()))
+            }
+            _ => TMappedError::reply(error),
+        }
+    }
+}
+
+/// `TOutputProtocol` that passes the exceptions written to it through a
+/// `TErrorMapper`.
+///
+/// Exception messages are buffered until they end, then read back, mapped
+/// and written to the wrapped protocol. Any other message is written
+/// through. Once a mapped error asks for the connection to be closed, the
+/// flush that sends it fails with an end-of-file error, which ends the
+/// connection the same way a client disconnecting does.
+pub(super) struct ErrorMappingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    inner: P,
+    mapper: Arc<dyn TErrorMapper>,
+    exception: Option<(TMessageIdentifier, TBinaryOutputProtocol<Vec<u8>>)>,
+    closing: bool,
+}
+
+impl<P> ErrorMappingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    pub(super) fn new(inner: P, mapper: Arc<dyn TErrorMapper>) -> ErrorMappingOutputProtocol<P> {
+        ErrorMappingOutputProtocol {
+            inner,
+            mapper,
+            exception: None,
+            closing: false,
+        }
+    }
+
+    /// Return the protocol the current message is written to.
+    fn target(&mut self) -> &mut dyn TOutputProtocol {
+        match self.exception {
+            Some((_, ref mut buffer)) => buffer,
+            None => &mut self.inner,
+        }
+    }
+
+    /// Map the exception buffered in `buffer` and write it.
+    fn send_mapped(
+        &mut self,
+        ident: TMessageIdentifier,
+        buffer: TBinaryOutputProtocol<Vec<u8>>,
+    ) -> crate::Result<()> {
+        let mut written = TBinaryInputProtocol::new(&buffer.transport[..], true);
+        let original = crate::Error::read_application_error_from_in_protocol(&mut written)?;
+        let mapper = &self.mapper;
+        let mapped = panic::catch_unwind(AssertUnwindSafe(|| mapper.map_error(&ident, original)))
+            .unwrap_or_else(|_| {
+                error!("error mapper panicked on request {}", ident.name);
+                TMappedError::close(ApplicationError::new(
+                    ApplicationErrorKind::InternalError,
+                    "internal error",
+                ))
+            });
+        self.closing |= mapped.close_connection;
+
+        self.inner.write_message_begin(&ident)?;
+        write_application_error_struct(&mapped.error, &mut self.inner)?;
+        self.inner.write_message_end()
+    }
+}
+
+impl<P> TOutputProtocol for ErrorMappingOutputProtocol<P>
+where
+    P: TOutputProtocol,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        if identifier.message_type == TMessageType::Exception {
+            let buffer = TBinaryOutputProtocol::new(Vec::new(), true);
+            self.exception = Some((identifier.clone(), buffer));
+            Ok(())
+        } else {
+            self.inner.write_message_begin(identifier)
+        }
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        match self.exception.take() {
+            Some((ident, buffer)) => self.send_mapped(ident, buffer),
+            None => self.inner.write_message_end(),
+        }
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        self.target().write_struct_begin(identifier)
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.target().write_struct_end()
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        self.target().write_field_begin(identifier)
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.target().write_field_end()
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        self.target().write_field_stop()
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.target().write_bytes(b)
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.target().write_bool(b)
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.target().write_i8(i)
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.target().write_i16(i)
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.target().write_i32(i)
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.target().write_i64(i)
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.target().write_double(d)
+    }
+
+    fn write_uuid(&mut self, uuid: &uuid::Uuid) -> crate::Result<()> {
+        self.target().write_uuid(uuid)
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.target().write_string(s)
+    }
+
+    fn write_raw_string(&mut self, s: &TRawString) -> crate::Result<()> {
+        self.target().write_raw_string(s)
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.target().write_list_begin(identifier)
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.target().write_list_end()
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.target().write_set_begin(identifier)
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.target().write_set_end()
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        self.target().write_map_begin(identifier)
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.target().write_map_end()
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        if self.exception.is_some() {
+            // the exception is sent once it is complete
+            return Ok(());
+        }
+        self.inner.flush()?;
+        if self.closing {
+            return Err(new_transport_error(
+                TransportErrorKind::EndOfFile,
+                "connection closed by the error mapper",
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.target().write_byte(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{TInputProtocol, TType};
+    use crate::server::handle_process_result;
+
+    fn mapping<F>(mapper: F) -> ErrorMappingOutputProtocol<TBinaryOutputProtocol<Vec<u8>>>
+    where
+        F: TErrorMapper + 'static,
+    {
+        let inner = TBinaryOutputProtocol::new(Vec::new(), true);
+        ErrorMappingOutputProtocol::new(inner, Arc::new(mapper))
+    }
+
+    fn fail(o_prot: &mut dyn TOutputProtocol, message: &str) -> crate::Result<()> {
+        let ident = TMessageIdentifier::new("lookup", TMessageType::Call, 7);
+        let failed = Err(crate::Error::from(ApplicationError::new(
+            ApplicationErrorKind::Unknown,
+            message,
+        )));
+        handle_process_result(&ident, failed, o_prot)
+    }
+
+    fn read_exception(written: &[u8]) -> (TMessageIdentifier, ApplicationError) {
+        let mut i_prot = TBinaryInputProtocol::new(written, true);
+        let ident = i_prot.read_message_begin().unwrap();
+        let error = crate::Error::read_application_error_from_in_protocol(&mut i_prot).unwrap();
+        i_prot.read_message_end().unwrap();
+        (ident, error)
+    }
+
+    #[test]
+    fn must_send_mapped_error() {
+        let mut o_prot = mapping(TRedactingErrorMapper::new());
+
+        fail(&mut o_prot, "connection to db-3.internal refused").unwrap();
+
+        let (ident, error) = read_exception(&o_prot.inner.transport);
+        assert_eq!(ident.name, "lookup");
+        assert_eq!(ident.message_type, TMessageType::Exception);
+        assert_eq!(ident.sequence_number, 7);
+        assert_eq!(
+            error,
+            ApplicationError::new(ApplicationErrorKind::Unknown, "internal error")
+        );
+    }
+
+    #[test]
+    fn must_write_replies_through() {
+        let mut o_prot = mapping(|_: &TMessageIdentifier, _| -> TMappedError {
+            panic!("replies must not be mapped")
+        });
+
+        let ident = TMessageIdentifier::new("lookup", TMessageType::Reply, 7);
+        o_prot.write_message_begin(&ident).unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("lookup_result"))
+            .unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("success", TType::String, 0))
+            .unwrap();
+        o_prot.write_string("found").unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        let mut expected = TBinaryOutputProtocol::new(Vec::new(), true);
+        expected.write_message_begin(&ident).unwrap();
+        expected
+            .write_struct_begin(&TStructIdentifier::new("lookup_result"))
+            .unwrap();
+        expected
+            .write_field_begin(&TFieldIdentifier::new("success", TType::String, 0))
+            .unwrap();
+        expected.write_string("found").unwrap();
+        expected.write_field_end().unwrap();
+        expected.write_field_stop().unwrap();
+        expected.write_struct_end().unwrap();
+        expected.write_message_end().unwrap();
+        assert_eq!(o_prot.inner.transport, expected.transport);
+    }
+
+    #[test]
+    fn must_close_connection_after_sending_error() {
+        let mut o_prot = mapping(|_: &TMessageIdentifier, e| TMappedError::close(e));
+
+        match fail(&mut o_prot, "bad request") {
+            Err(crate::Error::Transport(e)) => assert_eq!(e.kind, TransportErrorKind::EndOfFile),
+            other => panic!("expected end of file, got {:?}", other),
+        }
+        let (_, error) = read_exception(&o_prot.inner.transport);
+        assert_eq!(error.message, "bad request");
+    }
+}
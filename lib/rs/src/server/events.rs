@@ -0,0 +1,342 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::any::Any;
+use std::fmt;
+use std::net::SocketAddr;
+
+use crate::protocol::TMessageIdentifier;
+
+/// Hooks called by `TServer` over the lifetime of each connection.
+///
+/// Register an implementation with `TServer::set_event_handler` to attach
+/// logging, authorization or per-connection state without changing the
+/// accept loop or the processor. All methods have empty default
+/// implementations, so implementors only override the events they need.
+///
+/// `connection_accepted` is called on the accepting thread. The other
+/// methods are called on the worker thread serving the connection, in the
+/// order `context_created`, then `pre_process` and `post_process` around
+/// each request, then `connection_closed`.
+///
+/// # Examples
+///
+/// Count the requests on each connection.
+///
+/// ```
+/// use thrift::protocol::TMessageIdentifier;
+/// use thrift::server::{TConnectionContext, TServerEventHandler};
+///
+/// struct RequestLogger;
+///
+/// impl TServerEventHandler for RequestLogger {
+///     fn context_created(&self, context: &mut TConnectionContext) {
+///         context.set_state(Vec::<String>::new());
+///     }
+///
+///     fn pre_process(&self, context: &mut TConnectionContext, message: &TMessageIdentifier) {
+///         if let Some(calls) = context.state_mut::<Vec<String>>() {
+///             calls.push(message.name.clone());
+///         }
+///     }
+///
+///     fn connection_closed(&self, context: TConnectionContext) {
+///         let calls = context.state::<Vec<String>>().map_or(0, |calls| calls.len());
+///         println!("{:?} made {} calls", context.peer_addr(), calls);
+///     }
+/// }
+/// ```
+pub trait TServerEventHandler: Send + Sync {
+    /// Called when a connection from `peer_addr` is accepted. Return `false`
+    /// to close the connection without serving it.
+    ///
+    /// `peer_addr` is `None` for connections that have no IP address, such
+    /// as those accepted on a Unix domain socket.
+    fn connection_accepted(&self, _peer_addr: Option<SocketAddr>) -> bool {
+        true
+    }
+
+    /// Called on the worker before the first request on a connection is
+    /// read. Store per-connection state in `context` here.
+    fn context_created(&self, _context: &mut TConnectionContext) {}
+
+    /// Called after the header of a request has been read, before the
+    /// processor handles it.
+    fn pre_process(&self, _context: &mut TConnectionContext, _message: &TMessageIdentifier) {}
+
+    /// Called after the processor has handled a request, with the result it
+    /// returned.
+    fn post_process(
+        &self,
+        _context: &mut TConnectionContext,
+        _message: &TMessageIdentifier,
+        _result: &crate::Result<()>,
+    ) {
+    }
+
+    /// Called once the connection has been closed or has failed.
+    fn connection_closed(&self, _context: TConnectionContext) {}
+}
+
+/// Per-connection information and state passed to a `TServerEventHandler`.
+pub struct TConnectionContext {
+    peer_addr: Option<SocketAddr>,
+    pub(super) requests: u64,
+    state: Option<Box<dyn Any + Send>>,
+}
+
+impl TConnectionContext {
+    pub(super) fn new(peer_addr: Option<SocketAddr>) -> TConnectionContext {
+        TConnectionContext {
+            peer_addr,
+            requests: 0,
+            state: None,
+        }
+    }
+
+    /// Address of the client, if the connection has one.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Number of requests received on the connection so far, including the
+    /// one being processed.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Store `state` for the lifetime of the connection, replacing any
+    /// previously stored state.
+    pub fn set_state<T: Any + Send>(&mut self, state: T) {
+        self.state = Some(Box::new(state));
+    }
+
+    /// Return the stored state, if any state of type `T` is stored.
+    pub fn state<T: Any + Send>(&self) -> Option<&T> {
+        self.state.as_ref().and_then(|s| s.downcast_ref())
+    }
+
+    /// Return the stored state mutably, if any state of type `T` is stored.
+    pub fn state_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.state.as_mut().and_then(|s| s.downcast_mut())
+    }
+
+    /// Remove and return the stored state, if any state of type `T` is
+    /// stored.
+    pub fn take_state<T: Any + Send>(&mut self) -> Option<T> {
+        match self.state.take()?.downcast() {
+            Ok(state) => Some(*state),
+            Err(other) => {
+                self.state = Some(other);
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TConnectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TConnectionContext")
+            .field("peer_addr", &self.peer_addr)
+            .field("requests", &self.requests)
+            .field("has_state", &self.state.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TInputProtocol, TMessageType, TOutputProtocol,
+    };
+    use crate::server::{request_context, TProcessor, TServer};
+    use crate::transport::{
+        TBufferedReadTransportFactory, TBufferedWriteTransportFactory, TTcpChannel,
+    };
+
+    struct Echo;
+
+    impl TProcessor for Echo {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            let value = i.read_i32()?;
+            i.read_message_end()?;
+            let context = request_context().expect("request context");
+            assert_eq!(context.peer_addr().unwrap().ip(), Ipv4Addr::LOCALHOST);
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_i32(value)?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    /// Records each event, and rejects connections once `reject` is set.
+    struct Recorder {
+        events: Mutex<Sender<String>>,
+        reject: Mutex<bool>,
+    }
+
+    impl Recorder {
+        fn record(&self, event: String) {
+            self.events.lock().unwrap().send(event).unwrap();
+        }
+    }
+
+    impl TServerEventHandler for Recorder {
+        fn connection_accepted(&self, peer_addr: Option<SocketAddr>) -> bool {
+            assert!(peer_addr.is_some());
+            self.record("accepted".to_owned());
+            !*self.reject.lock().unwrap()
+        }
+
+        fn context_created(&self, context: &mut TConnectionContext) {
+            context.set_state(0i32);
+            self.record("created".to_owned());
+        }
+
+        fn pre_process(&self, context: &mut TConnectionContext, message: &TMessageIdentifier) {
+            *context.state_mut::<i32>().unwrap() += message.sequence_number;
+            self.record(format!("pre {} {}", message.name, context.requests()));
+        }
+
+        fn post_process(
+            &self,
+            _: &mut TConnectionContext,
+            message: &TMessageIdentifier,
+            result: &crate::Result<()>,
+        ) {
+            self.record(format!("post {} {}", message.name, result.is_ok()));
+        }
+
+        fn connection_closed(&self, mut context: TConnectionContext) {
+            let sum = context.take_state::<i32>().unwrap();
+            self.record(format!("closed {} {}", context.requests(), sum));
+        }
+    }
+
+    fn start() -> (SocketAddr, Arc<Recorder>, Receiver<String>) {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (tx, rx) = channel();
+        let recorder = Arc::new(Recorder {
+            events: Mutex::new(tx),
+            reject: Mutex::new(false),
+        });
+        let handler = recorder.clone();
+        thread::spawn(move || {
+            let mut server = TServer::new(
+                TBufferedReadTransportFactory::new(),
+                TBinaryInputProtocolFactory::new(),
+                TBufferedWriteTransportFactory::new(),
+                TBinaryOutputProtocolFactory::new(),
+                Echo,
+                2,
+            );
+            server.set_event_handler(handler);
+            server.listen(address)
+        });
+        (address, recorder, rx)
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let mut attempts = 0;
+        loop {
+            match TcpStream::connect(address) {
+                Ok(s) => return s,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("cannot connect to server: {:?}", e),
+            }
+        }
+    }
+
+    fn call(stream: &TcpStream, seq: i32) -> i32 {
+        let channel = TTcpChannel::with_stream(stream.try_clone().unwrap());
+        let mut o_prot =
+            TBinaryOutputProtocol::new(TTcpChannel::with_stream(stream.try_clone().unwrap()), true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("echo", TMessageType::Call, seq))
+            .unwrap();
+        o_prot.write_i32(seq * 10).unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        let mut i_prot = TBinaryInputProtocol::new(channel, true);
+        i_prot.read_message_begin().unwrap();
+        i_prot.read_i32().unwrap()
+    }
+
+    fn next(events: &Receiver<String>) -> String {
+        events.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn must_call_handler_in_connection_order() {
+        let (address, _, events) = start();
+        let stream = connect(address);
+        assert_eq!(call(&stream, 1), 10);
+        assert_eq!(call(&stream, 2), 20);
+        drop(stream);
+
+        let expected = [
+            "accepted",
+            "created",
+            "pre echo 1",
+            "post echo true",
+            "pre echo 2",
+            "post echo true",
+            "closed 2 3",
+        ];
+        for event in expected.iter() {
+            assert_eq!(&next(&events), event);
+        }
+    }
+
+    #[test]
+    fn must_close_rejected_connections() {
+        let (address, recorder, events) = start();
+        *recorder.reject.lock().unwrap() = true;
+
+        let mut stream = connect(address);
+        assert_eq!(next(&events), "accepted");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+        assert!(events.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TMessageType, TOutputProtocol, TStructIdentifier, TType,
+};
+use crate::{ApplicationError, ApplicationErrorKind};
+
+use super::{handle_process_result, TConnectionContext, TProcessor, TServerEventHandler};
+
+/// Health of a server, as reported by a `THealthService`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum THealthStatus {
+    /// The server is accepting and processing requests.
+    Serving,
+    /// The server is running but should not be sent requests, for example
+    /// while it is warming up or shutting down.
+    NotServing,
+}
+
+impl From<THealthStatus> for i32 {
+    fn from(status: THealthStatus) -> i32 {
+        match status {
+            THealthStatus::Serving => 1,
+            THealthStatus::NotServing => 2,
+        }
+    }
+}
+
+/// Built-in service that reports the health of a server.
+///
+/// `THealthService` is a `TProcessor` for the following service, so it can
+/// be called with a client generated from this IDL:
+///
+/// ```thrift
+/// enum HealthStatus {
+///   SERVING = 1,
+///   NOT_SERVING = 2,
+/// }
+///
+/// struct ConnectionCounts {
+///   1: i64 active,
+///   2: i64 total,
+/// }
+///
+/// service Health {
+///   HealthStatus status(),
+///   i64 uptime_millis(),
+///   ConnectionCounts connections(),
+///   map<string, list<string>> services(),
+/// }
+/// ```
+///
+/// Register it with a `TMultiplexedProcessor` under `SERVICE_NAME` to serve
+/// it next to the application's services. Connection counts are collected
+/// by registering the same `THealthService` as the server's
+/// `TServerEventHandler`; `services` returns whatever was registered with
+/// `add_service`.
+///
+/// Clones share the same state, so the application can keep a clone to
+/// change the reported status.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use thrift::protocol::{TBinaryInputProtocolFactory, TBinaryOutputProtocolFactory};
+/// use thrift::server::{THealthService, THealthStatus, TMultiplexedProcessor, TServer};
+/// use thrift::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+///
+/// let health = THealthService::new();
+/// health.add_service("Calculator", vec!["add", "subtract"]);
+///
+/// let mut processor = TMultiplexedProcessor::new();
+/// processor
+///     .register(THealthService::SERVICE_NAME, Box::new(health.clone()), false)
+///     .unwrap();
+/// // register the application's processors here
+///
+/// let mut server = TServer::new(
+///     TBufferedReadTransportFactory::new(),
+///     TBinaryInputProtocolFactory::new(),
+///     TBufferedWriteTransportFactory::new(),
+///     TBinaryOutputProtocolFactory::new(),
+///     processor,
+///     10,
+/// );
+/// server.set_event_handler(Arc::new(health.clone()));
+///
+/// // report "not serving" while draining, before shutting down
+/// health.set_status(THealthStatus::NotServing);
+/// # server.listen("127.0.0.1:9090").unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct THealthService {
+    state: Arc<HealthState>,
+}
+
+#[derive(Debug)]
+struct HealthState {
+    started: Instant,
+    not_serving: AtomicBool,
+    active_connections: AtomicU64,
+    total_connections: AtomicU64,
+    services: Mutex<BTreeMap<String, Vec<String>>>,
+}
+
+impl THealthService {
+    /// Name under which to register the service with a
+    /// `TMultiplexedProcessor`.
+    pub const SERVICE_NAME: &'static str = "Health";
+
+    /// Create a `THealthService` that reports `THealthStatus::Serving` and
+    /// measures uptime from now.
+    pub fn new() -> THealthService {
+        THealthService {
+            state: Arc::new(HealthState {
+                started: Instant::now(),
+                not_serving: AtomicBool::new(false),
+                active_connections: AtomicU64::new(0),
+                total_connections: AtomicU64::new(0),
+                services: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Set the status reported to clients.
+    pub fn set_status(&self, status: THealthStatus) {
+        let not_serving = status == THealthStatus::NotServing;
+        self.state.not_serving.store(not_serving, Ordering::Relaxed);
+    }
+
+    /// Status reported to clients.
+    pub fn status(&self) -> THealthStatus {
+        if self.state.not_serving.load(Ordering::Relaxed) {
+            THealthStatus::NotServing
+        } else {
+            THealthStatus::Serving
+        }
+    }
+
+    /// Time since the service was created.
+    pub fn uptime(&self) -> Duration {
+        self.state.started.elapsed()
+    }
+
+    /// Number of connections currently open.
+    pub fn active_connections(&self) -> u64 {
+        self.state.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections accepted since the service was created.
+    pub fn total_connections(&self) -> u64 {
+        self.state.total_connections.load(Ordering::Relaxed)
+    }
+
+    /// Report that the service named `service_name`, with the given methods,
+    /// is served. Replaces any methods previously reported for the service.
+    pub fn add_service<S, I, M>(&self, service_name: S, methods: I)
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = M>,
+        M: Into<String>,
+    {
+        let methods = methods.into_iter().map(Into::into).collect();
+        self.state
+            .services
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(service_name.into(), methods);
+    }
+
+    /// Return the services reported with `add_service`, with their methods.
+    pub fn services(&self) -> BTreeMap<String, Vec<String>> {
+        self.state
+            .services
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn write_success(&self, method: Method, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        match method {
+            Method::Status => {
+                write_success_field(o_prot, TType::I32)?;
+                o_prot.write_i32(self.status().into())?;
+            }
+            Method::UptimeMillis => {
+                write_success_field(o_prot, TType::I64)?;
+                o_prot.write_i64(to_i64(self.uptime().as_millis()))?;
+            }
+            Method::Connections => {
+                write_success_field(o_prot, TType::Struct)?;
+                o_prot.write_struct_begin(&TStructIdentifier::new("ConnectionCounts"))?;
+                o_prot.write_field_begin(&TFieldIdentifier::new("active", TType::I64, 1))?;
+                o_prot.write_i64(to_i64(self.active_connections().into()))?;
+                o_prot.write_field_end()?;
+                o_prot.write_field_begin(&TFieldIdentifier::new("total", TType::I64, 2))?;
+                o_prot.write_i64(to_i64(self.total_connections().into()))?;
+                o_prot.write_field_end()?;
+                o_prot.write_field_stop()?;
+                o_prot.write_struct_end()?;
+            }
+            Method::Services => {
+                let services = self.services();
+                write_success_field(o_prot, TType::Map)?;
+                o_prot.write_map_begin(&TMapIdentifier::new(
+                    TType::String,
+                    TType::List,
+                    services.len() as i32,
+                ))?;
+                for (service, methods) in services.iter() {
+                    o_prot.write_string(service)?;
+                    o_prot.write_list_begin(&TListIdentifier::new(
+                        TType::String,
+                        methods.len() as i32,
+                    ))?;
+                    for method in methods {
+                        o_prot.write_string(method)?;
+                    }
+                    o_prot.write_list_end()?;
+                }
+                o_prot.write_map_end()?;
+            }
+        }
+        o_prot.write_field_end()
+    }
+}
+
+impl Default for THealthService {
+    fn default() -> Self {
+        THealthService::new()
+    }
+}
+
+impl TProcessor for THealthService {
+    fn process(
+        &self,
+        i_prot: &mut dyn TInputProtocol,
+        o_prot: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()> {
+        let ident = i_prot.read_message_begin()?;
+        // none of the methods take arguments
+        i_prot.skip(TType::Struct)?;
+        i_prot.read_message_end()?;
+
+        let method = match Method::from_name(&ident.name) {
+            Some(method) => method,
+            None => {
+                let error = ApplicationError::new(
+                    ApplicationErrorKind::UnknownMethod,
+                    format!("unknown method {}", ident.name),
+                );
+                return handle_process_result(&ident, Err(error.into()), o_prot);
+            }
+        };
+        if ident.message_type == TMessageType::OneWay {
+            return Ok(());
+        }
+
+        o_prot.write_message_begin(&TMessageIdentifier::new(
+            ident.name.clone(),
+            TMessageType::Reply,
+            ident.sequence_number,
+        ))?;
+        o_prot.write_struct_begin(&TStructIdentifier::new(format!("{}_result", ident.name)))?;
+        self.write_success(method, o_prot)?;
+        o_prot.write_field_stop()?;
+        o_prot.write_struct_end()?;
+        o_prot.write_message_end()?;
+        o_prot.flush()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Method {
+    Status,
+    UptimeMillis,
+    Connections,
+    Services,
+}
+
+impl Method {
+    fn from_name(name: &str) -> Option<Method> {
+        match name {
+            "status" => Some(Method::Status),
+            "uptime_millis" => Some(Method::UptimeMillis),
+            "connections" => Some(Method::Connections),
+            "services" => Some(Method::Services),
+            _ => None,
+        }
+    }
+}
+
+impl TServerEventHandler for THealthService {
+    fn connection_accepted(&self, _peer_addr: Option<SocketAddr>) -> bool {
+        self.state.total_connections.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    fn context_created(&self, _context: &mut TConnectionContext) {
+        self.state
+            .active_connections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn connection_closed(&self, _context: TConnectionContext) {
+        self.state
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn write_success_field(o_prot: &mut dyn TOutputProtocol, field_type: TType) -> crate::Result<()> {
+    o_prot.write_field_begin(&TFieldIdentifier::new("success", field_type, 0))
+}
+
+fn to_i64(value: u128) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+
+    /// Call `method` on `service` and return the serialized reply.
+    fn call(service: &THealthService, method: &str) -> Vec<u8> {
+        let mut request = TBinaryOutputProtocol::new(Vec::new(), true);
+        request
+            .write_message_begin(&TMessageIdentifier::new(method, TMessageType::Call, 7))
+            .unwrap();
+        request
+            .write_struct_begin(&TStructIdentifier::new("args"))
+            .unwrap();
+        request.write_field_stop().unwrap();
+        request.write_struct_end().unwrap();
+        request.write_message_end().unwrap();
+
+        let mut i_prot = TBinaryInputProtocol::new(&request.transport[..], true);
+        let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+        service.process(&mut i_prot, &mut o_prot).unwrap();
+        o_prot.transport
+    }
+
+    /// Read the reply header and the header of the `success` field.
+    fn read_success(i_prot: &mut TBinaryInputProtocol<&[u8]>, field_type: TType) {
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Reply);
+        assert_eq!(ident.sequence_number, 7);
+        i_prot.read_struct_begin().unwrap();
+        let field = i_prot.read_field_begin().unwrap();
+        assert_eq!((field.id, field.field_type), (Some(0), field_type));
+    }
+
+    #[test]
+    fn must_report_status() {
+        let service = THealthService::new();
+        let reply = call(&service, "status");
+        let mut reply = TBinaryInputProtocol::new(&reply[..], true);
+        read_success(&mut reply, TType::I32);
+        assert_eq!(reply.read_i32().unwrap(), 1);
+
+        service.clone().set_status(THealthStatus::NotServing);
+        let reply = call(&service, "status");
+        let mut reply = TBinaryInputProtocol::new(&reply[..], true);
+        read_success(&mut reply, TType::I32);
+        assert_eq!(reply.read_i32().unwrap(), 2);
+    }
+
+    #[test]
+    fn must_report_connection_counts() {
+        let service = THealthService::new();
+        assert!(service.connection_accepted(None));
+        let mut context = TConnectionContext::new(None);
+        service.context_created(&mut context);
+        assert!(service.connection_accepted(None));
+        service.context_created(&mut TConnectionContext::new(None));
+        service.connection_closed(context);
+
+        let reply = call(&service, "connections");
+        let mut reply = TBinaryInputProtocol::new(&reply[..], true);
+        read_success(&mut reply, TType::Struct);
+        reply.read_struct_begin().unwrap();
+        reply.read_field_begin().unwrap();
+        assert_eq!(reply.read_i64().unwrap(), 1);
+        reply.read_field_end().unwrap();
+        reply.read_field_begin().unwrap();
+        assert_eq!(reply.read_i64().unwrap(), 2);
+    }
+
+    #[test]
+    fn must_list_registered_services() {
+        let service = THealthService::new();
+        service.add_service("Calculator", vec!["add", "subtract"]);
+        service.add_service(THealthService::SERVICE_NAME, vec!["status"]);
+
+        let reply = call(&service, "services");
+        let mut reply = TBinaryInputProtocol::new(&reply[..], true);
+        read_success(&mut reply, TType::Map);
+        let map = reply.read_map_begin().unwrap();
+        assert_eq!(map.size, 2);
+        let mut services = Vec::new();
+        for _ in 0..map.size {
+            let name = reply.read_string().unwrap();
+            let list = reply.read_list_begin().unwrap();
+            let methods: Vec<_> = (0..list.size)
+                .map(|_| reply.read_string().unwrap())
+                .collect();
+            services.push((name, methods));
+        }
+        assert_eq!(
+            services,
+            vec![
+                (
+                    "Calculator".to_owned(),
+                    vec!["add".to_owned(), "subtract".to_owned()]
+                ),
+                ("Health".to_owned(), vec!["status".to_owned()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn must_reject_unknown_methods() {
+        let reply = call(&THealthService::new(), "restart");
+        let mut reply = TBinaryInputProtocol::new(&reply[..], true);
+        let ident = reply.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Exception);
+        let error = crate::Error::read_application_error_from_in_protocol(&mut reply).unwrap();
+        assert_eq!(error.kind, ApplicationErrorKind::UnknownMethod);
+    }
+}
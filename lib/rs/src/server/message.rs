@@ -0,0 +1,266 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+#[cfg(feature = "tracing")]
+use tracing::span::EnteredSpan;
+
+use std::net::SocketAddr;
+
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TRawString, TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::{new_transport_error, TConfiguration, TransportErrorKind};
+
+use super::drain::DrainedConnection;
+
+/// `TInputProtocol` that keeps track of the message being read from a
+/// connection.
+///
+/// The header of the current message is remembered so the server can answer
+/// a request whose handler panicked, even if the header was read by the
+/// processor. With the `tracing` feature, a `thrift.request` span is also
+/// entered when a message header has been read, so the processor, the
+/// handler and the reply all run inside it. The span is closed when the next
+/// message header is about to be read or the connection ends.
+///
+/// On a server that can be drained, the connection is reported idle while
+/// it waits for a message header and busy once one has been read, and no
+/// further header is read once the server is draining.
+pub(super) struct MessageInputProtocol {
+    inner: Box<dyn TInputProtocol>,
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    peer_addr: Option<SocketAddr>,
+    message: Option<TMessageIdentifier>,
+    drain: Option<DrainedConnection>,
+    #[cfg(feature = "tracing")]
+    span: Option<EnteredSpan>,
+}
+
+impl MessageInputProtocol {
+    pub(super) fn new(
+        inner: Box<dyn TInputProtocol>,
+        peer_addr: Option<SocketAddr>,
+    ) -> MessageInputProtocol {
+        MessageInputProtocol {
+            inner,
+            peer_addr,
+            message: None,
+            drain: None,
+            #[cfg(feature = "tracing")]
+            span: None,
+        }
+    }
+
+    /// Report the state of the connection to `drain`.
+    pub(super) fn with_drain(mut self, drain: Option<DrainedConnection>) -> MessageInputProtocol {
+        self.drain = drain;
+        self
+    }
+
+    /// Header of the last message read, if any.
+    pub(super) fn message(&self) -> Option<&TMessageIdentifier> {
+        self.message.as_ref()
+    }
+
+    #[cfg(feature = "tracing")]
+    fn enter_span(&mut self, ident: &TMessageIdentifier) {
+        let span = tracing::info_span!(
+            "thrift.request",
+            rpc.method = %ident.name,
+            rpc.seq_id = ident.sequence_number,
+            net.peer = tracing::field::Empty,
+        );
+        if let Some(peer) = self.peer_addr {
+            span.record("net.peer", tracing::field::display(peer));
+        }
+        self.span = Some(span.entered());
+    }
+}
+
+impl TInputProtocol for MessageInputProtocol {
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        // close the previous request's span before waiting for the next one
+        self.message = None;
+        #[cfg(feature = "tracing")]
+        {
+            self.span = None;
+        }
+        if let Some(ref drain) = self.drain {
+            if !drain.idle() {
+                return Err(new_transport_error(
+                    TransportErrorKind::EndOfFile,
+                    "connection closed while draining server",
+                ));
+            }
+        }
+        let ident = self.inner.read_message_begin()?;
+        if let Some(ref drain) = self.drain {
+            drain.busy();
+        }
+        #[cfg(feature = "tracing")]
+        self.enter_span(&ident);
+        self.message = Some(ident.clone());
+        Ok(ident)
+    }
+
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        self.inner.read_message_end()
+    }
+
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.inner.read_struct_begin()
+    }
+
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.inner.read_struct_end()
+    }
+
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        self.inner.read_field_begin()
+    }
+
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        self.inner.read_field_end()
+    }
+
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        self.inner.read_bytes()
+    }
+
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        self.inner.read_bool()
+    }
+
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        self.inner.read_i8()
+    }
+
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.inner.read_i16()
+    }
+
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        self.inner.read_i32()
+    }
+
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        self.inner.read_i64()
+    }
+
+    fn read_double(&mut self) -> crate::Result<f64> {
+        self.inner.read_double()
+    }
+
+    fn read_uuid(&mut self) -> crate::Result<uuid::Uuid> {
+        self.inner.read_uuid()
+    }
+
+    fn read_string(&mut self) -> crate::Result<String> {
+        self.inner.read_string()
+    }
+
+    fn read_raw_string(&mut self) -> crate::Result<TRawString> {
+        self.inner.read_raw_string()
+    }
+
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        self.inner.read_list_begin()
+    }
+
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        self.inner.read_list_end()
+    }
+
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        self.inner.read_set_begin()
+    }
+
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        self.inner.read_set_end()
+    }
+
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        self.inner.read_map_begin()
+    }
+
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        self.inner.read_map_end()
+    }
+
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> crate::Result<()> {
+        self.inner.skip_till_depth(field_type, depth)
+    }
+
+    // utility
+    //
+
+    fn min_serialized_size(&self, field_type: TType) -> usize {
+        self.inner.min_serialized_size(field_type)
+    }
+
+    fn set_configuration(&mut self, config: &TConfiguration) {
+        self.inner.set_configuration(config)
+    }
+
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        self.inner.read_byte()
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use std::io;
+
+    use crate::protocol::SpanRecorder;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TMessageType, TOutputProtocol,
+    };
+
+    #[test]
+    fn must_open_span_per_message() {
+        let mut messages = TBinaryOutputProtocol::new(Vec::new(), true);
+        for (name, seq) in [("first", 1), ("second", 2)] {
+            messages
+                .write_message_begin(&TMessageIdentifier::new(name, TMessageType::Call, seq))
+                .unwrap();
+            messages.write_message_end().unwrap();
+        }
+        let messages = messages.transport;
+
+        let recorder = SpanRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let i_prot = TBinaryInputProtocol::new(io::Cursor::new(messages), true);
+            let peer: SocketAddr = "127.0.0.1:9090".parse().unwrap();
+            let mut i_prot = MessageInputProtocol::new(Box::new(i_prot), Some(peer));
+            i_prot.read_message_begin().unwrap();
+            tracing::info!("handled");
+            i_prot.read_message_begin().unwrap();
+        });
+
+        assert_eq!(
+            recorder.records(),
+            vec![
+                "span thrift.request rpc.method=first rpc.seq_id=1",
+                "record net.peer=127.0.0.1:9090",
+                "event message=handled",
+                "span thrift.request rpc.method=second rpc.seq_id=2",
+                "record net.peer=127.0.0.1:9090",
+            ]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::time::Duration;
+
+/// Receives measurements from a `TServer`.
+///
+/// Register an implementation with `TServer::set_metrics` to export request
+/// counts, latencies, errors and connection counts to a monitoring system.
+/// All methods have empty default implementations, so implementors only
+/// record what they need. Methods are called on the worker threads serving
+/// connections and must be cheap: they run on every request.
+///
+/// With the `metrics` feature, `TMetricsRecorder` forwards all measurements
+/// to the `metrics` crate.
+pub trait TServerMetrics: Send + Sync {
+    /// Called when a worker starts serving a connection.
+    fn connection_opened(&self) {}
+
+    /// Called when a connection has been closed, `duration` after it was
+    /// opened.
+    fn connection_closed(&self, _duration: Duration) {}
+
+    /// Called when the processor has handled a request for `method`.
+    ///
+    /// `latency` is measured from the moment the request header was read to
+    /// the moment the processor returned. `result` is the result returned by
+    /// the processor; generated processors send errors raised by handlers to
+    /// the client as exceptions and return `Ok` for them.
+    fn request_completed(&self, _method: &str, _latency: Duration, _result: &crate::Result<()>) {}
+}
+
+/// `TServerMetrics` that records measurements with the `metrics` crate.
+///
+/// Whichever recorder the application installs with `metrics` receives:
+///
+/// * `thrift_server_connections_total`: counter of connections served
+/// * `thrift_server_active_connections`: gauge of connections open now
+/// * `thrift_server_requests_total`: counter of requests, labelled `method`
+/// * `thrift_server_request_duration_seconds`: histogram of request
+///   latencies, labelled `method`
+/// * `thrift_server_errors_total`: counter of requests for which the
+///   processor returned an error, labelled `method`
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TMetricsRecorder;
+
+#[cfg(feature = "metrics")]
+impl TMetricsRecorder {
+    /// Create a `TMetricsRecorder`.
+    pub fn new() -> TMetricsRecorder {
+        TMetricsRecorder
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl TServerMetrics for TMetricsRecorder {
+    fn connection_opened(&self) {
+        metrics::counter!("thrift_server_connections_total").increment(1);
+        metrics::gauge!("thrift_server_active_connections").increment(1.0);
+    }
+
+    fn connection_closed(&self, _duration: Duration) {
+        metrics::gauge!("thrift_server_active_connections").decrement(1.0);
+    }
+
+    fn request_completed(&self, method: &str, latency: Duration, result: &crate::Result<()>) {
+        let method = method.to_owned();
+        metrics::counter!("thrift_server_requests_total", "method" => method.clone()).increment(1);
+        metrics::histogram!("thrift_server_request_duration_seconds", "method" => method.clone())
+            .record(latency.as_secs_f64());
+        if result.is_err() {
+            metrics::counter!("thrift_server_errors_total", "method" => method).increment(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TInputProtocol, TMessageIdentifier, TMessageType,
+        TOutputProtocol,
+    };
+    use crate::server::{TProcessor, TServer};
+    use crate::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+    struct Echo;
+
+    impl TProcessor for Echo {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            let value = i.read_i32()?;
+            i.read_message_end()?;
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_i32(value)?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl TServerMetrics for Recorder {
+        fn connection_opened(&self) {
+            self.events.lock().unwrap().push("opened".to_owned());
+        }
+
+        fn connection_closed(&self, _duration: Duration) {
+            self.events.lock().unwrap().push("closed".to_owned());
+        }
+
+        fn request_completed(&self, method: &str, _latency: Duration, result: &crate::Result<()>) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", method, result.is_ok()));
+        }
+    }
+
+    fn start(metrics: Arc<dyn TServerMetrics>) -> SocketAddr {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        thread::spawn(move || {
+            let mut server = TServer::new(
+                TBufferedReadTransportFactory::new(),
+                TBinaryInputProtocolFactory::new(),
+                TBufferedWriteTransportFactory::new(),
+                TBinaryOutputProtocolFactory::new(),
+                Echo,
+                1,
+            );
+            server.set_metrics(metrics);
+            server.listen(address)
+        });
+        address
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let mut attempts = 0;
+        loop {
+            match TcpStream::connect(address) {
+                Ok(s) => return s,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("cannot connect to server: {:?}", e),
+            }
+        }
+    }
+
+    fn call(stream: &TcpStream, method: &str) {
+        let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new(method, TMessageType::Call, 1))
+            .unwrap();
+        o_prot.write_i32(1).unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        let mut i_prot = TBinaryInputProtocol::new(stream.try_clone().unwrap(), true);
+        i_prot.read_message_begin().unwrap();
+        assert_eq!(i_prot.read_i32().unwrap(), 1);
+    }
+
+    fn wait_for_events(recorder: &Recorder, count: usize) -> Vec<String> {
+        for _ in 0..250 {
+            let events = recorder.events.lock().unwrap().clone();
+            if events.len() >= count {
+                return events;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("timed out waiting for {} events", count);
+    }
+
+    #[test]
+    fn must_record_requests_and_connections() {
+        let recorder = Arc::new(Recorder::default());
+        let address = start(recorder.clone());
+
+        let stream = connect(address);
+        call(&stream, "first");
+        call(&stream, "second");
+        drop(stream);
+
+        assert_eq!(
+            wait_for_events(&recorder, 4),
+            vec!["opened", "first true", "second true", "closed"]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn must_forward_measurements_to_metrics_crate() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let debugging = DebuggingRecorder::new();
+        let snapshotter = debugging.snapshotter();
+        metrics::with_local_recorder(&debugging, || {
+            let recorder = TMetricsRecorder::new();
+            recorder.connection_opened();
+            recorder.request_completed("ping", Duration::from_millis(5), &Ok(()));
+            recorder.request_completed("ping", Duration::from_millis(7), &Err("failed".into()));
+            recorder.connection_closed(Duration::from_secs(1));
+        });
+
+        let mut values: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let value = match value {
+                    DebugValue::Counter(n) => n as f64,
+                    DebugValue::Gauge(g) => g.into_inner(),
+                    DebugValue::Histogram(h) => h.len() as f64,
+                };
+                (key.key().name().to_owned(), value)
+            })
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            values,
+            vec![
+                ("thrift_server_active_connections".to_owned(), 0.0),
+                ("thrift_server_connections_total".to_owned(), 1.0),
+                ("thrift_server_errors_total".to_owned(), 1.0),
+                ("thrift_server_request_duration_seconds".to_owned(), 2.0),
+                ("thrift_server_requests_total".to_owned(), 2.0),
+            ]
+        );
+    }
+}
@@ -17,14 +17,123 @@
 
 //! Types used to implement a Thrift server.
 
+use std::time::Instant;
+
 use crate::protocol::{TInputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol};
+use crate::transport::TPeerIdentity;
 use crate::{ApplicationError, ApplicationErrorKind};
 
+#[cfg(unix)]
+mod activation;
+mod async_processor;
+mod auth;
+mod bind;
+mod builder;
+mod concurrent;
+mod connection;
+mod context;
+mod drain;
+mod error_map;
+mod events;
+mod health;
+mod message;
+mod metrics;
 mod multiplexed;
+mod oneway;
+mod panic;
+mod rate_limit;
+mod reload;
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+mod reuseport;
+mod thread_pool;
 mod threaded;
+mod timeout;
+mod tracker;
+mod udp;
+mod watermark;
 
+#[cfg(unix)]
+pub use self::activation::systemd_listeners;
+pub use self::async_processor::{
+    TAsyncProcessor, TBlockOnProcessor, TProcessFuture, TSpawnBlockingProcessor,
+};
+pub use self::auth::{TAuthenticator, TCertificateAuthenticator};
+pub use self::bind::TBindTarget;
+pub use self::builder::{TBoxedServer, TServerBuilder};
+pub use self::context::{request_context, with_request_context, TRequestContext};
+pub use self::drain::TDrainHandle;
+pub use self::error_map::{TErrorMapper, TMappedError, TRedactingErrorMapper};
+pub use self::events::{TConnectionContext, TServerEventHandler};
+pub use self::health::{THealthService, THealthStatus};
+#[cfg(feature = "metrics")]
+pub use self::metrics::TMetricsRecorder;
+pub use self::metrics::TServerMetrics;
 pub use self::multiplexed::TMultiplexedProcessor;
+pub use self::panic::THandlerPanic;
+pub use self::rate_limit::TRateLimiter;
+pub use self::reload::TConfigurationHandle;
+pub use self::thread_pool::{TOverloadPolicy, TThreadPoolServer};
 pub use self::threaded::TServer;
+pub use self::tracker::{TInFlightRequest, TRequestTracker};
+pub use self::udp::TUdpServer;
+pub use self::watermark::TWriteWatermarks;
+
+// Server diagnostics are `tracing` events with the `tracing` feature, and
+// `log` records otherwise.
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log::{debug, error, warn};
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error, warn};
+
+/// Return the verified identity of the client whose request is being handled
+/// on the current thread.
+///
+/// `TServer` sets the identity for connections accepted by
+/// `TServer::listen_tls` when the `ServerConfig` requests client
+/// certificates, so handlers can make authorization decisions based on the
+/// mutual TLS identity of the caller. Returns `None` outside of a request
+/// handler, for plaintext connections, and for clients that did not present
+/// a certificate.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::server;
+///
+/// fn handle_call() -> thrift::Result<()> {
+///     match server::peer_identity() {
+///         Some(peer) => println!("called by {} byte certificate", peer.certificate().len()),
+///         None => println!("anonymous caller"),
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn peer_identity() -> Option<TPeerIdentity> {
+    context::with_current(|c| c.and_then(|c| c.peer_identity().cloned()))
+}
+
+/// Return the deadline of the request being handled on the current thread.
+///
+/// `TServer` sets a deadline for every request when it is configured with
+/// `TServer::set_request_timeout`. The server answers a request that is
+/// still running at its deadline with a `TApplicationException`, but it
+/// cannot stop the handler, so long-running handlers should check the
+/// deadline and give up once it has passed. Returns `None` outside of a
+/// request handler and when no request timeout is configured.
+pub fn request_deadline() -> Option<Instant> {
+    context::with_current(|c| c.and_then(|c| c.deadline()))
+}
+
+/// Run `f` with the current request context extended by `deadline`.
+pub(crate) fn with_request_deadline<F, R>(deadline: Option<Instant>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let context = request_context()
+        .unwrap_or_default()
+        .with_deadline(deadline);
+    with_request_context(context, f)
+}
 
 /// Handles incoming Thrift messages and dispatches them to the user-defined
 /// handler functions.
@@ -97,11 +206,24 @@ pub trait TProcessor {
 
 /// Convenience function used in generated `TProcessor` implementations to
 /// return an `ApplicationError` if thrift message processing failed.
+///
+/// Clients do not wait for a reply to a oneway message, so a failed oneway
+/// message is logged rather than answered.
 pub fn handle_process_result(
     msg_ident: &TMessageIdentifier,
     res: crate::Result<()>,
     o_prot: &mut dyn TOutputProtocol,
 ) -> crate::Result<()> {
+    if msg_ident.message_type == TMessageType::OneWay {
+        if let Err(e) = res {
+            warn!(
+                "oneway request {} failed with error {:?}",
+                msg_ident.name, e
+            );
+        }
+        return Ok(());
+    }
+
     if let Err(e) = res {
         let e = match e {
             crate::Error::Application(a) => a,
@@ -15,8 +15,6 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use log::debug;
-
 use std::collections::HashMap;
 use std::convert::Into;
 use std::fmt;
@@ -25,7 +23,7 @@ use std::sync::{Arc, Mutex};
 
 use crate::protocol::{TInputProtocol, TMessageIdentifier, TOutputProtocol, TStoredInputProtocol};
 
-use super::{handle_process_result, TProcessor};
+use super::{debug, handle_process_result, TProcessor};
 
 const MISSING_SEPARATOR_AND_NO_DEFAULT: &str =
     "missing service separator and no default processor set";
@@ -0,0 +1,271 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use threadpool::ThreadPool;
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use crate::protocol::{
+    copy_value, TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TMessageIdentifier,
+    TStoredInputProtocol, TType, MAXIMUM_COPY_DEPTH,
+};
+
+use super::panic::{report_handler_panic, PanicHook};
+use super::{request_context, warn, with_request_context, TProcessor};
+
+/// Process the oneway request identified by `ident` on the current thread.
+///
+/// The processor is given an output protocol that discards everything
+/// written to it, so a misbehaving processor cannot send a reply the client
+/// does not expect.
+pub(super) fn process_oneway<PRC>(
+    processor: &PRC,
+    ident: TMessageIdentifier,
+    i_prot: &mut dyn TInputProtocol,
+) -> crate::Result<()>
+where
+    PRC: TProcessor,
+{
+    let name = ident.name.clone();
+    let mut discard = TBinaryOutputProtocol::new(io::sink(), true);
+    let result = processor.process(&mut TStoredInputProtocol::new(i_prot, ident), &mut discard);
+    match result {
+        Err(crate::Error::Application(e)) => {
+            // the handler failed, but the request was read in full and the
+            // connection can be used for the next one
+            warn!("oneway request {} failed with error {:?}", name, e);
+            Ok(())
+        }
+        other => other,
+    }
+}
+
+/// Read the arguments of the oneway request identified by `ident` from
+/// `i_prot`, and process the request on `pool`.
+///
+/// The connection is free to read the next request as soon as the arguments
+/// have been read. The request context of the current thread is passed on
+/// to the worker that processes the request.
+pub(super) fn queue_oneway<PRC>(
+    pool: &ThreadPool,
+    processor: &Arc<PRC>,
+    ident: TMessageIdentifier,
+    i_prot: &mut dyn TInputProtocol,
+    panic_hook: Option<PanicHook>,
+) -> crate::Result<()>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    let mut args = TBinaryOutputProtocol::new(Vec::new(), true);
+    copy_value(i_prot, &mut args, TType::Struct, MAXIMUM_COPY_DEPTH)?;
+    i_prot.read_message_end()?;
+    let args = args.transport;
+
+    let processor = processor.clone();
+    let context = request_context().unwrap_or_default();
+    pool.execute(move || {
+        let mut i_prot = TBinaryInputProtocol::new(&args[..], true);
+        let peer_addr = context.peer_addr();
+        let message = ident.clone();
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            with_request_context(context, || process_oneway(&*processor, ident, &mut i_prot))
+        }));
+        match served {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("oneway request {} failed with error {:?}", message.name, e),
+            Err(payload) => report_handler_panic(
+                payload,
+                Some(&message),
+                peer_addr,
+                panic_hook.as_ref(),
+                None,
+            ),
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::{
+        TBinaryInputProtocolFactory, TBinaryOutputProtocolFactory, TFieldIdentifier, TMessageType,
+        TOutputProtocol, TStructIdentifier,
+    };
+    use crate::server::{handle_process_result, TServer};
+    use crate::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+    use crate::{ApplicationError, ApplicationErrorKind};
+
+    /// Answers calls with their argument. Oneway requests are recorded, then
+    /// answered as if they were calls and reported as failed.
+    struct Misbehaving {
+        received: Mutex<Sender<i32>>,
+        oneway_delay: Duration,
+    }
+
+    impl TProcessor for Misbehaving {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.read_struct_begin()?;
+            i.read_field_begin()?;
+            let value = i.read_i32()?;
+            i.read_field_end()?;
+            i.read_field_begin()?;
+            i.read_struct_end()?;
+            i.read_message_end()?;
+
+            if ident.message_type == TMessageType::OneWay {
+                thread::sleep(self.oneway_delay);
+                self.received.lock().unwrap().send(value).unwrap();
+            }
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name.clone(),
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_i32(value)?;
+            o.write_message_end()?;
+            o.flush()?;
+            if ident.message_type == TMessageType::OneWay {
+                return Err(crate::Error::Application(ApplicationError::new(
+                    ApplicationErrorKind::Unknown,
+                    "oneway handler failed",
+                )));
+            }
+            Ok(())
+        }
+    }
+
+    fn start(oneway_workers: usize, oneway_delay: Duration) -> (SocketAddr, Receiver<i32>) {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (tx, rx) = channel();
+        let processor = Misbehaving {
+            received: Mutex::new(tx),
+            oneway_delay,
+        };
+        thread::spawn(move || {
+            let mut server = TServer::new(
+                TBufferedReadTransportFactory::new(),
+                TBinaryInputProtocolFactory::new(),
+                TBufferedWriteTransportFactory::new(),
+                TBinaryOutputProtocolFactory::new(),
+                processor,
+                2,
+            );
+            server.set_oneway_workers(oneway_workers);
+            server.listen(address)
+        });
+        (address, rx)
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let mut attempts = 0;
+        loop {
+            match TcpStream::connect(address) {
+                Ok(s) => return s,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("cannot connect to server: {:?}", e),
+            }
+        }
+    }
+
+    fn send(stream: &TcpStream, message_type: TMessageType, value: i32) {
+        let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("send", message_type, value))
+            .unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("send_args"))
+            .unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("value", TType::I32, 1))
+            .unwrap();
+        o_prot.write_i32(value).unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    fn reply(stream: &TcpStream) -> i32 {
+        let mut i_prot = TBinaryInputProtocol::new(stream.try_clone().unwrap(), true);
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Reply);
+        i_prot.read_i32().unwrap()
+    }
+
+    #[test]
+    fn must_not_reply_to_oneway_requests() {
+        let (address, received) = start(1, Duration::from_millis(0));
+        let mut stream = connect(address);
+
+        send(&stream, TMessageType::OneWay, 1);
+        assert_eq!(received.recv_timeout(Duration::from_secs(5)).unwrap(), 1);
+
+        // the failed oneway request neither answered nor closed the connection
+        send(&stream, TMessageType::Call, 2);
+        assert_eq!(reply(&stream), 2);
+
+        stream
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(stream.read(&mut [0u8; 1]).is_err());
+    }
+
+    #[test]
+    fn must_process_oneway_requests_on_oneway_pool() {
+        let (address, received) = start(1, Duration::from_millis(300));
+        let stream = connect(address);
+
+        send(&stream, TMessageType::OneWay, 1);
+        send(&stream, TMessageType::Call, 2);
+        assert_eq!(reply(&stream), 2);
+        assert!(received.try_recv().is_err());
+        assert_eq!(received.recv_timeout(Duration::from_secs(5)).unwrap(), 1);
+    }
+
+    #[test]
+    fn must_not_write_exception_for_failed_oneway() {
+        let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+        let ident = TMessageIdentifier::new("send", TMessageType::OneWay, 1);
+        let failed = Err(crate::Error::Application(ApplicationError::new(
+            ApplicationErrorKind::Unknown,
+            "failed",
+        )));
+        handle_process_result(&ident, failed, &mut o_prot).unwrap();
+        assert!(o_prot.transport.is_empty());
+    }
+}
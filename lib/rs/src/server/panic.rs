@@ -0,0 +1,295 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::any::Any;
+use std::fmt;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use crate::protocol::{TMessageIdentifier, TMessageType, TOutputProtocol};
+use crate::{ApplicationError, ApplicationErrorKind};
+
+use super::{error, handle_process_result};
+
+/// Details of a request handler that panicked, passed to the hook set with
+/// `TServer::set_panic_hook`.
+#[derive(Clone, Debug)]
+pub struct THandlerPanic {
+    method: Option<String>,
+    peer_addr: Option<SocketAddr>,
+    message: String,
+}
+
+impl THandlerPanic {
+    /// Name of the method being handled, if its message header had been
+    /// read.
+    pub fn method(&self) -> Option<&str> {
+        self.method.as_deref()
+    }
+
+    /// Address of the client, if the connection has one.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Message the handler panicked with.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+type Hook = dyn Fn(&THandlerPanic) + Send + Sync;
+
+/// Hook called when a handler panics.
+#[derive(Clone)]
+pub(super) struct PanicHook(pub(super) Arc<Hook>);
+
+impl fmt::Debug for PanicHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PanicHook")
+    }
+}
+
+/// Report a panic caught while handling a request.
+///
+/// The panic is logged and passed to `hook`, and the client is sent an
+/// `InternalError` exception if `message` is a call it waits for a reply to.
+pub(super) fn report_handler_panic(
+    payload: Box<dyn Any + Send>,
+    message: Option<&TMessageIdentifier>,
+    peer_addr: Option<SocketAddr>,
+    hook: Option<&PanicHook>,
+    o_prot: Option<&mut dyn TOutputProtocol>,
+) {
+    let details = THandlerPanic {
+        method: message.map(|m| m.name.clone()),
+        peer_addr,
+        message: panic_message(&*payload),
+    };
+    error!(
+        "handler for {} panicked: {}",
+        details.method().unwrap_or("unknown method"),
+        details.message()
+    );
+    if let Some(PanicHook(hook)) = hook {
+        // a panicking hook must not take the worker down either
+        if panic::catch_unwind(AssertUnwindSafe(|| hook(&details))).is_err() {
+            error!("panic hook panicked");
+        }
+    }
+
+    if let (Some(message), Some(o_prot)) = (message, o_prot) {
+        if message.message_type == TMessageType::Call {
+            let reply = ApplicationError::new(
+                ApplicationErrorKind::InternalError,
+                "request handler panicked",
+            );
+            if let Err(e) = handle_process_result(message, Err(reply.into()), o_prot) {
+                error!("failed to send panic reply with error {:?}", e);
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TFieldIdentifier, TInputProtocol, TStructIdentifier, TType,
+    };
+    use crate::server::{TProcessor, TServer};
+    use crate::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+    /// Answers requests with their argument, and panics on negative ones.
+    struct Fragile;
+
+    impl TProcessor for Fragile {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.read_struct_begin()?;
+            i.read_field_begin()?;
+            let value = i.read_i32()?;
+            i.read_field_end()?;
+            i.read_field_begin()?;
+            i.read_struct_end()?;
+            i.read_message_end()?;
+            if value < 0 {
+                panic!("negative value {}", value);
+            }
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_i32(value)?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    fn start(oneway_workers: usize) -> (SocketAddr, Receiver<THandlerPanic>) {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        thread::spawn(move || {
+            let mut server = TServer::new(
+                TBufferedReadTransportFactory::new(),
+                TBinaryInputProtocolFactory::new(),
+                TBufferedWriteTransportFactory::new(),
+                TBinaryOutputProtocolFactory::new(),
+                Fragile,
+                1,
+            );
+            server.set_oneway_workers(oneway_workers);
+            server.set_panic_hook(move |p| tx.lock().unwrap().send(p.clone()).unwrap());
+            server.listen(address)
+        });
+        (address, rx)
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let mut attempts = 0;
+        loop {
+            match TcpStream::connect(address) {
+                Ok(s) => return s,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("cannot connect to server: {:?}", e),
+            }
+        }
+    }
+
+    fn send(stream: &TcpStream, message_type: TMessageType, value: i32) {
+        let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("check", message_type, 7))
+            .unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("check_args"))
+            .unwrap();
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("value", TType::I32, 1))
+            .unwrap();
+        o_prot.write_i32(value).unwrap();
+        o_prot.write_field_end().unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    fn assert_closed(mut stream: TcpStream) {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn must_answer_panicking_call_and_close_connection() {
+        let (address, panics) = start(0);
+        let stream = connect(address);
+        send(&stream, TMessageType::Call, -1);
+
+        let mut i_prot = TBinaryInputProtocol::new(stream.try_clone().unwrap(), true);
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Exception);
+        assert_eq!(ident.name, "check");
+        assert_eq!(ident.sequence_number, 7);
+        let err = i_prot.read_application_error().unwrap();
+        assert_eq!(err.kind, ApplicationErrorKind::InternalError);
+        assert_closed(stream);
+
+        let details = panics.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(details.method(), Some("check"));
+        assert_eq!(details.message(), "negative value -1");
+        assert!(details.peer_addr().is_some());
+
+        // the only worker thread is still serving
+        let stream = connect(address);
+        send(&stream, TMessageType::Call, 3);
+        let mut i_prot = TBinaryInputProtocol::new(stream, true);
+        assert_eq!(
+            i_prot.read_message_begin().unwrap().message_type,
+            TMessageType::Reply
+        );
+        assert_eq!(i_prot.read_i32().unwrap(), 3);
+    }
+
+    #[test]
+    fn must_report_panicking_queued_oneway_request() {
+        let (address, panics) = start(1);
+        let stream = connect(address);
+        send(&stream, TMessageType::OneWay, -2);
+
+        let details = panics.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(details.method(), Some("check"));
+        assert_eq!(details.message(), "negative value -2");
+
+        // a queued oneway request runs apart from its connection
+        send(&stream, TMessageType::Call, 4);
+        let mut i_prot = TBinaryInputProtocol::new(stream, true);
+        i_prot.read_message_begin().unwrap();
+        assert_eq!(i_prot.read_i32().unwrap(), 4);
+    }
+
+    #[test]
+    fn must_survive_panicking_hook() {
+        let hook = PanicHook(Arc::new(|_: &THandlerPanic| panic!("hook failed")));
+        let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+        let message = TMessageIdentifier::new("check", TMessageType::Call, 1);
+        report_handler_panic(
+            Box::new("handler failed"),
+            Some(&message),
+            None,
+            Some(&hook),
+            Some(&mut o_prot),
+        );
+
+        let mut i_prot = TBinaryInputProtocol::new(&o_prot.transport[..], true);
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Exception);
+    }
+}
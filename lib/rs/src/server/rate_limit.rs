@@ -0,0 +1,411 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::protocol::{TInputProtocol, TMessageIdentifier, TOutputProtocol, TType};
+use crate::{ApplicationError, ApplicationErrorKind};
+
+use super::{handle_process_result, TRequestContext};
+
+/// Number of tracked keys above which idle keys are forgotten.
+const PRUNE_THRESHOLD: usize = 4096;
+
+type KeyFn = dyn Fn(&TRequestContext) -> Option<String> + Send + Sync;
+
+/// Limits the rate of new connections and requests from each client.
+///
+/// Clients are told apart by a key taken from their `TRequestContext`. By
+/// default the key is the IP address of the peer, so every connection from
+/// one host shares its limits; `with_key` replaces it, for example to key
+/// on a subnet or on the TLS identity of the client. Clients without a key,
+/// such as those connected over a Unix domain socket with the default key,
+/// are not limited.
+///
+/// Each limit is a token bucket: a client may send `burst` requests (or open
+/// `burst` connections) at once, and gains one more every `1 / per_second`
+/// seconds. A request over the limit is answered with an
+/// `ApplicationErrorKind::InternalError` exception, and the connection stays
+/// open. A connection over the limit has its first request answered that way
+/// and is then closed.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use thrift::server::TRateLimiter;
+///
+/// // 100 requests a second with bursts of 20, and 5 new connections a
+/// // second, per /24 subnet
+/// let limiter = TRateLimiter::new()
+///     .with_request_rate(100.0, 20)
+///     .with_connection_rate(5.0, 5)
+///     .with_key(|context| match context.peer_addr()?.ip() {
+///         std::net::IpAddr::V4(ip) => {
+///             let [a, b, c, _] = ip.octets();
+///             Some(format!("{}.{}.{}", a, b, c))
+///         }
+///         ip => Some(ip.to_string()),
+///     });
+/// let limiter = Arc::new(limiter);
+/// ```
+pub struct TRateLimiter {
+    request_rate: Option<Rate>,
+    connection_rate: Option<Rate>,
+    key: Arc<KeyFn>,
+    clients: Mutex<Clients>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Rate {
+    per_second: f64,
+    burst: f64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate, now: Instant) -> Bucket {
+        Bucket {
+            tokens: rate.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst);
+        self.updated = now;
+    }
+
+    fn take(&mut self, rate: Rate, now: Instant) -> bool {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_full(&self, rate: Rate, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * rate.per_second >= rate.burst
+    }
+}
+
+#[derive(Debug, Default)]
+struct Clients {
+    buckets: HashMap<String, (Option<Bucket>, Option<Bucket>)>,
+    prune_at: usize,
+}
+
+/// Which of a client's limits applies.
+#[derive(Clone, Copy)]
+enum Limit {
+    Requests,
+    Connections,
+}
+
+impl TRateLimiter {
+    /// Create a `TRateLimiter` that does not limit anything yet.
+    pub fn new() -> TRateLimiter {
+        TRateLimiter {
+            request_rate: None,
+            connection_rate: None,
+            key: Arc::new(|context: &TRequestContext| {
+                context.peer_addr().map(|a| a.ip().to_string())
+            }),
+            clients: Mutex::new(Clients {
+                buckets: HashMap::new(),
+                prune_at: PRUNE_THRESHOLD,
+            }),
+        }
+    }
+
+    /// Allow each client `per_second` requests a second, in bursts of up to
+    /// `burst` requests.
+    pub fn with_request_rate(mut self, per_second: f64, burst: u32) -> TRateLimiter {
+        self.request_rate = Some(Rate {
+            per_second,
+            burst: f64::from(burst),
+        });
+        self
+    }
+
+    /// Allow each client to open `per_second` connections a second, in
+    /// bursts of up to `burst` connections.
+    pub fn with_connection_rate(mut self, per_second: f64, burst: u32) -> TRateLimiter {
+        self.connection_rate = Some(Rate {
+            per_second,
+            burst: f64::from(burst),
+        });
+        self
+    }
+
+    /// Tell clients apart by the key `key` returns for their request
+    /// context. Clients for which it returns `None` are not limited.
+    ///
+    /// Connection limits are checked before the TLS handshake, so only the
+    /// peer address of the context is set at that point.
+    pub fn with_key<F>(mut self, key: F) -> TRateLimiter
+    where
+        F: Fn(&TRequestContext) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// Return `true` if the client with `context` may send another request,
+    /// and count the request against its limit if so.
+    pub fn allow_request(&self, context: &TRequestContext) -> bool {
+        self.allow(context, Limit::Requests)
+    }
+
+    /// Return `true` if the client with `context` may open another
+    /// connection, and count the connection against its limit if so.
+    pub fn allow_connection(&self, context: &TRequestContext) -> bool {
+        self.allow(context, Limit::Connections)
+    }
+
+    /// Return `true` if requests are limited at all.
+    pub(super) fn limits_requests(&self) -> bool {
+        self.request_rate.is_some()
+    }
+
+    fn allow(&self, context: &TRequestContext, limit: Limit) -> bool {
+        let rate = match limit {
+            Limit::Requests => self.request_rate,
+            Limit::Connections => self.connection_rate,
+        };
+        let (rate, key) = match (rate, (self.key)(context)) {
+            (Some(rate), Some(key)) => (rate, key),
+            _ => return true,
+        };
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.buckets.len() >= clients.prune_at {
+            self.prune(&mut clients, now);
+        }
+        let (requests, connections) = clients.buckets.entry(key).or_default();
+        let bucket = match limit {
+            Limit::Requests => requests,
+            Limit::Connections => connections,
+        };
+        bucket
+            .get_or_insert_with(|| Bucket::new(rate, now))
+            .take(rate, now)
+    }
+
+    /// Forget clients whose buckets have filled up again, as they behave
+    /// exactly like clients that were never seen.
+    fn prune(&self, clients: &mut Clients, now: Instant) {
+        let (request_rate, connection_rate) = (self.request_rate, self.connection_rate);
+        clients.buckets.retain(|_, (requests, connections)| {
+            let full = |bucket: &Option<Bucket>, rate: Option<Rate>| match (bucket, rate) {
+                (Some(bucket), Some(rate)) => bucket.is_full(rate, now),
+                _ => true,
+            };
+            !(full(requests, request_rate) && full(connections, connection_rate))
+        });
+        clients.prune_at = (clients.buckets.len() * 2).max(PRUNE_THRESHOLD);
+    }
+}
+
+impl Default for TRateLimiter {
+    fn default() -> Self {
+        TRateLimiter::new()
+    }
+}
+
+impl fmt::Debug for TRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TRateLimiter")
+            .field("request_rate", &self.request_rate)
+            .field("connection_rate", &self.connection_rate)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Skip the arguments of the request `ident` and answer it with a rate limit
+/// exception if the client waits for a reply.
+pub(super) fn reject_throttled(
+    ident: &TMessageIdentifier,
+    i_prot: &mut dyn TInputProtocol,
+    o_prot: &mut dyn TOutputProtocol,
+    message: &str,
+) -> crate::Result<()> {
+    i_prot.skip(TType::Struct)?;
+    i_prot.read_message_end()?;
+    let throttled = ApplicationError::new(ApplicationErrorKind::InternalError, message);
+    handle_process_result(ident, Err(throttled.into()), o_prot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TMessageType, TStructIdentifier,
+    };
+    use crate::server::{TProcessor, TServerBuilder};
+
+    fn client(address: &str) -> TRequestContext {
+        TRequestContext::new().with_peer_addr(Some(address.parse::<SocketAddr>().unwrap()))
+    }
+
+    #[test]
+    fn must_limit_each_client_separately() {
+        let limiter = TRateLimiter::new().with_request_rate(0.001, 2);
+        let (a, b) = (client("10.0.0.1:1000"), client("10.0.0.2:1000"));
+
+        assert!(limiter.allow_request(&a));
+        assert!(limiter.allow_request(&client("10.0.0.1:2000")));
+        assert!(!limiter.allow_request(&a));
+        assert!(limiter.allow_request(&b));
+        // connections are not limited
+        assert!(limiter.allow_connection(&a));
+    }
+
+    #[test]
+    fn must_refill_over_time() {
+        let limiter = TRateLimiter::new().with_connection_rate(50.0, 1);
+        let a = client("10.0.0.1:1000");
+
+        assert!(limiter.allow_connection(&a));
+        assert!(!limiter.allow_connection(&a));
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(limiter.allow_connection(&a));
+    }
+
+    #[test]
+    fn must_not_limit_clients_without_key() {
+        let limiter = TRateLimiter::new()
+            .with_request_rate(0.001, 1)
+            .with_key(|_| None);
+        let a = client("10.0.0.1:1000");
+        assert!(limiter.allow_request(&a));
+        assert!(limiter.allow_request(&a));
+        assert!(limiter.allow_request(&TRequestContext::new()));
+    }
+
+    #[test]
+    fn must_forget_idle_clients() {
+        let limiter = TRateLimiter::new().with_request_rate(1000.0, 1);
+        for i in 0..PRUNE_THRESHOLD {
+            let address = format!("10.{}.{}.1:1000", i / 256, i % 256);
+            assert!(limiter.allow_request(&client(&address)));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(limiter.allow_request(&client("10.255.0.1:1000")));
+        assert_eq!(limiter.clients.lock().unwrap().buckets.len(), 1);
+    }
+
+    /// Answers each call with an empty reply.
+    struct Empty;
+
+    impl TProcessor for Empty {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.skip(TType::Struct)?;
+            i.read_message_end()?;
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    fn start(limiter: TRateLimiter) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = TServerBuilder::new(Empty)
+            .workers(2)
+            .rate_limiter(Arc::new(limiter))
+            .build();
+        thread::spawn(move || server.listen_on(listener));
+        address
+    }
+
+    /// Send a call and return the type of its reply.
+    fn call(stream: &TcpStream) -> TMessageType {
+        let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("call", TMessageType::Call, 1))
+            .unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("call_args"))
+            .unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        let mut i_prot = TBinaryInputProtocol::new(stream.try_clone().unwrap(), true);
+        let ident = i_prot.read_message_begin().unwrap();
+        if ident.message_type == TMessageType::Exception {
+            let e = i_prot.read_application_error().unwrap();
+            assert_eq!(e.kind, ApplicationErrorKind::InternalError);
+        }
+        ident.message_type
+    }
+
+    #[test]
+    fn must_answer_requests_over_limit_with_exception() {
+        let address = start(TRateLimiter::new().with_request_rate(0.001, 1));
+        let stream = TcpStream::connect(address).unwrap();
+
+        assert_eq!(call(&stream), TMessageType::Reply);
+        assert_eq!(call(&stream), TMessageType::Exception);
+        // the connection stays usable
+        assert_eq!(call(&stream), TMessageType::Exception);
+    }
+
+    #[test]
+    fn must_close_connections_over_limit() {
+        let address = start(TRateLimiter::new().with_connection_rate(0.001, 1));
+        let first = TcpStream::connect(address).unwrap();
+        assert_eq!(call(&first), TMessageType::Reply);
+
+        let mut second = TcpStream::connect(address).unwrap();
+        assert_eq!(call(&second), TMessageType::Exception);
+        second
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(second.read(&mut [0u8; 1]).unwrap(), 0);
+        assert_eq!(call(&first), TMessageType::Reply);
+    }
+}
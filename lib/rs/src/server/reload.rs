@@ -0,0 +1,253 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::protocol::TInputProtocol;
+use crate::TConfiguration;
+
+/// Shared, replaceable `TConfiguration` for a running server.
+///
+/// Register a handle with `TServer::set_configuration_handle` and keep a
+/// clone of it. Every `set` replaces the configuration for the whole server
+/// at once: connections opened afterwards use it from their first request,
+/// and connections already open from their next one, without being closed.
+/// This lets operators tighten the message, string and container size and
+/// recursion limits while the server is under attack, and relax them
+/// again later.
+///
+/// Only the limits enforced by input protocols are replaced. Limits applied
+/// by transports, such as the maximum frame size of a framed transport, keep
+/// the value they were created with.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::server::TConfigurationHandle;
+/// use thrift::TConfiguration;
+///
+/// let limits = TConfigurationHandle::new(TConfiguration::default());
+/// // server.set_configuration_handle(limits.clone());
+///
+/// // later, from an admin endpoint or signal handler
+/// limits.set(
+///     TConfiguration::builder()
+///         .max_string_size(Some(64 * 1024))
+///         .max_container_size(Some(10_000))
+///         .build()
+///         .unwrap(),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct TConfigurationHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    // bumped after every change, so connections can tell cheaply whether
+    // they are up to date
+    generation: AtomicU64,
+    current: RwLock<(u64, Arc<TConfiguration>)>,
+}
+
+impl TConfigurationHandle {
+    /// Create a `TConfigurationHandle` holding `config`.
+    pub fn new(config: TConfiguration) -> TConfigurationHandle {
+        TConfigurationHandle {
+            shared: Arc::new(Shared {
+                generation: AtomicU64::new(1),
+                current: RwLock::new((1, Arc::new(config))),
+            }),
+        }
+    }
+
+    /// Return the current configuration.
+    pub fn get(&self) -> TConfiguration {
+        (*self.current().1).clone()
+    }
+
+    /// Replace the configuration of every connection with `config`.
+    pub fn set(&self, config: TConfiguration) {
+        let mut current = self
+            .shared
+            .current
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        let generation = current.0 + 1;
+        *current = (generation, Arc::new(config));
+        self.shared.generation.store(generation, Ordering::Release);
+    }
+
+    /// Apply the current configuration to `i_prot` if it has changed since
+    /// `applied`, the generation last applied to it, or 0 for none.
+    pub(super) fn refresh(&self, applied: &mut u64, i_prot: &mut dyn TInputProtocol) {
+        if self.shared.generation.load(Ordering::Acquire) == *applied {
+            return;
+        }
+        let (generation, config) = self.current();
+        i_prot.set_configuration(&config);
+        *applied = generation;
+    }
+
+    fn current(&self) -> (u64, Arc<TConfiguration>) {
+        let current = self
+            .shared
+            .current
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        (current.0, current.1.clone())
+    }
+}
+
+impl Default for TConfigurationHandle {
+    fn default() -> Self {
+        TConfigurationHandle::new(TConfiguration::default())
+    }
+}
+
+impl fmt::Debug for TConfigurationHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TConfigurationHandle")
+            .field(&self.current().1)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TFieldIdentifier, TMessageIdentifier,
+        TMessageType, TOutputProtocol, TStructIdentifier, TType,
+    };
+    use crate::server::{TProcessor, TServerBuilder};
+
+    fn limited_strings(limit: usize) -> TConfiguration {
+        TConfiguration::builder()
+            .max_string_size(Some(limit))
+            .build()
+            .unwrap()
+    }
+
+    fn encoded_string(s: &str) -> Vec<u8> {
+        let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+        o_prot.write_string(s).unwrap();
+        o_prot.transport
+    }
+
+    #[test]
+    fn must_apply_each_change_once() {
+        let handle = TConfigurationHandle::new(TConfiguration::default());
+        let buf = [
+            encoded_string("twelve bytes"),
+            encoded_string("twelve bytes"),
+        ]
+        .concat();
+        let mut i_prot = TBinaryInputProtocol::new(&buf[..], true);
+        let mut applied = 0;
+
+        handle.refresh(&mut applied, &mut i_prot);
+        assert_eq!(i_prot.read_string().unwrap(), "twelve bytes");
+
+        handle.set(limited_strings(4));
+        assert_eq!(handle.get().max_string_size(), Some(4));
+        let seen = applied;
+        handle.refresh(&mut applied, &mut i_prot);
+        assert_ne!(applied, seen);
+        assert!(i_prot.read_string().is_err());
+    }
+
+    /// Replies to each call with the length of its string argument.
+    struct Length;
+
+    impl TProcessor for Length {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.read_struct_begin()?;
+            i.read_field_begin()?;
+            let s = i.read_string()?;
+            i.read_field_end()?;
+            i.read_field_begin()?;
+            i.read_struct_end()?;
+            i.read_message_end()?;
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_i32(s.len() as i32)?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    fn call(stream: &TcpStream, arg: &str) -> crate::Result<i32> {
+        let mut o_prot = TBinaryOutputProtocol::new(Vec::new(), true);
+        o_prot.write_message_begin(&TMessageIdentifier::new("length", TMessageType::Call, 1))?;
+        o_prot.write_struct_begin(&TStructIdentifier::new("length_args"))?;
+        o_prot.write_field_begin(&TFieldIdentifier::new("arg", TType::String, 1))?;
+        o_prot.write_string(arg)?;
+        o_prot.write_field_end()?;
+        o_prot.write_field_stop()?;
+        o_prot.write_struct_end()?;
+        o_prot.write_message_end()?;
+        std::io::Write::write_all(&mut &*stream, &o_prot.transport)?;
+
+        let mut i_prot = TBinaryInputProtocol::new(stream, true);
+        i_prot.read_message_begin()?;
+        i_prot.read_i32()
+    }
+
+    #[test]
+    fn must_change_limits_of_open_connections() {
+        let handle = TConfigurationHandle::new(TConfiguration::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = TServerBuilder::new(Length)
+            .workers(2)
+            .configuration_handle(handle.clone())
+            .build();
+        thread::spawn(move || server.listen_on(listener));
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(call(&stream, "a long argument").unwrap(), 15);
+
+        handle.set(limited_strings(8));
+        assert_eq!(call(&stream, "short").unwrap(), 5);
+        assert!(call(&stream, "a long argument").is_err());
+
+        handle.set(TConfiguration::default());
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(call(&stream, "a long argument").unwrap(), 15);
+    }
+}
@@ -0,0 +1,144 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::transport::TTcpOptions;
+
+use super::threaded::prepare_stream;
+use super::warn;
+use super::TDrainHandle;
+
+/// Length of each socket's queue of connections waiting to be accepted.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Bind `num_acceptors` `SO_REUSEPORT` sockets to `listen_address` and
+/// accept connections from each on its own thread.
+///
+/// Accepted connections get `tcp_options` and `idle_timeout` on the
+/// acceptor thread and are then sent to the returned receiver. An acceptor
+/// stops once the receiver has been dropped. If `drain` is given, it wakes
+/// one of the acceptors when the server is drained.
+pub(super) fn spawn_acceptors<A: ToSocketAddrs>(
+    listen_address: A,
+    num_acceptors: usize,
+    tcp_options: TTcpOptions,
+    idle_timeout: Option<Duration>,
+    drain: Option<&TDrainHandle>,
+) -> io::Result<Receiver<TcpStream>> {
+    if num_acceptors == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one acceptor is required",
+        ));
+    }
+    let mut address = listen_address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to listen on"))?;
+
+    // bind every socket before accepting on any, so a failure is reported
+    // to the caller rather than leaving a partial set of acceptors running
+    let mut listeners = Vec::with_capacity(num_acceptors);
+    for _ in 0..num_acceptors {
+        let listener = bind_reuseport(address)?;
+        address = listener.local_addr()?;
+        listeners.push(listener);
+    }
+    if let Some(drain) = drain {
+        drain.add_listener(SockRef::from(&listeners[0]));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    for (i, listener) in listeners.into_iter().enumerate() {
+        let sender = sender.clone();
+        thread::Builder::new()
+            .name(format!("Thrift acceptor {}", i))
+            .spawn(move || accept(listener, sender, tcp_options, idle_timeout))?;
+    }
+    Ok(receiver)
+}
+
+fn bind_reuseport(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+}
+
+fn accept(
+    listener: TcpListener,
+    sender: Sender<TcpStream>,
+    tcp_options: TTcpOptions,
+    idle_timeout: Option<Duration>,
+) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                prepare_stream(&stream, &tcp_options, idle_timeout);
+                if sender.send(stream).is_err() {
+                    return; // the server stopped serving
+                }
+            }
+            Err(e) => {
+                warn!("failed to accept remote connection with error {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_accept_on_every_socket_bound_to_one_address() {
+        // only possible if SO_REUSEPORT is set on all of them
+        let first = bind_reuseport("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = first.local_addr().unwrap();
+        let streams = spawn_acceptors(address, 3, TTcpOptions::default(), None, None).unwrap();
+        drop(first);
+
+        let clients: Vec<TcpStream> = (0..8)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        for client in &clients {
+            let accepted = streams.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(
+                accepted.local_addr().unwrap().port(),
+                client.peer_addr().unwrap().port()
+            );
+        }
+    }
+
+    #[test]
+    fn must_require_an_acceptor() {
+        assert!(spawn_acceptors("127.0.0.1:0", 0, TTcpOptions::default(), None, None).is_err());
+    }
+}
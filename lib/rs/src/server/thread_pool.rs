@@ -0,0 +1,452 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::protocol::{
+    TInputProtocol, TInputProtocolFactory, TMessageType, TOutputProtocol, TOutputProtocolFactory,
+};
+use crate::transport::{
+    TIoChannel, TReadTransportFactory, TTcpChannel, TTcpOptions, TWriteTransportFactory,
+};
+use crate::{ApplicationError, ApplicationErrorKind};
+
+use super::connection::{handle_incoming_connection, ConnectionOptions};
+use super::{handle_process_result, warn, TProcessor, TRequestContext};
+
+type Connection = (
+    Box<dyn TInputProtocol + Send>,
+    Box<dyn TOutputProtocol + Send>,
+    Option<SocketAddr>,
+);
+
+/// What a `TThreadPoolServer` does with a connection accepted while every
+/// worker is busy and the pending-connection queue is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TOverloadPolicy {
+    /// Stop accepting connections until a worker takes a queued connection
+    /// off the queue. New clients wait in the listen backlog.
+    Block,
+    /// Read the first message from the connection, answer it with an
+    /// `ApplicationErrorKind::InternalError` exception and close the
+    /// connection.
+    Reject,
+}
+
+/// Blocking Thrift server with a fixed-size worker pool and a bounded queue
+/// of pending connections.
+///
+/// A `TThreadPoolServer` starts `num_workers` worker threads when it begins
+/// listening. Each accepted connection is served by one worker until the
+/// client disconnects. Connections accepted while every worker is busy wait
+/// in a queue of at most `max_pending` connections. Once the queue is full
+/// the server applies its `TOverloadPolicy`. By default it blocks. With
+/// `TOverloadPolicy::Reject` it fails the first request on the new
+/// connection with a `TApplicationException` instead. This matches the
+/// `TThreadPoolServer` of the other Thrift libraries.
+///
+/// Unlike `TServer`, whose pending connections are unbounded, a
+/// `TThreadPoolServer` never holds more than `num_workers + max_pending`
+/// connections open.
+///
+/// A worker whose handler panics logs the panic, drops the connection and
+/// goes on serving other connections.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocolFactory, TBinaryOutputProtocolFactory};
+/// use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// use thrift::server::{TOverloadPolicy, TProcessor, TThreadPoolServer};
+/// use thrift::transport::{TFramedReadTransportFactory, TFramedWriteTransportFactory};
+///
+/// struct SimpleServiceSyncProcessor;
+/// impl TProcessor for SimpleServiceSyncProcessor {
+///     fn process(&self, i: &mut dyn TInputProtocol, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+///         unimplemented!();
+///     }
+/// }
+///
+/// let mut server = TThreadPoolServer::new(
+///     TFramedReadTransportFactory::new(),
+///     TBinaryInputProtocolFactory::new(),
+///     TFramedWriteTransportFactory::new(),
+///     TBinaryOutputProtocolFactory::new(),
+///     SimpleServiceSyncProcessor,
+///     16,  // workers
+///     64,  // pending connections
+/// );
+/// server.set_overload_policy(TOverloadPolicy::Reject);
+///
+/// match server.listen("127.0.0.1:9090") {
+///   Ok(_)  => println!("listen completed"),
+///   Err(e) => println!("listen failed with error {:?}", e),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TThreadPoolServer<PRC, RTF, IPF, WTF, OPF>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+    RTF: TReadTransportFactory + 'static,
+    IPF: TInputProtocolFactory + 'static,
+    WTF: TWriteTransportFactory + 'static,
+    OPF: TOutputProtocolFactory + 'static,
+{
+    r_trans_factory: RTF,
+    i_proto_factory: IPF,
+    w_trans_factory: WTF,
+    o_proto_factory: OPF,
+    processor: Arc<PRC>,
+    num_workers: usize,
+    max_pending: usize,
+    overload_policy: TOverloadPolicy,
+    reject_timeout: Duration,
+    tcp_options: TTcpOptions,
+}
+
+impl<PRC, RTF, IPF, WTF, OPF> TThreadPoolServer<PRC, RTF, IPF, WTF, OPF>
+where
+    PRC: TProcessor + Send + Sync + 'static,
+    RTF: TReadTransportFactory + 'static,
+    IPF: TInputProtocolFactory + 'static,
+    WTF: TWriteTransportFactory + 'static,
+    OPF: TOutputProtocolFactory + 'static,
+{
+    /// Default time allowed for an overloaded client to send its first
+    /// message before the connection is closed without a reply.
+    pub const DEFAULT_REJECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Create a `TThreadPoolServer` with `num_workers` worker threads and
+    /// room for `max_pending` connections waiting for a worker.
+    ///
+    /// The factories are used as by `TServer::new`.
+    ///
+    /// # Panics
+    ///
+    /// If `num_workers` is `0`.
+    pub fn new(
+        read_transport_factory: RTF,
+        input_protocol_factory: IPF,
+        write_transport_factory: WTF,
+        output_protocol_factory: OPF,
+        processor: PRC,
+        num_workers: usize,
+        max_pending: usize,
+    ) -> TThreadPoolServer<PRC, RTF, IPF, WTF, OPF> {
+        assert!(
+            num_workers > 0,
+            "a thread pool server needs at least one worker"
+        );
+        TThreadPoolServer {
+            r_trans_factory: read_transport_factory,
+            i_proto_factory: input_protocol_factory,
+            w_trans_factory: write_transport_factory,
+            o_proto_factory: output_protocol_factory,
+            processor: Arc::new(processor),
+            num_workers,
+            max_pending,
+            overload_policy: TOverloadPolicy::Block,
+            reject_timeout: Self::DEFAULT_REJECT_TIMEOUT,
+            tcp_options: TTcpOptions::default(),
+        }
+    }
+
+    /// Set what happens to connections accepted while the pending queue is
+    /// full. Defaults to `TOverloadPolicy::Block`.
+    pub fn set_overload_policy(&mut self, policy: TOverloadPolicy) {
+        self.overload_policy = policy;
+    }
+
+    /// Set how long a rejected client is given to send the request that is
+    /// answered with the overload exception.
+    ///
+    /// Rejections are handled on the accepting thread, so keep this short.
+    /// Defaults to `DEFAULT_REJECT_TIMEOUT`.
+    pub fn set_reject_timeout(&mut self, timeout: Duration) {
+        self.reject_timeout = timeout;
+    }
+
+    /// Set the socket options applied to every accepted TCP connection.
+    ///
+    /// By default only `TCP_NODELAY` is set.
+    pub fn set_tcp_options(&mut self, options: TTcpOptions) {
+        self.tcp_options = options;
+    }
+
+    /// Listen for incoming connections on `listen_address`.
+    ///
+    /// Starts the worker threads and then accepts connections until an
+    /// unrecoverable error occurs. The workers exit once they have finished
+    /// serving their connections after `listen` returns.
+    pub fn listen<A: ToSocketAddrs>(&mut self, listen_address: A) -> crate::Result<()> {
+        let listener = TcpListener::bind(listen_address)?;
+        let queue = self.start_workers()?;
+        for stream in listener.incoming() {
+            match stream {
+                Ok(s) => {
+                    if let Err(e) = self.tcp_options.apply(&s) {
+                        warn!("failed to set socket options with error {:?}", e);
+                    }
+                    self.dispatch(s, &queue)?;
+                }
+                Err(e) => {
+                    warn!("failed to accept remote connection with error {:?}", e);
+                }
+            }
+        }
+
+        Err(crate::Error::Application(ApplicationError {
+            kind: ApplicationErrorKind::Unknown,
+            message: "aborted listen loop".into(),
+        }))
+    }
+
+    fn start_workers(&self) -> crate::Result<SyncSender<Connection>> {
+        let (sender, receiver) = mpsc::sync_channel::<Connection>(self.max_pending);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for n in 0..self.num_workers {
+            let receiver = receiver.clone();
+            let processor = self.processor.clone();
+            thread::Builder::new()
+                .name(format!("Thrift thread pool worker {}", n))
+                .spawn(move || serve_queued_connections(&receiver, processor))?;
+        }
+        Ok(sender)
+    }
+
+    fn dispatch(&mut self, stream: TcpStream, queue: &SyncSender<Connection>) -> crate::Result<()> {
+        let control = stream.try_clone()?;
+        let peer_addr = stream.peer_addr().ok();
+        let (r_chan, w_chan) = TTcpChannel::with_stream(stream).split()?;
+        let r_tran = self.r_trans_factory.create(Box::new(r_chan));
+        let w_tran = self.w_trans_factory.create(Box::new(w_chan));
+        let connection = (
+            self.i_proto_factory.create(r_tran),
+            self.o_proto_factory.create(w_tran),
+            peer_addr,
+        );
+
+        let rejected = match self.overload_policy {
+            TOverloadPolicy::Block => queue
+                .send(connection)
+                .map_err(|e| TrySendError::Disconnected(e.0)),
+            TOverloadPolicy::Reject => queue.try_send(connection),
+        };
+        match rejected {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full((mut i_prot, mut o_prot, _))) => {
+                warn!("rejecting connection: all workers are busy and the queue is full");
+                reject(&control, self.reject_timeout, &mut *i_prot, &mut *o_prot);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(crate::Error::Application(ApplicationError {
+                    kind: ApplicationErrorKind::Unknown,
+                    message: "thread pool workers exited".into(),
+                }))
+            }
+        }
+    }
+}
+
+fn serve_queued_connections<PRC>(queue: &Mutex<Receiver<Connection>>, processor: Arc<PRC>)
+where
+    PRC: TProcessor + Send + Sync + 'static,
+{
+    loop {
+        let next = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        let (i_prot, o_prot, peer_addr) = match next {
+            Ok(connection) => connection,
+            Err(_) => return, // the server stopped listening
+        };
+        let processor = processor.clone();
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            handle_incoming_connection(
+                processor,
+                i_prot,
+                o_prot,
+                ConnectionOptions::default(),
+                TRequestContext::new().with_peer_addr(peer_addr),
+            )
+        }));
+        if served.is_err() {
+            warn!("handler panicked; connection closed");
+        }
+    }
+}
+
+/// Answer the first request on an overloaded connection with an exception,
+/// giving the client at most `timeout` to send it, and close the connection.
+fn reject(
+    control: &TcpStream,
+    timeout: Duration,
+    i_prot: &mut dyn TInputProtocol,
+    o_prot: &mut dyn TOutputProtocol,
+) {
+    let _ = control.set_read_timeout(Some(timeout));
+    let _ = control.set_write_timeout(Some(timeout));
+    if let Ok(ident) = i_prot.read_message_begin() {
+        if ident.message_type == TMessageType::Call {
+            let overloaded = crate::Error::Application(ApplicationError::new(
+                ApplicationErrorKind::InternalError,
+                "server overloaded",
+            ));
+            if let Err(e) = handle_process_result(&ident, Err(overloaded), o_prot) {
+                warn!("failed to send overload exception with error {:?}", e);
+            }
+        }
+    }
+    let _ = control.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TMessageIdentifier,
+    };
+    use crate::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+    /// Replies to each call once `gate` permits it, after reporting on
+    /// `started` that the call is being handled.
+    struct Gated {
+        started: Mutex<Sender<()>>,
+        gate: Mutex<Receiver<()>>,
+    }
+
+    impl TProcessor for Gated {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.read_message_end()?;
+            self.started.lock().unwrap().send(()).unwrap();
+            self.gate.lock().unwrap().recv().unwrap();
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    fn start(policy: TOverloadPolicy) -> (std::net::SocketAddr, Receiver<()>, Sender<()>) {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel();
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        thread::spawn(move || {
+            let mut server = TThreadPoolServer::new(
+                TBufferedReadTransportFactory::new(),
+                TBinaryInputProtocolFactory::new(),
+                TBufferedWriteTransportFactory::new(),
+                TBinaryOutputProtocolFactory::new(),
+                Gated {
+                    started: Mutex::new(started_tx),
+                    gate: Mutex::new(gate_rx),
+                },
+                1,
+                0,
+            );
+            server.set_overload_policy(policy);
+            server.listen(address)
+        });
+        (address, started_rx, gate_tx)
+    }
+
+    fn call(address: std::net::SocketAddr, seq: i32) -> (TcpStream, TcpStream) {
+        let mut attempts = 0;
+        let stream = loop {
+            match TcpStream::connect(address) {
+                Ok(s) => break s,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("cannot connect to server: {:?}", e),
+            }
+        };
+        let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("ping", TMessageType::Call, seq))
+            .unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+        (stream.try_clone().unwrap(), stream)
+    }
+
+    fn reply(stream: TcpStream) -> crate::Result<TMessageIdentifier> {
+        let mut i_prot = TBinaryInputProtocol::new(stream, true);
+        let ident = i_prot.read_message_begin()?;
+        if ident.message_type == TMessageType::Exception {
+            return Err(crate::Error::read_application_error_from_in_protocol(&mut i_prot)?.into());
+        }
+        Ok(ident)
+    }
+
+    #[test]
+    fn must_reject_connections_beyond_queue_with_application_exception() {
+        let (address, started, gate) = start(TOverloadPolicy::Reject);
+
+        let (busy, _busy_keep) = call(address, 1);
+        started.recv().unwrap();
+
+        // the only worker is busy and there is no room in the queue
+        let (rejected, _rejected_keep) = call(address, 2);
+        match reply(rejected) {
+            Err(crate::Error::Application(e)) => {
+                assert_eq!(e.kind, ApplicationErrorKind::InternalError);
+                assert_eq!(e.message, "server overloaded");
+            }
+            other => panic!("expected overload exception, got {:?}", other),
+        }
+
+        gate.send(()).unwrap();
+        assert_eq!(reply(busy).unwrap().sequence_number, 1);
+    }
+
+    #[test]
+    fn must_hold_connections_beyond_queue_when_blocking() {
+        let (address, started, gate) = start(TOverloadPolicy::Block);
+
+        let (first, first_keep) = call(address, 1);
+        started.recv().unwrap();
+        let (second, _second_keep) = call(address, 2);
+
+        gate.send(()).unwrap();
+        assert_eq!(reply(first).unwrap().sequence_number, 1);
+        drop(first_keep);
+
+        // the second connection is served once the worker is free
+        started.recv().unwrap();
+        gate.send(()).unwrap();
+        assert_eq!(reply(second).unwrap().sequence_number, 2);
+    }
+}
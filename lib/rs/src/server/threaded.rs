@@ -15,12 +15,16 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use log::warn;
+use socket2::SockRef;
 
-use std::net::{TcpListener, ToSocketAddrs};
+use std::fmt;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 use std::sync::Arc;
+use std::time::Duration;
 use threadpool::ThreadPool;
 
+#[cfg(feature = "tls-native")]
+use native_tls::TlsAcceptor;
 #[cfg(feature = "rustls")]
 use rustls::ServerConfig;
 
@@ -31,14 +35,36 @@ use std::path::Path;
 
 use crate::protocol::{
     TInputProtocol, TInputProtocolFactory, TOutputProtocol, TOutputProtocolFactory,
+    TProtocolDetector, TProtocolSelection,
 };
+#[cfg(feature = "tls-native")]
+use crate::transport::TNativeTlsServerChannel;
 #[cfg(feature = "rustls")]
 use crate::transport::TTlsServerChannel;
-use crate::transport::{TIoChannel, TReadTransportFactory, TTcpChannel, TWriteTransportFactory};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::transport::TUringChannel;
+#[cfg(feature = "websocket")]
+use crate::transport::TWebSocketServerChannel;
+use crate::transport::{
+    TInstrumentedChannel, TIoChannel, TReadTransportFactory, TTcpChannel, TTcpOptions,
+    TTransportObserver, TWriteTransport, TWriteTransportFactory,
+};
 use crate::{ApplicationError, ApplicationErrorKind};
 
-use super::TProcessor;
-use crate::TransportErrorKind;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use super::bind::bind_abstract;
+use super::concurrent::Concurrency;
+use super::connection::{handle_incoming_connection, ConnectionOptions};
+use super::drain::Closer;
+use super::panic::PanicHook;
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+use super::reuseport::spawn_acceptors;
+use super::timeout::{RequestDeadline, RequestTimeout, RequestTimer};
+use super::{
+    warn, TAuthenticator, TBindTarget, TConfigurationHandle, TDrainHandle, TErrorMapper,
+    THandlerPanic, TProcessor, TRateLimiter, TRequestContext, TRequestTracker, TServerEventHandler,
+    TServerMetrics, TWriteWatermarks,
+};
 
 /// Fixed-size thread-pool blocking Thrift server.
 ///
@@ -140,6 +166,94 @@ where
     o_proto_factory: OPF,
     processor: Arc<PRC>,
     worker_pool: ThreadPool,
+    tcp_options: TTcpOptions,
+    transport_observer: Option<ObserverHandle>,
+    idle_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    request_timer: Option<Arc<RequestTimer>>,
+    event_handler: Option<EventHandlerHandle>,
+    oneway_pool: Option<ThreadPool>,
+    concurrency: Option<Concurrency>,
+    write_watermarks: Option<TWriteWatermarks>,
+    metrics: Option<MetricsHandle>,
+    panic_hook: Option<PanicHook>,
+    rate_limiter: Option<Arc<TRateLimiter>>,
+    authenticator: Option<AuthenticatorHandle>,
+    error_mapper: Option<ErrorMapperHandle>,
+    request_tracker: Option<Arc<TRequestTracker>>,
+    configuration: Option<TConfigurationHandle>,
+    drain: Option<TDrainHandle>,
+    protocol_detector: Option<Arc<TProtocolDetector>>,
+    #[cfg(any(feature = "rustls", feature = "tls-native"))]
+    tls: Option<TlsSettings>,
+}
+
+/// TLS settings `TServer::listen` serves connections with.
+#[cfg(any(feature = "rustls", feature = "tls-native"))]
+#[derive(Clone)]
+pub(super) enum TlsSettings {
+    #[cfg(feature = "rustls")]
+    Rustls(Arc<ServerConfig>),
+    #[cfg(feature = "tls-native")]
+    Native(Arc<TlsAcceptor>),
+}
+
+#[cfg(any(feature = "rustls", feature = "tls-native"))]
+impl fmt::Debug for TlsSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TlsSettings")
+    }
+}
+
+type Connection = (
+    Box<dyn TInputProtocol + Send>,
+    Box<dyn TOutputProtocol + Send>,
+    ConnectionOptions,
+);
+
+#[derive(Clone)]
+struct ObserverHandle(Arc<dyn TTransportObserver>);
+
+impl fmt::Debug for ObserverHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TTransportObserver")
+    }
+}
+
+#[derive(Clone)]
+struct EventHandlerHandle(Arc<dyn TServerEventHandler>);
+
+impl fmt::Debug for EventHandlerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TServerEventHandler")
+    }
+}
+
+#[derive(Clone)]
+struct MetricsHandle(Arc<dyn TServerMetrics>);
+
+impl fmt::Debug for MetricsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TServerMetrics")
+    }
+}
+
+#[derive(Clone)]
+struct AuthenticatorHandle(Arc<dyn TAuthenticator>);
+
+impl fmt::Debug for AuthenticatorHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TAuthenticator")
+    }
+}
+
+#[derive(Clone)]
+struct ErrorMapperHandle(Arc<dyn TErrorMapper>);
+
+impl fmt::Debug for ErrorMapperHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TErrorMapper")
+    }
 }
 
 impl<PRC, RTF, IPF, WTF, OPF> TServer<PRC, RTF, IPF, WTF, OPF>
@@ -157,6 +271,8 @@ where
     /// `read_transport_factory` and `input_protocol_factory` to create
     /// implementations for the input, and `write_transport_factory` and
     /// `output_protocol_factory` to create implementations for the output.
+    ///
+    /// `TServerBuilder` offers the same settings, and more, by name.
     pub fn new(
         read_transport_factory: RTF,
         input_protocol_factory: IPF,
@@ -172,25 +288,263 @@ where
             o_proto_factory: output_protocol_factory,
             processor: Arc::new(processor),
             worker_pool: ThreadPool::with_name("Thrift service processor".to_owned(), num_workers),
+            tcp_options: TTcpOptions::default(),
+            transport_observer: None,
+            idle_timeout: None,
+            request_timeout: None,
+            request_timer: None,
+            event_handler: None,
+            oneway_pool: None,
+            concurrency: None,
+            write_watermarks: None,
+            metrics: None,
+            panic_hook: None,
+            rate_limiter: None,
+            authenticator: None,
+            error_mapper: None,
+            request_tracker: None,
+            configuration: None,
+            drain: None,
+            protocol_detector: None,
+            #[cfg(any(feature = "rustls", feature = "tls-native"))]
+            tls: None,
         }
     }
 
-    /// Listen for incoming connections on `listen_address`.
+    /// Set the socket options applied to every accepted TCP connection.
+    ///
+    /// By default only `TCP_NODELAY` is set.
+    pub fn set_tcp_options(&mut self, options: TTcpOptions) {
+        self.tcp_options = options;
+    }
+
+    /// Report the traffic on every accepted connection to `observer`.
+    ///
+    /// Each connection is wrapped in a `TInstrumentedChannel` before it is
+    /// split, so `observer` sees the bytes that cross the network and one
+    /// open/close pair per connection.
+    pub fn set_transport_observer(&mut self, observer: Arc<dyn TTransportObserver>) {
+        self.transport_observer = Some(ObserverHandle(observer));
+    }
+
+    /// Call `handler` as connections are accepted, served and closed.
+    pub fn set_event_handler(&mut self, handler: Arc<dyn TServerEventHandler>) {
+        self.event_handler = Some(EventHandlerHandle(handler));
+    }
+
+    /// Report request and connection measurements to `metrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn TServerMetrics>) {
+        self.metrics = Some(MetricsHandle(metrics));
+    }
+
+    /// Limit the rate of connections and requests from each client with
+    /// `limiter`.
+    ///
+    /// Both limits are checked on the worker serving the connection, so a
+    /// client over its connection limit still occupies a worker until it
+    /// has sent its first request or the idle timeout has passed.
+    pub fn set_rate_limiter(&mut self, limiter: Arc<TRateLimiter>) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    /// Enforce the limits of the configuration held by `handle` on every
+    /// connection, following it as it is replaced.
+    ///
+    /// The configuration replaces the one of each connection's input
+    /// protocol before the connection's next request is read.
+    pub fn set_configuration_handle(&mut self, handle: TConfigurationHandle) {
+        self.configuration = Some(handle);
+    }
+
+    /// Take the server out of service when `handle` is drained.
+    ///
+    /// See `TDrainHandle` for what draining does. Every `listen` method
+    /// returns `Ok` once the server has been drained.
+    pub fn set_drain_handle(&mut self, handle: TDrainHandle) {
+        self.drain = Some(handle);
+    }
+
+    /// Record the requests being processed, and log slow ones, with
+    /// `tracker`.
+    pub fn set_request_tracker(&mut self, tracker: Arc<TRequestTracker>) {
+        self.request_tracker = Some(tracker);
+    }
+
+    /// Authenticate the client of every connection with `authenticator`
+    /// before serving its requests.
+    ///
+    /// Connections the authenticator rejects are closed before the
+    /// processor sees any of their requests. Handlers of the others find
+    /// the client's principal in `TRequestContext::principal`.
+    pub fn set_authenticator(&mut self, authenticator: Arc<dyn TAuthenticator>) {
+        self.authenticator = Some(AuthenticatorHandle(authenticator));
+    }
+
+    /// Pass every `TApplicationException` sent to clients through `mapper`.
+    ///
+    /// The mapper decides what clients learn about failed requests, and
+    /// whether the connection is closed afterwards. Exceptions declared in
+    /// the IDL are sent unchanged.
+    pub fn set_error_mapper(&mut self, mapper: Arc<dyn TErrorMapper>) {
+        self.error_mapper = Some(ErrorMapperHandle(mapper));
+    }
+
+    /// Pick the protocol of each connection with `detector` from the first
+    /// byte the client sends, instead of using the server's protocol
+    /// factories.
+    pub fn set_protocol_detector(&mut self, detector: Arc<TProtocolDetector>) {
+        self.protocol_detector = Some(detector);
+    }
+
+    /// Call `hook` whenever a request handler panics.
+    ///
+    /// A panic in a handler is always caught: the client is sent an
+    /// `ApplicationErrorKind::InternalError` exception if it waits for a
+    /// reply, the connection is closed and the worker thread goes on to
+    /// serve other connections. The hook is meant for alerting; it runs on
+    /// the thread that caught the panic.
+    pub fn set_panic_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&THandlerPanic) + Send + Sync + 'static,
+    {
+        self.panic_hook = Some(PanicHook(Arc::new(hook)));
+    }
+
+    /// Process oneway requests on a separate pool of `num_workers` threads.
+    ///
+    /// By default a oneway request is processed on the thread serving its
+    /// connection, like any other request, so the next request on that
+    /// connection waits for it. With a oneway pool the connection reads the
+    /// arguments of a oneway request, queues the request and moves on to the
+    /// next one at once. The queue is unbounded. Pass `0` to go back to
+    /// processing oneway requests on the connection's thread.
+    ///
+    /// Oneway requests taken from the pool, or read while a timeout or an
+    /// event handler is configured, are processed with an output protocol
+    /// that discards anything the processor writes, and failures are
+    /// logged rather than closing the connection.
+    pub fn set_oneway_workers(&mut self, num_workers: usize) {
+        self.oneway_pool = if num_workers == 0 {
+            None
+        } else {
+            Some(ThreadPool::with_name(
+                "Thrift oneway processor".to_owned(),
+                num_workers,
+            ))
+        };
+    }
+
+    /// Process up to `max_per_connection` requests of each connection at a
+    /// time, on a separate pool of `num_workers` threads.
+    ///
+    /// By default the requests of a connection are processed one after the
+    /// other, so a slow call holds up every call behind it. With concurrent
+    /// requests, the connection's thread reads each request into memory and
+    /// queues it, and reads the next one while it is processed. Replies are
+    /// sent as requests complete, which may not be the order they arrived
+    /// in: only enable this for clients that match replies to calls by
+    /// sequence number, and use a framed transport so that each reply is
+    /// sent as one frame. A request with the sequence number of one still
+    /// being processed waits for it. Pass `max_per_connection` of `1` or
+    /// less, or `num_workers` of `0`, to go back to processing requests one
+    /// at a time.
+    ///
+    /// The request timeout and the oneway pool do not apply to connections
+    /// served this way. A failed or panicking request stops the connection
+    /// from reading further requests, without waiting for the client to send
+    /// one, and closes it once the requests already read have completed.
+    pub fn set_concurrent_requests(&mut self, max_per_connection: usize, num_workers: usize) {
+        self.concurrency = if max_per_connection <= 1 || num_workers == 0 {
+            None
+        } else {
+            Some(Concurrency {
+                pool: ThreadPool::with_name(
+                    "Thrift concurrent request processor".to_owned(),
+                    num_workers,
+                ),
+                max_per_connection,
+            })
+        };
+    }
+
+    /// Stop reading requests from a connection while the replies waiting to
+    /// be sent on it are above `watermarks`.
     ///
-    /// `listen_address` should implement `ToSocketAddrs` trait.
+    /// Only connections that process requests concurrently hold more than
+    /// one reply at a time; see `TWriteWatermarks`. Pass `None`, the
+    /// default, to read requests regardless of the replies waiting.
+    pub fn set_write_watermarks(&mut self, watermarks: Option<TWriteWatermarks>) {
+        self.write_watermarks = watermarks;
+    }
+
+    /// Close connections on which no request arrives within `timeout`.
+    ///
+    /// The timeout also applies to each read while a request is being
+    /// received, so a client that stops sending halfway through a request is
+    /// disconnected as well. Defaults to `None`, which keeps idle
+    /// connections open until the client closes them.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Answer requests that are still being handled `timeout` after they
+    /// arrived with an `ApplicationErrorKind::InternalError` exception.
+    ///
+    /// The handler keeps running; its reply is discarded once the exception
+    /// has been sent. Handlers can read the deadline with
+    /// `thrift::server::request_deadline()` to stop early. The exception is
+    /// written by a separate transport created with the server's write
+    /// transport factory, so this only works with transports that carry no
+    /// state from one message to the next, such as the buffered and framed
+    /// transports. Defaults to `None`.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Serve TLS from `listen`, as `listen_tls` or `listen_native_tls` do.
+    #[cfg(any(feature = "rustls", feature = "tls-native"))]
+    pub(super) fn set_tls(&mut self, tls: TlsSettings) {
+        self.tls = Some(tls);
+    }
+
+    /// Listen for incoming connections on `target`.
+    ///
+    /// `target` is a TCP address, such as `"127.0.0.1:9090"` or a
+    /// `SocketAddr`, or a `TBindTarget` naming a Unix domain socket by path
+    /// or in the abstract namespace. TCP connections are served over TLS if
+    /// the server was built with TLS settings by a `TServerBuilder`.
     ///
     /// Return `()` if successful.
     ///
-    /// Return `Err` when the server cannot bind to `listen_address` or there
-    /// is an unrecoverable error.
-    pub fn listen<A: ToSocketAddrs>(&mut self, listen_address: A) -> crate::Result<()> {
-        let listener = TcpListener::bind(listen_address)?;
+    /// Return `Err` when the server cannot bind to `target` or there is an
+    /// unrecoverable error.
+    pub fn listen<T: Into<TBindTarget>>(&mut self, target: T) -> crate::Result<()> {
+        match target.into() {
+            TBindTarget::Tcp(address) => self.listen_on(TcpListener::bind(address.as_str())?),
+            #[cfg(unix)]
+            TBindTarget::Unix(path) => self.listen_on_unix(UnixListener::bind(path)?),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            TBindTarget::Abstract(name) => self.listen_on_unix(bind_abstract(&name)?),
+        }
+    }
+
+    /// Accept connections from `listener`, which is already bound and
+    /// listening.
+    ///
+    /// Use this to serve on a socket inherited from a service manager, such
+    /// as one returned by `systemd_listeners`, or kept open across a restart.
+    /// As with `listen`, connections are served over TLS if the server was
+    /// built with TLS settings.
+    pub fn listen_on(&mut self, listener: TcpListener) -> crate::Result<()> {
+        self.watch_listener(SockRef::from(&listener));
         for stream in listener.incoming() {
+            if self.draining() {
+                return Ok(());
+            }
             match stream {
                 Ok(s) => {
-                    s.set_nodelay(true).ok();
-                    let channel = TTcpChannel::with_stream(s);
-                    self.handle_stream(channel)?;
+                    self.prepare_stream(&s);
+                    self.serve_tcp_stream(s)?;
                 }
                 Err(e) => {
                     warn!("failed to accept remote connection with error {:?}", e);
@@ -204,25 +558,95 @@ where
         }))
     }
 
+    /// Listen for incoming connections on `listen_address` with
+    /// `num_acceptors` threads, each accepting from its own `SO_REUSEPORT`
+    /// socket bound to the same address.
+    ///
+    /// The kernel spreads new connections across the sockets, so accepting
+    /// and setting socket options is no longer limited to one thread. This
+    /// helps servers that see a very high rate of new connections on
+    /// machines with many cores. Accepted connections are still handed to
+    /// the worker pool by the calling thread. As with `listen`, connections
+    /// are served over TLS if the server was built with TLS settings.
+    ///
+    /// If the port of `listen_address` is 0, every socket is bound to the
+    /// port picked for the first one.
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    pub fn listen_reuseport<A: ToSocketAddrs>(
+        &mut self,
+        listen_address: A,
+        num_acceptors: usize,
+    ) -> crate::Result<()> {
+        let streams = spawn_acceptors(
+            listen_address,
+            num_acceptors,
+            self.tcp_options,
+            self.idle_timeout,
+            self.drain.as_ref(),
+        )?;
+        for stream in streams {
+            if self.draining() {
+                // the other acceptors stop once they next accept a
+                // connection and find nobody to hand it to
+                return Ok(());
+            }
+            self.serve_tcp_stream(stream)?;
+        }
+
+        Err(crate::Error::Application(ApplicationError {
+            kind: ApplicationErrorKind::Unknown,
+            message: "aborted listen loop".into(),
+        }))
+    }
+
+    /// Serve an accepted TCP connection, over TLS if the server was built
+    /// with TLS settings.
+    fn serve_tcp_stream(&mut self, stream: TcpStream) -> crate::Result<()> {
+        #[cfg(any(feature = "rustls", feature = "tls-native"))]
+        if let Some(tls) = self.tls.clone() {
+            return match tls {
+                #[cfg(feature = "rustls")]
+                TlsSettings::Rustls(config) => self.serve_tls_stream(stream, config),
+                #[cfg(feature = "tls-native")]
+                TlsSettings::Native(acceptor) => self.serve_native_tls_stream(stream, acceptor),
+            };
+        }
+        let peer_addr = stream.peer_addr().ok();
+        let closer = self.tcp_closer(&stream);
+        let channel = TTcpChannel::with_stream(stream);
+        self.handle_stream(channel, peer_addr, closer, TRequestContext::new)
+    }
+
     /// Listen for incoming TLS connections on `listen_address`.
     ///
     /// `config` controls certificate selection, client authentication, crypto
     /// provider, and protocol policy. Accepted connections are handed to a
     /// worker before the TLS handshake begins, so the accept loop does not
     /// block on a peer that connects without completing a handshake.
+    ///
+    /// If `config` requests client certificates, the verified identity of
+    /// each client is available to handlers through
+    /// `thrift::server::peer_identity()`.
     #[cfg(feature = "rustls")]
     pub fn listen_tls<A: ToSocketAddrs>(
         &mut self,
         listen_address: A,
         config: Arc<ServerConfig>,
     ) -> crate::Result<()> {
-        let listener = TcpListener::bind(listen_address)?;
+        self.serve_tls(TcpListener::bind(listen_address)?, config)
+    }
+
+    #[cfg(feature = "rustls")]
+    fn serve_tls(&mut self, listener: TcpListener, config: Arc<ServerConfig>) -> crate::Result<()> {
+        self.watch_listener(SockRef::from(&listener));
         for stream in listener.incoming() {
+            if self.draining() {
+                return Ok(());
+            }
             match stream {
                 Ok(stream) => {
-                    stream.set_nodelay(true).ok();
-                    let channel = TTlsServerChannel::with_stream(stream, Arc::clone(&config))?;
-                    self.handle_stream(channel)?;
+                    self.prepare_stream(&stream);
+                    self.serve_tls_stream(stream, Arc::clone(&config))?;
                 }
                 Err(error) => {
                     warn!(
@@ -239,9 +663,181 @@ where
         }))
     }
 
+    #[cfg(feature = "rustls")]
+    fn serve_tls_stream(
+        &mut self,
+        stream: TcpStream,
+        config: Arc<ServerConfig>,
+    ) -> crate::Result<()> {
+        let peer_addr = stream.peer_addr().ok();
+        let closer = self.tcp_closer(&stream);
+        let channel = TTlsServerChannel::with_stream(stream, config)?;
+        let mut handshake_channel = channel.clone();
+        self.handle_stream(channel, peer_addr, closer, move || {
+            // runs on the worker, so a slow handshake does not hold up the
+            // accept loop
+            if let Err(e) = handshake_channel.handshake() {
+                warn!("TLS handshake failed with error {:?}", e);
+                return TRequestContext::new();
+            }
+            TRequestContext::new()
+                .with_protocol(handshake_channel.alpn_protocol().ok().flatten())
+                .with_peer_identity(handshake_channel.peer_identity().ok().flatten())
+        })
+    }
+
+    /// Listen for incoming TLS connections on `listen_address`, using the
+    /// platform TLS library through `native-tls`.
+    ///
+    /// As with `listen_tls`, the handshake is completed on the worker that
+    /// serves the connection. `native-tls` cannot request client
+    /// certificates, so `thrift::server::peer_identity()` always returns
+    /// `None` for these connections; use `listen_tls` for mutual TLS.
+    #[cfg(feature = "tls-native")]
+    pub fn listen_native_tls<A: ToSocketAddrs>(
+        &mut self,
+        listen_address: A,
+        acceptor: Arc<TlsAcceptor>,
+    ) -> crate::Result<()> {
+        self.serve_native_tls(TcpListener::bind(listen_address)?, acceptor)
+    }
+
+    #[cfg(feature = "tls-native")]
+    fn serve_native_tls(
+        &mut self,
+        listener: TcpListener,
+        acceptor: Arc<TlsAcceptor>,
+    ) -> crate::Result<()> {
+        self.watch_listener(SockRef::from(&listener));
+        for stream in listener.incoming() {
+            if self.draining() {
+                return Ok(());
+            }
+            match stream {
+                Ok(stream) => {
+                    self.prepare_stream(&stream);
+                    self.serve_native_tls_stream(stream, acceptor.clone())?;
+                }
+                Err(error) => {
+                    warn!(
+                        "failed to accept remote TLS connection with error {:?}",
+                        error
+                    );
+                }
+            }
+        }
+
+        Err(crate::Error::Application(ApplicationError {
+            kind: ApplicationErrorKind::Unknown,
+            message: "aborted TLS listen loop".into(),
+        }))
+    }
+
+    #[cfg(feature = "tls-native")]
+    fn serve_native_tls_stream(
+        &mut self,
+        stream: TcpStream,
+        acceptor: Arc<TlsAcceptor>,
+    ) -> crate::Result<()> {
+        let peer_addr = stream.peer_addr().ok();
+        let closer = self.tcp_closer(&stream);
+        let channel = TNativeTlsServerChannel::with_stream(stream, acceptor);
+        let mut handshake_channel = channel.clone();
+        self.handle_stream(channel, peer_addr, closer, move || {
+            if let Err(e) = handshake_channel.handshake() {
+                warn!("TLS handshake failed with error {:?}", e);
+                return TRequestContext::new();
+            }
+            TRequestContext::new().with_protocol(handshake_channel.alpn_protocol().ok().flatten())
+        })
+    }
+
+    /// Listen for incoming WebSocket connections on `listen_address`.
+    ///
+    /// Each Thrift message is carried in a binary WebSocket message, as sent
+    /// by `TWebSocketClientChannel` and browser-based clients. The upgrade is
+    /// completed on the worker serving the connection, so the accept loop
+    /// does not block on a slow peer.
+    #[cfg(feature = "websocket")]
+    pub fn listen_websocket<A: ToSocketAddrs>(&mut self, listen_address: A) -> crate::Result<()> {
+        let listener = TcpListener::bind(listen_address)?;
+        self.watch_listener(SockRef::from(&listener));
+        for stream in listener.incoming() {
+            if self.draining() {
+                return Ok(());
+            }
+            match stream {
+                Ok(stream) => {
+                    self.prepare_stream(&stream);
+                    let peer_addr = stream.peer_addr().ok();
+                    let closer = self.tcp_closer(&stream);
+                    let channel = TWebSocketServerChannel::with_stream(stream);
+                    let mut handshake_channel = channel.clone();
+                    self.handle_stream(channel, peer_addr, closer, move || {
+                        if let Err(e) = handshake_channel.handshake() {
+                            warn!("WebSocket upgrade failed with error {:?}", e);
+                        }
+                        TRequestContext::new()
+                    })?;
+                }
+                Err(e) => {
+                    warn!(
+                        "failed to accept remote WebSocket connection with error {:?}",
+                        e
+                    );
+                }
+            }
+        }
+
+        Err(crate::Error::Application(ApplicationError {
+            kind: ApplicationErrorKind::Unknown,
+            message: "aborted WebSocket listen loop".into(),
+        }))
+    }
+
+    /// Listen for incoming connections on `listen_address` and serve each
+    /// one over a `TUringChannel`.
+    ///
+    /// Every reply is sent together with the read of the next request in a
+    /// single `io_uring` submission, which cuts the system call overhead of
+    /// small requests roughly in half. Connections for which an `io_uring`
+    /// instance cannot be created are closed with a warning.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn listen_io_uring<A: ToSocketAddrs>(&mut self, listen_address: A) -> crate::Result<()> {
+        let listener = TcpListener::bind(listen_address)?;
+        self.watch_listener(SockRef::from(&listener));
+        for stream in listener.incoming() {
+            if self.draining() {
+                return Ok(());
+            }
+            match stream {
+                Ok(stream) => {
+                    self.prepare_stream(&stream);
+                    let peer_addr = stream.peer_addr().ok();
+                    let closer = self.tcp_closer(&stream);
+                    match TUringChannel::with_stream(stream) {
+                        Ok(channel) => {
+                            self.handle_stream(channel, peer_addr, closer, TRequestContext::new)?
+                        }
+                        Err(e) => warn!("failed to create io_uring channel with error {:?}", e),
+                    }
+                }
+                Err(e) => {
+                    warn!("failed to accept remote connection with error {:?}", e);
+                }
+            }
+        }
+
+        Err(crate::Error::Application(ApplicationError {
+            kind: ApplicationErrorKind::Unknown,
+            message: "aborted io_uring listen loop".into(),
+        }))
+    }
+
     /// Listen for incoming connections on `listen_path`.
     ///
-    /// `listen_path` should implement `AsRef<Path>` trait.
+    /// `listen_path` should implement `AsRef<Path>` trait. This is the same
+    /// as calling `listen` with `TBindTarget::Unix`.
     ///
     /// Return `()` if successful.
     ///
@@ -249,11 +845,28 @@ where
     /// is an unrecoverable error.
     #[cfg(unix)]
     pub fn listen_uds<P: AsRef<Path>>(&mut self, listen_path: P) -> crate::Result<()> {
-        let listener = UnixListener::bind(listen_path)?;
+        self.listen(listen_path.as_ref())
+    }
+
+    /// Accept connections from the Unix domain socket `listener`, which is
+    /// already bound and listening.
+    #[cfg(unix)]
+    pub fn listen_on_unix(&mut self, listener: UnixListener) -> crate::Result<()> {
+        self.watch_listener(SockRef::from(&listener));
         for stream in listener.incoming() {
+            if self.draining() {
+                return Ok(());
+            }
             match stream {
                 Ok(s) => {
-                    self.handle_stream(s)?;
+                    if let Err(e) = s.set_read_timeout(self.idle_timeout) {
+                        warn!("failed to set idle timeout with error {:?}", e);
+                    }
+                    let closer = match self.needs_closer() {
+                        true => Closer::unix(&s),
+                        false => None,
+                    };
+                    self.handle_stream(s, None, closer, TRequestContext::new)?;
                 }
                 Err(e) => {
                     warn!(
@@ -270,57 +883,172 @@ where
         }))
     }
 
-    fn handle_stream<S: TIoChannel + Send + 'static>(&mut self, stream: S) -> crate::Result<()> {
-        let (i_prot, o_prot) = self.new_protocols_for_connection(stream)?;
+    fn prepare_stream(&self, stream: &TcpStream) {
+        prepare_stream(stream, &self.tcp_options, self.idle_timeout);
+    }
+
+    /// Return `true` if the server has been drained, so the accept loop
+    /// should stop.
+    fn draining(&self) -> bool {
+        matches!(self.drain, Some(ref drain) if drain.is_draining())
+    }
+
+    /// Wake the accept loop of `listener` when the server is drained.
+    fn watch_listener(&self, listener: SockRef<'_>) {
+        if let Some(ref drain) = self.drain {
+            drain.add_listener(listener);
+        }
+    }
+
+    /// Return what closes `stream` if it is idle when the server is drained,
+    /// or when one of its concurrently processed requests fails.
+    fn tcp_closer(&self, stream: &TcpStream) -> Option<Closer> {
+        match self.needs_closer() {
+            true => Closer::tcp(stream),
+            false => None,
+        }
+    }
+
+    fn needs_closer(&self) -> bool {
+        self.drain.is_some() || self.concurrency.is_some()
+    }
+
+    /// Serve `stream`, accepted from `peer_addr`, on a worker thread.
+    /// `handshake` is called on the worker before the first request and
+    /// returns what it learned about the client, such as its TLS identity.
+    /// `closer` closes the connection if it is idle when the server is
+    /// drained, or when one of its concurrently processed requests fails.
+    fn handle_stream<S, F>(
+        &mut self,
+        stream: S,
+        peer_addr: Option<SocketAddr>,
+        closer: Option<Closer>,
+        handshake: F,
+    ) -> crate::Result<()>
+    where
+        S: TIoChannel + Send + 'static,
+        F: FnOnce() -> TRequestContext + Send + 'static,
+    {
+        if let Some(EventHandlerHandle(ref handler)) = self.event_handler {
+            if !handler.connection_accepted(peer_addr) {
+                return Ok(());
+            }
+        }
+        let (i_prot, o_prot, mut options) = match self.transport_observer {
+            Some(ObserverHandle(ref observer)) => {
+                let stream = TInstrumentedChannel::new(stream, observer.clone());
+                self.new_protocols_for_connection(stream)?
+            }
+            None => self.new_protocols_for_connection(stream)?,
+        };
+        options.closer = self.concurrency.as_ref().and(closer.clone());
+        options.drain = self
+            .drain
+            .as_ref()
+            .map(|drain| drain.add_connection(closer));
         let processor = self.processor.clone();
-        self.worker_pool
-            .execute(move || handle_incoming_connection(processor, i_prot, o_prot));
+        self.worker_pool.execute(move || {
+            let context = handshake().with_peer_addr(peer_addr);
+            handle_incoming_connection(processor, i_prot, o_prot, options, context)
+        });
         Ok(())
     }
 
     fn new_protocols_for_connection<S: TIoChannel + Send + 'static>(
         &mut self,
         stream: S,
-    ) -> crate::Result<(
-        Box<dyn TInputProtocol + Send>,
-        Box<dyn TOutputProtocol + Send>,
-    )> {
+    ) -> crate::Result<Connection> {
         // split it into two - one to be owned by the
         // input tran/proto and the other by the output
         let (r_chan, w_chan) = stream.split()?;
 
         // input protocol and transport
         let r_tran = self.r_trans_factory.create(Box::new(r_chan));
-        let i_prot = self.i_proto_factory.create(r_tran);
+        let (i_prot, selection) = match self.protocol_detector {
+            Some(ref detector) => {
+                let (i_prot, selection) = detector.detect(r_tran);
+                (i_prot, Some(selection))
+            }
+            None => (self.i_proto_factory.create(r_tran), None),
+        };
+
+        let mut options = ConnectionOptions {
+            idle_timeout: self.idle_timeout,
+            request_timeout: None,
+            event_handler: self.event_handler.as_ref().map(|h| h.0.clone()),
+            oneway_pool: self.oneway_pool.clone(),
+            metrics: self.metrics.as_ref().map(|m| m.0.clone()),
+            panic_hook: self.panic_hook.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            authenticator: self.authenticator.as_ref().map(|a| a.0.clone()),
+            error_mapper: self.error_mapper.as_ref().map(|m| m.0.clone()),
+            request_tracker: self.request_tracker.clone(),
+            configuration: self.configuration.clone(),
+            concurrency: self.concurrency.clone(),
+            write_watermarks: self.write_watermarks,
+            drain: None,
+            closer: None,
+        };
+        let timeout = match self.request_timeout {
+            Some(timeout) => timeout,
+            None => {
+                // output protocol and transport
+                let w_tran = self.w_trans_factory.create(Box::new(w_chan));
+                let o_prot = self.new_output_protocol(selection.as_ref(), w_tran);
+                return Ok((i_prot, o_prot, options));
+            }
+        };
 
-        // output protocol and transport
-        let w_tran = self.w_trans_factory.create(Box::new(w_chan));
-        let o_prot = self.o_proto_factory.create(w_tran);
+        // the handler and the timeout reply share the write half, each
+        // through its own output protocol and transport
+        let timer = match self.request_timer {
+            Some(ref timer) => timer.clone(),
+            None => {
+                let timer = Arc::new(RequestTimer::start()?);
+                self.request_timer = Some(timer.clone());
+                timer
+            }
+        };
+        let deadline = Arc::new(RequestDeadline::new(Box::new(w_chan)));
+        let w_tran = self
+            .w_trans_factory
+            .create(Box::new(deadline.handler_writer()));
+        let o_prot = self.new_output_protocol(selection.as_ref(), w_tran);
+        let timeout_tran = self
+            .w_trans_factory
+            .create(Box::new(deadline.timeout_writer()));
+        deadline.set_timeout_reply(self.new_output_protocol(selection.as_ref(), timeout_tran));
+        options.request_timeout = Some(RequestTimeout {
+            timeout,
+            deadline,
+            timer,
+        });
 
-        Ok((i_prot, o_prot))
+        Ok((i_prot, o_prot, options))
     }
-}
 
-fn handle_incoming_connection<PRC>(
-    processor: Arc<PRC>,
-    i_prot: Box<dyn TInputProtocol>,
-    o_prot: Box<dyn TOutputProtocol>,
-) where
-    PRC: TProcessor,
-{
-    let mut i_prot = i_prot;
-    let mut o_prot = o_prot;
-    loop {
-        match processor.process(&mut *i_prot, &mut *o_prot) {
-            Ok(()) => {}
-            Err(err) => {
-                match err {
-                    crate::Error::Transport(ref transport_err)
-                        if transport_err.kind == TransportErrorKind::EndOfFile => {}
-                    other => warn!("processor completed with error: {:?}", other),
-                }
-                break;
-            }
+    fn new_output_protocol(
+        &self,
+        selection: Option<&TProtocolSelection>,
+        w_tran: Box<dyn TWriteTransport + Send>,
+    ) -> Box<dyn TOutputProtocol + Send> {
+        match selection {
+            Some(selection) => selection.output_protocol(w_tran),
+            None => self.o_proto_factory.create(w_tran),
         }
     }
 }
+
+/// Apply the server's socket options to an accepted connection.
+pub(super) fn prepare_stream(
+    stream: &TcpStream,
+    tcp_options: &TTcpOptions,
+    idle_timeout: Option<Duration>,
+) {
+    if let Err(e) = tcp_options.apply(stream) {
+        warn!("failed to set socket options with error {:?}", e);
+    }
+    if let Err(e) = stream.set_read_timeout(idle_timeout) {
+        warn!("failed to set idle timeout with error {:?}", e);
+    }
+}
@@ -0,0 +1,482 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    TInputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol, TStoredInputProtocol,
+};
+use crate::{ApplicationError, ApplicationErrorKind};
+
+use super::{handle_process_result, warn, with_request_deadline, TProcessor};
+
+type SharedChannel = Arc<Mutex<Box<dyn Write + Send>>>;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Request timeout applied to one connection.
+pub(super) struct RequestTimeout {
+    pub(super) timeout: Duration,
+    pub(super) deadline: Arc<RequestDeadline>,
+    pub(super) timer: Arc<RequestTimer>,
+}
+
+impl RequestTimeout {
+    /// Process the request identified by `ident` within the timeout.
+    pub(super) fn process<PRC>(
+        &self,
+        processor: &PRC,
+        ident: TMessageIdentifier,
+        i_prot: &mut dyn TInputProtocol,
+        o_prot: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()>
+    where
+        PRC: TProcessor,
+    {
+        let deadline = Instant::now() + self.timeout;
+        let name = ident.name.clone();
+        self.deadline.begin(&ident, deadline, &self.timer);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            with_request_deadline(Some(deadline), || {
+                processor.process(&mut TStoredInputProtocol::new(i_prot, ident), o_prot)
+            })
+        }));
+        // stop the timer before a panic is passed on, so it cannot answer
+        // the request while the panic is being reported
+        let expired = self.deadline.finish();
+        let result = match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        if expired {
+            warn!(
+                "request {} exceeded its deadline of {:?}; reply discarded",
+                name, self.timeout
+            );
+        }
+        result
+    }
+}
+
+#[derive(Debug)]
+enum Phase {
+    /// Waiting for the next request.
+    Idle,
+    /// Handling `ident`; nothing has been written yet.
+    Running(TMessageIdentifier),
+    /// The handler started writing its reply before the deadline.
+    Replying,
+    /// The deadline passed and the timeout exception was sent; the
+    /// handler's reply is discarded.
+    Expired,
+}
+
+struct RequestState {
+    generation: u64,
+    phase: Phase,
+}
+
+/// Enforces the request deadline of one connection.
+///
+/// The handler's output protocol writes through `handler_writer()`, and the
+/// exception sent when a request times out is written by a second output
+/// protocol, created with the same factories, through `timeout_writer()`.
+/// Whichever of the two starts writing first for a request wins; the other
+/// one's output for that request is dropped.
+pub(super) struct RequestDeadline {
+    state: Arc<Mutex<RequestState>>,
+    channel: SharedChannel,
+    timeout_reply: Mutex<Option<Box<dyn TOutputProtocol + Send>>>,
+}
+
+impl RequestDeadline {
+    pub(super) fn new(channel: Box<dyn Write + Send>) -> RequestDeadline {
+        RequestDeadline {
+            state: Arc::new(Mutex::new(RequestState {
+                generation: 0,
+                phase: Phase::Idle,
+            })),
+            channel: Arc::new(Mutex::new(channel)),
+            timeout_reply: Mutex::new(None),
+        }
+    }
+
+    /// Writer for the handler's output protocol.
+    pub(super) fn handler_writer(&self) -> HandlerWriter {
+        HandlerWriter {
+            state: self.state.clone(),
+            channel: self.channel.clone(),
+        }
+    }
+
+    /// Writer for the output protocol passed to `set_timeout_reply`.
+    pub(super) fn timeout_writer(&self) -> TimeoutWriter {
+        TimeoutWriter {
+            channel: self.channel.clone(),
+        }
+    }
+
+    pub(super) fn set_timeout_reply(&self, o_prot: Box<dyn TOutputProtocol + Send>) {
+        *lock(&self.timeout_reply) = Some(o_prot);
+    }
+
+    /// Start the deadline for `ident`, expiring at `deadline`.
+    pub(super) fn begin(
+        self: &Arc<Self>,
+        ident: &TMessageIdentifier,
+        deadline: Instant,
+        timer: &RequestTimer,
+    ) {
+        if ident.message_type == TMessageType::OneWay {
+            // nothing to reply to
+            return;
+        }
+        let generation = {
+            let mut state = lock(&self.state);
+            state.generation += 1;
+            state.phase = Phase::Running(ident.clone());
+            state.generation
+        };
+        timer.schedule(deadline, self.clone(), generation);
+    }
+
+    /// End the current request. Return `true` if it timed out.
+    pub(super) fn finish(&self) -> bool {
+        // wait for a timeout reply that is being written, so that it does
+        // not interleave with the reply to the next request
+        let _reply = lock(&self.timeout_reply);
+        let mut state = lock(&self.state);
+        let expired = matches!(state.phase, Phase::Expired);
+        state.phase = Phase::Idle;
+        expired
+    }
+
+    fn expire(&self, generation: u64) {
+        let mut reply = lock(&self.timeout_reply);
+        let ident = {
+            let mut state = lock(&self.state);
+            if state.generation != generation || !matches!(state.phase, Phase::Running(_)) {
+                return;
+            }
+            match std::mem::replace(&mut state.phase, Phase::Expired) {
+                Phase::Running(ident) => ident,
+                _ => unreachable!(),
+            }
+        };
+
+        if let Some(o_prot) = reply.as_mut() {
+            let timed_out = crate::Error::Application(ApplicationError::new(
+                ApplicationErrorKind::InternalError,
+                "request timed out",
+            ));
+            if let Err(e) = handle_process_result(&ident, Err(timed_out), &mut **o_prot) {
+                warn!(
+                    "failed to send request timeout exception with error {:?}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Write half of a connection as seen by the handler.
+pub(super) struct HandlerWriter {
+    state: Arc<Mutex<RequestState>>,
+    channel: SharedChannel,
+}
+
+impl Write for HandlerWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // hold the state lock so that the deadline cannot pass mid-write
+        let mut state = lock(&self.state);
+        match state.phase {
+            Phase::Expired => Ok(buf.len()),
+            Phase::Running(_) => {
+                state.phase = Phase::Replying;
+                lock(&self.channel).write(buf)
+            }
+            Phase::Idle | Phase::Replying => lock(&self.channel).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let state = lock(&self.state);
+        match state.phase {
+            Phase::Expired => Ok(()),
+            _ => lock(&self.channel).flush(),
+        }
+    }
+}
+
+/// Write half of a connection as seen by the timeout reply.
+pub(super) struct TimeoutWriter {
+    channel: SharedChannel,
+}
+
+impl Write for TimeoutWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.channel).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.channel).flush()
+    }
+}
+
+struct TimerEntry {
+    at: Instant,
+    request: Arc<RequestDeadline>,
+    generation: u64,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // earliest deadline first
+        other.at.cmp(&self.at)
+    }
+}
+
+#[derive(Default)]
+struct TimerState {
+    entries: BinaryHeap<TimerEntry>,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct TimerShared {
+    state: Mutex<TimerState>,
+    wakeup: Condvar,
+}
+
+/// Thread that sends the timeout reply for requests whose deadline passed.
+/// Shared by all connections of a server.
+pub(super) struct RequestTimer {
+    shared: Arc<TimerShared>,
+}
+
+impl fmt::Debug for RequestTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RequestTimer")
+    }
+}
+
+impl RequestTimer {
+    pub(super) fn start() -> io::Result<RequestTimer> {
+        let shared = Arc::new(TimerShared::default());
+        let timer_shared = shared.clone();
+        thread::Builder::new()
+            .name("Thrift request timer".to_owned())
+            .spawn(move || run_timer(&timer_shared))?;
+        Ok(RequestTimer { shared })
+    }
+
+    fn schedule(&self, at: Instant, request: Arc<RequestDeadline>, generation: u64) {
+        let mut state = lock(&self.shared.state);
+        let earliest = state.entries.peek().map(|e| e.at);
+        state.entries.push(TimerEntry {
+            at,
+            request,
+            generation,
+        });
+        if !matches!(earliest, Some(earliest) if earliest <= at) {
+            self.shared.wakeup.notify_one();
+        }
+    }
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        lock(&self.shared.state).stopped = true;
+        self.shared.wakeup.notify_one();
+    }
+}
+
+fn run_timer(shared: &TimerShared) {
+    let mut state = lock(&shared.state);
+    loop {
+        if state.stopped {
+            return;
+        }
+        let now = Instant::now();
+        let wait = match state.entries.peek() {
+            Some(entry) if entry.at <= now => {
+                let entry = state.entries.pop().unwrap();
+                drop(state);
+                entry.request.expire(entry.generation);
+                state = lock(&shared.state);
+                continue;
+            }
+            Some(entry) => entry.at - now,
+            None => Duration::from_secs(3600),
+        };
+        state = shared
+            .wakeup
+            .wait_timeout(state, wait)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory,
+    };
+    use crate::server::{request_deadline, TServer};
+    use crate::transport::{
+        TFramedReadTransport, TFramedReadTransportFactory, TFramedWriteTransport,
+        TFramedWriteTransportFactory,
+    };
+
+    /// Sleeps for the number of milliseconds in the request, then replies
+    /// with whether a deadline was set.
+    struct Sleeper;
+
+    impl TProcessor for Sleeper {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            let millis = i.read_i32()?;
+            i.read_message_end()?;
+            thread::sleep(Duration::from_millis(millis as u64));
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_bool(request_deadline().is_some())?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    fn start(idle: Option<Duration>, request: Option<Duration>) -> std::net::SocketAddr {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        thread::spawn(move || {
+            let mut server = TServer::new(
+                TFramedReadTransportFactory::new(),
+                TBinaryInputProtocolFactory::new(),
+                TFramedWriteTransportFactory::new(),
+                TBinaryOutputProtocolFactory::new(),
+                Sleeper,
+                2,
+            );
+            server.set_idle_timeout(idle);
+            server.set_request_timeout(request);
+            server.listen(address)
+        });
+        address
+    }
+
+    fn connect(address: std::net::SocketAddr) -> TcpStream {
+        let mut attempts = 0;
+        loop {
+            match TcpStream::connect(address) {
+                Ok(s) => return s,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("cannot connect to server: {:?}", e),
+            }
+        }
+    }
+
+    fn call(stream: &TcpStream, seq: i32, sleep_millis: i32) -> crate::Result<bool> {
+        let mut o_prot = TBinaryOutputProtocol::new(
+            TFramedWriteTransport::new(stream.try_clone().unwrap()),
+            true,
+        );
+        o_prot.write_message_begin(&TMessageIdentifier::new("sleep", TMessageType::Call, seq))?;
+        o_prot.write_i32(sleep_millis)?;
+        o_prot.write_message_end()?;
+        o_prot.flush()?;
+
+        let mut i_prot =
+            TBinaryInputProtocol::new(TFramedReadTransport::new(stream.try_clone().unwrap()), true);
+        let ident = i_prot.read_message_begin()?;
+        assert_eq!(ident.sequence_number, seq);
+        if ident.message_type == TMessageType::Exception {
+            return Err(crate::Error::read_application_error_from_in_protocol(&mut i_prot)?.into());
+        }
+        i_prot.read_bool()
+    }
+
+    #[test]
+    fn must_close_idle_connections() {
+        let address = start(Some(Duration::from_millis(100)), None);
+        let mut stream = connect(address);
+        assert!(!call(&stream, 1, 0).unwrap());
+
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn must_answer_late_requests_with_timeout_exception() {
+        let address = start(None, Some(Duration::from_millis(100)));
+        let stream = connect(address);
+
+        assert!(call(&stream, 1, 0).unwrap());
+        match call(&stream, 2, 400) {
+            Err(crate::Error::Application(e)) => {
+                assert_eq!(e.kind, ApplicationErrorKind::InternalError);
+                assert_eq!(e.message, "request timed out");
+            }
+            other => panic!("expected timeout exception, got {:?}", other),
+        }
+
+        // the late reply to the second call is discarded
+        assert!(call(&stream, 3, 0).unwrap());
+    }
+}
@@ -0,0 +1,323 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::protocol::TMessageIdentifier;
+
+use super::warn;
+
+/// Keeps track of the requests a server is processing.
+///
+/// Register a tracker with `TServer::set_request_tracker`, and keep a clone
+/// of the `Arc` to inspect the server while it runs: `snapshot` lists the
+/// requests being processed and how long each has taken so far, which shows
+/// what a stuck server is waiting on. With `with_slow_threshold`, every
+/// request that takes longer than the threshold is logged with its method,
+/// duration and peer address when it completes.
+///
+/// A request is tracked from the moment its message header has been read
+/// until the worker serving its connection is done with it. Oneway requests
+/// queued on the oneway pool are done once their arguments have been read.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use thrift::server::TRequestTracker;
+///
+/// let tracker = Arc::new(TRequestTracker::new().with_slow_threshold(Duration::from_secs(1)));
+/// // server.set_request_tracker(tracker.clone());
+///
+/// for request in tracker.snapshot() {
+///     println!("{} has been running for {:?}", request.method(), request.elapsed());
+/// }
+/// ```
+pub struct TRequestTracker {
+    slow_threshold: Option<Duration>,
+    next_id: AtomicU64,
+    in_flight: Mutex<HashMap<u64, Entry>>,
+}
+
+struct Entry {
+    method: String,
+    sequence_number: i32,
+    peer_addr: Option<SocketAddr>,
+    started: Instant,
+}
+
+impl TRequestTracker {
+    /// Create a `TRequestTracker` that does not log slow requests.
+    pub fn new() -> TRequestTracker {
+        TRequestTracker {
+            slow_threshold: None,
+            next_id: AtomicU64::new(0),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Log requests that take longer than `threshold`.
+    pub fn with_slow_threshold(mut self, threshold: Duration) -> TRequestTracker {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Return the requests being processed, the longest-running first.
+    pub fn snapshot(&self) -> Vec<TInFlightRequest> {
+        let now = Instant::now();
+        let mut requests: Vec<TInFlightRequest> = self
+            .entries()
+            .values()
+            .map(|entry| TInFlightRequest {
+                method: entry.method.clone(),
+                sequence_number: entry.sequence_number,
+                peer_addr: entry.peer_addr,
+                elapsed: now.saturating_duration_since(entry.started),
+            })
+            .collect();
+        requests.sort_by_key(|r| Reverse(r.elapsed));
+        requests
+    }
+
+    /// Start tracking the request identified by `ident`, until the returned
+    /// guard is dropped.
+    pub(super) fn start(
+        &self,
+        ident: &TMessageIdentifier,
+        peer_addr: Option<SocketAddr>,
+    ) -> TrackedRequest<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let entry = Entry {
+            method: ident.name.clone(),
+            sequence_number: ident.sequence_number,
+            peer_addr,
+            started: Instant::now(),
+        };
+        self.entries().insert(id, entry);
+        TrackedRequest { tracker: self, id }
+    }
+
+    fn finish(&self, id: u64) {
+        let entry = match self.entries().remove(&id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let elapsed = entry.started.elapsed();
+        if matches!(self.slow_threshold, Some(threshold) if elapsed > threshold) {
+            warn!(
+                "slow request {} from {:?} took {:?}",
+                entry.method, entry.peer_addr, elapsed
+            );
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for TRequestTracker {
+    fn default() -> Self {
+        TRequestTracker::new()
+    }
+}
+
+impl fmt::Debug for TRequestTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TRequestTracker")
+            .field("slow_threshold", &self.slow_threshold)
+            .field("in_flight", &self.entries().len())
+            .finish()
+    }
+}
+
+/// Guard that stops tracking a request when it is dropped, even if the
+/// handler panicked.
+pub(super) struct TrackedRequest<'a> {
+    tracker: &'a TRequestTracker,
+    id: u64,
+}
+
+impl Drop for TrackedRequest<'_> {
+    fn drop(&mut self) {
+        self.tracker.finish(self.id);
+    }
+}
+
+/// A request that was being processed when `TRequestTracker::snapshot` was
+/// called.
+#[derive(Clone, Debug)]
+pub struct TInFlightRequest {
+    method: String,
+    sequence_number: i32,
+    peer_addr: Option<SocketAddr>,
+    elapsed: Duration,
+}
+
+impl TInFlightRequest {
+    /// Name of the method called.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Sequence number of the request on its connection.
+    pub fn sequence_number(&self) -> i32 {
+        self.sequence_number
+    }
+
+    /// Address of the client, if the connection has one.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Time the request had been processed for.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TMessageType, TOutputProtocol,
+        TStructIdentifier, TType,
+    };
+    use crate::server::{TProcessor, TServerBuilder};
+
+    fn ident(name: &str, sequence_number: i32) -> TMessageIdentifier {
+        TMessageIdentifier::new(name, TMessageType::Call, sequence_number)
+    }
+
+    #[test]
+    fn must_list_requests_until_done() {
+        let tracker = TRequestTracker::new();
+        let first = tracker.start(&ident("first", 1), None);
+        thread::sleep(Duration::from_millis(5));
+        let second = tracker.start(&ident("second", 2), None);
+
+        let snapshot = tracker.snapshot();
+        let methods: Vec<&str> = snapshot.iter().map(|r| r.method()).collect();
+        assert_eq!(methods, ["first", "second"]);
+        assert!(snapshot[0].elapsed() >= Duration::from_millis(5));
+
+        drop(first);
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].sequence_number(), 2);
+        drop(second);
+        assert!(tracker.snapshot().is_empty());
+    }
+
+    #[test]
+    fn must_stop_tracking_when_handler_panics() {
+        let tracker = TRequestTracker::new().with_slow_threshold(Duration::ZERO);
+        let result = std::panic::catch_unwind(|| {
+            let _tracked = tracker.start(&ident("fragile", 1), None);
+            panic!("handler failed");
+        });
+        assert!(result.is_err());
+        assert!(tracker.snapshot().is_empty());
+    }
+
+    /// Waits for a signal before answering each call with an empty reply.
+    struct Gate(Mutex<Receiver<()>>);
+
+    impl TProcessor for Gate {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            i.skip(TType::Struct)?;
+            i.read_message_end()?;
+            self.0.lock().unwrap().recv().unwrap();
+            o.write_message_begin(&TMessageIdentifier::new(
+                ident.name,
+                TMessageType::Reply,
+                ident.sequence_number,
+            ))?;
+            o.write_message_end()?;
+            o.flush()
+        }
+    }
+
+    #[test]
+    fn must_list_requests_in_flight_on_server() {
+        let (release, gate) = channel();
+        let tracker = Arc::new(TRequestTracker::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = TServerBuilder::new(Gate(Mutex::new(gate)))
+            .workers(1)
+            .request_tracker(tracker.clone())
+            .build();
+        thread::spawn(move || server.listen_on(listener));
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut o_prot = TBinaryOutputProtocol::new(stream.try_clone().unwrap(), true);
+        o_prot.write_message_begin(&ident("stuck", 3)).unwrap();
+        o_prot
+            .write_struct_begin(&TStructIdentifier::new("stuck_args"))
+            .unwrap();
+        o_prot.write_field_stop().unwrap();
+        o_prot.write_struct_end().unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+
+        let mut snapshot = tracker.snapshot();
+        for _ in 0..500 {
+            if !snapshot.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            snapshot = tracker.snapshot();
+        }
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].method(), "stuck");
+        assert_eq!(snapshot[0].sequence_number(), 3);
+        assert_eq!(snapshot[0].peer_addr(), Some(stream.local_addr().unwrap()));
+
+        release.send(()).unwrap();
+        let mut i_prot = TBinaryInputProtocol::new(stream, true);
+        assert_eq!(
+            i_prot.read_message_begin().unwrap().message_type,
+            TMessageType::Reply
+        );
+        // the entry is removed after the reply has been written
+        for _ in 0..500 {
+            if tracker.snapshot().is_empty() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("request still tracked after its reply");
+    }
+}
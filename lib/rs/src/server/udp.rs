@@ -0,0 +1,288 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use crate::protocol::{TInputProtocolFactory, TOutputProtocolFactory};
+use crate::transport::{TBufferChannel, TUdpChannel};
+
+use super::{warn, TProcessor};
+
+/// Single-threaded Thrift server that receives requests as UDP datagrams.
+///
+/// Each datagram received by a `TUdpServer` must contain exactly one
+/// complete message, as sent by a client using `TUdpChannel`. Datagrams are
+/// processed sequentially on the thread that called `listen`. If the
+/// processor writes a reply it is sent back to the originating address as a
+/// single datagram; for `oneway` calls nothing is sent.
+///
+/// Datagrams are not framed or buffered on the server, so no transport
+/// factories are involved: protocols read directly from the received bytes.
+/// Processor errors are logged and the datagram dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TCompactInputProtocolFactory, TCompactOutputProtocolFactory};
+/// use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// use thrift::server::{TProcessor, TUdpServer};
+///
+/// struct TelemetryProcessor;
+/// impl TProcessor for TelemetryProcessor {
+///     fn process(&self, i: &mut dyn TInputProtocol, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+///         unimplemented!();
+///     }
+/// }
+///
+/// let mut server = TUdpServer::new(
+///     TCompactInputProtocolFactory::new(),
+///     TCompactOutputProtocolFactory::new(),
+///     TelemetryProcessor,
+/// );
+///
+/// match server.listen("0.0.0.0:9090") {
+///   Ok(_)  => println!("listen completed"),
+///   Err(e) => println!("listen failed with error {:?}", e),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TUdpServer<PRC, IPF, OPF>
+where
+    PRC: TProcessor,
+    IPF: TInputProtocolFactory,
+    OPF: TOutputProtocolFactory,
+{
+    i_proto_factory: IPF,
+    o_proto_factory: OPF,
+    processor: PRC,
+    max_datagram_size: usize,
+}
+
+impl<PRC, IPF, OPF> TUdpServer<PRC, IPF, OPF>
+where
+    PRC: TProcessor,
+    IPF: TInputProtocolFactory,
+    OPF: TOutputProtocolFactory,
+{
+    /// Create a `TUdpServer`.
+    ///
+    /// `input_protocol_factory` is used to decode received datagrams and
+    /// `output_protocol_factory` to encode replies.
+    pub fn new(
+        input_protocol_factory: IPF,
+        output_protocol_factory: OPF,
+        processor: PRC,
+    ) -> TUdpServer<PRC, IPF, OPF> {
+        TUdpServer {
+            i_proto_factory: input_protocol_factory,
+            o_proto_factory: output_protocol_factory,
+            processor,
+            max_datagram_size: TUdpChannel::DEFAULT_MAX_DATAGRAM_SIZE,
+        }
+    }
+
+    /// Set the maximum number of bytes received or sent in one datagram.
+    ///
+    /// Received datagrams larger than this are truncated and will fail to
+    /// decode; replies larger than this are dropped.
+    pub fn set_max_datagram_size(&mut self, size: usize) {
+        self.max_datagram_size = size;
+    }
+
+    /// Receive datagrams on `listen_address`.
+    ///
+    /// `listen_address` should implement `ToSocketAddrs` trait.
+    ///
+    /// Return `Err` when the server cannot bind to `listen_address` or there
+    /// is an unrecoverable error.
+    pub fn listen<A: ToSocketAddrs>(&mut self, listen_address: A) -> crate::Result<()> {
+        let socket = UdpSocket::bind(listen_address)?;
+        self.listen_on(socket)
+    }
+
+    /// Receive datagrams on an already bound `socket`.
+    pub fn listen_on(&mut self, socket: UdpSocket) -> crate::Result<()> {
+        let mut datagram = vec![0u8; self.max_datagram_size];
+        loop {
+            let (len, sender) = match socket.recv_from(&mut datagram) {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("failed to receive datagram with error {:?}", e);
+                    continue;
+                }
+            };
+
+            match self.process_datagram(&datagram[..len]) {
+                Ok(reply) if reply.is_empty() => {}
+                Ok(reply) if reply.len() > self.max_datagram_size => {
+                    warn!(
+                        "dropping {} byte reply to {} that exceeds maximum datagram size {}",
+                        reply.len(),
+                        sender,
+                        self.max_datagram_size
+                    );
+                }
+                Ok(reply) => {
+                    if let Err(e) = socket.send_to(&reply, sender) {
+                        warn!("failed to send reply to {} with error {:?}", sender, e);
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "processor completed with error for datagram from {}: {:?}",
+                        sender, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Run the processor over one datagram and return the reply bytes, if
+    /// any.
+    fn process_datagram(&self, datagram: &[u8]) -> crate::Result<Vec<u8>> {
+        let mut r_chan = TBufferChannel::new();
+        r_chan.set_readable_bytes(datagram);
+        let mut w_chan = TBufferChannel::new();
+
+        let mut i_prot = self.i_proto_factory.create(Box::new(r_chan));
+        let mut o_prot = self.o_proto_factory.create(Box::new(w_chan.clone()));
+        self.processor.process(&mut *i_prot, &mut *o_prot)?;
+
+        Ok(w_chan.take_written_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TInputProtocol, TMessageIdentifier, TMessageType,
+        TOutputProtocol,
+    };
+
+    struct EchoProcessor {
+        received: Mutex<Sender<String>>,
+    }
+
+    impl TProcessor for EchoProcessor {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> crate::Result<()> {
+            let ident = i.read_message_begin()?;
+            let payload = i.read_string()?;
+            i.read_message_end()?;
+            self.received.lock().unwrap().send(payload.clone()).unwrap();
+
+            if ident.message_type == TMessageType::Call {
+                o.write_message_begin(&TMessageIdentifier::new(
+                    ident.name,
+                    TMessageType::Reply,
+                    ident.sequence_number,
+                ))?;
+                o.write_string(&payload)?;
+                o.write_message_end()?;
+                o.flush()?;
+            }
+            Ok(())
+        }
+    }
+
+    fn start_server() -> (TUdpChannel, Receiver<String>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (tx, rx) = channel();
+        let mut server = TUdpServer::new(
+            TBinaryInputProtocolFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+            EchoProcessor {
+                received: Mutex::new(tx),
+            },
+        );
+        thread::spawn(move || server.listen_on(socket));
+
+        let mut client = TUdpChannel::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (client, rx)
+    }
+
+    fn send(client: &mut TUdpChannel, message_type: TMessageType, payload: &str) {
+        let mut o_prot = TBinaryOutputProtocol::new(&mut *client, true);
+        o_prot
+            .write_message_begin(&TMessageIdentifier::new("log", message_type, 7))
+            .unwrap();
+        o_prot.write_string(payload).unwrap();
+        o_prot.write_message_end().unwrap();
+        o_prot.flush().unwrap();
+    }
+
+    #[test]
+    fn must_process_oneway_datagrams_without_reply() {
+        let (mut client, rx) = start_server();
+
+        send(&mut client, TMessageType::OneWay, "first");
+        send(&mut client, TMessageType::OneWay, "second");
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), "first");
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), "second");
+
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut buf = [0u8; 16];
+        assert!(client.read(&mut buf).is_err());
+    }
+
+    #[test]
+    fn must_send_reply_to_sender_as_one_datagram() {
+        let (mut client, rx) = start_server();
+
+        send(&mut client, TMessageType::Call, "echo me");
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "echo me");
+
+        let mut i_prot = TBinaryInputProtocol::new(&mut client, true);
+        let ident = i_prot.read_message_begin().unwrap();
+        assert_eq!(ident.message_type, TMessageType::Reply);
+        assert_eq!(ident.sequence_number, 7);
+        assert_eq!(i_prot.read_string().unwrap(), "echo me");
+        i_prot.read_message_end().unwrap();
+    }
+
+    #[test]
+    fn must_keep_serving_after_malformed_datagram() {
+        let (mut client, rx) = start_server();
+
+        client.write_all(&[0xff, 0x00]).unwrap();
+        client.flush().unwrap();
+        send(&mut client, TMessageType::OneWay, "still alive");
+
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "still alive"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use crate::{ApplicationError, ApplicationErrorKind};
+
+/// Limits on the reply bytes a connection may hold before they are sent.
+///
+/// When requests of a connection are processed concurrently (see
+/// `TServer::set_concurrent_requests`), each reply is built in memory and
+/// then waits for its turn on the connection. A client that sends requests
+/// faster than it reads replies would make these pile up. Once the replies
+/// waiting on a connection reach `high` bytes, the server stops reading
+/// requests from that connection until they have been sent down to `low`
+/// bytes; the client then sees its own writes slow down.
+///
+/// Connections whose requests are processed one at a time never hold more
+/// than the reply being written, since the worker blocks on the socket
+/// until the client reads it.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::server::TWriteWatermarks;
+///
+/// // pause a connection at 8 MiB of unsent replies, resume at 1 MiB
+/// let watermarks = TWriteWatermarks::new(1024 * 1024, 8 * 1024 * 1024).unwrap();
+/// // server.set_write_watermarks(watermarks);
+/// assert_eq!(watermarks.high(), 8 * 1024 * 1024);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TWriteWatermarks {
+    low: usize,
+    high: usize,
+}
+
+impl TWriteWatermarks {
+    /// Create watermarks that pause a connection at `high` bytes of unsent
+    /// replies and resume it at `low` bytes.
+    ///
+    /// Return `Err` if `high` is `0` or `low` is larger than `high`.
+    pub fn new(low: usize, high: usize) -> crate::Result<TWriteWatermarks> {
+        if high == 0 || low > high {
+            return Err(crate::Error::Application(ApplicationError::new(
+                ApplicationErrorKind::Unknown,
+                format!(
+                    "invalid write watermarks: low ({}) must not exceed high ({}), which must be positive",
+                    low, high
+                ),
+            )));
+        }
+        Ok(TWriteWatermarks { low, high })
+    }
+
+    /// Unsent reply bytes at or below which a paused connection resumes.
+    pub fn low(&self) -> usize {
+        self.low
+    }
+
+    /// Unsent reply bytes at which a connection is paused.
+    pub fn high(&self) -> usize {
+        self.high
+    }
+}
+
+/// Reply bytes of one connection that have been built but not yet sent.
+pub(super) struct WriteBacklog {
+    watermarks: Option<TWriteWatermarks>,
+    bytes: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl WriteBacklog {
+    pub(super) fn new(watermarks: Option<TWriteWatermarks>) -> WriteBacklog {
+        WriteBacklog {
+            watermarks,
+            bytes: Mutex::new(0),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Count `reply` bytes as waiting to be sent until the returned guard is
+    /// dropped.
+    pub(super) fn hold(&self, reply: usize) -> HeldReply<'_> {
+        *self.lock() += reply;
+        HeldReply {
+            backlog: self,
+            bytes: reply,
+        }
+    }
+
+    /// Wait until the connection may read another request: immediately if
+    /// it is below its high watermark, otherwise once it is back down to
+    /// its low watermark.
+    pub(super) fn wait_for_room(&self) {
+        let watermarks = match self.watermarks {
+            Some(watermarks) => watermarks,
+            None => return,
+        };
+        let mut bytes = self.lock();
+        if *bytes < watermarks.high {
+            return;
+        }
+        while *bytes > watermarks.low {
+            bytes = self.changed.wait(bytes).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.bytes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Reply bytes counted in a `WriteBacklog` until they have been sent.
+pub(super) struct HeldReply<'a> {
+    backlog: &'a WriteBacklog,
+    bytes: usize,
+}
+
+impl Drop for HeldReply<'_> {
+    fn drop(&mut self) {
+        *self.backlog.lock() -= self.bytes;
+        self.backlog.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn must_reject_invalid_watermarks() {
+        assert!(TWriteWatermarks::new(0, 0).is_err());
+        assert!(TWriteWatermarks::new(10, 5).is_err());
+        assert!(TWriteWatermarks::new(5, 5).is_ok());
+    }
+
+    #[test]
+    fn must_pause_above_high_watermark_until_below_low_watermark() {
+        let backlog = Arc::new(WriteBacklog::new(Some(
+            TWriteWatermarks::new(10, 100).unwrap(),
+        )));
+        let first = backlog.hold(60);
+        backlog.wait_for_room(); // below the high watermark
+        let second = backlog.hold(60);
+
+        let (resumed_tx, resumed) = mpsc::channel();
+        let waiting = backlog.clone();
+        thread::spawn(move || {
+            waiting.wait_for_room();
+            resumed_tx.send(()).unwrap();
+        });
+        assert!(resumed.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(first); // 60 bytes left, still above the low watermark
+        assert!(resumed.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(second);
+        resumed.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn must_never_pause_without_watermarks() {
+        let backlog = WriteBacklog::new(None);
+        let _held = backlog.hold(usize::MAX / 2);
+        backlog.wait_for_room();
+    }
+}
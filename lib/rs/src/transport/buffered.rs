@@ -17,9 +17,12 @@
 
 use std::cmp;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufRead, IoSlice, Read, Write};
 
-use super::{TReadTransport, TReadTransportFactory, TWriteTransport, TWriteTransportFactory};
+use super::{
+    TBufferPool, TPeekableReadTransport, TPooledBuffer, TReadTransport, TReadTransportFactory,
+    TWriteTransport, TWriteTransportFactory,
+};
 
 /// Default capacity of the read buffer in bytes.
 const READ_CAPACITY: usize = 4096;
@@ -27,6 +30,198 @@ const READ_CAPACITY: usize = 4096;
 /// Default capacity of the write buffer in bytes..
 const WRITE_CAPACITY: usize = 4096;
 
+/// How the internal buffer of a buffered transport grows when a message does
+/// not fit into it.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TBufferGrowth {
+    /// Double the capacity until the message fits.
+    #[default]
+    Double,
+    /// Grow to exactly the size required by the message.
+    Exact,
+}
+
+/// When a buffer that grew past its initial capacity is shrunk back to it.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TBufferShrink {
+    /// Shrink as soon as the oversized message has been flushed or consumed.
+    #[default]
+    AfterMessage,
+    /// Shrink once this many consecutive messages have fit into the initial
+    /// capacity. Avoids reallocating on every message when large messages
+    /// are frequent.
+    AfterIdle(u32),
+    /// Keep the grown buffer for the lifetime of the transport.
+    Never,
+}
+
+/// Sizing policy for the internal buffer of `TBufferedReadTransport` and
+/// `TBufferedWriteTransport`.
+///
+/// A buffer starts at `initial_capacity` bytes. A message that does not fit
+/// grows it according to `growth`, but never beyond `max_capacity`; larger
+/// messages are streamed through the buffer in `max_capacity`-sized chunks.
+/// `shrink` decides when a grown buffer is released again, so that one large
+/// message does not pin a large buffer for the lifetime of a connection.
+///
+/// The default policy is a fixed 4096-byte buffer that never grows.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::transport::{TBufferPolicy, TBufferShrink};
+///
+/// let policy = TBufferPolicy::builder()
+///     .initial_capacity(8 * 1024)
+///     .max_capacity(1024 * 1024)
+///     .shrink(TBufferShrink::AfterIdle(16))
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(policy.max_capacity(), 1024 * 1024);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TBufferPolicy {
+    initial_capacity: usize,
+    max_capacity: usize,
+    growth: TBufferGrowth,
+    shrink: TBufferShrink,
+}
+
+impl TBufferPolicy {
+    /// Return a builder for a `TBufferPolicy`, starting from the default
+    /// policy.
+    pub fn builder() -> TBufferPolicyBuilder {
+        TBufferPolicyBuilder::default()
+    }
+
+    /// A buffer of `capacity` bytes that never grows.
+    fn fixed(capacity: usize) -> TBufferPolicy {
+        TBufferPolicy {
+            initial_capacity: capacity,
+            max_capacity: capacity,
+            growth: TBufferGrowth::default(),
+            shrink: TBufferShrink::default(),
+        }
+    }
+
+    pub fn initial_capacity(&self) -> usize {
+        self.initial_capacity
+    }
+
+    pub fn max_capacity(&self) -> usize {
+        self.max_capacity
+    }
+
+    pub fn growth(&self) -> TBufferGrowth {
+        self.growth
+    }
+
+    pub fn shrink(&self) -> TBufferShrink {
+        self.shrink
+    }
+
+    /// Capacity to grow from `current` to so that `needed` bytes fit, capped
+    /// at the maximum capacity.
+    fn grown_capacity(&self, current: usize, needed: usize) -> usize {
+        let grown = match self.growth {
+            TBufferGrowth::Double => {
+                let mut capacity = cmp::max(current, 1);
+                while capacity < needed {
+                    capacity = capacity.saturating_mul(2);
+                }
+                capacity
+            }
+            TBufferGrowth::Exact => needed,
+        };
+        cmp::min(grown, self.max_capacity)
+    }
+}
+
+impl Default for TBufferPolicy {
+    fn default() -> Self {
+        TBufferPolicy::fixed(READ_CAPACITY)
+    }
+}
+
+/// Builder for `TBufferPolicy`.
+#[derive(Debug, Default)]
+pub struct TBufferPolicyBuilder {
+    policy: TBufferPolicy,
+}
+
+impl TBufferPolicyBuilder {
+    /// Capacity the buffer is allocated with and shrinks back to. If the
+    /// maximum capacity is smaller it is raised to this value.
+    pub fn initial_capacity(mut self, capacity: usize) -> Self {
+        self.policy.initial_capacity = capacity;
+        self.policy.max_capacity = cmp::max(self.policy.max_capacity, capacity);
+        self
+    }
+
+    /// Largest capacity the buffer may grow to.
+    pub fn max_capacity(mut self, capacity: usize) -> Self {
+        self.policy.max_capacity = capacity;
+        self
+    }
+
+    pub fn growth(mut self, growth: TBufferGrowth) -> Self {
+        self.policy.growth = growth;
+        self
+    }
+
+    pub fn shrink(mut self, shrink: TBufferShrink) -> Self {
+        self.policy.shrink = shrink;
+        self
+    }
+
+    pub fn build(self) -> crate::Result<TBufferPolicy> {
+        let policy = self.policy;
+        if policy.initial_capacity == 0 {
+            return Err(crate::Error::Transport(crate::TransportError::new(
+                crate::TransportErrorKind::SizeLimit,
+                "Invalid buffer policy: initial_capacity must be positive",
+            )));
+        }
+        if policy.max_capacity < policy.initial_capacity {
+            return Err(crate::Error::Transport(crate::TransportError::new(
+                crate::TransportErrorKind::SizeLimit,
+                format!(
+                    "Invalid buffer policy: max_capacity ({}) cannot be less than initial_capacity ({})",
+                    policy.max_capacity, policy.initial_capacity
+                ),
+            )));
+        }
+        Ok(policy)
+    }
+}
+
+/// Tracks when a grown buffer should shrink back to its initial capacity.
+#[derive(Debug, Default)]
+struct ShrinkState {
+    idle: u32,
+}
+
+impl ShrinkState {
+    /// Record a message of `len` bytes and return whether a buffer of
+    /// `capacity` bytes should now shrink.
+    fn should_shrink(&mut self, policy: &TBufferPolicy, capacity: usize, len: usize) -> bool {
+        if len > policy.initial_capacity {
+            self.idle = 0;
+        } else {
+            self.idle = self.idle.saturating_add(1);
+        }
+        if capacity <= policy.initial_capacity {
+            return false;
+        }
+        match policy.shrink {
+            TBufferShrink::AfterMessage => true,
+            TBufferShrink::AfterIdle(n) => self.idle >= n,
+            TBufferShrink::Never => false,
+        }
+    }
+}
+
 /// Transport that reads messages via an internal buffer.
 ///
 /// A `TBufferedReadTransport` maintains a fixed-size internal read buffer.
@@ -51,15 +246,24 @@ const WRITE_CAPACITY: usize = 4096;
 ///
 /// t.read(&mut vec![0u8; 1]).unwrap();
 /// ```
+///
+/// A transport created with `with_policy` grows its buffer to service reads
+/// larger than the buffer, and shrinks it again according to the
+/// `TBufferPolicy`.
+///
+/// A transport created with `with_buffer_pool` checks its buffer out of a
+/// [`TBufferPool`] on the first read and returns it when dropped.
 #[derive(Debug)]
 pub struct TBufferedReadTransport<C>
 where
     C: Read,
 {
-    buf: Box<[u8]>,
+    buf: TPooledBuffer,
     pos: usize,
     cap: usize,
     chan: C,
+    policy: TBufferPolicy,
+    shrink_state: ShrinkState,
 }
 
 impl<C> TBufferedReadTransport<C>
@@ -76,16 +280,54 @@ where
     /// `read_capacity` and an internal write buffer of size
     /// `write_capacity` that wraps the given `TIoChannel`.
     pub fn with_capacity(read_capacity: usize, channel: C) -> TBufferedReadTransport<C> {
+        TBufferedReadTransport::with_policy(channel, TBufferPolicy::fixed(read_capacity))
+    }
+
+    /// Create a `TBufferedReadTransport` whose internal read buffer is sized
+    /// according to `policy` that wraps the given `TIoChannel`.
+    pub fn with_policy(channel: C, policy: TBufferPolicy) -> TBufferedReadTransport<C> {
         TBufferedReadTransport {
-            buf: vec![0; read_capacity].into_boxed_slice(),
+            buf: TPooledBuffer::unpooled(vec![0; policy.initial_capacity]),
             pos: 0,
             cap: 0,
             chan: channel,
+            policy,
+            shrink_state: ShrinkState::default(),
+        }
+    }
+
+    /// Check the read buffer out of `pool` on the first read instead of
+    /// allocating it. Call before reading from the transport.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TBufferedReadTransport<C> {
+        TBufferedReadTransport {
+            buf: TPooledBuffer::pooled(pool),
+            pos: 0,
+            cap: 0,
+            ..self
         }
     }
 
-    fn get_bytes(&mut self) -> io::Result<&[u8]> {
+    /// Current size of the internal read buffer.
+    pub fn buffer_capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Make sure a pooled read buffer has been checked out.
+    fn acquire_buffer(&mut self) {
+        let initial_capacity = self.policy.initial_capacity;
+        if self.buf.len() < initial_capacity {
+            self.buf.acquire(initial_capacity);
+            self.buf.resize(initial_capacity, 0);
+        }
+    }
+
+    /// Return the buffered bytes, refilling the buffer from the channel if
+    /// it is empty. `wanted` is the number of bytes the caller would like to
+    /// read, and is used to grow the buffer.
+    fn get_bytes(&mut self, wanted: usize) -> io::Result<&[u8]> {
         if self.cap - self.pos == 0 {
+            self.acquire_buffer();
+            self.resize_for(wanted);
             self.pos = 0;
             self.cap = self.chan.read(&mut self.buf)?;
         }
@@ -93,6 +335,22 @@ where
         Ok(&self.buf[self.pos..self.cap])
     }
 
+    fn resize_for(&mut self, wanted: usize) {
+        let capacity = self.buf.len();
+        if wanted > capacity {
+            let grown = self.policy.grown_capacity(capacity, wanted);
+            if grown > capacity {
+                self.buf.resize(grown, 0);
+            }
+        } else if self
+            .shrink_state
+            .should_shrink(&self.policy, capacity, wanted)
+        {
+            self.buf.truncate(self.policy.initial_capacity);
+            self.buf.shrink_to_fit();
+        }
+    }
+
     fn consume(&mut self, consumed: usize) {
         // TODO: was a bug here += <-- test somehow
         self.pos = cmp::min(self.cap, self.pos + consumed);
@@ -108,8 +366,8 @@ where
 
         loop {
             let nread = {
-                let avail_bytes = self.get_bytes()?;
                 let avail_space = buf.len() - bytes_read;
+                let avail_bytes = self.get_bytes(avail_space)?;
                 let nread = cmp::min(avail_space, avail_bytes.len());
                 buf[bytes_read..(bytes_read + nread)].copy_from_slice(&avail_bytes[..nread]);
                 nread
@@ -127,20 +385,94 @@ where
     }
 }
 
+impl<C> BufRead for TBufferedReadTransport<C>
+where
+    C: Read,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.get_bytes(0)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.cap, self.pos + amt);
+    }
+}
+
+/// Waits until `buf.len()` bytes are buffered, the buffer cannot grow any
+/// further, or the channel reaches the end of the stream.
+impl<C> TPeekableReadTransport for TBufferedReadTransport<C>
+where
+    C: Read,
+{
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cap - self.pos < buf.len() {
+            self.acquire_buffer();
+
+            // move the buffered bytes to the front to make room for more
+            self.buf.copy_within(self.pos..self.cap, 0);
+            self.cap -= self.pos;
+            self.pos = 0;
+
+            let capacity = self.buf.len();
+            if buf.len() > capacity {
+                let grown = self.policy.grown_capacity(capacity, buf.len());
+                if grown > capacity {
+                    self.buf.resize(grown, 0);
+                }
+            }
+
+            let wanted = cmp::min(buf.len(), self.buf.len());
+            while self.cap < wanted {
+                match self.chan.read(&mut self.buf[self.cap..]) {
+                    Ok(0) => break,
+                    Ok(n) => self.cap += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let npeek = cmp::min(buf.len(), self.cap - self.pos);
+        buf[..npeek].copy_from_slice(&self.buf[self.pos..self.pos + npeek]);
+        Ok(npeek)
+    }
+}
+
 /// Factory for creating instances of `TBufferedReadTransport`.
-#[derive(Default)]
-pub struct TBufferedReadTransportFactory;
+#[derive(Debug, Default)]
+pub struct TBufferedReadTransportFactory {
+    policy: TBufferPolicy,
+    pool: Option<TBufferPool>,
+}
 
 impl TBufferedReadTransportFactory {
     pub fn new() -> TBufferedReadTransportFactory {
-        TBufferedReadTransportFactory {}
+        TBufferedReadTransportFactory::default()
+    }
+
+    /// Create a `TBufferedReadTransportFactory` whose transports size their
+    /// read buffer according to `policy`.
+    pub fn with_policy(policy: TBufferPolicy) -> TBufferedReadTransportFactory {
+        TBufferedReadTransportFactory { policy, pool: None }
+    }
+
+    /// Make the created transports check their buffer out of `pool`.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TBufferedReadTransportFactory {
+        TBufferedReadTransportFactory {
+            pool: Some(pool),
+            ..self
+        }
     }
 }
 
 impl TReadTransportFactory for TBufferedReadTransportFactory {
     /// Create a `TBufferedReadTransport`.
     fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
-        Box::new(TBufferedReadTransport::new(channel))
+        let transport = TBufferedReadTransport::with_policy(channel, self.policy);
+        match self.pool {
+            Some(ref pool) => Box::new(transport.with_buffer_pool(pool.clone())),
+            None => Box::new(transport),
+        }
     }
 }
 
@@ -168,14 +500,23 @@ impl TReadTransportFactory for TBufferedReadTransportFactory {
 /// t.write(&[0x00]).unwrap();
 /// t.flush().unwrap();
 /// ```
+///
+/// A transport created with `with_policy` grows its buffer instead of
+/// flushing part of a message that does not fit, and shrinks it again after
+/// a flush according to the `TBufferPolicy`.
+///
+/// A transport created with `with_buffer_pool` checks its buffer out of a
+/// [`TBufferPool`] on the first write and returns it when dropped.
 #[derive(Debug)]
 pub struct TBufferedWriteTransport<C>
 where
     C: Write,
 {
-    buf: Vec<u8>,
+    buf: TPooledBuffer,
     cap: usize,
     channel: C,
+    policy: TBufferPolicy,
+    shrink_state: ShrinkState,
 }
 
 impl<C> TBufferedWriteTransport<C>
@@ -197,10 +538,49 @@ where
             "write buffer size must be a positive integer"
         );
 
+        TBufferedWriteTransport::with_policy(channel, TBufferPolicy::fixed(write_capacity))
+    }
+
+    /// Create a `TBufferedWriteTransport` whose internal write buffer is
+    /// sized according to `policy` that wraps the given `TIoChannel`.
+    pub fn with_policy(channel: C, policy: TBufferPolicy) -> TBufferedWriteTransport<C> {
+        assert!(
+            policy.initial_capacity > 0,
+            "write buffer size must be a positive integer"
+        );
+
         TBufferedWriteTransport {
-            buf: Vec::with_capacity(write_capacity),
-            cap: write_capacity,
+            buf: TPooledBuffer::unpooled(Vec::with_capacity(policy.initial_capacity)),
+            cap: policy.initial_capacity,
             channel,
+            policy,
+            shrink_state: ShrinkState::default(),
+        }
+    }
+
+    /// Check the write buffer out of `pool` on the first write instead of
+    /// allocating it. Call before writing to the transport.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TBufferedWriteTransport<C> {
+        TBufferedWriteTransport {
+            buf: TPooledBuffer::pooled(pool),
+            ..self
+        }
+    }
+
+    /// Current size of the internal write buffer.
+    pub fn buffer_capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Grow the buffer, if the policy allows, so that `additional` more bytes
+    /// fit into it.
+    fn reserve(&mut self, additional: usize) {
+        self.buf.acquire(self.cap);
+        let needed = self.buf.len().saturating_add(additional);
+        if needed > self.cap {
+            self.cap = self.policy.grown_capacity(self.cap, needed);
+            let additional = self.cap - self.buf.len();
+            self.buf.reserve(additional);
         }
     }
 }
@@ -211,6 +591,8 @@ where
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         if !buf.is_empty() {
+            self.reserve(buf.len());
+
             let mut avail_bytes;
 
             loop {
@@ -234,34 +616,86 @@ where
         }
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let total_len = bufs.iter().map(|b| b.len()).sum::<usize>();
+        self.reserve(total_len);
+
+        if total_len > self.cap - self.buf.len() {
+            let result = self.channel.write_all(&self.buf);
+            self.buf.clear();
+            result?;
+        }
+
+        if total_len >= self.cap {
+            // too large to buffer: hand the slices straight to the channel
+            // instead of copying them through the write buffer
+            self.channel.write_vectored(bufs)
+        } else {
+            for b in bufs {
+                self.buf.extend_from_slice(b);
+            }
+            Ok(total_len)
+        }
+    }
+
     fn flush(&mut self) -> io::Result<()> {
-        self.channel.write_all(&self.buf)?;
-        self.channel.flush()?;
+        // the buffer is emptied even if the write fails, so that a partly
+        // sent message is not sent again in front of the next one
+        let result = self
+            .channel
+            .write_all(&self.buf)
+            .and_then(|_| self.channel.flush());
+        let len = self.buf.len();
         self.buf.clear();
-        Ok(())
+        if self.shrink_state.should_shrink(&self.policy, self.cap, len) {
+            self.cap = self.policy.initial_capacity;
+            self.buf.shrink_to(self.cap);
+        }
+        result
     }
 }
 
 /// Factory for creating instances of `TBufferedWriteTransport`.
-#[derive(Default)]
-pub struct TBufferedWriteTransportFactory;
+#[derive(Debug, Default)]
+pub struct TBufferedWriteTransportFactory {
+    policy: TBufferPolicy,
+    pool: Option<TBufferPool>,
+}
 
 impl TBufferedWriteTransportFactory {
     pub fn new() -> TBufferedWriteTransportFactory {
-        TBufferedWriteTransportFactory {}
+        TBufferedWriteTransportFactory::default()
+    }
+
+    /// Create a `TBufferedWriteTransportFactory` whose transports size their
+    /// write buffer according to `policy`.
+    pub fn with_policy(policy: TBufferPolicy) -> TBufferedWriteTransportFactory {
+        TBufferedWriteTransportFactory { policy, pool: None }
+    }
+
+    /// Make the created transports check their buffer out of `pool`.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TBufferedWriteTransportFactory {
+        TBufferedWriteTransportFactory {
+            pool: Some(pool),
+            ..self
+        }
     }
 }
 
 impl TWriteTransportFactory for TBufferedWriteTransportFactory {
     /// Create a `TBufferedWriteTransport`.
     fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
-        Box::new(TBufferedWriteTransport::new(channel))
+        let transport = TBufferedWriteTransport::with_policy(channel, self.policy);
+        match self.pool {
+            Some(ref pool) => Box::new(transport.with_buffer_pool(pool.clone())),
+            None => Box::new(transport),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Read, Write};
+    use std::io::{BufRead, IoSlice, Read, Write};
 
     use super::*;
     use crate::transport::TBufferChannel;
@@ -277,6 +711,20 @@ mod tests {
         assert_eq!(read_result.unwrap(), 0);
     }
 
+    #[test]
+    fn must_expose_buffered_bytes_through_fill_buf() {
+        let mem = TBufferChannel::with_capacity(6, 0);
+        let mut t = TBufferedReadTransport::with_capacity(4, mem);
+
+        t.chan.set_readable_bytes(&[0, 1, 2, 3, 4, 5]);
+
+        assert_eq!(t.fill_buf().unwrap(), &[0, 1, 2, 3]);
+        t.consume(3);
+        assert_eq!(t.fill_buf().unwrap(), &[3]);
+        t.consume(1);
+        assert_eq!(t.fill_buf().unwrap(), &[4, 5]);
+    }
+
     #[test]
     fn must_return_zero_if_caller_reads_into_zero_capacity_buffer() {
         let mem = TBufferChannel::with_capacity(10, 0);
@@ -457,6 +905,34 @@ mod tests {
         assert_eq_transport_written_bytes!(t, b);
     }
 
+    #[test]
+    fn must_buffer_small_vectored_writes() {
+        let mem = TBufferChannel::with_capacity(0, 10);
+        let mut t = TBufferedWriteTransport::with_capacity(10, mem);
+
+        let bufs = [IoSlice::new(&[0, 1]), IoSlice::new(&[2, 3, 4])];
+        assert_eq!(t.write_vectored(&bufs).unwrap(), 5);
+        assert_eq_transport_num_written_bytes!(t, 0);
+
+        assert!(t.flush().is_ok());
+
+        let expected: [u8; 5] = [0, 1, 2, 3, 4];
+        assert_eq_transport_written_bytes!(t, expected);
+    }
+
+    #[test]
+    fn must_bypass_write_buffer_for_large_vectored_writes() {
+        let mem = TBufferChannel::with_capacity(0, 10);
+        let mut t = TBufferedWriteTransport::with_capacity(4, mem);
+
+        assert_eq!(t.write(&[0, 1]).unwrap(), 2);
+
+        // buffered bytes go out first, then the slices without being buffered
+        let bufs = [IoSlice::new(&[2, 3]), IoSlice::new(&[4, 5, 6])];
+        assert_eq!(t.write_vectored(&bufs).unwrap(), 2);
+        assert_eq_transport_num_written_bytes!(t, 4);
+    }
+
     #[test]
     fn must_write_successfully_after_flush() {
         let mem = TBufferChannel::with_capacity(0, 5);
@@ -480,4 +956,231 @@ mod tests {
         // check the flushed bytes
         assert_eq_transport_written_bytes!(t, b);
     }
+
+    fn growable_policy(shrink: TBufferShrink) -> TBufferPolicy {
+        TBufferPolicy::builder()
+            .initial_capacity(4)
+            .max_capacity(16)
+            .shrink(shrink)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn must_reject_invalid_buffer_policy() {
+        assert!(TBufferPolicy::builder()
+            .initial_capacity(0)
+            .build()
+            .is_err());
+        assert!(TBufferPolicy::builder()
+            .initial_capacity(8)
+            .max_capacity(4)
+            .build()
+            .is_err());
+        // raising the initial capacity raises the maximum along with it
+        let policy = TBufferPolicy::builder()
+            .initial_capacity(8192)
+            .build()
+            .unwrap();
+        assert_eq!(policy.max_capacity(), 8192);
+    }
+
+    #[test]
+    fn must_grow_write_buffer_instead_of_flushing_partial_message() {
+        let mem = TBufferChannel::with_capacity(0, 32);
+        let mut t =
+            TBufferedWriteTransport::with_policy(mem, growable_policy(TBufferShrink::Never));
+
+        let b = [0u8; 10];
+        assert_eq!(t.write(&b).unwrap(), 10);
+        assert_eq!(t.buffer_capacity(), 16);
+        assert_eq_transport_num_written_bytes!(t, 0);
+
+        t.flush().unwrap();
+        assert_eq_transport_num_written_bytes!(t, 10);
+        assert_eq!(t.buffer_capacity(), 16);
+    }
+
+    #[test]
+    fn must_not_grow_write_buffer_past_max_capacity() {
+        let mem = TBufferChannel::with_capacity(0, 32);
+        let mut t =
+            TBufferedWriteTransport::with_policy(mem, growable_policy(TBufferShrink::AfterMessage));
+
+        let b = [0u8; 20];
+        assert_eq!(t.write(&b).unwrap(), 16);
+        assert_eq!(t.buffer_capacity(), 16);
+        assert_eq!(t.write(&b[16..]).unwrap(), 4);
+        assert_eq_transport_num_written_bytes!(t, 16);
+    }
+
+    #[test]
+    fn must_shrink_write_buffer_after_oversized_message() {
+        let mem = TBufferChannel::with_capacity(0, 32);
+        let mut t =
+            TBufferedWriteTransport::with_policy(mem, growable_policy(TBufferShrink::AfterMessage));
+
+        t.write_all(&[0u8; 12]).unwrap();
+        assert_eq!(t.buffer_capacity(), 16);
+        t.flush().unwrap();
+        assert_eq!(t.buffer_capacity(), 4);
+    }
+
+    #[test]
+    fn must_shrink_write_buffer_after_idle_messages() {
+        let mem = TBufferChannel::with_capacity(0, 64);
+        let mut t =
+            TBufferedWriteTransport::with_policy(mem, growable_policy(TBufferShrink::AfterIdle(2)));
+
+        t.write_all(&[0u8; 12]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.buffer_capacity(), 16);
+
+        t.write_all(&[0u8; 2]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.buffer_capacity(), 16);
+
+        t.write_all(&[0u8; 2]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.buffer_capacity(), 4);
+    }
+
+    #[test]
+    fn must_grow_read_buffer_for_large_reads_and_shrink_afterwards() {
+        let mem = TBufferChannel::with_capacity(32, 0);
+        let mut t = TBufferedReadTransport::with_policy(
+            mem,
+            TBufferPolicy::builder()
+                .initial_capacity(4)
+                .max_capacity(16)
+                .growth(TBufferGrowth::Exact)
+                .build()
+                .unwrap(),
+        );
+        t.chan.set_readable_bytes(&[1; 32]);
+
+        let mut buf = [0u8; 10];
+        assert_eq!(t.read(&mut buf).unwrap(), 10);
+        assert_eq!(t.buffer_capacity(), 10);
+
+        let mut buf = [0u8; 2];
+        assert_eq!(t.read(&mut buf).unwrap(), 2);
+        assert_eq!(t.buffer_capacity(), 4);
+
+        // grows to the maximum for the bulk of the read, then shrinks again
+        // for the final refill, which only needs 2 bytes
+        let mut buf = [0u8; 20];
+        assert_eq!(t.read(&mut buf).unwrap(), 20);
+        assert_eq!(buf, [1; 20]);
+        assert_eq!(t.buffer_capacity(), 4);
+    }
+
+    #[test]
+    fn must_discard_buffered_bytes_when_flush_fails() {
+        let mem = TBufferChannel::with_capacity(0, 4);
+        let mut t = TBufferedWriteTransport::with_capacity(16, mem);
+
+        // the channel only has room for 4 bytes
+        t.write_all(&[1; 8]).unwrap();
+        assert!(t.flush().is_err());
+
+        t.channel.empty_write_buffer();
+        t.write_all(&[2; 3]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.channel.write_bytes(), vec![2; 3]);
+    }
+
+    #[test]
+    fn must_peek_without_consuming() {
+        let mem = TBufferChannel::with_capacity(10, 0);
+        let mut t = TBufferedReadTransport::with_capacity(10, mem);
+        t.chan.set_readable_bytes(&[0x01, 0x02, 0x03, 0x04]);
+
+        let mut peeked = [0u8; 2];
+        assert_eq!(t.peek(&mut peeked).unwrap(), 2);
+        assert_eq!(peeked, [0x01, 0x02]);
+
+        let mut read = [0u8; 4];
+        t.read_exact(&mut read).unwrap();
+        assert_eq!(read, [0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(t.peek(&mut peeked).unwrap(), 0);
+    }
+
+    #[test]
+    fn must_peek_across_short_channel_reads() {
+        // yields one byte per read
+        struct Trickle(Vec<u8>);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() || buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = self.0.remove(0);
+                Ok(1)
+            }
+        }
+
+        let mut t = TBufferedReadTransport::with_capacity(4, Trickle(vec![1, 2, 3, 4, 5, 6]));
+
+        let mut first = [0u8; 1];
+        t.read_exact(&mut first).unwrap();
+        assert_eq!(first, [1]);
+
+        // more than the buffer holds after the partial read
+        let mut peeked = [0u8; 4];
+        assert_eq!(t.peek(&mut peeked).unwrap(), 4);
+        assert_eq!(peeked, [2, 3, 4, 5]);
+
+        // fewer bytes are left than requested
+        let mut rest = Vec::new();
+        let mut peeked = [0u8; 8];
+        assert_eq!(t.peek(&mut peeked).unwrap(), 4);
+        assert_eq!(&peeked[..4], &[2, 3, 4, 5]);
+        t.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn must_grow_buffer_to_peek_when_policy_allows() {
+        let policy = TBufferPolicy::builder()
+            .initial_capacity(2)
+            .max_capacity(8)
+            .build()
+            .unwrap();
+        let mem = TBufferChannel::with_capacity(10, 0);
+        let mut t = TBufferedReadTransport::with_policy(mem, policy);
+        t.chan.set_readable_bytes(&[1, 2, 3, 4, 5, 6]);
+
+        let mut peeked = [0u8; 5];
+        assert_eq!(t.peek(&mut peeked).unwrap(), 5);
+        assert_eq!(peeked, [1, 2, 3, 4, 5]);
+        assert!(t.buffer_capacity() >= 5);
+    }
+
+    #[test]
+    fn must_check_pooled_buffers_out_on_first_use() {
+        let pool = TBufferPool::new(1024 * 1024);
+        drop(pool.checkout(READ_CAPACITY));
+        drop(pool.checkout(WRITE_CAPACITY));
+        assert_eq!(pool.retained_buffers(), 1);
+
+        let mut mem = TBufferChannel::with_capacity(10, 10);
+        mem.set_readable_bytes(&[1, 2, 3]);
+        let mut r = TBufferedReadTransport::new(mem.clone()).with_buffer_pool(pool.clone());
+        let mut w = TBufferedWriteTransport::new(mem.clone()).with_buffer_pool(pool.clone());
+        assert_eq!(pool.retained_buffers(), 1);
+
+        let mut read = [0u8; 3];
+        r.read_exact(&mut read).unwrap();
+        assert_eq!(read, [1, 2, 3]);
+        assert_eq!(pool.retained_buffers(), 0);
+
+        w.write_all(&[4, 5]).unwrap();
+        w.flush().unwrap();
+        assert_eq!(mem.write_bytes(), vec![4, 5]);
+
+        drop(r);
+        drop(w);
+        assert_eq!(pool.retained_buffers(), 2);
+    }
 }
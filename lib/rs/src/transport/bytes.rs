@@ -0,0 +1,436 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::io;
+use std::io::{IoSlice, Write};
+
+use ::bytes::{BufMut, Bytes, BytesMut};
+
+use super::{TWriteTransport, TWriteTransportFactory};
+use crate::TConfiguration;
+
+/// Default capacity of the write buffer in bytes.
+const WRITE_CAPACITY: usize = 4096;
+
+/// Size of the frame header written by a framed `TBytesWriteTransport`.
+const FRAME_HEADER_SIZE: usize = 4;
+
+/// Destination for the frames produced by a `TBytesWriteTransport`.
+///
+/// Each flushed message is handed over as a single `Bytes` that shares the
+/// transport's allocation, so a sink can forward it (for example to another
+/// connection, or through a channel to an async task) without copying it.
+///
+/// Any `FnMut(Bytes) -> io::Result<()>` is a sink.
+pub trait TBytesSink {
+    /// Take ownership of one complete frame.
+    fn send(&mut self, frame: Bytes) -> io::Result<()>;
+}
+
+impl<F> TBytesSink for F
+where
+    F: FnMut(Bytes) -> io::Result<()>,
+{
+    fn send(&mut self, frame: Bytes) -> io::Result<()> {
+        self(frame)
+    }
+}
+
+/// `TBytesSink` that writes every frame to a blocking channel with a
+/// single `write_all` followed by a `flush`.
+#[derive(Debug)]
+pub struct TBytesChannelSink<C>
+where
+    C: Write,
+{
+    channel: C,
+}
+
+impl<C> TBytesChannelSink<C>
+where
+    C: Write,
+{
+    /// Create a `TBytesChannelSink` that writes frames to the given
+    /// `TIoChannel`.
+    pub fn new(channel: C) -> TBytesChannelSink<C> {
+        TBytesChannelSink { channel }
+    }
+
+    /// Return the wrapped channel.
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+}
+
+impl<C> TBytesSink for TBytesChannelSink<C>
+where
+    C: Write,
+{
+    fn send(&mut self, frame: Bytes) -> io::Result<()> {
+        self.channel.write_all(&frame)?;
+        self.channel.flush()
+    }
+}
+
+/// Transport that builds each message in a `BytesMut` and hands it to a
+/// `TBytesSink` as one `Bytes` on flush.
+///
+/// Writes are appended to the buffer without intermediate copies, and
+/// `buffer_mut` exposes the buffer itself to code that wants to encode
+/// directly into it. On `TBytesWriteTransport::flush()` the pending message
+/// is split off the buffer and frozen, so the sink receives it without a
+/// copy. The buffer's allocation is reused for the next message once the
+/// sink has dropped the previous one.
+///
+/// A transport created with `TBytesWriteTransport::framed` prefixes every
+/// message with the 4-byte big-endian length header used by
+/// `TFramedWriteTransport`. Space for the header is reserved in front of the
+/// message, so the header and the message are still handed over as a single
+/// `Bytes`.
+///
+/// Writes that would grow the pending message beyond the `max_frame_size`
+/// of the transport's `TConfiguration` fail with
+/// `io::ErrorKind::InvalidData`, and the pending message is discarded.
+///
+/// # Examples
+///
+/// Create a framed `TBytesWriteTransport` that writes to a `TTcpChannel`.
+///
+/// ```no_run
+/// use std::io::Write;
+/// use thrift::transport::{TBytesChannelSink, TBytesWriteTransport, TTcpChannel};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let mut t = TBytesWriteTransport::framed(TBytesChannelSink::new(c));
+///
+/// t.write(&[0x00]).unwrap();
+/// t.flush().unwrap();
+/// ```
+///
+/// Hand the frames to another thread (or an async task) instead.
+///
+/// ```
+/// use std::io::Write;
+/// use std::sync::mpsc;
+/// use thrift::transport::TBytesWriteTransport;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let mut t = TBytesWriteTransport::new(move |frame| {
+///     tx.send(frame)
+///         .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+/// });
+///
+/// t.write_all(&[0x01, 0x02]).unwrap();
+/// t.flush().unwrap();
+///
+/// assert_eq!(&rx.recv().unwrap()[..], &[0x01, 0x02]);
+/// ```
+pub struct TBytesWriteTransport<S>
+where
+    S: TBytesSink,
+{
+    buf: BytesMut,
+    sink: S,
+    framed: bool,
+    config: TConfiguration,
+}
+
+impl<S> TBytesWriteTransport<S>
+where
+    S: TBytesSink,
+{
+    /// Create a `TBytesWriteTransport` that hands unframed messages to
+    /// `sink`.
+    pub fn new(sink: S) -> TBytesWriteTransport<S> {
+        TBytesWriteTransport {
+            buf: BytesMut::with_capacity(WRITE_CAPACITY),
+            sink,
+            framed: false,
+            config: TConfiguration::default(),
+        }
+    }
+
+    /// Create a `TBytesWriteTransport` that hands messages prefixed with a
+    /// frame header to `sink`.
+    pub fn framed(sink: S) -> TBytesWriteTransport<S> {
+        TBytesWriteTransport {
+            framed: true,
+            ..TBytesWriteTransport::new(sink)
+        }
+    }
+
+    /// Enforce the frame size limit in `config`.
+    pub fn with_config(self, config: TConfiguration) -> TBytesWriteTransport<S> {
+        TBytesWriteTransport { config, ..self }
+    }
+
+    /// Return the buffer holding the pending message, so that bytes can be
+    /// appended to it directly. Bytes appended this way are not checked
+    /// against the frame size limit.
+    pub fn buffer_mut(&mut self) -> &mut BytesMut {
+        self.start_frame();
+        &mut self.buf
+    }
+
+    /// Return the number of bytes of the pending message, excluding the
+    /// frame header.
+    pub fn pending_len(&self) -> usize {
+        self.buf.len().saturating_sub(self.header_size())
+    }
+
+    /// Return a reference to the sink.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Return a mutable reference to the sink.
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Send the pending message, if any, and return the sink.
+    pub fn finish(mut self) -> io::Result<S> {
+        self.flush()?;
+        Ok(self.sink)
+    }
+
+    fn header_size(&self) -> usize {
+        if self.framed {
+            FRAME_HEADER_SIZE
+        } else {
+            0
+        }
+    }
+
+    fn start_frame(&mut self) {
+        if self.framed && self.buf.is_empty() {
+            self.buf.put_u32(0);
+        }
+    }
+
+    fn check_frame_size(&mut self, additional: usize) -> io::Result<()> {
+        if let Some(max_frame) = self.config.max_frame_size() {
+            let frame_size = self.pending_len().saturating_add(additional);
+            if frame_size > max_frame {
+                self.buf.clear();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Frame size {} exceeds maximum allowed size of {}",
+                        frame_size, max_frame
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S> Write for TBytesWriteTransport<S>
+where
+    S: TBytesSink,
+{
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.check_frame_size(b.len())?;
+        self.start_frame();
+        self.buf.extend_from_slice(b);
+        Ok(b.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let total_len = bufs.iter().map(|b| b.len()).sum::<usize>();
+        self.check_frame_size(total_len)?;
+        self.start_frame();
+        self.buf.reserve(total_len);
+        for b in bufs {
+            self.buf.extend_from_slice(b);
+        }
+        Ok(total_len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let message_size = self.pending_len();
+        if message_size == 0 {
+            self.buf.clear();
+            return Ok(());
+        }
+
+        if self.framed {
+            self.buf[..FRAME_HEADER_SIZE].copy_from_slice(&(message_size as i32).to_be_bytes());
+        }
+
+        // the frame is taken out of the buffer even if the sink fails, so
+        // that it is not sent again in front of the next one
+        let frame = self.buf.split().freeze();
+        if self.buf.capacity() == 0 {
+            self.buf.reserve(WRITE_CAPACITY);
+        }
+        self.sink.send(frame)
+    }
+}
+
+impl<S> fmt::Debug for TBytesWriteTransport<S>
+where
+    S: TBytesSink,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TBytesWriteTransport")
+            .field("pending_len", &self.pending_len())
+            .field("framed", &self.framed)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// Factory for creating instances of `TBytesWriteTransport` that write to
+/// the channel they are created for through a `TBytesChannelSink`.
+#[derive(Debug, Default)]
+pub struct TBytesWriteTransportFactory {
+    framed: bool,
+    config: TConfiguration,
+}
+
+impl TBytesWriteTransportFactory {
+    /// Create a `TBytesWriteTransportFactory` whose transports write
+    /// unframed messages.
+    pub fn new() -> TBytesWriteTransportFactory {
+        TBytesWriteTransportFactory::default()
+    }
+
+    /// Create a `TBytesWriteTransportFactory` whose transports prefix
+    /// every message with a frame header.
+    pub fn framed() -> TBytesWriteTransportFactory {
+        TBytesWriteTransportFactory {
+            framed: true,
+            config: TConfiguration::default(),
+        }
+    }
+
+    /// Make the created transports enforce the frame size limit in
+    /// `config`.
+    pub fn with_config(self, config: TConfiguration) -> TBytesWriteTransportFactory {
+        TBytesWriteTransportFactory { config, ..self }
+    }
+}
+
+impl TWriteTransportFactory for TBytesWriteTransportFactory {
+    /// Create a `TBytesWriteTransport`.
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        let sink = TBytesChannelSink::new(channel);
+        let transport = if self.framed {
+            TBytesWriteTransport::framed(sink)
+        } else {
+            TBytesWriteTransport::new(sink)
+        };
+        Box::new(transport.with_config(self.config.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{TBufferChannel, TFramedReadTransport};
+    use std::io::Read;
+
+    fn collecting() -> (
+        impl FnMut(Bytes) -> io::Result<()>,
+        std::sync::mpsc::Receiver<Bytes>,
+    ) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sink = move |frame: Bytes| {
+            tx.send(frame).unwrap();
+            Ok(())
+        };
+        (sink, rx)
+    }
+
+    #[test]
+    fn must_hand_each_message_to_sink_as_one_frame() {
+        let (sink, rx) = collecting();
+        let mut t = TBytesWriteTransport::new(sink);
+
+        t.write_all(&[0x01, 0x02]).unwrap();
+        t.buffer_mut().put_u8(0x03);
+        t.flush().unwrap();
+        t.write_all(&[0x04]).unwrap();
+        t.flush().unwrap();
+
+        assert_eq!(&rx.recv().unwrap()[..], &[0x01, 0x02, 0x03]);
+        assert_eq!(&rx.recv().unwrap()[..], &[0x04]);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn must_not_send_empty_frame() {
+        let (sink, rx) = collecting();
+        let mut t = TBytesWriteTransport::framed(sink);
+
+        t.flush().unwrap();
+        t.buffer_mut();
+        t.flush().unwrap();
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn must_write_frames_readable_by_framed_read_transport() {
+        let mut t = TBytesWriteTransport::framed(TBytesChannelSink::new(
+            TBufferChannel::with_capacity(0, 64),
+        ));
+
+        t.write_all(&[0x01, 0x02, 0x03]).unwrap();
+        t.flush().unwrap();
+        t.write_all(&[0x04]).unwrap();
+        t.flush().unwrap();
+
+        let written = t.finish().unwrap().into_inner().write_bytes();
+        assert_eq!(
+            written,
+            vec![0x00, 0x00, 0x00, 0x03, 0x01, 0x02, 0x03, 0x00, 0x00, 0x00, 0x01, 0x04]
+        );
+
+        let mut c = TBufferChannel::with_capacity(written.len(), 0);
+        c.set_readable_bytes(&written);
+        let mut r = TFramedReadTransport::new(c);
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn must_reject_message_larger_than_max_frame_size() {
+        let (sink, rx) = collecting();
+        let config = TConfiguration::builder()
+            .max_frame_size(Some(4))
+            .build()
+            .unwrap();
+        let mut t = TBytesWriteTransport::framed(sink).with_config(config);
+
+        t.write_all(&[0x01, 0x02, 0x03]).unwrap();
+        let err = t.write_all(&[0x04, 0x05]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // the oversized message was discarded
+        t.flush().unwrap();
+        assert!(rx.try_recv().is_err());
+        t.write_all(&[0x06]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(&rx.recv().unwrap()[..], &[0x00, 0x00, 0x00, 0x01, 0x06]);
+    }
+}
@@ -0,0 +1,174 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "server")]
+use crate::{new_transport_error, TransportErrorKind};
+
+/// Deadline for the call being made over a channel.
+///
+/// A `TCallDeadline` is shared between the code making calls and the
+/// channel they are made over, which must be given a clone of it before it
+/// is split (see `TTcpChannel::set_call_deadline`). While a deadline is
+/// set, every read from the channel waits at most until the deadline; a
+/// reply that has not arrived by then fails the call with a
+/// `TransportErrorKind::TimedOut` error. The socket read timeout, if any,
+/// still applies to each read on its own.
+///
+/// A call that timed out leaves its reply, or part of it, unread, so the
+/// channel closes the connection rather than let the next call read the
+/// wrong reply. Later calls fail until a new connection is opened, which a
+/// `TReconnectingChannel` does on its own.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use thrift::protocol::{TBinaryInputProtocol, TInputProtocol};
+/// use thrift::transport::{TCallDeadline, TIoChannel, TTcpChannel};
+///
+/// let deadline = TCallDeadline::new();
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:9090").unwrap();
+/// channel.set_call_deadline(Some(deadline.clone()));
+/// let (i_chan, _o_chan) = channel.split().unwrap();
+/// let mut i_prot = TBinaryInputProtocol::new(i_chan, true);
+///
+/// // with a generated client: `deadline.call(timeout, || client.add(1, 2))`
+/// let reply = deadline.call(Duration::from_millis(200), || i_prot.read_message_begin());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TCallDeadline {
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+impl TCallDeadline {
+    /// Create a `TCallDeadline` with no deadline set.
+    pub fn new() -> TCallDeadline {
+        TCallDeadline::default()
+    }
+
+    /// Set the deadline of the next calls to `deadline`, or remove it if
+    /// `None`.
+    pub fn set(&self, deadline: Option<Instant>) {
+        *self.lock() = deadline;
+    }
+
+    /// Set the deadline to `timeout` from now.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.set(Some(Instant::now() + timeout));
+    }
+
+    /// Remove the deadline.
+    pub fn clear(&self) {
+        self.set(None);
+    }
+
+    /// Return the current deadline.
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.lock()
+    }
+
+    /// Run `call` with a deadline `timeout` from now, and remove the
+    /// deadline again once it returns.
+    pub fn call<T, F>(&self, timeout: Duration, call: F) -> crate::Result<T>
+    where
+        F: FnOnce() -> crate::Result<T>,
+    {
+        self.set_timeout(timeout);
+        let result = call();
+        self.clear();
+        result
+    }
+
+    /// Run `call` with a deadline `timeout` from now, or that of the request
+    /// being handled on this thread if it is earlier, and remove the
+    /// deadline again once it returns.
+    ///
+    /// A handler that calls other services through this shares its own
+    /// deadline, set by `TServer::set_request_timeout`, with those calls, so
+    /// they give up when its client has stopped waiting for the reply.
+    /// Fails with a `TimedOut` error, without running `call`, if the
+    /// request's deadline has already passed. The deadline is not sent to
+    /// the called server.
+    #[cfg(feature = "server")]
+    pub fn call_within_request<T, F>(&self, timeout: Duration, call: F) -> crate::Result<T>
+    where
+        F: FnOnce() -> crate::Result<T>,
+    {
+        let now = Instant::now();
+        let mut deadline = now + timeout;
+        if let Some(request_deadline) = crate::server::request_deadline() {
+            if request_deadline <= now {
+                return Err(new_transport_error(
+                    TransportErrorKind::TimedOut,
+                    "request deadline passed before the call was made",
+                ));
+            }
+            deadline = deadline.min(request_deadline);
+        }
+        self.set(Some(deadline));
+        let result = call();
+        self.clear();
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.deadline.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    use crate::server::{with_request_context, TRequestContext};
+
+    #[test]
+    fn must_bound_call_by_request_deadline() {
+        let deadline = TCallDeadline::new();
+        let request_deadline = Instant::now() + Duration::from_secs(1);
+        let context = TRequestContext::new().with_deadline(Some(request_deadline));
+
+        let during_call = with_request_context(context, || {
+            deadline.call_within_request(Duration::from_secs(60), || Ok(deadline.deadline()))
+        })
+        .unwrap();
+
+        assert_eq!(during_call, Some(request_deadline));
+        assert_eq!(deadline.deadline(), None);
+    }
+
+    #[test]
+    fn must_not_make_call_after_request_deadline() {
+        let deadline = TCallDeadline::new();
+        let context = TRequestContext::new().with_deadline(Some(Instant::now()));
+
+        let result = with_request_context(context, || {
+            deadline.call_within_request(Duration::from_secs(60), || -> crate::Result<()> {
+                panic!("call made after the request deadline")
+            })
+        });
+
+        match result {
+            Err(crate::Error::Transport(e)) => assert_eq!(e.kind, TransportErrorKind::TimedOut),
+            other => panic!("expected timeout, got {:?}", other),
+        }
+    }
+}
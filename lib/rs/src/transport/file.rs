@@ -0,0 +1,908 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{new_transport_error, TransportErrorKind};
+
+/// Settings shared by `TFileWriteTransport` and `TFileReadTransport`.
+///
+/// The defaults match those of the C++ `TFileTransport`. Readers and writers
+/// of the same file must use the same chunk size.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TFileTransportConfig {
+    chunk_size: u32,
+    max_event_size: Option<u32>,
+    event_queue_size: usize,
+    flush_interval: Duration,
+    flush_max_bytes: usize,
+    tail: bool,
+    tail_timeout: Option<Duration>,
+    eof_sleep: Duration,
+}
+
+impl TFileTransportConfig {
+    pub const DEFAULT_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+    pub const DEFAULT_EVENT_QUEUE_SIZE: usize = 10_000;
+    pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(3);
+    pub const DEFAULT_FLUSH_MAX_BYTES: usize = 1000 * 1024;
+    pub const DEFAULT_EOF_SLEEP: Duration = Duration::from_millis(500);
+
+    pub fn builder() -> TFileTransportConfigBuilder {
+        TFileTransportConfigBuilder::default()
+    }
+
+    /// Size of the chunks the file is divided into. Events never span two
+    /// chunks, so a reader can start at any chunk boundary.
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    /// Largest event accepted by the writer and the reader, if limited.
+    pub fn max_event_size(&self) -> Option<u32> {
+        self.max_event_size
+    }
+
+    /// Number of events the writer queues for its background thread before
+    /// `flush()` blocks.
+    pub fn event_queue_size(&self) -> usize {
+        self.event_queue_size
+    }
+
+    /// Longest time written events stay in memory before being flushed to
+    /// disk.
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    /// Number of unflushed bytes that cause the writer to flush to disk
+    /// immediately.
+    pub fn flush_max_bytes(&self) -> usize {
+        self.flush_max_bytes
+    }
+
+    /// Whether the reader waits for more events at the end of the file.
+    pub fn tail(&self) -> bool {
+        self.tail
+    }
+
+    /// Longest time a tailing reader waits for a new event, or `None` to
+    /// wait indefinitely.
+    pub fn tail_timeout(&self) -> Option<Duration> {
+        self.tail_timeout
+    }
+
+    /// Time a tailing reader sleeps before checking the file for new events.
+    pub fn eof_sleep(&self) -> Duration {
+        self.eof_sleep
+    }
+
+    fn chunk_of(&self, offset: u64) -> u64 {
+        offset / self.chunk_size as u64
+    }
+
+    fn chunk_start(&self, chunk: u64) -> u64 {
+        chunk * self.chunk_size as u64
+    }
+
+    /// Validate the size of an event about to be written.
+    fn check_event_size(&self, len: usize) -> io::Result<()> {
+        let limit = self
+            .max_event_size
+            .unwrap_or(u32::MAX)
+            .min(self.chunk_size - 4);
+        if len > limit as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("event of {len} bytes exceeds the maximum event size of {limit}"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for TFileTransportConfig {
+    fn default() -> Self {
+        TFileTransportConfig {
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+            max_event_size: None,
+            event_queue_size: Self::DEFAULT_EVENT_QUEUE_SIZE,
+            flush_interval: Self::DEFAULT_FLUSH_INTERVAL,
+            flush_max_bytes: Self::DEFAULT_FLUSH_MAX_BYTES,
+            tail: false,
+            tail_timeout: None,
+            eof_sleep: Self::DEFAULT_EOF_SLEEP,
+        }
+    }
+}
+
+/// Builder for `TFileTransportConfig`.
+#[derive(Debug, Default)]
+pub struct TFileTransportConfigBuilder {
+    config: TFileTransportConfig,
+}
+
+impl TFileTransportConfigBuilder {
+    pub fn chunk_size(mut self, size: u32) -> Self {
+        self.config.chunk_size = size;
+        self
+    }
+
+    pub fn max_event_size(mut self, limit: Option<u32>) -> Self {
+        self.config.max_event_size = limit;
+        self
+    }
+
+    pub fn event_queue_size(mut self, size: usize) -> Self {
+        self.config.event_queue_size = size;
+        self
+    }
+
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.config.flush_interval = interval;
+        self
+    }
+
+    pub fn flush_max_bytes(mut self, bytes: usize) -> Self {
+        self.config.flush_max_bytes = bytes;
+        self
+    }
+
+    pub fn tail(mut self, tail: bool) -> Self {
+        self.config.tail = tail;
+        self
+    }
+
+    pub fn tail_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.tail_timeout = timeout;
+        self
+    }
+
+    pub fn eof_sleep(mut self, sleep: Duration) -> Self {
+        self.config.eof_sleep = sleep;
+        self
+    }
+
+    pub fn build(self) -> crate::Result<TFileTransportConfig> {
+        if self.config.chunk_size <= 4 {
+            return Err(new_transport_error(
+                TransportErrorKind::SizeLimit,
+                "chunk size must be larger than the 4 byte event header",
+            ));
+        }
+        if self.config.event_queue_size == 0 {
+            return Err(new_transport_error(
+                TransportErrorKind::SizeLimit,
+                "event queue size must be positive",
+            ));
+        }
+        Ok(self.config)
+    }
+}
+
+/// Transport that appends events to a file in the format of the C++
+/// `TFileTransport`.
+///
+/// Bytes written are collected into an event, which `flush()` hands to a
+/// background thread. Each event is stored as a 4-byte little-endian length
+/// followed by its bytes, and the file is divided into chunks that no event
+/// crosses: an event that does not fit into the rest of a chunk is preceded
+/// by zero padding up to the next chunk. The background thread flushes the
+/// file to disk every `flush_interval`, after `flush_max_bytes`, when
+/// [`sync`](Self::sync) is called, and when the transport is closed or
+/// dropped.
+///
+/// Since output protocols flush once per message, every message written
+/// through a protocol becomes one event.
+///
+/// Errors on the background thread are reported by the next call to
+/// `flush()`, `sync()` or `close()`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryOutputProtocol, TOutputProtocol};
+/// use thrift::transport::TFileWriteTransport;
+///
+/// let t = TFileWriteTransport::open("events.log").unwrap();
+/// let mut o_prot = TBinaryOutputProtocol::new(t, true);
+///
+/// o_prot.write_i32(42).unwrap();
+/// o_prot.flush().unwrap();
+/// ```
+pub struct TFileWriteTransport {
+    event: Vec<u8>,
+    config: TFileTransportConfig,
+    queue: Option<SyncSender<WriterCommand>>,
+    writer: Option<JoinHandle<()>>,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+enum WriterCommand {
+    Event(Vec<u8>),
+    Sync(mpsc::Sender<()>),
+}
+
+impl TFileWriteTransport {
+    /// Open `path` for appending events with the default configuration,
+    /// creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<TFileWriteTransport> {
+        TFileWriteTransport::with_config(path, TFileTransportConfig::default())
+    }
+
+    /// Open `path` for appending events, creating it if it does not exist.
+    pub fn with_config<P: AsRef<Path>>(
+        path: P,
+        config: TFileTransportConfig,
+    ) -> crate::Result<TFileWriteTransport> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let offset = file.metadata()?.len();
+
+        let (queue, commands) = mpsc::sync_channel(config.event_queue_size);
+        let error = Arc::new(Mutex::new(None));
+        let writer = EventWriter {
+            out: BufWriter::new(file),
+            offset,
+            unflushed: 0,
+            config,
+            error: error.clone(),
+        };
+        let writer = thread::Builder::new()
+            .name("thrift-file-writer".to_owned())
+            .spawn(move || writer.run(commands))?;
+
+        Ok(TFileWriteTransport {
+            event: Vec::new(),
+            config,
+            queue: Some(queue),
+            writer: Some(writer),
+            error,
+        })
+    }
+
+    /// Flush the current event and block until every event written so far
+    /// is on disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let (done, wait) = mpsc::channel();
+        self.send(WriterCommand::Sync(done))?;
+        // the writer drops the sender without replying only if it has
+        // stopped, in which case the error explains why
+        let _ = wait.recv();
+        self.take_error()
+    }
+
+    /// Flush the current event, write all queued events to disk and stop
+    /// the background thread.
+    pub fn close(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        let flushed = self.flush();
+        self.queue = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        flushed.and_then(|_| self.take_error())
+    }
+
+    fn send(&self, command: WriterCommand) -> io::Result<()> {
+        let sent = match self.queue {
+            Some(ref queue) => queue.send(command).is_ok(),
+            None => false,
+        };
+        if sent {
+            Ok(())
+        } else {
+            self.take_error()?;
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "file writer thread has stopped",
+            ))
+        }
+    }
+
+    fn take_error(&self) -> io::Result<()> {
+        let error = self.error.lock().unwrap_or_else(|e| e.into_inner()).take();
+        error.map_or(Ok(()), Err)
+    }
+}
+
+impl Write for TFileWriteTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.config.check_event_size(self.event.len() + buf.len())?;
+        self.event.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.event.is_empty() {
+            let event = std::mem::take(&mut self.event);
+            self.send(WriterCommand::Event(event))?;
+        }
+        self.take_error()
+    }
+}
+
+impl Drop for TFileWriteTransport {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+impl fmt::Debug for TFileWriteTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TFileWriteTransport")
+            .field("config", &self.config)
+            .field("pending_event_bytes", &self.event.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// State of the background thread of a `TFileWriteTransport`.
+struct EventWriter {
+    out: BufWriter<File>,
+    offset: u64,
+    unflushed: usize,
+    config: TFileTransportConfig,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl EventWriter {
+    fn run(mut self, commands: Receiver<WriterCommand>) {
+        let mut flushed_at = Instant::now();
+        loop {
+            let timeout = self
+                .config
+                .flush_interval
+                .saturating_sub(flushed_at.elapsed());
+            match commands.recv_timeout(timeout) {
+                Ok(WriterCommand::Event(event)) => {
+                    let written = self.write_event(&event);
+                    self.record(written);
+                    if self.unflushed >= self.config.flush_max_bytes {
+                        self.flush();
+                        flushed_at = Instant::now();
+                    }
+                }
+                Ok(WriterCommand::Sync(done)) => {
+                    self.flush();
+                    flushed_at = Instant::now();
+                    let _ = done.send(());
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.unflushed > 0 {
+                        self.flush();
+                    }
+                    flushed_at = Instant::now();
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.flush();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write_event(&mut self, event: &[u8]) -> io::Result<()> {
+        let size = event.len() as u64 + 4;
+        let config = self.config;
+        if config.chunk_of(self.offset) != config.chunk_of(self.offset + size - 1) {
+            let padding = config.chunk_start(config.chunk_of(self.offset) + 1) - self.offset;
+            io::copy(&mut io::repeat(0).take(padding), &mut self.out)?;
+            self.offset += padding;
+            self.unflushed += padding as usize;
+        }
+
+        self.out.write_all(&(event.len() as u32).to_le_bytes())?;
+        self.out.write_all(event)?;
+        self.offset += size;
+        self.unflushed += size as usize;
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        let flushed = self
+            .out
+            .flush()
+            .and_then(|_| self.out.get_ref().sync_data());
+        self.unflushed = 0;
+        self.record(flushed);
+    }
+
+    fn record(&self, result: io::Result<()>) {
+        if let Err(e) = result {
+            let mut error = self.error.lock().unwrap_or_else(|e| e.into_inner());
+            error.get_or_insert(e);
+        }
+    }
+}
+
+/// Transport that reads the events of a file written by a
+/// `TFileWriteTransport` or the C++ `TFileTransport`.
+///
+/// Reads return the bytes of successive events. At the end of the file a
+/// read returns `0`, unless the transport is configured to tail the file, in
+/// which case it waits for more events to be appended. Events that are
+/// malformed - larger than the chunk size or the maximum event size, or
+/// crossing a chunk boundary - are skipped along with the rest of their
+/// chunk.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocol, TInputProtocol};
+/// use thrift::transport::TFileReadTransport;
+///
+/// let mut t = TFileReadTransport::open("events.log").unwrap();
+/// // start with the last chunk of the file
+/// t.seek_to_chunk(-1).unwrap();
+///
+/// let mut i_prot = TBinaryInputProtocol::new(t, true);
+/// let value = i_prot.read_i32().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TFileReadTransport {
+    file: BufReader<File>,
+    /// File position of the next unread byte.
+    offset: u64,
+    config: TFileTransportConfig,
+    event: Vec<u8>,
+    pos: usize,
+}
+
+impl TFileReadTransport {
+    /// Open `path` for reading with the default configuration.
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<TFileReadTransport> {
+        TFileReadTransport::with_config(path, TFileTransportConfig::default())
+    }
+
+    /// Open `path` for reading.
+    pub fn with_config<P: AsRef<Path>>(
+        path: P,
+        config: TFileTransportConfig,
+    ) -> crate::Result<TFileReadTransport> {
+        Ok(TFileReadTransport {
+            file: BufReader::new(File::open(path)?),
+            offset: 0,
+            config,
+            event: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Read the next complete event, discarding the unread part of the
+    /// current one. Returns `None` at the end of the file.
+    pub fn read_event(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.event.clear();
+        self.pos = 0;
+        self.next_event()
+    }
+
+    /// Number of chunks in the file, counting a partly filled last chunk.
+    pub fn num_chunks(&self) -> io::Result<u64> {
+        let len = self.file.get_ref().metadata()?.len();
+        if len == 0 {
+            Ok(0)
+        } else {
+            Ok(self.config.chunk_of(len) + 1)
+        }
+    }
+
+    /// The chunk the next event is read from.
+    pub fn current_chunk(&self) -> u64 {
+        self.config.chunk_of(self.offset)
+    }
+
+    /// Continue reading at the start of `chunk`. A negative `chunk` counts
+    /// back from the end of the file, so `-1` is the last chunk. Seeking
+    /// beyond the last chunk moves to the end of the file.
+    pub fn seek_to_chunk(&mut self, chunk: i64) -> io::Result<()> {
+        let num_chunks = self.num_chunks()?;
+        if num_chunks == 0 {
+            return Ok(());
+        }
+
+        self.event.clear();
+        self.pos = 0;
+
+        let chunk = if chunk < 0 {
+            (chunk + num_chunks as i64).max(0) as u64
+        } else {
+            chunk as u64
+        };
+        if chunk < num_chunks {
+            return self.seek(self.config.chunk_start(chunk));
+        }
+
+        // skip every event present now, so that only later ones are read
+        let end = self.file.get_ref().metadata()?.len();
+        self.seek(self.config.chunk_start(num_chunks - 1))?;
+        while self.offset < end {
+            if self.try_next_event()?.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Continue reading after the last event currently in the file.
+    pub fn seek_to_end(&mut self) -> io::Result<()> {
+        let num_chunks = self.num_chunks()?;
+        self.seek_to_chunk(num_chunks as i64)
+    }
+
+    fn seek(&mut self, offset: u64) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        Ok(())
+    }
+
+    fn next_event(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let started = Instant::now();
+        loop {
+            if let Some(event) = self.try_next_event()? {
+                return Ok(Some(event));
+            }
+            if !self.config.tail {
+                return Ok(None);
+            }
+            if let Some(timeout) = self.config.tail_timeout {
+                if started.elapsed() >= timeout {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "timed out waiting for a new event",
+                    ));
+                }
+            }
+            thread::sleep(self.config.eof_sleep);
+        }
+    }
+
+    /// Read the next event present in the file, leaving the position
+    /// unchanged if the file ends before a complete event.
+    fn try_next_event(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let config = self.config;
+        loop {
+            let start = self.offset;
+            let next_chunk = config.chunk_start(config.chunk_of(start) + 1);
+
+            // an event header never spans two chunks
+            if config.chunk_of(start) != config.chunk_of(start + 3) {
+                self.seek(next_chunk)?;
+                continue;
+            }
+
+            let mut header = [0u8; 4];
+            if !self.read_fully(&mut header)? {
+                self.seek(start)?;
+                return Ok(None);
+            }
+            let size = u32::from_le_bytes(header);
+            if size == 0 {
+                // padding
+                continue;
+            }
+
+            let corrupted = matches!(config.max_event_size, Some(max) if size > max)
+                || size > config.chunk_size
+                || config.chunk_of(start) != config.chunk_of(start + 4 + size as u64 - 1);
+            if corrupted {
+                self.seek(next_chunk)?;
+                continue;
+            }
+
+            let mut event = vec![0u8; size as usize];
+            if !self.read_fully(&mut event)? {
+                self.seek(start)?;
+                return Ok(None);
+            }
+            return Ok(Some(event));
+        }
+    }
+
+    /// Fill `buf`, returning `false` if the file ends first.
+    fn read_fully(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.file.read(&mut buf[filled..]) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    filled += n;
+                    self.offset += n as u64;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Read for TFileReadTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.event.len() {
+            match self.next_event()? {
+                Some(event) => {
+                    self.event = event;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = std::cmp::min(buf.len(), self.event.len() - self.pos);
+        buf[..len].copy_from_slice(&self.event[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TOutputProtocol,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("thrift-file-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn small_chunks() -> TFileTransportConfig {
+        TFileTransportConfig::builder()
+            .chunk_size(16)
+            .build()
+            .unwrap()
+    }
+
+    fn write_events(path: &Path, config: TFileTransportConfig, events: &[&[u8]]) {
+        let mut t = TFileWriteTransport::with_config(path, config).unwrap();
+        for event in events {
+            t.write_all(event).unwrap();
+            t.flush().unwrap();
+        }
+        t.close().unwrap();
+    }
+
+    fn read_events(t: &mut TFileReadTransport) -> Vec<Vec<u8>> {
+        let mut events = Vec::new();
+        while let Some(event) = t.read_event().unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn must_write_length_prefixed_events() {
+        let path = temp_path("format");
+        let mut t = TFileWriteTransport::open(&path).unwrap();
+        t.write_all(&[0x01, 0x02]).unwrap();
+        t.write_all(&[0x03]).unwrap();
+        t.flush().unwrap();
+        t.write_all(&[0xFF]).unwrap();
+        t.close().unwrap();
+
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            vec![0x03, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x01, 0x00, 0x00, 0x00, 0xFF]
+        );
+
+        let mut t = TFileReadTransport::open(&path).unwrap();
+        let mut buf = Vec::new();
+        t.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, vec![0x01, 0x02, 0x03, 0xFF]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_pad_events_that_would_cross_a_chunk_boundary() {
+        let path = temp_path("padding");
+        write_events(&path, small_chunks(), &[b"abcdef", b"ghijkl"]);
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 26);
+        assert_eq!(&bytes[10..16], &[0u8; 6]);
+        assert_eq!(&bytes[16..20], &6u32.to_le_bytes());
+
+        let mut t = TFileReadTransport::with_config(&path, small_chunks()).unwrap();
+        assert_eq!(t.num_chunks().unwrap(), 2);
+        assert_eq!(
+            read_events(&mut t),
+            vec![b"abcdef".to_vec(), b"ghijkl".to_vec()]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_append_to_an_existing_file() {
+        let path = temp_path("append");
+        write_events(&path, small_chunks(), &[b"abcdef"]);
+        write_events(&path, small_chunks(), &[b"ghijkl"]);
+
+        let mut t = TFileReadTransport::with_config(&path, small_chunks()).unwrap();
+        assert_eq!(
+            read_events(&mut t),
+            vec![b"abcdef".to_vec(), b"ghijkl".to_vec()]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_reject_events_larger_than_the_limit() {
+        let path = temp_path("limit");
+        let config = TFileTransportConfig::builder()
+            .chunk_size(16)
+            .max_event_size(Some(8))
+            .build()
+            .unwrap();
+        let mut t = TFileWriteTransport::with_config(&path, config).unwrap();
+        t.write_all(b"12345678").unwrap();
+        let err = t.write_all(b"9").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        t.close().unwrap();
+
+        let mut t = TFileWriteTransport::with_config(&path, small_chunks()).unwrap();
+        assert!(t.write_all(&[0u8; 13]).is_err());
+        t.close().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_skip_the_rest_of_a_chunk_after_a_corrupted_event() {
+        let path = temp_path("corrupted");
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(b"ok");
+        // claims to run past the end of the chunk
+        bytes.extend_from_slice(&100u32.to_le_bytes());
+        bytes.resize(16, 0xAA);
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(b"end");
+        fs::write(&path, bytes).unwrap();
+
+        let mut t = TFileReadTransport::with_config(&path, small_chunks()).unwrap();
+        assert_eq!(read_events(&mut t), vec![b"ok".to_vec(), b"end".to_vec()]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_seek_to_chunks() {
+        let path = temp_path("seek");
+        write_events(&path, small_chunks(), &[b"first-", b"second", b"third-"]);
+
+        let mut t = TFileReadTransport::with_config(&path, small_chunks()).unwrap();
+        assert_eq!(t.num_chunks().unwrap(), 3);
+
+        t.seek_to_chunk(2).unwrap();
+        assert_eq!(t.current_chunk(), 2);
+        assert_eq!(read_events(&mut t), vec![b"third-".to_vec()]);
+
+        t.seek_to_chunk(-2).unwrap();
+        assert_eq!(
+            read_events(&mut t),
+            vec![b"second".to_vec(), b"third-".to_vec()]
+        );
+
+        t.seek_to_chunk(-10).unwrap();
+        assert_eq!(read_events(&mut t).len(), 3);
+
+        t.seek_to_end().unwrap();
+        assert_eq!(t.read_event().unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_wait_for_new_events_when_tailing() {
+        let path = temp_path("tail");
+        write_events(&path, small_chunks(), &[b"old"]);
+
+        let config = TFileTransportConfig::builder()
+            .chunk_size(16)
+            .tail(true)
+            .tail_timeout(Some(Duration::from_secs(10)))
+            .eof_sleep(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let mut t = TFileReadTransport::with_config(&path, config).unwrap();
+        t.seek_to_end().unwrap();
+
+        let writer_path = path.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let mut t = TFileWriteTransport::with_config(&writer_path, small_chunks()).unwrap();
+            t.write_all(b"new").unwrap();
+            t.sync().unwrap();
+        });
+
+        assert_eq!(t.read_event().unwrap(), Some(b"new".to_vec()));
+        writer.join().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_time_out_when_tailing_without_new_events() {
+        let path = temp_path("tail-timeout");
+        write_events(&path, small_chunks(), &[]);
+
+        let config = TFileTransportConfig::builder()
+            .tail(true)
+            .tail_timeout(Some(Duration::from_millis(30)))
+            .eof_sleep(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let mut t = TFileReadTransport::with_config(&path, config).unwrap();
+        let err = t.read(&mut [0u8; 4]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_flush_events_in_the_background() {
+        let path = temp_path("background");
+        let config = TFileTransportConfig::builder()
+            .flush_interval(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let mut t = TFileWriteTransport::with_config(&path, config).unwrap();
+        t.write_all(b"event").unwrap();
+        t.flush().unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while fs::metadata(&path).unwrap().len() < 9 {
+            assert!(Instant::now() < deadline, "event was never flushed");
+            thread::sleep(Duration::from_millis(10));
+        }
+        t.close().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_round_trip_messages_through_protocols() {
+        let path = temp_path("protocol");
+        let t = TFileWriteTransport::open(&path).unwrap();
+        let mut o_prot = TBinaryOutputProtocol::new(t, true);
+        for i in 0..3 {
+            o_prot.write_i32(i).unwrap();
+            o_prot.write_string("event").unwrap();
+            o_prot.flush().unwrap();
+        }
+        drop(o_prot);
+
+        let t = TFileReadTransport::open(&path).unwrap();
+        let mut i_prot = TBinaryInputProtocol::new(t, true);
+        for i in 0..3 {
+            assert_eq!(i_prot.read_i32().unwrap(), i);
+            assert_eq!(i_prot.read_string().unwrap(), "event");
+        }
+        assert!(i_prot.read_i32().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
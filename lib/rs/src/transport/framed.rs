@@ -15,12 +15,16 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt};
 use std::cmp;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufRead, IoSlice, Read, Write};
 
-use super::{TReadTransport, TReadTransportFactory, TWriteTransport, TWriteTransportFactory};
+use super::crc32c::crc32c;
+use super::{
+    TBufferPool, TPeekableReadTransport, TPooledBuffer, TReadTransport, TReadTransportFactory,
+    TWriteTransport, TWriteTransportFactory,
+};
 use crate::TConfiguration;
 
 /// Default capacity of the read buffer in bytes.
@@ -38,6 +42,20 @@ const WRITE_CAPACITY: usize = 4096;
 /// until it is exhausted, at which point the next full message is read
 /// from the wrapped channel.
 ///
+/// Frames larger than the `max_frame_size` of the transport's
+/// `TConfiguration` are rejected with `io::ErrorKind::InvalidData` before
+/// any buffer is allocated for them.
+///
+/// A transport configured with `TFramedReadTransport::with_crc32c` expects every
+/// frame to be followed by a 4-byte big-endian CRC32C checksum of the frame
+/// bytes (the checksum is not counted in the frame size), and fails the read
+/// with `io::ErrorKind::InvalidData` if the checksum does not match. Both
+/// ends of the connection must agree on whether checksums are used.
+///
+/// A transport created with `with_buffer_pool` checks a buffer out of a
+/// [`TBufferPool`] for every frame and returns it once the frame has been
+/// read, so that it holds no buffer while waiting for the next frame.
+///
 /// # Examples
 ///
 /// Create and use a `TFramedReadTransport`.
@@ -58,11 +76,12 @@ pub struct TFramedReadTransport<C>
 where
     C: Read,
 {
-    buf: Vec<u8>,
+    buf: TPooledBuffer,
     pos: usize,
     cap: usize,
     chan: C,
     config: TConfiguration,
+    checksum: bool,
 }
 
 impl<C> TFramedReadTransport<C>
@@ -79,50 +98,110 @@ where
     /// of size `read_capacity` that wraps the given `TIoChannel`.
     pub fn with_capacity(read_capacity: usize, channel: C) -> TFramedReadTransport<C> {
         TFramedReadTransport {
-            buf: vec![0; read_capacity], // FIXME: do I actually have to do this?
+            buf: TPooledBuffer::unpooled(vec![0; read_capacity]), // FIXME: do I actually have to do this?
             pos: 0,
             cap: 0,
             chan: channel,
             config: TConfiguration::default(),
+            checksum: false,
         }
     }
-}
 
-impl<C> Read for TFramedReadTransport<C>
-where
-    C: Read,
-{
-    fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
-        if self.cap - self.pos == 0 {
-            let frame_size_bytes = self.chan.read_i32::<BigEndian>()?;
+    /// Create a `TFramedReadTransport` with a default-sized internal read
+    /// buffer that wraps the given `TIoChannel` and enforces the frame size
+    /// limit in `config`.
+    pub fn with_config(channel: C, config: TConfiguration) -> TFramedReadTransport<C> {
+        TFramedReadTransport {
+            config,
+            ..TFramedReadTransport::new(channel)
+        }
+    }
+
+    /// Verify the CRC32C trailer of every frame. Call before reading from
+    /// the transport.
+    pub fn with_crc32c(self) -> TFramedReadTransport<C> {
+        TFramedReadTransport {
+            checksum: true,
+            ..self
+        }
+    }
+
+    /// Check frame buffers out of `pool` instead of keeping a buffer for
+    /// the lifetime of the transport. Call before reading from the
+    /// transport.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TFramedReadTransport<C> {
+        TFramedReadTransport {
+            buf: TPooledBuffer::pooled(pool),
+            pos: 0,
+            cap: 0,
+            ..self
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<()> {
+        // the previous frame has been consumed: don't hold its buffer while
+        // waiting for the next one
+        self.buf.release();
+        self.pos = 0;
+        self.cap = 0;
+
+        let frame_size_bytes = self.chan.read_i32::<BigEndian>()?;
+
+        if frame_size_bytes < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Negative frame size: {}", frame_size_bytes),
+            ));
+        }
+
+        let message_size = frame_size_bytes as usize;
 
-            if frame_size_bytes < 0 {
+        if let Some(max_frame) = self.config.max_frame_size() {
+            if message_size > max_frame {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidData,
-                    format!("Negative frame size: {}", frame_size_bytes),
+                    format!(
+                        "Frame size {} exceeds maximum allowed size of {}",
+                        message_size, max_frame
+                    ),
                 ));
             }
+        }
+
+        let buf_capacity = cmp::max(message_size, READ_CAPACITY);
+        self.buf.acquire(buf_capacity);
+        self.buf.resize(buf_capacity, 0);
+
+        self.chan.read_exact(&mut self.buf[..message_size])?;
 
-            let message_size = frame_size_bytes as usize;
-
-            if let Some(max_frame) = self.config.max_frame_size() {
-                if message_size > max_frame {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!(
-                            "Frame size {} exceeds maximum allowed size of {}",
-                            message_size, max_frame
-                        ),
-                    ));
-                }
+        if self.checksum {
+            let expected = self.chan.read_u32::<BigEndian>()?;
+            let actual = crc32c(&self.buf[..message_size]);
+            if expected != actual {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Frame checksum mismatch: expected {:#010x} got {:#010x}",
+                        expected, actual
+                    ),
+                ));
             }
+        }
+
+        self.cap = message_size;
+        self.pos = 0;
 
-            let buf_capacity = cmp::max(message_size, READ_CAPACITY);
-            self.buf.resize(buf_capacity, 0);
+        Ok(())
+    }
+}
 
-            self.chan.read_exact(&mut self.buf[..message_size])?;
-            self.cap = message_size;
-            self.pos = 0;
+impl<C> Read for TFramedReadTransport<C>
+where
+    C: Read,
+{
+    fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+        if self.cap - self.pos == 0 {
+            self.read_frame()?;
         }
 
         let nread = cmp::min(b.len(), self.cap - self.pos);
@@ -133,20 +212,89 @@ where
     }
 }
 
+impl<C> BufRead for TFramedReadTransport<C>
+where
+    C: Read,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.cap - self.pos == 0 {
+            self.read_frame()?;
+        }
+
+        Ok(&self.buf[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.cap, self.pos + amt);
+    }
+}
+
+/// Peeks into the current frame, reading the next frame if the current one
+/// has been consumed. Never returns bytes from beyond the current frame.
+impl<C> TPeekableReadTransport for TFramedReadTransport<C>
+where
+    C: Read,
+{
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let npeek = cmp::min(buf.len(), available.len());
+        buf[..npeek].copy_from_slice(&available[..npeek]);
+        Ok(npeek)
+    }
+}
+
 /// Factory for creating instances of `TFramedReadTransport`.
-#[derive(Default)]
-pub struct TFramedReadTransportFactory;
+#[derive(Debug, Default)]
+pub struct TFramedReadTransportFactory {
+    config: TConfiguration,
+    pool: Option<TBufferPool>,
+    checksum: bool,
+}
 
 impl TFramedReadTransportFactory {
     pub fn new() -> TFramedReadTransportFactory {
-        TFramedReadTransportFactory {}
+        TFramedReadTransportFactory::default()
+    }
+
+    /// Create a `TFramedReadTransportFactory` whose transports enforce the
+    /// frame size limit in `config`.
+    pub fn with_config(config: TConfiguration) -> TFramedReadTransportFactory {
+        TFramedReadTransportFactory {
+            config,
+            ..TFramedReadTransportFactory::default()
+        }
+    }
+
+    /// Make the created transports check their frame buffers out of
+    /// `pool`.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TFramedReadTransportFactory {
+        TFramedReadTransportFactory {
+            pool: Some(pool),
+            ..self
+        }
+    }
+
+    /// Make the created transports verify the CRC32C trailer of every
+    /// frame.
+    pub fn with_crc32c(self) -> TFramedReadTransportFactory {
+        TFramedReadTransportFactory {
+            checksum: true,
+            ..self
+        }
     }
 }
 
 impl TReadTransportFactory for TFramedReadTransportFactory {
     /// Create a `TFramedReadTransport`.
     fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
-        Box::new(TFramedReadTransport::new(channel))
+        let mut transport = TFramedReadTransport::with_config(channel, self.config.clone());
+        if self.checksum {
+            transport = transport.with_crc32c();
+        }
+        match self.pool {
+            Some(ref pool) => Box::new(transport.with_buffer_pool(pool.clone())),
+            None => Box::new(transport),
+        }
     }
 }
 /// Transport that writes framed messages.
@@ -157,6 +305,19 @@ impl TReadTransportFactory for TFramedReadTransportFactory {
 /// header with a count of the buffered bytes is written, followed by the bytes
 /// themselves.
 ///
+/// Writes that would grow the buffered frame beyond the `max_frame_size` of
+/// the transport's `TConfiguration` fail with `io::ErrorKind::InvalidData`.
+/// The partially buffered frame is then discarded, so that nothing is sent
+/// for it.
+///
+/// A transport configured with `TFramedWriteTransport::with_crc32c` also
+/// writes a 4-byte big-endian CRC32C checksum of the frame bytes after each
+/// frame, to be verified by a `TFramedReadTransport` configured the same way.
+///
+/// A transport created with `with_buffer_pool` checks a buffer out of a
+/// [`TBufferPool`] when a frame is started and returns it once the frame has
+/// been sent.
+///
 /// # Examples
 ///
 /// Create and use a `TFramedWriteTransport`.
@@ -178,8 +339,10 @@ pub struct TFramedWriteTransport<C>
 where
     C: Write,
 {
-    buf: Vec<u8>,
+    buf: TPooledBuffer,
     channel: C,
+    config: TConfiguration,
+    checksum: bool,
 }
 
 impl<C> TFramedWriteTransport<C>
@@ -196,10 +359,65 @@ where
     /// of size `write_capacity` that wraps the given `TIoChannel`.
     pub fn with_capacity(write_capacity: usize, channel: C) -> TFramedWriteTransport<C> {
         TFramedWriteTransport {
-            buf: Vec::with_capacity(write_capacity),
+            buf: TPooledBuffer::unpooled(Vec::with_capacity(write_capacity)),
             channel,
+            config: TConfiguration::default(),
+            checksum: false,
+        }
+    }
+
+    /// Create a `TFramedWriteTransport` with a default-sized internal write
+    /// buffer that wraps the given `TIoChannel` and enforces the frame size
+    /// limit in `config`.
+    pub fn with_config(channel: C, config: TConfiguration) -> TFramedWriteTransport<C> {
+        TFramedWriteTransport {
+            config,
+            ..TFramedWriteTransport::new(channel)
         }
     }
+
+    fn check_frame_size(&mut self, additional: usize) -> io::Result<()> {
+        if let Some(max_frame) = self.config.max_frame_size() {
+            let frame_size = self.buf.len().saturating_add(additional);
+            if frame_size > max_frame {
+                self.buf.clear();
+                self.buf.release();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Frame size {} exceeds maximum allowed size of {}",
+                        frame_size, max_frame
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Append a CRC32C trailer to every frame. Call before writing to the
+    /// transport.
+    pub fn with_crc32c(self) -> TFramedWriteTransport<C> {
+        TFramedWriteTransport {
+            checksum: true,
+            ..self
+        }
+    }
+
+    /// Check frame buffers out of `pool` instead of keeping a buffer for
+    /// the lifetime of the transport. Call before writing to the transport.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TFramedWriteTransport<C> {
+        TFramedWriteTransport {
+            buf: TPooledBuffer::pooled(pool),
+            ..self
+        }
+    }
+
+    /// Send the pending frame, if any, and return the wrapped channel, for
+    /// example to shut it down once the last message is sent.
+    pub fn finish(mut self) -> io::Result<C> {
+        self.flush()?;
+        Ok(self.channel)
+    }
 }
 
 impl<C> Write for TFramedWriteTransport<C>
@@ -207,6 +425,9 @@ where
     C: Write,
 {
     fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.check_frame_size(b.len())?;
+        self.buf.acquire(cmp::max(b.len(), WRITE_CAPACITY));
+
         let current_capacity = self.buf.capacity();
         let available_space = current_capacity - self.buf.len();
         if b.len() > available_space {
@@ -218,44 +439,98 @@ where
         Ok(b.len())
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        // the frame size must be known before anything is sent, so the
+        // slices are still buffered, but with a single reservation
+        let total_len = bufs.iter().map(|b| b.len()).sum::<usize>();
+        self.check_frame_size(total_len)?;
+        self.buf.acquire(cmp::max(total_len, WRITE_CAPACITY));
+        self.buf.reserve(total_len);
+        for b in bufs {
+            self.buf.extend_from_slice(b);
+        }
+        Ok(total_len)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         let message_size = self.buf.len();
 
         if let 0 = message_size {
             return Ok(());
-        } else {
-            self.channel.write_i32::<BigEndian>(message_size as i32)?;
         }
 
-        // will spin if the underlying channel can't be written to
-        let mut byte_index = 0;
-        while byte_index < message_size {
-            let nwrite = self.channel.write(&self.buf[byte_index..message_size])?;
-            byte_index = cmp::min(byte_index + nwrite, message_size);
-        }
+        // send the header, the frame and the checksum (if any) together
+        let header = (message_size as i32).to_be_bytes();
+        let result = if self.checksum {
+            let trailer = crc32c(&self.buf).to_be_bytes();
+            TWriteTransport::write_all_vectored(&mut self.channel, &[&header, &self.buf, &trailer])
+        } else {
+            TWriteTransport::write_all_vectored(&mut self.channel, &[&header, &self.buf])
+        };
 
+        // the frame is discarded even if the write fails, so that it is not
+        // sent again in front of the next one
         let buf_capacity = cmp::min(self.buf.capacity(), WRITE_CAPACITY);
         self.buf.resize(buf_capacity, 0);
         self.buf.clear();
+        self.buf.release();
 
+        result?;
         self.channel.flush()
     }
 }
 
 /// Factory for creating instances of `TFramedWriteTransport`.
-#[derive(Default)]
-pub struct TFramedWriteTransportFactory;
+#[derive(Debug, Default)]
+pub struct TFramedWriteTransportFactory {
+    config: TConfiguration,
+    pool: Option<TBufferPool>,
+    checksum: bool,
+}
 
 impl TFramedWriteTransportFactory {
     pub fn new() -> TFramedWriteTransportFactory {
-        TFramedWriteTransportFactory {}
+        TFramedWriteTransportFactory::default()
+    }
+
+    /// Create a `TFramedWriteTransportFactory` whose transports enforce the
+    /// frame size limit in `config`.
+    pub fn with_config(config: TConfiguration) -> TFramedWriteTransportFactory {
+        TFramedWriteTransportFactory {
+            config,
+            ..TFramedWriteTransportFactory::default()
+        }
+    }
+
+    /// Make the created transports check their frame buffers out of
+    /// `pool`.
+    pub fn with_buffer_pool(self, pool: TBufferPool) -> TFramedWriteTransportFactory {
+        TFramedWriteTransportFactory {
+            pool: Some(pool),
+            ..self
+        }
+    }
+
+    /// Make the created transports append a CRC32C trailer to every frame.
+    pub fn with_crc32c(self) -> TFramedWriteTransportFactory {
+        TFramedWriteTransportFactory {
+            checksum: true,
+            ..self
+        }
     }
 }
 
 impl TWriteTransportFactory for TFramedWriteTransportFactory {
     /// Create a `TFramedWriteTransport`.
     fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
-        Box::new(TFramedWriteTransport::new(channel))
+        let mut transport = TFramedWriteTransport::with_config(channel, self.config.clone());
+        if self.checksum {
+            transport = transport.with_crc32c();
+        }
+        match self.pool {
+            Some(ref pool) => Box::new(transport.with_buffer_pool(pool.clone())),
+            None => Box::new(transport),
+        }
     }
 }
 
@@ -479,4 +754,276 @@ mod tests {
         // check the flushed bytes
         assert_eq!(t.channel.write_bytes(), expected);
     }
+
+    #[test]
+    fn must_write_crc32c_trailer_after_frame() {
+        let mem = TBufferChannel::with_capacity(0, 20);
+        let mut t = TFramedWriteTransport::new(mem).with_crc32c();
+
+        assert_eq!(t.write(b"123456789").unwrap(), 9);
+        assert!(t.flush().is_ok());
+
+        #[rustfmt::skip]
+        let expected = [
+            0x00, 0x00, 0x00, 0x09, /* message size */
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, /* message body */
+            0xE3, 0x06, 0x92, 0x83, /* crc32c */
+        ];
+
+        assert_eq_transport_written_bytes!(t, expected);
+    }
+
+    #[test]
+    fn must_read_frame_with_valid_crc32c_trailer() {
+        let c = TBufferChannel::with_capacity(20, 0);
+        let mut t = TFramedReadTransport::new(c).with_crc32c();
+
+        t.chan.set_readable_bytes(&[
+            0x00, 0x00, 0x00, 0x09, /* message size */
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, /* message body */
+            0xE3, 0x06, 0x92, 0x83, /* crc32c */
+        ]);
+
+        let mut buf = vec![0; 16];
+        assert_eq!(t.read(&mut buf).unwrap(), 9);
+        assert_eq!(&buf[..9], b"123456789");
+    }
+
+    #[test]
+    fn must_reject_frame_with_corrupted_body() {
+        let c = TBufferChannel::with_capacity(20, 0);
+        let mut t = TFramedReadTransport::new(c).with_crc32c();
+
+        t.chan.set_readable_bytes(&[
+            0x00, 0x00, 0x00, 0x09, /* message size */
+            0x31, 0x32, 0x33, 0x34, 0xFF, 0x36, 0x37, 0x38, 0x39, /* corrupted body */
+            0xE3, 0x06, 0x92, 0x83, /* crc32c */
+        ]);
+
+        let mut buf = vec![0; 16];
+        let err = t.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn must_expose_current_frame_through_fill_buf() {
+        let c = TBufferChannel::with_capacity(20, 0);
+        let mut t = TFramedReadTransport::with_capacity(2, c);
+
+        t.chan.set_readable_bytes(&[
+            0x00, 0x00, 0x00, 0x03, /* message size */
+            0x00, 0x01, 0x02, /* message body */
+            0x00, 0x00, 0x00, 0x01, /* message size */
+            0x03, /* message body */
+        ]);
+
+        assert_eq!(t.fill_buf().unwrap(), &[0x00, 0x01, 0x02]);
+        t.consume(2);
+        assert_eq!(t.fill_buf().unwrap(), &[0x02]);
+        t.consume(1);
+
+        // the next frame is only read once the current one is exhausted
+        assert_eq!(t.fill_buf().unwrap(), &[0x03]);
+    }
+
+    fn frame_limit_config(max_frame_size: usize) -> TConfiguration {
+        TConfiguration::builder()
+            .max_frame_size(Some(max_frame_size))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn must_reject_frame_larger_than_configured_limit() {
+        let c = TBufferChannel::with_capacity(20, 0);
+        let mut t = TFramedReadTransport::with_config(c, frame_limit_config(3));
+
+        t.chan.set_readable_bytes(&[
+            0x00, 0x00, 0x00, 0x04, /* message size */
+            0x00, 0x01, 0x02, 0x03, /* message body */
+        ]);
+
+        let mut buf = vec![0; 8];
+        let err = t.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // rejected before the frame buffer was grown
+        assert_eq!(t.buf.len(), READ_CAPACITY);
+    }
+
+    #[test]
+    fn must_check_crc32c_trailer_and_configured_limit_together() {
+        let c = TBufferChannel::with_capacity(40, 0);
+        let mut t = TFramedReadTransport::with_config(c, frame_limit_config(9)).with_crc32c();
+
+        t.chan.set_readable_bytes(&[
+            0x00, 0x00, 0x00, 0x09, /* message size */
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, /* message body */
+            0xE3, 0x06, 0x92, 0x83, /* crc32c */
+            0x00, 0x00, 0x00, 0x0A, /* message size over the limit */
+        ]);
+
+        let mut buf = vec![0; 16];
+        assert_eq!(t.read(&mut buf).unwrap(), 9);
+        assert_eq!(&buf[..9], b"123456789");
+        let err = t.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn must_create_crc32c_transports_from_factories() {
+        let config = frame_limit_config(16);
+        let mut channel = TBufferChannel::with_capacity(40, 40);
+
+        let mut w = TFramedWriteTransportFactory::with_config(config.clone())
+            .with_crc32c()
+            .create(Box::new(channel.clone()));
+        w.write_all(b"123456789").unwrap();
+        w.flush().unwrap();
+        #[rustfmt::skip]
+        assert_eq!(
+            channel.write_bytes(),
+            [
+                0x00, 0x00, 0x00, 0x09, /* message size */
+                0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, /* message body */
+                0xE3, 0x06, 0x92, 0x83, /* crc32c */
+            ]
+        );
+
+        channel.copy_write_buffer_to_read_buffer();
+        let mut r = TFramedReadTransportFactory::with_config(config)
+            .with_crc32c()
+            .create(Box::new(channel.clone()));
+        let mut buf = vec![0; 16];
+        assert_eq!(r.read(&mut buf).unwrap(), 9);
+        assert_eq!(&buf[..9], b"123456789");
+    }
+
+    #[test]
+    fn must_refuse_to_write_frame_larger_than_configured_limit() {
+        let mem = TBufferChannel::with_capacity(0, 20);
+        let mut t = TFramedWriteTransport::with_config(mem, frame_limit_config(4));
+
+        assert_eq!(t.write(&[0x00, 0x01]).unwrap(), 2);
+        let err = t.write(&[0x02, 0x03, 0x04]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // the oversized frame is dropped and nothing is sent for it
+        assert!(t.flush().is_ok());
+        assert_eq_transport_num_written_bytes!(t, 0);
+
+        // the next frame is unaffected
+        assert_eq!(t.write(&[0x05, 0x06, 0x07, 0x08]).unwrap(), 4);
+        assert!(t.flush().is_ok());
+        let expected = [0x00, 0x00, 0x00, 0x04, 0x05, 0x06, 0x07, 0x08];
+        assert_eq_transport_written_bytes!(t, expected);
+    }
+
+    #[test]
+    fn must_discard_frame_when_flush_fails() {
+        let c = TBufferChannel::with_capacity(0, 8);
+        let mut t = TFramedWriteTransport::new(c);
+
+        // 4 byte header + 8 byte frame does not fit into the channel
+        t.write_all(&[1; 8]).unwrap();
+        assert!(t.flush().is_err());
+
+        t.channel.empty_write_buffer();
+        t.write_all(&[2; 2]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.channel.write_bytes(), vec![0, 0, 0, 2, 2, 2]);
+    }
+
+    #[test]
+    fn must_peek_into_current_frame_only() {
+        let c = TBufferChannel::with_capacity(20, 0);
+        let mut t = TFramedReadTransport::with_capacity(8, c);
+
+        t.chan.set_readable_bytes(&[
+            0x00, 0x00, 0x00, 0x02, /* message size */
+            0x01, 0x02, /* message body */
+            0x00, 0x00, 0x00, 0x01, /* message size */
+            0x03, /* message body */
+        ]);
+
+        let mut peeked = [0u8; 4];
+        assert_eq!(t.peek(&mut peeked).unwrap(), 2);
+        assert_eq!(&peeked[..2], &[0x01, 0x02]);
+
+        let mut read = [0u8; 2];
+        t.read_exact(&mut read).unwrap();
+        assert_eq!(read, [0x01, 0x02]);
+
+        assert_eq!(t.peek(&mut peeked).unwrap(), 1);
+        assert_eq!(peeked[0], 0x03);
+        t.read_exact(&mut read[..1]).unwrap();
+        assert_eq!(read[0], 0x03);
+    }
+
+    #[test]
+    fn must_send_pending_frame_on_finish() {
+        let c = TBufferChannel::with_capacity(0, 10);
+        let mut t = TFramedWriteTransport::new(c);
+        t.write_all(&[0x01, 0x02]).unwrap();
+
+        let c = t.finish().unwrap();
+        assert_eq!(c.write_bytes(), vec![0x00, 0x00, 0x00, 0x02, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn must_return_pooled_buffer_after_each_frame() {
+        let pool = TBufferPool::new(1024 * 1024);
+        let c = TBufferChannel::with_capacity(20, 20);
+        let mut t = TFramedReadTransport::new(c).with_buffer_pool(pool.clone());
+        t.chan.set_readable_bytes(&[
+            0x00, 0x00, 0x00, 0x02, /* message size */
+            0x01, 0x02, /* message body */
+            0x00, 0x00, 0x00, 0x01, /* message size */
+            0x03, /* message body */
+        ]);
+
+        let mut read = [0u8; 2];
+        t.read_exact(&mut read).unwrap();
+        assert_eq!(read, [0x01, 0x02]);
+        assert_eq!(pool.retained_buffers(), 0);
+
+        t.read_exact(&mut read[..1]).unwrap();
+        assert_eq!(read[0], 0x03);
+        assert_eq!(pool.retained_buffers(), 0);
+
+        drop(t);
+        assert_eq!(pool.retained_buffers(), 1);
+    }
+
+    #[test]
+    fn must_return_pooled_buffer_after_flush() {
+        let pool = TBufferPool::new(1024 * 1024);
+        let c = TBufferChannel::with_capacity(0, 20);
+        let mut t = TFramedWriteTransport::new(c).with_buffer_pool(pool.clone());
+
+        t.write_all(&[0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(pool.retained_buffers(), 0);
+        t.flush().unwrap();
+        assert_eq!(pool.retained_buffers(), 1);
+
+        t.write_all(&[0x04]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(pool.retained_buffers(), 1);
+        assert_eq!(
+            t.channel.write_bytes(),
+            vec![0x00, 0x00, 0x00, 0x03, 0x01, 0x02, 0x03, 0x00, 0x00, 0x00, 0x01, 0x04]
+        );
+    }
+
+    #[test]
+    fn must_return_pooled_buffer_after_oversized_write() {
+        let pool = TBufferPool::new(1024 * 1024);
+        let c = TBufferChannel::with_capacity(0, 20);
+        let mut t = TFramedWriteTransport::with_config(c, frame_limit_config(4))
+            .with_buffer_pool(pool.clone());
+
+        t.write_all(&[0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(pool.retained_buffers(), 0);
+        assert!(t.write_all(&[0x04, 0x05]).is_err());
+        assert_eq!(pool.retained_buffers(), 1);
+    }
 }
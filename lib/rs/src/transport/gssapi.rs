@@ -0,0 +1,598 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+
+use super::{TSaslMechanism, TSaslServerMechanism};
+use crate::{new_transport_error, TransportErrorKind};
+
+/// Largest buffer a GSSAPI security layer can announce.
+const MAX_BUFFER_SIZE: u32 = 0x00FF_FFFF;
+
+/// Default buffer size announced to the peer, as in the Java implementation.
+const DEFAULT_BUFFER_SIZE: u32 = 65536;
+
+/// Quality of protection of the data frames exchanged after authenticating
+/// with GSSAPI.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TSaslQop {
+    /// Authentication only; frames are sent as is.
+    Auth,
+    /// Frames are integrity protected.
+    AuthInt,
+    /// Frames are integrity protected and encrypted.
+    AuthConf,
+}
+
+impl TSaslQop {
+    fn bit(self) -> u8 {
+        match self {
+            TSaslQop::Auth => 0x01,
+            TSaslQop::AuthInt => 0x02,
+            TSaslQop::AuthConf => 0x04,
+        }
+    }
+
+    fn from_bit(bit: u8) -> Option<TSaslQop> {
+        match bit {
+            0x01 => Some(TSaslQop::Auth),
+            0x02 => Some(TSaslQop::AuthInt),
+            0x04 => Some(TSaslQop::AuthConf),
+            _ => None,
+        }
+    }
+}
+
+/// A GSS-API security context, usually backed by Kerberos.
+///
+/// This crate implements the SASL `GSSAPI` mechanism (RFC 4752) on top of
+/// this trait, while the context itself comes from the application's GSS-API
+/// library, for example a `libgssapi` client context initialized from a
+/// keytab or credential cache for the `hive/host@REALM` service principal.
+pub trait TGssContext: Send {
+    /// Process a token from the peer - empty for the first call of an
+    /// initiator - and return the token to send back, if any.
+    fn step(&mut self, token: &[u8]) -> crate::Result<Option<Vec<u8>>>;
+
+    /// Whether the context is fully established.
+    fn is_established(&self) -> bool;
+
+    /// Name of the authenticated peer principal. Only acceptors need to
+    /// provide it.
+    fn peer_name(&self) -> crate::Result<String>;
+
+    /// Protect `data`, encrypting it if `encrypt` is `true`.
+    fn wrap(&mut self, data: &[u8], encrypt: bool) -> crate::Result<Vec<u8>>;
+
+    /// Verify and, if needed, decrypt `data` protected by the peer.
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>>;
+}
+
+fn gssapi_error<S: Into<String>>(message: S) -> crate::Error {
+    new_transport_error(TransportErrorKind::Unknown, message)
+}
+
+/// Encode a security layer message: the layer bit mask followed by a 3-byte
+/// big-endian buffer size.
+fn layer_message(mask: u8, max_buffer: u32) -> Vec<u8> {
+    let size = max_buffer.min(MAX_BUFFER_SIZE).to_be_bytes();
+    vec![mask, size[1], size[2], size[3]]
+}
+
+/// Client side of the SASL `GSSAPI` mechanism, used to authenticate with
+/// Kerberos to secured HiveServer2, Impala or HBase servers.
+///
+/// After the GSS-API context is established the server offers security
+/// layers, and the client picks the first of its preferred qualities of
+/// protection that the server supports. By default it prefers `AuthConf`,
+/// then `AuthInt`, then `Auth`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::transport::{
+///     TGssContext, TSaslClientTransport, TSaslGssapi, TSaslQop, TTcpChannel,
+/// };
+///
+/// # fn kerberos_context() -> Box<dyn TGssContext> { unimplemented!() }
+/// let mut channel = TTcpChannel::new();
+/// channel.open("hive.example.com:10000").unwrap();
+///
+/// let mechanism = TSaslGssapi::new(kerberos_context()).with_qop(&[TSaslQop::Auth]);
+/// let transport = TSaslClientTransport::new(channel, mechanism).unwrap();
+/// ```
+pub struct TSaslGssapi<G> {
+    context: G,
+    qop: Vec<TSaslQop>,
+    authorization_id: Option<String>,
+    max_buffer: u32,
+    chosen: Option<TSaslQop>,
+}
+
+impl<G: TGssContext> TSaslGssapi<G> {
+    /// Authenticate with the initiator `context`.
+    pub fn new(context: G) -> TSaslGssapi<G> {
+        TSaslGssapi {
+            context,
+            qop: vec![TSaslQop::AuthConf, TSaslQop::AuthInt, TSaslQop::Auth],
+            authorization_id: None,
+            max_buffer: DEFAULT_BUFFER_SIZE,
+            chosen: None,
+        }
+    }
+
+    /// Accepted qualities of protection, in order of preference.
+    pub fn with_qop(mut self, qop: &[TSaslQop]) -> TSaslGssapi<G> {
+        self.qop = qop.to_vec();
+        self
+    }
+
+    /// Ask the server to act on behalf of `authorization_id` once the
+    /// principal of the context is authenticated.
+    pub fn with_authorization_id<A: Into<String>>(mut self, authorization_id: A) -> TSaslGssapi<G> {
+        self.authorization_id = Some(authorization_id.into());
+        self
+    }
+
+    /// Largest frame the client accepts, announced to the server.
+    pub fn with_max_buffer_size(mut self, size: u32) -> TSaslGssapi<G> {
+        self.max_buffer = size;
+        self
+    }
+
+    /// Quality of protection agreed with the server, once authenticated.
+    pub fn qop(&self) -> Option<TSaslQop> {
+        self.chosen
+    }
+
+    fn select_layer(&mut self, challenge: &[u8]) -> crate::Result<Vec<u8>> {
+        let offer = self.context.unwrap(challenge)?;
+        if offer.len() != 4 {
+            return Err(gssapi_error(
+                "invalid GSSAPI security layer offer from server",
+            ));
+        }
+
+        let chosen = self
+            .qop
+            .iter()
+            .copied()
+            .find(|qop| offer[0] & qop.bit() != 0)
+            .ok_or_else(|| gssapi_error("no quality of protection in common with server"))?;
+        let max_buffer = match chosen {
+            TSaslQop::Auth => 0,
+            _ => self.max_buffer,
+        };
+
+        let mut response = layer_message(chosen.bit(), max_buffer);
+        if let Some(ref authorization_id) = self.authorization_id {
+            response.extend_from_slice(authorization_id.as_bytes());
+        }
+        let response = self.context.wrap(&response, false)?;
+        self.chosen = Some(chosen);
+        Ok(response)
+    }
+}
+
+impl<G> fmt::Debug for TSaslGssapi<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TSaslGssapi")
+            .field("qop", &self.qop)
+            .field("authorization_id", &self.authorization_id)
+            .field("max_buffer", &self.max_buffer)
+            .field("chosen", &self.chosen)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<G: TGssContext> TSaslMechanism for TSaslGssapi<G> {
+    fn name(&self) -> &str {
+        "GSSAPI"
+    }
+
+    fn initial_response(&mut self) -> crate::Result<Option<Vec<u8>>> {
+        Ok(Some(self.context.step(&[])?.unwrap_or_default()))
+    }
+
+    fn evaluate_challenge(&mut self, challenge: &[u8]) -> crate::Result<Vec<u8>> {
+        if self.context.is_established() {
+            self.select_layer(challenge)
+        } else {
+            Ok(self.context.step(challenge)?.unwrap_or_default())
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.chosen.is_some()
+    }
+
+    fn has_security_layer(&self) -> bool {
+        matches!(self.chosen, Some(TSaslQop::AuthInt | TSaslQop::AuthConf))
+    }
+
+    fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        let encrypt = self.chosen == Some(TSaslQop::AuthConf);
+        self.context.wrap(data, encrypt)
+    }
+
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        self.context.unwrap(data)
+    }
+}
+
+/// Signature of the function that decides whether an authenticated Kerberos
+/// principal (the first argument) may act as the requested authorization id
+/// (the second argument).
+pub type TSaslGssapiAuthorizer = dyn Fn(&str, &str) -> bool + Send;
+
+/// Server side of the SASL `GSSAPI` mechanism, which authenticates clients
+/// with an acceptor context and then checks the authenticated principal.
+///
+/// By default a client may only act as its own principal. Use
+/// [`with_authorizer`](Self::with_authorizer) to allow impersonation, or to
+/// restrict which principals are accepted at all.
+pub struct TSaslGssapiServer<G> {
+    context: G,
+    qop: Vec<TSaslQop>,
+    max_buffer: u32,
+    authorizer: Box<TSaslGssapiAuthorizer>,
+    offered_layers: bool,
+    chosen: Option<TSaslQop>,
+    authorization_id: Option<String>,
+}
+
+impl<G: TGssContext> TSaslGssapiServer<G> {
+    /// Authenticate clients with the acceptor `context`.
+    pub fn new(context: G) -> TSaslGssapiServer<G> {
+        TSaslGssapiServer {
+            context,
+            qop: vec![TSaslQop::AuthConf, TSaslQop::AuthInt, TSaslQop::Auth],
+            max_buffer: DEFAULT_BUFFER_SIZE,
+            authorizer: Box::new(|principal, authorization_id| principal == authorization_id),
+            offered_layers: false,
+            chosen: None,
+            authorization_id: None,
+        }
+    }
+
+    /// Qualities of protection offered to clients.
+    pub fn with_qop(mut self, qop: &[TSaslQop]) -> TSaslGssapiServer<G> {
+        self.qop = qop.to_vec();
+        self
+    }
+
+    /// Largest frame the server accepts, announced to clients.
+    pub fn with_max_buffer_size(mut self, size: u32) -> TSaslGssapiServer<G> {
+        self.max_buffer = size;
+        self
+    }
+
+    /// Decide with `authorizer` whether an authenticated principal may act
+    /// as the authorization id it requested. A client that requests none
+    /// asks to act as its own principal.
+    pub fn with_authorizer<F>(mut self, authorizer: F) -> TSaslGssapiServer<G>
+    where
+        F: Fn(&str, &str) -> bool + Send + 'static,
+    {
+        self.authorizer = Box::new(authorizer);
+        self
+    }
+
+    /// Quality of protection agreed with the client, once authenticated.
+    pub fn qop(&self) -> Option<TSaslQop> {
+        self.chosen
+    }
+
+    fn offer_layers(&mut self) -> crate::Result<Vec<u8>> {
+        let mask = self.qop.iter().fold(0, |mask, qop| mask | qop.bit());
+        self.offered_layers = true;
+        self.context
+            .wrap(&layer_message(mask, self.max_buffer), false)
+    }
+
+    fn accept_layer(&mut self, response: &[u8]) -> crate::Result<Vec<u8>> {
+        let response = self.context.unwrap(response)?;
+        if response.len() < 4 {
+            return Err(gssapi_error(
+                "invalid GSSAPI security layer selection from client",
+            ));
+        }
+        let chosen = TSaslQop::from_bit(response[0])
+            .filter(|qop| self.qop.contains(qop))
+            .ok_or_else(|| gssapi_error("client selected an unsupported quality of protection"))?;
+
+        let principal = self.context.peer_name()?;
+        let requested = std::str::from_utf8(&response[4..])
+            .map_err(|_| gssapi_error("invalid GSSAPI authorization id"))?;
+        let authorization_id = if requested.is_empty() {
+            principal.as_str()
+        } else {
+            requested
+        };
+        if !(self.authorizer)(&principal, authorization_id) {
+            return Err(gssapi_error(format!(
+                "{} is not allowed to act as {}",
+                principal, authorization_id
+            )));
+        }
+
+        self.authorization_id = Some(authorization_id.to_owned());
+        self.chosen = Some(chosen);
+        Ok(Vec::new())
+    }
+}
+
+impl<G> fmt::Debug for TSaslGssapiServer<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TSaslGssapiServer")
+            .field("qop", &self.qop)
+            .field("max_buffer", &self.max_buffer)
+            .field("chosen", &self.chosen)
+            .field("authorization_id", &self.authorization_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<G: TGssContext> TSaslServerMechanism for TSaslGssapiServer<G> {
+    fn name(&self) -> &str {
+        "GSSAPI"
+    }
+
+    fn evaluate_response(&mut self, response: &[u8]) -> crate::Result<Vec<u8>> {
+        if self.offered_layers {
+            return self.accept_layer(response);
+        }
+        if self.context.is_established() {
+            // the client acknowledged the last context token
+            return self.offer_layers();
+        }
+
+        match self.context.step(response)? {
+            Some(token) => Ok(token),
+            None if self.context.is_established() => self.offer_layers(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.authorization_id.is_some()
+    }
+
+    fn authorization_id(&self) -> Option<&str> {
+        self.authorization_id.as_deref()
+    }
+
+    fn has_security_layer(&self) -> bool {
+        matches!(self.chosen, Some(TSaslQop::AuthInt | TSaslQop::AuthConf))
+    }
+
+    fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        let encrypt = self.chosen == Some(TSaslQop::AuthConf);
+        self.context.wrap(data, encrypt)
+    }
+
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        self.context.unwrap(data)
+    }
+}
+
+impl<G: TGssContext + ?Sized> TGssContext for Box<G> {
+    fn step(&mut self, token: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+        (**self).step(token)
+    }
+
+    fn is_established(&self) -> bool {
+        (**self).is_established()
+    }
+
+    fn peer_name(&self) -> crate::Result<String> {
+        (**self).peer_name()
+    }
+
+    fn wrap(&mut self, data: &[u8], encrypt: bool) -> crate::Result<Vec<u8>> {
+        (**self).wrap(data, encrypt)
+    }
+
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        (**self).unwrap(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::*;
+    use crate::transport::{TSaslClientTransport, TSaslServerTransport};
+
+    /// Context that establishes after one token in each direction and
+    /// marks (and, when encrypting, scrambles) wrapped data.
+    struct FakeContext {
+        initiator: bool,
+        established: bool,
+    }
+
+    impl FakeContext {
+        fn initiator() -> FakeContext {
+            FakeContext {
+                initiator: true,
+                established: false,
+            }
+        }
+
+        fn acceptor() -> FakeContext {
+            FakeContext {
+                initiator: false,
+                established: false,
+            }
+        }
+    }
+
+    impl TGssContext for FakeContext {
+        fn step(&mut self, token: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+            match (self.initiator, token) {
+                (true, b"") => Ok(Some(b"ap-req".to_vec())),
+                (true, b"ap-rep") | (false, b"ap-req") => {
+                    self.established = true;
+                    Ok(if self.initiator {
+                        None
+                    } else {
+                        Some(b"ap-rep".to_vec())
+                    })
+                }
+                _ => Err(gssapi_error("unexpected token")),
+            }
+        }
+
+        fn is_established(&self) -> bool {
+            self.established
+        }
+
+        fn peer_name(&self) -> crate::Result<String> {
+            Ok("user@EXAMPLE.COM".to_owned())
+        }
+
+        fn wrap(&mut self, data: &[u8], encrypt: bool) -> crate::Result<Vec<u8>> {
+            let mut wrapped = vec![if encrypt { b'E' } else { b'I' }];
+            wrapped.extend(data.iter().map(|b| if encrypt { b ^ 0x5A } else { *b }));
+            Ok(wrapped)
+        }
+
+        fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+            match data.split_first() {
+                Some((b'E', rest)) => Ok(rest.iter().map(|b| b ^ 0x5A).collect()),
+                Some((b'I', rest)) => Ok(rest.to_vec()),
+                _ => Err(gssapi_error("invalid wrapped data")),
+            }
+        }
+    }
+
+    fn message(err: crate::Error) -> String {
+        match err {
+            crate::Error::Transport(e) => e.message,
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    /// Run `server` and `client` on the two ends of a loopback connection.
+    fn run<S, C>(server: S, client: C)
+    where
+        S: FnOnce(TcpStream) + Send + 'static,
+        C: FnOnce(TcpStream),
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || server(listener.accept().unwrap().0));
+        client(TcpStream::connect(address).unwrap());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn must_authenticate_and_encrypt_frames() {
+        run(
+            |s| {
+                let mechanism: Box<dyn TSaslServerMechanism> =
+                    Box::new(TSaslGssapiServer::new(FakeContext::acceptor()));
+                let mut t = TSaslServerTransport::accept(s, vec![mechanism]).unwrap();
+                assert_eq!(t.authorization_id(), "user@EXAMPLE.COM");
+
+                let mut request = [0u8; 4];
+                t.read_exact(&mut request).unwrap();
+                assert_eq!(&request, b"ping");
+                t.write_all(b"pong").unwrap();
+                t.flush().unwrap();
+            },
+            |s| {
+                let mechanism = TSaslGssapi::new(FakeContext::initiator());
+                let mut t = TSaslClientTransport::new(s, mechanism).unwrap();
+                assert_eq!(t.mechanism_name(), "GSSAPI");
+
+                t.write_all(b"ping").unwrap();
+                t.flush().unwrap();
+                let mut reply = [0u8; 4];
+                t.read_exact(&mut reply).unwrap();
+                assert_eq!(&reply, b"pong");
+            },
+        );
+    }
+
+    #[test]
+    fn must_select_first_preferred_qop_offered_by_server() {
+        let mut client = TSaslGssapi::new(FakeContext::initiator())
+            .with_qop(&[TSaslQop::AuthConf, TSaslQop::Auth])
+            .with_authorization_id("hive");
+        let mut server = TSaslGssapiServer::new(FakeContext::acceptor())
+            .with_qop(&[TSaslQop::AuthInt, TSaslQop::Auth])
+            .with_authorizer(|principal, authorization_id| {
+                principal == "user@EXAMPLE.COM" && authorization_id == "hive"
+            });
+
+        let token = client.initial_response().unwrap().unwrap();
+        let token = server.evaluate_response(&token).unwrap();
+        let ack = client.evaluate_challenge(&token).unwrap();
+        let offer = server.evaluate_response(&ack).unwrap();
+        let selection = client.evaluate_challenge(&offer).unwrap();
+        assert!(client.is_complete());
+        server.evaluate_response(&selection).unwrap();
+        assert!(server.is_complete());
+
+        assert_eq!(client.qop(), Some(TSaslQop::Auth));
+        assert_eq!(server.qop(), Some(TSaslQop::Auth));
+        assert!(!TSaslMechanism::has_security_layer(&client));
+        assert_eq!(server.authorization_id(), Some("hive"));
+    }
+
+    #[test]
+    fn must_fail_without_common_qop() {
+        let mut client = TSaslGssapi::new(FakeContext::initiator()).with_qop(&[TSaslQop::AuthConf]);
+        let mut server =
+            TSaslGssapiServer::new(FakeContext::acceptor()).with_qop(&[TSaslQop::Auth]);
+
+        let token = client.initial_response().unwrap().unwrap();
+        let token = server.evaluate_response(&token).unwrap();
+        let ack = client.evaluate_challenge(&token).unwrap();
+        let offer = server.evaluate_response(&ack).unwrap();
+        let err = client.evaluate_challenge(&offer).unwrap_err();
+        assert_eq!(
+            message(err),
+            "no quality of protection in common with server"
+        );
+    }
+
+    #[test]
+    fn must_reject_unauthorized_impersonation() {
+        run(
+            |s| {
+                let mechanism: Box<dyn TSaslServerMechanism> =
+                    Box::new(TSaslGssapiServer::new(FakeContext::acceptor()));
+                let err = TSaslServerTransport::accept(s, vec![mechanism]).unwrap_err();
+                assert_eq!(
+                    message(err),
+                    "user@EXAMPLE.COM is not allowed to act as admin"
+                );
+            },
+            |s| {
+                let mechanism =
+                    TSaslGssapi::new(FakeContext::initiator()).with_authorization_id("admin");
+                let err = TSaslClientTransport::new(s, mechanism).unwrap_err();
+                assert!(message(err).contains("not allowed to act as admin"));
+            },
+        );
+    }
+}
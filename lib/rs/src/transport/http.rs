@@ -0,0 +1,927 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, IoSlice, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::{ReadHalf, TIoChannel, TSharedChannel, WriteHalf};
+use crate::{new_transport_error, TConfiguration, TransportErrorKind};
+
+/// Default number of redirects followed for a single request.
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+/// Maximum combined size of the status line and headers of a response.
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+const CONTENT_TYPE: &str = "application/x-thrift";
+
+/// A bidirectional byte stream used to carry HTTP requests.
+pub trait THttpStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> THttpStream for T {}
+
+/// Opens the connections used by a `THttpClient`.
+///
+/// Implement this to reach `https` URLs - for example by returning a
+/// `TTlsClientChannel` - or to tunnel through a proxy.
+pub trait THttpConnector: Send {
+    /// Connect to `host`:`port` for a URL with the given `scheme`.
+    fn connect(&self, scheme: &str, host: &str, port: u16) -> io::Result<Box<dyn THttpStream>>;
+}
+
+/// Plain TCP connector for `http` URLs.
+#[derive(Clone, Debug, Default)]
+pub struct THttpTcpConnector {
+    timeout: Option<Duration>,
+}
+
+impl THttpTcpConnector {
+    pub fn new() -> THttpTcpConnector {
+        THttpTcpConnector::default()
+    }
+
+    /// Create a `THttpTcpConnector` whose connections time out reads and
+    /// writes after `timeout`.
+    pub fn with_timeout(timeout: Duration) -> THttpTcpConnector {
+        THttpTcpConnector {
+            timeout: Some(timeout),
+        }
+    }
+}
+
+impl THttpConnector for THttpTcpConnector {
+    fn connect(&self, scheme: &str, host: &str, port: u16) -> io::Result<Box<dyn THttpStream>> {
+        if scheme != "http" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported URL scheme {scheme}; use a connector that supports it"),
+            ));
+        }
+        let stream = TcpStream::connect((host.trim_matches(|c| c == '[' || c == ']'), port))?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Client channel that sends Thrift messages as HTTP POST requests.
+///
+/// Bytes written to a `THttpClient` are buffered until `flush()`, which
+/// POSTs them to the configured URL with `Content-Type: application/x-thrift`
+/// and reads the complete response body. Subsequent reads are serviced from
+/// that body. Since output protocols flush once per message, every call is a
+/// single HTTP request.
+///
+/// Connections are kept alive between requests when the server allows it.
+/// Responses with status `301`, `302`, `307` or `308` are followed - with the
+/// same method and body - up to a configurable number of times. Any other
+/// non-`2xx` status fails the flush with an error that includes the status.
+///
+/// The default connector only supports `http` URLs. Supply a
+/// [`THttpConnector`] to `with_connector` to use TLS or a proxy.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{THttpClient, TIoChannel};
+///
+/// let mut channel = THttpClient::new("http://localhost:9090/thrift").unwrap();
+/// channel.set_header("Authorization", "Bearer token").unwrap();
+///
+/// let (i_chan, o_chan) = channel.split().unwrap();
+/// let i_prot = TBinaryInputProtocol::new(i_chan, true);
+/// let o_prot = TBinaryOutputProtocol::new(o_chan, true);
+/// ```
+#[derive(Clone, Debug)]
+pub struct THttpClient {
+    inner: TSharedChannel<HttpClient>,
+}
+
+impl THttpClient {
+    /// Create a `THttpClient` that posts to the `http` URL `url`.
+    pub fn new(url: &str) -> crate::Result<THttpClient> {
+        THttpClient::with_connector(url, THttpTcpConnector::new())
+    }
+
+    /// Create a `THttpClient` that posts to `url` over connections opened by
+    /// `connector`.
+    pub fn with_connector<C>(url: &str, connector: C) -> crate::Result<THttpClient>
+    where
+        C: THttpConnector + 'static,
+    {
+        let url = HttpUrl::parse(url).map_err(|e| {
+            new_transport_error(TransportErrorKind::Unknown, format!("invalid URL: {e}"))
+        })?;
+        Ok(THttpClient {
+            inner: TSharedChannel::new(HttpClient {
+                url,
+                connector: Box::new(connector),
+                headers: Vec::new(),
+                max_redirects: DEFAULT_MAX_REDIRECTS,
+                max_response_size: Some(TConfiguration::DEFAULT_MAX_MESSAGE_SIZE),
+                connection: None,
+                request: Vec::new(),
+                response: Response::default(),
+                pos: 0,
+            }),
+        })
+    }
+
+    /// Add a header to every request, replacing any header of the same name.
+    ///
+    /// Setting `Content-Type`, `Accept` or `User-Agent` replaces the default
+    /// value. `Host` and `Content-Length` are always computed by the client.
+    pub fn set_header(&mut self, name: &str, value: &str) -> crate::Result<()> {
+        if name.contains(['\r', '\n', ':']) || value.contains(['\r', '\n']) {
+            return Err(new_transport_error(
+                TransportErrorKind::Unknown,
+                format!("invalid HTTP header {name:?}"),
+            ));
+        }
+        let mut inner = self.inner.lock()?;
+        inner.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        inner.headers.push((name.to_owned(), value.to_owned()));
+        Ok(())
+    }
+
+    /// Stop sending the header `name`.
+    pub fn remove_header(&mut self, name: &str) -> crate::Result<()> {
+        let mut inner = self.inner.lock()?;
+        inner.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        Ok(())
+    }
+
+    /// Set the number of redirects followed for a single request. `0`
+    /// disables redirects.
+    pub fn set_max_redirects(&mut self, max_redirects: usize) -> crate::Result<()> {
+        self.inner.lock()?.max_redirects = max_redirects;
+        Ok(())
+    }
+
+    /// Set the largest response body accepted, or `None` for no limit.
+    /// Defaults to `TConfiguration::DEFAULT_MAX_MESSAGE_SIZE`.
+    pub fn set_max_response_size(&mut self, limit: Option<usize>) -> crate::Result<()> {
+        self.inner.lock()?.max_response_size = limit;
+        Ok(())
+    }
+
+    /// Return the status code of the last response, including unsuccessful
+    /// ones.
+    pub fn last_status(&self) -> crate::Result<Option<u16>> {
+        Ok(self.inner.lock()?.response.status)
+    }
+
+    /// Return the value of header `name` in the last response.
+    pub fn response_header(&self, name: &str) -> crate::Result<Option<String>> {
+        Ok(self.inner.lock()?.response.header(name).map(str::to_owned))
+    }
+}
+
+impl Read for THttpClient {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for THttpClient {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl TIoChannel for THttpClient {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+struct HttpClient {
+    url: HttpUrl,
+    connector: Box<dyn THttpConnector>,
+    headers: Vec<(String, String)>,
+    max_redirects: usize,
+    max_response_size: Option<usize>,
+    connection: Option<Connection>,
+    request: Vec<u8>,
+    response: Response,
+    pos: usize,
+}
+
+impl fmt::Debug for HttpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpClient")
+            .field("url", &self.url)
+            .field("headers", &self.headers)
+            .field("max_redirects", &self.max_redirects)
+            .field("max_response_size", &self.max_response_size)
+            .field("connected", &self.connection.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl HttpClient {
+    // io::Error::other requires Rust 1.74.
+    #[allow(unknown_lints)]
+    #[allow(clippy::io_other_error)]
+    fn post(&mut self) -> io::Result<()> {
+        let body = std::mem::take(&mut self.request);
+        let mut url = self.url.clone();
+        let mut redirects = 0;
+
+        loop {
+            self.response = self.round_trip(&url, &body)?;
+            self.pos = 0;
+            let status = self.response.status.unwrap_or_default();
+            match status {
+                200..=299 => return Ok(()),
+                301 | 302 | 307 | 308 if redirects < self.max_redirects => {
+                    let location = self.response.header("location").ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("HTTP redirect {status} without a Location header"),
+                        )
+                    })?;
+                    url = url.join(location)?;
+                    redirects += 1;
+                }
+                _ => {
+                    let reason = self.response.reason.clone();
+                    self.response.body.clear();
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("HTTP request to {url} failed with status {status} {reason}"),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn round_trip(&mut self, url: &HttpUrl, body: &[u8]) -> io::Result<Response> {
+        // a kept-alive connection may have been closed by the server since
+        // the last request; retry once on a fresh connection in that case
+        let reused = matches!(self.connection, Some(ref c) if c.origin == url.origin());
+        match self.try_round_trip(url, body) {
+            Err(ref e) if reused && is_stale_connection_error(e) => {
+                self.connection = None;
+                self.try_round_trip(url, body)
+            }
+            result => result,
+        }
+    }
+
+    fn try_round_trip(&mut self, url: &HttpUrl, body: &[u8]) -> io::Result<Response> {
+        if !matches!(self.connection, Some(ref c) if c.origin == url.origin()) {
+            let stream = self.connector.connect(&url.scheme, &url.host, url.port)?;
+            self.connection = Some(Connection {
+                origin: url.origin(),
+                stream: BufReader::new(stream),
+            });
+        }
+
+        let head = self.request_head(url, body.len());
+        let connection = self.connection.as_mut().expect("connection opened above");
+        let result = write_request(connection.stream.get_mut(), &head, body)
+            .and_then(|_| read_response(&mut connection.stream, self.max_response_size));
+        match result {
+            Ok((response, keep_alive)) => {
+                if !keep_alive {
+                    self.connection = None;
+                }
+                Ok(response)
+            }
+            Err(e) => {
+                self.connection = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn request_head(&self, url: &HttpUrl, content_length: usize) -> String {
+        let mut head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n",
+            url.path,
+            url.host_header(),
+            content_length
+        );
+        for (name, default) in [
+            ("Content-Type", CONTENT_TYPE),
+            ("Accept", CONTENT_TYPE),
+            ("User-Agent", "Thrift/Rust"),
+        ] {
+            if !self
+                .headers
+                .iter()
+                .any(|(n, _)| n.eq_ignore_ascii_case(name))
+            {
+                head.push_str(&format!("{name}: {default}\r\n"));
+            }
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+impl Read for HttpClient {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = &self.response.body[self.pos..];
+        let len = std::cmp::min(buf.len(), available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for HttpClient {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.request.is_empty() {
+            return Ok(());
+        }
+        self.post()
+    }
+}
+
+struct Connection {
+    origin: (String, String, u16),
+    stream: BufReader<Box<dyn THttpStream>>,
+}
+
+#[derive(Debug, Default)]
+struct Response {
+    status: Option<u16>,
+    reason: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn write_request(stream: &mut dyn THttpStream, head: &str, body: &[u8]) -> io::Result<()> {
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Read a response, returning it and whether the connection can be reused.
+fn read_response<R: BufRead>(
+    stream: &mut R,
+    max_body_size: Option<usize>,
+) -> io::Result<(Response, bool)> {
+    let mut header_bytes = 0;
+    let status_line = read_line(stream, &mut header_bytes)?;
+    if status_line.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before HTTP response",
+        ));
+    }
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status = parts
+        .next()
+        .and_then(|s| s.parse::<u16>().ok())
+        .filter(|_| version.starts_with("HTTP/1."))
+        .ok_or_else(|| invalid_response(format!("invalid status line {status_line:?}")))?;
+    let reason = parts.next().unwrap_or_default().to_owned();
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(stream, &mut header_bytes)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid_response(format!("invalid header line {line:?}")))?;
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+
+    let mut response = Response {
+        status: Some(status),
+        reason,
+        headers,
+        body: Vec::new(),
+    };
+
+    let connection = response.header("connection").map(str::to_ascii_lowercase);
+    let mut keep_alive = match connection.as_deref() {
+        Some("close") => false,
+        Some("keep-alive") => true,
+        _ => version == "HTTP/1.1",
+    };
+
+    let chunked = matches!(
+        response.header("transfer-encoding"),
+        Some(te) if te.to_ascii_lowercase().contains("chunked")
+    );
+    if chunked {
+        read_chunked_body(stream, &mut response.body, max_body_size)?;
+    } else if let Some(length) = response.header("content-length") {
+        let length = length
+            .parse::<usize>()
+            .map_err(|_| invalid_response(format!("invalid Content-Length {length:?}")))?;
+        check_body_size(length, max_body_size)?;
+        response.body.resize(length, 0);
+        stream.read_exact(&mut response.body)?;
+    } else if status != 204 && status != 304 {
+        // the body extends to the end of the connection
+        let limit = max_body_size.map_or(u64::MAX, |max| max as u64 + 1);
+        stream.take(limit).read_to_end(&mut response.body)?;
+        check_body_size(response.body.len(), max_body_size)?;
+        keep_alive = false;
+    }
+
+    Ok((response, keep_alive))
+}
+
+fn read_chunked_body<R: BufRead>(
+    stream: &mut R,
+    body: &mut Vec<u8>,
+    max_body_size: Option<usize>,
+) -> io::Result<()> {
+    let mut header_bytes = 0;
+    loop {
+        let line = read_line(stream, &mut header_bytes)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_response(format!("invalid chunk size {line:?}")))?;
+        if size == 0 {
+            break;
+        }
+        check_body_size(body.len().saturating_add(size), max_body_size)?;
+        let start = body.len();
+        body.resize(start + size, 0);
+        stream.read_exact(&mut body[start..])?;
+        if !read_line(stream, &mut header_bytes)?.is_empty() {
+            return Err(invalid_response("missing CRLF after chunk"));
+        }
+    }
+    // trailers
+    while !read_line(stream, &mut header_bytes)?.is_empty() {}
+    Ok(())
+}
+
+fn read_line<R: BufRead>(stream: &mut R, consumed: &mut usize) -> io::Result<String> {
+    let mut line = Vec::new();
+    let limit = MAX_HEADER_BYTES.saturating_sub(*consumed) as u64;
+    let read = stream.take(limit).read_until(b'\n', &mut line)?;
+    *consumed += read;
+    if line.last() != Some(&b'\n') {
+        if read == 0 && line.is_empty() {
+            return Ok(String::new());
+        }
+        return Err(invalid_response(
+            "HTTP response headers too long or truncated",
+        ));
+    }
+    while matches!(line.last(), Some(b'\n') | Some(b'\r')) {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| invalid_response("HTTP response line is not UTF-8"))
+}
+
+fn check_body_size(size: usize, max_body_size: Option<usize>) -> io::Result<()> {
+    match max_body_size {
+        Some(max) if size > max => Err(invalid_response(format!(
+            "HTTP response body of {size} bytes exceeds maximum allowed size of {max}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn invalid_response<E>(msg: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn is_stale_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// The parts of an absolute `http`/`https` URL used by the client.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct HttpUrl {
+    scheme: String,
+    host: String,
+    port: u16,
+    /// Path and query, always starting with `/`.
+    path: String,
+}
+
+impl HttpUrl {
+    fn parse(url: &str) -> io::Result<HttpUrl> {
+        let invalid =
+            |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{msg}: {url}"));
+
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| invalid("missing URL scheme"))?;
+        let scheme = scheme.to_ascii_lowercase();
+        let default_port = match scheme.as_str() {
+            "http" => 80,
+            "https" => 443,
+            _ => return Err(invalid("unsupported URL scheme")),
+        };
+
+        let (authority, path) = match rest.find(['/', '?', '#']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let path = path.split('#').next().unwrap_or_default();
+        let path = if path.starts_with('/') {
+            path.to_owned()
+        } else {
+            format!("/{path}")
+        };
+        if authority.contains('@') {
+            return Err(invalid("credentials in URLs are not supported"));
+        }
+
+        let (host, port) = if let Some(end) = authority.strip_prefix('[').and_then(|a| a.find(']'))
+        {
+            // IPv6 literal, kept in brackets
+            let host = &authority[..end + 2];
+            let port = &authority[end + 2..];
+            (host, port.strip_prefix(':'))
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid("missing URL host"));
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid("invalid URL port"))?,
+            None => default_port,
+        };
+
+        Ok(HttpUrl {
+            scheme,
+            host: host.to_ascii_lowercase(),
+            port,
+            path,
+        })
+    }
+
+    /// Resolve a `Location` header against this URL.
+    fn join(&self, location: &str) -> io::Result<HttpUrl> {
+        if location.contains("://") {
+            HttpUrl::parse(location)
+        } else if let Some(rest) = location.strip_prefix("//") {
+            HttpUrl::parse(&format!("{}://{}", self.scheme, rest))
+        } else if location.starts_with('/') {
+            Ok(HttpUrl {
+                path: location.to_owned(),
+                ..self.clone()
+            })
+        } else {
+            let base = &self.path[..self.path.rfind('/').map_or(0, |i| i + 1)];
+            Ok(HttpUrl {
+                path: format!("{base}{location}"),
+                ..self.clone()
+            })
+        }
+    }
+
+    fn origin(&self) -> (String, String, u16) {
+        (self.scheme.clone(), self.host.clone(), self.port)
+    }
+
+    fn host_header(&self) -> String {
+        let default_port = if self.scheme == "https" { 443 } else { 80 };
+        if self.port == default_port {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}{}", self.scheme, self.host_header(), self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+
+    struct Request {
+        head: String,
+        body: Vec<u8>,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.head.lines().skip(1).find_map(|l| {
+                let (n, v) = l.split_once(':')?;
+                n.eq_ignore_ascii_case(name).then(|| v.trim())
+            })
+        }
+    }
+
+    fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                return None;
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let mut request = Request {
+            head,
+            body: Vec::new(),
+        };
+        let length = request.header("content-length").unwrap().parse().unwrap();
+        request.body.resize(length, 0);
+        reader.read_exact(&mut request.body).unwrap();
+        Some(request)
+    }
+
+    /// Serve connections on a local port, answering each request with the
+    /// response returned by `respond`. Every request (and the index of the
+    /// connection it arrived on) is forwarded on the returned receiver.
+    fn serve<F>(respond: F) -> (String, mpsc::Receiver<(usize, Request)>)
+    where
+        F: Fn(&Request) -> Vec<u8> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (index, stream) in listener.incoming().enumerate() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                while let Some(request) = read_request(&mut reader) {
+                    let response = respond(&request);
+                    let close = request.head.starts_with("POST /close");
+                    if tx.send((index, request)).is_err() {
+                        return;
+                    }
+                    writer.write_all(&response).unwrap();
+                    if close {
+                        break;
+                    }
+                }
+            }
+        });
+        (url, rx)
+    }
+
+    fn echo(request: &Request) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-thrift\r\nX-Echo: yes\r\nContent-Length: {}\r\n\r\n",
+            request.body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&request.body);
+        response
+    }
+
+    fn call(client: &mut THttpClient, payload: &[u8]) -> io::Result<Vec<u8>> {
+        client.write_all(payload)?;
+        client.flush()?;
+        let mut response = Vec::new();
+        client.read_to_end(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn must_post_flushed_bytes_and_read_response_body() {
+        let (url, requests) = serve(echo);
+        let mut client = THttpClient::new(&format!("{url}/thrift?x=1")).unwrap();
+
+        assert_eq!(call(&mut client, b"hello").unwrap(), b"hello");
+        assert_eq!(client.last_status().unwrap(), Some(200));
+        assert_eq!(
+            client.response_header("x-echo").unwrap().as_deref(),
+            Some("yes")
+        );
+
+        let (_, request) = requests.recv().unwrap();
+        assert!(request.head.starts_with("POST /thrift?x=1 HTTP/1.1\r\n"));
+        assert_eq!(request.header("content-type"), Some(CONTENT_TYPE));
+        assert_eq!(request.header("accept"), Some(CONTENT_TYPE));
+        assert_eq!(request.header("host"), Some(&url["http://".len()..]));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn must_send_custom_headers() {
+        let (url, requests) = serve(echo);
+        let mut client = THttpClient::new(&url).unwrap();
+        client.set_header("Authorization", "Bearer abc").unwrap();
+        client
+            .set_header("Content-Type", "application/vnd.apache.thrift.binary")
+            .unwrap();
+        client.set_header("X-Removed", "1").unwrap();
+        client.remove_header("x-removed").unwrap();
+
+        call(&mut client, b"x").unwrap();
+
+        let (_, request) = requests.recv().unwrap();
+        assert_eq!(request.header("authorization"), Some("Bearer abc"));
+        assert_eq!(
+            request.header("content-type"),
+            Some("application/vnd.apache.thrift.binary")
+        );
+        assert_eq!(request.head.matches("Content-Type").count(), 1);
+        assert_eq!(request.header("x-removed"), None);
+
+        assert!(client.set_header("X-Bad", "a\r\nInjected: 1").is_err());
+    }
+
+    #[test]
+    fn must_reuse_kept_alive_connection() {
+        let (url, requests) = serve(echo);
+        let mut client = THttpClient::new(&url).unwrap();
+
+        assert_eq!(call(&mut client, b"one").unwrap(), b"one");
+        assert_eq!(call(&mut client, b"two").unwrap(), b"two");
+
+        assert_eq!(requests.recv().unwrap().0, 0);
+        assert_eq!(requests.recv().unwrap().0, 0);
+    }
+
+    #[test]
+    fn must_reconnect_after_server_closes_connection() {
+        let (url, requests) = serve(echo);
+        let mut client = THttpClient::new(&format!("{url}/close")).unwrap();
+
+        assert_eq!(call(&mut client, b"one").unwrap(), b"one");
+        assert_eq!(call(&mut client, b"two").unwrap(), b"two");
+
+        assert_eq!(requests.recv().unwrap().0, 0);
+        assert_eq!(requests.recv().unwrap().0, 1);
+    }
+
+    #[test]
+    fn must_decode_chunked_response() {
+        let (url, _requests) = serve(|_| {
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3;ext=1\r\nabc\r\n4\r\ndefg\r\n0\r\nX-Trailer: 1\r\n\r\n"
+                .to_vec()
+        });
+        let mut client = THttpClient::new(&url).unwrap();
+
+        assert_eq!(call(&mut client, b"x").unwrap(), b"abcdefg");
+        // the connection is still usable after the trailer
+        assert_eq!(call(&mut client, b"y").unwrap(), b"abcdefg");
+    }
+
+    #[test]
+    fn must_follow_redirects_with_same_body() {
+        let (url, requests) = serve(|request| {
+            if request.head.starts_with("POST /old ") {
+                b"HTTP/1.1 307 Temporary Redirect\r\nLocation: /new\r\nContent-Length: 0\r\n\r\n"
+                    .to_vec()
+            } else {
+                echo(request)
+            }
+        });
+        let mut client = THttpClient::new(&format!("{url}/old")).unwrap();
+
+        assert_eq!(call(&mut client, b"payload").unwrap(), b"payload");
+
+        let (_, first) = requests.recv().unwrap();
+        let (_, second) = requests.recv().unwrap();
+        assert!(first.head.starts_with("POST /old "));
+        assert!(second.head.starts_with("POST /new "));
+        assert_eq!(second.body, b"payload");
+    }
+
+    #[test]
+    fn must_stop_following_redirects_at_limit() {
+        let (url, _requests) = serve(|_| {
+            b"HTTP/1.1 302 Found\r\nLocation: /loop\r\nContent-Length: 0\r\n\r\n".to_vec()
+        });
+        let mut client = THttpClient::new(&url).unwrap();
+        client.set_max_redirects(2).unwrap();
+
+        let err = call(&mut client, b"x").unwrap_err();
+        assert!(err.to_string().contains("302"), "{err}");
+        assert_eq!(client.last_status().unwrap(), Some(302));
+    }
+
+    #[test]
+    fn must_fail_flush_on_error_status() {
+        let (url, _requests) = serve(|_| {
+            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\n\r\nbusy".to_vec()
+        });
+        let mut client = THttpClient::new(&url).unwrap();
+
+        client.write_all(b"x").unwrap();
+        let err = client.flush().unwrap_err();
+        assert!(err.to_string().contains("503 Service Unavailable"), "{err}");
+        assert_eq!(client.last_status().unwrap(), Some(503));
+
+        // the error body is not handed to the protocol
+        let mut buf = [0u8; 4];
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn must_reject_oversized_response() {
+        let (url, _requests) = serve(echo);
+        let mut client = THttpClient::new(&url).unwrap();
+        client.set_max_response_size(Some(4)).unwrap();
+
+        let err = call(&mut client, b"too long").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn must_parse_urls() {
+        let url = HttpUrl::parse("http://Example.com").unwrap();
+        assert_eq!(
+            (url.host.as_str(), url.port, url.path.as_str()),
+            ("example.com", 80, "/")
+        );
+
+        let url = HttpUrl::parse("https://[::1]:8443/a/b?q=1#frag").unwrap();
+        assert_eq!(
+            (url.host.as_str(), url.port, url.path.as_str()),
+            ("[::1]", 8443, "/a/b?q=1")
+        );
+        assert_eq!(url.host_header(), "[::1]:8443");
+
+        assert_eq!(url.join("c").unwrap().path, "/a/c");
+        assert_eq!(url.join("/d").unwrap().path, "/d");
+        assert_eq!(url.join("//other/e").unwrap().host, "other");
+
+        assert!(HttpUrl::parse("localhost:9090").is_err());
+        assert!(HttpUrl::parse("ftp://localhost").is_err());
+        assert!(HttpUrl::parse("http://:80/").is_err());
+        assert!(HttpUrl::parse("http://host:port/").is_err());
+    }
+
+    #[test]
+    fn must_reject_https_with_default_connector() {
+        let mut client = THttpClient::new("https://localhost:1/").unwrap();
+        let err = call(&mut client, b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,450 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{ReadHalf, TIoChannel, WriteHalf};
+
+/// Receives notifications about the traffic on an instrumented channel.
+///
+/// Every method has an empty default implementation, so an observer only
+/// implements the events it is interested in. Methods are called on the
+/// thread doing the I/O and should return quickly: typically they increment
+/// a counter or record a histogram sample in the application's metrics
+/// library.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use thrift::transport::TTransportObserver;
+///
+/// #[derive(Default)]
+/// struct RequestBytes(AtomicU64);
+///
+/// impl TTransportObserver for RequestBytes {
+///     fn on_bytes_read(&self, count: usize) {
+///         self.0.fetch_add(count as u64, Ordering::Relaxed);
+///     }
+/// }
+/// ```
+pub trait TTransportObserver: Send + Sync {
+    /// A channel was opened.
+    fn on_open(&self) {}
+
+    /// `count` bytes were read from a channel.
+    fn on_bytes_read(&self, _count: usize) {}
+
+    /// `count` bytes were written to a channel.
+    fn on_bytes_written(&self, _count: usize) {}
+
+    /// A channel was flushed.
+    fn on_flush(&self) {}
+
+    /// A channel that had been open for `lifetime` was closed.
+    fn on_close(&self, _lifetime: Duration) {}
+}
+
+impl<T> TTransportObserver for Arc<T>
+where
+    T: TTransportObserver + ?Sized,
+{
+    fn on_open(&self) {
+        (**self).on_open()
+    }
+
+    fn on_bytes_read(&self, count: usize) {
+        (**self).on_bytes_read(count)
+    }
+
+    fn on_bytes_written(&self, count: usize) {
+        (**self).on_bytes_written(count)
+    }
+
+    fn on_flush(&self) {
+        (**self).on_flush()
+    }
+
+    fn on_close(&self, lifetime: Duration) {
+        (**self).on_close(lifetime)
+    }
+}
+
+/// `TTransportObserver` that keeps running totals.
+///
+/// Clones share the same counters, so a single `TTransportCounters` can be
+/// handed to every instrumented channel and read from a metrics exporter.
+#[derive(Clone, Debug, Default)]
+pub struct TTransportCounters {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    flushes: AtomicU64,
+    opened: AtomicU64,
+    closed: AtomicU64,
+}
+
+impl TTransportCounters {
+    /// Create a `TTransportCounters` with all counters at zero.
+    pub fn new() -> TTransportCounters {
+        TTransportCounters::default()
+    }
+
+    /// Total number of bytes read.
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Total number of bytes written.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Total number of flushes.
+    pub fn flushes(&self) -> u64 {
+        self.inner.flushes.load(Ordering::Relaxed)
+    }
+
+    /// Number of channels opened.
+    pub fn opened(&self) -> u64 {
+        self.inner.opened.load(Ordering::Relaxed)
+    }
+
+    /// Number of channels closed.
+    pub fn closed(&self) -> u64 {
+        self.inner.closed.load(Ordering::Relaxed)
+    }
+
+    /// Number of channels currently open.
+    pub fn open(&self) -> u64 {
+        // load `closed` first so a concurrent close cannot make this negative
+        let closed = self.closed();
+        self.opened().saturating_sub(closed)
+    }
+}
+
+impl TTransportObserver for TTransportCounters {
+    fn on_open(&self) {
+        self.inner.opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_bytes_read(&self, count: usize) {
+        self.inner
+            .bytes_read
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn on_bytes_written(&self, count: usize) {
+        self.inner
+            .bytes_written
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn on_flush(&self) {
+        self.inner.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_close(&self, _lifetime: Duration) {
+        self.inner.closed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Reports `on_open` when created and `on_close` when the last half of a
+/// channel is dropped.
+struct Lifetime {
+    observer: Arc<dyn TTransportObserver>,
+    opened_at: Instant,
+}
+
+impl Drop for Lifetime {
+    fn drop(&mut self) {
+        self.observer.on_close(self.opened_at.elapsed());
+    }
+}
+
+enum Inner<C>
+where
+    C: Read + Write,
+{
+    Whole(C),
+    Read(ReadHalf<C>),
+    Write(WriteHalf<C>),
+}
+
+/// Channel that reports its traffic to a `TTransportObserver`.
+///
+/// Bytes read and written, flushes, and the time between opening and
+/// closing the channel are reported as they happen. The channel counts as
+/// opened when the `TInstrumentedChannel` is created and as closed when it,
+/// or both halves returned by `split`, have been dropped.
+///
+/// `TServer::set_transport_observer` wraps every accepted connection in a
+/// `TInstrumentedChannel`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use thrift::protocol::TCompactInputProtocol;
+/// use thrift::protocol::TCompactOutputProtocol;
+/// use thrift::transport::{TInstrumentedChannel, TIoChannel, TTcpChannel, TTransportCounters};
+///
+/// let counters = TTransportCounters::new();
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let channel = TInstrumentedChannel::new(c, Arc::new(counters.clone()));
+/// let (i_chan, o_chan) = channel.split().unwrap();
+///
+/// let i_prot = TCompactInputProtocol::new(i_chan);
+/// let o_prot = TCompactOutputProtocol::new(o_chan);
+///
+/// // ...
+///
+/// println!("sent {} bytes", counters.bytes_written());
+/// ```
+pub struct TInstrumentedChannel<C>
+where
+    C: Read + Write,
+{
+    inner: Inner<C>,
+    lifetime: Arc<Lifetime>,
+}
+
+impl<C> TInstrumentedChannel<C>
+where
+    C: Read + Write,
+{
+    /// Create a `TInstrumentedChannel` that reports the traffic on `channel`
+    /// to `observer`.
+    pub fn new(channel: C, observer: Arc<dyn TTransportObserver>) -> TInstrumentedChannel<C> {
+        observer.on_open();
+        TInstrumentedChannel {
+            inner: Inner::Whole(channel),
+            lifetime: Arc::new(Lifetime {
+                observer,
+                opened_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn observer(&self) -> &dyn TTransportObserver {
+        &*self.lifetime.observer
+    }
+}
+
+impl<C> fmt::Debug for TInstrumentedChannel<C>
+where
+    C: Read + Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let half = match self.inner {
+            Inner::Whole(_) => "whole",
+            Inner::Read(_) => "read",
+            Inner::Write(_) => "write",
+        };
+        f.debug_struct("TInstrumentedChannel")
+            .field("half", &half)
+            .field("opened_at", &self.lifetime.opened_at)
+            .finish()
+    }
+}
+
+impl<C> TIoChannel for TInstrumentedChannel<C>
+where
+    C: TIoChannel,
+{
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        let (r_chan, w_chan) = match self.inner {
+            Inner::Whole(channel) => channel.split()?,
+            _ => {
+                return Err(crate::new_transport_error(
+                    crate::TransportErrorKind::Unknown,
+                    "cannot split a half of a TInstrumentedChannel",
+                ))
+            }
+        };
+        Ok((
+            ReadHalf::new(TInstrumentedChannel {
+                inner: Inner::Read(r_chan),
+                lifetime: self.lifetime.clone(),
+            }),
+            WriteHalf::new(TInstrumentedChannel {
+                inner: Inner::Write(w_chan),
+                lifetime: self.lifetime,
+            }),
+        ))
+    }
+}
+
+fn wrong_half(operation: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot {} this half of a TInstrumentedChannel", operation),
+    )
+}
+
+impl<C> Read for TInstrumentedChannel<C>
+where
+    C: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.inner {
+            Inner::Whole(ref mut c) => c.read(buf)?,
+            Inner::Read(ref mut c) => c.read(buf)?,
+            Inner::Write(_) => return Err(wrong_half("read from")),
+        };
+        self.observer().on_bytes_read(n);
+        Ok(n)
+    }
+}
+
+impl<C> Write for TInstrumentedChannel<C>
+where
+    C: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match self.inner {
+            Inner::Whole(ref mut c) => c.write(buf)?,
+            Inner::Write(ref mut c) => c.write(buf)?,
+            Inner::Read(_) => return Err(wrong_half("write to")),
+        };
+        self.observer().on_bytes_written(n);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let n = match self.inner {
+            Inner::Whole(ref mut c) => c.write_vectored(bufs)?,
+            Inner::Write(ref mut c) => c.write_vectored(bufs)?,
+            Inner::Read(_) => return Err(wrong_half("write to")),
+        };
+        self.observer().on_bytes_written(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            Inner::Whole(ref mut c) => c.flush()?,
+            Inner::Write(ref mut c) => c.flush()?,
+            Inner::Read(_) => return Err(wrong_half("flush")),
+        }
+        self.observer().on_flush();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::transport::TBufferChannel;
+
+    #[derive(Default)]
+    struct Lifetimes(Mutex<Vec<Duration>>);
+
+    impl TTransportObserver for Lifetimes {
+        fn on_close(&self, lifetime: Duration) {
+            self.0.lock().unwrap().push(lifetime);
+        }
+    }
+
+    #[test]
+    fn must_count_bytes_and_flushes() {
+        let counters = TTransportCounters::new();
+        let mut inner = TBufferChannel::new();
+        inner.set_readable_bytes(b"abcdef");
+        let mut channel = TInstrumentedChannel::new(inner.clone(), Arc::new(counters.clone()));
+
+        let mut buf = [0u8; 4];
+        channel.read_exact(&mut buf).unwrap();
+        channel.write_all(b"xyz").unwrap();
+        channel.flush().unwrap();
+        channel.flush().unwrap();
+
+        assert_eq!(counters.bytes_read(), 4);
+        assert_eq!(counters.bytes_written(), 3);
+        assert_eq!(counters.flushes(), 2);
+        assert_eq!(inner.write_bytes(), b"xyz");
+    }
+
+    #[test]
+    fn must_track_open_channels() {
+        let counters = TTransportCounters::new();
+        let first = TInstrumentedChannel::new(TBufferChannel::new(), Arc::new(counters.clone()));
+        let second = TInstrumentedChannel::new(TBufferChannel::new(), Arc::new(counters.clone()));
+        assert_eq!(counters.opened(), 2);
+        assert_eq!(counters.open(), 2);
+
+        drop(first);
+        assert_eq!(counters.closed(), 1);
+        assert_eq!(counters.open(), 1);
+
+        drop(second);
+        assert_eq!(counters.open(), 0);
+    }
+
+    #[test]
+    fn must_close_once_when_both_halves_are_dropped() {
+        let lifetimes = Arc::new(Lifetimes::default());
+        let observer: Arc<dyn TTransportObserver> = lifetimes.clone();
+        let mut inner = TBufferChannel::new();
+        inner.set_readable_bytes(b"in");
+
+        let channel = TInstrumentedChannel::new(inner, observer);
+        let (mut r_chan, mut w_chan) = channel.split().unwrap();
+        let mut buf = [0u8; 2];
+        r_chan.read_exact(&mut buf).unwrap();
+        w_chan.write_all(b"out").unwrap();
+
+        drop(r_chan);
+        assert!(lifetimes.0.lock().unwrap().is_empty());
+        drop(w_chan);
+        assert_eq!(lifetimes.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn must_report_traffic_on_split_halves() {
+        let counters = TTransportCounters::new();
+        let mut inner = TBufferChannel::new();
+        inner.set_readable_bytes(b"12345");
+
+        let channel = TInstrumentedChannel::new(inner, Arc::new(counters.clone()));
+        let (mut r_chan, mut w_chan) = channel.split().unwrap();
+        let mut buf = [0u8; 5];
+        r_chan.read_exact(&mut buf).unwrap();
+        w_chan.write_all(b"67").unwrap();
+        w_chan.flush().unwrap();
+
+        assert_eq!(counters.bytes_read(), 5);
+        assert_eq!(counters.bytes_written(), 2);
+        assert_eq!(counters.flushes(), 1);
+        assert_eq!(counters.opened(), 1);
+    }
+}
@@ -0,0 +1,374 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::{ReadHalf, TIoChannel, WriteHalf};
+
+/// Bounded byte queue carrying data in one direction.
+#[derive(Debug)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+impl Pipe {
+    fn new(capacity: usize) -> Arc<Pipe> {
+        Arc::new(Pipe {
+            state: Mutex::new(PipeState {
+                buf: VecDeque::with_capacity(capacity),
+                capacity,
+                reader_closed: false,
+                writer_closed: false,
+            }),
+            changed: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        // the state is always left consistent, so a poisoned lock is still
+        // usable
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait until `ready` holds, or `timeout` expires.
+    fn wait<'a, F>(
+        &self,
+        mut state: MutexGuard<'a, PipeState>,
+        timeout: Option<Duration>,
+        ready: F,
+    ) -> io::Result<MutexGuard<'a, PipeState>>
+    where
+        F: Fn(&PipeState) -> bool,
+    {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        while !ready(&state) {
+            state = match deadline {
+                None => self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "loopback channel timed out",
+                        ));
+                    }
+                    self.changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+        Ok(state)
+    }
+}
+
+/// Marks the read side of a pipe closed when the last handle is dropped.
+#[derive(Debug)]
+struct ReadEnd(Arc<Pipe>);
+
+impl Drop for ReadEnd {
+    fn drop(&mut self) {
+        self.0.lock().reader_closed = true;
+        self.0.changed.notify_all();
+    }
+}
+
+/// Marks the write side of a pipe closed when the last handle is dropped.
+#[derive(Debug)]
+struct WriteEnd(Arc<Pipe>);
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        self.0.lock().writer_closed = true;
+        self.0.changed.notify_all();
+    }
+}
+
+/// One end of an in-process, bidirectional channel.
+///
+/// `TLoopbackChannel::pair()` returns two connected channels: bytes written
+/// to one are read from the other. Each direction is a bounded in-memory
+/// queue: reads block until bytes are available and writes block while the
+/// queue is full, like a socket with a fixed-size buffer. This lets a client
+/// and a `TProcessor` talk to each other end-to-end in tests without opening
+/// sockets.
+///
+/// When a channel and both of its split halves have been dropped, reads on
+/// its peer return end-of-file once the queued bytes are consumed, and
+/// writes on its peer fail with `io::ErrorKind::BrokenPipe`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use std::thread;
+/// use thrift::transport::TLoopbackChannel;
+///
+/// let (mut client, mut server) = TLoopbackChannel::pair();
+///
+/// let echo = thread::spawn(move || {
+///     let mut buf = [0u8; 5];
+///     server.read_exact(&mut buf).unwrap();
+///     server.write_all(&buf).unwrap();
+/// });
+///
+/// client.write_all(b"hello").unwrap();
+/// let mut reply = [0u8; 5];
+/// client.read_exact(&mut reply).unwrap();
+/// assert_eq!(&reply, b"hello");
+/// echo.join().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TLoopbackChannel {
+    reader: Arc<ReadEnd>,
+    writer: Arc<WriteEnd>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl TLoopbackChannel {
+    /// Default number of bytes queued in each direction.
+    pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+    /// Create two connected channels that queue up to
+    /// `DEFAULT_CAPACITY` bytes in each direction.
+    pub fn pair() -> (TLoopbackChannel, TLoopbackChannel) {
+        TLoopbackChannel::pair_with_capacity(TLoopbackChannel::DEFAULT_CAPACITY)
+    }
+
+    /// Create two connected channels that queue up to `capacity` bytes in
+    /// each direction.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn pair_with_capacity(capacity: usize) -> (TLoopbackChannel, TLoopbackChannel) {
+        assert!(capacity > 0, "loopback capacity must be positive");
+        let a_to_b = Pipe::new(capacity);
+        let b_to_a = Pipe::new(capacity);
+        (
+            TLoopbackChannel::new(b_to_a.clone(), a_to_b.clone()),
+            TLoopbackChannel::new(a_to_b, b_to_a),
+        )
+    }
+
+    fn new(read: Arc<Pipe>, write: Arc<Pipe>) -> TLoopbackChannel {
+        TLoopbackChannel {
+            reader: Arc::new(ReadEnd(read)),
+            writer: Arc::new(WriteEnd(write)),
+            read_timeout: None,
+            write_timeout: None,
+        }
+    }
+
+    /// Fail reads with `io::ErrorKind::TimedOut` if no bytes arrive within
+    /// `timeout`. `None` blocks indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Fail writes with `io::ErrorKind::TimedOut` if the queue stays full
+    /// for `timeout`. `None` blocks indefinitely.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+    }
+
+    fn handle(&self) -> TLoopbackChannel {
+        TLoopbackChannel {
+            reader: self.reader.clone(),
+            writer: self.writer.clone(),
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+        }
+    }
+}
+
+impl TIoChannel for TLoopbackChannel {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.handle()), WriteHalf::new(self)))
+    }
+}
+
+impl Read for TLoopbackChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let pipe = &self.reader.0;
+        let mut state = pipe.wait(pipe.lock(), self.read_timeout, |s| {
+            !s.buf.is_empty() || s.writer_closed
+        })?;
+
+        let nread = buf.len().min(state.buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..nread)) {
+            *dst = src;
+        }
+        drop(state);
+        pipe.changed.notify_all();
+        Ok(nread)
+    }
+}
+
+impl Write for TLoopbackChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let pipe = &self.writer.0;
+        let mut state = pipe.wait(pipe.lock(), self.write_timeout, |s| {
+            s.buf.len() < s.capacity || s.reader_closed
+        })?;
+        if state.reader_closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "loopback peer closed",
+            ));
+        }
+
+        let nwrite = buf.len().min(state.capacity - state.buf.len());
+        state.buf.extend(&buf[..nwrite]);
+        drop(state);
+        pipe.changed.notify_all();
+        Ok(nwrite)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TMessageIdentifier,
+        TMessageType, TOutputProtocol,
+    };
+    use crate::transport::{TFramedReadTransport, TFramedWriteTransport};
+
+    #[test]
+    fn must_block_writer_until_reader_makes_room() {
+        let (mut a, mut b) = TLoopbackChannel::pair_with_capacity(4);
+
+        let writer = thread::spawn(move || {
+            a.write_all(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        });
+
+        let mut buf = [0u8; 10];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn must_return_eof_after_peer_is_dropped() {
+        let (mut a, b) = TLoopbackChannel::pair();
+        let (_, mut b_write) = b.split().unwrap();
+        b_write.write_all(&[1, 2]).unwrap();
+        drop(b_write);
+
+        let mut buf = Vec::new();
+        a.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, vec![1, 2]);
+    }
+
+    #[test]
+    fn must_fail_write_after_peer_is_dropped() {
+        let (mut a, b) = TLoopbackChannel::pair();
+        drop(b);
+
+        let err = a.write(&[1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn must_time_out_read_without_data() {
+        let (mut a, _b) = TLoopbackChannel::pair();
+        a.set_read_timeout(Some(Duration::from_millis(10)));
+
+        let err = a.read(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn must_carry_calls_between_client_and_server() {
+        let (client, server) = TLoopbackChannel::pair();
+
+        let server = thread::spawn(move || {
+            let (r_chan, w_chan) = server.split().unwrap();
+            let mut i_prot = TBinaryInputProtocol::new(TFramedReadTransport::new(r_chan), true);
+            let mut o_prot = TBinaryOutputProtocol::new(TFramedWriteTransport::new(w_chan), true);
+
+            // serve until the client goes away
+            while let Ok(ident) = i_prot.read_message_begin() {
+                let value = i_prot.read_i32().unwrap();
+                i_prot.read_message_end().unwrap();
+
+                o_prot
+                    .write_message_begin(&TMessageIdentifier::new(
+                        ident.name,
+                        TMessageType::Reply,
+                        ident.sequence_number,
+                    ))
+                    .unwrap();
+                o_prot.write_i32(value * 2).unwrap();
+                o_prot.write_message_end().unwrap();
+                o_prot.flush().unwrap();
+            }
+        });
+
+        let (r_chan, w_chan) = client.split().unwrap();
+        let mut i_prot = TBinaryInputProtocol::new(TFramedReadTransport::new(r_chan), true);
+        let mut o_prot = TBinaryOutputProtocol::new(TFramedWriteTransport::new(w_chan), true);
+        for seq in 1..=3 {
+            o_prot
+                .write_message_begin(&TMessageIdentifier::new("double", TMessageType::Call, seq))
+                .unwrap();
+            o_prot.write_i32(seq * 10).unwrap();
+            o_prot.write_message_end().unwrap();
+            o_prot.flush().unwrap();
+
+            let ident = i_prot.read_message_begin().unwrap();
+            assert_eq!(ident.sequence_number, seq);
+            assert_eq!(i_prot.read_i32().unwrap(), seq * 20);
+            i_prot.read_message_end().unwrap();
+        }
+
+        drop(i_prot);
+        drop(o_prot);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,576 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use byteorder::{ByteOrder, LittleEndian};
+use lz4_flex::frame::{FrameDecoder, FrameEncoder, FrameInfo};
+use std::io;
+use std::io::{Read, Write};
+
+use super::{
+    TFramedReadTransport, TFramedWriteTransport, TReadTransport, TReadTransportFactory,
+    TWriteTransport, TWriteTransportFactory,
+};
+use crate::TConfiguration;
+
+/// Magic number that starts every LZ4 frame.
+const MAGIC: u32 = 0x184D_2204;
+
+// frame descriptor flag bits
+const FLG_VERSION_MASK: u8 = 0b1100_0000;
+const FLG_VERSION: u8 = 0b0100_0000;
+const FLG_BLOCK_CHECKSUM: u8 = 0b0001_0000;
+const FLG_CONTENT_SIZE: u8 = 0b0000_1000;
+const FLG_CONTENT_CHECKSUM: u8 = 0b0000_0100;
+const FLG_DICT_ID: u8 = 0b0000_0001;
+
+/// High bit of a block size, set when the block is stored uncompressed.
+const BLOCK_UNCOMPRESSED: u32 = 0x8000_0000;
+
+/// Largest block size allowed by the LZ4 frame format (4 MiB).
+const MAX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Compressed and uncompressed sizes of one or more LZ4 frames.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TLz4FrameSizes {
+    /// Number of bytes on the wire, including the LZ4 frame header.
+    pub compressed: u64,
+    /// Number of bytes before compression.
+    pub uncompressed: u64,
+}
+
+impl TLz4FrameSizes {
+    fn add(&mut self, frame: TLz4FrameSizes) {
+        self.compressed += frame.compressed;
+        self.uncompressed += frame.uncompressed;
+    }
+}
+
+/// Per-frame and cumulative size counters kept by the LZ4 transports.
+#[derive(Clone, Copy, Debug, Default)]
+struct Counters {
+    frames: u64,
+    last: TLz4FrameSizes,
+    total: TLz4FrameSizes,
+}
+
+impl Counters {
+    fn record(&mut self, frame: TLz4FrameSizes) {
+        self.frames += 1;
+        self.last = frame;
+        self.total.add(frame);
+    }
+}
+
+/// Transport that reads messages compressed into LZ4 frames.
+///
+/// A `TLz4ReadTransport` reads one complete
+/// [LZ4 frame](https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md)
+/// from the wrapped channel, decompresses it into an internal buffer and
+/// services reads from that buffer until it is exhausted, at which point the
+/// next frame is read. It reads frames produced by a `TLz4WriteTransport` as
+/// well as any other standard LZ4 frame encoder; legacy and skippable frames
+/// are rejected.
+///
+/// LZ4 trades compression ratio for speed, which makes it a better fit than
+/// zlib or zstd for latency-sensitive services. It composes with the framed
+/// transport: wrap a `TFramedReadTransport` to read LZ4 frames that are
+/// themselves carried in Thrift frames.
+///
+/// Frames whose compressed or uncompressed size exceeds the `max_frame_size`
+/// of the transport's `TConfiguration` are rejected with
+/// `io::ErrorKind::InvalidData`.
+///
+/// # Examples
+///
+/// Create and use a `TLz4ReadTransport` over a framed transport.
+///
+/// ```no_run
+/// use std::io::Read;
+/// use thrift::transport::{TFramedReadTransport, TLz4ReadTransport, TTcpChannel};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let mut t = TLz4ReadTransport::new(TFramedReadTransport::new(c));
+///
+/// t.read(&mut vec![0u8; 1]).unwrap();
+///
+/// let last = t.last_frame_sizes();
+/// println!("{} -> {} bytes", last.compressed, last.uncompressed);
+/// ```
+#[derive(Debug)]
+pub struct TLz4ReadTransport<C>
+where
+    C: Read,
+{
+    chan: C,
+    config: TConfiguration,
+    frame: Vec<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+    counters: Counters,
+}
+
+impl<C> TLz4ReadTransport<C>
+where
+    C: Read,
+{
+    /// Create a `TLz4ReadTransport` that wraps the given `TIoChannel`.
+    pub fn new(channel: C) -> TLz4ReadTransport<C> {
+        TLz4ReadTransport::with_config(channel, TConfiguration::default())
+    }
+
+    /// Create a `TLz4ReadTransport` that wraps the given `TIoChannel` and
+    /// enforces the frame size limit in `config`.
+    pub fn with_config(channel: C, config: TConfiguration) -> TLz4ReadTransport<C> {
+        TLz4ReadTransport {
+            chan: channel,
+            config,
+            frame: Vec::new(),
+            buf: Vec::new(),
+            pos: 0,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Sizes of the most recently read LZ4 frame.
+    pub fn last_frame_sizes(&self) -> TLz4FrameSizes {
+        self.counters.last
+    }
+
+    /// Sizes of all LZ4 frames read so far.
+    pub fn total_sizes(&self) -> TLz4FrameSizes {
+        self.counters.total
+    }
+
+    /// Number of LZ4 frames read so far.
+    pub fn frame_count(&self) -> u64 {
+        self.counters.frames
+    }
+
+    /// Read and decompress the next LZ4 frame. Returns `false` if the channel
+    /// ended cleanly before a new frame started.
+    fn read_frame(&mut self) -> io::Result<bool> {
+        self.frame.clear();
+
+        if !self.read_magic()? {
+            return Ok(false);
+        }
+
+        // frame descriptor
+        let descriptor_start = self.frame.len();
+        self.read_to_frame(2)?;
+        let flg = self.frame[descriptor_start];
+        if flg & FLG_VERSION_MASK != FLG_VERSION {
+            return Err(invalid_data("unsupported LZ4 frame version"));
+        }
+        let mut optional = 1; // header checksum
+        if flg & FLG_CONTENT_SIZE != 0 {
+            optional += 8;
+        }
+        if flg & FLG_DICT_ID != 0 {
+            optional += 4;
+        }
+        let optional_start = self.frame.len();
+        self.read_to_frame(optional)?;
+        if flg & FLG_CONTENT_SIZE != 0 {
+            let content_size = LittleEndian::read_u64(&self.frame[optional_start..]);
+            self.check_size(content_size)?;
+        }
+
+        // data blocks, up to and including the end mark
+        loop {
+            let size_start = self.frame.len();
+            self.read_to_frame(4)?;
+            let block_size = LittleEndian::read_u32(&self.frame[size_start..]);
+            if block_size == 0 {
+                break;
+            }
+            let block_size = (block_size & !BLOCK_UNCOMPRESSED) as usize;
+            if block_size > MAX_BLOCK_SIZE {
+                return Err(invalid_data("LZ4 block larger than 4 MiB"));
+            }
+            let checksum_size = if flg & FLG_BLOCK_CHECKSUM != 0 { 4 } else { 0 };
+            self.check_size((self.frame.len() + block_size + checksum_size) as u64)?;
+            self.read_to_frame(block_size + checksum_size)?;
+        }
+        if flg & FLG_CONTENT_CHECKSUM != 0 {
+            self.read_to_frame(4)?;
+        }
+
+        // the frame is complete, so the decoder never touches the channel
+        let limit = self.config.max_frame_size().unwrap_or(usize::MAX) as u64;
+        self.buf.clear();
+        self.pos = 0;
+        FrameDecoder::new(&self.frame[..])
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut self.buf)?;
+        self.check_size(self.buf.len() as u64)?;
+
+        self.counters.record(TLz4FrameSizes {
+            compressed: self.frame.len() as u64,
+            uncompressed: self.buf.len() as u64,
+        });
+
+        Ok(true)
+    }
+
+    fn read_magic(&mut self) -> io::Result<bool> {
+        let mut magic = [0u8; 4];
+        let mut read = 0;
+        while read < magic.len() {
+            match self.chan.read(&mut magic[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if LittleEndian::read_u32(&magic) != MAGIC {
+            return Err(invalid_data("invalid LZ4 frame magic number"));
+        }
+        self.frame.extend_from_slice(&magic);
+        Ok(true)
+    }
+
+    fn read_to_frame(&mut self, len: usize) -> io::Result<()> {
+        let start = self.frame.len();
+        self.frame.resize(start + len, 0);
+        self.chan.read_exact(&mut self.frame[start..])
+    }
+
+    fn check_size(&self, size: u64) -> io::Result<()> {
+        if let Some(max_frame) = self.config.max_frame_size() {
+            if size > max_frame as u64 {
+                return Err(invalid_data(format!(
+                    "LZ4 frame size {} exceeds maximum allowed size of {}",
+                    size, max_frame
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C> Read for TLz4ReadTransport<C>
+where
+    C: Read,
+{
+    fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if !self.read_frame()? {
+                return Ok(0);
+            }
+        }
+
+        let len = std::cmp::min(b.len(), self.buf.len() - self.pos);
+        b[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Factory for creating instances of `TLz4ReadTransport`.
+#[derive(Debug, Default)]
+pub struct TLz4ReadTransportFactory {
+    framed: bool,
+}
+
+impl TLz4ReadTransportFactory {
+    /// Create a `TLz4ReadTransportFactory` whose transports read LZ4 frames
+    /// directly from the channel.
+    pub fn new() -> TLz4ReadTransportFactory {
+        TLz4ReadTransportFactory::default()
+    }
+
+    /// Create a `TLz4ReadTransportFactory` whose transports read LZ4 frames
+    /// carried in Thrift frames, i.e. over a `TFramedReadTransport`.
+    pub fn framed() -> TLz4ReadTransportFactory {
+        TLz4ReadTransportFactory { framed: true }
+    }
+}
+
+impl TReadTransportFactory for TLz4ReadTransportFactory {
+    /// Create a `TLz4ReadTransport`.
+    fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
+        if self.framed {
+            Box::new(TLz4ReadTransport::new(TFramedReadTransport::new(channel)))
+        } else {
+            Box::new(TLz4ReadTransport::new(channel))
+        }
+    }
+}
+
+/// Transport that writes messages compressed into LZ4 frames.
+///
+/// A `TLz4WriteTransport` buffers all bytes written to it. On a call to
+/// `flush()` - which output protocols make at the end of every message - the
+/// buffered bytes are compressed into a single
+/// [LZ4 frame](https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md)
+/// that is written to the wrapped channel, after which the channel is
+/// flushed. Each frame records its uncompressed size in the frame header.
+///
+/// It composes with the framed transport: wrapping a `TFramedWriteTransport`
+/// sends each LZ4 frame as one Thrift frame.
+///
+/// # Examples
+///
+/// Create and use a `TLz4WriteTransport` over a framed transport.
+///
+/// ```no_run
+/// use std::io::Write;
+/// use thrift::transport::{TFramedWriteTransport, TLz4WriteTransport, TTcpChannel};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let mut t = TLz4WriteTransport::new(TFramedWriteTransport::new(c));
+///
+/// t.write(&[0x00]).unwrap();
+/// t.flush().unwrap();
+///
+/// let last = t.last_frame_sizes();
+/// println!("{} -> {} bytes", last.uncompressed, last.compressed);
+/// ```
+#[derive(Debug)]
+pub struct TLz4WriteTransport<C>
+where
+    C: Write,
+{
+    channel: C,
+    buf: Vec<u8>,
+    frame: Vec<u8>,
+    counters: Counters,
+}
+
+impl<C> TLz4WriteTransport<C>
+where
+    C: Write,
+{
+    /// Create a `TLz4WriteTransport` that wraps the given `TIoChannel`.
+    pub fn new(channel: C) -> TLz4WriteTransport<C> {
+        TLz4WriteTransport {
+            channel,
+            buf: Vec::new(),
+            frame: Vec::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Sizes of the most recently written LZ4 frame.
+    pub fn last_frame_sizes(&self) -> TLz4FrameSizes {
+        self.counters.last
+    }
+
+    /// Sizes of all LZ4 frames written so far.
+    pub fn total_sizes(&self) -> TLz4FrameSizes {
+        self.counters.total
+    }
+
+    /// Number of LZ4 frames written so far.
+    pub fn frame_count(&self) -> u64 {
+        self.counters.frames
+    }
+}
+
+impl<C> Write for TLz4WriteTransport<C>
+where
+    C: Write,
+{
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(b);
+        Ok(b.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return self.channel.flush();
+        }
+
+        self.frame.clear();
+        let info = FrameInfo::new().content_size(Some(self.buf.len() as u64));
+        let mut encoder = FrameEncoder::with_frame_info(info, &mut self.frame);
+        encoder.write_all(&self.buf)?;
+        encoder.finish()?;
+
+        let sizes = TLz4FrameSizes {
+            compressed: self.frame.len() as u64,
+            uncompressed: self.buf.len() as u64,
+        };
+        self.buf.clear();
+
+        self.channel.write_all(&self.frame)?;
+        self.counters.record(sizes);
+        self.channel.flush()
+    }
+}
+
+/// Factory for creating instances of `TLz4WriteTransport`.
+#[derive(Debug, Default)]
+pub struct TLz4WriteTransportFactory {
+    framed: bool,
+}
+
+impl TLz4WriteTransportFactory {
+    /// Create a `TLz4WriteTransportFactory` whose transports write LZ4 frames
+    /// directly to the channel.
+    pub fn new() -> TLz4WriteTransportFactory {
+        TLz4WriteTransportFactory::default()
+    }
+
+    /// Create a `TLz4WriteTransportFactory` whose transports send each LZ4
+    /// frame as a Thrift frame, i.e. over a `TFramedWriteTransport`.
+    pub fn framed() -> TLz4WriteTransportFactory {
+        TLz4WriteTransportFactory { framed: true }
+    }
+}
+
+impl TWriteTransportFactory for TLz4WriteTransportFactory {
+    /// Create a `TLz4WriteTransport`.
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        if self.framed {
+            Box::new(TLz4WriteTransport::new(TFramedWriteTransport::new(channel)))
+        } else {
+            Box::new(TLz4WriteTransport::new(channel))
+        }
+    }
+}
+
+fn invalid_data<E>(msg: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(n: usize) -> Vec<u8> {
+        b"thrift lz4 ".iter().copied().cycle().take(n).collect()
+    }
+
+    fn compress(messages: &[Vec<u8>]) -> Vec<u8> {
+        let mut t = TLz4WriteTransport::new(Vec::new());
+        for m in messages {
+            t.write_all(m).unwrap();
+            t.flush().unwrap();
+        }
+        t.channel
+    }
+
+    #[test]
+    fn must_round_trip_one_frame_per_flush() {
+        let messages = vec![message(10), message(1000), message(200_000)];
+        let compressed = compress(&messages);
+
+        let mut r = TLz4ReadTransport::new(&compressed[..]);
+        for (i, m) in messages.iter().enumerate() {
+            let mut buf = vec![0u8; m.len()];
+            r.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, m);
+            assert_eq!(r.frame_count(), i as u64 + 1);
+            assert_eq!(r.last_frame_sizes().uncompressed, m.len() as u64);
+        }
+        assert_eq!(r.read(&mut [0u8; 1]).unwrap(), 0);
+        assert_eq!(r.total_sizes().compressed, compressed.len() as u64);
+    }
+
+    #[test]
+    fn must_count_sizes_of_each_written_frame() {
+        let mut t = TLz4WriteTransport::new(Vec::new());
+
+        t.write_all(&message(4096)).unwrap();
+        t.flush().unwrap();
+        let first = t.last_frame_sizes();
+        assert_eq!(first.uncompressed, 4096);
+        assert_eq!(first.compressed, t.channel.len() as u64);
+        assert!(first.compressed < first.uncompressed);
+
+        // an empty flush does not produce a frame
+        t.flush().unwrap();
+        assert_eq!(t.frame_count(), 1);
+
+        t.write_all(&message(16)).unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.frame_count(), 2);
+        assert_eq!(t.last_frame_sizes().uncompressed, 16);
+        assert_eq!(t.total_sizes().uncompressed, 4096 + 16);
+        assert_eq!(t.total_sizes().compressed, t.channel.len() as u64);
+    }
+
+    #[test]
+    fn must_read_frames_carried_in_thrift_frames() {
+        let mut written = Vec::new();
+        let mut t = TLz4WriteTransport::new(TFramedWriteTransport::new(&mut written));
+        t.write_all(&message(100)).unwrap();
+        t.flush().unwrap();
+        let first = t.last_frame_sizes();
+        t.write_all(&message(300)).unwrap();
+        t.flush().unwrap();
+        let total = t.total_sizes();
+        drop(t);
+
+        // each Thrift frame holds exactly one LZ4 frame
+        let first_len = u32::from_be_bytes(written[..4].try_into().unwrap());
+        assert_eq!(first_len as u64, first.compressed);
+
+        let mut r = TLz4ReadTransport::new(TFramedReadTransport::new(&written[..]));
+        let mut buf = vec![0u8; 400];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [message(100), message(300)].concat());
+        assert_eq!(r.frame_count(), 2);
+        assert_eq!(r.total_sizes(), total);
+    }
+
+    #[test]
+    fn must_reject_frames_larger_than_configured_maximum() {
+        let compressed = compress(&[message(1000)]);
+
+        let config = TConfiguration::builder()
+            .max_frame_size(Some(999))
+            .build()
+            .unwrap();
+        let mut r = TLz4ReadTransport::with_config(&compressed[..], config);
+        let err = r.read(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let config = TConfiguration::builder()
+            .max_frame_size(Some(1000))
+            .build()
+            .unwrap();
+        let mut r = TLz4ReadTransport::with_config(&compressed[..], config);
+        assert_eq!(r.read(&mut [0u8; 1]).unwrap(), 1);
+    }
+
+    #[test]
+    fn must_reject_data_that_is_not_an_lz4_frame() {
+        let mut r = TLz4ReadTransport::new(&[0x00, 0x00, 0x00, 0x08, 0x80][..]);
+        let err = r.read(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn must_fail_on_truncated_frame() {
+        let compressed = compress(&[message(1000)]);
+        let mut r = TLz4ReadTransport::new(&compressed[..compressed.len() - 1]);
+        let err = r.read(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -17,20 +17,24 @@
 
 use std::cmp;
 use std::io;
+use std::mem;
 use std::sync::{Arc, Mutex};
 
-use super::{ReadHalf, TIoChannel, WriteHalf};
+use super::{ReadHalf, TIoChannel, TPeekableReadTransport, WriteHalf};
 
-/// In-memory read and write channel with fixed-size read and write buffers.
+/// In-memory read and write channel.
 ///
-/// On a `write` bytes are written to the internal write buffer. Writes are no
-/// longer accepted once this buffer is full. Callers must `empty_write_buffer()`
-/// before subsequent writes are accepted.
+/// On a `write` bytes are written to the internal write buffer. A channel
+/// created with `new()` grows its buffers as needed. A channel created with
+/// `with_capacity(...)` has fixed-size buffers: once the write buffer is full
+/// writes fail with an `io::ErrorKind::WriteZero` error, and callers must
+/// `empty_write_buffer()` or `take_written_bytes()` before subsequent writes
+/// are accepted.
 ///
 /// You can set readable bytes in the internal read buffer by filling it with
 /// `set_readable_bytes(...)`. Callers can then read until the buffer is
 /// depleted. No further reads are accepted until the internal read buffer is
-/// replenished again.
+/// replenished again or rewound with `rewind_read_buffer()`.
 #[derive(Clone, Debug)]
 pub struct TBufferChannel {
     read: Arc<Mutex<ReadData>>,
@@ -39,34 +43,56 @@ pub struct TBufferChannel {
 
 #[derive(Debug)]
 struct ReadData {
-    buf: Box<[u8]>,
+    buf: Vec<u8>,
     pos: usize,
-    idx: usize,
-    cap: usize,
+    cap: Option<usize>,
 }
 
 #[derive(Debug)]
 struct WriteData {
-    buf: Box<[u8]>,
-    pos: usize,
-    cap: usize,
+    buf: Vec<u8>,
+    cap: Option<usize>,
+}
+
+impl WriteData {
+    fn take(&mut self) -> Vec<u8> {
+        let fresh = match self.cap {
+            Some(cap) => Vec::with_capacity(cap),
+            None => Vec::new(),
+        };
+        mem::replace(&mut self.buf, fresh)
+    }
 }
 
 impl TBufferChannel {
+    /// Constructs a new, empty `TBufferChannel` whose read and write buffers
+    /// grow as needed.
+    pub fn new() -> TBufferChannel {
+        TBufferChannel {
+            read: Arc::new(Mutex::new(ReadData {
+                buf: Vec::new(),
+                pos: 0,
+                cap: None,
+            })),
+            write: Arc::new(Mutex::new(WriteData {
+                buf: Vec::new(),
+                cap: None,
+            })),
+        }
+    }
+
     /// Constructs a new, empty `TBufferChannel` with the given
     /// read buffer capacity and write buffer capacity.
     pub fn with_capacity(read_capacity: usize, write_capacity: usize) -> TBufferChannel {
         TBufferChannel {
             read: Arc::new(Mutex::new(ReadData {
-                buf: vec![0; read_capacity].into_boxed_slice(),
-                idx: 0,
+                buf: Vec::with_capacity(read_capacity),
                 pos: 0,
-                cap: read_capacity,
+                cap: Some(read_capacity),
             })),
             write: Arc::new(Mutex::new(WriteData {
-                buf: vec![0; write_capacity].into_boxed_slice(),
-                pos: 0,
-                cap: write_capacity,
+                buf: Vec::with_capacity(write_capacity),
+                cap: Some(write_capacity),
             })),
         }
     }
@@ -75,31 +101,42 @@ impl TBufferChannel {
     /// Returns an empty vector if no readable bytes are present.
     pub fn read_bytes(&self) -> Vec<u8> {
         let rdata = self.read.as_ref().lock().unwrap();
-        let mut buf = vec![0u8; rdata.idx];
-        buf.copy_from_slice(&rdata.buf[..rdata.idx]);
-        buf
+        rdata.buf.clone()
+    }
+
+    /// Return the number of bytes of the internal read buffer that have
+    /// already been read.
+    pub fn read_position(&self) -> usize {
+        let rdata = self.read.as_ref().lock().unwrap();
+        rdata.pos
     }
 
     // FIXME: do I really need this API call?
-    // FIXME: should this simply reset to the last set of readable bytes?
     /// Reset the number of readable bytes to zero.
     ///
     /// Subsequent calls to `read` will return nothing.
     pub fn empty_read_buffer(&mut self) {
         let mut rdata = self.read.as_ref().lock().unwrap();
         rdata.pos = 0;
-        rdata.idx = 0;
+        rdata.buf.clear();
+    }
+
+    /// Make the bytes held by the internal read buffer readable again from
+    /// the start.
+    pub fn rewind_read_buffer(&mut self) {
+        let mut rdata = self.read.as_ref().lock().unwrap();
+        rdata.pos = 0;
     }
 
     /// Copy bytes from the source buffer `buf` into the internal read buffer,
     /// overwriting any existing bytes. Returns the number of bytes copied,
-    /// which is `min(buf.len(), internal_read_buf.len())`.
+    /// which is `buf.len()` for a growable channel and
+    /// `min(buf.len(), read_capacity)` otherwise.
     pub fn set_readable_bytes(&mut self, buf: &[u8]) -> usize {
         self.empty_read_buffer();
         let mut rdata = self.read.as_ref().lock().unwrap();
-        let max_bytes = cmp::min(rdata.cap, buf.len());
-        rdata.buf[..max_bytes].clone_from_slice(&buf[..max_bytes]);
-        rdata.idx = max_bytes;
+        let max_bytes = rdata.cap.map_or(buf.len(), |cap| cmp::min(cap, buf.len()));
+        rdata.buf.extend_from_slice(&buf[..max_bytes]);
         max_bytes
     }
 
@@ -107,34 +144,48 @@ impl TBufferChannel {
     /// Returns an empty vector if no bytes were written.
     pub fn write_bytes(&self) -> Vec<u8> {
         let wdata = self.write.as_ref().lock().unwrap();
-        let mut buf = vec![0u8; wdata.pos];
-        buf.copy_from_slice(&wdata.buf[..wdata.pos]);
-        buf
+        wdata.buf.clone()
+    }
+
+    /// Remove and return the bytes held by the internal write buffer without
+    /// copying them. The write buffer is empty after this operation.
+    pub fn take_written_bytes(&mut self) -> Vec<u8> {
+        let mut wdata = self.write.as_ref().lock().unwrap();
+        wdata.take()
     }
 
     /// Resets the internal write buffer, making it seem like no bytes were
     /// written. Calling `write_buffer` after this returns an empty vector.
     pub fn empty_write_buffer(&mut self) {
         let mut wdata = self.write.as_ref().lock().unwrap();
-        wdata.pos = 0;
+        wdata.buf.clear();
     }
 
     /// Overwrites the contents of the read buffer with the contents of the
     /// write buffer. The write buffer is emptied after this operation.
+    ///
+    /// # Panics
+    ///
+    /// If the written bytes do not fit into a fixed-size read buffer.
     pub fn copy_write_buffer_to_read_buffer(&mut self) {
-        // FIXME: redo this entire method
-        let buf = {
-            let wdata = self.write.as_ref().lock().unwrap();
-            let b = &wdata.buf[..wdata.pos];
-            let mut b_ret = vec![0; b.len()];
-            b_ret.copy_from_slice(b);
-            b_ret
-        };
-
-        let bytes_copied = self.set_readable_bytes(&buf);
-        assert_eq!(bytes_copied, buf.len());
+        let buf = self.take_written_bytes();
+        let mut rdata = self.read.as_ref().lock().unwrap();
+        if let Some(cap) = rdata.cap {
+            assert!(
+                buf.len() <= cap,
+                "{} written bytes exceed the read buffer capacity of {}",
+                buf.len(),
+                cap
+            );
+        }
+        rdata.pos = 0;
+        rdata.buf = buf;
+    }
+}
 
-        self.empty_write_buffer();
+impl Default for TBufferChannel {
+    fn default() -> Self {
+        TBufferChannel::new()
     }
 }
 
@@ -165,20 +216,39 @@ impl TIoChannel for TBufferChannel {
 impl io::Read for TBufferChannel {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let mut rdata = self.read.as_ref().lock().unwrap();
-        let nread = cmp::min(buf.len(), rdata.idx - rdata.pos);
+        let nread = cmp::min(buf.len(), rdata.buf.len() - rdata.pos);
         buf[..nread].clone_from_slice(&rdata.buf[rdata.pos..rdata.pos + nread]);
         rdata.pos += nread;
         Ok(nread)
     }
 }
 
+impl TPeekableReadTransport for TBufferChannel {
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let rdata = self.read.as_ref().lock().unwrap();
+        let npeek = cmp::min(buf.len(), rdata.buf.len() - rdata.pos);
+        buf[..npeek].copy_from_slice(&rdata.buf[rdata.pos..rdata.pos + npeek]);
+        Ok(npeek)
+    }
+}
+
 impl io::Write for TBufferChannel {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut wdata = self.write.as_ref().lock().unwrap();
-        let nwrite = cmp::min(buf.len(), wdata.cap - wdata.pos);
-        let (start, end) = (wdata.pos, wdata.pos + nwrite);
-        wdata.buf[start..end].clone_from_slice(&buf[..nwrite]);
-        wdata.pos += nwrite;
+        let nwrite = match wdata.cap {
+            Some(cap) => {
+                let available = cap - wdata.buf.len();
+                if available == 0 && !buf.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        format!("write buffer capacity of {} bytes exhausted", cap),
+                    ));
+                }
+                cmp::min(buf.len(), available)
+            }
+            None => buf.len(),
+        };
+        wdata.buf.extend_from_slice(&buf[..nwrite]);
         Ok(nwrite)
     }
 
@@ -189,7 +259,7 @@ impl io::Write for TBufferChannel {
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Read, Write};
+    use std::io::{self, Read, Write};
 
     use super::TBufferChannel;
 
@@ -217,9 +287,9 @@ mod tests {
         assert_eq!(result.unwrap(), 2);
         assert_eq!(&t.write_bytes(), &bytes_to_write);
 
-        // try write again (nothing should be written)
+        // try write again (the buffer is full)
         let result = t.write(&bytes_to_write);
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WriteZero);
         assert_eq!(&t.write_bytes(), &bytes_to_write); // still the same as before
 
         // now reset the buffer
@@ -257,7 +327,7 @@ mod tests {
         // fourth write (no writes are accepted)
         let bytes_to_write_3: [u8; 3] = [0xBF, 0xAA, 0xFD];
         let write_3_result = t.write(&bytes_to_write_3);
-        assert_eq!(write_3_result.unwrap(), 0);
+        assert_eq!(write_3_result.unwrap_err().kind(), io::ErrorKind::WriteZero);
 
         // check the full write buffer
         let mut expected: Vec<u8> = Vec::with_capacity(10);
@@ -384,4 +454,73 @@ mod tests {
         assert_eq!(read_result.unwrap(), 2);
         assert_eq!(t.read_bytes(), &read_buf[0..2]);
     }
+
+    #[test]
+    fn must_grow_write_buffer_when_growable() {
+        let mut t = TBufferChannel::new();
+        let bytes: Vec<u8> = (0..=255).collect();
+        for _ in 0..64 {
+            t.write_all(&bytes).unwrap();
+        }
+        assert_eq!(t.write_bytes().len(), 64 * 256);
+    }
+
+    #[test]
+    fn must_fail_write_all_beyond_fixed_capacity() {
+        let mut t = TBufferChannel::with_capacity(0, 4);
+        let err = t.write_all(&[0x01, 0x02, 0x03, 0x04, 0x05]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(t.write_bytes(), &[0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn must_take_written_bytes() {
+        let mut t = TBufferChannel::with_capacity(0, 4);
+        t.write_all(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+        assert_eq!(t.take_written_bytes(), vec![0x01, 0x02, 0x03, 0x04]);
+        assert!(t.write_bytes().is_empty());
+
+        // the full capacity is available again
+        t.write_all(&[0x05, 0x06, 0x07, 0x08]).unwrap();
+        assert_eq!(t.take_written_bytes(), vec![0x05, 0x06, 0x07, 0x08]);
+    }
+
+    #[test]
+    fn must_read_everything_set_on_growable_channel() {
+        let mut t = TBufferChannel::new();
+        let bytes = vec![0xAB; 10_000];
+        assert_eq!(t.set_readable_bytes(&bytes), bytes.len());
+
+        let mut read = Vec::new();
+        t.read_to_end(&mut read).unwrap();
+        assert_eq!(read, bytes);
+        assert_eq!(t.read_position(), bytes.len());
+    }
+
+    #[test]
+    fn must_rewind_read_buffer() {
+        let mut t = TBufferChannel::with_capacity(3, 0);
+        t.set_readable_bytes(&[0x01, 0x02, 0x03]);
+
+        let mut buf = [0u8; 2];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(t.read_position(), 2);
+
+        t.rewind_read_buffer();
+        let mut read = Vec::new();
+        t.read_to_end(&mut read).unwrap();
+        assert_eq!(read, vec![0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn must_move_written_bytes_to_read_buffer() {
+        let mut t = TBufferChannel::new();
+        t.write_all(&[0x01, 0x02]).unwrap();
+        t.copy_write_buffer_to_read_buffer();
+        assert!(t.write_bytes().is_empty());
+
+        let mut read = Vec::new();
+        t.read_to_end(&mut read).unwrap();
+        assert_eq!(read, vec![0x01, 0x02]);
+    }
 }
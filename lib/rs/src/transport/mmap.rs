@@ -0,0 +1,258 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+/// Read transport over a memory-mapped file.
+///
+/// The whole file is mapped into memory, so reads are plain memory copies
+/// with no system calls. The transport implements `std::io::BufRead` with
+/// the rest of the file as its buffer, which lets
+/// `TAcceleratedBinaryInputProtocol` decode values straight from the
+/// mapping; [`remaining`](Self::remaining) gives direct access to the
+/// unread bytes.
+///
+/// The file must not be truncated or modified while it is mapped: depending
+/// on the platform, doing so changes the bytes seen by the transport or
+/// makes it fault.
+///
+/// # Examples
+///
+/// Read a count-prefixed list of binary-encoded strings.
+///
+/// ```no_run
+/// use thrift::protocol::{TAcceleratedBinaryInputProtocol, TInputProtocol};
+/// use thrift::transport::TMmapReadTransport;
+///
+/// let t = TMmapReadTransport::open("strings.bin").unwrap();
+/// let mut i_prot = TAcceleratedBinaryInputProtocol::new(t, true);
+///
+/// let count = i_prot.read_i32().unwrap();
+/// for _ in 0..count {
+///     let value = i_prot.read_string().unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TMmapReadTransport {
+    // `None` for an empty file, which cannot be mapped on every platform
+    map: Option<Mmap>,
+    pos: u64,
+}
+
+impl TMmapReadTransport {
+    /// Map the file at `path` and read it from the beginning.
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<TMmapReadTransport> {
+        let file = File::open(path)?;
+        TMmapReadTransport::with_file(&file)
+    }
+
+    /// Map an already opened `file` and read it from the beginning. The file
+    /// may be closed once the transport is created.
+    pub fn with_file(file: &File) -> crate::Result<TMmapReadTransport> {
+        let map = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            // Safety: the mapping is only ever read, and callers are told
+            // not to modify the file while it is mapped.
+            Some(unsafe { Mmap::map(file)? })
+        };
+        Ok(TMmapReadTransport { map, pos: 0 })
+    }
+
+    /// All bytes of the mapped file.
+    pub fn as_slice(&self) -> &[u8] {
+        match self.map {
+            Some(ref map) => map,
+            None => &[],
+        }
+    }
+
+    /// Bytes that have not been read yet.
+    pub fn remaining(&self) -> &[u8] {
+        let slice = self.as_slice();
+        &slice[cmp::min(self.pos, slice.len() as u64) as usize..]
+    }
+
+    /// Offset of the next byte to be read.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Size of the mapped file in bytes.
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    /// Whether every byte of the file has been read.
+    pub fn is_empty(&self) -> bool {
+        self.remaining().is_empty()
+    }
+}
+
+impl Read for TMmapReadTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.remaining();
+        let len = cmp::min(buf.len(), remaining.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let remaining = self.remaining();
+        if remaining.len() < buf.len() {
+            self.pos = self.len() as u64;
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+        buf.copy_from_slice(&remaining[..buf.len()]);
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl BufRead for TMmapReadTransport {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.remaining())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt as u64, self.len() as u64);
+    }
+}
+
+impl Seek for TMmapReadTransport {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => self.len() as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.pos as i128 + offset as i128,
+        };
+        if target < 0 || target > u64::MAX as i128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        }
+        // like a file, the position may be past the end, where reads return
+        // nothing
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::protocol::{
+        TAcceleratedBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TOutputProtocol,
+    };
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("thrift-mmap-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn must_read_file_contents() {
+        let path = temp_file("read", b"0123456789");
+        let mut t = TMmapReadTransport::open(&path).unwrap();
+        assert_eq!(t.len(), 10);
+
+        let mut buf = [0u8; 4];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"0123");
+        assert_eq!(t.remaining(), b"456789");
+
+        let mut rest = Vec::new();
+        t.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"456789");
+        assert!(t.is_empty());
+        assert_eq!(t.read(&mut buf).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_expose_remaining_bytes_as_buffer() {
+        let path = temp_file("buffer", b"abcdef");
+        let mut t = TMmapReadTransport::open(&path).unwrap();
+        assert_eq!(t.fill_buf().unwrap(), b"abcdef");
+        t.consume(4);
+        assert_eq!(t.fill_buf().unwrap(), b"ef");
+        t.consume(10);
+        assert!(t.fill_buf().unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_handle_empty_file() {
+        let path = temp_file("empty", b"");
+        let mut t = TMmapReadTransport::open(&path).unwrap();
+        assert!(t.is_empty());
+        assert_eq!(t.read(&mut [0u8; 4]).unwrap(), 0);
+        let err = t.read_exact(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_seek() {
+        let path = temp_file("seek", b"0123456789");
+        let mut t = TMmapReadTransport::open(&path).unwrap();
+        assert_eq!(t.seek(SeekFrom::End(-3)).unwrap(), 7);
+        assert_eq!(t.remaining(), b"789");
+        assert_eq!(t.seek(SeekFrom::Current(-5)).unwrap(), 2);
+        assert_eq!(t.remaining(), b"23456789");
+        assert_eq!(t.seek(SeekFrom::Start(20)).unwrap(), 20);
+        assert!(t.remaining().is_empty());
+        assert!(t.seek(SeekFrom::Current(-21)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_decode_values_with_accelerated_protocol() {
+        let mut encoded = Vec::new();
+        {
+            let mut o_prot = TBinaryOutputProtocol::new(&mut encoded, true);
+            o_prot.write_i32(3).unwrap();
+            for value in ["a", "bb", "ccc"] {
+                o_prot.write_string(value).unwrap();
+            }
+        }
+        let path = temp_file("protocol", &encoded);
+
+        let t = TMmapReadTransport::open(&path).unwrap();
+        let mut i_prot = TAcceleratedBinaryInputProtocol::new(t, true);
+        let count = i_prot.read_i32().unwrap();
+        let values: Vec<String> = (0..count).map(|_| i_prot.read_string().unwrap()).collect();
+        assert_eq!(values, vec!["a", "bb", "ccc"]);
+        assert!(i_prot.into_inner().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
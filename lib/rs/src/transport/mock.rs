@@ -0,0 +1,370 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::cmp;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use super::{ReadHalf, TIoChannel, WriteHalf};
+
+#[derive(Clone, Copy, Debug)]
+enum Fault {
+    Error(io::ErrorKind),
+    Eof,
+}
+
+fn injected(kind: io::ErrorKind) -> io::Error {
+    io::Error::new(kind, "injected fault")
+}
+
+/// Faults keyed by the byte offset at which they fire, in the order they
+/// were added.
+#[derive(Debug)]
+struct Faults<F> {
+    pending: Vec<(usize, F)>,
+}
+
+impl<F> Default for Faults<F> {
+    fn default() -> Self {
+        Faults {
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<F: Copy> Faults<F> {
+    fn add(&mut self, offset: usize, fault: F) {
+        self.pending.push((offset, fault));
+    }
+
+    /// Remove and return the first fault due at `pos`.
+    fn take(&mut self, pos: usize) -> Option<F> {
+        let index = self.pending.iter().position(|&(at, _)| at == pos)?;
+        Some(self.pending.remove(index).1)
+    }
+
+    /// Limit a transfer of `len` bytes starting at `pos` so that it stops
+    /// at the next fault.
+    fn limit(&self, pos: usize, len: usize) -> usize {
+        self.pending
+            .iter()
+            .filter(|&&(at, _)| at > pos)
+            .map(|&(at, _)| at - pos)
+            .fold(len, cmp::min)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReadScript {
+    buf: Vec<u8>,
+    pos: usize,
+    max_read: Option<usize>,
+    faults: Faults<Fault>,
+}
+
+#[derive(Debug, Default)]
+struct WriteLog {
+    buf: Vec<u8>,
+    writes: Vec<Vec<u8>>,
+    flushes: usize,
+    max_write: Option<usize>,
+    faults: Faults<io::ErrorKind>,
+    flush_faults: Vec<io::ErrorKind>,
+}
+
+/// Scriptable in-memory channel for testing how code copes with unusual
+/// I/O behaviour.
+///
+/// Reads are served from bytes queued with `push_readable_bytes(...)`, and
+/// return end-of-file once they are exhausted. Unlike `TBufferChannel`, a
+/// `TMockChannel` can:
+///
+/// * return short reads and writes, via `set_max_read_size(...)` and
+///   `set_max_write_size(...)`
+/// * fail a read with any `io::ErrorKind` - such as `WouldBlock` or
+///   `Interrupted` - or return a spurious end-of-file, when the read position
+///   reaches a given offset, via `fail_read_at(...)` and `eof_at(...)`
+/// * fail a write when a given number of bytes has been written, via
+///   `fail_write_at(...)`, and fail flushes, via `fail_next_flush(...)`
+/// * record every write and flush
+///
+/// Each injected fault fires once. Reads and writes never cross the offset
+/// of a pending fault, so the fault is observed exactly at its offset.
+///
+/// Clones of a `TMockChannel` share the same state, so a test can keep a
+/// clone to script and inspect a channel that was moved into a transport.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{ErrorKind, Read};
+/// use thrift::transport::TMockChannel;
+///
+/// let mut channel = TMockChannel::new();
+/// channel.push_readable_bytes(&[1, 2, 3, 4]);
+/// channel.set_max_read_size(1);
+/// channel.fail_read_at(2, ErrorKind::Interrupted);
+///
+/// let mut buf = [0u8; 4];
+/// assert_eq!(channel.read(&mut buf).unwrap(), 1);
+/// assert_eq!(channel.read(&mut buf).unwrap(), 1);
+/// assert_eq!(channel.read(&mut buf).unwrap_err().kind(), ErrorKind::Interrupted);
+/// assert_eq!(channel.read(&mut buf).unwrap(), 1);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TMockChannel {
+    read: Arc<Mutex<ReadScript>>,
+    write: Arc<Mutex<WriteLog>>,
+}
+
+impl TMockChannel {
+    /// Create a `TMockChannel` with nothing to read and no faults.
+    pub fn new() -> TMockChannel {
+        TMockChannel::default()
+    }
+
+    /// Queue `buf` to be read after any bytes already queued.
+    pub fn push_readable_bytes(&mut self, buf: &[u8]) {
+        self.read.lock().unwrap().buf.extend_from_slice(buf);
+    }
+
+    /// Return at most `size` bytes from each read.
+    pub fn set_max_read_size(&mut self, size: usize) {
+        self.read.lock().unwrap().max_read = Some(size);
+    }
+
+    /// Fail the read at read position `offset` with an error of `kind`.
+    pub fn fail_read_at(&mut self, offset: usize, kind: io::ErrorKind) {
+        self.read
+            .lock()
+            .unwrap()
+            .faults
+            .add(offset, Fault::Error(kind));
+    }
+
+    /// Return end-of-file from the read at read position `offset`. Later
+    /// reads continue with the remaining bytes.
+    pub fn eof_at(&mut self, offset: usize) {
+        self.read.lock().unwrap().faults.add(offset, Fault::Eof);
+    }
+
+    /// Number of bytes read so far.
+    pub fn read_position(&self) -> usize {
+        self.read.lock().unwrap().pos
+    }
+
+    /// Accept at most `size` bytes in each write.
+    pub fn set_max_write_size(&mut self, size: usize) {
+        self.write.lock().unwrap().max_write = Some(size);
+    }
+
+    /// Fail the write that would start once `offset` bytes have been
+    /// written with an error of `kind`.
+    pub fn fail_write_at(&mut self, offset: usize, kind: io::ErrorKind) {
+        self.write.lock().unwrap().faults.add(offset, kind);
+    }
+
+    /// Fail the next flush with an error of `kind`. Calling this several
+    /// times fails that many flushes.
+    pub fn fail_next_flush(&mut self, kind: io::ErrorKind) {
+        self.write.lock().unwrap().flush_faults.push(kind);
+    }
+
+    /// Return a copy of all the bytes written so far.
+    pub fn written_bytes(&self) -> Vec<u8> {
+        self.write.lock().unwrap().buf.clone()
+    }
+
+    /// Return the bytes accepted by each successful write, in order.
+    pub fn writes(&self) -> Vec<Vec<u8>> {
+        self.write.lock().unwrap().writes.clone()
+    }
+
+    /// Number of successful flushes.
+    pub fn flush_count(&self) -> usize {
+        self.write.lock().unwrap().flushes
+    }
+}
+
+impl TIoChannel for TMockChannel {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+impl io::Read for TMockChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut script = self.read.lock().unwrap();
+        let pos = script.pos;
+        match script.faults.take(pos) {
+            Some(Fault::Error(kind)) => return Err(injected(kind)),
+            Some(Fault::Eof) => return Ok(0),
+            None => {}
+        }
+
+        let mut nread = cmp::min(buf.len(), script.buf.len() - pos);
+        nread = script.faults.limit(pos, nread);
+        if let Some(max_read) = script.max_read {
+            nread = cmp::min(nread, max_read);
+        }
+        buf[..nread].copy_from_slice(&script.buf[pos..pos + nread]);
+        script.pos += nread;
+        Ok(nread)
+    }
+}
+
+impl io::Write for TMockChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut log = self.write.lock().unwrap();
+        let pos = log.buf.len();
+        if let Some(kind) = log.faults.take(pos) {
+            return Err(injected(kind));
+        }
+
+        let mut nwrite = log.faults.limit(pos, buf.len());
+        if let Some(max_write) = log.max_write {
+            nwrite = cmp::min(nwrite, max_write);
+        }
+        log.buf.extend_from_slice(&buf[..nwrite]);
+        log.writes.push(buf[..nwrite].to_vec());
+        Ok(nwrite)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut log = self.write.lock().unwrap();
+        if !log.flush_faults.is_empty() {
+            let kind = log.flush_faults.remove(0);
+            return Err(injected(kind));
+        }
+        log.flushes += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn must_serve_scripted_bytes_then_eof() {
+        let mut c = TMockChannel::new();
+        c.push_readable_bytes(&[1, 2]);
+        c.push_readable_bytes(&[3]);
+
+        let mut buf = Vec::new();
+        c.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, vec![1, 2, 3]);
+        assert_eq!(c.read(&mut [0u8; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn must_stop_reads_at_fault_offset() {
+        let mut c = TMockChannel::new();
+        c.push_readable_bytes(&[1, 2, 3, 4, 5]);
+        c.fail_read_at(3, ErrorKind::WouldBlock);
+
+        let mut buf = [0u8; 5];
+        assert_eq!(c.read(&mut buf).unwrap(), 3);
+        assert_eq!(c.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(c.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], &[4, 5]);
+    }
+
+    #[test]
+    fn must_fire_faults_at_same_offset_in_order() {
+        let mut c = TMockChannel::new();
+        c.push_readable_bytes(&[1]);
+        c.fail_read_at(0, ErrorKind::Interrupted);
+        c.eof_at(0);
+
+        let mut buf = [0u8; 1];
+        assert_eq!(c.read(&mut buf).unwrap_err().kind(), ErrorKind::Interrupted);
+        assert_eq!(c.read(&mut buf).unwrap(), 0);
+        assert_eq!(c.read(&mut buf).unwrap(), 1);
+    }
+
+    #[test]
+    fn read_exact_must_retry_interrupted_short_reads() {
+        let mut c = TMockChannel::new();
+        c.push_readable_bytes(&[1, 2, 3, 4]);
+        c.set_max_read_size(1);
+        c.fail_read_at(1, ErrorKind::Interrupted);
+
+        let mut buf = [0u8; 4];
+        c.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(c.read_position(), 4);
+    }
+
+    #[test]
+    fn must_record_short_writes_and_faults() {
+        let mut c = TMockChannel::new();
+        c.set_max_write_size(2);
+        c.fail_write_at(4, ErrorKind::BrokenPipe);
+
+        assert_eq!(c.write(&[1, 2, 3]).unwrap(), 2);
+        assert_eq!(c.write(&[3, 4, 5]).unwrap(), 2);
+        assert_eq!(c.write(&[5]).unwrap_err().kind(), ErrorKind::BrokenPipe);
+        c.write_all(&[5]).unwrap();
+
+        assert_eq!(c.written_bytes(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(c.writes(), vec![vec![1, 2], vec![3, 4], vec![5]]);
+    }
+
+    #[test]
+    fn must_fail_flushes_and_count_the_rest() {
+        let mut c = TMockChannel::new();
+        c.fail_next_flush(ErrorKind::TimedOut);
+
+        assert_eq!(c.flush().unwrap_err().kind(), ErrorKind::TimedOut);
+        c.flush().unwrap();
+        assert_eq!(c.flush_count(), 1);
+    }
+
+    #[test]
+    fn must_share_state_between_split_halves() {
+        let mut c = TMockChannel::new();
+        c.push_readable_bytes(&[9]);
+        let (mut r, mut w) = c.clone().split().unwrap();
+
+        w.write_all(&[7]).unwrap();
+        let mut buf = [0u8; 1];
+        r.read_exact(&mut buf).unwrap();
+
+        assert_eq!(buf, [9]);
+        assert_eq!(c.written_bytes(), vec![7]);
+        assert_eq!(c.read_position(), 1);
+    }
+
+    #[test]
+    fn framed_transport_must_survive_short_and_interrupted_reads() {
+        let mut c = TMockChannel::new();
+        c.push_readable_bytes(&[0x00, 0x00, 0x00, 0x03, 0x0a, 0x0b, 0x0c]);
+        c.set_max_read_size(1);
+        c.fail_read_at(2, ErrorKind::Interrupted);
+        c.fail_read_at(5, ErrorKind::Interrupted);
+
+        let mut t = crate::transport::TFramedReadTransport::new(c);
+        let mut buf = [0u8; 3];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x0a, 0x0b, 0x0c]);
+    }
+}
@@ -23,8 +23,9 @@
 //! `TInputProtocol` and `TOutputProtocol` instances deal with language primitives
 //! the types in this module understand only bytes.
 
+use std::cmp;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{IoSlice, Read, Write};
 use std::ops::{Deref, DerefMut};
 
 #[cfg(test)]
@@ -42,26 +43,118 @@ macro_rules! assert_eq_transport_written_bytes {
 }
 
 mod buffered;
+#[cfg(feature = "bytes")]
+mod bytes;
+mod crc32c;
+mod deadline;
+mod file;
 mod framed;
+mod gssapi;
+mod http;
+mod instrumented;
+mod loopback;
+#[cfg(feature = "lz4")]
+mod lz4;
 mod mem;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod mock;
+mod peer;
+mod pool;
+mod proxy;
+mod reconnect;
+mod sasl;
 mod shared;
+mod simple_file;
 mod socket;
+mod stack;
+mod tee;
+mod throttle;
 #[cfg(feature = "rustls")]
 mod tls;
+#[cfg(feature = "tls-native")]
+mod tls_native;
+mod udp;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "zlib")]
+mod zlib;
+#[cfg(feature = "zstd")]
+mod zstd;
 
 pub use self::buffered::{
-    TBufferedReadTransport, TBufferedReadTransportFactory, TBufferedWriteTransport,
-    TBufferedWriteTransportFactory,
+    TBufferGrowth, TBufferPolicy, TBufferPolicyBuilder, TBufferShrink, TBufferedReadTransport,
+    TBufferedReadTransportFactory, TBufferedWriteTransport, TBufferedWriteTransportFactory,
+};
+#[cfg(feature = "bytes")]
+pub use self::bytes::{
+    TBytesChannelSink, TBytesSink, TBytesWriteTransport, TBytesWriteTransportFactory,
+};
+pub use self::deadline::TCallDeadline;
+pub use self::file::{
+    TFileReadTransport, TFileTransportConfig, TFileTransportConfigBuilder, TFileWriteTransport,
 };
 pub use self::framed::{
     TFramedReadTransport, TFramedReadTransportFactory, TFramedWriteTransport,
     TFramedWriteTransportFactory,
 };
+pub use self::gssapi::{
+    TGssContext, TSaslGssapi, TSaslGssapiAuthorizer, TSaslGssapiServer, TSaslQop,
+};
+pub use self::http::{THttpClient, THttpConnector, THttpStream, THttpTcpConnector};
+pub use self::instrumented::{TInstrumentedChannel, TTransportCounters, TTransportObserver};
+pub use self::loopback::TLoopbackChannel;
+#[cfg(feature = "lz4")]
+pub use self::lz4::{
+    TLz4FrameSizes, TLz4ReadTransport, TLz4ReadTransportFactory, TLz4WriteTransport,
+    TLz4WriteTransportFactory,
+};
 pub use self::mem::TBufferChannel;
-pub use self::shared::TSharedChannel;
-pub use self::socket::TTcpChannel;
+#[cfg(feature = "mmap")]
+pub use self::mmap::TMmapReadTransport;
+pub use self::mock::TMockChannel;
+pub use self::peer::TPeerIdentity;
+pub use self::pool::{TBufferPool, TPooledBuffer};
+pub use self::proxy::{TProxy, TProxyKind};
+pub use self::reconnect::{TBackoff, TReconnectingChannel};
+pub use self::sasl::{
+    TSaslClientTransport, TSaslMechanism, TSaslPlain, TSaslPlainServer, TSaslPlainValidator,
+    TSaslServerMechanism, TSaslServerTransport,
+};
+pub use self::shared::{TCloneChannel, TSharedChannel};
+pub use self::simple_file::TSimpleFileTransport;
+pub use self::socket::{TTcpChannel, TTcpKeepalive, TTcpOptions, TTcpOptionsBuilder};
+pub use self::stack::{
+    TStackedReadTransportFactory, TStackedWriteTransportFactory, TTransportKind,
+    TTransportStackBuilder,
+};
+pub use self::tee::{
+    TTeeReadTransport, TTeeReadTransportFactory, TTeeSinkFactory, TTeeWriteTransport,
+    TTeeWriteTransportFactory,
+};
+pub use self::throttle::{
+    TRateLimiter, TThrottledReadTransport, TThrottledReadTransportFactory,
+    TThrottledWriteTransport, TThrottledWriteTransportFactory,
+};
 #[cfg(feature = "rustls")]
-pub use self::tls::{TTlsClientChannel, TTlsServerChannel};
+pub use self::tls::{TTlsChannel, TTlsClientChannel, TTlsClientConfigBuilder, TTlsServerChannel};
+#[cfg(feature = "tls-native")]
+pub use self::tls_native::{TNativeTlsClientChannel, TNativeTlsServerChannel};
+pub use self::udp::TUdpChannel;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use self::uring::TUringChannel;
+#[cfg(feature = "websocket")]
+pub use self::websocket::{TWebSocketClientChannel, TWebSocketServerChannel};
+#[cfg(feature = "zlib")]
+pub use self::zlib::{
+    TZlibReadTransport, TZlibReadTransportFactory, TZlibWriteTransport, TZlibWriteTransportFactory,
+};
+#[cfg(feature = "zstd")]
+pub use self::zstd::{
+    TZstdReadTransport, TZstdReadTransportFactory, TZstdWriteTransport, TZstdWriteTransportFactory,
+};
 
 /// Identifies a transport used by a `TInputProtocol` to receive bytes.
 pub trait TReadTransport: Read {}
@@ -73,8 +166,80 @@ pub trait TReadTransportFactory {
     fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send>;
 }
 
+/// A `TReadTransport` that can look at upcoming bytes without consuming
+/// them, for example to detect the protocol a client speaks before handing
+/// the transport to a `TInputProtocol`.
+pub trait TPeekableReadTransport: TReadTransport {
+    /// Copy upcoming bytes into `buf` without consuming them, waiting for
+    /// input if none is available. Returns the number of bytes copied, which
+    /// may be less than `buf.len()`, and is `0` at the end of the stream.
+    ///
+    /// A later `read` returns the same bytes.
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+// Number of `IoSlice` entries handed to a single `write_vectored` call by
+// `TWriteTransport::write_all_vectored`.
+const MAX_IO_SLICES: usize = 8;
+
 /// Identifies a transport used by `TOutputProtocol` to send bytes.
-pub trait TWriteTransport: Write {}
+pub trait TWriteTransport: Write {
+    /// Write every byte in `bufs`, in order, using as few
+    /// `Write::write_vectored` calls as the underlying writer allows.
+    ///
+    /// This lets an output protocol hand a length prefix and a large payload
+    /// to the transport together, without first copying them into a single
+    /// contiguous buffer.
+    ///
+    /// `std::io::Write` has an unstable method with the same name, so call
+    /// this as `TWriteTransport::write_all_vectored(&mut t, bufs)` to avoid
+    /// ambiguity.
+    fn write_all_vectored(&mut self, bufs: &[&[u8]]) -> io::Result<()> {
+        let mut index = 0;
+        let mut offset = 0;
+
+        while index < bufs.len() {
+            if offset == bufs[index].len() {
+                index += 1;
+                offset = 0;
+                continue;
+            }
+
+            let count = cmp::min(bufs.len() - index, MAX_IO_SLICES);
+            let mut slices = [IoSlice::new(&[]); MAX_IO_SLICES];
+            slices[0] = IoSlice::new(&bufs[index][offset..]);
+            for (slice, buf) in slices[1..count].iter_mut().zip(&bufs[index + 1..]) {
+                *slice = IoSlice::new(buf);
+            }
+
+            let mut written = match self.write_vectored(&slices[..count]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            while written > 0 {
+                let remaining = bufs[index].len() - offset;
+                if written >= remaining {
+                    written -= remaining;
+                    index += 1;
+                    offset = 0;
+                } else {
+                    offset += written;
+                    written = 0;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
 
 /// Helper type used by a server to create `TWriteTransport` instances for
 /// accepted client connections.
@@ -89,6 +254,15 @@ impl<T> TWriteTransport for T where T: Write {}
 
 // FIXME: implement the Debug trait for boxed transports
 
+impl<T> TPeekableReadTransport for Box<T>
+where
+    T: TPeekableReadTransport + ?Sized,
+{
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).peek(buf)
+    }
+}
+
 impl<T> TReadTransportFactory for Box<T>
 where
     T: TReadTransportFactory + ?Sized,
@@ -174,6 +348,15 @@ where
     }
 }
 
+impl<C> TPeekableReadTransport for ReadHalf<C>
+where
+    C: TPeekableReadTransport,
+{
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.handle.peek(buf)
+    }
+}
+
 impl<C> Write for WriteHalf<C>
 where
     C: Write,
@@ -182,6 +365,10 @@ where
         self.handle.write(buf)
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.handle.write_vectored(bufs)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.handle.flush()
     }
@@ -286,6 +473,51 @@ mod tests {
         takes_write_transport(&mut t)
     }
 
+    #[test]
+    fn must_write_all_vectored_buffers_in_order() {
+        let mut w: Vec<u8> = Vec::new();
+        TWriteTransport::write_all_vectored(&mut w, &[&[0x00, 0x01], &[], &[0x02], &[0x03, 0x04]])
+            .unwrap();
+        assert_eq!(w, vec![0x00, 0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn must_write_all_vectored_buffers_through_short_writes() {
+        // accepts at most 3 bytes per call, and only from the first slice
+        struct Trickle(Vec<u8>);
+
+        impl Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = cmp::min(buf.len(), 3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let payload: Vec<u8> = (0..20).collect();
+        let mut bufs: Vec<&[u8]> = vec![&[0xFF, 0xFE]];
+        bufs.extend(payload.chunks(4));
+
+        let mut w = Trickle(Vec::new());
+        TWriteTransport::write_all_vectored(&mut w, &bufs).unwrap();
+
+        let mut expected = vec![0xFF, 0xFE];
+        expected.extend_from_slice(&payload);
+        assert_eq!(w.0, expected);
+    }
+
+    #[test]
+    fn must_fail_write_all_vectored_if_writer_accepts_nothing() {
+        let mut b = [0u8; 2];
+        let mut w = &mut b[..];
+        let res = TWriteTransport::write_all_vectored(&mut w, &[&[0x00], &[0x01, 0x02]]);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::WriteZero);
+    }
+
     fn takes_read_transport<R>(t: &mut R)
     where
         R: TReadTransport,
@@ -0,0 +1,54 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+/// The certificate-based identity of the remote end of a TLS connection.
+///
+/// Holds the DER-encoded certificate chain presented by the peer, end-entity
+/// certificate first. On a server, a peer identity is only available when
+/// the TLS configuration requested a client certificate and the client
+/// presented one that passed verification; the certificates have therefore
+/// been verified before they are exposed here.
+///
+/// Parsing the certificate - for example to extract a subject name or a
+/// SPIFFE URI for authorization decisions - is left to the application.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TPeerIdentity {
+    certificates: Vec<Vec<u8>>,
+}
+
+impl TPeerIdentity {
+    /// Create a `TPeerIdentity` from a DER-encoded certificate chain, end
+    /// entity first. Returns `None` if the chain is empty.
+    pub fn new(certificates: Vec<Vec<u8>>) -> Option<TPeerIdentity> {
+        if certificates.is_empty() {
+            None
+        } else {
+            Some(TPeerIdentity { certificates })
+        }
+    }
+
+    /// The DER-encoded end-entity certificate of the peer.
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificates[0]
+    }
+
+    /// The DER-encoded certificate chain presented by the peer, end entity
+    /// first.
+    pub fn certificate_chain(&self) -> &[Vec<u8>] {
+        &self.certificates
+    }
+}
@@ -0,0 +1,289 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Shared pool of reusable byte buffers.
+///
+/// Framed and buffered transports created with `with_buffer_pool` check
+/// their buffers out of a `TBufferPool` when they first need one and return
+/// them when they are done with them, instead of allocating a buffer per
+/// connection or per large message. A framed transport returns its buffer
+/// after every frame, so idle connections do not hold on to one.
+///
+/// The pool bounds the memory it keeps: buffers larger than the maximum
+/// buffer size are freed instead of being returned, as are buffers that
+/// would take the bytes held by the pool beyond `max_retained_bytes`.
+///
+/// Clones of a `TBufferPool` share the same buffers.
+///
+/// # Examples
+///
+/// ```
+/// use thrift::transport::{
+///     TBufferPool, TFramedReadTransportFactory, TFramedWriteTransportFactory,
+/// };
+///
+/// // keep up to 64MiB of idle buffers of at most 1MiB each
+/// let pool = TBufferPool::new(64 * 1024 * 1024).with_max_buffer_size(1024 * 1024);
+///
+/// let r_trans_factory = TFramedReadTransportFactory::new().with_buffer_pool(pool.clone());
+/// let w_trans_factory = TFramedWriteTransportFactory::new().with_buffer_pool(pool);
+/// ```
+#[derive(Clone)]
+pub struct TBufferPool {
+    inner: Arc<Pool>,
+}
+
+struct Pool {
+    max_retained_bytes: usize,
+    max_buffer_size: usize,
+    idle: Mutex<Idle>,
+}
+
+#[derive(Default)]
+struct Idle {
+    buffers: Vec<Vec<u8>>,
+    bytes: usize,
+}
+
+impl TBufferPool {
+    /// Default largest buffer that is returned to the pool.
+    pub const DEFAULT_MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
+    /// Create an empty `TBufferPool` that keeps at most `max_retained_bytes`
+    /// of idle buffers.
+    pub fn new(max_retained_bytes: usize) -> TBufferPool {
+        TBufferPool::with_limits(max_retained_bytes, TBufferPool::DEFAULT_MAX_BUFFER_SIZE)
+    }
+
+    /// Free buffers larger than `size` bytes instead of returning them to
+    /// the pool, so that one unusually large message does not pin its
+    /// buffer.
+    ///
+    /// Returns a new, empty pool: call this before handing the pool out.
+    pub fn with_max_buffer_size(self, size: usize) -> TBufferPool {
+        TBufferPool::with_limits(self.inner.max_retained_bytes, size)
+    }
+
+    fn with_limits(max_retained_bytes: usize, max_buffer_size: usize) -> TBufferPool {
+        TBufferPool {
+            inner: Arc::new(Pool {
+                max_retained_bytes,
+                max_buffer_size,
+                idle: Mutex::new(Idle::default()),
+            }),
+        }
+    }
+
+    /// Check out an empty buffer with room for at least `min_capacity`
+    /// bytes. The buffer returns to the pool when it is dropped.
+    pub fn checkout(&self, min_capacity: usize) -> TPooledBuffer {
+        TPooledBuffer {
+            buf: self.take(min_capacity),
+            pool: Some(self.clone()),
+        }
+    }
+
+    /// Number of idle buffers in the pool.
+    pub fn retained_buffers(&self) -> usize {
+        self.idle().buffers.len()
+    }
+
+    /// Total capacity in bytes of the idle buffers in the pool.
+    pub fn retained_bytes(&self) -> usize {
+        self.idle().bytes
+    }
+
+    fn idle(&self) -> MutexGuard<'_, Idle> {
+        // the idle list is always left consistent, so a poisoned lock is
+        // still usable
+        self.inner.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn take(&self, min_capacity: usize) -> Vec<u8> {
+        let mut buf = {
+            let mut idle = self.idle();
+            // prefer a buffer that is already large enough, otherwise grow
+            // the most recently returned one
+            let index = idle
+                .buffers
+                .iter()
+                .position(|b| b.capacity() >= min_capacity)
+                .or_else(|| idle.buffers.len().checked_sub(1));
+            match index {
+                Some(index) => {
+                    let buf = idle.buffers.swap_remove(index);
+                    idle.bytes -= buf.capacity();
+                    buf
+                }
+                None => Vec::new(),
+            }
+        };
+        buf.reserve(min_capacity);
+        buf
+    }
+
+    fn give_back(&self, mut buf: Vec<u8>) {
+        let capacity = buf.capacity();
+        if capacity == 0 || capacity > self.inner.max_buffer_size {
+            return;
+        }
+        let mut idle = self.idle();
+        if idle.bytes + capacity <= self.inner.max_retained_bytes {
+            buf.clear();
+            idle.bytes += capacity;
+            idle.buffers.push(buf);
+        }
+    }
+}
+
+impl fmt::Debug for TBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TBufferPool")
+            .field("max_retained_bytes", &self.inner.max_retained_bytes)
+            .field("max_buffer_size", &self.inner.max_buffer_size)
+            .field("retained_bytes", &self.retained_bytes())
+            .finish()
+    }
+}
+
+/// Byte buffer checked out of a `TBufferPool`.
+///
+/// Dereferences to the underlying `Vec<u8>` and returns it to the pool when
+/// dropped.
+#[derive(Debug, Default)]
+pub struct TPooledBuffer {
+    buf: Vec<u8>,
+    pool: Option<TBufferPool>,
+}
+
+impl TPooledBuffer {
+    /// A buffer that does not belong to any pool.
+    pub(crate) fn unpooled(buf: Vec<u8>) -> TPooledBuffer {
+        TPooledBuffer { buf, pool: None }
+    }
+
+    /// An empty buffer that is checked out of `pool` by the first `acquire`.
+    pub(crate) fn pooled(pool: TBufferPool) -> TPooledBuffer {
+        TPooledBuffer {
+            buf: Vec::new(),
+            pool: Some(pool),
+        }
+    }
+
+    /// Check a buffer with room for at least `min_capacity` bytes out of the
+    /// pool, unless one is already held. Does nothing for unpooled buffers.
+    pub(crate) fn acquire(&mut self, min_capacity: usize) {
+        if let Some(ref pool) = self.pool {
+            if self.buf.capacity() == 0 {
+                self.buf = pool.take(min_capacity);
+            }
+        }
+    }
+
+    /// Return the held buffer to the pool, leaving this one empty. Does
+    /// nothing for unpooled buffers.
+    pub(crate) fn release(&mut self) {
+        if let Some(ref pool) = self.pool {
+            pool.give_back(mem::take(&mut self.buf));
+        }
+    }
+}
+
+impl Deref for TPooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for TPooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for TPooledBuffer {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_reuse_returned_buffers() {
+        let pool = TBufferPool::new(1024 * 1024);
+
+        let mut buf = pool.checkout(100);
+        buf.extend_from_slice(b"payload");
+        let ptr = buf.as_ptr();
+        drop(buf);
+        assert_eq!(pool.retained_buffers(), 1);
+
+        let buf = pool.checkout(50);
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(pool.retained_buffers(), 0);
+    }
+
+    #[test]
+    fn must_grow_buffer_to_requested_capacity() {
+        let pool = TBufferPool::new(1024 * 1024);
+        drop(pool.checkout(10));
+
+        let buf = pool.checkout(4096);
+        assert!(buf.capacity() >= 4096);
+    }
+
+    #[test]
+    fn must_free_buffers_larger_than_max_buffer_size() {
+        let pool = TBufferPool::new(1024 * 1024).with_max_buffer_size(1000);
+
+        drop(pool.checkout(2000));
+        assert_eq!(pool.retained_buffers(), 0);
+
+        drop(pool.checkout(500));
+        assert_eq!(pool.retained_buffers(), 1);
+    }
+
+    #[test]
+    fn must_not_retain_more_than_max_retained_bytes() {
+        let pool = TBufferPool::new(3000);
+
+        let bufs: Vec<TPooledBuffer> = (0..4).map(|_| pool.checkout(1000)).collect();
+        let capacity = bufs[0].capacity();
+        drop(bufs);
+
+        assert_eq!(pool.retained_buffers(), 3000 / capacity);
+        assert!(pool.retained_bytes() <= 3000);
+    }
+
+    #[test]
+    fn must_ignore_unpooled_buffers() {
+        let mut buf = TPooledBuffer::unpooled(vec![1, 2, 3]);
+        buf.release();
+        buf.acquire(10);
+        assert_eq!(*buf, vec![1, 2, 3]);
+    }
+}
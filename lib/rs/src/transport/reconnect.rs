@@ -0,0 +1,569 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use super::{ReadHalf, TIoChannel, TSharedChannel, WriteHalf};
+
+/// Delays between successive attempts to establish a connection.
+///
+/// The first retry waits `initial_delay`; each later retry waits `multiplier`
+/// times longer than the one before, up to `max_delay`. After `max_attempts`
+/// failed attempts the last error is returned.
+///
+/// The default policy starts at 100ms, doubles up to 10s and gives up after 5
+/// attempts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TBackoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: u32,
+    max_attempts: Option<u32>,
+}
+
+impl TBackoff {
+    /// Delays that double from `initial_delay` up to `max_delay`.
+    pub fn exponential(initial_delay: Duration, max_delay: Duration) -> TBackoff {
+        TBackoff {
+            initial_delay,
+            max_delay,
+            multiplier: 2,
+            max_attempts: Some(5),
+        }
+    }
+
+    /// The same `delay` before every retry.
+    pub fn fixed(delay: Duration) -> TBackoff {
+        TBackoff {
+            initial_delay: delay,
+            max_delay: delay,
+            multiplier: 1,
+            max_attempts: Some(5),
+        }
+    }
+
+    /// Multiply the delay by `multiplier` after every retry.
+    pub fn with_multiplier(self, multiplier: u32) -> TBackoff {
+        TBackoff { multiplier, ..self }
+    }
+
+    /// Give up after `max_attempts` failed attempts, or never if `None`.
+    /// `Some(0)` is treated as `Some(1)`.
+    pub fn with_max_attempts(self, max_attempts: Option<u32>) -> TBackoff {
+        TBackoff {
+            max_attempts,
+            ..self
+        }
+    }
+
+    /// Delay before the first retry.
+    pub fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+
+    /// Upper bound on the delay between retries.
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// Factor by which the delay grows after every retry.
+    pub fn multiplier(&self) -> u32 {
+        self.multiplier
+    }
+
+    /// Number of attempts after which to give up.
+    pub fn max_attempts(&self) -> Option<u32> {
+        self.max_attempts
+    }
+
+    /// Delay to wait after the failed attempt numbered `attempt`, counting
+    /// from 0.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.checked_pow(attempt).unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+
+    /// Whether another attempt may be made after `failed` attempts.
+    fn may_retry(&self, failed: u32) -> bool {
+        !matches!(self.max_attempts, Some(max) if failed >= max.max(1))
+    }
+}
+
+impl Default for TBackoff {
+    fn default() -> Self {
+        TBackoff::exponential(Duration::from_millis(100), Duration::from_secs(10))
+    }
+}
+
+/// Channel that re-establishes its connection after the connection fails.
+///
+/// The channel is opened by calling `connect`, retrying according to a
+/// [`TBackoff`] policy. When a read or write fails because the connection was
+/// lost - the error is `NotConnected`, `BrokenPipe`, `ConnectionReset`,
+/// `ConnectionAborted` or `UnexpectedEof`, or a read reports end-of-file -
+/// the error is returned to the caller and the connection is dropped. The
+/// next read or write opens a new connection.
+///
+/// A call in flight when the connection is lost fails rather than being
+/// retried, since the server may already have acted on it. The buffered and
+/// framed transports discard a message whose flush fails, so protocols layered
+/// over this channel start the next call on the new connection in a clean
+/// state.
+///
+/// Both halves returned by [`TIoChannel::split`] share the connection.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{
+///     TBackoff, TBufferedReadTransport, TBufferedWriteTransport, TIoChannel,
+///     TReconnectingChannel, TTcpChannel,
+/// };
+///
+/// let channel = TReconnectingChannel::with_backoff(
+///     || {
+///         let mut c = TTcpChannel::new();
+///         c.open("localhost:9090")?;
+///         Ok(c)
+///     },
+///     TBackoff::exponential(Duration::from_millis(50), Duration::from_secs(5))
+///         .with_max_attempts(Some(10)),
+/// )
+/// .unwrap();
+///
+/// let (i_chan, o_chan) = channel.split().unwrap();
+/// let i_prot = TBinaryInputProtocol::new(TBufferedReadTransport::new(i_chan), true);
+/// let o_prot = TBinaryOutputProtocol::new(TBufferedWriteTransport::new(o_chan), true);
+/// ```
+pub struct TReconnectingChannel<C> {
+    inner: TSharedChannel<Reconnecting<C>>,
+}
+
+impl<C: Read + Write> TReconnectingChannel<C> {
+    /// Create a `TReconnectingChannel` that opens connections with `connect`
+    /// using the default `TBackoff`.
+    ///
+    /// The first connection is opened before this returns.
+    pub fn new<F>(connect: F) -> crate::Result<TReconnectingChannel<C>>
+    where
+        F: FnMut() -> crate::Result<C> + Send + 'static,
+    {
+        TReconnectingChannel::with_backoff(connect, TBackoff::default())
+    }
+
+    /// Create a `TReconnectingChannel` that opens connections with `connect`,
+    /// waiting between failed attempts according to `backoff`.
+    ///
+    /// The first connection is opened before this returns.
+    pub fn with_backoff<F>(connect: F, backoff: TBackoff) -> crate::Result<TReconnectingChannel<C>>
+    where
+        F: FnMut() -> crate::Result<C> + Send + 'static,
+    {
+        let channel = TReconnectingChannel::lazy(connect, backoff);
+        channel.inner.lock()?.connect()?;
+        Ok(channel)
+    }
+
+    /// Create a `TReconnectingChannel` that opens connections with `connect`,
+    /// waiting between failed attempts according to `backoff`.
+    ///
+    /// No connection is opened until the first read or write, so the server
+    /// need not be running yet. If it cannot be reached then, that read or
+    /// write fails with `NotConnected` and the next one tries again.
+    pub fn lazy<F>(connect: F, backoff: TBackoff) -> TReconnectingChannel<C>
+    where
+        F: FnMut() -> crate::Result<C> + Send + 'static,
+    {
+        TReconnectingChannel {
+            inner: TSharedChannel::new(Reconnecting {
+                connect: Box::new(connect),
+                backoff,
+                channel: None,
+                connections: 0,
+            }),
+        }
+    }
+
+    /// Whether a connection is currently open.
+    pub fn is_connected(&self) -> crate::Result<bool> {
+        Ok(self.inner.lock()?.channel.is_some())
+    }
+
+    /// Number of times the connection has been re-established.
+    pub fn reconnects(&self) -> crate::Result<u64> {
+        Ok(self.inner.lock()?.connections.saturating_sub(1))
+    }
+
+    /// Drop the current connection. The next read or write opens a new one.
+    pub fn disconnect(&mut self) -> crate::Result<()> {
+        self.inner.lock()?.channel = None;
+        Ok(())
+    }
+}
+
+impl<C> Clone for TReconnectingChannel<C> {
+    fn clone(&self) -> Self {
+        TReconnectingChannel {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for TReconnectingChannel<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TReconnectingChannel")
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: Read + Write> Read for TReconnectingChannel<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<C: Read + Write> Write for TReconnectingChannel<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<C: Read + Write> TIoChannel for TReconnectingChannel<C> {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+struct Reconnecting<C> {
+    connect: Box<dyn FnMut() -> crate::Result<C> + Send>,
+    backoff: TBackoff,
+    channel: Option<C>,
+    connections: u64,
+}
+
+impl<C: Read + Write> Reconnecting<C> {
+    fn connect(&mut self) -> crate::Result<&mut C> {
+        if self.channel.is_none() {
+            let mut failed = 0;
+            let channel = loop {
+                match (self.connect)() {
+                    Ok(channel) => break channel,
+                    Err(e) => {
+                        if !self.backoff.may_retry(failed + 1) {
+                            return Err(e);
+                        }
+                        thread::sleep(self.backoff.delay(failed));
+                        failed += 1;
+                    }
+                }
+            };
+            self.connections += 1;
+            self.channel = Some(channel);
+        }
+        Ok(self.channel.as_mut().expect("connected above"))
+    }
+
+    /// Run `op` on the connection, opening one first if necessary, and drop
+    /// the connection if `op` shows that it has been lost.
+    fn with_channel<T, F>(&mut self, op: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut C) -> io::Result<T>,
+    {
+        let channel = self.connect().map_err(|e| {
+            let message = match e {
+                crate::Error::Transport(e) => e.message,
+                e => e.to_string(),
+            };
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("cannot reconnect: {message}"),
+            )
+        })?;
+        let result = op(channel);
+        if let Err(ref e) = result {
+            if is_connection_lost(e) {
+                self.channel = None;
+            }
+        }
+        result
+    }
+}
+
+impl<C: Read + Write> Read for Reconnecting<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.with_channel(|c| c.read(buf))?;
+        if read == 0 && !buf.is_empty() {
+            // the peer closed the connection
+            self.channel = None;
+        }
+        Ok(read)
+    }
+}
+
+impl<C: Read + Write> Write for Reconnecting<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_channel(|c| c.write(buf))
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.with_channel(|c| c.write_vectored(bufs))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_channel(|c| c.flush())
+    }
+}
+
+fn is_connection_lost(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::transport::{TBufferChannel, TBufferedWriteTransport, TTcpChannel};
+    use crate::{new_transport_error, TransportErrorKind};
+
+    /// Bytes written, tagged with the number of the connection they were
+    /// written to.
+    type WriteLog = Arc<Mutex<Vec<(usize, Vec<u8>)>>>;
+
+    /// Connection that records what is written to it and fails with
+    /// `BrokenPipe` once `fail_after` writes have been made across all
+    /// connections.
+    struct Scripted {
+        id: usize,
+        log: WriteLog,
+        writes: Arc<AtomicUsize>,
+        fail_after: usize,
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            buf[0] = self.id as u8;
+            Ok(1)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.writes.fetch_add(1, Ordering::SeqCst) == self.fail_after {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "gone"));
+            }
+            self.log.lock().unwrap().push((self.id, buf.to_vec()));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn scripted(fail_after: usize) -> (TReconnectingChannel<Scripted>, WriteLog) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let writes = Arc::new(AtomicUsize::new(0));
+        let mut next_id = 0;
+        let connect_log = log.clone();
+        let channel = TReconnectingChannel::with_backoff(
+            move || {
+                next_id += 1;
+                Ok(Scripted {
+                    id: next_id,
+                    log: connect_log.clone(),
+                    writes: writes.clone(),
+                    fail_after,
+                })
+            },
+            TBackoff::fixed(Duration::from_millis(1)),
+        )
+        .unwrap();
+        (channel, log)
+    }
+
+    #[test]
+    fn must_compute_backoff_delays() {
+        let backoff = TBackoff::exponential(Duration::from_millis(100), Duration::from_secs(1));
+        let delays: Vec<_> = (0..6).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+
+        let backoff = TBackoff::exponential(Duration::from_millis(5), Duration::from_secs(1))
+            .with_multiplier(3);
+        assert_eq!(backoff.delay(2), Duration::from_millis(45));
+        assert_eq!(
+            TBackoff::fixed(Duration::from_millis(5)).delay(3),
+            Duration::from_millis(5)
+        );
+    }
+
+    #[test]
+    fn must_retry_failed_connects_until_attempts_are_exhausted() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let result = TReconnectingChannel::<TTcpChannel>::with_backoff(
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(new_transport_error(TransportErrorKind::NotOpen, "refused"))
+            },
+            TBackoff::fixed(Duration::from_millis(1)).with_max_attempts(Some(3)),
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let channel = TReconnectingChannel::with_backoff(
+            move || match counter.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(new_transport_error(TransportErrorKind::NotOpen, "refused")),
+                _ => Ok(TTcpChannel::new()),
+            },
+            TBackoff::fixed(Duration::from_millis(1)),
+        )
+        .unwrap();
+        assert!(channel.is_connected().unwrap());
+        assert_eq!(channel.reconnects().unwrap(), 0);
+    }
+
+    #[test]
+    fn must_not_connect_lazy_channel_until_first_use() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let mut channel = TReconnectingChannel::lazy(
+            move || match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(new_transport_error(TransportErrorKind::NotOpen, "refused")),
+                _ => Ok(TBufferChannel::with_capacity(8, 8)),
+            },
+            TBackoff::fixed(Duration::from_millis(1)).with_max_attempts(Some(1)),
+        );
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+        assert!(!channel.is_connected().unwrap());
+
+        let e = channel.write(b"early").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotConnected);
+        assert!(e.to_string().contains("refused"));
+
+        channel.write_all(b"later").unwrap();
+        assert!(channel.is_connected().unwrap());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(channel.reconnects().unwrap(), 0);
+    }
+
+    #[test]
+    fn must_reconnect_on_next_call_after_connection_is_lost() {
+        let (mut channel, log) = scripted(1);
+
+        channel.write_all(b"one").unwrap();
+        assert_eq!(
+            channel.write(b"two").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert!(!channel.is_connected().unwrap());
+
+        channel.write_all(b"three").unwrap();
+        let mut buf = [0u8; 1];
+        channel.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[0], 2, "read from the second connection");
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![(1, b"one".to_vec()), (2, b"three".to_vec())]
+        );
+        assert_eq!(channel.reconnects().unwrap(), 1);
+    }
+
+    #[test]
+    fn must_not_resend_failed_message_on_new_connection() {
+        let (channel, log) = scripted(0);
+        let (_, w_chan) = channel.clone().split().unwrap();
+        let mut t = TBufferedWriteTransport::new(w_chan);
+
+        t.write_all(b"lost").unwrap();
+        assert!(t.flush().is_err());
+
+        t.write_all(b"next").unwrap();
+        t.flush().unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec![(2, b"next".to_vec())]);
+    }
+
+    #[test]
+    fn must_reconnect_after_peer_closes_tcp_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            // answer one byte per connection, then close it
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 1];
+                stream.read_exact(&mut buf).unwrap();
+                stream.write_all(&[i as u8]).unwrap();
+            }
+        });
+
+        let mut channel = TReconnectingChannel::new(move || {
+            let mut c = TTcpChannel::new();
+            c.open(address)?;
+            Ok(c)
+        })
+        .unwrap();
+
+        let mut buf = [0u8; 1];
+        for expected in 0..3u8 {
+            channel.write_all(&[0]).unwrap();
+            channel.flush().unwrap();
+            channel.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[0], expected);
+            // the server has closed the connection
+            assert_eq!(channel.read(&mut buf).unwrap(), 0);
+            assert!(!channel.is_connected().unwrap());
+        }
+        assert_eq!(channel.reconnects().unwrap(), 2);
+    }
+}
@@ -0,0 +1,1107 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::{ReadHalf, TIoChannel, TSharedChannel, WriteHalf};
+use crate::{new_transport_error, TConfiguration, TransportErrorKind};
+
+/// Status byte that starts every SASL negotiation message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum NegotiationStatus {
+    Start = 0x01,
+    Ok = 0x02,
+    Bad = 0x03,
+    Error = 0x04,
+    Complete = 0x05,
+}
+
+impl NegotiationStatus {
+    fn from_u8(status: u8) -> Option<NegotiationStatus> {
+        match status {
+            0x01 => Some(NegotiationStatus::Start),
+            0x02 => Some(NegotiationStatus::Ok),
+            0x03 => Some(NegotiationStatus::Bad),
+            0x04 => Some(NegotiationStatus::Error),
+            0x05 => Some(NegotiationStatus::Complete),
+            _ => None,
+        }
+    }
+}
+
+/// Client side of a SASL authentication mechanism.
+///
+/// `TSaslPlain` and `TSaslGssapi` implement this trait; implement it to plug
+/// other mechanisms into a `TSaslClientTransport`. The transport calls `initial_response` once, then
+/// `evaluate_challenge` for every challenge sent by the server until the
+/// mechanism reports that it is complete.
+///
+/// A mechanism that negotiates a security layer (a quality of protection
+/// other than `auth`) returns `true` from `has_security_layer`; every data
+/// frame is then passed through `wrap` before it is sent and `unwrap` after
+/// it is received.
+pub trait TSaslMechanism: Send {
+    /// Name of the mechanism, as sent to the server.
+    fn name(&self) -> &str;
+
+    /// Response sent along with the mechanism name, or `None` if the
+    /// mechanism waits for a challenge from the server first.
+    fn initial_response(&mut self) -> crate::Result<Option<Vec<u8>>>;
+
+    /// Compute the response to a challenge sent by the server.
+    fn evaluate_challenge(&mut self, challenge: &[u8]) -> crate::Result<Vec<u8>>;
+
+    /// Whether the mechanism needs no further challenges.
+    fn is_complete(&self) -> bool;
+
+    /// Whether data frames are protected by a negotiated security layer.
+    fn has_security_layer(&self) -> bool {
+        false
+    }
+
+    /// Protect an outgoing data frame.
+    fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    /// Verify and decode an incoming data frame.
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
+
+/// The SASL `PLAIN` mechanism (RFC 4616), which sends a username and
+/// password in the clear.
+///
+/// This is the mechanism HiveServer2 and Impala use for LDAP and custom
+/// password authentication. Since the password is not protected, it should
+/// only be used over TLS or on a trusted network.
+#[derive(Clone)]
+pub struct TSaslPlain {
+    authorization_id: Option<String>,
+    username: String,
+    password: String,
+    complete: bool,
+}
+
+impl TSaslPlain {
+    /// Authenticate as `username` with `password`.
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> TSaslPlain {
+        TSaslPlain {
+            authorization_id: None,
+            username: username.into(),
+            password: password.into(),
+            complete: false,
+        }
+    }
+
+    /// Ask the server to act on behalf of `authorization_id` once
+    /// `username` is authenticated.
+    pub fn with_authorization_id<A: Into<String>>(mut self, authorization_id: A) -> TSaslPlain {
+        self.authorization_id = Some(authorization_id.into());
+        self
+    }
+}
+
+impl fmt::Debug for TSaslPlain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TSaslPlain")
+            .field("authorization_id", &self.authorization_id)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl TSaslMechanism for TSaslPlain {
+    fn name(&self) -> &str {
+        "PLAIN"
+    }
+
+    fn initial_response(&mut self) -> crate::Result<Option<Vec<u8>>> {
+        let authorization_id = self.authorization_id.as_deref().unwrap_or("");
+        let mut response = Vec::with_capacity(
+            authorization_id.len() + self.username.len() + self.password.len() + 2,
+        );
+        response.extend_from_slice(authorization_id.as_bytes());
+        response.push(0);
+        response.extend_from_slice(self.username.as_bytes());
+        response.push(0);
+        response.extend_from_slice(self.password.as_bytes());
+        self.complete = true;
+        Ok(Some(response))
+    }
+
+    fn evaluate_challenge(&mut self, _challenge: &[u8]) -> crate::Result<Vec<u8>> {
+        Err(new_transport_error(
+            TransportErrorKind::Unknown,
+            "SASL PLAIN does not expect a challenge",
+        ))
+    }
+
+    fn is_complete(&self) -> bool {
+        self.complete
+    }
+}
+
+/// Server side of a SASL authentication mechanism, used by
+/// `TSaslServerTransport`.
+///
+/// The transport passes the client's initial response, then each further
+/// response, to `evaluate_response` and sends back the returned challenge,
+/// until the mechanism reports that it is complete. A mechanism rejects a
+/// client by returning an error, whose message is sent to the client.
+pub trait TSaslServerMechanism: Send {
+    /// Name of the mechanism, as requested by clients.
+    fn name(&self) -> &str;
+
+    /// Process a response from the client and return the next challenge.
+    fn evaluate_response(&mut self, response: &[u8]) -> crate::Result<Vec<u8>>;
+
+    /// Whether authentication has succeeded.
+    fn is_complete(&self) -> bool;
+
+    /// Identity the authenticated client acts as, once complete.
+    fn authorization_id(&self) -> Option<&str>;
+
+    /// Whether data frames are protected by a negotiated security layer.
+    fn has_security_layer(&self) -> bool {
+        false
+    }
+
+    /// Protect an outgoing data frame.
+    fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    /// Verify and decode an incoming data frame.
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
+
+/// Signature of the function that checks `PLAIN` credentials: it receives
+/// the authorization id (empty if the client sent none), the username and
+/// the password, and returns whether they are valid.
+pub type TSaslPlainValidator = dyn Fn(&str, &str, &str) -> bool + Send;
+
+/// Server side of the SASL `PLAIN` mechanism, which checks the credentials
+/// sent by a client with a validation function.
+pub struct TSaslPlainServer {
+    validator: Box<TSaslPlainValidator>,
+    authorization_id: Option<String>,
+}
+
+impl TSaslPlainServer {
+    /// Accept clients whose credentials are approved by `validator`.
+    pub fn new<F>(validator: F) -> TSaslPlainServer
+    where
+        F: Fn(&str, &str, &str) -> bool + Send + 'static,
+    {
+        TSaslPlainServer {
+            validator: Box::new(validator),
+            authorization_id: None,
+        }
+    }
+}
+
+impl fmt::Debug for TSaslPlainServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TSaslPlainServer")
+            .field("authorization_id", &self.authorization_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TSaslServerMechanism for TSaslPlainServer {
+    fn name(&self) -> &str {
+        "PLAIN"
+    }
+
+    fn evaluate_response(&mut self, response: &[u8]) -> crate::Result<Vec<u8>> {
+        let invalid = || {
+            new_transport_error(
+                TransportErrorKind::Unknown,
+                "invalid SASL PLAIN credentials",
+            )
+        };
+
+        let mut fields = response.split(|b| *b == 0).map(std::str::from_utf8);
+        let (authorization_id, username, password) =
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(Ok(a)), Some(Ok(u)), Some(Ok(p)), None) if !u.is_empty() => (a, u, p),
+                _ => return Err(invalid()),
+            };
+        if !(self.validator)(authorization_id, username, password) {
+            return Err(new_transport_error(
+                TransportErrorKind::Unknown,
+                format!("authentication failed for user {}", username),
+            ));
+        }
+
+        let authorization_id = if authorization_id.is_empty() {
+            username
+        } else {
+            authorization_id
+        };
+        self.authorization_id = Some(authorization_id.to_owned());
+        Ok(Vec::new())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.authorization_id.is_some()
+    }
+
+    fn authorization_id(&self) -> Option<&str> {
+        self.authorization_id.as_deref()
+    }
+}
+
+/// Client transport that authenticates with SASL, as used by HiveServer2,
+/// Impala and other services of the Hadoop ecosystem.
+///
+/// Construction runs the SASL negotiation of the Java `TSaslClientTransport`
+/// over `channel`: the client sends the mechanism name and its initial
+/// response, then answers challenges until both sides report completion.
+/// Afterwards, like a framed transport, every flush sends the written bytes
+/// as one frame with a 4-byte big-endian length header, and reads are served
+/// one frame at a time. Frames are wrapped and unwrapped by the mechanism if
+/// it negotiated a security layer.
+///
+/// Both halves returned by [`TIoChannel::split`] share the connection.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{TIoChannel, TSaslClientTransport, TSaslPlain, TTcpChannel};
+///
+/// let mut channel = TTcpChannel::new();
+/// channel.open("localhost:10000").unwrap();
+///
+/// let transport =
+///     TSaslClientTransport::new(channel, TSaslPlain::new("user", "password")).unwrap();
+/// let (i_chan, o_chan) = transport.split().unwrap();
+///
+/// let i_prot = TBinaryInputProtocol::new(i_chan, true);
+/// let o_prot = TBinaryOutputProtocol::new(o_chan, true);
+/// ```
+pub struct TSaslClientTransport<C> {
+    inner: TSharedChannel<SaslConnection<C, dyn TSaslMechanism>>,
+    mechanism_name: String,
+}
+
+/// Server transport that authenticates clients of a `TSaslClientTransport`
+/// or of the SASL transports of other Thrift libraries.
+///
+/// Construction reads the mechanism requested by the client, runs the
+/// negotiation with the matching server mechanism and fails, after telling
+/// the client why, if the mechanism is not offered or rejects the client.
+/// Data is then exchanged in frames exactly like `TSaslClientTransport`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::net::TcpListener;
+/// use thrift::transport::{TSaslPlainServer, TSaslServerMechanism, TSaslServerTransport};
+///
+/// let listener = TcpListener::bind("127.0.0.1:10000").unwrap();
+/// let (stream, _) = listener.accept().unwrap();
+///
+/// let plain = TSaslPlainServer::new(|_, user, password| user == "hive" && password == "secret");
+/// let mechanisms: Vec<Box<dyn TSaslServerMechanism>> = vec![Box::new(plain)];
+/// let transport = TSaslServerTransport::accept(stream, mechanisms).unwrap();
+///
+/// assert_eq!(transport.authorization_id(), "hive");
+/// ```
+pub struct TSaslServerTransport<C> {
+    inner: TSharedChannel<SaslConnection<C, dyn TSaslServerMechanism>>,
+    mechanism_name: String,
+    authorization_id: String,
+}
+
+/// Security layer negotiated by a client or server mechanism.
+trait SecurityLayer {
+    fn has_security_layer(&self) -> bool;
+    fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>>;
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>>;
+}
+
+impl SecurityLayer for dyn TSaslMechanism {
+    fn has_security_layer(&self) -> bool {
+        TSaslMechanism::has_security_layer(self)
+    }
+
+    fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        TSaslMechanism::wrap(self, data)
+    }
+
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        TSaslMechanism::unwrap(self, data)
+    }
+}
+
+impl SecurityLayer for dyn TSaslServerMechanism {
+    fn has_security_layer(&self) -> bool {
+        TSaslServerMechanism::has_security_layer(self)
+    }
+
+    fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        TSaslServerMechanism::wrap(self, data)
+    }
+
+    fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        TSaslServerMechanism::unwrap(self, data)
+    }
+}
+
+struct SaslConnection<C, M: ?Sized> {
+    channel: C,
+    mechanism: Box<M>,
+    config: TConfiguration,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Vec<u8>,
+}
+
+impl<C, M: ?Sized> SaslConnection<C, M> {
+    fn new(channel: C, mechanism: Box<M>, config: TConfiguration) -> Self {
+        SaslConnection {
+            channel,
+            mechanism,
+            config,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        }
+    }
+}
+
+impl<C> TSaslClientTransport<C>
+where
+    C: Read + Write,
+{
+    /// Authenticate over `channel` with `mechanism`.
+    pub fn new<M>(channel: C, mechanism: M) -> crate::Result<TSaslClientTransport<C>>
+    where
+        M: TSaslMechanism + 'static,
+    {
+        TSaslClientTransport::with_config(channel, mechanism, TConfiguration::default())
+    }
+
+    /// Authenticate over `channel` with `mechanism`, enforcing the message
+    /// size limit of `config` on negotiation messages and its frame size
+    /// limit on data frames.
+    pub fn with_config<M>(
+        channel: C,
+        mechanism: M,
+        config: TConfiguration,
+    ) -> crate::Result<TSaslClientTransport<C>>
+    where
+        M: TSaslMechanism + 'static,
+    {
+        let mechanism: Box<dyn TSaslMechanism> = Box::new(mechanism);
+        let mut connection = SaslConnection::new(channel, mechanism, config);
+        connection.negotiate()?;
+
+        let mechanism_name = connection.mechanism.name().to_owned();
+        Ok(TSaslClientTransport {
+            inner: TSharedChannel::new(connection),
+            mechanism_name,
+        })
+    }
+
+    /// Name of the mechanism used to authenticate.
+    pub fn mechanism_name(&self) -> &str {
+        &self.mechanism_name
+    }
+}
+
+impl<C> TSaslServerTransport<C>
+where
+    C: Read + Write,
+{
+    /// Authenticate the client on `channel` with whichever of `mechanisms`
+    /// it requests.
+    pub fn accept(
+        channel: C,
+        mechanisms: Vec<Box<dyn TSaslServerMechanism>>,
+    ) -> crate::Result<TSaslServerTransport<C>> {
+        TSaslServerTransport::with_config(channel, mechanisms, TConfiguration::default())
+    }
+
+    /// Authenticate the client on `channel` with whichever of `mechanisms`
+    /// it requests, enforcing the message size limit of `config` on
+    /// negotiation messages and its frame size limit on data frames.
+    pub fn with_config(
+        mut channel: C,
+        mechanisms: Vec<Box<dyn TSaslServerMechanism>>,
+        config: TConfiguration,
+    ) -> crate::Result<TSaslServerTransport<C>> {
+        let (status, name) = receive_message(&mut channel, &config)?;
+        if status != NegotiationStatus::Start {
+            let e = new_transport_error(
+                TransportErrorKind::Unknown,
+                format!("expected SASL START but got {:?}", status),
+            );
+            return abort(&mut channel, NegotiationStatus::Bad, e);
+        }
+
+        let name = String::from_utf8_lossy(&name);
+        let mechanism = match mechanisms.into_iter().find(|m| m.name() == name) {
+            Some(mechanism) => mechanism,
+            None => {
+                let e = new_transport_error(
+                    TransportErrorKind::Unknown,
+                    format!("unsupported SASL mechanism {}", name),
+                );
+                return abort(&mut channel, NegotiationStatus::Bad, e);
+            }
+        };
+
+        let mut connection = SaslConnection::new(channel, mechanism, config);
+        connection.negotiate()?;
+
+        let mechanism_name = connection.mechanism.name().to_owned();
+        let authorization_id = connection
+            .mechanism
+            .authorization_id()
+            .unwrap_or_default()
+            .to_owned();
+        Ok(TSaslServerTransport {
+            inner: TSharedChannel::new(connection),
+            mechanism_name,
+            authorization_id,
+        })
+    }
+
+    /// Name of the mechanism the client authenticated with.
+    pub fn mechanism_name(&self) -> &str {
+        &self.mechanism_name
+    }
+
+    /// Identity the authenticated client acts as.
+    pub fn authorization_id(&self) -> &str {
+        &self.authorization_id
+    }
+}
+
+impl<C> SaslConnection<C, dyn TSaslMechanism>
+where
+    C: Read + Write,
+{
+    fn negotiate(&mut self) -> crate::Result<()> {
+        let initial_response = match self.mechanism.initial_response() {
+            Ok(response) => response.unwrap_or_default(),
+            Err(e) => return self.abort(NegotiationStatus::Error, e),
+        };
+        let name = self.mechanism.name().as_bytes().to_vec();
+        self.send(NegotiationStatus::Start, &name)?;
+        self.send(self.response_status(), &initial_response)?;
+
+        let mut last_status = None;
+        while !self.mechanism.is_complete() {
+            let (status, challenge) = self.receive_reply()?;
+            last_status = Some(status);
+            let response = match self.mechanism.evaluate_challenge(&challenge) {
+                Ok(response) => response,
+                Err(e) => return self.abort(NegotiationStatus::Error, e),
+            };
+            // the server needs no further response once it is complete
+            if status != NegotiationStatus::Complete {
+                self.send(self.response_status(), &response)?;
+            }
+        }
+
+        // a mechanism that completes on its initial response still has to
+        // hear that the server agrees
+        if last_status != Some(NegotiationStatus::Complete) {
+            let (status, _) = self.receive_reply()?;
+            if status != NegotiationStatus::Complete {
+                return Err(new_transport_error(
+                    TransportErrorKind::Unknown,
+                    format!("expected SASL COMPLETE but got {:?}", status),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn response_status(&self) -> NegotiationStatus {
+        if self.mechanism.is_complete() {
+            NegotiationStatus::Complete
+        } else {
+            NegotiationStatus::Ok
+        }
+    }
+}
+
+impl<C> SaslConnection<C, dyn TSaslServerMechanism>
+where
+    C: Read + Write,
+{
+    fn negotiate(&mut self) -> crate::Result<()> {
+        while !self.mechanism.is_complete() {
+            let (_, response) = self.receive_reply()?;
+            let challenge = match self.mechanism.evaluate_response(&response) {
+                Ok(challenge) => challenge,
+                Err(e) => return self.abort(NegotiationStatus::Bad, e),
+            };
+            let status = if self.mechanism.is_complete() {
+                NegotiationStatus::Complete
+            } else {
+                NegotiationStatus::Ok
+            };
+            self.send(status, &challenge)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C, M> SaslConnection<C, M>
+where
+    C: Read + Write,
+    M: SecurityLayer + ?Sized,
+{
+    fn send(&mut self, status: NegotiationStatus, payload: &[u8]) -> crate::Result<()> {
+        send_message(&mut self.channel, status, payload)
+    }
+
+    /// Receive a negotiation message, failing unless its status is `OK` or
+    /// `COMPLETE`.
+    fn receive_reply(&mut self) -> crate::Result<(NegotiationStatus, Vec<u8>)> {
+        let (status, payload) = receive_message(&mut self.channel, &self.config)?;
+        if status == NegotiationStatus::Start {
+            let e =
+                new_transport_error(TransportErrorKind::Unknown, "unexpected SASL START message");
+            return self.abort(NegotiationStatus::Bad, e);
+        }
+        Ok((status, payload))
+    }
+
+    fn abort<T>(&mut self, status: NegotiationStatus, error: crate::Error) -> crate::Result<T> {
+        abort(&mut self.channel, status, error)
+    }
+
+    fn read_frame(&mut self) -> io::Result<()> {
+        let mut header = [0u8; 4];
+        self.channel.read_exact(&mut header)?;
+        let len = u32::from_be_bytes(header) as usize;
+        if let Some(max) = self.config.max_frame_size() {
+            if len > max {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Frame size {} exceeds maximum allowed size of {}", len, max),
+                ));
+            }
+        }
+
+        let mut frame = vec![0u8; len];
+        self.channel.read_exact(&mut frame)?;
+        if self.mechanism.has_security_layer() {
+            frame = self.mechanism.unwrap(&frame).map_err(into_io_error)?;
+        }
+        self.read_buf = frame;
+        self.read_pos = 0;
+        Ok(())
+    }
+}
+
+fn send_message<C: Write>(
+    channel: &mut C,
+    status: NegotiationStatus,
+    payload: &[u8],
+) -> crate::Result<()> {
+    let mut message = Vec::with_capacity(5 + payload.len());
+    message.push(status as u8);
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+    channel.write_all(&message)?;
+    channel.flush()?;
+    Ok(())
+}
+
+/// Receive a negotiation message, failing if the peer reports an error.
+fn receive_message<C: Read + Write>(
+    channel: &mut C,
+    config: &TConfiguration,
+) -> crate::Result<(NegotiationStatus, Vec<u8>)> {
+    let mut header = [0u8; 5];
+    channel.read_exact(&mut header)?;
+
+    let status = match NegotiationStatus::from_u8(header[0]) {
+        Some(status) => status,
+        None => {
+            let e = new_transport_error(
+                TransportErrorKind::Unknown,
+                format!("invalid SASL negotiation status {}", header[0]),
+            );
+            return abort(channel, NegotiationStatus::Error, e);
+        }
+    };
+
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if let Some(max) = config.max_message_size() {
+        if len > max {
+            let e = new_transport_error(
+                TransportErrorKind::SizeLimit,
+                format!(
+                    "SASL negotiation message of {} bytes exceeds the limit of {}",
+                    len, max
+                ),
+            );
+            return abort(channel, NegotiationStatus::Error, e);
+        }
+    }
+    let mut payload = vec![0u8; len];
+    channel.read_exact(&mut payload)?;
+
+    match status {
+        NegotiationStatus::Bad | NegotiationStatus::Error => Err(new_transport_error(
+            TransportErrorKind::Unknown,
+            format!(
+                "SASL negotiation failed with {:?}: {}",
+                status,
+                String::from_utf8_lossy(&payload)
+            ),
+        )),
+        _ => Ok((status, payload)),
+    }
+}
+
+/// Tell the peer that negotiation failed, then fail with `error`.
+fn abort<C: Write, T>(
+    channel: &mut C,
+    status: NegotiationStatus,
+    error: crate::Error,
+) -> crate::Result<T> {
+    let message = match error {
+        crate::Error::Transport(ref e) => e.message.clone(),
+        ref e => e.to_string(),
+    };
+    // the original error matters more than a failure to report it
+    let _ = send_message(channel, status, message.as_bytes());
+    Err(error)
+}
+
+fn into_io_error(error: crate::Error) -> io::Error {
+    let message = match error {
+        crate::Error::Transport(e) => e.message,
+        e => e.to_string(),
+    };
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<C, M> Read for SaslConnection<C, M>
+where
+    C: Read + Write,
+    M: SecurityLayer + ?Sized,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read_pos == self.read_buf.len() {
+            self.read_frame()?;
+        }
+        let nread = cmp::min(buf.len(), self.read_buf.len() - self.read_pos);
+        buf[..nread].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + nread]);
+        self.read_pos += nread;
+        Ok(nread)
+    }
+}
+
+impl<C, M> Write for SaslConnection<C, M>
+where
+    C: Read + Write,
+    M: SecurityLayer + ?Sized,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.write_buf.is_empty() {
+            return self.channel.flush();
+        }
+
+        let data = std::mem::take(&mut self.write_buf);
+        let frame = if self.mechanism.has_security_layer() {
+            self.mechanism.wrap(&data).map_err(into_io_error)?
+        } else {
+            data
+        };
+        let header = (frame.len() as u32).to_be_bytes();
+        self.channel.write_all(&header)?;
+        self.channel.write_all(&frame)?;
+        self.channel.flush()
+    }
+}
+
+impl<C> Clone for TSaslClientTransport<C> {
+    fn clone(&self) -> Self {
+        TSaslClientTransport {
+            inner: self.inner.clone(),
+            mechanism_name: self.mechanism_name.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for TSaslClientTransport<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TSaslClientTransport")
+            .field("mechanism_name", &self.mechanism_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C> Read for TSaslClientTransport<C>
+where
+    C: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<C> Write for TSaslClientTransport<C>
+where
+    C: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<C> TIoChannel for TSaslClientTransport<C>
+where
+    C: Read + Write,
+{
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+impl<C> Clone for TSaslServerTransport<C> {
+    fn clone(&self) -> Self {
+        TSaslServerTransport {
+            inner: self.inner.clone(),
+            mechanism_name: self.mechanism_name.clone(),
+            authorization_id: self.authorization_id.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for TSaslServerTransport<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TSaslServerTransport")
+            .field("mechanism_name", &self.mechanism_name)
+            .field("authorization_id", &self.authorization_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C> Read for TSaslServerTransport<C>
+where
+    C: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<C> Write for TSaslServerTransport<C>
+where
+    C: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<C> TIoChannel for TSaslServerTransport<C>
+where
+    C: Read + Write,
+{
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::*;
+
+    fn message(err: crate::Error) -> String {
+        match err {
+            crate::Error::Transport(e) => e.message,
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    /// Run `server` on the accepted end of a loopback connection.
+    fn with_server<F>(server: F) -> (TcpStream, thread::JoinHandle<()>)
+    where
+        F: FnOnce(TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || server(listener.accept().unwrap().0));
+        (TcpStream::connect(address).unwrap(), handle)
+    }
+
+    fn receive_message(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).unwrap();
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        let mut payload = vec![0u8; len as usize];
+        stream.read_exact(&mut payload).unwrap();
+        (header[0], payload)
+    }
+
+    fn send_message(stream: &mut TcpStream, status: u8, payload: &[u8]) {
+        stream.write_all(&[status]).unwrap();
+        stream
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(payload).unwrap();
+    }
+
+    fn receive_frame(stream: &mut TcpStream) -> Vec<u8> {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).unwrap();
+        let mut frame = vec![0u8; u32::from_be_bytes(header) as usize];
+        stream.read_exact(&mut frame).unwrap();
+        frame
+    }
+
+    fn send_frame(stream: &mut TcpStream, frame: &[u8]) {
+        stream
+            .write_all(&(frame.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(frame).unwrap();
+    }
+
+    #[test]
+    fn must_encode_plain_credentials() {
+        let mut plain = TSaslPlain::new("user", "secret").with_authorization_id("admin");
+        assert!(!plain.is_complete());
+        assert_eq!(
+            plain.initial_response().unwrap().unwrap(),
+            b"admin\0user\0secret".to_vec()
+        );
+        assert!(plain.is_complete());
+        assert!(!format!("{:?}", plain).contains("secret"));
+    }
+
+    #[test]
+    fn must_authenticate_with_plain_and_exchange_frames() {
+        let (stream, server) = with_server(|mut s| {
+            assert_eq!(receive_message(&mut s), (0x01, b"PLAIN".to_vec()));
+            assert_eq!(receive_message(&mut s), (0x05, b"\0user\0secret".to_vec()));
+            send_message(&mut s, 0x05, b"");
+
+            let request = receive_frame(&mut s);
+            assert_eq!(request, b"ping".to_vec());
+            send_frame(&mut s, b"pong");
+        });
+
+        let transport =
+            TSaslClientTransport::new(stream, TSaslPlain::new("user", "secret")).unwrap();
+        assert_eq!(transport.mechanism_name(), "PLAIN");
+
+        let (mut i_chan, mut o_chan) = transport.split().unwrap();
+        o_chan.write_all(b"pi").unwrap();
+        o_chan.write_all(b"ng").unwrap();
+        o_chan.flush().unwrap();
+
+        let mut reply = [0u8; 4];
+        i_chan.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"pong");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn must_report_server_rejection() {
+        let (stream, server) = with_server(|mut s| {
+            receive_message(&mut s);
+            receive_message(&mut s);
+            send_message(&mut s, 0x03, b"invalid credentials");
+        });
+
+        let err = TSaslClientTransport::new(stream, TSaslPlain::new("user", "wrong")).unwrap_err();
+        assert!(message(err).contains("invalid credentials"));
+        server.join().unwrap();
+    }
+
+    /// Two-step mechanism that XORs data frames as its security layer.
+    struct ChallengeResponse {
+        step: u8,
+    }
+
+    impl TSaslMechanism for ChallengeResponse {
+        fn name(&self) -> &str {
+            "X-TEST"
+        }
+
+        fn initial_response(&mut self) -> crate::Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn evaluate_challenge(&mut self, challenge: &[u8]) -> crate::Result<Vec<u8>> {
+            self.step += 1;
+            match (self.step, challenge) {
+                (1, b"nonce") => Ok(b"proof".to_vec()),
+                (2, b"ok") => Ok(Vec::new()),
+                _ => Err(new_transport_error(
+                    TransportErrorKind::Unknown,
+                    "unexpected challenge",
+                )),
+            }
+        }
+
+        fn is_complete(&self) -> bool {
+            self.step == 2
+        }
+
+        fn has_security_layer(&self) -> bool {
+            true
+        }
+
+        fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+            Ok(data.iter().map(|b| b ^ 0x5A).collect())
+        }
+
+        fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+            self.wrap(data)
+        }
+    }
+
+    #[test]
+    fn must_answer_challenges_and_wrap_frames() {
+        let (stream, server) = with_server(|mut s| {
+            assert_eq!(receive_message(&mut s), (0x01, b"X-TEST".to_vec()));
+            assert_eq!(receive_message(&mut s), (0x02, Vec::new()));
+            send_message(&mut s, 0x02, b"nonce");
+            assert_eq!(receive_message(&mut s), (0x02, b"proof".to_vec()));
+            send_message(&mut s, 0x05, b"ok");
+
+            let request = receive_frame(&mut s);
+            assert_eq!(request, vec![0x01 ^ 0x5A]);
+            send_frame(&mut s, &[0x02 ^ 0x5A]);
+        });
+
+        let mut transport =
+            TSaslClientTransport::new(stream, ChallengeResponse { step: 0 }).unwrap();
+        transport.write_all(&[0x01]).unwrap();
+        transport.flush().unwrap();
+
+        let mut reply = [0u8; 1];
+        transport.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, [0x02]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn must_report_mechanism_failure_to_server() {
+        let (stream, server) = with_server(|mut s| {
+            receive_message(&mut s);
+            receive_message(&mut s);
+            send_message(&mut s, 0x02, b"garbage");
+            let (status, payload) = receive_message(&mut s);
+            assert_eq!(status, 0x04);
+            assert_eq!(payload, b"unexpected challenge".to_vec());
+        });
+
+        let err = TSaslClientTransport::new(stream, ChallengeResponse { step: 0 }).unwrap_err();
+        assert_eq!(message(err), "unexpected challenge");
+        server.join().unwrap();
+    }
+
+    fn plain_server() -> Box<dyn TSaslServerMechanism> {
+        Box::new(TSaslPlainServer::new(|authorization_id, user, password| {
+            user == "user" && password == "secret" && authorization_id != "root"
+        }))
+    }
+
+    #[test]
+    fn must_authenticate_plain_client_with_plain_server() {
+        let (stream, server) = with_server(|s| {
+            let mut transport = TSaslServerTransport::accept(s, vec![plain_server()]).unwrap();
+            assert_eq!(transport.mechanism_name(), "PLAIN");
+            assert_eq!(transport.authorization_id(), "admin");
+
+            let mut request = [0u8; 4];
+            transport.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"ping");
+            transport.write_all(b"pong").unwrap();
+            transport.flush().unwrap();
+        });
+
+        let plain = TSaslPlain::new("user", "secret").with_authorization_id("admin");
+        let mut transport = TSaslClientTransport::new(stream, plain).unwrap();
+        transport.write_all(b"ping").unwrap();
+        transport.flush().unwrap();
+
+        let mut reply = [0u8; 4];
+        transport.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"pong");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn must_reject_invalid_plain_credentials() {
+        let (stream, server) = with_server(|s| {
+            let err = TSaslServerTransport::accept(s, vec![plain_server()]).unwrap_err();
+            assert_eq!(message(err), "authentication failed for user user");
+        });
+
+        let err = TSaslClientTransport::new(stream, TSaslPlain::new("user", "wrong")).unwrap_err();
+        assert!(message(err).contains("authentication failed for user user"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn must_reject_unsupported_mechanism() {
+        let (stream, server) = with_server(|s| {
+            let err = TSaslServerTransport::accept(s, vec![plain_server()]).unwrap_err();
+            assert_eq!(message(err), "unsupported SASL mechanism X-TEST");
+        });
+
+        let err = TSaslClientTransport::new(stream, ChallengeResponse { step: 0 }).unwrap_err();
+        assert!(message(err).contains("unsupported SASL mechanism X-TEST"));
+        server.join().unwrap();
+    }
+}
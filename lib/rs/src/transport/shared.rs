@@ -15,7 +15,7 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::io::{self, Read, Write};
+use std::io::{self, IoSlice, Read, Write};
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use super::{ReadHalf, TIoChannel, WriteHalf};
@@ -73,6 +73,10 @@ impl<C: Write> Write for TSharedChannel<C> {
         self.lock()?.write(buffer)
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.lock()?.write_vectored(bufs)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.lock()?.flush()
     }
@@ -86,3 +90,120 @@ impl<C: Read + Write> TIoChannel for TSharedChannel<C> {
         Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
     }
 }
+
+/// Adapter that implements [`TIoChannel`] for a cloneable bidirectional
+/// stream.
+///
+/// `split` hands a clone of the stream to the readable half and the
+/// original to the writable half, without any locking. It is meant for
+/// types whose clones share one underlying connection, so that the halves
+/// can be used concurrently from different threads.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::{self, Read, Write};
+/// use std::net::TcpStream;
+/// use std::sync::Arc;
+///
+/// use thrift::transport::{TCloneChannel, TIoChannel};
+///
+/// // a connection handle whose clones share one socket
+/// #[derive(Clone)]
+/// struct Connection(Arc<TcpStream>);
+///
+/// impl Read for Connection {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         (&*self.0).read(buf)
+///     }
+/// }
+///
+/// impl Write for Connection {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         (&*self.0).write(buf)
+///     }
+///
+///     fn flush(&mut self) -> io::Result<()> {
+///         (&*self.0).flush()
+///     }
+/// }
+///
+/// let stream = TcpStream::connect("localhost:9090").unwrap();
+/// let channel = TCloneChannel::new(Connection(Arc::new(stream)));
+/// let (i_chan, o_chan) = channel.split().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TCloneChannel<C> {
+    inner: C,
+}
+
+impl<C> TCloneChannel<C> {
+    /// Wrap `inner` in a channel that is split by cloning it.
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+
+    /// Return a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the wrapped stream.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Consume this channel and return the wrapped stream.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl<C: Read> Read for TCloneChannel<C> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buffer)
+    }
+}
+
+impl<C: Write> Write for TCloneChannel<C> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.inner.write(buffer)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<C: Read + Write + Clone> TIoChannel for TCloneChannel<C> {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TBufferChannel;
+
+    #[test]
+    fn must_split_clone_channel_into_halves_sharing_the_stream() {
+        let mut inner = TBufferChannel::with_capacity(4, 4);
+        inner.set_readable_bytes(&[0x01, 0x02]);
+
+        let (mut i_chan, mut o_chan) = TCloneChannel::new(inner.clone()).split().unwrap();
+        o_chan.write_all(&[0x03]).unwrap();
+        let mut read = [0u8; 2];
+        i_chan.read_exact(&mut read).unwrap();
+
+        assert_eq!(read, [0x01, 0x02]);
+        assert_eq!(inner.write_bytes(), vec![0x03]);
+    }
+}
@@ -0,0 +1,312 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Capacity of the read and write buffers in bytes.
+const BUFFER_CAPACITY: usize = 4096;
+
+/// Buffered transport over a plain file.
+///
+/// Reads and writes go through internal buffers, and the transport keeps them
+/// consistent: written bytes are flushed to the file before the next read or
+/// seek, and bytes read ahead are given back before the next write. This
+/// makes it possible to write a file and read it back through one transport.
+///
+/// Unlike `TFileWriteTransport`, no framing is added: the file holds exactly
+/// the bytes written by the protocol.
+///
+/// # Examples
+///
+/// Serialize to a file and read the value back.
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// use thrift::transport::TSimpleFileTransport;
+///
+/// let t = TSimpleFileTransport::create("value.bin").unwrap();
+/// let mut o_prot = TBinaryOutputProtocol::new(t, true);
+/// o_prot.write_string("hello").unwrap();
+/// o_prot.flush().unwrap();
+///
+/// let t = TSimpleFileTransport::open("value.bin").unwrap();
+/// let mut i_prot = TBinaryInputProtocol::new(t, true);
+/// assert_eq!(i_prot.read_string().unwrap(), "hello");
+/// ```
+#[derive(Debug)]
+pub struct TSimpleFileTransport {
+    file: File,
+    read_buf: Box<[u8]>,
+    read_pos: usize,
+    read_cap: usize,
+    write_buf: Vec<u8>,
+}
+
+impl TSimpleFileTransport {
+    /// Open an existing file for reading and writing, starting at its
+    /// beginning.
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<TSimpleFileTransport> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(TSimpleFileTransport::with_file(file))
+    }
+
+    /// Create a file for reading and writing, truncating it if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> crate::Result<TSimpleFileTransport> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(TSimpleFileTransport::with_file(file))
+    }
+
+    /// Open a file for reading and appending, creating it if it does not
+    /// exist. Reads start at the beginning of the file, while writes always
+    /// go to its end.
+    pub fn append<P: AsRef<Path>>(path: P) -> crate::Result<TSimpleFileTransport> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        Ok(TSimpleFileTransport::with_file(file))
+    }
+
+    /// Create a `TSimpleFileTransport` that wraps an already opened `file`.
+    pub fn with_file(file: File) -> TSimpleFileTransport {
+        TSimpleFileTransport {
+            file,
+            read_buf: vec![0; BUFFER_CAPACITY].into_boxed_slice(),
+            read_pos: 0,
+            read_cap: 0,
+            write_buf: Vec::with_capacity(BUFFER_CAPACITY),
+        }
+    }
+
+    /// Flush buffered bytes and block until the file contents are on disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.file.sync_data()
+    }
+
+    /// Return a reference to the underlying file.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Flush buffered bytes and return the underlying file, positioned after
+    /// the last byte consumed.
+    pub fn into_inner(mut self) -> io::Result<File> {
+        self.flush()?;
+        self.discard_read_ahead()?;
+        let file = self.file.try_clone()?;
+        Ok(file)
+    }
+
+    fn flush_write_buf(&mut self) -> io::Result<()> {
+        if !self.write_buf.is_empty() {
+            // discard the bytes even if the write fails, like the buffered
+            // transport does
+            let written = self.file.write_all(&self.write_buf);
+            self.write_buf.clear();
+            written?;
+        }
+        Ok(())
+    }
+
+    /// Move the file position back over bytes read ahead but not consumed.
+    fn discard_read_ahead(&mut self) -> io::Result<()> {
+        let unread = self.read_cap - self.read_pos;
+        self.read_pos = 0;
+        self.read_cap = 0;
+        if unread > 0 {
+            self.file.seek(SeekFrom::Current(-(unread as i64)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TSimpleFileTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush_write_buf()?;
+
+        if self.read_pos == self.read_cap {
+            // large reads bypass the buffer
+            if buf.len() >= self.read_buf.len() {
+                return self.file.read(buf);
+            }
+            self.read_cap = self.file.read(&mut self.read_buf)?;
+            self.read_pos = 0;
+        }
+
+        let len = cmp::min(buf.len(), self.read_cap - self.read_pos);
+        buf[..len].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + len]);
+        self.read_pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for TSimpleFileTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.read_cap > 0 {
+            self.discard_read_ahead()?;
+        }
+        if self.write_buf.len() + buf.len() > BUFFER_CAPACITY {
+            self.flush_write_buf()?;
+        }
+        if buf.len() >= BUFFER_CAPACITY {
+            return self.file.write(buf);
+        }
+        self.write_buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_write_buf()?;
+        self.file.flush()
+    }
+}
+
+impl Seek for TSimpleFileTransport {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush_write_buf()?;
+        let unread = (self.read_cap - self.read_pos) as i64;
+        self.read_pos = 0;
+        self.read_cap = 0;
+        match pos {
+            // the file is ahead of the logical position by the unread bytes
+            SeekFrom::Current(offset) => self.file.seek(SeekFrom::Current(offset - unread)),
+            _ => self.file.seek(pos),
+        }
+    }
+}
+
+impl Drop for TSimpleFileTransport {
+    fn drop(&mut self) {
+        let _ = self.flush_write_buf();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TInputProtocol, TOutputProtocol,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "thrift-simple-file-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn must_round_trip_values_through_protocols() {
+        let path = temp_path("protocol");
+        let t = TSimpleFileTransport::create(&path).unwrap();
+        let mut o_prot = TBinaryOutputProtocol::new(t, true);
+        o_prot.write_i64(-7).unwrap();
+        o_prot.write_string("hello").unwrap();
+        o_prot.flush().unwrap();
+        drop(o_prot);
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 8 + 4 + 5);
+
+        let t = TSimpleFileTransport::open(&path).unwrap();
+        let mut i_prot = TBinaryInputProtocol::new(t, true);
+        assert_eq!(i_prot.read_i64().unwrap(), -7);
+        assert_eq!(i_prot.read_string().unwrap(), "hello");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_write_buffered_bytes_on_drop() {
+        let path = temp_path("drop");
+        let mut t = TSimpleFileTransport::create(&path).unwrap();
+        t.write_all(b"unflushed").unwrap();
+        drop(t);
+        assert_eq!(fs::read(&path).unwrap(), b"unflushed");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_append_to_existing_file() {
+        let path = temp_path("append");
+        fs::write(&path, b"abc").unwrap();
+
+        let mut t = TSimpleFileTransport::append(&path).unwrap();
+        let mut buf = [0u8; 2];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ab");
+        t.write_all(b"def").unwrap();
+        t.flush().unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"abcdef");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_read_back_written_bytes_after_seeking() {
+        let path = temp_path("seek");
+        let mut t = TSimpleFileTransport::create(&path).unwrap();
+        t.write_all(b"0123456789").unwrap();
+        t.seek(SeekFrom::Start(2)).unwrap();
+
+        let mut buf = [0u8; 3];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"234");
+        assert_eq!(t.stream_position().unwrap(), 5);
+
+        // writing after a read continues at the logical position
+        t.read_exact(&mut buf[..1]).unwrap();
+        t.write_all(b"XY").unwrap();
+        t.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut contents = Vec::new();
+        t.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"012345XY89");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn must_pass_through_large_reads_and_writes() {
+        let path = temp_path("large");
+        let data: Vec<u8> = (0..3 * BUFFER_CAPACITY).map(|i| i as u8).collect();
+
+        let mut t = TSimpleFileTransport::create(&path).unwrap();
+        t.write_all(&data[..10]).unwrap();
+        t.write_all(&data[10..]).unwrap();
+        t.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut read = vec![0u8; data.len()];
+        t.read_exact(&mut read).unwrap();
+        assert_eq!(read, data);
+        fs::remove_file(&path).unwrap();
+    }
+}
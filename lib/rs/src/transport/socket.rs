@@ -17,16 +17,184 @@
 
 use std::convert::From;
 use std::io;
-use std::io::{ErrorKind, Read, Write};
-use std::net::{Shutdown, TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use std::io::{ErrorKind, IoSlice, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[cfg(unix)]
 use std::os::unix::net::UnixStream;
 
-use super::{ReadHalf, TIoChannel, WriteHalf};
+use socket2::{SockRef, TcpKeepalive};
+
+use super::{ReadHalf, TCallDeadline, TIoChannel, TPeekableReadTransport, TProxy, WriteHalf};
 use crate::{new_transport_error, TransportErrorKind};
 
+/// TCP keepalive settings.
+///
+/// `time` is how long a connection must be idle before the first probe is
+/// sent and `interval` the time between unanswered probes. If `interval` is
+/// not set the operating system default is used; it is also ignored on
+/// platforms that do not support setting it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TTcpKeepalive {
+    pub time: Duration,
+    pub interval: Option<Duration>,
+}
+
+impl TTcpKeepalive {
+    /// Keepalive that sends the first probe after `time` of idleness.
+    pub fn new(time: Duration) -> TTcpKeepalive {
+        TTcpKeepalive {
+            time,
+            interval: None,
+        }
+    }
+
+    /// Set the time between unanswered probes.
+    pub fn with_interval(self, interval: Duration) -> TTcpKeepalive {
+        TTcpKeepalive {
+            interval: Some(interval),
+            ..self
+        }
+    }
+
+    fn to_socket2(self) -> TcpKeepalive {
+        let keepalive = TcpKeepalive::new().with_time(self.time);
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "windows",
+        ))]
+        let keepalive = match self.interval {
+            Some(interval) => keepalive.with_interval(interval),
+            None => keepalive,
+        };
+        keepalive
+    }
+}
+
+/// Socket options applied to TCP connections.
+///
+/// Options that are not set leave the operating system default in place,
+/// except `TCP_NODELAY`, which is enabled by default because Nagle's
+/// algorithm delays small RPC messages.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use thrift::transport::{TTcpChannel, TTcpKeepalive, TTcpOptions};
+///
+/// let options = TTcpOptions::builder()
+///     .keepalive(Some(
+///         TTcpKeepalive::new(Duration::from_secs(60)).with_interval(Duration::from_secs(10)),
+///     ))
+///     .recv_buffer_size(Some(256 * 1024))
+///     .build();
+///
+/// let mut c = TTcpChannel::new();
+/// c.set_options(options).unwrap();
+/// c.open("localhost:9090").unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TTcpOptions {
+    nodelay: bool,
+    keepalive: Option<TTcpKeepalive>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+impl TTcpOptions {
+    pub fn builder() -> TTcpOptionsBuilder {
+        TTcpOptionsBuilder::default()
+    }
+
+    /// Whether `TCP_NODELAY` is set, i.e. Nagle's algorithm is disabled.
+    pub fn nodelay(&self) -> bool {
+        self.nodelay
+    }
+
+    /// `SO_KEEPALIVE` settings, or `None` if keepalive is left disabled.
+    pub fn keepalive(&self) -> Option<TTcpKeepalive> {
+        self.keepalive
+    }
+
+    /// `SO_SNDBUF` size in bytes.
+    pub fn send_buffer_size(&self) -> Option<usize> {
+        self.send_buffer_size
+    }
+
+    /// `SO_RCVBUF` size in bytes.
+    pub fn recv_buffer_size(&self) -> Option<usize> {
+        self.recv_buffer_size
+    }
+
+    /// Apply these options to `stream`.
+    pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        let socket = SockRef::from(stream);
+        if let Some(keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(&keepalive.to_socket2())?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for TTcpOptions {
+    fn default() -> Self {
+        TTcpOptions {
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+/// Builder for `TTcpOptions`.
+#[derive(Debug, Default)]
+pub struct TTcpOptionsBuilder {
+    options: TTcpOptions,
+}
+
+impl TTcpOptionsBuilder {
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.options.nodelay = nodelay;
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: Option<TTcpKeepalive>) -> Self {
+        self.options.keepalive = keepalive;
+        self
+    }
+
+    pub fn send_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.options.send_buffer_size = size;
+        self
+    }
+
+    pub fn recv_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.options.recv_buffer_size = size;
+        self
+    }
+
+    pub fn build(self) -> TTcpOptions {
+        self.options
+    }
+}
+
 /// Bidirectional TCP/IP channel.
 ///
 /// # Examples
@@ -62,14 +230,47 @@ use crate::{new_transport_error, TransportErrorKind};
 /// c.read(&mut buf).unwrap();
 /// c.write(&vec![0, 1, 2]).unwrap();
 /// ```
-#[derive(Debug, Default)]
+///
+/// When the remote address resolves to several addresses, for example both
+/// IPv6 and IPv4 ones, `open` connects to them "Happy Eyeballs" style (RFC
+/// 8305): addresses of the two families are tried alternately, and if an
+/// attempt has not completed after the connection attempt delay the next one
+/// is started in parallel. The first connection to succeed is used. This
+/// avoids long stalls when one address family is broken.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use thrift::transport::TTcpChannel;
+///
+/// let mut c = TTcpChannel::new();
+/// c.set_connect_timeout(Some(Duration::from_secs(2)));
+/// c.set_connection_attempt_delay(Some(Duration::from_millis(100)));
+/// c.open("dual-stack.example.com:9090").unwrap();
+/// ```
+#[derive(Debug)]
 pub struct TTcpChannel {
     stream: Option<TcpStream>,
     read_timeout: Option<Duration>,
     write_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    connection_attempt_delay: Option<Duration>,
+    options: TTcpOptions,
+    call_deadline: Option<TCallDeadline>,
+    // whether the socket read timeout was last set from the call deadline
+    deadline_timeout_set: bool,
+}
+
+impl Default for TTcpChannel {
+    fn default() -> Self {
+        TTcpChannel::new()
+    }
 }
 
 impl TTcpChannel {
+    /// Default time to wait for a connection attempt before starting the
+    /// next one, as recommended by RFC 8305.
+    pub const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
     /// Create an uninitialized `TTcpChannel`.
     ///
     /// The returned instance must be opened using `TTcpChannel::open(...)`
@@ -79,13 +280,19 @@ impl TTcpChannel {
             stream: None,
             read_timeout: None,
             write_timeout: None,
+            connect_timeout: None,
+            connection_attempt_delay: Some(TTcpChannel::DEFAULT_CONNECTION_ATTEMPT_DELAY),
+            options: TTcpOptions::default(),
+            call_deadline: None,
+            deadline_timeout_set: false,
         }
     }
 
     /// Create a `TTcpChannel` that wraps an existing `TcpStream`.
     ///
     /// The passed-in stream is assumed to have been opened before being wrapped
-    /// by the created `TTcpChannel` instance.
+    /// by the created `TTcpChannel` instance. Its socket options are left
+    /// unchanged until `TTcpChannel::set_options(...)` is called.
     pub fn with_stream(stream: TcpStream) -> TTcpChannel {
         let read_timeout = stream.read_timeout().unwrap_or_default();
         let write_timeout = stream.write_timeout().unwrap_or_default();
@@ -94,7 +301,54 @@ impl TTcpChannel {
             stream: Some(stream),
             read_timeout,
             write_timeout,
+            ..TTcpChannel::new()
+        }
+    }
+
+    /// Return the socket options last set on this channel.
+    pub fn options(&self) -> TTcpOptions {
+        self.options
+    }
+
+    /// Set the socket options for this channel.
+    ///
+    /// The options are applied immediately if the channel is connected, and
+    /// when it is opened otherwise.
+    pub fn set_options(&mut self, options: TTcpOptions) -> crate::Result<()> {
+        if let Some(ref stream) = self.stream {
+            options.apply(stream)?;
         }
+
+        self.options = options;
+        Ok(())
+    }
+
+    /// Take the connected stream out of this channel.
+    #[cfg(any(feature = "rustls", feature = "tls-native"))]
+    pub(crate) fn into_stream(self) -> crate::Result<TcpStream> {
+        self.stream.ok_or_else(|| {
+            new_transport_error(TransportErrorKind::NotOpen, "tcp endpoint not connected")
+        })
+    }
+
+    /// Return the underlying socket for options not covered by
+    /// `TTcpOptions`, or `None` if the channel is not connected.
+    pub fn socket(&self) -> Option<SockRef<'_>> {
+        self.stream.as_ref().map(SockRef::from)
+    }
+
+    /// Return the call deadline shared with this channel, if any.
+    pub fn call_deadline(&self) -> Option<&TCallDeadline> {
+        self.call_deadline.as_ref()
+    }
+
+    /// Bound reads from this channel by `deadline`, or stop doing so if
+    /// `None`.
+    ///
+    /// Set it before calling `split`: both halves share the deadline. See
+    /// `TCallDeadline` for what happens when a call runs past it.
+    pub fn set_call_deadline(&mut self, deadline: Option<TCallDeadline>) {
+        self.call_deadline = deadline;
     }
 
     /// Return the read timeout for this channel.
@@ -146,6 +400,30 @@ impl TTcpChannel {
         Ok(())
     }
 
+    /// Return the time allowed for each connection attempt.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Limit each connection attempt made by `open` to `timeout`. `None`,
+    /// the default, leaves it to the operating system.
+    pub fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+
+    /// Return the delay before `open` starts a parallel connection attempt.
+    pub fn connection_attempt_delay(&self) -> Option<Duration> {
+        self.connection_attempt_delay
+    }
+
+    /// Set how long `open` waits for a connection attempt before starting
+    /// one to the next address in parallel. Defaults to
+    /// `DEFAULT_CONNECTION_ATTEMPT_DELAY`. `None` tries the addresses one
+    /// after the other, in the order they were resolved.
+    pub fn set_connection_attempt_delay(&mut self, delay: Option<Duration>) {
+        self.connection_attempt_delay = delay;
+    }
+
     /// Connect to `remote_address`, which should implement `ToSocketAddrs` trait.
     pub fn open<A: ToSocketAddrs>(&mut self, remote_address: A) -> crate::Result<()> {
         if self.stream.is_some() {
@@ -154,9 +432,9 @@ impl TTcpChannel {
                 "tcp connection previously opened",
             ))
         } else {
-            match TcpStream::connect(&remote_address) {
+            match self.connect(remote_address) {
                 Ok(s) => {
-                    s.set_nodelay(true)?;
+                    self.options.apply(&s)?;
                     s.set_read_timeout(self.read_timeout)?;
                     s.set_write_timeout(self.write_timeout)?;
                     self.stream = Some(s);
@@ -167,6 +445,28 @@ impl TTcpChannel {
         }
     }
 
+    /// Connect to `target` (`host:port`) through a tunnel opened by `proxy`.
+    ///
+    /// The channel's socket options and timeouts apply to the connection to
+    /// the proxy, including the tunnel negotiation, and remain in effect once
+    /// the tunnel is established.
+    pub fn open_via_proxy(&mut self, proxy: &TProxy, target: &str) -> crate::Result<()> {
+        if self.stream.is_some() {
+            return Err(new_transport_error(
+                TransportErrorKind::AlreadyOpen,
+                "tcp connection previously opened",
+            ));
+        }
+
+        let mut s = self.connect(proxy.address())?;
+        self.options.apply(&s)?;
+        s.set_read_timeout(self.read_timeout)?;
+        s.set_write_timeout(self.write_timeout)?;
+        proxy.tunnel(&mut s, target)?;
+        self.stream = Some(s);
+        Ok(())
+    }
+
     /// Shut down this channel.
     ///
     /// Both send and receive halves are closed, and this instance can no
@@ -176,6 +476,61 @@ impl TTcpChannel {
             .map_err(From::from)
     }
 
+    /// Shut down the send half, the receive half, or both halves of this
+    /// channel.
+    ///
+    /// Shutting down the send half with `Shutdown::Write` signals the end of
+    /// the stream to the remote endpoint, which reads EOF once it has
+    /// received everything sent before, while replies can still be read.
+    /// The halves returned by `split` share the socket, so shutting down the
+    /// write half affects both.
+    pub fn shutdown(&mut self, how: Shutdown) -> crate::Result<()> {
+        self.if_set(|s| s.shutdown(how)).map_err(From::from)
+    }
+
+    fn connect<A: ToSocketAddrs>(&self, remote_address: A) -> io::Result<TcpStream> {
+        let addrs = interleave_families(remote_address.to_socket_addrs()?.collect());
+        match self.connection_attempt_delay {
+            Some(delay) if addrs.len() > 1 => connect_staggered(addrs, delay, self.connect_timeout),
+            _ => connect_sequential(&addrs, self.connect_timeout),
+        }
+    }
+
+    /// Read with the socket read timeout shortened to the time left until
+    /// `deadline`, closing the connection once it has passed.
+    fn read_before(&mut self, deadline: Instant, b: &mut [u8]) -> io::Result<usize> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(self.expire_call());
+        }
+
+        let timeout = match self.read_timeout {
+            Some(configured) if configured < remaining => configured,
+            _ => remaining,
+        };
+        self.if_set(|s| s.set_read_timeout(Some(timeout)))?;
+        self.deadline_timeout_set = true;
+
+        match self.if_set(|s| s.read(b)) {
+            Err(e)
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                    && Instant::now() >= deadline =>
+            {
+                Err(self.expire_call())
+            }
+            result => result,
+        }
+    }
+
+    /// Close the connection of a call that ran past its deadline.
+    ///
+    /// The reply may still arrive, so the connection cannot be used for
+    /// another call.
+    fn expire_call(&mut self) -> io::Error {
+        let _ = self.if_set(|s| s.shutdown(Shutdown::Both));
+        io::Error::new(ErrorKind::TimedOut, "call deadline passed")
+    }
+
     fn if_set<F, T>(&mut self, mut stream_operation: F) -> io::Result<T>
     where
         F: FnMut(&mut TcpStream) -> io::Result<T>,
@@ -191,6 +546,103 @@ impl TTcpChannel {
     }
 }
 
+/// Order `addrs` so that the two address families alternate, starting with
+/// the family of the first resolved address (RFC 8305, section 4).
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let preferred_v6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == preferred_v6);
+
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+fn connect_one(addr: &SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    match timeout {
+        Some(timeout) => TcpStream::connect_timeout(addr, timeout),
+        None => TcpStream::connect(addr),
+    }
+}
+
+fn no_addresses() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        "could not resolve to any addresses",
+    )
+}
+
+fn connect_sequential(addrs: &[SocketAddr], timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        match connect_one(addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(no_addresses))
+}
+
+/// Start a connection attempt to each address in turn, each one `delay`
+/// after the previous one or as soon as the previous one fails, and return
+/// the first connection established.
+///
+/// Attempts still in progress when one succeeds are abandoned: their
+/// threads finish in the background and drop whatever they connect.
+fn connect_staggered(
+    addrs: Vec<SocketAddr>,
+    delay: Duration,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let (tx, rx) = mpsc::channel();
+    let mut pending = addrs.into_iter();
+    let mut in_flight = 0;
+    let mut last_error = None;
+
+    loop {
+        if let Some(addr) = pending.next() {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                // the receiver is gone if another attempt already won
+                let _ = tx.send(connect_one(&addr, timeout));
+            });
+            in_flight += 1;
+        }
+        if in_flight == 0 {
+            return Err(last_error.unwrap_or_else(no_addresses));
+        }
+
+        let result = if pending.as_slice().is_empty() {
+            match rx.recv() {
+                Ok(result) => result,
+                Err(_) => unreachable!("connection attempts hold a sender"),
+            }
+        } else {
+            match rx.recv_timeout(delay) {
+                Ok(result) => result,
+                // start the next attempt alongside this one
+                Err(_) => continue,
+            }
+        };
+
+        in_flight -= 1;
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+}
+
 impl TIoChannel for TTcpChannel {
     fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
     where
@@ -218,11 +670,21 @@ impl TIoChannel for TTcpChannel {
                     stream: s.stream.take(),
                     read_timeout,
                     write_timeout,
+                    connect_timeout: s.connect_timeout,
+                    connection_attempt_delay: s.connection_attempt_delay,
+                    options: s.options,
+                    call_deadline: s.call_deadline.clone(),
+                    deadline_timeout_set: false,
                 });
                 let write_half = WriteHalf::new(TTcpChannel {
                     stream: Some(cloned),
                     read_timeout,
                     write_timeout,
+                    connect_timeout: s.connect_timeout,
+                    connection_attempt_delay: s.connection_attempt_delay,
+                    options: s.options,
+                    call_deadline: s.call_deadline.clone(),
+                    deadline_timeout_set: false,
                 });
                 (read_half, write_half)
             })
@@ -237,29 +699,66 @@ impl TIoChannel for TTcpChannel {
 
 impl Read for TTcpChannel {
     fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self
+            .call_deadline
+            .as_ref()
+            .and_then(TCallDeadline::deadline)
+        {
+            return self.read_before(deadline, b);
+        }
+        if self.deadline_timeout_set {
+            let read_timeout = self.read_timeout;
+            self.if_set(|s| s.set_read_timeout(read_timeout))?;
+            self.deadline_timeout_set = false;
+        }
         self.if_set(|s| s.read(b))
     }
 }
 
+/// Peeks with a single `TcpStream::peek`, which returns the bytes already
+/// received by the socket.
+impl TPeekableReadTransport for TTcpChannel {
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.if_set(|s| s.peek(buf))
+    }
+}
+
 impl Write for TTcpChannel {
     fn write(&mut self, b: &[u8]) -> io::Result<usize> {
         self.if_set(|s| s.write(b))
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.if_set(|s| s.write_vectored(bufs))
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.if_set(|s| s.flush())
     }
 }
 
+/// Splits a connected stream, for example one accepted from a listener
+/// configured outside this crate, into halves that share the socket.
+impl TIoChannel for TcpStream {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        let socket_tx = self.try_clone()?;
+
+        Ok((ReadHalf::new(self), WriteHalf::new(socket_tx)))
+    }
+}
+
 #[cfg(unix)]
 impl TIoChannel for UnixStream {
     fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
     where
         Self: Sized,
     {
-        let socket_rx = self.try_clone().unwrap();
+        let socket_tx = self.try_clone()?;
 
-        Ok((ReadHalf::new(self), WriteHalf::new(socket_rx)))
+        Ok((ReadHalf::new(self), WriteHalf::new(socket_tx)))
     }
 }
 
@@ -441,4 +940,235 @@ mod tests {
         assert_eq!(read_half.read_timeout().unwrap(), updated);
         assert_eq!(write_half.write_timeout().unwrap(), updated_write);
     }
+
+    #[test]
+    fn must_apply_options_to_connected_channel() {
+        let (mut channel, _server) = wrapped_channel();
+        let options = TTcpOptions::builder()
+            .nodelay(false)
+            .keepalive(Some(
+                TTcpKeepalive::new(Duration::from_secs(30)).with_interval(Duration::from_secs(5)),
+            ))
+            .send_buffer_size(Some(64 * 1024))
+            .recv_buffer_size(Some(64 * 1024))
+            .build();
+
+        channel.set_options(options).unwrap();
+
+        let socket = channel.socket().unwrap();
+        assert!(!socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        // the kernel may round buffer sizes up
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(channel.options(), options);
+    }
+
+    #[test]
+    fn must_apply_options_when_opening_channel() {
+        let (listener, address) = listening_address();
+        let accept_handle = thread::spawn(move || listener.accept().unwrap().0);
+        let mut channel = TTcpChannel::new();
+
+        assert!(channel.socket().is_none());
+        channel
+            .set_options(TTcpOptions::builder().nodelay(false).build())
+            .unwrap();
+        channel.open(address).unwrap();
+
+        assert!(!channel.socket().unwrap().nodelay().unwrap());
+        let _server = accept_handle.join().unwrap();
+    }
+
+    #[test]
+    fn must_enable_nodelay_by_default_when_opening_channel() {
+        let (listener, address) = listening_address();
+        let accept_handle = thread::spawn(move || listener.accept().unwrap().0);
+        let mut channel = TTcpChannel::new();
+
+        channel.open(address).unwrap();
+
+        assert!(channel.socket().unwrap().nodelay().unwrap());
+        let _server = accept_handle.join().unwrap();
+    }
+
+    #[test]
+    fn must_peek_without_consuming() {
+        let (channel, mut server) = wrapped_channel();
+        server.write_all(&[0x80, 0x01, 0x00, 0x01]).unwrap();
+
+        let (mut read_half, _write_half) = channel.split().unwrap();
+        let mut peeked = [0u8; 2];
+        assert_eq!(read_half.peek(&mut peeked).unwrap(), 2);
+        assert_eq!(peeked, [0x80, 0x01]);
+
+        let mut read = [0u8; 4];
+        read_half.read_exact(&mut read).unwrap();
+        assert_eq!(read, [0x80, 0x01, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn must_fail_to_peek_unopened_channel() {
+        let mut channel = TTcpChannel::new();
+        let err = channel.peek(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn must_fail_read_and_close_connection_when_call_deadline_passes() {
+        let (mut channel, mut server) = wrapped_channel();
+        let deadline = TCallDeadline::new();
+        channel.set_call_deadline(Some(deadline.clone()));
+        let (mut read_half, _write_half) = channel.split().unwrap();
+
+        deadline.set_timeout(Duration::from_millis(50));
+        let mut buf = [0u8; 4];
+        let error = read_half.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+
+        // the late reply must not be read by the next call
+        deadline.clear();
+        server.write_all(&[0x80, 0x01]).unwrap_or_default();
+        assert_eq!(read_half.read(&mut buf).unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn must_read_normally_within_call_deadline() {
+        let (mut channel, mut server) = wrapped_channel();
+        channel
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let deadline = TCallDeadline::new();
+        channel.set_call_deadline(Some(deadline.clone()));
+        let (mut read_half, _write_half) = channel.split().unwrap();
+        server.write_all(&[0x80, 0x01]).unwrap();
+
+        let mut buf = [0u8; 2];
+        deadline
+            .call(Duration::from_secs(5), || {
+                read_half.read_exact(&mut buf).map_err(From::from)
+            })
+            .unwrap();
+        assert_eq!(buf, [0x80, 0x01]);
+
+        // the configured read timeout is restored once the deadline is gone
+        server.write_all(&[0x02]).unwrap();
+        read_half.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(
+            read_half.read_timeout().unwrap(),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn must_half_close_through_write_half() {
+        let (channel, mut server) = wrapped_channel();
+        let (mut read_half, mut write_half) = channel.split().unwrap();
+
+        write_half.write_all(&[0x01, 0x02]).unwrap();
+        write_half.shutdown(Shutdown::Write).unwrap();
+
+        // the server sees everything sent, then EOF
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+        assert_eq!(received, vec![0x01, 0x02]);
+
+        // the receive half still works
+        server.write_all(&[0x03]).unwrap();
+        let mut reply = [0u8; 1];
+        read_half.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, [0x03]);
+
+        assert!(write_half.write_all(&[0x04]).is_err());
+    }
+
+    #[test]
+    fn must_fail_to_shut_down_unopened_channel() {
+        let mut channel = TTcpChannel::new();
+        assert!(channel.shutdown(Shutdown::Write).is_err());
+    }
+
+    #[test]
+    fn must_split_tcp_stream() {
+        let (client, mut server) = connected_streams();
+        let (mut read_half, mut write_half) = client.split().unwrap();
+
+        write_half.write_all(&[0x01]).unwrap();
+        let mut received = [0u8; 1];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(received, [0x01]);
+
+        server.write_all(&[0x02]).unwrap();
+        read_half.read_exact(&mut received).unwrap();
+        assert_eq!(received, [0x02]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn must_split_unix_stream() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let (mut read_half, mut write_half) = client.split().unwrap();
+
+        write_half.write_all(&[0x01]).unwrap();
+        let mut received = [0u8; 1];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(received, [0x01]);
+
+        server.write_all(&[0x02]).unwrap();
+        read_half.read_exact(&mut received).unwrap();
+        assert_eq!(received, [0x02]);
+    }
+
+    fn refused_address() -> SocketAddr {
+        // nothing listens on the port once the listener is dropped
+        listening_address().1
+    }
+
+    #[test]
+    fn must_interleave_address_families() {
+        let v4 = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let v6 = |port| SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port));
+
+        assert_eq!(
+            interleave_families(vec![v6(1), v6(2), v6(3), v4(4), v4(5)]),
+            vec![v6(1), v4(4), v6(2), v4(5), v6(3)]
+        );
+        assert_eq!(
+            interleave_families(vec![v4(1), v6(2), v4(3)]),
+            vec![v4(1), v6(2), v4(3)]
+        );
+        assert!(interleave_families(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn must_connect_to_next_address_when_first_is_refused() {
+        for delay in [Some(Duration::from_secs(10)), None] {
+            let (listener, address) = listening_address();
+            let mut c = TTcpChannel::new();
+            c.set_connection_attempt_delay(delay);
+
+            c.open(&[refused_address(), address][..]).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+
+            c.write_all(&[0x01]).unwrap();
+            let mut buf = [0u8; 1];
+            server.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [0x01]);
+        }
+    }
+
+    #[test]
+    fn must_fail_when_every_address_is_refused() {
+        let mut c = TTcpChannel::new();
+        c.set_connect_timeout(Some(Duration::from_secs(5)));
+
+        let err = c
+            .open(&[refused_address(), refused_address()][..])
+            .unwrap_err();
+        match err {
+            crate::Error::Transport(e) => assert_eq!(e.kind, TransportErrorKind::NotOpen),
+            e => panic!("expected transport error, got {:?}", e),
+        }
+        assert!(c.stream.is_none());
+    }
 }
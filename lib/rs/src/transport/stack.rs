@@ -0,0 +1,506 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+use std::sync::Arc;
+
+use super::{
+    TBufferPolicy, TBufferedReadTransportFactory, TBufferedWriteTransportFactory,
+    TFramedReadTransportFactory, TFramedWriteTransportFactory, TRateLimiter, TReadTransport,
+    TReadTransportFactory, TThrottledReadTransportFactory, TThrottledWriteTransportFactory,
+    TTransportObserver, TWriteTransport, TWriteTransportFactory,
+};
+use crate::TConfiguration;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum LayerKind {
+    Buffered,
+    Framed,
+    Compressed,
+    Throttled,
+    Instrumented,
+    Custom,
+}
+
+#[derive(Clone)]
+struct Layer {
+    kind: LayerKind,
+    name: &'static str,
+    read: Arc<dyn TReadTransportFactory + Send + Sync>,
+    write: Arc<dyn TWriteTransportFactory + Send + Sync>,
+}
+
+impl fmt::Debug for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+/// Identifies one of the common transport stacks.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TTransportKind {
+    /// A buffered transport (`TBufferedReadTransport`/`TBufferedWriteTransport`).
+    Buffered,
+    /// A framed transport (`TFramedReadTransport`/`TFramedWriteTransport`).
+    Framed,
+}
+
+impl From<TTransportKind> for TTransportStackBuilder {
+    fn from(kind: TTransportKind) -> Self {
+        match kind {
+            TTransportKind::Buffered => TTransportStackBuilder::new().buffered(),
+            TTransportKind::Framed => TTransportStackBuilder::new().framed(),
+        }
+    }
+}
+
+/// Builds matching read and write transport factories from a stack of
+/// layers.
+///
+/// Layers are listed starting from the one closest to the network: the
+/// first layer wraps the connection's channel, the second layer wraps the
+/// first, and so on, with the protocol reading from and writing to the last
+/// layer. Every layer is applied to both directions, so clients and servers
+/// configured with the same stack always agree on the wire format.
+///
+/// `build()` rejects stacks that cannot work or are almost certainly a
+/// mistake:
+///
+/// * more than one framed layer, since each would add its own length prefix
+/// * more than one compression layer, since compressed data does not
+///   compress further
+///
+/// Layers added with `layer(...)` are not validated.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use thrift::protocol::{TBinaryInputProtocolFactory, TBinaryOutputProtocolFactory};
+/// use thrift::protocol::{TInputProtocol, TOutputProtocol};
+/// use thrift::server::{TProcessor, TServer};
+/// use thrift::transport::{TTransportCounters, TTransportStackBuilder};
+///
+/// # struct Processor;
+/// # impl TProcessor for Processor {
+/// #     fn process(&self, _: &mut dyn TInputProtocol, _: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+/// #         unimplemented!()
+/// #     }
+/// # }
+/// let counters = TTransportCounters::new();
+///
+/// // count the bytes on the wire, then frame, then buffer
+/// let (r_trans_factory, w_trans_factory) = TTransportStackBuilder::new()
+///     .instrumented(Arc::new(counters.clone()))
+///     .framed()
+///     .buffered()
+///     .build()
+///     .unwrap();
+///
+/// let mut server = TServer::new(
+///     r_trans_factory,
+///     TBinaryInputProtocolFactory::new(),
+///     w_trans_factory,
+///     TBinaryOutputProtocolFactory::new(),
+///     Processor,
+///     10,
+/// );
+/// server.listen("127.0.0.1:9090").unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct TTransportStackBuilder {
+    layers: Vec<Layer>,
+}
+
+impl TTransportStackBuilder {
+    /// Create a builder for an empty stack, which passes bytes through
+    /// unchanged.
+    pub fn new() -> TTransportStackBuilder {
+        TTransportStackBuilder::default()
+    }
+
+    /// Add a buffered layer with the default buffer policy.
+    pub fn buffered(self) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Buffered,
+            "buffered",
+            TBufferedReadTransportFactory::new(),
+            TBufferedWriteTransportFactory::new(),
+        )
+    }
+
+    /// Add a buffered layer whose buffers follow `policy`.
+    pub fn buffered_with_policy(self, policy: TBufferPolicy) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Buffered,
+            "buffered",
+            TBufferedReadTransportFactory::with_policy(policy),
+            TBufferedWriteTransportFactory::with_policy(policy),
+        )
+    }
+
+    /// Add a framed layer with the default frame size limit.
+    pub fn framed(self) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Framed,
+            "framed",
+            TFramedReadTransportFactory::new(),
+            TFramedWriteTransportFactory::new(),
+        )
+    }
+
+    /// Add a framed layer that enforces the frame size limit in `config`.
+    pub fn framed_with_config(self, config: TConfiguration) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Framed,
+            "framed",
+            TFramedReadTransportFactory::with_config(config.clone()),
+            TFramedWriteTransportFactory::with_config(config),
+        )
+    }
+
+    /// Add a layer that limits traffic to the budget of `limiter`.
+    ///
+    /// Add it first for the budget to apply to the bytes that cross the
+    /// network.
+    pub fn throttled(self, limiter: TRateLimiter) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Throttled,
+            "throttled",
+            TThrottledReadTransportFactory::new(limiter.clone()),
+            TThrottledWriteTransportFactory::new(limiter),
+        )
+    }
+
+    /// Add a layer that reports the bytes read and written, and flushes, to
+    /// `observer`.
+    ///
+    /// Connection lifetimes are not reported, because read and write
+    /// transports are created independently; use
+    /// `TInstrumentedChannel` for those.
+    pub fn instrumented(self, observer: Arc<dyn TTransportObserver>) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Instrumented,
+            "instrumented",
+            ObservedReadFactory(observer.clone()),
+            ObservedWriteFactory(observer),
+        )
+    }
+
+    /// Add a zlib compression layer with the default compression level.
+    #[cfg(feature = "zlib")]
+    pub fn zlib(self) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Compressed,
+            "zlib",
+            super::TZlibReadTransportFactory::new(),
+            super::TZlibWriteTransportFactory::new(),
+        )
+    }
+
+    /// Add a zstd compression layer with the default compression level.
+    #[cfg(feature = "zstd")]
+    pub fn zstd(self) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Compressed,
+            "zstd",
+            super::TZstdReadTransportFactory::new(),
+            super::TZstdWriteTransportFactory::new(),
+        )
+    }
+
+    /// Add an LZ4 compression layer.
+    #[cfg(feature = "lz4")]
+    pub fn lz4(self) -> TTransportStackBuilder {
+        self.push(
+            LayerKind::Compressed,
+            "lz4",
+            super::TLz4ReadTransportFactory::new(),
+            super::TLz4WriteTransportFactory::new(),
+        )
+    }
+
+    /// Add a layer created by `read_factory` and `write_factory`.
+    ///
+    /// The two factories must create transports that understand each other.
+    pub fn layer<R, W>(self, read_factory: R, write_factory: W) -> TTransportStackBuilder
+    where
+        R: TReadTransportFactory + Send + Sync + 'static,
+        W: TWriteTransportFactory + Send + Sync + 'static,
+    {
+        self.push(LayerKind::Custom, "custom", read_factory, write_factory)
+    }
+
+    /// Validate the stack and return its read and write transport
+    /// factories.
+    pub fn build(
+        self,
+    ) -> crate::Result<(TStackedReadTransportFactory, TStackedWriteTransportFactory)> {
+        let framed = self.count(LayerKind::Framed);
+        if framed > 1 {
+            return Err(invalid_stack(format!(
+                "{} framed layers, at most one is allowed",
+                framed
+            )));
+        }
+
+        let compressed: Vec<&str> = self
+            .layers
+            .iter()
+            .filter(|l| l.kind == LayerKind::Compressed)
+            .map(|l| l.name)
+            .collect();
+        if compressed.len() > 1 {
+            return Err(invalid_stack(format!(
+                "more than one compression layer ({})",
+                compressed.join(", ")
+            )));
+        }
+
+        let layers: Arc<[Layer]> = self.layers.into();
+        Ok((
+            TStackedReadTransportFactory {
+                layers: layers.clone(),
+            },
+            TStackedWriteTransportFactory { layers },
+        ))
+    }
+
+    fn push<R, W>(
+        mut self,
+        kind: LayerKind,
+        name: &'static str,
+        read: R,
+        write: W,
+    ) -> TTransportStackBuilder
+    where
+        R: TReadTransportFactory + Send + Sync + 'static,
+        W: TWriteTransportFactory + Send + Sync + 'static,
+    {
+        self.layers.push(Layer {
+            kind,
+            name,
+            read: Arc::new(read),
+            write: Arc::new(write),
+        });
+        self
+    }
+
+    fn count(&self, kind: LayerKind) -> usize {
+        self.layers.iter().filter(|l| l.kind == kind).count()
+    }
+}
+
+fn invalid_stack(message: String) -> crate::Error {
+    crate::Error::Transport(crate::TransportError::new(
+        crate::TransportErrorKind::Unknown,
+        format!("Invalid transport stack: {}", message),
+    ))
+}
+
+/// Read transport factory built by `TTransportStackBuilder`.
+///
+/// Clones share the same layers.
+#[derive(Clone, Debug)]
+pub struct TStackedReadTransportFactory {
+    layers: Arc<[Layer]>,
+}
+
+impl TReadTransportFactory for TStackedReadTransportFactory {
+    /// Create a transport by applying every layer in turn to `channel`.
+    fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
+        let mut transport: Box<dyn TReadTransport + Send> = Box::new(channel);
+        for layer in self.layers.iter() {
+            transport = layer.read.create(Box::new(transport));
+        }
+        transport
+    }
+}
+
+/// Write transport factory built by `TTransportStackBuilder`.
+///
+/// Clones share the same layers.
+#[derive(Clone, Debug)]
+pub struct TStackedWriteTransportFactory {
+    layers: Arc<[Layer]>,
+}
+
+impl TWriteTransportFactory for TStackedWriteTransportFactory {
+    /// Create a transport by applying every layer in turn to `channel`.
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        let mut transport: Box<dyn TWriteTransport + Send> = Box::new(channel);
+        for layer in self.layers.iter() {
+            transport = layer.write.create(Box::new(transport));
+        }
+        transport
+    }
+}
+
+struct ObservedReadFactory(Arc<dyn TTransportObserver>);
+
+impl TReadTransportFactory for ObservedReadFactory {
+    fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
+        Box::new(ObservedRead {
+            channel,
+            observer: self.0.clone(),
+        })
+    }
+}
+
+struct ObservedRead {
+    channel: Box<dyn Read + Send>,
+    observer: Arc<dyn TTransportObserver>,
+}
+
+impl Read for ObservedRead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.channel.read(buf)?;
+        self.observer.on_bytes_read(n);
+        Ok(n)
+    }
+}
+
+struct ObservedWriteFactory(Arc<dyn TTransportObserver>);
+
+impl TWriteTransportFactory for ObservedWriteFactory {
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        Box::new(ObservedWrite {
+            channel,
+            observer: self.0.clone(),
+        })
+    }
+}
+
+struct ObservedWrite {
+    channel: Box<dyn Write + Send>,
+    observer: Arc<dyn TTransportObserver>,
+}
+
+impl Write for ObservedWrite {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.channel.write(buf)?;
+        self.observer.on_bytes_written(n);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let n = self.channel.write_vectored(bufs)?;
+        self.observer.on_bytes_written(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.channel.flush()?;
+        self.observer.on_flush();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{TBufferChannel, TTransportCounters};
+
+    fn message(err: crate::Error) -> String {
+        match err {
+            crate::Error::Transport(e) => e.message,
+            e => panic!("expected transport error, got {:?}", e),
+        }
+    }
+
+    fn round_trip(
+        (r_fact, w_fact): (TStackedReadTransportFactory, TStackedWriteTransportFactory),
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut wire = TBufferChannel::new();
+        let mut w_tran = w_fact.create(Box::new(wire.clone()));
+        w_tran.write_all(payload).unwrap();
+        w_tran.flush().unwrap();
+        drop(w_tran);
+
+        wire.copy_write_buffer_to_read_buffer();
+        let wire_bytes = wire.read_bytes();
+        let mut r_tran = r_fact.create(Box::new(wire));
+        let mut received = vec![0u8; payload.len()];
+        r_tran.read_exact(&mut received).unwrap();
+        assert_eq!(received, payload);
+        wire_bytes
+    }
+
+    #[test]
+    fn must_pass_bytes_through_empty_stack() {
+        let wire = round_trip(TTransportStackBuilder::new().build().unwrap(), b"plain");
+        assert_eq!(wire, b"plain");
+    }
+
+    #[test]
+    fn must_apply_layers_starting_from_the_channel() {
+        let counters = TTransportCounters::new();
+        let stack = TTransportStackBuilder::new()
+            .instrumented(Arc::new(counters.clone()))
+            .framed()
+            .buffered()
+            .build()
+            .unwrap();
+
+        let wire = round_trip(stack, b"hello");
+
+        // the instrumented layer sits below the framed layer, so it sees the
+        // frame header too
+        assert_eq!(wire, b"\x00\x00\x00\x05hello");
+        assert_eq!(counters.bytes_written(), 9);
+        assert_eq!(counters.bytes_read(), 9);
+        assert_eq!(counters.flushes(), 1);
+    }
+
+    #[test]
+    fn must_reject_more_than_one_framed_layer() {
+        let err = TTransportStackBuilder::new()
+            .framed()
+            .buffered()
+            .framed()
+            .build()
+            .unwrap_err();
+        assert!(message(err).contains("2 framed layers"));
+    }
+
+    #[cfg(all(feature = "zlib", feature = "zstd"))]
+    #[test]
+    fn must_reject_more_than_one_compression_layer() {
+        let err = TTransportStackBuilder::new()
+            .zlib()
+            .zstd()
+            .build()
+            .unwrap_err();
+        assert!(message(err).contains("zlib, zstd"));
+    }
+
+    #[test]
+    fn must_accept_custom_layers_without_validation() {
+        let stack = TTransportStackBuilder::new()
+            .framed()
+            .layer(
+                TFramedReadTransportFactory::new(),
+                TFramedWriteTransportFactory::new(),
+            )
+            .build()
+            .unwrap();
+
+        let wire = round_trip(stack, b"xy");
+        assert_eq!(wire, b"\x00\x00\x00\x06\x00\x00\x00\x02xy");
+    }
+}
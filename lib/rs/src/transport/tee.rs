@@ -0,0 +1,521 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+
+use super::{TReadTransport, TReadTransportFactory, TWriteTransport, TWriteTransportFactory};
+
+/// Creates the sink that captures the traffic of one connection.
+pub type TTeeSinkFactory = dyn Fn() -> io::Result<Box<dyn Write + Send>> + Send + Sync;
+
+/// Copies bytes into a sink until the limit is reached or the sink fails.
+///
+/// Capture must never break the connection it observes, so sink errors stop
+/// the capture instead of being returned to the caller.
+struct Tee<S> {
+    sink: S,
+    remaining: Option<u64>,
+    error: Option<io::Error>,
+}
+
+impl<S: Write> Tee<S> {
+    fn new(sink: S) -> Tee<S> {
+        Tee {
+            sink,
+            remaining: None,
+            error: None,
+        }
+    }
+
+    fn is_capturing(&self) -> bool {
+        self.error.is_none() && self.remaining != Some(0)
+    }
+
+    fn copy(&mut self, bytes: &[u8]) {
+        if !self.is_capturing() {
+            return;
+        }
+        let len = match self.remaining {
+            Some(remaining) => bytes
+                .len()
+                .min(usize::try_from(remaining).unwrap_or(usize::MAX)),
+            None => bytes.len(),
+        };
+        match self.sink.write_all(&bytes[..len]) {
+            Ok(()) => {
+                if let Some(ref mut remaining) = self.remaining {
+                    *remaining -= len as u64;
+                    if *remaining == 0 {
+                        self.flush();
+                    }
+                }
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.error.is_none() {
+            if let Err(e) = self.sink.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for Tee<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tee")
+            .field("remaining", &self.remaining)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+/// Transport that copies every byte read from a channel into a sink.
+///
+/// Use it to capture samples of real traffic, for example to replay them
+/// offline or to seed a fuzzing corpus. The bytes are captured exactly as
+/// they are read from the wrapped channel, so wrap the raw connection to
+/// capture what crosses the network.
+///
+/// Capture stops, without affecting reads, once `max_bytes` bytes have been
+/// copied or the sink returns an error. The error is available from
+/// `sink_error()`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::Read;
+/// use thrift::transport::{TTcpChannel, TTeeReadTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let capture = File::create("responses.bin").unwrap();
+/// let mut t = TTeeReadTransport::new(c, capture).with_max_bytes(1024 * 1024);
+///
+/// t.read(&mut vec![0u8; 1]).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TTeeReadTransport<C, S>
+where
+    C: Read,
+    S: Write,
+{
+    channel: C,
+    tee: Tee<S>,
+}
+
+impl<C, S> TTeeReadTransport<C, S>
+where
+    C: Read,
+    S: Write,
+{
+    /// Create a `TTeeReadTransport` that reads from `channel` and copies
+    /// everything it reads into `sink`.
+    pub fn new(channel: C, sink: S) -> TTeeReadTransport<C, S> {
+        TTeeReadTransport {
+            channel,
+            tee: Tee::new(sink),
+        }
+    }
+
+    /// Stop capturing after `max_bytes` bytes.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> TTeeReadTransport<C, S> {
+        self.tee.remaining = Some(max_bytes);
+        self
+    }
+
+    /// Whether bytes are still being copied into the sink.
+    pub fn is_capturing(&self) -> bool {
+        self.tee.is_capturing()
+    }
+
+    /// The error that stopped the capture, if any.
+    pub fn sink_error(&self) -> Option<&io::Error> {
+        self.tee.error.as_ref()
+    }
+
+    /// Return a reference to the sink.
+    pub fn sink(&self) -> &S {
+        &self.tee.sink
+    }
+
+    /// Return the wrapped channel and the sink.
+    pub fn into_inner(self) -> (C, S) {
+        (self.channel, self.tee.sink)
+    }
+}
+
+impl<C, S> Read for TTeeReadTransport<C, S>
+where
+    C: Read,
+    S: Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.channel.read(buf)?;
+        self.tee.copy(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Factory for creating instances of `TTeeReadTransport`.
+///
+/// A new sink is created for every connection. If creating the sink fails
+/// the connection is served without capture.
+pub struct TTeeReadTransportFactory {
+    make_sink: Box<TTeeSinkFactory>,
+    max_bytes: Option<u64>,
+    inner: Option<Box<dyn TReadTransportFactory + Send + Sync>>,
+}
+
+impl TTeeReadTransportFactory {
+    /// Create a factory whose transports capture into sinks created by
+    /// `make_sink`.
+    pub fn new<F>(make_sink: F) -> TTeeReadTransportFactory
+    where
+        F: Fn() -> io::Result<Box<dyn Write + Send>> + Send + Sync + 'static,
+    {
+        TTeeReadTransportFactory {
+            make_sink: Box::new(make_sink),
+            max_bytes: None,
+            inner: None,
+        }
+    }
+
+    /// Create a factory whose transports are created by `factory` over a
+    /// capturing channel, so that the bytes that actually cross the network
+    /// are captured.
+    pub fn with_transport_factory<F, T>(make_sink: F, factory: T) -> TTeeReadTransportFactory
+    where
+        F: Fn() -> io::Result<Box<dyn Write + Send>> + Send + Sync + 'static,
+        T: TReadTransportFactory + Send + Sync + 'static,
+    {
+        TTeeReadTransportFactory {
+            inner: Some(Box::new(factory)),
+            ..TTeeReadTransportFactory::new(make_sink)
+        }
+    }
+
+    /// Stop capturing a connection after `max_bytes` bytes.
+    pub fn with_max_bytes(self, max_bytes: u64) -> TTeeReadTransportFactory {
+        TTeeReadTransportFactory {
+            max_bytes: Some(max_bytes),
+            ..self
+        }
+    }
+}
+
+impl fmt::Debug for TTeeReadTransportFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TTeeReadTransportFactory")
+            .field("max_bytes", &self.max_bytes)
+            .field("layered", &self.inner.is_some())
+            .finish()
+    }
+}
+
+impl TReadTransportFactory for TTeeReadTransportFactory {
+    /// Create a `TTeeReadTransport`.
+    fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
+        let channel: Box<dyn TReadTransport + Send> = match (self.make_sink)() {
+            Ok(sink) => {
+                let mut tee = TTeeReadTransport::new(channel, sink);
+                tee.tee.remaining = self.max_bytes;
+                Box::new(tee)
+            }
+            Err(_) => Box::new(channel),
+        };
+        match self.inner {
+            Some(ref factory) => factory.create(Box::new(channel)),
+            None => channel,
+        }
+    }
+}
+
+/// Transport that copies every byte written to a channel into a sink.
+///
+/// Only bytes accepted by the wrapped channel are captured, and the sink is
+/// flushed whenever the transport is flushed, so a sink that records flushes
+/// sees message boundaries.
+///
+/// Capture stops, without affecting writes, once `max_bytes` bytes have been
+/// copied or the sink returns an error. The error is available from
+/// `sink_error()`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::Write;
+/// use thrift::transport::{TTcpChannel, TTeeWriteTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let capture = File::create("requests.bin").unwrap();
+/// let mut t = TTeeWriteTransport::new(c, capture);
+///
+/// t.write(&[0x00]).unwrap();
+/// t.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TTeeWriteTransport<C, S>
+where
+    C: Write,
+    S: Write,
+{
+    channel: C,
+    tee: Tee<S>,
+}
+
+impl<C, S> TTeeWriteTransport<C, S>
+where
+    C: Write,
+    S: Write,
+{
+    /// Create a `TTeeWriteTransport` that writes to `channel` and copies
+    /// everything it writes into `sink`.
+    pub fn new(channel: C, sink: S) -> TTeeWriteTransport<C, S> {
+        TTeeWriteTransport {
+            channel,
+            tee: Tee::new(sink),
+        }
+    }
+
+    /// Stop capturing after `max_bytes` bytes.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> TTeeWriteTransport<C, S> {
+        self.tee.remaining = Some(max_bytes);
+        self
+    }
+
+    /// Whether bytes are still being copied into the sink.
+    pub fn is_capturing(&self) -> bool {
+        self.tee.is_capturing()
+    }
+
+    /// The error that stopped the capture, if any.
+    pub fn sink_error(&self) -> Option<&io::Error> {
+        self.tee.error.as_ref()
+    }
+
+    /// Return a reference to the sink.
+    pub fn sink(&self) -> &S {
+        &self.tee.sink
+    }
+
+    /// Return the wrapped channel and the sink.
+    pub fn into_inner(self) -> (C, S) {
+        (self.channel, self.tee.sink)
+    }
+}
+
+impl<C, S> Write for TTeeWriteTransport<C, S>
+where
+    C: Write,
+    S: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.channel.write(buf)?;
+        self.tee.copy(&buf[..n]);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut remaining = self.channel.write_vectored(bufs)?;
+        let written = remaining;
+        for buf in bufs {
+            if remaining == 0 {
+                break;
+            }
+            let n = remaining.min(buf.len());
+            self.tee.copy(&buf[..n]);
+            remaining -= n;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.channel.flush()?;
+        self.tee.flush();
+        Ok(())
+    }
+}
+
+/// Factory for creating instances of `TTeeWriteTransport`.
+///
+/// A new sink is created for every connection. If creating the sink fails
+/// the connection is served without capture.
+pub struct TTeeWriteTransportFactory {
+    make_sink: Box<TTeeSinkFactory>,
+    max_bytes: Option<u64>,
+    inner: Option<Box<dyn TWriteTransportFactory + Send + Sync>>,
+}
+
+impl TTeeWriteTransportFactory {
+    /// Create a factory whose transports capture into sinks created by
+    /// `make_sink`.
+    pub fn new<F>(make_sink: F) -> TTeeWriteTransportFactory
+    where
+        F: Fn() -> io::Result<Box<dyn Write + Send>> + Send + Sync + 'static,
+    {
+        TTeeWriteTransportFactory {
+            make_sink: Box::new(make_sink),
+            max_bytes: None,
+            inner: None,
+        }
+    }
+
+    /// Create a factory whose transports are created by `factory` over a
+    /// capturing channel, so that the bytes that actually cross the network
+    /// are captured.
+    pub fn with_transport_factory<F, T>(make_sink: F, factory: T) -> TTeeWriteTransportFactory
+    where
+        F: Fn() -> io::Result<Box<dyn Write + Send>> + Send + Sync + 'static,
+        T: TWriteTransportFactory + Send + Sync + 'static,
+    {
+        TTeeWriteTransportFactory {
+            inner: Some(Box::new(factory)),
+            ..TTeeWriteTransportFactory::new(make_sink)
+        }
+    }
+
+    /// Stop capturing a connection after `max_bytes` bytes.
+    pub fn with_max_bytes(self, max_bytes: u64) -> TTeeWriteTransportFactory {
+        TTeeWriteTransportFactory {
+            max_bytes: Some(max_bytes),
+            ..self
+        }
+    }
+}
+
+impl fmt::Debug for TTeeWriteTransportFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TTeeWriteTransportFactory")
+            .field("max_bytes", &self.max_bytes)
+            .field("layered", &self.inner.is_some())
+            .finish()
+    }
+}
+
+impl TWriteTransportFactory for TTeeWriteTransportFactory {
+    /// Create a `TTeeWriteTransport`.
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        let channel: Box<dyn TWriteTransport + Send> = match (self.make_sink)() {
+            Ok(sink) => {
+                let mut tee = TTeeWriteTransport::new(channel, sink);
+                tee.tee.remaining = self.max_bytes;
+                Box::new(tee)
+            }
+            Err(_) => Box::new(channel),
+        };
+        match self.inner {
+            Some(ref factory) => factory.create(Box::new(channel)),
+            None => channel,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::transport::{TBufferChannel, TFramedWriteTransportFactory};
+
+    struct FailingSink;
+
+    impl Write for FailingSink {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn must_capture_bytes_read() {
+        let mut c = TBufferChannel::new();
+        c.set_readable_bytes(&[1, 2, 3, 4, 5]);
+        let mut t = TTeeReadTransport::new(c, Vec::new());
+
+        let mut buf = [0u8; 3];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(t.sink(), &vec![1, 2, 3]);
+
+        let mut rest = Vec::new();
+        t.read_to_end(&mut rest).unwrap();
+        assert_eq!(t.sink(), &vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn must_stop_capturing_at_limit() {
+        let mut c = TBufferChannel::new();
+        c.set_readable_bytes(&[1, 2, 3, 4, 5]);
+        let mut t = TTeeReadTransport::new(c, Vec::new()).with_max_bytes(2);
+
+        let mut buf = [0u8; 5];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+        assert_eq!(t.sink(), &vec![1, 2]);
+        assert!(!t.is_capturing());
+    }
+
+    #[test]
+    fn must_keep_writing_when_sink_fails() {
+        let c = TBufferChannel::new();
+        let mut t = TTeeWriteTransport::new(c, FailingSink);
+
+        t.write_all(&[1, 2]).unwrap();
+        t.flush().unwrap();
+        assert!(!t.is_capturing());
+        assert_eq!(
+            t.sink_error().map(|e| e.to_string()),
+            Some("disk full".to_owned())
+        );
+
+        let (c, _) = t.into_inner();
+        assert_eq!(c.write_bytes(), vec![1, 2]);
+    }
+
+    #[test]
+    fn must_capture_vectored_writes() {
+        let mut t = TTeeWriteTransport::new(TBufferChannel::new(), Vec::new());
+        TWriteTransport::write_all_vectored(&mut t, &[&[1, 2], &[3]]).unwrap();
+        assert_eq!(t.sink(), &vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn must_capture_wire_bytes_below_layered_factory() {
+        let captured = TBufferChannel::new();
+        let sink = captured.clone();
+        let factory = TTeeWriteTransportFactory::with_transport_factory(
+            move || Ok(Box::new(sink.clone()) as Box<dyn Write + Send>),
+            TFramedWriteTransportFactory::new(),
+        );
+
+        let mut t = factory.create(Box::new(TBufferChannel::new()));
+        t.write_all(&[0xab]).unwrap();
+        t.flush().unwrap();
+
+        assert_eq!(captured.write_bytes(), vec![0x00, 0x00, 0x00, 0x01, 0xab]);
+    }
+}
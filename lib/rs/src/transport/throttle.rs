@@ -0,0 +1,563 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{TReadTransport, TReadTransportFactory, TWriteTransport, TWriteTransportFactory};
+
+/// Shared byte and message budgets for throttled transports.
+///
+/// Each budget is a token bucket: it holds up to `burst` tokens, refills at
+/// the configured rate per second, and every byte or message consumes one
+/// token. A transport that runs out of tokens sleeps until the bucket has
+/// refilled. Budgets that are not configured are unlimited. The burst
+/// defaults to one second's worth of tokens.
+///
+/// Clones of a `TRateLimiter` share the same buckets, so a single limiter
+/// can cap the combined traffic of several transports, connections or
+/// threads.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::transport::{
+///     TBufferedWriteTransportFactory, TRateLimiter, TThrottledWriteTransportFactory,
+/// };
+///
+/// // at most 1MiB/s and 50 requests per second across all connections
+/// let limiter = TRateLimiter::new()
+///     .with_bytes_per_second(1024 * 1024)
+///     .with_messages_per_second(50);
+///
+/// let factory = TThrottledWriteTransportFactory::with_transport_factory(
+///     limiter,
+///     TBufferedWriteTransportFactory::new(),
+/// );
+/// ```
+#[derive(Clone, Default)]
+pub struct TRateLimiter {
+    bytes: Option<Arc<Mutex<TokenBucket>>>,
+    messages: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl TRateLimiter {
+    /// Create a `TRateLimiter` without any limits.
+    pub fn new() -> TRateLimiter {
+        TRateLimiter::default()
+    }
+
+    /// Limit throughput to `rate` bytes per second.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is zero.
+    pub fn with_bytes_per_second(self, rate: u64) -> TRateLimiter {
+        TRateLimiter {
+            bytes: Some(Arc::new(Mutex::new(TokenBucket::new(rate, rate)))),
+            ..self
+        }
+    }
+
+    /// Allow bursts of up to `burst` bytes. Has no effect unless a byte rate
+    /// has been set.
+    pub fn with_byte_burst(self, burst: u64) -> TRateLimiter {
+        TRateLimiter {
+            bytes: with_burst(self.bytes, burst),
+            ..self
+        }
+    }
+
+    /// Limit throughput to `rate` messages per second.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is zero.
+    pub fn with_messages_per_second(self, rate: u64) -> TRateLimiter {
+        TRateLimiter {
+            messages: Some(Arc::new(Mutex::new(TokenBucket::new(rate, rate)))),
+            ..self
+        }
+    }
+
+    /// Allow bursts of up to `burst` messages. Has no effect unless a
+    /// message rate has been set.
+    pub fn with_message_burst(self, burst: u64) -> TRateLimiter {
+        TRateLimiter {
+            messages: with_burst(self.messages, burst),
+            ..self
+        }
+    }
+
+    /// Configured byte rate per second, if any.
+    pub fn bytes_per_second(&self) -> Option<u64> {
+        self.bytes.as_ref().map(|b| lock_bucket(b).rate)
+    }
+
+    /// Configured message rate per second, if any.
+    pub fn messages_per_second(&self) -> Option<u64> {
+        self.messages.as_ref().map(|b| lock_bucket(b).rate)
+    }
+
+    /// Largest number of bytes worth transferring in one call: larger
+    /// transfers are split so that they are spread over time.
+    fn max_chunk(&self, len: usize) -> usize {
+        match self.bytes {
+            Some(ref bucket) => {
+                let burst = lock_bucket(bucket).burst;
+                len.min(usize::try_from(burst).unwrap_or(usize::MAX).max(1))
+            }
+            None => len,
+        }
+    }
+
+    fn consume_bytes(&self, count: usize) {
+        if let Some(ref bucket) = self.bytes {
+            consume(bucket, count as u64);
+        }
+    }
+
+    fn consume_message(&self) {
+        if let Some(ref bucket) = self.messages {
+            consume(bucket, 1);
+        }
+    }
+}
+
+impl fmt::Debug for TRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TRateLimiter")
+            .field("bytes_per_second", &self.bytes_per_second())
+            .field("messages_per_second", &self.messages_per_second())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: u64,
+    burst: u64,
+    /// May be negative: a transfer larger than the available tokens is let
+    /// through and the debt is paid by waiting.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, burst: u64) -> TokenBucket {
+        assert!(rate > 0, "rate limit must be positive");
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take `count` tokens and return how long to wait before proceeding.
+    fn take(&mut self, count: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.refilled_at = now;
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.burst as f64);
+        self.tokens -= count as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+fn with_burst(
+    bucket: Option<Arc<Mutex<TokenBucket>>>,
+    burst: u64,
+) -> Option<Arc<Mutex<TokenBucket>>> {
+    bucket.map(|b| {
+        let rate = lock_bucket(&b).rate;
+        Arc::new(Mutex::new(TokenBucket::new(rate, burst)))
+    })
+}
+
+fn lock_bucket(bucket: &Mutex<TokenBucket>) -> std::sync::MutexGuard<'_, TokenBucket> {
+    // a bucket is always left consistent, so a poisoned lock is still usable
+    bucket.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn consume(bucket: &Mutex<TokenBucket>, count: u64) {
+    // sleep without holding the lock; later callers see the debt and wait
+    // their turn behind this one
+    let wait = lock_bucket(bucket).take(count, Instant::now());
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+/// Transport that limits the rate at which bytes are read from a channel.
+///
+/// Reads are split into chunks no larger than the byte burst and each chunk
+/// is charged to the byte budget of the [`TRateLimiter`]. Since the end of an
+/// incoming message cannot be recognized at this layer, only the byte budget
+/// applies to reads.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::Read;
+/// use thrift::transport::{TRateLimiter, TTcpChannel, TThrottledReadTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let limiter = TRateLimiter::new().with_bytes_per_second(64 * 1024);
+/// let mut t = TThrottledReadTransport::new(c, limiter);
+///
+/// t.read(&mut vec![0u8; 1]).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TThrottledReadTransport<C>
+where
+    C: Read,
+{
+    channel: C,
+    limiter: TRateLimiter,
+}
+
+impl<C> TThrottledReadTransport<C>
+where
+    C: Read,
+{
+    /// Create a `TThrottledReadTransport` that reads from `channel` within
+    /// the budget of `limiter`.
+    pub fn new(channel: C, limiter: TRateLimiter) -> TThrottledReadTransport<C> {
+        TThrottledReadTransport { channel, limiter }
+    }
+}
+
+impl<C> Read for TThrottledReadTransport<C>
+where
+    C: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.limiter.max_chunk(buf.len());
+        let read = self.channel.read(&mut buf[..len])?;
+        self.limiter.consume_bytes(read);
+        Ok(read)
+    }
+}
+
+/// Factory for creating instances of `TThrottledReadTransport`.
+///
+/// All transports created by one factory share its `TRateLimiter`, so the
+/// budget applies to the combined traffic of every connection.
+pub struct TThrottledReadTransportFactory {
+    limiter: TRateLimiter,
+    inner: Option<Box<dyn TReadTransportFactory + Send + Sync>>,
+}
+
+impl TThrottledReadTransportFactory {
+    pub fn new(limiter: TRateLimiter) -> TThrottledReadTransportFactory {
+        TThrottledReadTransportFactory {
+            limiter,
+            inner: None,
+        }
+    }
+
+    /// Create a factory whose transports are created by `factory` over a
+    /// throttled channel, so that the budget applies to the bytes that
+    /// actually cross the network.
+    pub fn with_transport_factory<F>(
+        limiter: TRateLimiter,
+        factory: F,
+    ) -> TThrottledReadTransportFactory
+    where
+        F: TReadTransportFactory + Send + Sync + 'static,
+    {
+        TThrottledReadTransportFactory {
+            limiter,
+            inner: Some(Box::new(factory)),
+        }
+    }
+}
+
+impl fmt::Debug for TThrottledReadTransportFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TThrottledReadTransportFactory")
+            .field("limiter", &self.limiter)
+            .field("layered", &self.inner.is_some())
+            .finish()
+    }
+}
+
+impl TReadTransportFactory for TThrottledReadTransportFactory {
+    /// Create a `TThrottledReadTransport`.
+    fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
+        let throttled = TThrottledReadTransport::new(channel, self.limiter.clone());
+        match self.inner {
+            Some(ref factory) => factory.create(Box::new(throttled)),
+            None => Box::new(throttled),
+        }
+    }
+}
+
+/// Transport that limits the rate at which bytes and messages are written to
+/// a channel.
+///
+/// Writes are split into chunks no larger than the byte burst and each chunk
+/// is charged to the byte budget of the [`TRateLimiter`]. The first write
+/// after a flush starts a new message and is charged to the message budget
+/// before any of its bytes are sent.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::Write;
+/// use thrift::transport::{TRateLimiter, TTcpChannel, TThrottledWriteTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let limiter = TRateLimiter::new().with_messages_per_second(10);
+/// let mut t = TThrottledWriteTransport::new(c, limiter);
+///
+/// t.write(&[0x00]).unwrap();
+/// t.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TThrottledWriteTransport<C>
+where
+    C: Write,
+{
+    channel: C,
+    limiter: TRateLimiter,
+    in_message: bool,
+}
+
+impl<C> TThrottledWriteTransport<C>
+where
+    C: Write,
+{
+    /// Create a `TThrottledWriteTransport` that writes to `channel` within
+    /// the budget of `limiter`.
+    pub fn new(channel: C, limiter: TRateLimiter) -> TThrottledWriteTransport<C> {
+        TThrottledWriteTransport {
+            channel,
+            limiter,
+            in_message: false,
+        }
+    }
+}
+
+impl<C> Write for TThrottledWriteTransport<C>
+where
+    C: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.in_message {
+            self.limiter.consume_message();
+            self.in_message = true;
+        }
+        let len = self.limiter.max_chunk(buf.len());
+        let written = self.channel.write(&buf[..len])?;
+        self.limiter.consume_bytes(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.in_message = false;
+        self.channel.flush()
+    }
+}
+
+/// Factory for creating instances of `TThrottledWriteTransport`.
+///
+/// All transports created by one factory share its `TRateLimiter`, so the
+/// budget applies to the combined traffic of every connection.
+pub struct TThrottledWriteTransportFactory {
+    limiter: TRateLimiter,
+    inner: Option<Box<dyn TWriteTransportFactory + Send + Sync>>,
+}
+
+impl TThrottledWriteTransportFactory {
+    pub fn new(limiter: TRateLimiter) -> TThrottledWriteTransportFactory {
+        TThrottledWriteTransportFactory {
+            limiter,
+            inner: None,
+        }
+    }
+
+    /// Create a factory whose transports are created by `factory` over a
+    /// throttled channel, so that the budget applies to the bytes that
+    /// actually cross the network.
+    pub fn with_transport_factory<F>(
+        limiter: TRateLimiter,
+        factory: F,
+    ) -> TThrottledWriteTransportFactory
+    where
+        F: TWriteTransportFactory + Send + Sync + 'static,
+    {
+        TThrottledWriteTransportFactory {
+            limiter,
+            inner: Some(Box::new(factory)),
+        }
+    }
+}
+
+impl fmt::Debug for TThrottledWriteTransportFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TThrottledWriteTransportFactory")
+            .field("limiter", &self.limiter)
+            .field("layered", &self.inner.is_some())
+            .finish()
+    }
+}
+
+impl TWriteTransportFactory for TThrottledWriteTransportFactory {
+    /// Create a `TThrottledWriteTransport`.
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        let throttled = TThrottledWriteTransport::new(channel, self.limiter.clone());
+        match self.inner {
+            Some(ref factory) => factory.create(Box::new(throttled)),
+            None => Box::new(throttled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::transport::{TBufferChannel, TBufferedWriteTransportFactory};
+
+    #[test]
+    fn must_refill_bucket_at_configured_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(100, 10);
+        bucket.refilled_at = start;
+
+        // the burst is available immediately
+        assert_eq!(bucket.take(10, start), Duration::ZERO);
+        // anything beyond it is paid for by waiting
+        assert_eq!(bucket.take(5, start), Duration::from_millis(50));
+        // 100ms later the debt of 5 is repaid and 5 tokens are back
+        assert_eq!(
+            bucket.take(5, start + Duration::from_millis(100)),
+            Duration::ZERO
+        );
+        // tokens never accumulate beyond the burst
+        assert_eq!(
+            bucket.take(15, start + Duration::from_secs(10)),
+            Duration::from_millis(50)
+        );
+    }
+
+    #[test]
+    fn must_split_transfers_into_burst_sized_chunks() {
+        let limiter = TRateLimiter::new()
+            .with_bytes_per_second(1_000_000)
+            .with_byte_burst(4);
+        let mut t = TThrottledWriteTransport::new(TBufferChannel::with_capacity(0, 16), limiter);
+
+        assert_eq!(t.write(&[1; 10]).unwrap(), 4);
+        t.write_all(&[2; 6]).unwrap();
+        assert_eq!(t.channel.write_bytes(), [1, 1, 1, 1, 2, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn must_limit_read_throughput() {
+        let limiter = TRateLimiter::new()
+            .with_bytes_per_second(1000)
+            .with_byte_burst(100);
+        let mut channel = TBufferChannel::with_capacity(300, 0);
+        channel.set_readable_bytes(&[7; 300]);
+        let mut t = TThrottledReadTransport::new(channel, limiter);
+
+        let start = Instant::now();
+        let mut buf = [0u8; 300];
+        t.read_exact(&mut buf).unwrap();
+
+        // 100 bytes of burst, then 200 bytes at 1000 bytes/s
+        assert!(start.elapsed() >= Duration::from_millis(180));
+        assert_eq!(buf, [7; 300]);
+    }
+
+    #[test]
+    fn must_limit_messages_shared_across_transports() {
+        let limiter = TRateLimiter::new()
+            .with_messages_per_second(20)
+            .with_message_burst(1);
+        let mut first =
+            TThrottledWriteTransport::new(TBufferChannel::with_capacity(0, 16), limiter.clone());
+        let mut second =
+            TThrottledWriteTransport::new(TBufferChannel::with_capacity(0, 16), limiter);
+
+        let start = Instant::now();
+        // a message is charged once, however many writes it takes
+        let send = |t: &mut TThrottledWriteTransport<TBufferChannel>| {
+            t.write_all(&[1]).unwrap();
+            t.write_all(&[2]).unwrap();
+            t.flush().unwrap();
+        };
+        send(&mut first);
+        send(&mut second);
+        send(&mut first);
+
+        // the burst covers the first message, the next two wait 50ms each
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert_eq!(first.channel.write_bytes(), [1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn must_not_limit_without_configured_rates() {
+        let limiter = TRateLimiter::new();
+        assert_eq!(limiter.bytes_per_second(), None);
+        assert_eq!(limiter.messages_per_second(), None);
+
+        let mut t = TThrottledWriteTransport::new(TBufferChannel::with_capacity(0, 4096), limiter);
+        assert_eq!(t.write(&[0; 4096]).unwrap(), 4096);
+    }
+
+    #[test]
+    fn must_layer_factory_transports_over_throttled_channel() {
+        let limiter = TRateLimiter::new().with_bytes_per_second(1_000_000);
+        let factory = TThrottledWriteTransportFactory::with_transport_factory(
+            limiter,
+            TBufferedWriteTransportFactory::new(),
+        );
+        let channel = TBufferChannel::with_capacity(0, 8);
+        let mut t = factory.create(Box::new(channel.clone()));
+
+        t.write_all(&[1, 2, 3]).unwrap();
+        assert!(channel.write_bytes().is_empty(), "buffered until flushed");
+        t.flush().unwrap();
+        assert_eq!(channel.write_bytes(), [1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "rate limit must be positive")]
+    fn must_reject_zero_rate() {
+        let _ = TRateLimiter::new().with_bytes_per_second(0);
+    }
+}
@@ -15,27 +15,138 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::io::{self, Read, Write};
+use std::io::{self, IoSlice, Read, Write};
 use std::net::{Shutdown, TcpStream, ToSocketAddrs};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use std::time::Duration;
 
-use rustls::pki_types::ServerName;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
 use rustls::{
-    ClientConfig, ClientConnection, ConnectionCommon, ServerConfig, ServerConnection, SideData,
-    StreamOwned,
+    ClientConfig, ClientConnection, ConnectionCommon, RootCertStore, ServerConfig,
+    ServerConnection, SideData, StreamOwned,
 };
 
-use super::{ReadHalf, TIoChannel, TSharedChannel, WriteHalf};
+use super::{ReadHalf, TIoChannel, TPeerIdentity, TProxy, TSharedChannel, TTcpChannel, WriteHalf};
 use crate::{new_transport_error, TransportErrorKind};
 
+/// The client TLS channel. An alias of [`TTlsClientChannel`].
+pub type TTlsChannel = TTlsClientChannel;
+
+/// Builder for a rustls [`ClientConfig`] that verifies servers against a
+/// custom root store.
+///
+/// This covers the common client setup - trust anchors, ALPN, SNI and an
+/// optional client certificate for mutual TLS - while
+/// leaving the choice of crypto provider and the source of the roots to the
+/// application. Anything more specialised can be configured on a
+/// `ClientConfig` built directly with rustls.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use rustls::pki_types::ServerName;
+/// use rustls::RootCertStore;
+/// use thrift::transport::{TTlsChannel, TTlsClientConfigBuilder};
+///
+/// # fn provider() -> rustls::crypto::CryptoProvider { unimplemented!() }
+/// # fn roots() -> RootCertStore { unimplemented!() }
+/// let config = TTlsClientConfigBuilder::new(Arc::new(provider()))
+///     .root_certificates(roots())
+///     .alpn_protocols(vec![b"thrift".to_vec()])
+///     .build()
+///     .unwrap();
+///
+/// let channel = TTlsChannel::connect(
+///     "localhost:9090",
+///     ServerName::try_from("localhost").unwrap(),
+///     config,
+/// )
+/// .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TTlsClientConfigBuilder {
+    provider: Arc<CryptoProvider>,
+    roots: RootCertStore,
+    alpn_protocols: Vec<Vec<u8>>,
+    enable_sni: bool,
+    client_identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+}
+
+impl TTlsClientConfigBuilder {
+    /// Start building a `ClientConfig` that uses `provider` and trusts no
+    /// roots.
+    pub fn new(provider: Arc<CryptoProvider>) -> TTlsClientConfigBuilder {
+        TTlsClientConfigBuilder {
+            provider,
+            roots: RootCertStore::empty(),
+            alpn_protocols: Vec::new(),
+            enable_sni: true,
+            client_identity: None,
+        }
+    }
+
+    /// Trust anchors used to verify the server certificate.
+    pub fn root_certificates(mut self, roots: RootCertStore) -> Self {
+        self.roots = roots;
+        self
+    }
+
+    /// ALPN protocols to offer, in order of preference.
+    pub fn alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+
+    /// Whether to send the server name in the TLS SNI extension. Enabled by
+    /// default; the name is still used to verify the server certificate when
+    /// disabled.
+    pub fn enable_sni(mut self, enable: bool) -> Self {
+        self.enable_sni = enable;
+        self
+    }
+
+    /// Certificate chain, end entity first, and private key presented to
+    /// servers that request client authentication.
+    pub fn client_identity(
+        mut self,
+        certificates: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Self {
+        self.client_identity = Some((certificates, key));
+        self
+    }
+
+    pub fn build(self) -> crate::Result<Arc<ClientConfig>> {
+        let builder = ClientConfig::builder_with_provider(self.provider)
+            .with_safe_default_protocol_versions()
+            .map_err(tls_config_error)?
+            .with_root_certificates(self.roots);
+        let mut config = match self.client_identity {
+            Some((certificates, key)) => builder
+                .with_client_auth_cert(certificates, key)
+                .map_err(tls_config_error)?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = self.alpn_protocols;
+        config.enable_sni = self.enable_sni;
+        Ok(Arc::new(config))
+    }
+}
+
 /// A blocking rustls client channel.
 ///
 /// The caller supplies a fully configured rustls [`ClientConfig`], including
 /// its crypto provider, server-certificate verifier, optional client identity,
 /// and protocol policy. The TLS handshake completes according to that
-/// configuration before [`connect`](Self::connect) returns.
+/// configuration before [`connect`](Self::connect) returns. The server name
+/// is sent via SNI unless the configuration disables it, and the ALPN
+/// protocols offered by the configuration are negotiated during the
+/// handshake.
+///
+/// Both halves returned by [`TIoChannel::split`] share the TLS session.
 #[derive(Clone, Debug)]
 pub struct TTlsClientChannel {
     inner: TSharedChannel<StreamOwned<ClientConnection, TcpStream>>,
@@ -54,6 +165,32 @@ impl TTlsClientChannel {
         Self::with_stream(stream, server_name, config)
     }
 
+    /// Connect to `target` (`host:port`) through `proxy` and complete a TLS
+    /// handshake for `server_name` over the tunnel.
+    pub fn connect_via_proxy(
+        proxy: &TProxy,
+        target: &str,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    ) -> crate::Result<Self> {
+        let mut channel = TTcpChannel::new();
+        channel.open_via_proxy(proxy, target)?;
+        Self::with_channel(channel, server_name, config)
+    }
+
+    /// Wrap an open `TTcpChannel` and complete a TLS handshake for
+    /// `server_name`.
+    ///
+    /// The socket options and timeouts configured on the channel stay in
+    /// effect for the TLS connection.
+    pub fn with_channel(
+        channel: TTcpChannel,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    ) -> crate::Result<Self> {
+        Self::with_stream(channel.into_stream()?, server_name, config)
+    }
+
     /// Wrap an already-connected TCP stream and complete a TLS handshake.
     pub fn with_stream(
         mut stream: TcpStream,
@@ -78,6 +215,16 @@ impl TTlsClientChannel {
         })
     }
 
+    /// Return the ALPN protocol agreed with the server, if any.
+    pub fn alpn_protocol(&self) -> crate::Result<Option<Vec<u8>>> {
+        alpn_protocol(&self.inner)
+    }
+
+    /// Return the verified certificate chain presented by the server.
+    pub fn peer_identity(&self) -> crate::Result<Option<TPeerIdentity>> {
+        peer_identity(&self.inner)
+    }
+
     /// Return the read timeout of the underlying TCP stream.
     pub fn read_timeout(&self) -> crate::Result<Option<Duration>> {
         read_timeout(&self.inner)
@@ -125,6 +272,10 @@ impl Write for TTlsClientChannel {
         self.inner.write(buffer)
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.inner.flush()
     }
@@ -183,6 +334,32 @@ impl TTlsServerChannel {
         Ok(())
     }
 
+    /// Return the server name the client sent via SNI, if any.
+    ///
+    /// Returns `None` until the handshake has received the client hello.
+    pub fn server_name(&self) -> crate::Result<Option<String>> {
+        Ok(self.inner.lock()?.conn.server_name().map(str::to_owned))
+    }
+
+    /// Return the ALPN protocol agreed with the client, if any.
+    pub fn alpn_protocol(&self) -> crate::Result<Option<Vec<u8>>> {
+        alpn_protocol(&self.inner)
+    }
+
+    /// Return the verified certificate chain presented by the client.
+    ///
+    /// Returns `None` until the handshake has completed, and when the
+    /// `ServerConfig` does not request client certificates or the client did
+    /// not send one.
+    pub fn peer_identity(&self) -> crate::Result<Option<TPeerIdentity>> {
+        let stream = self.inner.lock()?;
+        if stream.conn.is_handshaking() {
+            return Ok(None);
+        }
+        drop(stream);
+        peer_identity(&self.inner)
+    }
+
     /// Return the read timeout of the underlying TCP stream.
     pub fn read_timeout(&self) -> crate::Result<Option<Duration>> {
         read_timeout(&self.inner)
@@ -230,6 +407,10 @@ impl Write for TTlsServerChannel {
         self.inner.write(buffer)
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.inner.flush()
     }
@@ -260,6 +441,35 @@ fn handshake_io_error(role: &str, error: io::Error) -> crate::Error {
     }
 }
 
+fn tls_config_error(error: rustls::Error) -> crate::Error {
+    new_transport_error(
+        TransportErrorKind::Unknown,
+        format!("invalid TLS configuration: {error}"),
+    )
+}
+
+fn alpn_protocol<C, S>(
+    channel: &TSharedChannel<StreamOwned<C, TcpStream>>,
+) -> crate::Result<Option<Vec<u8>>>
+where
+    C: Deref<Target = ConnectionCommon<S>>,
+{
+    Ok(channel.lock()?.conn.alpn_protocol().map(|p| p.to_vec()))
+}
+
+fn peer_identity<C, S>(
+    channel: &TSharedChannel<StreamOwned<C, TcpStream>>,
+) -> crate::Result<Option<TPeerIdentity>>
+where
+    C: Deref<Target = ConnectionCommon<S>>,
+{
+    Ok(channel
+        .lock()?
+        .conn
+        .peer_certificates()
+        .and_then(|chain| TPeerIdentity::new(chain.iter().map(|c| c.to_vec()).collect())))
+}
+
 fn read_timeout<C>(
     channel: &TSharedChannel<StreamOwned<C, TcpStream>>,
 ) -> crate::Result<Option<Duration>> {
@@ -0,0 +1,377 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use native_tls::{HandshakeError, TlsAcceptor, TlsConnector, TlsStream};
+
+use super::{ReadHalf, TIoChannel, TPeerIdentity, TProxy, TSharedChannel, TTcpChannel, WriteHalf};
+use crate::{new_transport_error, TransportErrorKind};
+
+/// A blocking TLS client channel backed by the platform TLS library.
+///
+/// `TNativeTlsClientChannel` uses [`native_tls`], i.e. SChannel on Windows,
+/// Secure Transport on macOS and OpenSSL elsewhere. A `TlsConnector` created
+/// with `TlsConnector::new()` verifies servers against the platform
+/// certificate store, including any trust managed by the operating system or
+/// corporate policy, so no roots need to be bundled with the application.
+///
+/// The caller supplies a configured `TlsConnector`, which controls trust
+/// anchors, client identity, protocol versions and ALPN. The TLS handshake
+/// completes before [`connect`](Self::connect) returns. Both halves returned
+/// by [`TIoChannel::split`] share the TLS session.
+///
+/// # Examples
+///
+/// ```no_run
+/// use native_tls::TlsConnector;
+/// use thrift::transport::TNativeTlsClientChannel;
+///
+/// let connector = TlsConnector::new().unwrap();
+/// let channel =
+///     TNativeTlsClientChannel::connect("example.com:9090", "example.com", &connector).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TNativeTlsClientChannel {
+    inner: TSharedChannel<TlsStream<TcpStream>>,
+}
+
+impl TNativeTlsClientChannel {
+    /// Connect to `remote_address` and complete a TLS handshake for `domain`.
+    pub fn connect<A: ToSocketAddrs>(
+        remote_address: A,
+        domain: &str,
+        connector: &TlsConnector,
+    ) -> crate::Result<Self> {
+        let stream = TcpStream::connect(remote_address)?;
+        stream.set_nodelay(true)?;
+        Self::with_stream(stream, domain, connector)
+    }
+
+    /// Connect to `target` (`host:port`) through `proxy` and complete a TLS
+    /// handshake for `domain` over the tunnel.
+    pub fn connect_via_proxy(
+        proxy: &TProxy,
+        target: &str,
+        domain: &str,
+        connector: &TlsConnector,
+    ) -> crate::Result<Self> {
+        let mut channel = TTcpChannel::new();
+        channel.open_via_proxy(proxy, target)?;
+        Self::with_channel(channel, domain, connector)
+    }
+
+    /// Wrap an open `TTcpChannel` and complete a TLS handshake for `domain`.
+    ///
+    /// The socket options and timeouts configured on the channel stay in
+    /// effect for the TLS connection.
+    pub fn with_channel(
+        channel: TTcpChannel,
+        domain: &str,
+        connector: &TlsConnector,
+    ) -> crate::Result<Self> {
+        Self::with_stream(channel.into_stream()?, domain, connector)
+    }
+
+    /// Wrap an already-connected TCP stream and complete a TLS handshake.
+    pub fn with_stream(
+        stream: TcpStream,
+        domain: &str,
+        connector: &TlsConnector,
+    ) -> crate::Result<Self> {
+        let stream = connector
+            .connect(domain, stream)
+            .map_err(|error| handshake_error("client", error))?;
+
+        Ok(Self {
+            inner: TSharedChannel::new(stream),
+        })
+    }
+
+    /// Return the ALPN protocol agreed with the server, if any.
+    pub fn alpn_protocol(&self) -> crate::Result<Option<Vec<u8>>> {
+        alpn_protocol(&*self.inner.lock()?)
+    }
+
+    /// Return the verified certificate presented by the server.
+    ///
+    /// Platform TLS libraries only expose the end-entity certificate, so the
+    /// returned chain has a single entry.
+    pub fn peer_identity(&self) -> crate::Result<Option<TPeerIdentity>> {
+        peer_identity(&*self.inner.lock()?)
+    }
+
+    /// Return the read timeout of the underlying TCP stream.
+    pub fn read_timeout(&self) -> crate::Result<Option<Duration>> {
+        Ok(self.inner.lock()?.get_ref().read_timeout()?)
+    }
+
+    /// Return the write timeout of the underlying TCP stream.
+    pub fn write_timeout(&self) -> crate::Result<Option<Duration>> {
+        Ok(self.inner.lock()?.get_ref().write_timeout()?)
+    }
+
+    /// Set the read timeout of the underlying TCP stream.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.inner.lock()?.get_ref().set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Set the write timeout of the underlying TCP stream.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.inner.lock()?.get_ref().set_write_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Set the read and write timeouts of the underlying TCP stream.
+    pub fn set_timeouts(
+        &mut self,
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+    ) -> crate::Result<()> {
+        self.set_read_timeout(read_timeout)?;
+        self.set_write_timeout(write_timeout)
+    }
+
+    /// Send a TLS close notification and shut down the underlying TCP stream.
+    pub fn close(&mut self) -> crate::Result<()> {
+        close(&mut *self.inner.lock()?)
+    }
+}
+
+impl Read for TNativeTlsClientChannel {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buffer)
+    }
+}
+
+impl Write for TNativeTlsClientChannel {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.inner.write(buffer)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl TIoChannel for TNativeTlsClientChannel {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+/// A blocking TLS server channel backed by the platform TLS library.
+///
+/// A channel created with [`accept`](Self::accept) completes the handshake
+/// before it is returned. A channel created with
+/// [`with_stream`](Self::with_stream) defers it to the first read or write,
+/// or to an explicit call to [`handshake`](Self::handshake), so that it can
+/// be completed on the thread that serves the connection.
+///
+/// `native-tls` cannot request client certificates, so mutual TLS on the
+/// server side requires the rustls channels. Clients can still present a
+/// certificate by configuring an identity on their `TlsConnector`.
+#[derive(Clone, Debug)]
+pub struct TNativeTlsServerChannel {
+    inner: TSharedChannel<ServerStream>,
+}
+
+impl TNativeTlsServerChannel {
+    /// Complete a TLS handshake on an accepted TCP stream.
+    pub fn accept(stream: TcpStream, acceptor: &TlsAcceptor) -> crate::Result<Self> {
+        let stream = acceptor
+            .accept(stream)
+            .map_err(|error| handshake_error("server", error))?;
+
+        Ok(Self {
+            inner: TSharedChannel::new(ServerStream::Established(stream)),
+        })
+    }
+
+    /// Wrap an accepted TCP stream without starting the TLS handshake.
+    pub fn with_stream(stream: TcpStream, acceptor: Arc<TlsAcceptor>) -> Self {
+        Self {
+            inner: TSharedChannel::new(ServerStream::Pending(Some((stream, acceptor)))),
+        }
+    }
+
+    /// Complete the TLS handshake if it has not been completed yet.
+    pub fn handshake(&mut self) -> crate::Result<()> {
+        self.inner.lock()?.established().map(|_| ())
+    }
+
+    /// Return the ALPN protocol agreed with the client, if any.
+    pub fn alpn_protocol(&self) -> crate::Result<Option<Vec<u8>>> {
+        alpn_protocol(self.inner.lock()?.established()?)
+    }
+
+    /// Send a TLS close notification and shut down the underlying TCP stream.
+    pub fn close(&mut self) -> crate::Result<()> {
+        close(self.inner.lock()?.established()?)
+    }
+}
+
+/// Server side TLS stream whose handshake may not have happened yet.
+enum ServerStream {
+    Pending(Option<(TcpStream, Arc<TlsAcceptor>)>),
+    Established(TlsStream<TcpStream>),
+    Failed,
+}
+
+impl ServerStream {
+    fn established(&mut self) -> crate::Result<&mut TlsStream<TcpStream>> {
+        if let ServerStream::Pending(pending) = self {
+            let (stream, acceptor) = pending.take().expect("pending TLS stream");
+            *self = match acceptor.accept(stream) {
+                Ok(stream) => ServerStream::Established(stream),
+                Err(error) => {
+                    *self = ServerStream::Failed;
+                    return Err(handshake_error("server", error));
+                }
+            };
+        }
+        match self {
+            ServerStream::Established(stream) => Ok(stream),
+            _ => Err(new_transport_error(
+                TransportErrorKind::NotOpen,
+                "TLS server handshake failed earlier",
+            )),
+        }
+    }
+
+    fn established_io(&mut self) -> io::Result<&mut TlsStream<TcpStream>> {
+        self.established()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))
+    }
+}
+
+impl fmt::Debug for ServerStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerStream::Pending(_) => f.write_str("Pending"),
+            ServerStream::Established(stream) => {
+                f.debug_tuple("Established").field(stream).finish()
+            }
+            ServerStream::Failed => f.write_str("Failed"),
+        }
+    }
+}
+
+impl Read for ServerStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.established_io()?.read(buffer)
+    }
+}
+
+impl Write for ServerStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.established_io()?.write(buffer)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.established_io()?.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.established_io()?.flush()
+    }
+}
+
+impl Read for TNativeTlsServerChannel {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buffer)
+    }
+}
+
+impl Write for TNativeTlsServerChannel {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.inner.write(buffer)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl TIoChannel for TNativeTlsServerChannel {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+fn handshake_error(role: &str, error: HandshakeError<TcpStream>) -> crate::Error {
+    match error {
+        HandshakeError::WouldBlock(_) => io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!("TLS {role} handshake did not complete"),
+        )
+        .into(),
+        HandshakeError::Failure(error) => new_transport_error(
+            TransportErrorKind::Unknown,
+            format!("TLS {role} handshake failed: {error}"),
+        ),
+    }
+}
+
+fn alpn_protocol(stream: &TlsStream<TcpStream>) -> crate::Result<Option<Vec<u8>>> {
+    stream.negotiated_alpn().map_err(|error| {
+        new_transport_error(
+            TransportErrorKind::Unknown,
+            format!("cannot read negotiated ALPN protocol: {error}"),
+        )
+    })
+}
+
+fn peer_identity(stream: &TlsStream<TcpStream>) -> crate::Result<Option<TPeerIdentity>> {
+    let certificate = stream.peer_certificate().and_then(|c| match c {
+        Some(c) => c.to_der().map(Some),
+        None => Ok(None),
+    });
+    certificate
+        .map(|c| c.and_then(|c| TPeerIdentity::new(vec![c])))
+        .map_err(|error| {
+            new_transport_error(
+                TransportErrorKind::Unknown,
+                format!("cannot read peer certificate: {error}"),
+            )
+        })
+}
+
+fn close(stream: &mut TlsStream<TcpStream>) -> crate::Result<()> {
+    stream.shutdown()?;
+    stream.get_ref().shutdown(Shutdown::Both)?;
+    Ok(())
+}
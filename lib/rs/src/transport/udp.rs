@@ -0,0 +1,291 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::io;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use super::{ReadHalf, TIoChannel, WriteHalf};
+use crate::{new_transport_error, TransportErrorKind};
+
+/// Datagram channel over a connected UDP socket.
+///
+/// Bytes written to a `TUdpChannel` are buffered until `flush()` is called,
+/// at which point they are sent to the remote peer as a **single** UDP
+/// datagram. A write that would grow the pending datagram beyond the
+/// configured maximum size fails with an `InvalidInput` error and discards
+/// the pending bytes, so a message is either sent whole or not at all.
+///
+/// Reads receive one datagram at a time and serve its bytes until it is
+/// exhausted, then block waiting for the next one.
+///
+/// UDP makes no delivery or ordering guarantees. This channel is intended
+/// for high-volume `oneway` calls, such as telemetry, where the cost of TCP
+/// connection setup is prohibitive and an occasional lost message is
+/// acceptable. Use an unframed write transport (or `TBufferedWriteTransport`
+/// with a capacity of at least the maximum datagram size) so that exactly one
+/// message is flushed per datagram.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TCompactOutputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol};
+/// use thrift::transport::TUdpChannel;
+///
+/// let channel = TUdpChannel::connect("127.0.0.1:9090").unwrap();
+/// let mut o_prot = TCompactOutputProtocol::new(channel);
+///
+/// o_prot
+///     .write_message_begin(&TMessageIdentifier::new("record", TMessageType::OneWay, 1))
+///     .unwrap();
+/// // ...
+/// o_prot.write_message_end().unwrap();
+/// o_prot.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TUdpChannel {
+    socket: UdpSocket,
+    max_datagram_size: usize,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Vec<u8>,
+}
+
+impl TUdpChannel {
+    /// Largest UDP payload that fits in an IPv4 datagram.
+    pub const DEFAULT_MAX_DATAGRAM_SIZE: usize = 65_507;
+
+    /// Create a `TUdpChannel` that sends to and receives from
+    /// `remote_address`.
+    ///
+    /// The socket is bound to an ephemeral port on the unspecified address of
+    /// the same family as the remote address.
+    pub fn connect<A: ToSocketAddrs>(remote_address: A) -> crate::Result<TUdpChannel> {
+        let addrs: Vec<SocketAddr> = remote_address.to_socket_addrs()?.collect();
+        let remote = addrs.first().ok_or_else(|| {
+            new_transport_error(
+                TransportErrorKind::NotOpen,
+                "remote address did not resolve to any socket address",
+            )
+        })?;
+        let local: SocketAddr = if remote.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(&addrs[..])?;
+        Ok(TUdpChannel::with_socket(socket))
+    }
+
+    /// Create a `TUdpChannel` that wraps an existing socket.
+    ///
+    /// `socket` must already be connected to the remote peer.
+    pub fn with_socket(socket: UdpSocket) -> TUdpChannel {
+        TUdpChannel {
+            socket,
+            max_datagram_size: TUdpChannel::DEFAULT_MAX_DATAGRAM_SIZE,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        }
+    }
+
+    /// Maximum number of bytes sent or received in one datagram.
+    pub fn max_datagram_size(&self) -> usize {
+        self.max_datagram_size
+    }
+
+    /// Set the maximum number of bytes sent or received in one datagram.
+    ///
+    /// Defaults to `DEFAULT_MAX_DATAGRAM_SIZE`. Lower it to stay below the path
+    /// MTU and avoid IP fragmentation. Received datagrams larger than this
+    /// are truncated.
+    pub fn set_max_datagram_size(&mut self, size: usize) {
+        self.max_datagram_size = size;
+    }
+
+    /// Set the read timeout on the underlying socket.
+    ///
+    /// `None` blocks indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.socket.set_read_timeout(timeout).map_err(From::from)
+    }
+
+    /// Return a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    fn try_clone(&self) -> io::Result<TUdpChannel> {
+        Ok(TUdpChannel {
+            socket: self.socket.try_clone()?,
+            max_datagram_size: self.max_datagram_size,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        })
+    }
+}
+
+impl TIoChannel for TUdpChannel {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        let write = self.try_clone()?;
+        Ok((ReadHalf::new(self), WriteHalf::new(write)))
+    }
+}
+
+impl Read for TUdpChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.read_pos == self.read_buf.len() {
+            self.read_buf.resize(self.max_datagram_size, 0);
+            let len = self.socket.recv(&mut self.read_buf)?;
+            self.read_buf.truncate(len);
+            self.read_pos = 0;
+        }
+
+        let available = &self.read_buf[self.read_pos..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+}
+
+impl Write for TUdpChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.write_buf.len() + buf.len() > self.max_datagram_size {
+            let pending = self.write_buf.len() + buf.len();
+            self.write_buf.clear();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "datagram size {} exceeds maximum {}",
+                    pending, self.max_datagram_size
+                ),
+            ));
+        }
+
+        self.write_buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.write_buf.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.socket.send(&self.write_buf);
+        let len = self.write_buf.len();
+        self.write_buf.clear();
+        match result? {
+            n if n == len => Ok(()),
+            n => Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("sent {} of {} datagram bytes", n, len),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (TUdpChannel, TUdpChannel) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        a.connect(b.local_addr().unwrap()).unwrap();
+        b.connect(a.local_addr().unwrap()).unwrap();
+        let mut a = TUdpChannel::with_socket(a);
+        let mut b = TUdpChannel::with_socket(b);
+        a.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        b.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn must_send_each_flush_as_one_datagram() {
+        let (mut a, b) = pair();
+
+        a.write_all(b"hello ").unwrap();
+        a.write_all(b"world").unwrap();
+        a.flush().unwrap();
+        a.write_all(b"again").unwrap();
+        a.flush().unwrap();
+
+        let mut buf = [0u8; 64];
+        let n = b.get_ref().recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello world");
+        let n = b.get_ref().recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"again");
+    }
+
+    #[test]
+    fn must_serve_reads_from_received_datagram() {
+        let (a, mut b) = pair();
+
+        a.get_ref().send(b"abcdef").unwrap();
+        a.get_ref().send(b"gh").unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(b.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"abcd");
+        assert_eq!(b.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ef");
+        assert_eq!(b.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"gh");
+    }
+
+    #[test]
+    fn must_reject_oversized_datagram_and_discard_pending_bytes() {
+        let (mut a, mut b) = pair();
+        a.set_max_datagram_size(8);
+
+        a.write_all(b"12345").unwrap();
+        let err = a.write_all(b"6789").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        a.write_all(b"ok").unwrap();
+        a.flush().unwrap();
+
+        let mut buf = [0u8; 16];
+        let n = b.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ok");
+    }
+
+    #[test]
+    fn must_split_into_independent_halves() {
+        let (a, b) = pair();
+        let (_, mut a_write) = a.split().unwrap();
+        let (mut b_read, _) = b.split().unwrap();
+
+        a_write.write_all(b"split").unwrap();
+        a_write.flush().unwrap();
+
+        let mut buf = [0u8; 16];
+        let n = b_read.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"split");
+    }
+}
@@ -0,0 +1,461 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use io_uring::{opcode, types, IoUring};
+
+use super::{ReadHalf, TIoChannel, WriteHalf};
+
+/// Submission queue entries per ring. At most one send and one receive are
+/// outstanding at any time.
+const RING_ENTRIES: u32 = 8;
+
+const SEND: u64 = 1;
+const RECV: u64 = 2;
+
+/// TCP channel that performs its I/O through a Linux `io_uring`.
+///
+/// A `TUringChannel` is a drop-in replacement for `TTcpChannel` that is
+/// tuned for small request/response exchanges. Writes are buffered in the
+/// channel. By default `flush()` only queues the buffered bytes for sending,
+/// and the send is submitted to the kernel together with the receive issued
+/// by the next `read()`. A client call or a server reply plus the next
+/// request therefore costs a single `io_uring_enter` system call instead of
+/// a `write` and a `read`. Reads receive up to `DEFAULT_READ_CAPACITY` bytes
+/// at a time, so small messages need one receive however they are decoded.
+///
+/// Because a flushed message is only sent once the channel is read from,
+/// call `submit()` (or turn deferred flushes off with
+/// `set_deferred_flush(false)`) after a `oneway` call that is not followed
+/// by a read. Any queued message is also sent when the channel is dropped.
+///
+/// The halves returned by `split()` share one ring and must be used from
+/// one thread at a time, as the synchronous clients and `TServer` do. The
+/// channel has no read or write timeouts.
+///
+/// Available on Linux through the optional `io-uring` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::{Read, Write};
+/// use thrift::transport::TUringChannel;
+///
+/// let mut c = TUringChannel::connect("localhost:9090").unwrap();
+///
+/// c.write_all(&[0x00]).unwrap();
+/// c.flush().unwrap(); // queued
+///
+/// let mut buf = [0u8; 1];
+/// c.read_exact(&mut buf).unwrap(); // sends the queued bytes, then reads
+/// ```
+#[derive(Clone)]
+pub struct TUringChannel {
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl TUringChannel {
+    /// Number of bytes requested from the kernel by each receive.
+    pub const DEFAULT_READ_CAPACITY: usize = 16 * 1024;
+
+    /// Connect to `remote_address` and create a `TUringChannel` for the
+    /// connection. `TCP_NODELAY` is set on the socket.
+    pub fn connect<A: ToSocketAddrs>(remote_address: A) -> crate::Result<TUringChannel> {
+        let stream = TcpStream::connect(remote_address)?;
+        stream.set_nodelay(true)?;
+        TUringChannel::with_stream(stream)
+    }
+
+    /// Create a `TUringChannel` that wraps an open `TcpStream`.
+    ///
+    /// Fails if an `io_uring` instance cannot be created, for example
+    /// because the kernel does not support it or it is disabled.
+    pub fn with_stream(stream: TcpStream) -> crate::Result<TUringChannel> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+        Ok(TUringChannel {
+            ring: Arc::new(Mutex::new(Ring {
+                ring,
+                stream,
+                write_buf: Vec::new(),
+                send_buf: Vec::new(),
+                send_pos: 0,
+                sending: false,
+                receiving: false,
+                broken: false,
+                read_buf: vec![0; TUringChannel::DEFAULT_READ_CAPACITY].into_boxed_slice(),
+                read_pos: 0,
+                read_cap: 0,
+                deferred_flush: true,
+            })),
+        })
+    }
+
+    /// Return `true` if `flush()` queues the buffered bytes to be sent
+    /// with the next read instead of sending them immediately.
+    pub fn deferred_flush(&self) -> bool {
+        self.lock().deferred_flush
+    }
+
+    /// Set whether `flush()` defers sending until the next read.
+    ///
+    /// Defaults to `true`.
+    pub fn set_deferred_flush(&mut self, deferred: bool) {
+        self.lock().deferred_flush = deferred;
+    }
+
+    /// Send any queued bytes now and wait until the kernel has accepted
+    /// them.
+    pub fn submit(&mut self) -> io::Result<()> {
+        let mut ring = self.lock();
+        ring.drive(false).map(|_| ())
+    }
+
+    /// Shut down the read, write, or both halves of the connection.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.lock().stream.shutdown(how)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for TUringChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ring = self.lock();
+        f.debug_struct("TUringChannel")
+            .field("stream", &ring.stream)
+            .field("deferred_flush", &ring.deferred_flush)
+            .finish()
+    }
+}
+
+impl TIoChannel for TUringChannel {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        let write = self.clone();
+        Ok((ReadHalf::new(self), WriteHalf::new(write)))
+    }
+}
+
+impl Read for TUringChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl Write for TUringChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut ring = self.lock();
+        ring.check_usable()?;
+        ring.write_buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+struct Ring {
+    ring: IoUring,
+    stream: TcpStream,
+    // bytes written since the last flush
+    write_buf: Vec<u8>,
+    // bytes handed to the kernel; must not be touched while `sending`
+    send_buf: Vec<u8>,
+    send_pos: usize,
+    sending: bool,
+    // must not be touched while `receiving`
+    read_buf: Box<[u8]>,
+    receiving: bool,
+    read_pos: usize,
+    read_cap: usize,
+    // an operation may still be in flight after a failed submission
+    broken: bool,
+    deferred_flush: bool,
+}
+
+impl Ring {
+    fn check_usable(&self) -> io::Result<()> {
+        if self.broken {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "io_uring submission failed; channel is no longer usable",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_usable()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.read_pos == self.read_cap {
+            let entry = opcode::Recv::new(
+                types::Fd(self.stream.as_raw_fd()),
+                self.read_buf.as_mut_ptr(),
+                self.read_buf.len() as u32,
+            )
+            .build()
+            .user_data(RECV);
+            self.push(&entry)?;
+            self.receiving = true;
+
+            let received = self.drive(true)?.unwrap_or(0);
+            self.read_pos = 0;
+            self.read_cap = received;
+        }
+
+        let n = (self.read_cap - self.read_pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.check_usable()?;
+        if self.write_buf.is_empty() {
+            return Ok(());
+        }
+
+        // wait for the previous message before handing over the next one
+        if self.sending {
+            self.drive(false)?;
+        }
+
+        mem::swap(&mut self.write_buf, &mut self.send_buf);
+        self.send_pos = 0;
+        self.queue_send()?;
+
+        if self.deferred_flush {
+            Ok(())
+        } else {
+            self.drive(false).map(|_| ())
+        }
+    }
+
+    fn queue_send(&mut self) -> io::Result<()> {
+        let remaining = &self.send_buf[self.send_pos..];
+        let entry = opcode::Send::new(
+            types::Fd(self.stream.as_raw_fd()),
+            remaining.as_ptr(),
+            remaining.len() as u32,
+        )
+        .build()
+        .user_data(SEND);
+        self.push(&entry)?;
+        self.sending = true;
+        Ok(())
+    }
+
+    fn push(&mut self, entry: &io_uring::squeue::Entry) -> io::Result<()> {
+        // safety: the buffers referenced by the entries are owned by the
+        // ring and are neither moved nor freed while an operation is in
+        // flight (see `Drop`)
+        unsafe { self.ring.submission().push(entry) }.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "io_uring submission queue is full",
+            )
+        })
+    }
+
+    /// Submit the queued operations and process completions until no send
+    /// is outstanding and, if `until_received`, the pending receive has
+    /// completed. Return the number of bytes received, if any.
+    fn drive(&mut self, until_received: bool) -> io::Result<Option<usize>> {
+        let mut received = None;
+        let mut send_error = None;
+
+        while self.sending || (until_received && self.receiving) {
+            if let Err(e) = self.ring.submit_and_wait(1) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                self.broken = true;
+                return Err(e);
+            }
+
+            loop {
+                let (user_data, result) = match self.ring.completion().next() {
+                    Some(cqe) => (cqe.user_data(), cqe.result()),
+                    None => break,
+                };
+                match user_data {
+                    SEND => {
+                        if let Err(e) = self.sent(result) {
+                            // the peer will not reply to a partial message,
+                            // so end the receive instead of waiting for it
+                            let _ = self.stream.shutdown(Shutdown::Both);
+                            send_error = Some(e);
+                        }
+                    }
+                    RECV => {
+                        self.receiving = false;
+                        received = Some(if result < 0 {
+                            Err(io::Error::from_raw_os_error(-result))
+                        } else {
+                            Ok(result as usize)
+                        });
+                    }
+                    _ => unreachable!("unexpected io_uring completion {}", user_data),
+                }
+            }
+        }
+
+        if let Some(e) = send_error {
+            return Err(e);
+        }
+        received.transpose()
+    }
+
+    fn sent(&mut self, result: i32) -> io::Result<()> {
+        self.sending = false;
+        if result <= 0 {
+            self.send_buf.clear();
+            return Err(if result < 0 {
+                io::Error::from_raw_os_error(-result)
+            } else {
+                io::Error::new(io::ErrorKind::WriteZero, "connection closed while sending")
+            });
+        }
+
+        self.send_pos += result as usize;
+        if self.send_pos < self.send_buf.len() {
+            self.queue_send()
+        } else {
+            self.send_buf.clear();
+            Ok(())
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        if !self.broken && self.sending {
+            let _ = self.drive(false);
+        }
+        if self.broken || self.sending || self.receiving {
+            // the kernel may still write to or read from these buffers
+            mem::forget(mem::take(&mut self.read_buf));
+            mem::forget(mem::take(&mut self.send_buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn echo_server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => stream.write_all(&buf[..n]).unwrap(),
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn must_send_deferred_flush_with_next_read() {
+        let mut c = TUringChannel::connect(echo_server()).unwrap();
+        assert!(c.deferred_flush());
+
+        for i in 0..10u8 {
+            c.write_all(&[i, i + 1]).unwrap();
+            c.flush().unwrap();
+            let mut buf = [0u8; 2];
+            c.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [i, i + 1]);
+        }
+    }
+
+    #[test]
+    fn must_send_immediately_when_flush_is_not_deferred() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut c = TUringChannel::connect(listener.local_addr().unwrap()).unwrap();
+        c.set_deferred_flush(false);
+        let (mut server, _) = listener.accept().unwrap();
+
+        c.write_all(b"ping").unwrap();
+        c.flush().unwrap();
+
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn must_send_queued_bytes_on_submit_and_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut c = TUringChannel::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        c.write_all(b"one").unwrap();
+        c.flush().unwrap();
+        c.submit().unwrap();
+        let mut buf = [0u8; 3];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"one");
+
+        c.write_all(b"two").unwrap();
+        c.flush().unwrap();
+        drop(c);
+        let mut rest = Vec::new();
+        server.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"two");
+    }
+
+    #[test]
+    fn must_send_large_messages_and_read_across_receives() {
+        let c = TUringChannel::connect(echo_server()).unwrap();
+        let (mut r, mut w) = c.clone().split().unwrap();
+
+        let message: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let expected = message.clone();
+        w.write_all(&message).unwrap();
+        w.flush().unwrap();
+
+        let mut echoed = vec![0u8; expected.len()];
+        r.read_exact(&mut echoed).unwrap();
+        assert_eq!(echoed, expected);
+
+        c.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(r.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+}
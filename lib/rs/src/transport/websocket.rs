@@ -0,0 +1,369 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use tungstenite::client::IntoClientRequest;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Bytes, Message, WebSocket};
+
+use super::{ReadHalf, TIoChannel, TSharedChannel, WriteHalf};
+use crate::{new_transport_error, TConfiguration, TransportErrorKind};
+
+/// A blocking WebSocket client channel.
+///
+/// Every flushed Thrift message is sent as a single binary WebSocket message,
+/// and each binary message received is read back as one response. This is
+/// the framing used by browser-based clients, which can only speak WebSocket,
+/// and it passes through HTTP proxies and load balancers that support the
+/// WebSocket upgrade.
+///
+/// Ping frames are answered automatically. A close frame from the server is
+/// reported to readers as end-of-file. Text messages are rejected.
+///
+/// Both halves returned by [`TIoChannel::split`] share the WebSocket.
+///
+/// # Examples
+///
+/// ```no_run
+/// use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+/// use thrift::transport::{TIoChannel, TWebSocketClientChannel};
+///
+/// let channel = TWebSocketClientChannel::connect("ws://localhost:9090/thrift").unwrap();
+///
+/// let (i_chan, o_chan) = channel.split().unwrap();
+/// let i_prot = TBinaryInputProtocol::new(i_chan, true);
+/// let o_prot = TBinaryOutputProtocol::new(o_chan, true);
+/// ```
+#[derive(Debug)]
+pub struct TWebSocketClientChannel<S = TcpStream> {
+    inner: TSharedChannel<WsConnection<S>>,
+}
+
+impl TWebSocketClientChannel<TcpStream> {
+    /// Connect to the `ws` URL `url` and complete the WebSocket handshake.
+    ///
+    /// Use [`with_stream`](Self::with_stream) over a TLS channel for `wss`
+    /// URLs.
+    pub fn connect(url: &str) -> crate::Result<Self> {
+        let request = url.into_client_request().map_err(ws_error)?;
+        if request.uri().scheme_str() != Some("ws") {
+            return Err(new_transport_error(
+                TransportErrorKind::Unknown,
+                format!("cannot connect to {url}: only ws URLs are supported"),
+            ));
+        }
+        let host = request.uri().host().unwrap_or_default();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = request.uri().port_u16().unwrap_or(80);
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_nodelay(true)?;
+        TWebSocketClientChannel::with_stream(stream, request)
+    }
+}
+
+impl<S: Read + Write> TWebSocketClientChannel<S> {
+    /// Complete the WebSocket handshake for `request` over an already
+    /// connected `stream`.
+    ///
+    /// `request` is usually a URL, but can be a full HTTP request to send
+    /// extra headers - such as `Authorization` or `Sec-WebSocket-Protocol` -
+    /// with the upgrade.
+    pub fn with_stream<R: IntoClientRequest>(stream: S, request: R) -> crate::Result<Self> {
+        let (socket, _) =
+            tungstenite::client::client_with_config(request, stream, Some(default_config()))
+                .map_err(|e| handshake_error("client", e))?;
+        Ok(TWebSocketClientChannel {
+            inner: TSharedChannel::new(WsConnection::new(socket)),
+        })
+    }
+
+    /// Set the largest message accepted from the server, or `None` for no
+    /// limit. Defaults to `TConfiguration::DEFAULT_MAX_MESSAGE_SIZE`.
+    pub fn set_max_message_size(&mut self, limit: Option<usize>) -> crate::Result<()> {
+        self.inner.lock()?.set_max_message_size(limit);
+        Ok(())
+    }
+
+    /// Send a close frame and flush it to the server.
+    pub fn close(&mut self) -> crate::Result<()> {
+        self.inner.lock()?.close().map_err(From::from)
+    }
+}
+
+impl<S> Clone for TWebSocketClientChannel<S> {
+    fn clone(&self) -> Self {
+        TWebSocketClientChannel {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S: Read + Write> Read for TWebSocketClientChannel<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for TWebSocketClientChannel<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Read + Write> TIoChannel for TWebSocketClientChannel<S> {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+/// A blocking WebSocket server channel.
+///
+/// Construction does not perform socket I/O. The WebSocket upgrade is
+/// completed lazily by the first read or write, allowing a server accept loop
+/// to enqueue the connection without waiting on an untrusted peer.
+///
+/// Messages are framed as described for [`TWebSocketClientChannel`].
+#[derive(Debug)]
+pub struct TWebSocketServerChannel<S = TcpStream> {
+    inner: TSharedChannel<WsServerState<S>>,
+}
+
+impl<S: Read + Write> TWebSocketServerChannel<S> {
+    /// Wrap an already-connected stream without blocking for the upgrade.
+    pub fn with_stream(stream: S) -> Self {
+        TWebSocketServerChannel {
+            inner: TSharedChannel::new(WsServerState::Handshaking(Some(stream))),
+        }
+    }
+
+    /// Complete the WebSocket upgrade immediately.
+    ///
+    /// If the upgrade is already complete this returns without performing
+    /// socket I/O. A failed upgrade cannot be retried.
+    pub fn handshake(&mut self) -> crate::Result<()> {
+        self.inner.lock()?.open().map(|_| ()).map_err(From::from)
+    }
+
+    /// Set the largest message accepted from the client, or `None` for no
+    /// limit. Defaults to `TConfiguration::DEFAULT_MAX_MESSAGE_SIZE`.
+    ///
+    /// Completes the upgrade if it has not happened yet.
+    pub fn set_max_message_size(&mut self, limit: Option<usize>) -> crate::Result<()> {
+        self.inner.lock()?.open()?.set_max_message_size(limit);
+        Ok(())
+    }
+
+    /// Send a close frame and flush it to the client.
+    pub fn close(&mut self) -> crate::Result<()> {
+        self.inner.lock()?.open()?.close().map_err(From::from)
+    }
+}
+
+impl<S> Clone for TWebSocketServerChannel<S> {
+    fn clone(&self) -> Self {
+        TWebSocketServerChannel {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S: Read + Write> Read for TWebSocketServerChannel<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.lock()?.open()?.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for TWebSocketServerChannel<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock()?.open()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock()?.open()?.flush()
+    }
+}
+
+impl<S: Read + Write> TIoChannel for TWebSocketServerChannel<S> {
+    fn split(self) -> crate::Result<(ReadHalf<Self>, WriteHalf<Self>)>
+    where
+        Self: Sized,
+    {
+        Ok((ReadHalf::new(self.clone()), WriteHalf::new(self)))
+    }
+}
+
+enum WsServerState<S> {
+    /// `None` once an upgrade attempt has failed.
+    Handshaking(Option<S>),
+    Open(Box<WsConnection<S>>),
+}
+
+impl<S> fmt::Debug for WsServerState<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WsServerState::Handshaking(_) => f.write_str("Handshaking"),
+            WsServerState::Open(connection) => connection.fmt(f),
+        }
+    }
+}
+
+impl<S: Read + Write> WsServerState<S> {
+    fn open(&mut self) -> io::Result<&mut WsConnection<S>> {
+        if let WsServerState::Handshaking(stream) = self {
+            let stream = stream.take().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "WebSocket upgrade previously failed",
+                )
+            })?;
+            let socket = tungstenite::accept_with_config(stream, Some(default_config()))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            *self = WsServerState::Open(Box::new(WsConnection::new(socket)));
+        }
+        match self {
+            WsServerState::Open(connection) => Ok(connection),
+            WsServerState::Handshaking(_) => unreachable!("upgrade completed above"),
+        }
+    }
+}
+
+/// An established WebSocket carrying one Thrift message per binary message.
+struct WsConnection<S> {
+    socket: WebSocket<S>,
+    read_buf: Bytes,
+    write_buf: Vec<u8>,
+}
+
+impl<S> fmt::Debug for WsConnection<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WsConnection")
+            .field("buffered_read", &self.read_buf.len())
+            .field("buffered_write", &self.write_buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Read + Write> WsConnection<S> {
+    fn new(socket: WebSocket<S>) -> Self {
+        WsConnection {
+            socket,
+            read_buf: Bytes::new(),
+            write_buf: Vec::new(),
+        }
+    }
+
+    fn set_max_message_size(&mut self, limit: Option<usize>) {
+        self.socket
+            .set_config(|config| config.max_message_size = limit);
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self.socket.close(None) {
+            Ok(()) => Ok(()),
+            Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => {
+                Ok(())
+            }
+            Err(e) => Err(into_io_error(e)),
+        }
+    }
+}
+
+impl<S: Read + Write> Read for WsConnection<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_buf.is_empty() {
+            match self.socket.read() {
+                Ok(Message::Binary(data)) => self.read_buf = data,
+                Ok(Message::Text(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "received text WebSocket message; Thrift requires binary messages",
+                    ))
+                }
+                // control frames are handled by tungstenite; a close is
+                // reported as ConnectionClosed by the next read
+                Ok(_) => {}
+                Err(tungstenite::Error::ConnectionClosed)
+                | Err(tungstenite::Error::AlreadyClosed) => return Ok(0),
+                Err(e) => return Err(into_io_error(e)),
+            }
+        }
+        let len = std::cmp::min(buf.len(), self.read_buf.len());
+        buf[..len].copy_from_slice(&self.read_buf.split_to(len));
+        Ok(len)
+    }
+}
+
+impl<S: Read + Write> Write for WsConnection<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = if self.write_buf.is_empty() {
+            self.socket.flush()
+        } else {
+            let message = Message::Binary(std::mem::take(&mut self.write_buf).into());
+            self.socket.send(message)
+        };
+        result.map_err(into_io_error)
+    }
+}
+
+fn default_config() -> WebSocketConfig {
+    WebSocketConfig::default().max_message_size(Some(TConfiguration::DEFAULT_MAX_MESSAGE_SIZE))
+}
+
+// io::Error::other requires Rust 1.74.
+#[allow(unknown_lints)]
+#[allow(clippy::io_other_error)]
+fn into_io_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::BrokenPipe, error.to_string())
+        }
+        tungstenite::Error::Capacity(_)
+        | tungstenite::Error::Protocol(_)
+        | tungstenite::Error::Utf8 => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        e => io::Error::new(io::ErrorKind::Other, e.to_string()),
+    }
+}
+
+fn ws_error(error: tungstenite::Error) -> crate::Error {
+    new_transport_error(
+        TransportErrorKind::Unknown,
+        format!("invalid WebSocket request: {error}"),
+    )
+}
+
+fn handshake_error<E: fmt::Display>(side: &str, error: E) -> crate::Error {
+    new_transport_error(
+        TransportErrorKind::Unknown,
+        format!("WebSocket {side} handshake failed: {error}"),
+    )
+}
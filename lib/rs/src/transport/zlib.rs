@@ -0,0 +1,286 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io;
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use super::{TReadTransport, TReadTransportFactory, TWriteTransport, TWriteTransportFactory};
+
+/// Compression level used by `TZlibWriteTransport::new`.
+const DEFAULT_LEVEL: u32 = 6;
+
+/// Transport that reads a zlib-compressed stream.
+///
+/// A `TZlibReadTransport` inflates a single zlib stream (RFC 1950) read from
+/// the wrapped channel, as written by `TZlibWriteTransport` or by the
+/// `TZlibTransport` of the C++ and Python libraries. Data is available as
+/// soon as the writer flushes; the stream does not have to be finished.
+///
+/// # Examples
+///
+/// Create and use a `TZlibReadTransport`.
+///
+/// ```no_run
+/// use std::io::Read;
+/// use thrift::transport::{TTcpChannel, TZlibReadTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let mut t = TZlibReadTransport::new(c);
+///
+/// t.read(&mut vec![0u8; 1]).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TZlibReadTransport<C>
+where
+    C: Read,
+{
+    decoder: ZlibDecoder<C>,
+}
+
+impl<C> TZlibReadTransport<C>
+where
+    C: Read,
+{
+    /// Create a `TZlibReadTransport` that reads compressed bytes from the
+    /// given `TIoChannel`.
+    pub fn new(channel: C) -> TZlibReadTransport<C> {
+        TZlibReadTransport {
+            decoder: ZlibDecoder::new(channel),
+        }
+    }
+
+    /// Number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.total_in()
+    }
+
+    /// Number of uncompressed bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.decoder.total_out()
+    }
+}
+
+impl<C> Read for TZlibReadTransport<C>
+where
+    C: Read,
+{
+    fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(b)
+    }
+}
+
+/// Factory for creating instances of `TZlibReadTransport`.
+#[derive(Default)]
+pub struct TZlibReadTransportFactory;
+
+impl TZlibReadTransportFactory {
+    pub fn new() -> TZlibReadTransportFactory {
+        TZlibReadTransportFactory {}
+    }
+}
+
+impl TReadTransportFactory for TZlibReadTransportFactory {
+    /// Create a `TZlibReadTransport`.
+    fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
+        Box::new(TZlibReadTransport::new(channel))
+    }
+}
+
+/// Transport that writes a zlib-compressed stream.
+///
+/// A `TZlibWriteTransport` deflates everything written to it into a single
+/// zlib stream (RFC 1950) on the wrapped channel. Calling `flush()` - which
+/// output protocols do at the end of every message - performs a zlib sync
+/// flush, so the peer can decode everything written so far without waiting
+/// for more data, and then flushes the channel.
+///
+/// The stream is terminated, including its checksum trailer, by `finish()`.
+/// Dropping the transport without calling `finish()` also terminates the
+/// stream, but ignores any error while doing so.
+///
+/// # Examples
+///
+/// Create and use a `TZlibWriteTransport`.
+///
+/// ```no_run
+/// use std::io::Write;
+/// use thrift::transport::{TTcpChannel, TZlibWriteTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let mut t = TZlibWriteTransport::new(c);
+///
+/// t.write(&[0x00]).unwrap();
+/// t.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TZlibWriteTransport<C>
+where
+    C: Write,
+{
+    encoder: ZlibEncoder<C>,
+}
+
+impl<C> TZlibWriteTransport<C>
+where
+    C: Write,
+{
+    /// Create a `TZlibWriteTransport` with the default compression level
+    /// that writes compressed bytes to the given `TIoChannel`.
+    pub fn new(channel: C) -> TZlibWriteTransport<C> {
+        TZlibWriteTransport::with_level(channel, DEFAULT_LEVEL)
+    }
+
+    /// Create a `TZlibWriteTransport` with compression level `level`, from
+    /// 0 (no compression) to 9 (best compression), that writes compressed
+    /// bytes to the given `TIoChannel`.
+    pub fn with_level(channel: C, level: u32) -> TZlibWriteTransport<C> {
+        TZlibWriteTransport {
+            encoder: ZlibEncoder::new(channel, Compression::new(level)),
+        }
+    }
+
+    /// Number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.encoder.total_in()
+    }
+
+    /// Number of compressed bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.encoder.total_out()
+    }
+
+    /// Terminate the zlib stream and return the wrapped channel.
+    pub fn finish(self) -> io::Result<C> {
+        self.encoder.finish()
+    }
+}
+
+impl<C> Write for TZlibWriteTransport<C>
+where
+    C: Write,
+{
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.encoder.write(b)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+/// Factory for creating instances of `TZlibWriteTransport`.
+#[derive(Debug)]
+pub struct TZlibWriteTransportFactory {
+    level: u32,
+}
+
+impl TZlibWriteTransportFactory {
+    /// Create a `TZlibWriteTransportFactory` using the default compression
+    /// level.
+    pub fn new() -> TZlibWriteTransportFactory {
+        TZlibWriteTransportFactory::with_level(DEFAULT_LEVEL)
+    }
+
+    /// Create a `TZlibWriteTransportFactory` using compression level
+    /// `level`, from 0 to 9.
+    pub fn with_level(level: u32) -> TZlibWriteTransportFactory {
+        TZlibWriteTransportFactory { level }
+    }
+}
+
+impl Default for TZlibWriteTransportFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TWriteTransportFactory for TZlibWriteTransportFactory {
+    /// Create a `TZlibWriteTransport`.
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        Box::new(TZlibWriteTransport::with_level(channel, self.level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a binary protocol "ping" call header followed by a field stop, as
+    // compressed and sync-flushed by Python's zlib module
+    const PYTHON_SYNC_FLUSHED: [u8; 24] = [
+        0x78, 0x9C, 0x6A, 0x60, 0x64, 0x60, 0x64, 0x60, 0x60, 0x60, 0x29, 0xC8, 0xCC, 0x4B, 0x07,
+        0xD2, 0x8C, 0x0C, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF,
+    ];
+    const PING: [u8; 17] = [
+        0x80, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, b'p', b'i', b'n', b'g', 0x00, 0x00, 0x00,
+        0x01, 0x00,
+    ];
+
+    #[test]
+    fn must_read_sync_flushed_stream_from_other_implementations() {
+        let mut t = TZlibReadTransport::new(&PYTHON_SYNC_FLUSHED[..]);
+
+        let mut buf = [0u8; PING.len()];
+        t.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, PING);
+        assert_eq!(t.total_out(), PING.len() as u64);
+    }
+
+    #[test]
+    fn must_make_each_flushed_message_readable_before_stream_ends() {
+        let mut t = TZlibWriteTransport::new(Vec::new());
+
+        t.write_all(&PING).unwrap();
+        t.flush().unwrap();
+        let first_len = t.encoder.get_ref().len();
+
+        // the first message decodes from only the bytes flushed so far
+        let flushed = t.encoder.get_ref().clone();
+        let mut r = TZlibReadTransport::new(&flushed[..]);
+        let mut buf = [0u8; PING.len()];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, PING);
+
+        t.write_all(b"second").unwrap();
+        t.flush().unwrap();
+        assert!(t.encoder.get_ref().len() > first_len);
+
+        let compressed = t.finish().unwrap();
+        let mut r = TZlibReadTransport::new(&compressed[..]);
+        let mut all = Vec::new();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(&all[..PING.len()], &PING);
+        assert_eq!(&all[PING.len()..], b"second");
+    }
+
+    #[test]
+    fn must_compress_repetitive_data() {
+        let mut t = TZlibWriteTransport::with_level(Vec::new(), 9);
+        t.write_all(&[0x42; 10_000]).unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.total_in(), 10_000);
+        assert!(t.total_out() < 100);
+    }
+}
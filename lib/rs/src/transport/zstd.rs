@@ -0,0 +1,378 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fmt;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::sync::Arc;
+
+use zstd::stream::read::Decoder;
+use zstd::stream::write::Encoder;
+
+use super::{TReadTransport, TReadTransportFactory, TWriteTransport, TWriteTransportFactory};
+
+/// Compression level used when none is specified. A level of `0` selects
+/// zstd's own default (currently `3`).
+const DEFAULT_LEVEL: i32 = 0;
+
+/// Transport that reads a zstd-compressed stream.
+///
+/// A `TZstdReadTransport` decompresses the zstd frames written to the
+/// wrapped channel by a `TZstdWriteTransport`. Data is available as soon as
+/// the writer flushes. A transport created with `with_dictionary` must be
+/// given the same dictionary as the writer.
+///
+/// # Examples
+///
+/// Create and use a `TZstdReadTransport`.
+///
+/// ```no_run
+/// use std::io::Read;
+/// use thrift::transport::{TTcpChannel, TZstdReadTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let mut t = TZstdReadTransport::new(c).unwrap();
+///
+/// t.read(&mut vec![0u8; 1]).unwrap();
+/// ```
+pub struct TZstdReadTransport<C>
+where
+    C: Read,
+{
+    decoder: Decoder<'static, BufReader<C>>,
+}
+
+impl<C> TZstdReadTransport<C>
+where
+    C: Read,
+{
+    /// Create a `TZstdReadTransport` that reads compressed bytes from the
+    /// given `TIoChannel`.
+    pub fn new(channel: C) -> io::Result<TZstdReadTransport<C>> {
+        TZstdReadTransport::with_dictionary(channel, &[])
+    }
+
+    /// Create a `TZstdReadTransport` that reads bytes compressed with
+    /// `dictionary` from the given `TIoChannel`.
+    pub fn with_dictionary(channel: C, dictionary: &[u8]) -> io::Result<TZstdReadTransport<C>> {
+        Ok(TZstdReadTransport {
+            decoder: Decoder::with_dictionary(BufReader::new(channel), dictionary)?,
+        })
+    }
+}
+
+impl<C> fmt::Debug for TZstdReadTransport<C>
+where
+    C: Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TZstdReadTransport").finish_non_exhaustive()
+    }
+}
+
+impl<C> Read for TZstdReadTransport<C>
+where
+    C: Read,
+{
+    fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(b)
+    }
+}
+
+/// Factory for creating instances of `TZstdReadTransport`.
+#[derive(Debug, Default)]
+pub struct TZstdReadTransportFactory {
+    dictionary: Option<Arc<[u8]>>,
+}
+
+impl TZstdReadTransportFactory {
+    pub fn new() -> TZstdReadTransportFactory {
+        TZstdReadTransportFactory::default()
+    }
+
+    /// Create a `TZstdReadTransportFactory` whose transports decompress with
+    /// `dictionary`.
+    ///
+    /// Fails if zstd rejects the dictionary.
+    pub fn with_dictionary(dictionary: &[u8]) -> io::Result<TZstdReadTransportFactory> {
+        // fail here rather than in `create`
+        TZstdReadTransport::with_dictionary(io::empty(), dictionary)?;
+        Ok(TZstdReadTransportFactory {
+            dictionary: Some(dictionary.into()),
+        })
+    }
+}
+
+impl TReadTransportFactory for TZstdReadTransportFactory {
+    /// Create a `TZstdReadTransport`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if zstd cannot allocate a decompression context.
+    fn create(&self, channel: Box<dyn Read + Send>) -> Box<dyn TReadTransport + Send> {
+        let dictionary = self.dictionary.as_deref().unwrap_or(&[]);
+        Box::new(
+            TZstdReadTransport::with_dictionary(channel, dictionary)
+                .expect("failed to create zstd decompression context"),
+        )
+    }
+}
+
+/// Transport that writes a zstd-compressed stream.
+///
+/// A `TZstdWriteTransport` compresses everything written to it and writes
+/// the result to the wrapped channel. Calling `flush()` - which output
+/// protocols do at the end of every message - completes the current zstd
+/// block, so the peer can decode everything written so far, and then flushes
+/// the channel.
+///
+/// Compression works best when many messages are written between flushes,
+/// for example when batching messages into a file or a bulk transfer. Small,
+/// individually flushed messages compress much better with a dictionary
+/// trained on representative messages (see `zstd::dict`), passed to both
+/// ends with `with_dictionary`.
+///
+/// The stream is terminated by `finish()`. Dropping the transport without
+/// calling `finish()` leaves the last frame unterminated; everything flushed
+/// before that can still be read.
+///
+/// # Examples
+///
+/// Create and use a `TZstdWriteTransport`.
+///
+/// ```no_run
+/// use std::io::Write;
+/// use thrift::transport::{TTcpChannel, TZstdWriteTransport};
+///
+/// let mut c = TTcpChannel::new();
+/// c.open("localhost:9090").unwrap();
+///
+/// let mut t = TZstdWriteTransport::with_level(c, 19).unwrap();
+///
+/// t.write(&[0x00]).unwrap();
+/// t.flush().unwrap();
+/// ```
+pub struct TZstdWriteTransport<C>
+where
+    C: Write,
+{
+    encoder: Encoder<'static, C>,
+}
+
+impl<C> TZstdWriteTransport<C>
+where
+    C: Write,
+{
+    /// Create a `TZstdWriteTransport` with zstd's default compression level
+    /// that writes compressed bytes to the given `TIoChannel`.
+    pub fn new(channel: C) -> io::Result<TZstdWriteTransport<C>> {
+        TZstdWriteTransport::with_level(channel, DEFAULT_LEVEL)
+    }
+
+    /// Create a `TZstdWriteTransport` with compression level `level` that
+    /// writes compressed bytes to the given `TIoChannel`.
+    ///
+    /// Levels range from 1 to 22; negative levels trade ratio for speed and
+    /// `0` selects zstd's default.
+    pub fn with_level(channel: C, level: i32) -> io::Result<TZstdWriteTransport<C>> {
+        TZstdWriteTransport::with_dictionary(channel, level, &[])
+    }
+
+    /// Create a `TZstdWriteTransport` with compression level `level` that
+    /// compresses with `dictionary` and writes the compressed bytes to the
+    /// given `TIoChannel`.
+    pub fn with_dictionary(
+        channel: C,
+        level: i32,
+        dictionary: &[u8],
+    ) -> io::Result<TZstdWriteTransport<C>> {
+        Ok(TZstdWriteTransport {
+            encoder: Encoder::with_dictionary(channel, level, dictionary)?,
+        })
+    }
+
+    /// Terminate the zstd stream and return the wrapped channel.
+    pub fn finish(self) -> io::Result<C> {
+        self.encoder.finish()
+    }
+}
+
+impl<C> fmt::Debug for TZstdWriteTransport<C>
+where
+    C: Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TZstdWriteTransport")
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C> Write for TZstdWriteTransport<C>
+where
+    C: Write,
+{
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.encoder.write(b)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+/// Factory for creating instances of `TZstdWriteTransport`.
+#[derive(Debug, Default)]
+pub struct TZstdWriteTransportFactory {
+    level: i32,
+    dictionary: Option<Arc<[u8]>>,
+}
+
+impl TZstdWriteTransportFactory {
+    /// Create a `TZstdWriteTransportFactory` using zstd's default
+    /// compression level.
+    pub fn new() -> TZstdWriteTransportFactory {
+        TZstdWriteTransportFactory::with_level(DEFAULT_LEVEL)
+    }
+
+    /// Create a `TZstdWriteTransportFactory` using compression level `level`.
+    pub fn with_level(level: i32) -> TZstdWriteTransportFactory {
+        TZstdWriteTransportFactory {
+            level,
+            dictionary: None,
+        }
+    }
+
+    /// Create a `TZstdWriteTransportFactory` using compression level `level`
+    /// and `dictionary`.
+    ///
+    /// Fails if zstd rejects the level or the dictionary.
+    pub fn with_dictionary(
+        level: i32,
+        dictionary: &[u8],
+    ) -> io::Result<TZstdWriteTransportFactory> {
+        // fail here rather than in `create`
+        TZstdWriteTransport::with_dictionary(io::sink(), level, dictionary)?;
+        Ok(TZstdWriteTransportFactory {
+            level,
+            dictionary: Some(dictionary.into()),
+        })
+    }
+}
+
+impl TWriteTransportFactory for TZstdWriteTransportFactory {
+    /// Create a `TZstdWriteTransport`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if zstd cannot allocate a compression context or rejects the
+    /// compression level.
+    fn create(&self, channel: Box<dyn Write + Send>) -> Box<dyn TWriteTransport + Send> {
+        let dictionary = self.dictionary.as_deref().unwrap_or(&[]);
+        Box::new(
+            TZstdWriteTransport::with_dictionary(channel, self.level, dictionary)
+                .expect("failed to create zstd compression context"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(n: u8) -> Vec<u8> {
+        let mut m = b"{\"name\":\"ping\",\"type\":\"call\",\"seq\":".to_vec();
+        m.push(b'0' + n);
+        m.push(b'}');
+        m
+    }
+
+    #[test]
+    fn must_make_each_flushed_message_readable_before_stream_ends() {
+        let mut t = TZstdWriteTransport::new(Vec::new()).unwrap();
+        t.write_all(&message(1)).unwrap();
+        t.flush().unwrap();
+
+        let flushed = t.encoder.get_ref().clone();
+        let mut r = TZstdReadTransport::new(&flushed[..]).unwrap();
+        let mut buf = vec![0u8; message(1).len()];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, message(1));
+
+        t.write_all(&message(2)).unwrap();
+        let compressed = t.finish().unwrap();
+
+        let mut r = TZstdReadTransport::new(&compressed[..]).unwrap();
+        let mut all = Vec::new();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(all, [message(1), message(2)].concat());
+    }
+
+    #[test]
+    fn must_round_trip_with_dictionary() {
+        let dictionary = [message(0), message(9)].concat().repeat(4);
+
+        let mut t = TZstdWriteTransport::with_dictionary(Vec::new(), 19, &dictionary).unwrap();
+        t.write_all(&message(5)).unwrap();
+        let with_dictionary = t.finish().unwrap();
+
+        let mut t = TZstdWriteTransport::with_level(Vec::new(), 19).unwrap();
+        t.write_all(&message(5)).unwrap();
+        let without_dictionary = t.finish().unwrap();
+        assert!(with_dictionary.len() < without_dictionary.len());
+
+        let mut r = TZstdReadTransport::with_dictionary(&with_dictionary[..], &dictionary).unwrap();
+        let mut all = Vec::new();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(all, message(5));
+
+        // without the dictionary the data cannot be decoded
+        let mut r = TZstdReadTransport::new(&with_dictionary[..]).unwrap();
+        assert!(r.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn must_create_transports_from_factories() {
+        let dictionary = message(0).repeat(8);
+        let writer = TZstdWriteTransportFactory::with_dictionary(3, &dictionary).unwrap();
+        let reader = TZstdReadTransportFactory::with_dictionary(&dictionary).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        struct Sink(std::sync::mpsc::Sender<Vec<u8>>);
+        impl Write for Sink {
+            fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+                self.0.send(b.to_vec()).unwrap();
+                Ok(b.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut t = writer.create(Box::new(Sink(tx)));
+        t.write_all(&message(3)).unwrap();
+        t.flush().unwrap();
+        drop(t);
+
+        let compressed: Vec<u8> = rx.iter().flatten().collect();
+        let mut r = reader.create(Box::new(io::Cursor::new(compressed)));
+        let mut buf = vec![0u8; message(3).len()];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, message(3));
+    }
+}
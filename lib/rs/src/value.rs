@@ -0,0 +1,510 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Dynamic representation of Thrift data.
+//!
+//! `Value` holds any Thrift value without generated code for its type, so
+//! tools can read arbitrary data, inspect or change it, and write it out
+//! again, possibly with another protocol. To pass a message from one protocol
+//! to another unchanged, `protocol::transcode` avoids building the tree.
+//!
+//! Since the binary and compact protocols do not record field names, struct
+//! fields are keyed by id. They also encode strings and binary values the
+//! same way, so a value read from the wire is a `Value::String` if it is
+//! valid UTF-8 and a `Value::Binary` otherwise. Both are written the same
+//! way, so re-emitting a value does not change its encoding.
+//!
+//! # Examples
+//!
+//! ```
+//! use thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+//! use thrift::value::Value;
+//!
+//! let mut fields = std::collections::BTreeMap::new();
+//! fields.insert(1, Value::from("tea"));
+//! fields.insert(2, Value::from(3i32));
+//! let order = Value::Struct(fields);
+//!
+//! let mut bytes = Vec::new();
+//! order
+//!     .write(&mut TBinaryOutputProtocol::new(&mut bytes, true))
+//!     .unwrap();
+//!
+//! let read = Value::read(&mut TBinaryInputProtocol::new(&bytes[..], true)).unwrap();
+//! assert_eq!(read, order);
+//! ```
+
+use std::collections::BTreeMap;
+
+use ordered_float::OrderedFloat;
+
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TOutputProtocol,
+    TSetIdentifier, TStructIdentifier, TType, MAXIMUM_SKIP_DEPTH,
+};
+use crate::ProtocolErrorKind;
+
+/// Any Thrift value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    /// A `bool`.
+    Bool(bool),
+    /// An `i8` (Thrift `byte`).
+    I8(i8),
+    /// An `i16`.
+    I16(i16),
+    /// An `i32`, which is also how enums are encoded.
+    I32(i32),
+    /// An `i64`.
+    I64(i64),
+    /// A `double`.
+    Double(OrderedFloat<f64>),
+    /// A UTF-8 `string`.
+    String(String),
+    /// A `binary` value.
+    Binary(Vec<u8>),
+    /// A `uuid`.
+    Uuid(uuid::Uuid),
+    /// A `list`. Every element must have type `element_type`.
+    List {
+        /// Type of the elements.
+        element_type: TType,
+        /// The elements, in order.
+        elements: Vec<Value>,
+    },
+    /// A `set`. Every element must have type `element_type`.
+    Set {
+        /// Type of the elements.
+        element_type: TType,
+        /// The elements, in the order they were read or will be written.
+        elements: Vec<Value>,
+    },
+    /// A `map`. Every entry must have types `key_type` and `value_type`.
+    Map {
+        /// Type of the keys.
+        key_type: TType,
+        /// Type of the values.
+        value_type: TType,
+        /// The entries, in the order they were read or will be written.
+        entries: Vec<(Value, Value)>,
+    },
+    /// A struct, union or exception, keyed by field id.
+    Struct(BTreeMap<i16, Value>),
+}
+
+impl Value {
+    /// Read a struct from `i_prot`.
+    ///
+    /// This is how the arguments and results of every call are encoded, so
+    /// it reads any message body.
+    pub fn read(i_prot: &mut dyn TInputProtocol) -> crate::Result<Value> {
+        Value::read_typed(i_prot, TType::Struct)
+    }
+
+    /// Read a value of type `value_type` from `i_prot`.
+    ///
+    /// Fails if values are nested more than 64 levels deep. Protocols need
+    /// not record the key and value types of an empty map; they are read as
+    /// `TType::I08` when missing.
+    pub fn read_typed(i_prot: &mut dyn TInputProtocol, value_type: TType) -> crate::Result<Value> {
+        read_value(i_prot, value_type, MAXIMUM_SKIP_DEPTH)
+    }
+
+    /// Write this value to `o_prot`.
+    ///
+    /// Fails if the elements of a container do not have its declared types.
+    pub fn write(&self, o_prot: &mut dyn TOutputProtocol) -> crate::Result<()> {
+        match self {
+            Value::Bool(b) => o_prot.write_bool(*b),
+            Value::I8(i) => o_prot.write_i8(*i),
+            Value::I16(i) => o_prot.write_i16(*i),
+            Value::I32(i) => o_prot.write_i32(*i),
+            Value::I64(i) => o_prot.write_i64(*i),
+            Value::Double(d) => o_prot.write_double(d.0),
+            Value::String(s) => o_prot.write_string(s),
+            Value::Binary(b) => o_prot.write_bytes(b),
+            Value::Uuid(u) => o_prot.write_uuid(u),
+            Value::List {
+                element_type,
+                elements,
+            } => {
+                o_prot.write_list_begin(&TListIdentifier::new(*element_type, size(elements)?))?;
+                for element in elements {
+                    write_element(o_prot, *element_type, element)?;
+                }
+                o_prot.write_list_end()
+            }
+            Value::Set {
+                element_type,
+                elements,
+            } => {
+                o_prot.write_set_begin(&TSetIdentifier::new(*element_type, size(elements)?))?;
+                for element in elements {
+                    write_element(o_prot, *element_type, element)?;
+                }
+                o_prot.write_set_end()
+            }
+            Value::Map {
+                key_type,
+                value_type,
+                entries,
+            } => {
+                o_prot.write_map_begin(&TMapIdentifier::new(
+                    *key_type,
+                    *value_type,
+                    size(entries)?,
+                ))?;
+                for (key, value) in entries {
+                    write_element(o_prot, *key_type, key)?;
+                    write_element(o_prot, *value_type, value)?;
+                }
+                o_prot.write_map_end()
+            }
+            Value::Struct(fields) => {
+                o_prot.write_struct_begin(&TStructIdentifier::new("Value"))?;
+                for (id, value) in fields {
+                    o_prot.write_field_begin(&TFieldIdentifier {
+                        name: None,
+                        field_type: value.value_type(),
+                        id: Some(*id),
+                    })?;
+                    value.write(o_prot)?;
+                    o_prot.write_field_end()?;
+                }
+                o_prot.write_field_stop()?;
+                o_prot.write_struct_end()
+            }
+        }
+    }
+
+    /// The Thrift type this value is written as.
+    pub fn value_type(&self) -> TType {
+        match self {
+            Value::Bool(_) => TType::Bool,
+            Value::I8(_) => TType::I08,
+            Value::I16(_) => TType::I16,
+            Value::I32(_) => TType::I32,
+            Value::I64(_) => TType::I64,
+            Value::Double(_) => TType::Double,
+            Value::String(_) | Value::Binary(_) => TType::String,
+            Value::Uuid(_) => TType::Uuid,
+            Value::List { .. } => TType::List,
+            Value::Set { .. } => TType::Set,
+            Value::Map { .. } => TType::Map,
+            Value::Struct(_) => TType::Struct,
+        }
+    }
+}
+
+fn read_value(
+    i_prot: &mut dyn TInputProtocol,
+    value_type: TType,
+    depth: i8,
+) -> crate::Result<Value> {
+    if depth == 0 {
+        return Err(crate::new_protocol_error(
+            ProtocolErrorKind::DepthLimit,
+            format!("cannot parse past {:?}", value_type),
+        ));
+    }
+
+    let value = match value_type {
+        TType::Bool => Value::Bool(i_prot.read_bool()?),
+        TType::I08 => Value::I8(i_prot.read_i8()?),
+        TType::I16 => Value::I16(i_prot.read_i16()?),
+        TType::I32 => Value::I32(i_prot.read_i32()?),
+        TType::I64 => Value::I64(i_prot.read_i64()?),
+        TType::Double => Value::Double(OrderedFloat(i_prot.read_double()?)),
+        TType::String => match String::from_utf8(i_prot.read_bytes()?) {
+            Ok(s) => Value::String(s),
+            Err(e) => Value::Binary(e.into_bytes()),
+        },
+        TType::Uuid => Value::Uuid(i_prot.read_uuid()?),
+        TType::List => {
+            let list_ident = i_prot.read_list_begin()?;
+            let elements = read_elements(i_prot, list_ident.element_type, list_ident.size, depth)?;
+            i_prot.read_list_end()?;
+            Value::List {
+                element_type: list_ident.element_type,
+                elements,
+            }
+        }
+        TType::Set => {
+            let set_ident = i_prot.read_set_begin()?;
+            let elements = read_elements(i_prot, set_ident.element_type, set_ident.size, depth)?;
+            i_prot.read_set_end()?;
+            Value::Set {
+                element_type: set_ident.element_type,
+                elements,
+            }
+        }
+        TType::Map => {
+            let map_ident = i_prot.read_map_begin()?;
+            // empty maps need not record their key and value types
+            let key_type = map_ident.key_type.unwrap_or(TType::I08);
+            let value_type = map_ident.value_type.unwrap_or(TType::I08);
+            let mut entries = Vec::new();
+            for _ in 0..map_ident.size {
+                let key = read_value(i_prot, key_type, depth - 1)?;
+                let value = read_value(i_prot, value_type, depth - 1)?;
+                entries.push((key, value));
+            }
+            i_prot.read_map_end()?;
+            Value::Map {
+                key_type,
+                value_type,
+                entries,
+            }
+        }
+        TType::Struct => {
+            i_prot.read_struct_begin()?;
+            let mut fields = BTreeMap::new();
+            loop {
+                let field_ident = i_prot.read_field_begin()?;
+                if field_ident.field_type == TType::Stop {
+                    break;
+                }
+                let id = field_ident.id.ok_or_else(|| {
+                    crate::new_protocol_error(
+                        ProtocolErrorKind::InvalidData,
+                        "cannot read a struct field without an id",
+                    )
+                })?;
+                let value = read_value(i_prot, field_ident.field_type, depth - 1)?;
+                i_prot.read_field_end()?;
+                fields.insert(id, value);
+            }
+            i_prot.read_struct_end()?;
+            Value::Struct(fields)
+        }
+        u => {
+            return Err(crate::new_protocol_error(
+                ProtocolErrorKind::Unknown,
+                format!("cannot read field type {:?}", u),
+            ))
+        }
+    };
+    Ok(value)
+}
+
+fn read_elements(
+    i_prot: &mut dyn TInputProtocol,
+    element_type: TType,
+    size: i32,
+    depth: i8,
+) -> crate::Result<Vec<Value>> {
+    // the declared size is not trusted for the initial allocation
+    let mut elements = Vec::new();
+    for _ in 0..size {
+        elements.push(read_value(i_prot, element_type, depth - 1)?);
+    }
+    Ok(elements)
+}
+
+fn write_element(
+    o_prot: &mut dyn TOutputProtocol,
+    expected: TType,
+    element: &Value,
+) -> crate::Result<()> {
+    let element_type = element.value_type();
+    if element_type != expected {
+        return Err(crate::new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            format!(
+                "expected elements of type {} but found {}",
+                expected, element_type
+            ),
+        ));
+    }
+    element.write(o_prot)
+}
+
+fn size<T>(elements: &[T]) -> crate::Result<i32> {
+    i32::try_from(elements.len()).map_err(|_| {
+        crate::new_protocol_error(
+            ProtocolErrorKind::SizeLimit,
+            format!(
+                "{} elements are too many for a Thrift container",
+                elements.len()
+            ),
+        )
+    })
+}
+
+macro_rules! from_primitive {
+    ($($ty:ty => $variant:ident,)*) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(v: $ty) -> Value {
+                    Value::$variant(v.into())
+                }
+            }
+        )*
+    };
+}
+
+from_primitive! {
+    bool => Bool,
+    i8 => I8,
+    i16 => I16,
+    i32 => I32,
+    i64 => I64,
+    f64 => Double,
+    OrderedFloat<f64> => Double,
+    String => String,
+    &str => String,
+    Vec<u8> => Binary,
+    uuid::Uuid => Uuid,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::{
+        TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol,
+    };
+
+    fn sample() -> Value {
+        let mut inner = BTreeMap::new();
+        inner.insert(1, Value::from(true));
+        inner.insert(2, Value::from(vec![0xff, 0x00]));
+
+        let mut fields = BTreeMap::new();
+        fields.insert(1, Value::from(7i8));
+        fields.insert(2, Value::from(-300i16));
+        fields.insert(3, Value::from(1 << 20));
+        fields.insert(4, Value::from(1i64 << 40));
+        fields.insert(5, Value::from(2.5));
+        fields.insert(6, Value::from("hello"));
+        fields.insert(7, Value::from(uuid::Uuid::from_u128(42)));
+        fields.insert(
+            8,
+            Value::List {
+                element_type: TType::Struct,
+                elements: vec![Value::Struct(inner.clone()), Value::Struct(inner)],
+            },
+        );
+        fields.insert(
+            9,
+            Value::Set {
+                element_type: TType::String,
+                elements: vec![Value::from("a"), Value::from("b")],
+            },
+        );
+        fields.insert(
+            10,
+            Value::Map {
+                key_type: TType::I32,
+                value_type: TType::List,
+                entries: vec![(
+                    Value::from(1),
+                    Value::List {
+                        element_type: TType::I64,
+                        elements: vec![Value::from(3i64)],
+                    },
+                )],
+            },
+        );
+        fields.insert(
+            11,
+            Value::List {
+                element_type: TType::String,
+                elements: Vec::new(),
+            },
+        );
+        Value::Struct(fields)
+    }
+
+    #[test]
+    fn must_round_trip_through_binary_protocol() {
+        let mut bytes = Vec::new();
+        assert_success!(sample().write(&mut TBinaryOutputProtocol::new(&mut bytes, true)));
+        let read = assert_success!(Value::read(&mut TBinaryInputProtocol::new(
+            &bytes[..],
+            true
+        )));
+        assert_eq!(read, sample());
+    }
+
+    #[test]
+    fn must_round_trip_through_compact_protocol() {
+        let mut bytes = Vec::new();
+        assert_success!(sample().write(&mut TCompactOutputProtocol::new(&mut bytes)));
+        let read = assert_success!(Value::read(&mut TCompactInputProtocol::new(&bytes[..])));
+        assert_eq!(read, sample());
+    }
+
+    #[test]
+    fn must_read_fields_written_by_hand() {
+        let mut bytes = Vec::new();
+        let mut o_prot = TBinaryOutputProtocol::new(&mut bytes, true);
+        assert_success!(o_prot.write_struct_begin(&TStructIdentifier::new("Order")));
+        assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("item", TType::String, 3)));
+        assert_success!(o_prot.write_string("tea"));
+        assert_success!(o_prot.write_field_end());
+        assert_success!(o_prot.write_field_begin(&TFieldIdentifier::new("count", TType::I32, 1)));
+        assert_success!(o_prot.write_i32(2));
+        assert_success!(o_prot.write_field_end());
+        assert_success!(o_prot.write_field_stop());
+        assert_success!(o_prot.write_struct_end());
+
+        let read = assert_success!(Value::read(&mut TBinaryInputProtocol::new(
+            &bytes[..],
+            true
+        )));
+        let mut fields = BTreeMap::new();
+        fields.insert(1, Value::I32(2));
+        fields.insert(3, Value::String("tea".to_owned()));
+        assert_eq!(read, Value::Struct(fields));
+    }
+
+    #[test]
+    fn must_not_write_container_with_mismatched_elements() {
+        let list = Value::List {
+            element_type: TType::I32,
+            elements: vec![Value::from(1), Value::from("two")],
+        };
+        let mut bytes = Vec::new();
+        match list.write(&mut TBinaryOutputProtocol::new(&mut bytes, true)) {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::InvalidData),
+            other => panic!("expected an invalid data error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn must_not_read_values_nested_too_deeply() {
+        let mut value = Value::from(1);
+        for _ in 0..MAXIMUM_SKIP_DEPTH {
+            value = Value::List {
+                element_type: value.value_type(),
+                elements: vec![value],
+            };
+        }
+        let mut bytes = Vec::new();
+        assert_success!(value.write(&mut TBinaryOutputProtocol::new(&mut bytes, true)));
+
+        let read = Value::read_typed(
+            &mut TBinaryInputProtocol::new(&bytes[..], true),
+            TType::List,
+        );
+        match read {
+            Err(crate::Error::Protocol(e)) => assert_eq!(e.kind, ProtocolErrorKind::DepthLimit),
+            other => panic!("expected a depth limit error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#![cfg(feature = "derive")]
+
+use std::collections::{BTreeMap, HashSet};
+
+use thrift::protocol::{
+    TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol,
+    TFieldIdentifier, TInputProtocol, TOutputProtocol, TSerializable, TStructIdentifier, TType,
+};
+use thrift::{ProtocolErrorKind, ThriftDeserialize, ThriftSerialize};
+
+#[derive(Debug, Default, PartialEq, ThriftSerialize, ThriftDeserialize)]
+struct Span {
+    #[thrift(id = 1, required)]
+    start: i64,
+    #[thrift(id = 2)]
+    end: i64,
+}
+
+#[derive(Debug, PartialEq, ThriftSerialize, ThriftDeserialize)]
+struct LogEntry {
+    #[thrift(id = 1, required)]
+    level: i32,
+    #[thrift(id = 2)]
+    message: String,
+    #[thrift(id = 4)]
+    payload: Vec<u8>,
+    #[thrift(id = 5)]
+    tags: HashSet<String>,
+    #[thrift(id = 6)]
+    counts: BTreeMap<String, i64>,
+    #[thrift(id = 7)]
+    span: Option<Span>,
+    #[thrift(id = 8)]
+    children: Vec<LogEntry>,
+    #[thrift(id = 9)]
+    note: Option<String>,
+}
+
+fn entry() -> LogEntry {
+    LogEntry {
+        level: 3,
+        message: "disk full".to_owned(),
+        payload: vec![0, 255, 7],
+        tags: ["disk".to_owned(), "storage".to_owned()].into(),
+        counts: [("retries".to_owned(), 2)].into(),
+        span: Some(Span { start: 10, end: 20 }),
+        children: vec![LogEntry {
+            level: 1,
+            message: "retrying".to_owned(),
+            payload: Vec::new(),
+            tags: HashSet::new(),
+            counts: BTreeMap::new(),
+            span: None,
+            children: Vec::new(),
+            note: None,
+        }],
+        note: None,
+    }
+}
+
+#[test]
+fn must_round_trip_derived_struct() {
+    let mut bytes = Vec::new();
+    entry()
+        .write_to_out_protocol(&mut TBinaryOutputProtocol::new(&mut bytes, true))
+        .unwrap();
+    let read =
+        LogEntry::read_from_in_protocol(&mut TBinaryInputProtocol::new(&bytes[..], true)).unwrap();
+    assert_eq!(read, entry());
+
+    let mut bytes = Vec::new();
+    entry()
+        .write_to_out_protocol(&mut TCompactOutputProtocol::new(&mut bytes))
+        .unwrap();
+    let read =
+        LogEntry::read_from_in_protocol(&mut TCompactInputProtocol::new(&bytes[..])).unwrap();
+    assert_eq!(read, entry());
+}
+
+#[test]
+fn must_write_fields_with_their_ids_and_types() {
+    let mut bytes = Vec::new();
+    Span { start: 1, end: 2 }
+        .write_to_out_protocol(&mut TBinaryOutputProtocol::new(&mut bytes, true))
+        .unwrap();
+
+    let mut i_prot = TBinaryInputProtocol::new(&bytes[..], true);
+    i_prot.read_struct_begin().unwrap();
+    let mut fields = Vec::new();
+    loop {
+        let field = i_prot.read_field_begin().unwrap();
+        if field.field_type == TType::Stop {
+            break;
+        }
+        fields.push((field.id, field.field_type, i_prot.read_i64().unwrap()));
+        i_prot.read_field_end().unwrap();
+    }
+    assert_eq!(
+        fields,
+        vec![(Some(1), TType::I64, 1), (Some(2), TType::I64, 2)]
+    );
+}
+
+/// Write a `Span` with only the given fields.
+fn write_span(fields: &[(i16, TType)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut o_prot = TBinaryOutputProtocol::new(&mut bytes, true);
+    o_prot
+        .write_struct_begin(&TStructIdentifier::new("Span"))
+        .unwrap();
+    for &(id, field_type) in fields {
+        o_prot
+            .write_field_begin(&TFieldIdentifier::new("field", field_type, id))
+            .unwrap();
+        match field_type {
+            TType::I64 => o_prot.write_i64(5).unwrap(),
+            _ => o_prot.write_string("five").unwrap(),
+        }
+        o_prot.write_field_end().unwrap();
+    }
+    o_prot.write_field_stop().unwrap();
+    o_prot.write_struct_end().unwrap();
+    bytes
+}
+
+#[test]
+fn must_skip_unknown_and_mistyped_fields_and_default_missing_ones() {
+    let bytes = write_span(&[(3, TType::String), (2, TType::String), (1, TType::I64)]);
+    let span = Span::read_from_in_protocol(&mut TBinaryInputProtocol::new(&bytes[..], true));
+    assert_eq!(span.unwrap(), Span { start: 5, end: 0 });
+}
+
+#[test]
+fn must_fail_to_read_struct_without_required_field() {
+    let bytes = write_span(&[(2, TType::I64)]);
+    let span = Span::read_from_in_protocol(&mut TBinaryInputProtocol::new(&bytes[..], true));
+    match span {
+        Err(thrift::Error::Protocol(e)) => {
+            assert_eq!(e.kind, ProtocolErrorKind::Unknown);
+            assert_eq!(e.message, "missing required field Span.start");
+        }
+        other => panic!("expected a missing field error, got {:?}", other),
+    }
+}
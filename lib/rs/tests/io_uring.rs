@@ -0,0 +1,128 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+#![cfg(all(feature = "io-uring", feature = "server", target_os = "linux"))]
+
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use thrift::protocol::{
+    TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+    TBinaryOutputProtocolFactory, TInputProtocol, TOutputProtocol,
+};
+use thrift::server::{TProcessor, TServer};
+use thrift::transport::{
+    TBufferedReadTransportFactory, TBufferedWriteTransportFactory, TFramedReadTransport,
+    TFramedReadTransportFactory, TFramedWriteTransport, TFramedWriteTransportFactory, TIoChannel,
+    TUringChannel,
+};
+
+struct Doubler;
+
+impl TProcessor for Doubler {
+    fn process(
+        &self,
+        i: &mut dyn TInputProtocol,
+        o: &mut dyn TOutputProtocol,
+    ) -> thrift::Result<()> {
+        let n = i.read_i32()?;
+        o.write_i32(n * 2)?;
+        o.flush()
+    }
+}
+
+fn connect(address: std::net::SocketAddr) -> TUringChannel {
+    let mut attempts = 0;
+    loop {
+        match TUringChannel::connect(address) {
+            Ok(channel) => return channel,
+            Err(_) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("cannot connect to server: {e:?}"),
+        }
+    }
+}
+
+fn free_address() -> std::net::SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+#[test]
+fn server_processes_requests_over_io_uring() {
+    let address = free_address();
+    thread::spawn(move || {
+        let mut server = TServer::new(
+            TBufferedReadTransportFactory::new(),
+            TBinaryInputProtocolFactory::new(),
+            TBufferedWriteTransportFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+            Doubler,
+            1,
+        );
+        server.listen_io_uring(address)
+    });
+
+    let (i_chan, o_chan) = connect(address).split().unwrap();
+    let mut i_prot = TBinaryInputProtocol::new(i_chan, true);
+    let mut o_prot = TBinaryOutputProtocol::new(o_chan, true);
+    for n in [1, 21, -4] {
+        o_prot.write_i32(n).unwrap();
+        o_prot.flush().unwrap();
+        assert_eq!(i_prot.read_i32().unwrap(), n * 2);
+    }
+}
+
+#[test]
+fn framed_client_talks_to_io_uring_server() {
+    let address = free_address();
+    thread::spawn(move || {
+        let mut server = TServer::new(
+            TFramedReadTransportFactory::new(),
+            TBinaryInputProtocolFactory::new(),
+            TFramedWriteTransportFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+            Doubler,
+            2,
+        );
+        server.listen_io_uring(address)
+    });
+
+    let clients: Vec<_> = (0..2)
+        .map(|c| {
+            let channel = connect(address);
+            thread::spawn(move || {
+                let (i_chan, o_chan) = channel.split().unwrap();
+                let mut i_prot = TBinaryInputProtocol::new(TFramedReadTransport::new(i_chan), true);
+                let mut o_prot =
+                    TBinaryOutputProtocol::new(TFramedWriteTransport::new(o_chan), true);
+                for n in 0..100 {
+                    o_prot.write_i32(c * 1000 + n).unwrap();
+                    o_prot.flush().unwrap();
+                    assert_eq!(i_prot.read_i32().unwrap(), (c * 1000 + n) * 2);
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+}
@@ -23,10 +23,72 @@ use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Duration;
 
-use rustls::pki_types::ServerName;
-use rustls::server::ResolvesServerCertUsingSni;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::{ResolvesServerCertUsingSni, WebPkiClientVerifier};
 use rustls::{ClientConfig, RootCertStore, ServerConfig};
-use thrift::transport::{TTlsClientChannel, TTlsServerChannel};
+use thrift::transport::{
+    TIoChannel, TTcpChannel, TTcpOptions, TTlsChannel, TTlsClientChannel, TTlsClientConfigBuilder,
+    TTlsServerChannel,
+};
+
+// generated by tests/keys/gen.sh
+const KEYS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/keys");
+
+fn identity(name: &str) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+    let cert = CertificateDer::from_pem_file(format!("{KEYS_DIR}/{name}.crt")).unwrap();
+    let key = PrivateKeyDer::from_pem_file(format!("{KEYS_DIR}/{name}.key")).unwrap();
+    (vec![cert], key)
+}
+
+fn trusted_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_file(format!("{KEYS_DIR}/ca.crt")).unwrap())
+        .unwrap();
+    roots
+}
+
+fn mtls_server_config() -> Arc<ServerConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier =
+        WebPkiClientVerifier::builder_with_provider(Arc::new(trusted_roots()), provider.clone())
+            .build()
+            .unwrap();
+    let (certs, key) = identity("server");
+    Arc::new(
+        ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)
+            .unwrap(),
+    )
+}
+
+fn mtls_client_config(with_certificate: bool) -> Arc<ClientConfig> {
+    let mut builder =
+        TTlsClientConfigBuilder::new(Arc::new(rustls::crypto::ring::default_provider()))
+            .root_certificates(trusted_roots());
+    if with_certificate {
+        let (certs, key) = identity("client");
+        builder = builder.client_identity(certs, key);
+    }
+    builder.build().unwrap()
+}
+
+fn server_config_with_identity(alpn_protocols: Vec<Vec<u8>>) -> Arc<ServerConfig> {
+    let (certs, key) = identity("server");
+    let mut config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap();
+    config.alpn_protocols = alpn_protocols;
+    Arc::new(config)
+}
 
 fn server_config() -> Arc<ServerConfig> {
     Arc::new(
@@ -153,6 +215,214 @@ fn server_wraps_stream_without_waiting_for_a_handshake() {
     server.join().unwrap();
 }
 
+#[test]
+fn client_config_builder_negotiates_alpn_and_sends_sni() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let config = server_config_with_identity(vec![b"thrift".to_vec()]);
+        let mut channel = TTlsServerChannel::with_stream(stream, config).unwrap();
+        let mut request = [0; 4];
+        channel.read_exact(&mut request).unwrap();
+        channel.write_all(&request).unwrap();
+        channel.flush().unwrap();
+        (
+            channel.server_name().unwrap(),
+            channel.alpn_protocol().unwrap(),
+        )
+    });
+
+    let config = TTlsClientConfigBuilder::new(Arc::new(rustls::crypto::ring::default_provider()))
+        .root_certificates(trusted_roots())
+        .alpn_protocols(vec![b"h2".to_vec(), b"thrift".to_vec()])
+        .build()
+        .unwrap();
+    let mut tcp = TTcpChannel::new();
+    tcp.set_options(TTcpOptions::builder().nodelay(true).build())
+        .unwrap();
+    tcp.open(address).unwrap();
+    let channel =
+        TTlsChannel::with_channel(tcp, ServerName::try_from("localhost").unwrap(), config).unwrap();
+    assert_eq!(channel.alpn_protocol().unwrap(), Some(b"thrift".to_vec()));
+
+    // both halves share the TLS session
+    let (mut read_half, mut write_half) = channel.split().unwrap();
+    write_half.write_all(&[1, 2, 3, 4]).unwrap();
+    write_half.flush().unwrap();
+    let mut response = [0; 4];
+    read_half.read_exact(&mut response).unwrap();
+    assert_eq!(response, [1, 2, 3, 4]);
+
+    let (server_name, alpn_protocol) = server.join().unwrap();
+    assert_eq!(server_name.as_deref(), Some("localhost"));
+    assert_eq!(alpn_protocol, Some(b"thrift".to_vec()));
+}
+
+#[test]
+fn client_config_builder_rejects_untrusted_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel =
+            TTlsServerChannel::with_stream(stream, server_config_with_identity(vec![])).unwrap();
+        assert!(channel.handshake().is_err());
+    });
+
+    let config = TTlsClientConfigBuilder::new(Arc::new(rustls::crypto::ring::default_provider()))
+        .build()
+        .unwrap();
+    let result =
+        TTlsClientChannel::connect(address, ServerName::try_from("localhost").unwrap(), config);
+    assert!(result.is_err());
+    server.join().unwrap();
+}
+
+#[test]
+fn client_rejects_unopened_tcp_channel() {
+    let config = TTlsClientConfigBuilder::new(Arc::new(rustls::crypto::ring::default_provider()))
+        .build()
+        .unwrap();
+    let result = TTlsChannel::with_channel(
+        TTcpChannel::new(),
+        ServerName::try_from("localhost").unwrap(),
+        config,
+    );
+    match result {
+        Err(thrift::Error::Transport(error)) => {
+            assert_eq!(error.kind, thrift::TransportErrorKind::NotOpen)
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn mutual_tls_exposes_verified_peer_identities() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel = TTlsServerChannel::with_stream(stream, mtls_server_config()).unwrap();
+        assert_eq!(channel.peer_identity().unwrap(), None);
+        channel.handshake().unwrap();
+        channel.peer_identity().unwrap()
+    });
+
+    let channel = TTlsClientChannel::connect(
+        address,
+        ServerName::try_from("localhost").unwrap(),
+        mtls_client_config(true),
+    )
+    .unwrap();
+
+    let server_identity = channel.peer_identity().unwrap().unwrap();
+    assert_eq!(
+        server_identity.certificate(),
+        identity("server").0[0].as_ref()
+    );
+    let client_identity = server.join().unwrap().unwrap();
+    assert_eq!(
+        client_identity.certificate(),
+        identity("client").0[0].as_ref()
+    );
+    assert_eq!(client_identity.certificate_chain().len(), 1);
+}
+
+#[test]
+fn mutual_tls_rejects_client_without_certificate() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel = TTlsServerChannel::with_stream(stream, mtls_server_config()).unwrap();
+        channel.handshake()
+    });
+
+    // with TLS 1.3 the client finishes its side of the handshake before the
+    // server has checked for a certificate, so the failure surfaces on read
+    let mut channel = TTlsClientChannel::connect(
+        address,
+        ServerName::try_from("localhost").unwrap(),
+        mtls_client_config(false),
+    )
+    .unwrap();
+    assert!(channel.read(&mut [0]).is_err());
+    assert!(server.join().unwrap().is_err());
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn server_exposes_client_identity_to_handlers() {
+    use thrift::protocol::{
+        TBinaryInputProtocolFactory, TBinaryOutputProtocolFactory, TInputProtocol, TOutputProtocol,
+    };
+    use thrift::server::{self, TProcessor, TServer};
+    use thrift::transport::TPeerIdentity;
+    use thrift::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+    struct IdentityProbe(std::sync::Mutex<mpsc::Sender<Option<TPeerIdentity>>>);
+
+    impl TProcessor for IdentityProbe {
+        fn process(
+            &self,
+            _: &mut dyn TInputProtocol,
+            _: &mut dyn TOutputProtocol,
+        ) -> thrift::Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .send(server::peer_identity())
+                .unwrap();
+            Err(thrift::Error::Transport(thrift::TransportError::new(
+                thrift::TransportErrorKind::EndOfFile,
+                "done",
+            )))
+        }
+    }
+
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (identity_tx, identity_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut server = TServer::new(
+            TBufferedReadTransportFactory::new(),
+            TBinaryInputProtocolFactory::new(),
+            TBufferedWriteTransportFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+            IdentityProbe(std::sync::Mutex::new(identity_tx)),
+            1,
+        );
+        server.listen_tls(address, mtls_server_config())
+    });
+
+    let mut attempts = 0;
+    let _channel = loop {
+        match TTlsClientChannel::connect(
+            address,
+            ServerName::try_from("localhost").unwrap(),
+            mtls_client_config(true),
+        ) {
+            Ok(channel) => break channel,
+            Err(_) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("cannot connect to server: {e:?}"),
+        }
+    };
+
+    let peer = identity_rx
+        .recv_timeout(Duration::from_secs(5))
+        .unwrap()
+        .unwrap();
+    assert_eq!(peer.certificate(), identity("client").0[0].as_ref());
+    // the identity is only visible while the connection is being served
+    assert_eq!(server::peer_identity(), None);
+}
+
 fn assert_incomplete_handshake_error<T>(result: thrift::Result<T>, role: &str) {
     let error = match result {
         Ok(_) => panic!("incomplete TLS handshake succeeded"),
@@ -0,0 +1,202 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#![cfg(feature = "tls-native")]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+
+use native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector};
+use thrift::transport::{
+    TIoChannel, TNativeTlsClientChannel, TNativeTlsServerChannel, TTcpChannel,
+};
+
+// generated by tests/keys/gen.sh
+const KEYS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/keys");
+
+fn read_key_file(name: &str) -> Vec<u8> {
+    fs::read(format!("{KEYS_DIR}/{name}")).unwrap()
+}
+
+fn acceptor() -> TlsAcceptor {
+    let identity =
+        Identity::from_pkcs8(&read_key_file("server.crt"), &read_key_file("server.key")).unwrap();
+    TlsAcceptor::builder(identity)
+        .accept_alpn(&["thrift"])
+        .build()
+        .unwrap()
+}
+
+fn connector(trust_test_ca: bool) -> TlsConnector {
+    let mut builder = TlsConnector::builder();
+    builder.disable_built_in_roots(true);
+    builder.request_alpns(&["thrift"]);
+    if trust_test_ca {
+        builder.add_root_certificate(Certificate::from_pem(&read_key_file("ca.crt")).unwrap());
+    }
+    builder.build().unwrap()
+}
+
+fn spawn_echo_server() -> (
+    SocketAddr,
+    thread::JoinHandle<thrift::Result<Option<Vec<u8>>>>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel = TNativeTlsServerChannel::accept(stream, &acceptor())?;
+        let mut request = [0; 4];
+        channel.read_exact(&mut request)?;
+        channel.write_all(&request)?;
+        channel.flush()?;
+        channel.alpn_protocol()
+    });
+    (address, server)
+}
+
+#[test]
+fn client_and_server_exchange_data_over_native_tls() {
+    let (address, server) = spawn_echo_server();
+
+    let mut tcp = TTcpChannel::new();
+    tcp.open(address).unwrap();
+    let channel =
+        TNativeTlsClientChannel::with_channel(tcp, "localhost", &connector(true)).unwrap();
+    assert_eq!(channel.alpn_protocol().unwrap(), Some(b"thrift".to_vec()));
+
+    let (mut read_half, mut write_half) = channel.split().unwrap();
+    write_half.write_all(&[1, 2, 3, 4]).unwrap();
+    write_half.flush().unwrap();
+    let mut response = [0; 4];
+    read_half.read_exact(&mut response).unwrap();
+    assert_eq!(response, [1, 2, 3, 4]);
+
+    assert_eq!(server.join().unwrap().unwrap(), Some(b"thrift".to_vec()));
+}
+
+#[test]
+fn client_rejects_server_not_signed_by_a_trusted_root() {
+    let (address, server) = spawn_echo_server();
+
+    let result = TNativeTlsClientChannel::connect(address, "localhost", &connector(false));
+    match result {
+        Err(thrift::Error::Transport(error)) => {
+            assert!(error.message.starts_with("TLS client handshake failed"))
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    assert!(server.join().unwrap().is_err());
+}
+
+#[test]
+fn client_rejects_server_with_mismatched_name() {
+    let (address, server) = spawn_echo_server();
+
+    let result = TNativeTlsClientChannel::connect(address, "example.com", &connector(true));
+    assert!(result.is_err());
+    assert!(server.join().unwrap().is_err());
+}
+
+#[test]
+fn server_channel_defers_handshake_until_first_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel = TNativeTlsServerChannel::with_stream(stream, Arc::new(acceptor()));
+        let mut request = [0; 2];
+        channel.read_exact(&mut request).unwrap();
+        channel.write_all(&request).unwrap();
+        channel.flush().unwrap();
+    });
+
+    let mut channel =
+        TNativeTlsClientChannel::connect(address, "localhost", &connector(true)).unwrap();
+    channel.write_all(&[7, 8]).unwrap();
+    channel.flush().unwrap();
+    let mut response = [0; 2];
+    channel.read_exact(&mut response).unwrap();
+    assert_eq!(response, [7, 8]);
+    server.join().unwrap();
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn server_processes_requests_over_native_tls() {
+    use std::time::Duration;
+    use thrift::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TInputProtocol, TOutputProtocol,
+    };
+    use thrift::server::{TProcessor, TServer};
+    use thrift::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+    struct Doubler;
+
+    impl TProcessor for Doubler {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> thrift::Result<()> {
+            let n = i.read_i32()?;
+            o.write_i32(n * 2)?;
+            o.flush()
+        }
+    }
+
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    thread::spawn(move || {
+        let mut server = TServer::new(
+            TBufferedReadTransportFactory::new(),
+            TBinaryInputProtocolFactory::new(),
+            TBufferedWriteTransportFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+            Doubler,
+            1,
+        );
+        server.listen_native_tls(address, Arc::new(acceptor()))
+    });
+
+    let mut attempts = 0;
+    let channel = loop {
+        match TNativeTlsClientChannel::connect(address, "localhost", &connector(true)) {
+            Ok(channel) => break channel,
+            Err(_) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("cannot connect to server: {e:?}"),
+        }
+    };
+
+    let (i_chan, o_chan) = channel.split().unwrap();
+    let mut i_prot = TBinaryInputProtocol::new(i_chan, true);
+    let mut o_prot = TBinaryOutputProtocol::new(o_chan, true);
+    for n in [1, 21, -4] {
+        o_prot.write_i32(n).unwrap();
+        o_prot.flush().unwrap();
+        assert_eq!(i_prot.read_i32().unwrap(), n * 2);
+    }
+}
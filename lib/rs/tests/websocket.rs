@@ -0,0 +1,219 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements. See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership. The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License. You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#![cfg(feature = "websocket")]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use thrift::transport::{TIoChannel, TWebSocketClientChannel, TWebSocketServerChannel};
+use tungstenite::Message;
+
+/// Accept one connection with a plain tungstenite server and return every
+/// message it receives until the client closes the connection.
+fn spawn_recording_server(reply: &'static [u8]) -> (SocketAddr, thread::JoinHandle<Vec<Message>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut socket = tungstenite::accept(stream).unwrap();
+        let mut received = Vec::new();
+        loop {
+            match socket.read() {
+                Ok(message @ Message::Binary(_)) => {
+                    received.push(message);
+                    socket.send(Message::binary(reply)).unwrap();
+                }
+                Ok(Message::Close(_)) => {}
+                Ok(message) => received.push(message),
+                Err(_) => return received,
+            }
+        }
+    });
+    (address, handle)
+}
+
+#[test]
+fn client_sends_each_flush_as_one_binary_message() {
+    let (address, server) = spawn_recording_server(b"pong");
+    let mut channel = TWebSocketClientChannel::connect(&format!("ws://{address}/thrift")).unwrap();
+
+    for request in [&b"first"[..], &b"second"[..]] {
+        // several writes before a flush still make up one message
+        channel.write_all(&request[..2]).unwrap();
+        channel.write_all(&request[2..]).unwrap();
+        channel.flush().unwrap();
+
+        let mut reply = [0u8; 4];
+        channel.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"pong");
+    }
+    channel.close().unwrap();
+
+    let received = server.join().unwrap();
+    assert_eq!(
+        received,
+        vec![
+            Message::binary(&b"first"[..]),
+            Message::binary(&b"second"[..])
+        ]
+    );
+}
+
+#[test]
+fn split_halves_share_the_connection() {
+    let (address, _server) = spawn_recording_server(b"reply");
+    let channel = TWebSocketClientChannel::connect(&format!("ws://{address}/")).unwrap();
+    let (mut read_half, mut write_half) = channel.split().unwrap();
+
+    write_half.write_all(b"request").unwrap();
+    write_half.flush().unwrap();
+
+    let mut reply = [0u8; 5];
+    read_half.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"reply");
+}
+
+#[test]
+fn server_channel_upgrades_lazily_and_echoes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel = TWebSocketServerChannel::with_stream(stream);
+        let mut buf = Vec::new();
+        channel.read_to_end(&mut buf).unwrap();
+        buf
+    });
+
+    let (mut socket, _) = tungstenite::connect(format!("ws://{address}/")).unwrap();
+    socket.send(Message::binary(&b"abc"[..])).unwrap();
+    socket.send(Message::binary(&b"def"[..])).unwrap();
+    socket.close(None).unwrap();
+    while socket.read().is_ok() {}
+
+    // the close frame is reported to the reader as end-of-file
+    assert_eq!(server.join().unwrap(), b"abcdef");
+}
+
+#[test]
+fn server_channel_rejects_text_messages() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel = TWebSocketServerChannel::with_stream(stream);
+        let mut buf = [0u8; 8];
+        channel.read(&mut buf).unwrap_err()
+    });
+
+    let (mut socket, _) = tungstenite::connect(format!("ws://{address}/")).unwrap();
+    socket.send(Message::text("not thrift")).unwrap();
+
+    let error = server.join().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn server_channel_fails_for_plain_http_client() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut channel = TWebSocketServerChannel::with_stream(stream);
+        let first = channel.handshake().is_err();
+        let second = channel.handshake().is_err();
+        (first, second)
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n")
+        .unwrap();
+
+    assert_eq!(server.join().unwrap(), (true, true));
+}
+
+#[test]
+fn client_rejects_non_ws_urls() {
+    assert!(TWebSocketClientChannel::connect("http://localhost:1/").is_err());
+    assert!(TWebSocketClientChannel::connect("not a url").is_err());
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn server_processes_requests_over_websocket() {
+    use std::time::Duration;
+    use thrift::protocol::{
+        TBinaryInputProtocol, TBinaryInputProtocolFactory, TBinaryOutputProtocol,
+        TBinaryOutputProtocolFactory, TInputProtocol, TOutputProtocol,
+    };
+    use thrift::server::{TProcessor, TServer};
+    use thrift::transport::{TBufferedReadTransportFactory, TBufferedWriteTransportFactory};
+
+    struct Doubler;
+
+    impl TProcessor for Doubler {
+        fn process(
+            &self,
+            i: &mut dyn TInputProtocol,
+            o: &mut dyn TOutputProtocol,
+        ) -> thrift::Result<()> {
+            let n = i.read_i32()?;
+            o.write_i32(n * 2)?;
+            o.flush()
+        }
+    }
+
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    thread::spawn(move || {
+        let mut server = TServer::new(
+            TBufferedReadTransportFactory::new(),
+            TBinaryInputProtocolFactory::new(),
+            TBufferedWriteTransportFactory::new(),
+            TBinaryOutputProtocolFactory::new(),
+            Doubler,
+            1,
+        );
+        server.listen_websocket(address)
+    });
+
+    let mut attempts = 0;
+    let channel = loop {
+        match TWebSocketClientChannel::connect(&format!("ws://{address}/")) {
+            Ok(channel) => break channel,
+            Err(_) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("cannot connect to server: {e:?}"),
+        }
+    };
+
+    let (i_chan, o_chan) = channel.split().unwrap();
+    let mut i_prot = TBinaryInputProtocol::new(i_chan, true);
+    let mut o_prot = TBinaryOutputProtocol::new(o_chan, true);
+    for n in [1, 21, -4] {
+        o_prot.write_i32(n).unwrap();
+        o_prot.flush().unwrap();
+        assert_eq!(i_prot.read_i32().unwrap(), n * 2);
+    }
+}